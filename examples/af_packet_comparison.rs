@@ -0,0 +1,582 @@
+//! Runs the same workload over AF_PACKET and AF_XDP and compares the
+//! two.
+//!
+//! dev1 sends `num_packets` UDP packets to dev2, first with a raw
+//! AF_PACKET socket and a TPACKET_V3 rx ring, then with an AF_XDP
+//! socket at either end. The receive rate of each is reported, along
+//! with what mode the AF_XDP socket ended up bound in. If AF_XDP comes
+//! out no faster than AF_PACKET it's usually because it's running in
+//! generic (SKB) or copy mode, in which case it's flagged.
+//!
+//! Build with `--features bench`.
+
+use std::{
+    convert::TryInto,
+    error::Error,
+    ffi::CString,
+    io::{self, Write},
+    mem,
+    net::Ipv4Addr,
+    os::unix::io::AsRawFd,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+use tokio::runtime::Runtime;
+use xsk_rs::{
+    config::{BindFlags, SocketConfig, UmemConfig, XdpFlags},
+    Socket, Umem,
+};
+
+#[allow(dead_code)]
+mod setup;
+use setup::{util, veth_setup, LinkIpAddr, PacketGenerator, VethDevConfig};
+
+/// How long the receiver waits for stragglers once the sender's done.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+const TPACKET_BLOCK_SIZE: u32 = 1 << 22;
+const TPACKET_BLOCK_NR: u32 = 64;
+const TPACKET_FRAME_SIZE: u32 = 2048;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "af_packet_comparison")]
+struct Opt {
+    /// Force the AF_XDP socket's XDP program into generic (SKB) mode
+    #[structopt(long)]
+    skb_mode: bool,
+
+    /// Print the results as a single JSON object
+    #[structopt(long)]
+    json: bool,
+
+    /// Packet payload size
+    #[structopt(default_value = "32")]
+    payload_size: usize,
+
+    /// Max number of packets to send at once over AF_XDP
+    #[structopt(default_value = "64")]
+    max_batch_size: usize,
+
+    /// Total number of packets to send
+    #[structopt(default_value = "2000000")]
+    num_packets: usize,
+}
+
+/// The outcome of one run of the workload.
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    sent: usize,
+    received: usize,
+    elapsed: Duration,
+}
+
+impl Run {
+    fn pps(&self) -> f64 {
+        self.received as f64 / self.elapsed.as_secs_f64()
+    }
+
+    fn loss(&self) -> f64 {
+        1.0 - self.received as f64 / self.sent as f64
+    }
+}
+
+fn if_index(if_name: &str) -> io::Result<i32> {
+    let name = CString::new(if_name).unwrap();
+
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index as i32),
+    }
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+unsafe fn set_opt<T>(fd: i32, level: i32, name: i32, val: &T) -> io::Result<()> {
+    check(unsafe {
+        libc::setsockopt(
+            fd,
+            level,
+            name,
+            val as *const T as *const libc::c_void,
+            mem::size_of::<T>() as libc::socklen_t,
+        )
+    })
+    .map(|_| ())
+}
+
+/// A raw AF_PACKET socket bound to `if_name`, receiving everything if
+/// `protocol` is `ETH_P_ALL` or nothing if it's zero.
+fn packet_socket(if_name: &str, protocol: i32) -> io::Result<i32> {
+    let protocol = (protocol as u16).to_be();
+
+    let fd = check(unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, protocol.into()) })?;
+
+    let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+    addr.sll_family = libc::AF_PACKET as u16;
+    addr.sll_protocol = protocol;
+    addr.sll_ifindex = if_index(if_name)?;
+
+    check(unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    })?;
+
+    Ok(fd)
+}
+
+fn af_packet_send(if_name: &str, pkt: &[u8], num_packets: usize) -> io::Result<usize> {
+    let fd = packet_socket(if_name, 0)?;
+
+    // Skip the qdisc layer, as AF_XDP does.
+    unsafe { set_opt(fd, libc::SOL_PACKET, libc::PACKET_QDISC_BYPASS, &1i32)? };
+
+    let mut sent = 0;
+
+    while sent < num_packets {
+        let ret = unsafe { libc::send(fd, pkt.as_ptr() as *const libc::c_void, pkt.len(), 0) };
+
+        if ret >= 0 {
+            sent += 1;
+            continue;
+        }
+
+        let err = io::Error::last_os_error();
+
+        match err.raw_os_error() {
+            Some(libc::ENOBUFS) | Some(libc::EAGAIN) => thread::yield_now(),
+            _ => {
+                unsafe { libc::close(fd) };
+                return Err(err);
+            }
+        }
+    }
+
+    unsafe { libc::close(fd) };
+
+    Ok(sent)
+}
+
+/// Count packets of `pkt_len` bytes arriving on `if_name` via a
+/// TPACKET_V3 ring, until `expected` have been seen or the sender's
+/// done and nothing's turned up for a while. Returns the count and
+/// the time the last one arrived.
+fn af_packet_recv(
+    if_name: &str,
+    pkt_len: usize,
+    expected: usize,
+    ready: &AtomicBool,
+    sender_done: &AtomicBool,
+) -> io::Result<(usize, Instant)> {
+    let fd = packet_socket(if_name, libc::ETH_P_ALL)?;
+
+    let version = libc::tpacket_versions::TPACKET_V3 as libc::c_int;
+
+    let req = libc::tpacket_req3 {
+        tp_block_size: TPACKET_BLOCK_SIZE,
+        tp_block_nr: TPACKET_BLOCK_NR,
+        tp_frame_size: TPACKET_FRAME_SIZE,
+        tp_frame_nr: TPACKET_BLOCK_SIZE / TPACKET_FRAME_SIZE * TPACKET_BLOCK_NR,
+        tp_retire_blk_tov: 10,
+        tp_sizeof_priv: 0,
+        tp_feature_req_word: 0,
+    };
+
+    unsafe {
+        set_opt(fd, libc::SOL_PACKET, libc::PACKET_VERSION, &version)?;
+        set_opt(fd, libc::SOL_PACKET, libc::PACKET_RX_RING, &req)?;
+    }
+
+    let ring_len = (TPACKET_BLOCK_SIZE * TPACKET_BLOCK_NR) as usize;
+
+    let ring = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            ring_len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_POPULATE,
+            fd,
+            0,
+        )
+    };
+
+    if ring == libc::MAP_FAILED {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+
+    ready.store(true, Ordering::Release);
+
+    let mut received = 0;
+    let mut last_rx = Instant::now();
+    let mut block_idx = 0;
+
+    while received < expected {
+        let block = unsafe {
+            (ring as *mut u8).add(block_idx * TPACKET_BLOCK_SIZE as usize)
+                as *mut libc::tpacket_block_desc
+        };
+
+        let hdr = unsafe { ptr::addr_of_mut!((*block).hdr.bh1) };
+        let status = unsafe { ptr::addr_of!((*hdr).block_status).read_volatile() };
+
+        if status & libc::TP_STATUS_USER == 0 {
+            if sender_done.load(Ordering::Acquire) && last_rx.elapsed() > DRAIN_TIMEOUT {
+                break;
+            }
+
+            let mut pfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN | libc::POLLERR,
+                revents: 0,
+            };
+
+            unsafe { libc::poll(&mut pfd, 1, 10) };
+
+            continue;
+        }
+
+        std::sync::atomic::fence(Ordering::Acquire);
+
+        let (num_pkts, mut offset) = unsafe {
+            (
+                (*hdr).num_pkts as usize,
+                (*hdr).offset_to_first_pkt as usize,
+            )
+        };
+
+        for _ in 0..num_pkts {
+            let pkt_hdr = unsafe { (block as *const u8).add(offset) as *const libc::tpacket3_hdr };
+            let pkt_hdr = unsafe { &*pkt_hdr };
+
+            if pkt_hdr.tp_snaplen as usize == pkt_len {
+                received += 1;
+            }
+
+            offset += pkt_hdr.tp_next_offset as usize;
+        }
+
+        last_rx = Instant::now();
+
+        std::sync::atomic::fence(Ordering::Release);
+        unsafe { ptr::addr_of_mut!((*hdr).block_status).write_volatile(libc::TP_STATUS_KERNEL) };
+
+        block_idx = (block_idx + 1) % TPACKET_BLOCK_NR as usize;
+    }
+
+    unsafe {
+        libc::munmap(ring, ring_len);
+        libc::close(fd);
+    }
+
+    Ok((received, last_rx))
+}
+
+fn run_af_packet(
+    opt: &Opt,
+    pkt: &[u8],
+    dev1: &VethDevConfig,
+    dev2: &VethDevConfig,
+) -> io::Result<Run> {
+    let ready = Arc::new(AtomicBool::new(false));
+    let sender_done = Arc::new(AtomicBool::new(false));
+
+    let rx = {
+        let if_name = dev2.if_name().to_owned();
+        let (pkt_len, expected) = (pkt.len(), opt.num_packets);
+        let (ready, sender_done) = (Arc::clone(&ready), Arc::clone(&sender_done));
+
+        thread::spawn(move || af_packet_recv(&if_name, pkt_len, expected, &ready, &sender_done))
+    };
+
+    while !ready.load(Ordering::Acquire) && !rx.is_finished() {
+        thread::yield_now();
+    }
+
+    let start = Instant::now();
+    let sent = af_packet_send(dev1.if_name(), pkt, opt.num_packets);
+    sender_done.store(true, Ordering::Release);
+
+    let (received, last_rx) = rx.join().unwrap()?;
+
+    Ok(Run {
+        sent: sent?,
+        received,
+        elapsed: last_rx.saturating_duration_since(start),
+    })
+}
+
+/// Whether the AF_XDP socket `fd` is bound in zero-copy mode.
+fn xdp_zero_copy(fd: i32) -> io::Result<bool> {
+    let mut opts = libc::xdp_options { flags: 0 };
+    let mut len = mem::size_of::<libc::xdp_options>() as libc::socklen_t;
+
+    check(unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_XDP,
+            libc::XDP_OPTIONS,
+            &mut opts as *mut libc::xdp_options as *mut libc::c_void,
+            &mut len,
+        )
+    })?;
+
+    Ok(opts.flags & libc::XDP_OPTIONS_ZEROCOPY != 0)
+}
+
+fn run_af_xdp(
+    opt: &Opt,
+    pkt: &[u8],
+    dev1: &VethDevConfig,
+    dev2: &VethDevConfig,
+) -> Result<(Run, bool), Box<dyn Error>> {
+    let frame_count = 8192.try_into().unwrap();
+
+    let mut xdp_flags = XdpFlags::empty();
+
+    if opt.skb_mode {
+        xdp_flags |= XdpFlags::XDP_FLAGS_SKB_MODE;
+    }
+
+    let socket_config = SocketConfig::builder()
+        .xdp_flags(xdp_flags)
+        .bind_flags(BindFlags::XDP_USE_NEED_WAKEUP)
+        .build();
+
+    let (tx_umem, mut tx_descs) = Umem::new(UmemConfig::default(), frame_count, false)?;
+    let (rx_umem, rx_descs) = Umem::new(UmemConfig::default(), frame_count, false)?;
+
+    let (mut tx_q, _, tx_fq_and_cq) =
+        unsafe { Socket::new(socket_config, &tx_umem, &dev1.if_name().parse()?, 0) }?;
+
+    let (_, mut rx_q, rx_fq_and_cq) =
+        unsafe { Socket::new(socket_config, &rx_umem, &dev2.if_name().parse()?, 0) }?;
+
+    let (_, mut tx_cq) = tx_fq_and_cq.unwrap();
+    let (mut rx_fq, _) = rx_fq_and_cq.unwrap();
+
+    let zero_copy = xdp_zero_copy(rx_q.fd().as_raw_fd())?;
+
+    for desc in tx_descs.iter_mut() {
+        unsafe { tx_umem.data_mut(desc).cursor().write_all(pkt)? };
+    }
+
+    let fq_size = UmemConfig::default().fill_queue_size().get() as usize;
+    let mut rx_batch = rx_descs[..opt.max_batch_size].to_vec();
+
+    assert_eq!(unsafe { rx_fq.produce(&rx_descs[..fq_size]) }, fq_size);
+
+    let sender_done = Arc::new(AtomicBool::new(false));
+
+    let tx = {
+        let (max_batch_size, num_packets) = (opt.max_batch_size, opt.num_packets);
+        let sender_done = Arc::clone(&sender_done);
+
+        thread::spawn(move || -> io::Result<usize> {
+            let mut free = tx_descs.clone();
+            let mut completed = tx_descs;
+            let mut sent = 0;
+
+            while sent < num_packets {
+                let cnt = unsafe { tx_cq.consume(&mut completed) };
+                free.extend_from_slice(&completed[..cnt]);
+
+                let batch = max_batch_size.min(free.len()).min(num_packets - sent);
+                let from = free.len() - batch;
+
+                let cnt = unsafe { tx_q.produce(&free[from..]) };
+                free.drain(from..from + cnt);
+                sent += cnt;
+
+                if tx_q.needs_wakeup() {
+                    tx_q.wakeup()?;
+                }
+            }
+
+            sender_done.store(true, Ordering::Release);
+
+            // Keep the UMEM alive till the receiver's done.
+            drop(tx_umem);
+
+            Ok(sent)
+        })
+    };
+
+    let start = Instant::now();
+    let mut received = 0;
+    let mut last_rx = start;
+
+    while received < opt.num_packets {
+        let cnt = unsafe { rx_q.poll_and_consume(&mut rx_batch, 10)? };
+
+        if cnt == 0 {
+            if sender_done.load(Ordering::Acquire) && last_rx.elapsed() > DRAIN_TIMEOUT {
+                break;
+            }
+
+            if rx_fq.needs_wakeup() {
+                rx_fq.wakeup(rx_q.fd_mut(), 10)?;
+            }
+
+            continue;
+        }
+
+        received += rx_batch[..cnt]
+            .iter()
+            .filter(|desc| desc.lengths().data() == pkt.len())
+            .count();
+
+        last_rx = Instant::now();
+
+        unsafe { rx_fq.produce(&rx_batch[..cnt]) };
+    }
+
+    let sent = tx.join().unwrap()?;
+
+    Ok((
+        Run {
+            sent,
+            received,
+            elapsed: last_rx.saturating_duration_since(start),
+        },
+        zero_copy,
+    ))
+}
+
+fn run_comparison(
+    opt: Opt,
+    dev1: (VethDevConfig, PacketGenerator),
+    dev2: (VethDevConfig, PacketGenerator),
+) {
+    let pkt = dev1
+        .1
+        .generate_packet(1234, 1234, opt.payload_size)
+        .unwrap();
+
+    let af_packet = run_af_packet(&opt, &pkt, &dev1.0, &dev2.0).expect("AF_PACKET run failed");
+    let (af_xdp, zero_copy) = run_af_xdp(&opt, &pkt, &dev1.0, &dev2.0).expect("AF_XDP run failed");
+
+    let speedup = af_xdp.pps() / af_packet.pps();
+
+    let xdp_mode = if opt.skb_mode { "skb" } else { "default" };
+    let bind_mode = if zero_copy { "zero-copy" } else { "copy" };
+
+    // A rough threshold, AF_XDP in native mode is normally well clear
+    // of it.
+    let suspect = speedup < 1.1;
+
+    if opt.json {
+        println!(
+            "{{\"packets\":{},\"packet_len\":{},\
+             \"af_packet\":{{\"received\":{},\"pps\":{:.0},\"loss\":{:.4}}},\
+             \"af_xdp\":{{\"received\":{},\"pps\":{:.0},\"loss\":{:.4},\
+             \"xdp_mode\":\"{}\",\"bind_mode\":\"{}\"}},\
+             \"speedup\":{:.3},\"suspect\":{}}}",
+            opt.num_packets,
+            pkt.len(),
+            af_packet.received,
+            af_packet.pps(),
+            af_packet.loss(),
+            af_xdp.received,
+            af_xdp.pps(),
+            af_xdp.loss(),
+            xdp_mode,
+            bind_mode,
+            speedup,
+            suspect
+        );
+
+        return;
+    }
+
+    println!(
+        "{} packets of {} bytes, AF_XDP xdp mode {}, bound {}",
+        opt.num_packets,
+        pkt.len(),
+        xdp_mode,
+        bind_mode
+    );
+    println!("{:<10} {:>10} {:>14} {:>8}", "", "received", "pps", "loss");
+
+    for (name, run) in [("AF_PACKET", af_packet), ("AF_XDP", af_xdp)] {
+        println!(
+            "{:<10} {:>10} {:>14.0} {:>7.2}%",
+            name,
+            run.received,
+            run.pps(),
+            run.loss() * 100.0
+        );
+    }
+
+    println!("AF_XDP is {:.2}x AF_PACKET", speedup);
+
+    if suspect {
+        println!(
+            "warning: AF_XDP is barely faster than AF_PACKET, check the \
+             XDP program isn't running in generic (SKB) mode and whether \
+             the driver supports zero-copy"
+        );
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    let dev1_config = VethDevConfig {
+        if_name: "xsk_test_dev1".into(),
+        addr: [0xf6, 0xe0, 0xf6, 0xc9, 0x60, 0x0a],
+        ip_addr: LinkIpAddr::new(Ipv4Addr::new(192, 168, 69, 1), 24),
+    };
+
+    let dev2_config = VethDevConfig {
+        if_name: "xsk_test_dev2".into(),
+        addr: [0x4a, 0xf1, 0x30, 0xeb, 0x0d, 0x31],
+        ip_addr: LinkIpAddr::new(Ipv4Addr::new(192, 168, 69, 2), 24),
+    };
+
+    // We'll keep track of ctrl+c events but not let them kill the process
+    // immediately as we may need to clean up the veth pair.
+    let ctrl_c_events = util::ctrl_channel().unwrap();
+
+    let (complete_tx, complete_rx) = crossbeam_channel::bounded(1);
+
+    let runtime = Runtime::new().unwrap();
+
+    let example_handle = thread::spawn(move || {
+        let res = runtime.block_on(veth_setup::run_with_veth_pair(
+            dev1_config,
+            dev2_config,
+            move |dev1, dev2| run_comparison(opt, dev1, dev2),
+        ));
+
+        let _ = complete_tx.send(());
+
+        res
+    });
+
+    // Wait for either the example to finish or for a ctrl+c event to occur.
+    crossbeam_channel::select! {
+        recv(complete_rx) -> _ => {
+        },
+        recv(ctrl_c_events) -> _ => {
+            println!("SIGINT received");
+        }
+    }
+
+    example_handle.join().unwrap().unwrap();
+}
@@ -0,0 +1,355 @@
+//! Measures the cycles each ring operation spends per descriptor and
+//! checks them against a budget.
+//!
+//! dev1 sends `num_packets` UDP packets to dev2 over AF_XDP, a batch
+//! at a time on a single thread. The cycles taken by each call to
+//! `TxQueue::produce` and `CompQueue::consume` on dev1 and
+//! `RxQueue::consume` and `FillQueue::produce` (or
+//! `FillQueue::recycle_from_rx` with `--recycle`) on dev2 are divided by
+//! the number of descriptors it handled and recorded. The chosen
+//! percentile of each is then compared against `--budget`, and the
+//! process exits with a non-zero status if any is over, so the bench
+//! can gate a release locally without needing CI hardware.
+//!
+//! Wakeups and polling aren't counted, only the ring operations
+//! themselves, which should cost the same per descriptor whatever the
+//! batch size. Use `--json` to keep results for comparison between
+//! releases.
+//!
+//! Build with `--features bench`.
+
+use std::{
+    convert::TryInto,
+    error::Error,
+    io::{self, Write},
+    net::Ipv4Addr,
+    process, thread,
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
+use tokio::runtime::Runtime;
+use xsk_rs::{
+    config::{BindFlags, SocketConfig, UmemConfig, XdpFlags},
+    pipeline::timing::cycles,
+    stats::Histogram,
+    Socket, Umem,
+};
+
+#[allow(dead_code)]
+mod setup;
+use setup::{util, veth_setup, LinkIpAddr, PacketGenerator, VethDevConfig};
+
+/// How long to wait for stragglers once everything's been sent.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "desc_bench")]
+struct Opt {
+    /// Force the XDP program into generic (SKB) mode
+    #[structopt(long)]
+    skb_mode: bool,
+
+    /// Print the results as a single JSON object
+    #[structopt(long)]
+    json: bool,
+
+    /// Return received frames with `FillQueue::recycle_from_rx`
+    /// rather than `FillQueue::produce`
+    #[structopt(long)]
+    recycle: bool,
+
+    /// Max cycles per descriptor any operation may take at the
+    /// chosen percentile
+    #[structopt(long, default_value = "100")]
+    budget: u64,
+
+    /// Percentile of the per call cycles per descriptor compared
+    /// against the budget
+    #[structopt(long, default_value = "0.5")]
+    percentile: f64,
+
+    /// Max number of descriptors per call
+    #[structopt(default_value = "64")]
+    batch_size: usize,
+
+    /// Total number of packets to send
+    #[structopt(default_value = "1000000")]
+    num_packets: usize,
+}
+
+/// Cycles per descriptor of every call to one ring operation which
+/// handled at least one.
+#[derive(Debug)]
+struct OpTiming {
+    name: &'static str,
+    per_desc: Histogram,
+    descs: u64,
+}
+
+impl OpTiming {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            per_desc: Histogram::new(),
+            descs: 0,
+        }
+    }
+
+    /// Time `f`, which returns the number of descriptors it handled.
+    #[inline]
+    fn time<F: FnOnce() -> usize>(&mut self, f: F) -> usize {
+        let start = cycles();
+        let cnt = f();
+        let elapsed = cycles().wrapping_sub(start);
+
+        if cnt > 0 {
+            self.per_desc.record(elapsed / cnt as u64);
+            self.descs += cnt as u64;
+        }
+
+        cnt
+    }
+}
+
+fn run_bench(
+    opt: &Opt,
+    pkt: &[u8],
+    dev1: &VethDevConfig,
+    dev2: &VethDevConfig,
+) -> Result<[OpTiming; 4], Box<dyn Error>> {
+    let frame_count = 8192.try_into().unwrap();
+
+    let mut xdp_flags = XdpFlags::empty();
+
+    if opt.skb_mode {
+        xdp_flags |= XdpFlags::XDP_FLAGS_SKB_MODE;
+    }
+
+    let socket_config = SocketConfig::builder()
+        .xdp_flags(xdp_flags)
+        .bind_flags(BindFlags::XDP_USE_NEED_WAKEUP)
+        .build();
+
+    let (tx_umem, mut tx_descs) = Umem::new(UmemConfig::default(), frame_count, false)?;
+    let (rx_umem, rx_descs) = Umem::new(UmemConfig::default(), frame_count, false)?;
+
+    let (mut tx_q, _, tx_fq_and_cq) =
+        unsafe { Socket::new(socket_config, &tx_umem, &dev1.if_name().parse()?, 0) }?;
+
+    let (_, mut rx_q, rx_fq_and_cq) =
+        unsafe { Socket::new(socket_config, &rx_umem, &dev2.if_name().parse()?, 0) }?;
+
+    let (_, mut tx_cq) = tx_fq_and_cq.unwrap();
+    let (mut rx_fq, _) = rx_fq_and_cq.unwrap();
+
+    for desc in tx_descs.iter_mut() {
+        unsafe { tx_umem.data_mut(desc).cursor().write_all(pkt)? };
+    }
+
+    let fq_size = UmemConfig::default().fill_queue_size().get() as usize;
+    assert_eq!(unsafe { rx_fq.produce(&rx_descs[..fq_size]) }, fq_size);
+
+    let mut tx_produce = OpTiming::new("tx_produce");
+    let mut cq_consume = OpTiming::new("cq_consume");
+    let mut rx_consume = OpTiming::new("rx_consume");
+    let mut fq_produce = OpTiming::new(if opt.recycle {
+        "fq_recycle"
+    } else {
+        "fq_produce"
+    });
+
+    let mut free = tx_descs.clone();
+    let mut completed = tx_descs;
+    let mut rx_batch = rx_descs[..opt.batch_size].to_vec();
+
+    let mut sent = 0;
+    let mut received = 0;
+    let mut last_progress = Instant::now();
+
+    while received < opt.num_packets {
+        let cnt = cq_consume.time(|| unsafe { tx_cq.consume(&mut completed) });
+        free.extend_from_slice(&completed[..cnt]);
+
+        let batch = opt.batch_size.min(free.len()).min(opt.num_packets - sent);
+
+        if batch > 0 {
+            let from = free.len() - batch;
+            let cnt = tx_produce.time(|| unsafe { tx_q.produce(&free[from..]) });
+
+            free.drain(from..from + cnt);
+            sent += cnt;
+        }
+
+        if tx_q.needs_wakeup() {
+            tx_q.wakeup()?;
+        }
+
+        let cnt = rx_consume.time(|| unsafe { rx_q.consume(&mut rx_batch) });
+
+        if cnt == 0 {
+            if sent == opt.num_packets && last_progress.elapsed() > DRAIN_TIMEOUT {
+                break;
+            }
+
+            if rx_fq.needs_wakeup() {
+                rx_fq.wakeup(rx_q.fd_mut(), 0)?;
+            }
+
+            continue;
+        }
+
+        received += cnt;
+        last_progress = Instant::now();
+
+        if opt.recycle {
+            fq_produce.time(|| unsafe { rx_fq.recycle_from_rx(&mut rx_batch[..cnt]) });
+        } else {
+            fq_produce.time(|| unsafe { rx_fq.produce(&rx_batch[..cnt]) });
+        }
+    }
+
+    Ok([tx_produce, cq_consume, rx_consume, fq_produce])
+}
+
+/// Prints the results, returning whether every operation was within
+/// budget.
+fn report(opt: &Opt, timings: &[OpTiming]) -> bool {
+    let over: Vec<bool> = timings
+        .iter()
+        .map(|t| t.per_desc.percentile(opt.percentile) > opt.budget)
+        .collect();
+
+    let pass = !over.iter().any(|o| *o);
+
+    if opt.json {
+        let ops: Vec<String> = timings
+            .iter()
+            .zip(&over)
+            .map(|(t, over)| {
+                format!(
+                    "\"{}\":{{\"descs\":{},\"calls\":{},\"mean\":{},\"p50\":{},\"p99\":{},\
+                     \"max\":{},\"over_budget\":{}}}",
+                    t.name,
+                    t.descs,
+                    t.per_desc.count(),
+                    t.per_desc.mean(),
+                    t.per_desc.percentile(0.5),
+                    t.per_desc.percentile(0.99),
+                    t.per_desc.max(),
+                    over
+                )
+            })
+            .collect();
+
+        println!(
+            "{{\"version\":\"{}\",\"batch_size\":{},\"budget\":{},\"percentile\":{},\
+             \"ops\":{{{}}},\"pass\":{}}}",
+            env!("CARGO_PKG_VERSION"),
+            opt.batch_size,
+            opt.budget,
+            opt.percentile,
+            ops.join(","),
+            pass
+        );
+
+        return pass;
+    }
+
+    println!(
+        "cycles per descriptor, batches of up to {}, budget {} at p{}",
+        opt.batch_size,
+        opt.budget,
+        opt.percentile * 100.0
+    );
+    println!(
+        "{:<12} {:>10} {:>8} {:>8} {:>8} {:>8}",
+        "", "descs", "mean", "p50", "p99", "max"
+    );
+
+    for (t, over) in timings.iter().zip(&over) {
+        println!(
+            "{:<12} {:>10} {:>8} {:>8} {:>8} {:>8}{}",
+            t.name,
+            t.descs,
+            t.per_desc.mean(),
+            t.per_desc.percentile(0.5),
+            t.per_desc.percentile(0.99),
+            t.per_desc.max(),
+            if *over { "  over budget" } else { "" }
+        );
+    }
+
+    pass
+}
+
+fn run(
+    opt: Opt,
+    dev1: (VethDevConfig, PacketGenerator),
+    dev2: (VethDevConfig, PacketGenerator),
+) -> io::Result<bool> {
+    let pkt = dev1.1.generate_packet(1234, 1234, 32).unwrap();
+
+    let timings =
+        run_bench(&opt, &pkt, &dev1.0, &dev2.0).map_err(|e| io::Error::other(e.to_string()))?;
+
+    Ok(report(&opt, &timings))
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    let dev1_config = VethDevConfig {
+        if_name: "xsk_test_dev1".into(),
+        addr: [0xf6, 0xe0, 0xf6, 0xc9, 0x60, 0x0a],
+        ip_addr: LinkIpAddr::new(Ipv4Addr::new(192, 168, 69, 1), 24),
+    };
+
+    let dev2_config = VethDevConfig {
+        if_name: "xsk_test_dev2".into(),
+        addr: [0x4a, 0xf1, 0x30, 0xeb, 0x0d, 0x31],
+        ip_addr: LinkIpAddr::new(Ipv4Addr::new(192, 168, 69, 2), 24),
+    };
+
+    // We'll keep track of ctrl+c events but not let them kill the process
+    // immediately as we may need to clean up the veth pair.
+    let ctrl_c_events = util::ctrl_channel().unwrap();
+
+    let (complete_tx, complete_rx) = crossbeam_channel::bounded(1);
+
+    let runtime = Runtime::new().unwrap();
+
+    let bench_handle = thread::spawn(move || {
+        let res = runtime.block_on(veth_setup::run_with_veth_pair(
+            dev1_config,
+            dev2_config,
+            move |dev1, dev2| run(opt, dev1, dev2),
+        ));
+
+        let _ = complete_tx.send(());
+
+        res
+    });
+
+    // Wait for either the bench to finish or for a ctrl+c event to occur.
+    crossbeam_channel::select! {
+        recv(complete_rx) -> _ => {
+        },
+        recv(ctrl_c_events) -> _ => {
+            println!("SIGINT received");
+        }
+    }
+
+    // The veth pair's gone by now, so it's safe to exit.
+    match bench_handle.join().unwrap() {
+        Ok(Ok(true)) => (),
+        Ok(Ok(false)) => process::exit(1),
+        Ok(Err(e)) => {
+            eprintln!("bench failed: {}", e);
+            process::exit(2);
+        }
+        Err(e) => {
+            eprintln!("veth setup failed: {}", e);
+            process::exit(2);
+        }
+    }
+}
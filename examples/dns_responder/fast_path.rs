@@ -0,0 +1,216 @@
+//! Answering DNS A queries in place.
+//!
+//! [`respond`] takes a received frame, and if it's a standard query
+//! over untagged IPv4 UDP for a name in the [`Zone`], rewrites it into
+//! the response in the same buffer, swapping addresses and ports so it
+//! can go straight back out on the tx ring.
+
+use std::net::Ipv4Addr;
+
+use xsk_rs::{addr, checksum};
+
+const ETH_HLEN: usize = 14;
+const ETH_P_IPV4: u16 = 0x0800;
+const IPPROTO_UDP: u8 = 17;
+const UDP_HLEN: usize = 8;
+const DNS_HLEN: usize = 12;
+
+const QTYPE_A: u16 = 1;
+const QTYPE_ANY: u16 = 255;
+const QCLASS_IN: u16 = 1;
+
+const RCODE_NXDOMAIN: u8 = 3;
+
+/// The size of an answer: a pointer to the question's name, type,
+/// class, TTL, length and address.
+const ANSWER_LEN: usize = 2 + 2 + 2 + 4 + 2 + 4;
+
+/// Names served and their addresses.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    // Names in wire format, lower case.
+    records: Vec<(Vec<u8>, Ipv4Addr)>,
+    ttl: u32,
+}
+
+impl Zone {
+    /// An empty zone whose answers have a TTL of `ttl` seconds.
+    pub fn new(ttl: u32) -> Self {
+        Self {
+            records: Vec::new(),
+            ttl,
+        }
+    }
+
+    /// Answer queries for `name`, e.g. `"www.example.com"`, with
+    /// `addr`.
+    pub fn add_a(&mut self, name: &str, addr: Ipv4Addr) -> &mut Self {
+        let mut wire = Vec::with_capacity(name.len() + 2);
+
+        for label in name.trim_end_matches('.').split('.') {
+            wire.push(label.len() as u8);
+            wire.extend(label.bytes().map(|b| b.to_ascii_lowercase()));
+        }
+
+        wire.push(0);
+
+        self.records.push((wire, addr));
+        self
+    }
+
+    fn lookup(&self, qname: &[u8]) -> Option<Ipv4Addr> {
+        self.records
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(qname))
+            .map(|(_, addr)| *addr)
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    buf.get(offset..offset + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn write_u16(buf: &mut [u8], offset: usize, v: u16) {
+    buf[offset..offset + 2].copy_from_slice(&v.to_be_bytes());
+}
+
+/// The length of the wire format name starting at `offset`, if it's
+/// well formed and uncompressed.
+fn name_len(buf: &[u8], offset: usize) -> Option<usize> {
+    let mut pos = offset;
+
+    loop {
+        let len = *buf.get(pos)? as usize;
+
+        if len == 0 {
+            return Some(pos + 1 - offset);
+        }
+
+        // Compression pointers and extended labels aren't valid in a
+        // query's question.
+        if len > 63 {
+            return None;
+        }
+
+        pos += 1 + len;
+    }
+}
+
+/// Turn the query in `buf[..len]` into its response, returning the
+/// response's length, or `None` if this isn't a query to answer.
+///
+/// `buf` is the whole of the frame's buffer, since the response is
+/// longer than the query when there's an answer. Only queries to
+/// `port` with a single question are answered, with NXDOMAIN if the
+/// name isn't in `zone`. Any additional records, e.g. EDNS options,
+/// are dropped.
+pub fn respond(zone: &Zone, port: u16, buf: &mut [u8], len: usize) -> Option<usize> {
+    let frame = buf.get(..len)?;
+
+    if read_u16(frame, 12)? != ETH_P_IPV4 {
+        return None;
+    }
+
+    let ip = ETH_HLEN;
+    let vihl = *frame.get(ip)?;
+    let ihl = usize::from(vihl & 0x0f) * 4;
+
+    if vihl >> 4 != 4 || ihl < 20 || frame.get(ip + 9)? != &IPPROTO_UDP {
+        return None;
+    }
+
+    // Fragments aren't reassembled.
+    if read_u16(frame, ip + 6)? & 0x3fff != 0 {
+        return None;
+    }
+
+    let udp = ip + ihl;
+
+    if read_u16(frame, udp + 2)? != port {
+        return None;
+    }
+
+    let dns = udp + UDP_HLEN;
+    let flags = read_u16(frame, dns + 2)?;
+
+    // A query (QR clear) with the standard opcode and one question.
+    if flags & 0xf800 != 0 || read_u16(frame, dns + 4)? != 1 {
+        return None;
+    }
+
+    let qname = dns + DNS_HLEN;
+    let question_end = qname + name_len(frame, qname)? + 4;
+
+    if question_end > len || question_end > usize::from(read_u16(frame, ip + 2)?) + ip {
+        return None;
+    }
+
+    let qtype = read_u16(frame, question_end - 4)?;
+    let qclass = read_u16(frame, question_end - 2)?;
+
+    let found = zone.lookup(&frame[qname..question_end - 4]);
+
+    let answer = match found {
+        Some(addr) if qclass == QCLASS_IN && (qtype == QTYPE_A || qtype == QTYPE_ANY) => Some(addr),
+        _ => None,
+    };
+
+    let resp_len = question_end + if answer.is_some() { ANSWER_LEN } else { 0 };
+
+    if resp_len > buf.len() {
+        return None;
+    }
+
+    // DNS header: keep the id and RD bit, set QR and AA.
+    let rcode = if found.is_none() { RCODE_NXDOMAIN } else { 0 };
+
+    write_u16(buf, dns + 2, 0x8400 | (flags & 0x0100) | u16::from(rcode));
+    write_u16(buf, dns + 6, answer.is_some() as u16);
+    write_u16(buf, dns + 8, 0);
+    write_u16(buf, dns + 10, 0);
+
+    if let Some(addr) = answer {
+        let a = &mut buf[question_end..resp_len];
+
+        // A pointer back to the question's name.
+        a[0..2].copy_from_slice(&(0xc000 | DNS_HLEN as u16).to_be_bytes());
+        a[2..4].copy_from_slice(&QTYPE_A.to_be_bytes());
+        a[4..6].copy_from_slice(&QCLASS_IN.to_be_bytes());
+        a[6..10].copy_from_slice(&zone.ttl.to_be_bytes());
+        a[10..12].copy_from_slice(&4u16.to_be_bytes());
+        a[12..16].copy_from_slice(&addr.octets());
+    }
+
+    reflect(&mut buf[..resp_len], ip, udp);
+
+    Some(resp_len)
+}
+
+/// Swap the MAC and IP addresses and UDP ports of the frame, making it
+/// a reply, and fix up its lengths and checksums.
+fn reflect(frame: &mut [u8], ip: usize, udp: usize) {
+    let len = frame.len();
+
+    // `respond` checked the IP header is all there.
+    let src = addr::read_ipv4(frame, ip + 12).unwrap();
+    let dst = addr::read_ipv4(frame, ip + 16).unwrap();
+
+    addr::swap_eth_addrs(frame);
+    addr::write_ipv4(frame, ip + 12, dst);
+    addr::write_ipv4(frame, ip + 16, src);
+
+    for i in 0..2 {
+        frame.swap(udp + i, udp + 2 + i);
+    }
+
+    write_u16(frame, ip + 2, (len - ip) as u16);
+    frame[ip + 8] = 64;
+    let check = checksum::ipv4_header(&frame[ip..udp]);
+    write_u16(frame, ip + 10, check);
+
+    write_u16(frame, udp + 4, (len - udp) as u16);
+
+    let check = checksum::ipv4_l4(dst, src, IPPROTO_UDP, &frame[udp..]);
+    write_u16(frame, udp + 6, check);
+}
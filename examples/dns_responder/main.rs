@@ -0,0 +1,288 @@
+//! Answers DNS queries straight off the rx ring.
+//!
+//! dev1 sends A queries for names in a small zone, plus a few that
+//! aren't, and dev2 rewrites each query into its response in the same
+//! UMEM frame and transmits it back, without copying. Completed frames
+//! go straight back onto dev2's fill queue.
+
+use etherparse::PacketBuilder;
+use std::{
+    convert::TryInto,
+    io::Write,
+    net::Ipv4Addr,
+    thread,
+    time::{Duration, Instant},
+};
+use tokio::runtime::Runtime;
+use xsk_rs::{
+    config::{SocketConfig, UmemConfig},
+    Socket, Umem,
+};
+
+mod fast_path;
+use fast_path::Zone;
+
+#[allow(dead_code)]
+#[path = "../setup/mod.rs"]
+mod setup;
+use setup::{util, veth_setup, LinkIpAddr, PacketGenerator, VethDevConfig};
+
+const DNS_PORT: u16 = 53;
+const FRAME_COUNT: u32 = 2048;
+const BATCH_SIZE: usize = 64;
+const NUM_QUERIES: usize = 500_000;
+
+const NAMES: [&str; 4] = [
+    "example.com",
+    "www.example.com",
+    "mail.example.com",
+    "nope.example.com",
+];
+
+fn zone() -> Zone {
+    let mut zone = Zone::new(300);
+
+    zone.add_a("example.com", Ipv4Addr::new(192, 0, 2, 1))
+        .add_a("www.example.com", Ipv4Addr::new(192, 0, 2, 2))
+        .add_a("mail.example.com", Ipv4Addr::new(192, 0, 2, 3));
+
+    zone
+}
+
+/// A standard A query for `name` with id `id`.
+fn dns_query(id: u16, name: &str) -> Vec<u8> {
+    let mut query = Vec::with_capacity(12 + name.len() + 6);
+
+    query.extend_from_slice(&id.to_be_bytes());
+    // RD set, one question.
+    query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+
+    // Root label, type A, class IN.
+    query.extend_from_slice(&[0, 0, 1, 0, 1]);
+
+    query
+}
+
+fn query_packet(src: &VethDevConfig, dst: &VethDevConfig, id: u16, name: &str) -> Vec<u8> {
+    let builder = PacketBuilder::ethernet2(src.addr(), dst.addr())
+        .ipv4(src.ip_addr().octets(), dst.ip_addr().octets(), 64)
+        .udp(40000 + (id % 1000), DNS_PORT);
+
+    let payload = dns_query(id, name);
+    let mut pkt = Vec::with_capacity(builder.size(payload.len()));
+
+    builder.write(&mut pkt, &payload).unwrap();
+
+    pkt
+}
+
+fn dns_responder(dev1: (VethDevConfig, PacketGenerator), dev2: (VethDevConfig, PacketGenerator)) {
+    let zone = zone();
+
+    let queries: Vec<Vec<u8>> = (0..NAMES.len() as u16)
+        .map(|i| query_packet(&dev1.0, &dev2.0, i, NAMES[usize::from(i)]))
+        .collect();
+
+    let (client_umem, client_descs) = Umem::new(
+        UmemConfig::default(),
+        FRAME_COUNT.try_into().unwrap(),
+        false,
+    )
+    .expect("failed to create client UMEM");
+
+    let (mut client_tx_q, mut client_rx_q, client_fq_and_cq) = unsafe {
+        Socket::new(
+            SocketConfig::default(),
+            &client_umem,
+            &dev1.0.if_name().parse().unwrap(),
+            0,
+        )
+    }
+    .expect("failed to create client socket");
+
+    let (mut client_fq, mut client_cq) = client_fq_and_cq.unwrap();
+
+    let (server_umem, server_descs) = Umem::new(
+        UmemConfig::default(),
+        FRAME_COUNT.try_into().unwrap(),
+        false,
+    )
+    .expect("failed to create server UMEM");
+
+    let (mut server_tx_q, mut server_rx_q, server_fq_and_cq) = unsafe {
+        Socket::new(
+            SocketConfig::default(),
+            &server_umem,
+            &dev2.0.if_name().parse().unwrap(),
+            0,
+        )
+    }
+    .expect("failed to create server socket");
+
+    let (mut server_fq, mut server_cq) = server_fq_and_cq.unwrap();
+
+    // Half of the client's frames receive answers, the other half send
+    // queries. All the server's frames start on its fill queue.
+    let (client_rx_descs, client_free) = client_descs.split_at(client_descs.len() / 2);
+    let mut client_free = client_free.to_vec();
+
+    unsafe {
+        client_fq.produce(client_rx_descs);
+        server_fq.produce(&server_descs);
+    }
+
+    let mut client_batch = client_descs[..BATCH_SIZE].to_vec();
+    let mut server_batch = server_descs[..BATCH_SIZE].to_vec();
+
+    let mut sent = 0;
+    let mut answered = 0;
+    let mut nxdomain = 0;
+
+    let start = Instant::now();
+    let deadline = start + Duration::from_secs(30);
+
+    while answered + nxdomain < NUM_QUERIES && Instant::now() < deadline {
+        // Client: recycle completed frames and send more queries.
+        let cnt = unsafe { client_cq.consume(&mut client_batch) };
+        client_free.extend_from_slice(&client_batch[..cnt]);
+
+        let batch = BATCH_SIZE.min(client_free.len()).min(NUM_QUERIES - sent);
+        let from = client_free.len() - batch;
+
+        for (i, desc) in client_free[from..].iter_mut().enumerate() {
+            let query = &queries[(sent + i) % queries.len()];
+
+            unsafe {
+                let mut data = client_umem.data_mut(desc);
+                let mut cursor = data.cursor();
+
+                cursor.set_pos(0);
+                cursor.write_all(query).unwrap();
+            }
+        }
+
+        let cnt = unsafe { client_tx_q.produce(&client_free[from..]) };
+        client_free.drain(from..from + cnt);
+        sent += cnt;
+
+        if client_tx_q.needs_wakeup() {
+            client_tx_q.wakeup().unwrap();
+        }
+
+        // Server: answer what's arrived, from the same frames.
+        let cnt = unsafe { server_rx_q.poll_and_consume(&mut server_batch, 1).unwrap() };
+
+        let mut replies = 0;
+
+        for i in 0..cnt {
+            let mut desc = server_batch[i];
+
+            let resp_len = unsafe {
+                let mut data = server_umem.data_mut(&mut desc);
+                let len = data.contents().len();
+
+                // Expose the whole frame, since the answer makes the
+                // packet longer.
+                data.cursor().set_pos(usize::MAX);
+                let resp_len = fast_path::respond(&zone, DNS_PORT, data.contents_mut(), len);
+                data.cursor().set_pos(resp_len.unwrap_or(0));
+
+                resp_len
+            };
+
+            if resp_len.is_some() {
+                server_batch[replies] = desc;
+                replies += 1;
+            } else {
+                unsafe { server_fq.produce(&[desc]) };
+            }
+        }
+
+        if replies > 0 {
+            unsafe {
+                server_tx_q
+                    .produce_and_wakeup(&server_batch[..replies])
+                    .unwrap()
+            };
+        }
+
+        let cnt = unsafe { server_cq.consume(&mut server_batch) };
+        unsafe { server_fq.produce(&server_batch[..cnt]) };
+
+        // Client: count answers.
+        let cnt = unsafe { client_rx_q.consume(&mut client_batch) };
+
+        for desc in &client_batch[..cnt] {
+            let data = unsafe { client_umem.data(desc) };
+
+            // The RCODE is the low nibble of the fourth DNS header byte.
+            match data.contents().get(42 + 3) {
+                Some(b) if b & 0x0f == 0 => answered += 1,
+                _ => nxdomain += 1,
+            }
+        }
+
+        unsafe { client_fq.produce(&client_batch[..cnt]) };
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+
+    println!(
+        "{} queries sent, {} answered and {} NXDOMAIN in {:.2}s ({:.0} responses/s)",
+        sent,
+        answered,
+        nxdomain,
+        elapsed,
+        (answered + nxdomain) as f64 / elapsed
+    );
+}
+
+fn main() {
+    let dev1_config = VethDevConfig {
+        if_name: "xsk_test_dev1".into(),
+        addr: [0xf6, 0xe0, 0xf6, 0xc9, 0x60, 0x0a],
+        ip_addr: LinkIpAddr::new(Ipv4Addr::new(192, 168, 69, 1), 24),
+    };
+
+    let dev2_config = VethDevConfig {
+        if_name: "xsk_test_dev2".into(),
+        addr: [0x4a, 0xf1, 0x30, 0xeb, 0x0d, 0x31],
+        ip_addr: LinkIpAddr::new(Ipv4Addr::new(192, 168, 69, 2), 24),
+    };
+
+    // We'll keep track of ctrl+c events but not let them kill the process
+    // immediately as we may need to clean up the veth pair.
+    let ctrl_c_events = util::ctrl_channel().unwrap();
+
+    let (complete_tx, complete_rx) = crossbeam_channel::bounded(1);
+
+    let runtime = Runtime::new().unwrap();
+
+    let example_handle = thread::spawn(move || {
+        let res = runtime.block_on(veth_setup::run_with_veth_pair(
+            dev1_config,
+            dev2_config,
+            dns_responder,
+        ));
+
+        let _ = complete_tx.send(());
+
+        res
+    });
+
+    // Wait for either the example to finish or for a ctrl+c event to occur.
+    crossbeam_channel::select! {
+        recv(complete_rx) -> _ => {
+        },
+        recv(ctrl_c_events) -> _ => {
+            println!("SIGINT received");
+        }
+    }
+
+    example_handle.join().unwrap().unwrap();
+}
@@ -0,0 +1,44 @@
+/*
+ * C interface to xsk-rs, built with the `ffi` feature:
+ *
+ *     cargo rustc --release --features ffi --crate-type cdylib
+ *
+ * See the `xsk_rs::ffi` module docs for details. Functions returning
+ * int give a negative errno on failure.
+ */
+#ifndef XSK_RS_H
+#define XSK_RS_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct XskRsSocket XskRsSocket;
+
+typedef struct XskRsDesc {
+	uint64_t addr;
+	uint32_t len;
+	uint32_t options;
+} XskRsDesc;
+
+int xsk_rs_socket_create(const char *if_name, uint32_t queue_id,
+			 uint32_t frame_count, XskRsSocket **out);
+void xsk_rs_socket_destroy(XskRsSocket *sock);
+int xsk_rs_socket_fd(const XskRsSocket *sock);
+
+uint32_t xsk_rs_rx_burst(XskRsSocket *sock, XskRsDesc *descs, uint32_t nb);
+uint32_t xsk_rs_alloc(XskRsSocket *sock, XskRsDesc *descs, uint32_t nb);
+uint32_t xsk_rs_tx_burst(XskRsSocket *sock, const XskRsDesc *descs,
+			 uint32_t nb);
+uint32_t xsk_rs_recycle(XskRsSocket *sock, const XskRsDesc *descs,
+			uint32_t nb);
+
+uint8_t *xsk_rs_frame_data(XskRsSocket *sock, uint64_t addr, uint32_t *cap);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* XSK_RS_H */
@@ -0,0 +1,361 @@
+//! Typed link and network layer addresses, and reading and writing
+//! them in frames.
+//!
+//! IP addresses are the standard library's [`Ipv4Addr`] and
+//! [`Ipv6Addr`], with [`MacAddr`] alongside for Ethernet. The
+//! `read_*` and `write_*` functions move them in and out of a frame at
+//! a given offset, in network byte order, and [`eth_addrs`] and
+//! [`ip_addrs`] pull out a frame's addresses without any header
+//! arithmetic:
+//!
+//! ```
+//! use std::net::{IpAddr, Ipv4Addr};
+//! use xsk_rs::addr::{self, MacAddr};
+//!
+//! let mut frame = [0; 34];
+//! frame[12..14].copy_from_slice(&0x0800u16.to_be_bytes());
+//! frame[14] = 0x45;
+//!
+//! let mac: MacAddr = "02:00:00:00:00:01".parse().unwrap();
+//!
+//! addr::write_mac(&mut frame, 0, mac);
+//! addr::write_ipv4(&mut frame, 14 + 16, Ipv4Addr::new(192, 168, 0, 1));
+//!
+//! assert_eq!(addr::eth_addrs(&frame), Some((mac, MacAddr::UNSPECIFIED)));
+//! assert_eq!(
+//!     addr::ip_addrs(&frame).map(|(_, dst)| dst),
+//!     Some(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)))
+//! );
+//! ```
+//!
+//! Nothing here touches checksums, so a frame whose IP addresses are
+//! rewritten needs them updating, e.g. with the
+//! [`checksum`](crate::checksum) module.
+
+use std::{
+    array::TryFromSliceError,
+    convert::TryFrom,
+    error::Error,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use crate::packet::{self, ETH_P_IPV4, ETH_P_IPV6};
+
+/// A 48 bit Ethernet MAC address.
+///
+/// Displayed and parsed in the usual colon separated hex form, e.g.
+/// `02:00:00:00:00:01`. Parsing also accepts dashes.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MacAddr([u8; 6]);
+
+impl MacAddr {
+    /// The broadcast address, `ff:ff:ff:ff:ff:ff`.
+    pub const BROADCAST: Self = Self([0xff; 6]);
+
+    /// The all zeroes address.
+    pub const UNSPECIFIED: Self = Self([0; 6]);
+
+    /// An address from its six octets.
+    pub const fn new(a: u8, b: u8, c: u8, d: u8, e: u8, f: u8) -> Self {
+        Self([a, b, c, d, e, f])
+    }
+
+    /// The address's six octets.
+    #[inline]
+    pub const fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+
+    /// Whether this is the all zeroes address.
+    #[inline]
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    /// Whether this is the broadcast address.
+    #[inline]
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// Whether this is a group address, broadcast included.
+    #[inline]
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+
+    /// Whether this is an individual address.
+    #[inline]
+    pub fn is_unicast(&self) -> bool {
+        !self.is_multicast()
+    }
+
+    /// Whether this address is locally administered rather than
+    /// assigned by a manufacturer, as with those of veth pairs.
+    #[inline]
+    pub fn is_local(&self) -> bool {
+        self.0[0] & 0x02 != 0
+    }
+}
+
+impl From<[u8; 6]> for MacAddr {
+    fn from(octets: [u8; 6]) -> Self {
+        Self(octets)
+    }
+}
+
+impl From<MacAddr> for [u8; 6] {
+    fn from(addr: MacAddr) -> Self {
+        addr.0
+    }
+}
+
+impl TryFrom<&[u8]> for MacAddr {
+    type Error = TryFromSliceError;
+
+    /// The address in `bytes`, which must be exactly six bytes long.
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        <[u8; 6]>::try_from(bytes).map(Self)
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+impl fmt::Debug for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for MacAddr {
+    type Err = MacAddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sep = if s.contains('-') { '-' } else { ':' };
+
+        let mut octets = [0; 6];
+        let mut parts = s.split(sep);
+
+        for octet in octets.iter_mut() {
+            let part = parts.next().ok_or(MacAddrParseError(()))?;
+
+            if part.len() != 2 {
+                return Err(MacAddrParseError(()));
+            }
+
+            *octet = u8::from_str_radix(part, 16).map_err(|_| MacAddrParseError(()))?;
+        }
+
+        if parts.next().is_some() {
+            return Err(MacAddrParseError(()));
+        }
+
+        Ok(Self(octets))
+    }
+}
+
+/// Error returned when parsing a [`MacAddr`] fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacAddrParseError(());
+
+impl fmt::Display for MacAddrParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid MAC address syntax")
+    }
+}
+
+impl Error for MacAddrParseError {}
+
+/// The MAC address at `offset` in `buf`, or [`None`] if `buf` is too
+/// short.
+#[inline]
+pub fn read_mac(buf: &[u8], offset: usize) -> Option<MacAddr> {
+    let bytes = buf.get(offset..offset.checked_add(6)?)?;
+    MacAddr::try_from(bytes).ok()
+}
+
+/// The IPv4 address at `offset` in `buf`, or [`None`] if `buf` is too
+/// short.
+#[inline]
+pub fn read_ipv4(buf: &[u8], offset: usize) -> Option<Ipv4Addr> {
+    packet::read_u32(buf, offset).map(Ipv4Addr::from)
+}
+
+/// The IPv6 address at `offset` in `buf`, or [`None`] if `buf` is too
+/// short.
+#[inline]
+pub fn read_ipv6(buf: &[u8], offset: usize) -> Option<Ipv6Addr> {
+    let bytes = buf.get(offset..offset.checked_add(16)?)?;
+    <[u8; 16]>::try_from(bytes).ok().map(Ipv6Addr::from)
+}
+
+/// Write `addr` at `offset` in `buf`.
+///
+/// # Panics
+///
+/// If `buf` is too short.
+#[inline]
+pub fn write_mac(buf: &mut [u8], offset: usize, addr: MacAddr) {
+    buf[offset..offset + 6].copy_from_slice(&addr.0);
+}
+
+/// Write `addr` at `offset` in `buf`.
+///
+/// # Panics
+///
+/// If `buf` is too short.
+#[inline]
+pub fn write_ipv4(buf: &mut [u8], offset: usize, addr: Ipv4Addr) {
+    buf[offset..offset + 4].copy_from_slice(&addr.octets());
+}
+
+/// Write `addr` at `offset` in `buf`.
+///
+/// # Panics
+///
+/// If `buf` is too short.
+#[inline]
+pub fn write_ipv6(buf: &mut [u8], offset: usize, addr: Ipv6Addr) {
+    buf[offset..offset + 16].copy_from_slice(&addr.octets());
+}
+
+/// The destination and source MAC addresses of the Ethernet `frame`.
+#[inline]
+pub fn eth_addrs(frame: &[u8]) -> Option<(MacAddr, MacAddr)> {
+    Some((read_mac(frame, 0)?, read_mac(frame, 6)?))
+}
+
+/// The source and destination addresses of the IPv4 or IPv6 packet in
+/// the Ethernet `frame`, behind up to two VLAN tags.
+///
+/// [`None`] if the frame carries something else or is too short.
+pub fn ip_addrs(frame: &[u8]) -> Option<(IpAddr, IpAddr)> {
+    let (ethertype, l3) = packet::l3(frame)?;
+
+    match ethertype {
+        ETH_P_IPV4 => Some((
+            read_ipv4(frame, l3 + 12)?.into(),
+            read_ipv4(frame, l3 + 16)?.into(),
+        )),
+        ETH_P_IPV6 => Some((
+            read_ipv6(frame, l3 + 8)?.into(),
+            read_ipv6(frame, l3 + 24)?.into(),
+        )),
+        _ => None,
+    }
+}
+
+/// Swap the destination and source MAC addresses of the Ethernet
+/// `frame`, e.g. to send it back where it came from.
+///
+/// # Panics
+///
+/// If `frame` is shorter than the two addresses.
+#[inline]
+pub fn swap_eth_addrs(frame: &mut [u8]) {
+    let (dst, src) = frame[..12].split_at_mut(6);
+    dst.swap_with_slice(src);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mac_addrs_round_trip_through_strings() {
+        let mac = MacAddr::new(0x02, 0xab, 0, 0, 0x10, 0xff);
+
+        assert_eq!(mac.to_string(), "02:ab:00:00:10:ff");
+        assert_eq!(format!("{:?}", mac), "02:ab:00:00:10:ff");
+        assert_eq!("02:ab:00:00:10:ff".parse(), Ok(mac));
+        assert_eq!("02-AB-00-00-10-FF".parse(), Ok(mac));
+
+        for bad in [
+            "",
+            "02:ab:00:00:10",
+            "02:ab:00:00:10:ff:00",
+            "2:ab:0:0:10:ff",
+            "02:ab:00:00:10:fg",
+        ] {
+            assert!(bad.parse::<MacAddr>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn mac_addr_kinds() {
+        assert!(MacAddr::BROADCAST.is_broadcast());
+        assert!(MacAddr::BROADCAST.is_multicast());
+        assert!(MacAddr::new(0x01, 0, 0x5e, 0, 0, 1).is_multicast());
+        assert!(MacAddr::UNSPECIFIED.is_unspecified());
+        assert!(MacAddr::UNSPECIFIED.is_unicast());
+        assert!(MacAddr::new(0x02, 0, 0, 0, 0, 1).is_local());
+        assert!(!MacAddr::new(0x00, 0x1b, 0x21, 0, 0, 1).is_local());
+    }
+
+    #[test]
+    fn addrs_are_read_and_written_in_place() {
+        let mut buf = [0; 24];
+
+        let mac = MacAddr::from([1, 2, 3, 4, 5, 6]);
+        let v4 = Ipv4Addr::new(10, 0, 0, 1);
+        let v6 = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+
+        write_mac(&mut buf, 1, mac);
+        assert_eq!(&buf[1..7], &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(read_mac(&buf, 1), Some(mac));
+
+        write_ipv4(&mut buf, 20, v4);
+        assert_eq!(read_ipv4(&buf, 20), Some(v4));
+
+        write_ipv6(&mut buf, 8, v6);
+        assert_eq!(read_ipv6(&buf, 8), Some(v6));
+
+        assert_eq!(read_mac(&buf, 19), None);
+        assert_eq!(read_ipv4(&buf, 21), None);
+        assert_eq!(read_ipv6(&buf, 9), None);
+        assert_eq!(read_mac(&buf, usize::MAX - 2), None);
+        assert_eq!(read_ipv6(&buf, usize::MAX - 2), None);
+        assert_eq!(read_ipv4(&buf, usize::MAX - 2), None);
+
+        assert!(MacAddr::try_from(&buf[..5]).is_err());
+    }
+
+    #[test]
+    fn frame_addrs_are_found_behind_vlan_tags() {
+        let src = MacAddr::new(0x02, 0, 0, 0, 0, 1);
+        let dst = MacAddr::new(0x02, 0, 0, 0, 0, 2);
+
+        let mut frame = vec![0; 18 + 40];
+        write_mac(&mut frame, 0, dst);
+        write_mac(&mut frame, 6, src);
+        frame[12..14].copy_from_slice(&0x8100u16.to_be_bytes());
+        frame[16..18].copy_from_slice(&ETH_P_IPV6.to_be_bytes());
+
+        let ip_src = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+        let ip_dst = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+        write_ipv6(&mut frame, 18 + 8, ip_src);
+        write_ipv6(&mut frame, 18 + 24, ip_dst);
+
+        assert_eq!(eth_addrs(&frame), Some((dst, src)));
+        assert_eq!(ip_addrs(&frame), Some((ip_src.into(), ip_dst.into())));
+
+        swap_eth_addrs(&mut frame);
+        assert_eq!(eth_addrs(&frame), Some((src, dst)));
+
+        assert_eq!(ip_addrs(&frame[..50]), None);
+        frame[16..18].copy_from_slice(&0x0806u16.to_be_bytes());
+        assert_eq!(ip_addrs(&frame), None);
+    }
+}
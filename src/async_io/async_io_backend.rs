@@ -0,0 +1,37 @@
+use ::async_io::{Async, Timer};
+use std::{
+    io,
+    os::unix::prelude::OwnedFd,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use super::Readiness;
+
+/// [`Readiness`] backed by the `async-io` reactor, as used by smol
+/// and async-std.
+#[derive(Debug)]
+pub struct AsyncIoReadiness(Async<OwnedFd>);
+
+impl Readiness for AsyncIoReadiness {
+    type Sleep = Timer;
+
+    fn register(fd: OwnedFd) -> io::Result<Self> {
+        Async::new(fd).map(Self)
+    }
+
+    #[inline]
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.poll_readable(cx)
+    }
+
+    #[inline]
+    fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.poll_writable(cx)
+    }
+
+    #[inline]
+    fn sleep_until(deadline: Instant) -> Self::Sleep {
+        Timer::at(deadline)
+    }
+}
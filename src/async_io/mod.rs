@@ -0,0 +1,558 @@
+//! Awaitable [`RxQueue`] and [`TxQueue`] wrappers.
+//!
+//! The wrappers are runtime agnostic, they only need something which
+//! can tell them when the socket's file descriptor becomes readable
+//! or writable, i.e. an implementation of [`Readiness`]. Two are
+//! provided behind features:
+//!
+//! - `async-io`: `AsyncIoReadiness`, built on `async-io`'s `Async`
+//!   type and so usable from smol, async-std or anything else driven
+//!   by the `async-io` reactor.
+//! - `tokio`: `TokioReadiness`, built on tokio's `AsyncFd`.
+//!
+//! Each wrapper registers its own duplicate of the socket's file
+//! descriptor, since most reactors refuse to register the same
+//! descriptor twice and the rx and tx queues share one.
+//!
+//! With tokio, say, receiving is then just:
+//!
+//! ```no_run
+//! # #[cfg(feature = "tokio")]
+//! # async fn f(rx_q: xsk_rs::RxQueue, mut fq: xsk_rs::FillQueue, mut descs: Vec<xsk_rs::FrameDesc>) -> std::io::Result<()> {
+//! use xsk_rs::async_io::TokioRxQueue;
+//!
+//! let mut rx_q = TokioRxQueue::new(rx_q)?;
+//!
+//! loop {
+//!     let cnt = unsafe { rx_q.recv(&mut fq, &mut descs).await? };
+//!
+//!     // Handle `descs[..cnt]`, then hand them back to `fq`.
+//!     unsafe { fq.produce(&descs[..cnt]) };
+//! }
+//! # }
+//! ```
+
+#[cfg(feature = "async-io")]
+mod async_io_backend;
+#[cfg(feature = "async-io")]
+pub use async_io_backend::AsyncIoReadiness;
+
+#[cfg(feature = "tokio")]
+mod tokio_backend;
+#[cfg(feature = "tokio")]
+pub use tokio_backend::TokioReadiness;
+
+/// An [`AsyncRxQueue`] driven by tokio's reactor.
+#[cfg(feature = "tokio")]
+pub type TokioRxQueue = AsyncRxQueue<TokioReadiness>;
+
+/// An [`AsyncTxQueue`] driven by tokio's reactor.
+#[cfg(feature = "tokio")]
+pub type TokioTxQueue = AsyncTxQueue<TokioReadiness>;
+
+use std::{
+    future::{self, Future},
+    io, mem,
+    os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    pin::pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use crate::{cancel::CancelToken, umem::frame::FrameDesc, FillQueue, RxQueue, TxQueue};
+
+/// Readiness notifications for a file descriptor, along with a timer,
+/// provided by some async runtime's reactor.
+pub trait Readiness: Sized {
+    /// A timer future, completing once its deadline has passed.
+    type Sleep: Future;
+
+    /// Register `fd` with the reactor.
+    fn register(fd: OwnedFd) -> io::Result<Self>;
+
+    /// Poll for read readiness.
+    ///
+    /// Returning [`Poll::Ready`] only means the ring is worth checking
+    /// again, it may be spurious. If this returns [`Poll::Pending`],
+    /// `cx`'s waker must be woken once the descriptor next becomes
+    /// readable.
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+
+    /// Poll for write readiness. Same semantics as
+    /// [`poll_readable`](Self::poll_readable).
+    fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+
+    /// Create a timer which fires at `deadline`.
+    fn sleep_until(deadline: Instant) -> Self::Sleep;
+}
+
+fn dup_fd(fd: RawFd) -> io::Result<OwnedFd> {
+    let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+
+    if dup < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: `dup` is a freshly created descriptor owned by nobody
+    // else.
+    Ok(unsafe { OwnedFd::from_raw_fd(dup) })
+}
+
+/// An [`RxQueue`] whose consumption can be awaited.
+#[derive(Debug)]
+pub struct AsyncRxQueue<R> {
+    rx_q: RxQueue,
+    io: R,
+}
+
+impl<R: Readiness> AsyncRxQueue<R> {
+    /// Wrap `rx_q`, registering its socket with the reactor behind
+    /// `R`.
+    pub fn new(rx_q: RxQueue) -> io::Result<Self> {
+        let io = R::register(dup_fd(rx_q.fd().as_raw_fd())?)?;
+
+        Ok(Self { rx_q, io })
+    }
+
+    /// Poll for received frames, populating `descs` as per
+    /// [`RxQueue::consume`] once at least one is available.
+    ///
+    /// # Safety
+    ///
+    /// See [`RxQueue::consume`].
+    pub unsafe fn poll_consume(
+        &mut self,
+        cx: &mut Context<'_>,
+        descs: &mut [FrameDesc],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let cnt = unsafe { self.rx_q.consume(descs) };
+
+            if cnt > 0 || descs.is_empty() {
+                return Poll::Ready(Ok(cnt));
+            }
+
+            match self.io.poll_readable(cx) {
+                Poll::Ready(Ok(())) => continue,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    /// Wait until at least one frame has been received, then populate
+    /// `descs` as per [`RxQueue::consume`]. Returns the number of
+    /// elements of `descs` updated, which is only zero if `descs` is
+    /// empty.
+    ///
+    /// This is cancel safe: frames are only taken off the ring in the
+    /// same poll which completes the future, so dropping it early
+    /// never loses any.
+    ///
+    /// # Safety
+    ///
+    /// See [`RxQueue::consume`].
+    pub async unsafe fn consume(&mut self, descs: &mut [FrameDesc]) -> io::Result<usize> {
+        future::poll_fn(|cx| unsafe { self.poll_consume(cx, descs) }).await
+    }
+
+    /// Same as [`poll_consume`](Self::poll_consume), but also wakes the
+    /// kernel through `fq` if it [needs it](FillQueue::needs_wakeup)
+    /// before waiting.
+    ///
+    /// With
+    /// [`XDP_USE_NEED_WAKEUP`](crate::config::BindFlags::XDP_USE_NEED_WAKEUP)
+    /// set the driver stops receiving once it runs out of fill ring
+    /// frames, until woken with a syscall. Waiting on the reactor alone
+    /// doesn't do that, so after refilling an empty fill ring the wait
+    /// could go on forever.
+    ///
+    /// # Safety
+    ///
+    /// See [`RxQueue::consume`].
+    pub unsafe fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+        fq: &mut FillQueue,
+        descs: &mut [FrameDesc],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let cnt = unsafe { self.rx_q.consume(descs) };
+
+            if cnt > 0 || descs.is_empty() {
+                return Poll::Ready(Ok(cnt));
+            }
+
+            if fq.needs_wakeup() {
+                if let Err(e) = fq.wakeup(self.rx_q.fd_mut(), 0) {
+                    return Poll::Ready(Err(e));
+                }
+            }
+
+            match self.io.poll_readable(cx) {
+                Poll::Ready(Ok(())) => continue,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    /// Same as [`consume`](Self::consume), waking the kernel through
+    /// `fq` as per [`poll_recv`](Self::poll_recv). Cancel safe in the
+    /// same way.
+    ///
+    /// # Safety
+    ///
+    /// See [`RxQueue::consume`].
+    pub async unsafe fn recv(
+        &mut self,
+        fq: &mut FillQueue,
+        descs: &mut [FrameDesc],
+    ) -> io::Result<usize> {
+        future::poll_fn(|cx| unsafe { self.poll_recv(cx, fq, descs) }).await
+    }
+
+    /// Same as [`consume`](Self::consume) but give up once `cancel` is
+    /// cancelled, returning zero if no frames arrived before then.
+    ///
+    /// Frames already on the ring are still returned after cancelling,
+    /// and cancel safe in the same way as [`consume`](Self::consume),
+    /// so none are lost either way.
+    ///
+    /// # Safety
+    ///
+    /// See [`RxQueue::consume`].
+    pub async unsafe fn consume_or_cancel(
+        &mut self,
+        descs: &mut [FrameDesc],
+        cancel: &CancelToken,
+    ) -> io::Result<usize> {
+        future::poll_fn(|cx| {
+            if let Poll::Ready(res) = unsafe { self.poll_consume(cx, descs) } {
+                return Poll::Ready(res);
+            }
+
+            cancel.poll_cancelled(cx).map(|()| Ok(0))
+        })
+        .await
+    }
+
+    /// Same as [`recv`](Self::recv) but give up once `cancel` is
+    /// cancelled, as per [`consume_or_cancel`](Self::consume_or_cancel).
+    ///
+    /// # Safety
+    ///
+    /// See [`RxQueue::consume`].
+    pub async unsafe fn recv_or_cancel(
+        &mut self,
+        fq: &mut FillQueue,
+        descs: &mut [FrameDesc],
+        cancel: &CancelToken,
+    ) -> io::Result<usize> {
+        future::poll_fn(|cx| {
+            if let Poll::Ready(res) = unsafe { self.poll_recv(cx, fq, descs) } {
+                return Poll::Ready(res);
+            }
+
+            cancel.poll_cancelled(cx).map(|()| Ok(0))
+        })
+        .await
+    }
+
+    /// Same as [`consume`](Self::consume) but give up once `timeout`
+    /// has elapsed, returning zero if no frames arrived before then.
+    ///
+    /// Cancel safe in the same way as [`consume`](Self::consume), and
+    /// frames are never consumed once the timer has fired, so none
+    /// are lost when timing out either.
+    ///
+    /// # Safety
+    ///
+    /// See [`RxQueue::consume`].
+    pub async unsafe fn consume_timeout(
+        &mut self,
+        descs: &mut [FrameDesc],
+        timeout: Duration,
+    ) -> io::Result<usize> {
+        unsafe { self.consume_deadline(descs, Instant::now() + timeout) }.await
+    }
+
+    /// Same as [`consume_timeout`](Self::consume_timeout) but giving up
+    /// at `deadline`. A deadline which has already passed still checks
+    /// the ring once before returning.
+    ///
+    /// # Safety
+    ///
+    /// See [`RxQueue::consume`].
+    pub async unsafe fn consume_deadline(
+        &mut self,
+        descs: &mut [FrameDesc],
+        deadline: Instant,
+    ) -> io::Result<usize> {
+        let mut sleep = pin!(R::sleep_until(deadline));
+
+        future::poll_fn(|cx| {
+            // Check the ring before the timer, so frames which are
+            // already waiting get picked up even if the deadline has
+            // passed.
+            if let Poll::Ready(res) = unsafe { self.poll_consume(cx, descs) } {
+                return Poll::Ready(res);
+            }
+
+            sleep.as_mut().poll(cx).map(|_| Ok(0))
+        })
+        .await
+    }
+
+    /// A reference to the wrapped [`RxQueue`].
+    pub fn get_ref(&self) -> &RxQueue {
+        &self.rx_q
+    }
+
+    /// A mutable reference to the wrapped [`RxQueue`].
+    pub fn get_mut(&mut self) -> &mut RxQueue {
+        &mut self.rx_q
+    }
+
+    /// Deregister from the reactor and return the wrapped
+    /// [`RxQueue`].
+    pub fn into_inner(self) -> RxQueue {
+        self.rx_q
+    }
+}
+
+/// The most times in a row [`AsyncTxQueue`] yields waiting for the
+/// kernel to drain a full ring before falling back to the reactor, in
+/// case it's stalled, e.g. because the link went down.
+const MAX_YIELDS: u32 = 64;
+
+/// How [`AsyncTxQueue`] has waited for room on a full tx ring.
+///
+/// When the socket was bound with
+/// [`XDP_USE_NEED_WAKEUP`](crate::config::BindFlags::XDP_USE_NEED_WAKEUP)
+/// and the kernel doesn't need waking, it's already working through
+/// the ring, so rather than registering interest with the reactor and
+/// waiting for an epoll wakeup the task just yields and tries again.
+/// Under load most waits should then be yields.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WakeupStats {
+    registrations: u64,
+    wakes: u64,
+    yields: u64,
+}
+
+impl WakeupStats {
+    /// The number of times the reactor was polled for write readiness.
+    #[inline]
+    pub fn registrations(&self) -> u64 {
+        self.registrations
+    }
+
+    /// The number of registrations which ended with the reactor
+    /// reporting the socket writable, straight away or by waking the
+    /// task. Once every wait has finished this matches
+    /// [`registrations`](Self::registrations).
+    #[inline]
+    pub fn wakes(&self) -> u64 {
+        self.wakes
+    }
+
+    /// The number of times the task yielded instead, since the kernel
+    /// would make progress without a syscall.
+    #[inline]
+    pub fn yields(&self) -> u64 {
+        self.yields
+    }
+}
+
+/// How to wait for the kernel to free up space on the tx ring.
+#[derive(Debug, PartialEq, Eq)]
+enum TxWait {
+    Yield,
+    Register,
+}
+
+fn tx_wait(uses_need_wakeup: bool, needs_wakeup: bool, yields_in_a_row: u32) -> TxWait {
+    if uses_need_wakeup && !needs_wakeup && yields_in_a_row < MAX_YIELDS {
+        TxWait::Yield
+    } else {
+        TxWait::Register
+    }
+}
+
+/// A [`TxQueue`] whose submissions can be awaited when the ring is
+/// full.
+#[derive(Debug)]
+pub struct AsyncTxQueue<R> {
+    tx_q: TxQueue,
+    io: R,
+    stats: WakeupStats,
+    yields_in_a_row: u32,
+    // Registered with the reactor, which is yet to report back.
+    registered: bool,
+}
+
+impl<R: Readiness> AsyncTxQueue<R> {
+    /// Wrap `tx_q`, registering its socket with the reactor behind
+    /// `R`.
+    pub fn new(tx_q: TxQueue) -> io::Result<Self> {
+        let io = R::register(dup_fd(tx_q.fd().as_raw_fd())?)?;
+
+        Ok(Self {
+            tx_q,
+            io,
+            stats: WakeupStats::default(),
+            yields_in_a_row: 0,
+            registered: false,
+        })
+    }
+
+    /// Poll to submit all of `descs` for transmission, waking the
+    /// kernel as required, as per [`TxQueue::produce_and_wakeup`].
+    ///
+    /// Fails immediately if `descs` could never fit on the ring.
+    ///
+    /// # Safety
+    ///
+    /// See [`TxQueue::produce`].
+    pub unsafe fn poll_produce(
+        &mut self,
+        cx: &mut Context<'_>,
+        descs: &[FrameDesc],
+    ) -> Poll<io::Result<usize>> {
+        if descs.len() > self.tx_q.capacity() {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "more descriptors provided than the tx ring can hold",
+            )));
+        }
+
+        // Polled again after registering, so the reactor woke us.
+        if mem::take(&mut self.registered) {
+            self.stats.wakes += 1;
+        }
+
+        loop {
+            let cnt = unsafe { self.tx_q.produce(descs) };
+
+            if cnt > 0 || descs.is_empty() {
+                self.yields_in_a_row = 0;
+
+                return Poll::Ready(self.tx_q.commit_wakeup().map(|_| cnt));
+            }
+
+            // Ring's full, make sure the kernel is draining it before
+            // waiting for space.
+            if let Err(e) = self.tx_q.commit_wakeup() {
+                return Poll::Ready(Err(e));
+            }
+
+            let wait = tx_wait(
+                self.tx_q.uses_need_wakeup(),
+                self.tx_q.needs_wakeup(),
+                self.yields_in_a_row,
+            );
+
+            if wait == TxWait::Yield {
+                self.stats.yields += 1;
+                self.yields_in_a_row += 1;
+
+                cx.waker().wake_by_ref();
+
+                return Poll::Pending;
+            }
+
+            self.stats.registrations += 1;
+            self.yields_in_a_row = 0;
+
+            match self.io.poll_writable(cx) {
+                Poll::Ready(Ok(())) => {
+                    self.stats.wakes += 1;
+                    continue;
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => {
+                    self.registered = true;
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+
+    /// Wait until there is room on the ring for all of `descs`, then
+    /// submit them and wake the kernel if required. Returns the
+    /// number of frames submitted.
+    ///
+    /// This is cancel safe: either all of `descs` are submitted in
+    /// the poll which completes the future or none are.
+    ///
+    /// # Safety
+    ///
+    /// See [`TxQueue::produce`].
+    pub async unsafe fn produce(&mut self, descs: &[FrameDesc]) -> io::Result<usize> {
+        future::poll_fn(|cx| unsafe { self.poll_produce(cx, descs) }).await
+    }
+
+    /// Same as [`produce`](Self::produce) but give up once `cancel`
+    /// is cancelled, returning zero if there wasn't room for `descs`
+    /// by then. As with `produce`, either all of `descs` are submitted
+    /// or none are, and they're still submitted after cancelling if
+    /// there's room.
+    ///
+    /// # Safety
+    ///
+    /// See [`TxQueue::produce`].
+    pub async unsafe fn produce_or_cancel(
+        &mut self,
+        descs: &[FrameDesc],
+        cancel: &CancelToken,
+    ) -> io::Result<usize> {
+        future::poll_fn(|cx| {
+            if let Poll::Ready(res) = unsafe { self.poll_produce(cx, descs) } {
+                return Poll::Ready(res);
+            }
+
+            cancel.poll_cancelled(cx).map(|()| Ok(0))
+        })
+        .await
+    }
+
+    /// How waits for room on the ring have been handled so far.
+    pub fn wakeup_stats(&self) -> WakeupStats {
+        self.stats
+    }
+
+    /// A reference to the wrapped [`TxQueue`].
+    pub fn get_ref(&self) -> &TxQueue {
+        &self.tx_q
+    }
+
+    /// A mutable reference to the wrapped [`TxQueue`].
+    pub fn get_mut(&mut self) -> &mut TxQueue {
+        &mut self.tx_q
+    }
+
+    /// Deregister from the reactor and return the wrapped
+    /// [`TxQueue`].
+    pub fn into_inner(self) -> TxQueue {
+        self.tx_q
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tx_yields_only_while_the_kernel_is_draining_unprompted() {
+        assert_eq!(tx_wait(true, false, 0), TxWait::Yield);
+        assert_eq!(tx_wait(true, true, 0), TxWait::Register);
+
+        // Without the bind flag `needs_wakeup` is always false, so
+        // says nothing.
+        assert_eq!(tx_wait(false, false, 0), TxWait::Register);
+
+        assert_eq!(tx_wait(true, false, MAX_YIELDS - 1), TxWait::Yield);
+        assert_eq!(tx_wait(true, false, MAX_YIELDS), TxWait::Register);
+    }
+}
@@ -0,0 +1,58 @@
+use std::{
+    io,
+    os::unix::prelude::OwnedFd,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tokio::{
+    io::unix::AsyncFd,
+    time::{self, Sleep},
+};
+
+use super::Readiness;
+
+/// [`Readiness`] backed by tokio's reactor.
+///
+/// Must be registered from within a tokio runtime.
+#[derive(Debug)]
+pub struct TokioReadiness(AsyncFd<OwnedFd>);
+
+impl Readiness for TokioReadiness {
+    type Sleep = Sleep;
+
+    fn register(fd: OwnedFd) -> io::Result<Self> {
+        AsyncFd::new(fd).map(Self)
+    }
+
+    #[inline]
+    fn poll_readable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Tokio's readiness is sticky until cleared. Clearing before
+        // the caller rechecks the ring means a packet landing in
+        // between still raises a fresh event.
+        match self.0.poll_read_ready(cx) {
+            Poll::Ready(Ok(mut guard)) => {
+                guard.clear_ready();
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    #[inline]
+    fn poll_writable(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.0.poll_write_ready(cx) {
+            Poll::Ready(Ok(mut guard)) => {
+                guard.clear_ready();
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    #[inline]
+    fn sleep_until(deadline: Instant) -> Self::Sleep {
+        time::sleep_until(deadline.into())
+    }
+}
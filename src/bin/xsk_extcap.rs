@@ -0,0 +1,373 @@
+//! A Wireshark extcap tool capturing with AF_XDP.
+//!
+//! Copy or link the binary into Wireshark's personal extcap folder,
+//! see Help > About Wireshark > Folders. Every interface then shows up
+//! a second time as `xdp:<name>`, with the queues to capture from set
+//! in its options.
+//!
+//! Capturing needs root, or `CAP_NET_ADMIN`, `CAP_NET_RAW` and
+//! `CAP_BPF`. The default XDP program redirects everything arriving on
+//! the bound queues to the capture, so that traffic no longer reaches
+//! the kernel stack while it's running.
+
+use std::{
+    convert::TryInto,
+    env,
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter},
+    os::unix::io::AsRawFd,
+    process,
+    time::SystemTime,
+};
+
+use xsk_rs::{
+    config::{Interface, SocketConfig, UmemConfig},
+    multi_queue,
+    pcap::PcapWriter,
+    FrameDesc, Xsk,
+};
+
+const PREFIX: &str = "xdp:";
+
+const SNAPLEN: u32 = 65535;
+
+/// The most queues ids can go up to when the interface's queue count
+/// can't be read.
+const MAX_QUEUES: u32 = 1024;
+
+/// Options taking a value which are passed by Wireshark but unused.
+const IGNORED_WITH_VALUE: &[&str] = &[
+    "--extcap-version",
+    "--extcap-capture-filter",
+    "--extcap-control-in",
+    "--extcap-control-out",
+];
+
+#[derive(Debug, Default)]
+struct Args {
+    interfaces: bool,
+    dlts: bool,
+    config: bool,
+    capture: bool,
+    interface: Option<String>,
+    fifo: Option<String>,
+    queues: Option<String>,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{} requires a value", name))
+            };
+
+            match name.as_str() {
+                "--extcap-interfaces" => parsed.interfaces = true,
+                "--extcap-dlts" => parsed.dlts = true,
+                "--extcap-config" => parsed.config = true,
+                "--capture" => parsed.capture = true,
+                "--extcap-interface" => parsed.interface = Some(value()?),
+                "--fifo" => parsed.fifo = Some(value()?),
+                "--queues" => parsed.queues = Some(value()?),
+                other if IGNORED_WITH_VALUE.contains(&other) && inline.is_none() => {
+                    value()?;
+                }
+                _ => (),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn interface(&self) -> Result<Interface, String> {
+        let name = self
+            .interface
+            .as_deref()
+            .ok_or("--extcap-interface is required")?;
+
+        name.strip_prefix(PREFIX)
+            .ok_or_else(|| format!("not an xdp interface: {}", name))?
+            .parse()
+            .map_err(|e| format!("invalid interface name {}: {}", name, e))
+    }
+}
+
+/// Parse a comma separated list of queue ids and inclusive ranges,
+/// e.g. `0,2-3`, each id less than `count`.
+fn parse_queues(s: &str, count: u32) -> Result<Vec<u32>, String> {
+    let mut queues = Vec::new();
+
+    for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let id = |s: &str| match s.trim().parse::<u32>() {
+            Ok(id) if id < count => Ok(id),
+            Ok(id) => Err(format!("queue {} out of range, there are {}", id, count)),
+            Err(_) => Err(format!("invalid queue id: {}", s)),
+        };
+
+        match part.split_once('-') {
+            Some((start, end)) => queues.extend(id(start)?..=id(end)?),
+            None => queues.push(id(part)?),
+        }
+    }
+
+    queues.sort_unstable();
+    queues.dedup();
+
+    if queues.is_empty() {
+        Err("no queues given".into())
+    } else {
+        Ok(queues)
+    }
+}
+
+fn list_interfaces() -> io::Result<()> {
+    println!(
+        "extcap {{version={}}}{{help={}}}",
+        env!("CARGO_PKG_VERSION"),
+        env!("CARGO_PKG_REPOSITORY")
+    );
+
+    let mut names: Vec<_> = fs::read_dir("/sys/class/net")?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect();
+
+    names.sort();
+
+    for name in names {
+        println!(
+            "interface {{value={}{}}}{{display=AF_XDP capture on {}}}",
+            PREFIX, name, name
+        );
+    }
+
+    Ok(())
+}
+
+fn list_dlts() {
+    println!("dlt {{number=1}}{{name=EN10MB}}{{display=Ethernet}}");
+}
+
+fn list_config() {
+    println!(
+        "arg {{number=0}}{{call=--queues}}{{display=Queues}}{{type=string}}{{default=0}}\
+         {{tooltip=Comma separated queue ids or ranges to capture from, e.g. 0,2-3}}"
+    );
+}
+
+/// Why a capture stopped.
+#[derive(Debug)]
+enum Stop {
+    /// Wireshark closed the fifo, which is how it ends a capture.
+    Closed,
+    Failed(String),
+}
+
+impl From<String> for Stop {
+    fn from(reason: String) -> Self {
+        Self::Failed(reason)
+    }
+}
+
+fn write_error(err: io::Error) -> Stop {
+    if err.kind() == io::ErrorKind::BrokenPipe {
+        Stop::Closed
+    } else {
+        Stop::Failed(format!("failed to write capture: {}", err))
+    }
+}
+
+/// `err` followed by its sources.
+fn describe(err: &dyn Error) -> String {
+    let mut desc = err.to_string();
+    let mut source = err.source();
+
+    while let Some(err) = source {
+        desc.push_str(&format!(": {}", err));
+        source = err.source();
+    }
+
+    desc
+}
+
+struct Capture {
+    xsk: Xsk,
+    descs: Vec<FrameDesc>,
+}
+
+fn capture(if_name: &Interface, queues: &[u32], fifo: &str) -> Result<(), Stop> {
+    let umem_config = UmemConfig::default();
+
+    // As many frames as the fill queue holds, so every one can be
+    // posted to it up front.
+    let frame_count = umem_config.fill_queue_size().get().try_into().unwrap();
+
+    let mut captures = queues
+        .iter()
+        .map(|&queue_id| {
+            let mut xsk = Xsk::build(
+                if_name,
+                queue_id,
+                umem_config,
+                SocketConfig::default(),
+                frame_count,
+            )
+            .map_err(|e| format!("failed to bind to queue {}: {}", queue_id, describe(&e)))?;
+
+            let descs = xsk.descs.clone();
+
+            // SAFETY: the frames belong to this socket's UMEM and
+            // aren't in use.
+            unsafe { xsk.fq.produce_and_wakeup(&descs, xsk.rx_q.fd_mut(), 0) }
+                .map_err(|e| format!("failed to post fill queue: {}", e))?;
+
+            Ok(Capture { xsk, descs })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let out = File::create(fifo).map_err(|e| format!("failed to open {}: {}", fifo, e))?;
+    let mut pcap = PcapWriter::new(BufWriter::new(out), SNAPLEN).map_err(write_error)?;
+
+    pcap.flush().map_err(write_error)?;
+
+    let mut pollfds: Vec<_> = captures
+        .iter()
+        .map(|c| libc::pollfd {
+            fd: c.xsk.rx_q.fd().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+
+    loop {
+        // SAFETY: `pollfds` is a valid array of its length.
+        let ready = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as _, 100) };
+
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+
+            return Err(format!("poll failed: {}", err).into());
+        }
+
+        for c in &mut captures {
+            // SAFETY: only this socket's UMEM frames are on its rings.
+            let cnt = unsafe { c.xsk.rx_q.consume(&mut c.descs) };
+
+            if cnt == 0 {
+                continue;
+            }
+
+            let now = SystemTime::now();
+
+            for desc in &c.descs[..cnt] {
+                // SAFETY: the frame was just received so isn't on any
+                // ring.
+                let data = unsafe { c.xsk.umem.data(desc) };
+
+                pcap.write_packet(now, data.contents())
+                    .map_err(write_error)?;
+            }
+
+            // SAFETY: as above.
+            unsafe {
+                c.xsk
+                    .fq
+                    .produce_and_wakeup(&c.descs[..cnt], c.xsk.rx_q.fd_mut(), 0)
+            }
+            .map_err(|e| format!("failed to post fill queue: {}", e))?;
+        }
+
+        pcap.flush().map_err(write_error)?;
+    }
+}
+
+fn run() -> Result<(), String> {
+    let args = Args::parse(env::args().skip(1))?;
+
+    if args.interfaces {
+        list_interfaces().map_err(|e| format!("failed to list interfaces: {}", e))
+    } else if args.dlts {
+        list_dlts();
+        Ok(())
+    } else if args.config {
+        list_config();
+        Ok(())
+    } else if args.capture {
+        let if_name = args.interface()?;
+        let count = multi_queue::rx_queue_count(&if_name).unwrap_or(MAX_QUEUES);
+        let queues = parse_queues(args.queues.as_deref().unwrap_or("0"), count)?;
+        let fifo = args.fifo.as_deref().ok_or("--fifo is required")?;
+
+        match capture(&if_name, &queues, fifo) {
+            Ok(()) | Err(Stop::Closed) => Ok(()),
+            Err(Stop::Failed(reason)) => Err(reason),
+        }
+    } else {
+        Err(
+            "expected one of --extcap-interfaces, --extcap-dlts, --extcap-config or --capture"
+                .into(),
+        )
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_lists_and_wireshark_arguments_are_parsed() {
+        assert_eq!(parse_queues("3, 0-2,2", 4).unwrap(), [0, 1, 2, 3]);
+        assert!(parse_queues("1-x", 4).is_err());
+        assert!(parse_queues(",", 4).is_err());
+        assert!(parse_queues("0-4", 4).is_err());
+        assert!(parse_queues("0-4294967295", MAX_QUEUES).is_err());
+
+        let args = Args::parse(
+            [
+                "--capture",
+                "--extcap-version=4.2",
+                "--extcap-interface",
+                "xdp:eth0",
+                "--extcap-capture-filter",
+                "udp",
+                "--fifo=/tmp/fifo",
+                "--queues",
+                "1",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert!(args.capture);
+        assert_eq!(args.interface.as_deref(), Some("xdp:eth0"));
+        assert!(args.interface().is_ok());
+        assert_eq!(args.fifo.as_deref(), Some("/tmp/fifo"));
+        assert_eq!(args.queues.as_deref(), Some("1"));
+
+        let args = Args::parse(["--extcap-interface=eth0".to_string()]).unwrap();
+        assert!(args.interface().is_err());
+    }
+}
@@ -0,0 +1,106 @@
+//! Checks whether an interface and its driver are ready for AF_XDP
+//! and prints a report, see `xsk_rs::selftest`.
+//!
+//! ```text
+//! xsk-selftest <interface> [--queue <id>]
+//! ```
+//!
+//! Exits with status 0 if the interface is ready, 1 if not and 2 on
+//! bad arguments. Needs root, or `CAP_NET_ADMIN`, `CAP_NET_RAW` and
+//! `CAP_BPF`, to bind.
+
+use std::{env, process};
+
+use xsk_rs::{config::Interface, selftest};
+
+const USAGE: &str = "usage: xsk-selftest <interface> [--queue <id>]";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    if_name: String,
+    queue_id: u32,
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut if_name = None;
+        let mut queue_id = 0;
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--queue" => {
+                    let value = args.next().ok_or("--queue requires a value")?;
+
+                    queue_id = value
+                        .parse()
+                        .map_err(|_| format!("bad queue id {}", value))?;
+                }
+                "-h" | "--help" => return Err(USAGE.into()),
+                other if other.starts_with('-') => {
+                    return Err(format!("unknown option {}\n{}", other, USAGE))
+                }
+                _ if if_name.is_some() => return Err(USAGE.into()),
+                _ => if_name = Some(arg),
+            }
+        }
+
+        Ok(Self {
+            if_name: if_name.ok_or(USAGE)?,
+            queue_id,
+        })
+    }
+}
+
+fn main() {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    let if_name: Interface = match args.if_name.parse() {
+        Ok(if_name) => if_name,
+        Err(e) => {
+            eprintln!("bad interface name: {}", e);
+            process::exit(2);
+        }
+    };
+
+    let report = selftest::run_on_queue(&if_name, args.queue_id);
+
+    println!("{}", report);
+
+    if !report.is_ready() {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn interface_and_queue_are_parsed() {
+        assert_eq!(
+            parse(&["eth0", "--queue", "3"]).unwrap(),
+            Args {
+                if_name: "eth0".into(),
+                queue_id: 3
+            }
+        );
+        assert_eq!(parse(&["eth0"]).unwrap().queue_id, 0);
+
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["eth0", "eth1"]).is_err());
+        assert!(parse(&["eth0", "--queue"]).is_err());
+        assert!(parse(&["eth0", "--queue", "x"]).is_err());
+        assert!(parse(&["eth0", "--verbose"]).is_err());
+    }
+}
@@ -0,0 +1,275 @@
+//! Cancelling waits from one place, e.g. for a clean shutdown or a
+//! config reload.
+//!
+//! A [`CancelToken`] is cloned into everything which should stop
+//! together, and once [cancelled](CancelToken::cancel) each of the
+//! following returns promptly with whatever it got done:
+//!
+//! - Blocking polls: [`RxQueue::poll_or_cancel`] and
+//!   [`TxQueue::poll_or_cancel`] return early, and the token is itself
+//!   [`AsRawFd`] for adding to an application's own poll set.
+//! - Drivers: [`Driver::cancel_on`] makes [`Driver::run`] return after
+//!   the current turn, as a [`Stopper`] would.
+//! - Async adapters: the `_or_cancel` methods of [`AsyncRxQueue`] and
+//!   [`AsyncTxQueue`] resolve to zero frames, and
+//!   [`cancelled`](CancelToken::cancelled) can be awaited directly.
+//!
+//! ```no_run
+//! # use xsk_rs::{CancelToken, RxQueue};
+//! # fn run(mut rx_q: RxQueue) -> std::io::Result<()> {
+//! let cancel = CancelToken::new()?;
+//!
+//! {
+//!     let cancel = cancel.clone();
+//!     ctrlc::set_handler(move || cancel.cancel()).unwrap();
+//! }
+//!
+//! while !cancel.is_cancelled() {
+//!     if rx_q.poll_or_cancel(&cancel, -1)?.is_ready() {
+//!         // ... consume from `rx_q` ...
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Other blocking calls don't observe a token, and return only once
+//! their own timeout expires: [`FramePool::alloc_blocking`] and
+//! [`alloc_timeout`](crate::umem::FramePool::alloc_timeout),
+//! [`FanIn::poll_and_consume`], [`easy::XskSocket::recv_timeout`],
+//! [`RxQueue::drain_for`] and [`QueueGroup::shutdown`]. Loops around
+//! them should check [`is_cancelled`](CancelToken::is_cancelled)
+//! between calls, with timeouts short enough to stop in time.
+//!
+//! Cancellation is one way: a token can't be reset, so a new one is
+//! needed to start things up again.
+//!
+//! [`RxQueue::poll_or_cancel`]: crate::RxQueue::poll_or_cancel
+//! [`TxQueue::poll_or_cancel`]: crate::TxQueue::poll_or_cancel
+//! [`Driver::cancel_on`]: crate::driver::Driver::cancel_on
+//! [`Driver::run`]: crate::driver::Driver::run
+//! [`Stopper`]: crate::driver::Stopper
+//! [`AsyncRxQueue`]: crate::async_io::AsyncRxQueue
+//! [`AsyncTxQueue`]: crate::async_io::AsyncTxQueue
+//! [`FramePool::alloc_blocking`]: crate::umem::FramePool::alloc_blocking
+//! [`FanIn::poll_and_consume`]: crate::socket::FanIn::poll_and_consume
+//! [`easy::XskSocket::recv_timeout`]: crate::easy::XskSocket::recv_timeout
+//! [`RxQueue::drain_for`]: crate::RxQueue::drain_for
+//! [`QueueGroup::shutdown`]: crate::group::QueueGroup::shutdown
+
+use std::{
+    fmt, future, io, mem,
+    os::unix::io::{AsRawFd, RawFd},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use crate::socket::{WakeFd, WeakWakeFd};
+
+struct Inner {
+    cancelled: AtomicBool,
+    // Woken once on cancelling and never cleared, so stays readable.
+    fd: WakeFd,
+    // Weak, so fds dropped before cancelling aren't kept open.
+    wakes: Mutex<Vec<WeakWakeFd>>,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+/// A cloneable handle for cancelling everything it's been passed to,
+/// see the [module docs](self).
+#[derive(Clone)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+impl CancelToken {
+    /// A token which isn't cancelled yet.
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            inner: Arc::new(Inner {
+                cancelled: AtomicBool::new(false),
+                fd: WakeFd::new()?,
+                wakes: Mutex::new(Vec::new()),
+                wakers: Mutex::new(Vec::new()),
+            }),
+        })
+    }
+
+    /// Cancel the token and every clone of it, waking anything waiting
+    /// on one. Cancelling again does nothing.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        // Nothing more can be done if these fail, but blocking waits
+        // still see the flag within their timeout.
+        let _ = self.inner.fd.wake();
+
+        let wakes = mem::take(&mut *lock(&self.inner.wakes));
+
+        for wake in wakes.iter().filter_map(WeakWakeFd::upgrade) {
+            let _ = wake.wake();
+        }
+
+        let wakers = mem::take(&mut *lock(&self.inner.wakers));
+
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Whether the token has been cancelled.
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Wake `wake` on cancelling, or straight away if already
+    /// cancelled, for loops which wait with
+    /// [`poll_or_wake`](crate::RxQueue::poll_or_wake). The loop should
+    /// check [`is_cancelled`](Self::is_cancelled) when woken.
+    ///
+    /// The token doesn't keep `wake` open, so dropping every clone of
+    /// it deregisters it.
+    pub fn wake_on_cancel(&self, wake: &WakeFd) {
+        {
+            let mut wakes = lock(&self.inner.wakes);
+
+            wakes.retain(|w| !w.is_dead());
+            wakes.push(wake.downgrade());
+        }
+
+        if self.is_cancelled() {
+            let _ = wake.wake();
+        }
+    }
+
+    /// Poll for cancellation, registering `cx`'s waker to be woken on
+    /// it if not yet cancelled.
+    ///
+    /// Each task's waker is kept until the token is cancelled, so
+    /// this is meant for a fixed set of long running tasks.
+    pub fn poll_cancelled(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_cancelled() {
+            return Poll::Ready(());
+        }
+
+        {
+            let mut wakers = lock(&self.inner.wakers);
+
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+
+        // Cancelling after the first check may already have taken the
+        // wakers, so check again now ours is in.
+        if self.is_cancelled() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Wait until the token is cancelled.
+    pub async fn cancelled(&self) {
+        future::poll_fn(|cx| self.poll_cancelled(cx)).await
+    }
+}
+
+/// A poisoned lock only means a waker panicked, which leaves the
+/// list itself intact.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl AsRawFd for CancelToken {
+    /// An eventfd which becomes readable once the token is cancelled,
+    /// and stays so. It mustn't be read from.
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::AtomicUsize,
+        task::{Wake, Waker},
+    };
+
+    use super::*;
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn readable(fd: RawFd) -> bool {
+        let mut pfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        unsafe { libc::poll(&mut pfd, 1, 0) == 1 }
+    }
+
+    #[test]
+    fn cancelling_wakes_tasks_and_fds_once() {
+        let cancel = CancelToken::new().unwrap();
+        let clone = cancel.clone();
+
+        let count = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(Arc::clone(&count));
+        let mut cx = Context::from_waker(&waker);
+
+        let wake = WakeFd::new().unwrap();
+        cancel.wake_on_cancel(&wake);
+
+        assert!(cancel.poll_cancelled(&mut cx).is_pending());
+        assert!(cancel.poll_cancelled(&mut cx).is_pending());
+        assert!(!readable(cancel.as_raw_fd()));
+
+        clone.cancel();
+        clone.cancel();
+
+        assert!(cancel.is_cancelled());
+        assert!(cancel.poll_cancelled(&mut cx).is_ready());
+        assert_eq!(count.0.load(Ordering::Relaxed), 1);
+
+        // The token's fd stays readable, while the registered one is
+        // the caller's to clear.
+        assert!(readable(cancel.as_raw_fd()));
+        assert!(readable(cancel.as_raw_fd()));
+        assert!(readable(wake.as_raw_fd()));
+
+        let dropped = WakeFd::new().unwrap();
+        let fresh = CancelToken::new().unwrap();
+        fresh.wake_on_cancel(&dropped);
+        let weak = dropped.downgrade();
+        drop(dropped);
+        assert!(weak.is_dead());
+        fresh.cancel();
+
+        let late = WakeFd::new().unwrap();
+        cancel.wake_on_cancel(&late);
+        assert!(readable(late.as_raw_fd()));
+    }
+}
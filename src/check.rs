@@ -0,0 +1,268 @@
+//! Invariant checks on the hot paths of the
+//! [`FramePool`](crate::umem::FramePool) and the queues, selectable at
+//! runtime.
+//!
+//! Misuse of the `unsafe` queue methods, like handing the kernel a
+//! frame twice or one outside the [`Umem`], tends to
+//! surface far from its cause, as corrupted packets or a pool slowly
+//! running dry. These checks catch it where it happens, panicking with
+//! a description of what went wrong:
+//!
+//! - [`CheckLevel::Cheap`] checks counts and ranges only, at the cost
+//!   of a comparison or two per frame. Frames produced to the
+//!   [`FillQueue`](crate::FillQueue) and [`TxQueue`](crate::TxQueue)
+//!   must lie within their UMEM, and a pool must never hold more
+//!   frames than it was created with.
+//! - [`CheckLevel::Full`] adds checks which scale with batch or pool
+//!   size: no frame may appear twice in a batch produced to a queue,
+//!   or be returned to a pool it's already in.
+//!
+//! The level is process wide and can be changed at any point, e.g. to
+//! run production traffic with cheap checks on:
+//!
+//! ```
+//! use xsk_rs::check::{self, CheckLevel};
+//!
+//! check::set_level(CheckLevel::Cheap);
+//! assert_eq!(check::level(), CheckLevel::Cheap);
+//! ```
+//!
+//! Each batch is checked as a whole before any of it is applied, so a
+//! violation leaves the queue or pool as it was before the call.
+//!
+//! Defaults to [`Full`](CheckLevel::Full) in debug builds and
+//! [`Off`](CheckLevel::Off) otherwise.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::{portable::FrameLayout, umem::Umem};
+
+/// How thoroughly invariants are checked, see the
+/// [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum CheckLevel {
+    /// No checks.
+    Off = 0,
+    /// Constant time count and range checks.
+    Cheap = 1,
+    /// Cheap checks plus those which scale with batch or pool size.
+    Full = 2,
+}
+
+const DEFAULT_LEVEL: CheckLevel = if cfg!(debug_assertions) {
+    CheckLevel::Full
+} else {
+    CheckLevel::Off
+};
+
+static LEVEL: AtomicU8 = AtomicU8::new(DEFAULT_LEVEL as u8);
+
+/// Held by tests which change the level, so they don't race.
+#[cfg(test)]
+pub(crate) static TEST_LEVEL_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Set the check level for the whole process.
+pub fn set_level(level: CheckLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// The current check level.
+pub fn level() -> CheckLevel {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => CheckLevel::Off,
+        1 => CheckLevel::Cheap,
+        _ => CheckLevel::Full,
+    }
+}
+
+/// Whether at least the cheap checks are on.
+#[inline]
+pub(crate) fn cheap() -> bool {
+    LEVEL.load(Ordering::Relaxed) >= CheckLevel::Cheap as u8
+}
+
+/// Whether the full checks are on.
+#[inline]
+pub(crate) fn full() -> bool {
+    LEVEL.load(Ordering::Relaxed) >= CheckLevel::Full as u8
+}
+
+#[cold]
+#[inline(never)]
+#[track_caller]
+pub(crate) fn violated(args: fmt::Arguments<'_>) -> ! {
+    panic!("xsk-rs invariant violated: {}", args)
+}
+
+/// The extent of a [`Umem`], for range checking descriptors against
+/// it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Bounds {
+    len: usize,
+    unaligned_chunks: bool,
+}
+
+impl Bounds {
+    pub(crate) fn of(umem: &Umem) -> Self {
+        Self {
+            len: umem.frame_count() * umem.frame_size(),
+            unaligned_chunks: umem.unaligned_chunks(),
+        }
+    }
+
+    /// Check the `len` bytes at `addr` are within the UMEM, if cheap
+    /// checks are on.
+    #[inline]
+    #[track_caller]
+    pub(crate) fn check(&self, queue: &str, addr: usize, len: usize) {
+        if cheap() && !self.contains(addr, len) {
+            violated(format_args!(
+                "{} given frame {:#x}, which runs past the end of its {} byte UMEM",
+                queue, addr, self.len
+            ));
+        }
+    }
+
+    /// Check every `(addr, len)` in `frames` is within the UMEM, if
+    /// cheap checks are on.
+    #[inline]
+    #[track_caller]
+    pub(crate) fn check_all<I>(&self, queue: &str, frames: I)
+    where
+        I: IntoIterator<Item = (usize, usize)>,
+    {
+        if cheap() {
+            for (addr, len) in frames {
+                self.check(queue, addr, len);
+            }
+        }
+    }
+
+    /// Whether the `len` bytes at `addr` pass [`check`](Self::check),
+    /// for callers which need to tidy up before it panics.
+    #[inline]
+    pub(crate) fn allows(&self, addr: usize, len: usize) -> bool {
+        !cheap() || self.contains(addr, len)
+    }
+
+    #[inline]
+    fn contains(&self, addr: usize, len: usize) -> bool {
+        let offset = FrameLayout::resolve_addr(addr, self.unaligned_chunks);
+
+        offset.checked_add(len).is_some_and(|end| end <= self.len)
+    }
+}
+
+/// Check no address appears twice in `addrs`, if full checks are on.
+#[inline]
+#[track_caller]
+pub(crate) fn unique<I>(queue: &str, addrs: I)
+where
+    I: IntoIterator<Item = usize>,
+{
+    if !full() {
+        return;
+    }
+
+    let dup = with_scratch(|scratch| {
+        scratch.extend(addrs);
+        first_duplicate(scratch)
+    });
+
+    if let Some(addr) = dup {
+        violated(format_args!(
+            "{} given frame {:#x} twice in one batch",
+            queue, addr
+        ));
+    }
+}
+
+thread_local! {
+    static SCRATCH: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Run `f` with an empty buffer which is kept between calls on the
+/// same thread, so full checks don't allocate on every batch.
+pub(crate) fn with_scratch<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut Vec<usize>) -> R,
+{
+    SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+        Ok(mut scratch) => {
+            scratch.clear();
+            f(&mut scratch)
+        }
+        // Only if `f` itself checks, which none do.
+        Err(_) => f(&mut Vec::new()),
+    })
+}
+
+/// The first address to appear twice in `addrs`, which is sorted in
+/// the process.
+pub(crate) fn first_duplicate(addrs: &mut [usize]) -> Option<usize> {
+    addrs.sort_unstable();
+
+    addrs
+        .windows(2)
+        .find(|pair| pair[0] == pair[1])
+        .map(|pair| pair[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_ordered_and_round_trip() {
+        assert!(CheckLevel::Off < CheckLevel::Cheap);
+        assert!(CheckLevel::Cheap < CheckLevel::Full);
+
+        let _guard = TEST_LEVEL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let before = level();
+
+        for lvl in [CheckLevel::Off, CheckLevel::Cheap, CheckLevel::Full] {
+            set_level(lvl);
+            assert_eq!(level(), lvl);
+        }
+
+        set_level(before);
+    }
+
+    #[test]
+    fn bounds_accept_descriptors_inside_the_umem_only() {
+        let bounds = Bounds {
+            len: 4 * 2048,
+            unaligned_chunks: true,
+        };
+
+        assert!(bounds.contains(3 * 2048, 2048));
+        assert!(bounds.contains((64 << 48) | (2 * 2048), 100));
+
+        assert!(!bounds.contains(3 * 2048 + 1, 2048));
+        assert!(!bounds.contains((2048 << 48) | (3 * 2048), 1));
+        assert!(!bounds.contains(usize::MAX, 1));
+    }
+
+    #[test]
+    fn duplicates_are_found_wherever_they_are_in_a_batch() {
+        assert_eq!(first_duplicate(&mut [0, 2048, 4096]), None);
+        assert_eq!(first_duplicate(&mut [4096, 0, 2048, 4096]), Some(4096));
+        assert_eq!(first_duplicate(&mut []), None);
+
+        let cap = with_scratch(|scratch| {
+            scratch.extend([1, 2, 3]);
+            scratch.capacity()
+        });
+
+        // The buffer is reused, and handed out empty.
+        with_scratch(|scratch| {
+            assert!(scratch.is_empty());
+            assert_eq!(scratch.capacity(), cap);
+        });
+    }
+}
@@ -0,0 +1,198 @@
+//! The Internet checksum (RFC 1071), for frames built or rewritten in
+//! place.
+//!
+//! [`sum`] accumulates the one's complement sum of some bytes, which
+//! may be called several times to cover data in pieces, e.g. a pseudo
+//! header then a segment, and [`finish`] folds and complements it into
+//! the value written to the header. [`update`] adjusts an existing
+//! checksum for a changed 16 bit word without summing everything
+//! again, as per RFC 1624.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::packet::{IPPROTO_TCP, IPPROTO_UDP};
+
+/// Add the big endian 16 bit words of `data` to the running sum
+/// `acc`, padding an odd trailing byte with zero.
+///
+/// Only the last piece summed may be of odd length.
+#[inline]
+pub fn sum(data: &[u8], acc: u32) -> u32 {
+    let mut acc = u64::from(acc);
+    let mut chunks = data.chunks_exact(2);
+
+    for word in &mut chunks {
+        acc += u64::from(u16::from_be_bytes([word[0], word[1]]));
+    }
+
+    if let [last] = chunks.remainder() {
+        acc += u64::from(*last) << 8;
+    }
+
+    fold32(acc)
+}
+
+fn fold32(mut acc: u64) -> u32 {
+    while acc >> 32 != 0 {
+        acc = (acc & 0xffff_ffff) + (acc >> 32);
+    }
+
+    acc as u32
+}
+
+/// Fold the running sum `acc` to 16 bits and complement it.
+#[inline]
+pub fn finish(acc: u32) -> u16 {
+    let mut acc = acc;
+
+    while acc >> 16 != 0 {
+        acc = (acc & 0xffff) + (acc >> 16);
+    }
+
+    !(acc as u16)
+}
+
+/// The checksum of `data`.
+#[inline]
+pub fn checksum(data: &[u8]) -> u16 {
+    finish(sum(data, 0))
+}
+
+/// The checksum of an IPv4 header, ignoring whatever is in its
+/// checksum field.
+///
+/// # Panics
+///
+/// If `header` is shorter than 20 bytes.
+pub fn ipv4_header(header: &[u8]) -> u16 {
+    assert!(header.len() >= 20, "IPv4 header too short");
+
+    finish(sum(&header[12..], sum(&header[..10], 0)))
+}
+
+/// The checksum of a UDP or TCP segment over IPv4, from `src` to
+/// `dst`, given as [`Ipv4Addr`]s or their octets, ignoring whatever
+/// is in its checksum field. `protocol` is the IP protocol number, 6
+/// for TCP or 17 for UDP.
+///
+/// For UDP a computed checksum of zero is returned as `0xffff`, since
+/// zero means no checksum.
+///
+/// # Panics
+///
+/// If `segment` is too short to hold the checksum field, i.e. shorter
+/// than 8 bytes for UDP or 18 for TCP.
+pub fn ipv4_l4(
+    src: impl Into<Ipv4Addr>,
+    dst: impl Into<Ipv4Addr>,
+    protocol: u8,
+    segment: &[u8],
+) -> u16 {
+    let check_offset = if protocol == IPPROTO_TCP { 16 } else { 6 };
+
+    assert!(segment.len() >= check_offset + 2, "segment too short");
+
+    let mut acc = sum(&src.into().octets(), 0);
+    acc = sum(&dst.into().octets(), acc);
+    acc += u32::from(protocol);
+    acc += segment.len() as u32;
+
+    l4(acc, protocol, segment, check_offset)
+}
+
+/// The checksum of a UDP or TCP segment over IPv6, as
+/// [`ipv4_l4`] but with the IPv6 pseudo header.
+///
+/// # Panics
+///
+/// As for [`ipv4_l4`].
+pub fn ipv6_l4(
+    src: impl Into<Ipv6Addr>,
+    dst: impl Into<Ipv6Addr>,
+    protocol: u8,
+    segment: &[u8],
+) -> u16 {
+    let check_offset = if protocol == IPPROTO_TCP { 16 } else { 6 };
+
+    assert!(segment.len() >= check_offset + 2, "segment too short");
+
+    let mut acc = sum(&src.into().octets(), 0);
+    acc = sum(&dst.into().octets(), acc);
+    acc = sum(&(segment.len() as u32).to_be_bytes(), acc);
+    acc += u32::from(protocol);
+
+    l4(acc, protocol, segment, check_offset)
+}
+
+/// Finish a transport checksum whose pseudo header is summed in
+/// `acc`, skipping the checksum field at `check_offset`.
+fn l4(acc: u32, protocol: u8, segment: &[u8], check_offset: usize) -> u16 {
+    let acc = sum(&segment[..check_offset], acc);
+    let check = finish(sum(&segment[check_offset + 2..], acc));
+
+    if check == 0 && protocol == IPPROTO_UDP {
+        0xffff
+    } else {
+        check
+    }
+}
+
+/// The new checksum after a 16 bit word covered by `check` changes
+/// from `old` to `new`, as per RFC 1624.
+#[inline]
+pub fn update(check: u16, old: u16, new: u16) -> u16 {
+    // HC' = ~(~HC + ~m + m')
+    finish(u32::from(!check) + u32::from(!old) + u32::from(new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example header from RFC 1071's Wikipedia page, with its
+    // checksum of 0xb861.
+    const IPV4_HEADER: [u8; 20] = [
+        0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xb8, 0x61, 0xc0, 0xa8, 0x00,
+        0x01, 0xc0, 0xa8, 0x00, 0xc7,
+    ];
+
+    #[test]
+    fn header_checksum_matches_and_verifies_to_zero() {
+        assert_eq!(ipv4_header(&IPV4_HEADER), 0xb861);
+        assert_eq!(checksum(&IPV4_HEADER), 0);
+
+        // Summing in pieces gives the same result.
+        assert_eq!(finish(sum(&IPV4_HEADER[4..], sum(&IPV4_HEADER[..4], 0))), 0);
+    }
+
+    #[test]
+    fn incremental_update_matches_recomputing() {
+        let mut header = IPV4_HEADER;
+
+        // Decrement the TTL, the high byte of the word at offset 8.
+        let old = u16::from_be_bytes([header[8], header[9]]);
+        header[8] -= 1;
+        let new = u16::from_be_bytes([header[8], header[9]]);
+
+        assert_eq!(update(0xb861, old, new), ipv4_header(&header));
+    }
+
+    #[test]
+    fn udp_checksum_covers_pseudo_header_and_odd_lengths() {
+        let src = [192, 168, 0, 1];
+        let dst = [192, 168, 0, 199];
+
+        let mut segment = vec![
+            0x04, 0xd2, 0x00, 0x35, 0x00, 0x0b, 0x00, 0x00, b'a', b'b', b'c',
+        ];
+        let check = ipv4_l4(src, dst, IPPROTO_UDP, &segment);
+        segment[6..8].copy_from_slice(&check.to_be_bytes());
+
+        // Verifying over pseudo header and segment gives zero.
+        let mut acc = sum(&src, 0);
+        acc = sum(&dst, acc);
+        acc += 17 + segment.len() as u32;
+
+        assert_eq!(finish(sum(&segment, acc)), 0);
+    }
+}
@@ -0,0 +1,236 @@
+//! The vectorised part of classification: given header fields
+//! gathered into columns, find the frames which are plain IPv4 TCP or
+//! UDP with no options and no fragmentation, whose ports sit at fixed
+//! offsets.
+//!
+//! AVX2 is used on x86_64 when the CPU supports it, NEON on aarch64,
+//! and otherwise a scalar loop.
+
+use crate::packet::{ETH_P_IPV4, IPPROTO_TCP, IPPROTO_UDP};
+
+/// The version and header length byte of an IPv4 header with no
+/// options.
+pub const IPV4_NO_OPTS: u16 = 0x45;
+
+/// The more fragments flag and fragment offset bits.
+pub const IPV4_FRAG_MASK: u16 = 0x3fff;
+
+/// Header fields of each frame in a batch, one column per field.
+#[derive(Debug, Default, Clone)]
+pub struct Columns {
+    pub ethertype: Vec<u16>,
+    pub vihl: Vec<u16>,
+    pub frag: Vec<u16>,
+    pub proto: Vec<u16>,
+    /// Output, `0xffff` for frames on the fast path and zero
+    /// otherwise.
+    pub mask: Vec<u16>,
+}
+
+impl Columns {
+    pub fn clear(&mut self) {
+        self.ethertype.clear();
+        self.vihl.clear();
+        self.frag.clear();
+        self.proto.clear();
+        self.mask.clear();
+    }
+
+    pub fn push(&mut self, ethertype: u16, vihl: u16, frag: u16, proto: u16) {
+        self.ethertype.push(ethertype);
+        self.vihl.push(vihl);
+        self.frag.push(frag);
+        self.proto.push(proto);
+    }
+
+    /// Fill in [`mask`](Self::mask) from the other columns.
+    pub fn compute_mask(&mut self) {
+        self.mask.clear();
+        self.mask.resize(self.ethertype.len(), 0);
+
+        compute_mask(self);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn compute_mask(cols: &mut Columns) {
+    if is_x86_feature_detected!("avx2") {
+        // SAFETY: we've just checked AVX2 is available.
+        unsafe { x86_64::compute_mask(cols, 0) }
+    } else {
+        scalar(cols, 0)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn compute_mask(cols: &mut Columns) {
+    // SAFETY: NEON is part of the aarch64 baseline.
+    unsafe { aarch64::compute_mask(cols, 0) }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn compute_mask(cols: &mut Columns) {
+    scalar(cols, 0)
+}
+
+/// Compute the mask for every lane from `start` onwards.
+pub fn scalar(cols: &mut Columns, start: usize) {
+    for i in start..cols.mask.len() {
+        let fast = cols.ethertype[i] == ETH_P_IPV4
+            && cols.vihl[i] == IPV4_NO_OPTS
+            && cols.frag[i] & IPV4_FRAG_MASK == 0
+            && (cols.proto[i] == u16::from(IPPROTO_TCP) || cols.proto[i] == u16::from(IPPROTO_UDP));
+
+        cols.mask[i] = if fast { 0xffff } else { 0 };
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86_64 {
+    use std::arch::x86_64::*;
+
+    use super::*;
+
+    const LANES: usize = 16;
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn compute_mask(cols: &mut Columns, start: usize) {
+        let len = cols.mask.len();
+        let mut i = start;
+
+        // SAFETY: all columns have `len` elements and each iteration
+        // only touches the `LANES` elements starting at `i`, where
+        // `i + LANES <= len`. Unaligned loads and stores are used
+        // throughout.
+        unsafe {
+            let ethertype = _mm256_set1_epi16(ETH_P_IPV4 as i16);
+            let vihl = _mm256_set1_epi16(IPV4_NO_OPTS as i16);
+            let frag_mask = _mm256_set1_epi16(IPV4_FRAG_MASK as i16);
+            let tcp = _mm256_set1_epi16(IPPROTO_TCP as i16);
+            let udp = _mm256_set1_epi16(IPPROTO_UDP as i16);
+            let zero = _mm256_setzero_si256();
+
+            while i + LANES <= len {
+                macro_rules! load {
+                    ($col:expr) => {
+                        _mm256_loadu_si256($col.as_ptr().add(i) as *const __m256i)
+                    };
+                }
+
+                let is_ipv4 = _mm256_cmpeq_epi16(load!(cols.ethertype), ethertype);
+                let no_opts = _mm256_cmpeq_epi16(load!(cols.vihl), vihl);
+                let unfragmented =
+                    _mm256_cmpeq_epi16(_mm256_and_si256(load!(cols.frag), frag_mask), zero);
+
+                let proto = load!(cols.proto);
+                let has_ports = _mm256_or_si256(
+                    _mm256_cmpeq_epi16(proto, tcp),
+                    _mm256_cmpeq_epi16(proto, udp),
+                );
+
+                let mask = _mm256_and_si256(
+                    _mm256_and_si256(is_ipv4, no_opts),
+                    _mm256_and_si256(unfragmented, has_ports),
+                );
+
+                _mm256_storeu_si256(cols.mask.as_mut_ptr().add(i) as *mut __m256i, mask);
+
+                i += LANES;
+            }
+        }
+
+        scalar(cols, i)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod aarch64 {
+    use std::arch::aarch64::*;
+
+    use super::*;
+
+    const LANES: usize = 8;
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn compute_mask(cols: &mut Columns, start: usize) {
+        let len = cols.mask.len();
+        let mut i = start;
+
+        // SAFETY: all columns have `len` elements and each iteration
+        // only touches the `LANES` elements starting at `i`, where
+        // `i + LANES <= len`.
+        unsafe {
+            let ethertype = vdupq_n_u16(ETH_P_IPV4);
+            let vihl = vdupq_n_u16(IPV4_NO_OPTS);
+            let frag_mask = vdupq_n_u16(IPV4_FRAG_MASK);
+            let tcp = vdupq_n_u16(u16::from(IPPROTO_TCP));
+            let udp = vdupq_n_u16(u16::from(IPPROTO_UDP));
+            let zero = vdupq_n_u16(0);
+
+            while i + LANES <= len {
+                macro_rules! load {
+                    ($col:expr) => {
+                        vld1q_u16($col.as_ptr().add(i))
+                    };
+                }
+
+                let is_ipv4 = vceqq_u16(load!(cols.ethertype), ethertype);
+                let no_opts = vceqq_u16(load!(cols.vihl), vihl);
+                let unfragmented = vceqq_u16(vandq_u16(load!(cols.frag), frag_mask), zero);
+
+                let proto = load!(cols.proto);
+                let has_ports = vorrq_u16(vceqq_u16(proto, tcp), vceqq_u16(proto, udp));
+
+                let mask = vandq_u16(
+                    vandq_u16(is_ipv4, no_opts),
+                    vandq_u16(unfragmented, has_ports),
+                );
+
+                vst1q_u16(cols.mask.as_mut_ptr().add(i), mask);
+
+                i += LANES;
+            }
+        }
+
+        scalar(cols, i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectorised_mask_matches_scalar() {
+        let mut cols = Columns::default();
+
+        // Enough lanes to cover full vectors and a tail, cycling
+        // through which field disqualifies each one.
+        for i in 0..53u16 {
+            let ethertype = if i % 7 == 1 { 0x86dd } else { ETH_P_IPV4 };
+            let vihl = if i % 5 == 2 { 0x46 } else { IPV4_NO_OPTS };
+            let frag = match i % 11 {
+                3 => 0x2000,
+                4 => 0x0010,
+                5 => 0x4000,
+                _ => 0,
+            };
+            let proto = match i % 3 {
+                0 => u16::from(IPPROTO_UDP),
+                1 => u16::from(IPPROTO_TCP),
+                _ => 1,
+            };
+
+            cols.push(ethertype, vihl, frag, proto);
+        }
+
+        cols.compute_mask();
+        let vectorised = cols.mask.clone();
+
+        scalar(&mut cols, 0);
+
+        assert_eq!(vectorised, cols.mask);
+        assert!(vectorised.contains(&0xffff));
+        assert!(vectorised.contains(&0));
+    }
+}
@@ -0,0 +1,211 @@
+use libxdp_sys::XDP_PACKET_HEADROOM;
+use std::{error, fmt};
+
+use super::UmemConfig;
+
+/// The headroom reserved by XDP at the start of every
+/// [`Umem`](crate::Umem) frame, i.e. [`XDP_PACKET_HEADROOM`].
+///
+/// Kept as a distinct type so it can't be mixed up with the
+/// user-configurable [`frame_headroom`], which sits between this
+/// reserved area and the packet data segment.
+///
+/// [`frame_headroom`]: crate::config::UmemConfig::frame_headroom
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XdpHeadroom(u32);
+
+impl XdpHeadroom {
+    /// The amount of headroom reserved by XDP.
+    pub const RESERVED: XdpHeadroom = XdpHeadroom(XDP_PACKET_HEADROOM);
+
+    /// The size of the reserved area in bytes.
+    #[inline]
+    pub fn get(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Tracks how much of a frame's user headroom is still available for
+/// prepending headers (e.g. a VLAN tag or tunnel encapsulation).
+///
+/// Each push is taken from the end of the frame headroom closest to
+/// the packet data, working backwards towards the XDP reserved area.
+/// The budget refuses any push which would cross into that area,
+/// rather than letting it silently overwrite memory XDP owns.
+///
+/// A budget describes a single frame, so one should be created (or
+/// [`reset`](Self::reset)) per packet.
+#[derive(Debug, Clone, Copy)]
+pub struct HeadroomBudget {
+    xdp_headroom: XdpHeadroom,
+    frame_headroom: u32,
+    used: u32,
+}
+
+impl HeadroomBudget {
+    /// Creates a new budget for frames of a [`Umem`](crate::Umem)
+    /// built with `config`, with no headroom used.
+    pub fn new(config: &UmemConfig) -> Self {
+        Self {
+            xdp_headroom: XdpHeadroom::RESERVED,
+            frame_headroom: config.frame_headroom(),
+            used: 0,
+        }
+    }
+
+    /// The headroom reserved by XDP, which is never handed out.
+    #[inline]
+    pub fn xdp_headroom(&self) -> XdpHeadroom {
+        self.xdp_headroom
+    }
+
+    /// The total user headroom of the frame.
+    #[inline]
+    pub fn frame_headroom(&self) -> u32 {
+        self.frame_headroom
+    }
+
+    /// The amount of headroom pushed so far.
+    #[inline]
+    pub fn used(&self) -> u32 {
+        self.used
+    }
+
+    /// The amount of headroom still available.
+    #[inline]
+    pub fn remaining(&self) -> u32 {
+        self.frame_headroom - self.used
+    }
+
+    /// Reserve `len` bytes of headroom directly in front of whatever
+    /// has been pushed already.
+    ///
+    /// On success returns the offset, counting backwards from the
+    /// start of the packet data segment, at which the pushed bytes
+    /// begin. Fails without changing the budget if there are fewer
+    /// than `len` bytes remaining.
+    #[inline]
+    pub fn reserve(&mut self, len: u32) -> Result<u32, HeadroomBudgetError> {
+        if len > self.remaining() {
+            return Err(HeadroomBudgetError {
+                requested: len,
+                remaining: self.remaining(),
+            });
+        }
+
+        self.used += len;
+
+        Ok(self.used)
+    }
+
+    /// Hand back `len` bytes of previously reserved headroom, for
+    /// example after popping a header.
+    ///
+    /// Releasing more than has been reserved simply frees the whole
+    /// budget.
+    #[inline]
+    pub fn release(&mut self, len: u32) {
+        self.used = self.used.saturating_sub(len);
+    }
+
+    /// Mark all of the headroom as available again.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.used = 0;
+    }
+}
+
+impl From<UmemConfig> for HeadroomBudget {
+    fn from(config: UmemConfig) -> Self {
+        Self::new(&config)
+    }
+}
+
+/// Error signifying that a headroom reservation would have
+/// encroached on the XDP reserved area.
+#[derive(Debug)]
+pub struct HeadroomBudgetError {
+    requested: u32,
+    remaining: u32,
+}
+
+impl HeadroomBudgetError {
+    /// The number of bytes which were requested.
+    pub fn requested(&self) -> u32 {
+        self.requested
+    }
+
+    /// The number of bytes which were available.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+}
+
+impl fmt::Display for HeadroomBudgetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "requested {} bytes of headroom but only {} remaining",
+            self.requested, self.remaining
+        )
+    }
+}
+
+impl error::Error for HeadroomBudgetError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(frame_headroom: u32) -> HeadroomBudget {
+        let config = UmemConfig::builder()
+            .frame_headroom(frame_headroom)
+            .build()
+            .unwrap();
+
+        HeadroomBudget::new(&config)
+    }
+
+    #[test]
+    fn reservations_cannot_exceed_frame_headroom() {
+        let mut budget = budget(16);
+
+        assert_eq!(budget.reserve(4).unwrap(), 4);
+        assert_eq!(budget.reserve(8).unwrap(), 12);
+        assert_eq!(budget.remaining(), 4);
+
+        let err = budget.reserve(5).unwrap_err();
+
+        assert_eq!(err.requested(), 5);
+        assert_eq!(err.remaining(), 4);
+        assert_eq!(budget.used(), 12);
+
+        assert_eq!(budget.reserve(4).unwrap(), 16);
+        assert_eq!(budget.remaining(), 0);
+    }
+
+    #[test]
+    fn zero_frame_headroom_allows_no_pushes() {
+        let mut budget = budget(0);
+
+        assert_eq!(budget.xdp_headroom().get(), XDP_PACKET_HEADROOM);
+        assert!(budget.reserve(1).is_err());
+        assert!(budget.reserve(0).is_ok());
+    }
+
+    #[test]
+    fn release_and_reset_return_headroom() {
+        let mut budget = budget(16);
+
+        budget.reserve(10).unwrap();
+        budget.release(4);
+        assert_eq!(budget.used(), 6);
+
+        budget.release(100);
+        assert_eq!(budget.used(), 0);
+
+        budget.reserve(16).unwrap();
+        budget.reset();
+        assert_eq!(budget.remaining(), 16);
+    }
+}
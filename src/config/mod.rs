@@ -4,24 +4,23 @@
 mod socket;
 pub use socket::{
     BindFlags, Config as SocketConfig, ConfigBuilder as SocketConfigBuilder, Interface,
-    LibxdpFlags, XdpFlags,
+    LibxdpFlags, UnknownDescOptions, XdpFlags,
 };
 
+mod headroom;
+pub use headroom::{HeadroomBudget, HeadroomBudgetError, XdpHeadroom};
+
 mod umem;
 pub use umem::{
     Config as UmemConfig, ConfigBuildError as UmemConfigBuilderError,
-    ConfigBuilder as UmemConfigBuilder,
+    ConfigBuilder as UmemConfigBuilder, HugePages,
 };
 
 use std::{convert::TryFrom, error, fmt};
 
-use crate::util;
+use crate::portable;
 
-/// The minimum [`Umem`](crate::Umem) frame size.
-///
-/// Matches the constant of the same name defined in the linux source
-/// at `net/xdp/xdp_umem.c`
-pub const XDP_UMEM_MIN_CHUNK_SIZE: u32 = 2048;
+pub use crate::portable::XDP_UMEM_MIN_CHUNK_SIZE;
 
 /// A ring's buffer size. Must be a power of two.
 #[derive(Debug, Clone, Copy)]
@@ -31,7 +30,7 @@ impl QueueSize {
     /// Create a new `QueueSize` instance. Fails if `size` is not a
     /// power of two.
     pub fn new(size: u32) -> Result<Self, QueueSizeError> {
-        if !util::is_pow_of_two(size) {
+        if !portable::is_valid_queue_size(size) {
             Err(QueueSizeError(size))
         } else {
             Ok(Self(size))
@@ -73,7 +72,7 @@ impl FrameSize {
     /// Create a new `FrameSize` instance. Fails if `size` is smaller
     /// than [`XDP_UMEM_MIN_CHUNK_SIZE`].
     pub fn new(size: u32) -> Result<Self, FrameSizeError> {
-        if size < XDP_UMEM_MIN_CHUNK_SIZE {
+        if !portable::is_valid_frame_size(size) {
             Err(FrameSizeError(size))
         } else {
             Ok(Self(size))
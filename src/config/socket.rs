@@ -6,9 +6,13 @@ use libxdp_sys::{
 use std::{
     convert::{TryFrom, TryInto},
     ffi::{CStr, CString, NulError},
+    fs, io,
+    num::NonZeroUsize,
     str::FromStr,
 };
 
+use crate::{addr::MacAddr, socket::BusyPoll};
+
 use super::QueueSize;
 
 bitflags! {
@@ -59,9 +63,39 @@ bitflags! {
         /// in the
         /// [docs](https://www.kernel.org/doc/html/latest/networking/af_xdp.html#xdp-use-need-wakeup-bind-flag).
         const XDP_USE_NEED_WAKEUP = 8;
+        /// Enable multi-buffer, so packets larger than a frame are
+        /// received and sent as a chain of descriptors linked with
+        /// [`XDP_PKT_CONTD`]. Requires driver support, socket
+        /// creation fails with `EOPNOTSUPP` otherwise. See
+        /// [`Packets`] for splitting received descriptors back into
+        /// packets and [`chain`] for marking ones to send.
+        ///
+        /// [`XDP_PKT_CONTD`]: crate::umem::frame::DescOptions::XDP_PKT_CONTD
+        /// [`Packets`]: crate::umem::frame::Packets
+        /// [`chain`]: crate::umem::frame::chain
+        const XDP_USE_SG = 16;
     }
 }
 
+/// What an [`RxQueue`](crate::RxQueue) does with descriptor option
+/// bits it doesn't recognise, i.e. anything outside of
+/// [`DescOptions::all`](crate::umem::frame::DescOptions::all).
+///
+/// Newer kernels may start setting bits this version of the library
+/// knows nothing about. Either way the frame itself is still handed
+/// over as normal and the number of such descriptors is counted, see
+/// [`RxQueue::unknown_options_count`](crate::RxQueue::unknown_options_count).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownDescOptions {
+    /// Pass unknown bits through untouched, so they can be inspected
+    /// via [`FrameDesc::unknown_options`](crate::FrameDesc::unknown_options).
+    #[default]
+    Preserve,
+    /// Clear unknown bits, so [`FrameDesc::options`](crate::FrameDesc::options)
+    /// only ever contains known ones.
+    Strip,
+}
+
 /// A device interface name.
 #[derive(Debug, Clone)]
 pub struct Interface(CString);
@@ -72,6 +106,36 @@ impl Interface {
         Self(name)
     }
 
+    /// The interface's current MTU, as read from sysfs.
+    ///
+    /// This excludes the link layer header, so the largest frame the
+    /// interface will send or receive is somewhat bigger.
+    pub fn mtu(&self) -> io::Result<u32> {
+        let name = self
+            .0
+            .to_str()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        fs::read_to_string(format!("/sys/class/net/{}/mtu", name))?
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The interface's current MAC address, as read from sysfs, e.g.
+    /// for the source address of frames built to send from it.
+    pub fn mac_addr(&self) -> io::Result<MacAddr> {
+        let name = self
+            .0
+            .to_str()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        fs::read_to_string(format!("/sys/class/net/{}/address", name))?
+            .trim()
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
     pub(crate) fn as_cstr(&self) -> &CStr {
         &self.0
     }
@@ -116,14 +180,16 @@ impl ConfigBuilder {
     }
 
     /// Set the [`RxQueue`](crate::RxQueue) size. Default is
-    /// [`XSK_RING_CONS__DEFAULT_NUM_DESCS`].
+    /// [`XSK_RING_CONS__DEFAULT_NUM_DESCS`]. Ignored by
+    /// [`Socket::new_tx_only`](crate::Socket::new_tx_only).
     pub fn rx_queue_size(&mut self, size: QueueSize) -> &mut Self {
         self.config.rx_queue_size = size;
         self
     }
 
     /// Set the [`TxQueue`](crate::RxQueue) size. Default is
-    /// [`XSK_RING_PROD__DEFAULT_NUM_DESCS`].
+    /// [`XSK_RING_PROD__DEFAULT_NUM_DESCS`]. Ignored by
+    /// [`Socket::new_rx_only`](crate::Socket::new_rx_only).
     pub fn tx_queue_size(&mut self, size: QueueSize) -> &mut Self {
         self.config.tx_queue_size = size;
         self
@@ -147,6 +213,50 @@ impl ConfigBuilder {
         self
     }
 
+    /// Set how unrecognised descriptor option bits are handled on
+    /// receive. Default is [`UnknownDescOptions::Preserve`].
+    pub fn unknown_desc_options(&mut self, policy: UnknownDescOptions) -> &mut Self {
+        self.config.unknown_desc_options = policy;
+        self
+    }
+
+    /// Set the busy polling options applied to the socket when it's
+    /// created, the equivalent of calling
+    /// [`Fd::set_busy_poll`](crate::socket::Fd::set_busy_poll)
+    /// straight after. Default is `None`, leaving them unset.
+    ///
+    /// Socket creation fails if the options can't be set, e.g. if
+    /// [preferring](BusyPoll::prefer) busy polling or setting a
+    /// [budget](BusyPoll::budget) on a kernel older than 5.11. A plain
+    /// [`BusyPoll::new`] timeout can be set on any kernel, though it
+    /// only has an effect from 5.11.
+    ///
+    /// Pair with [`RxQueue::busy_poll_recv`] and
+    /// [`TxQueue::busy_poll_send`], which drive the driver from the
+    /// calling thread.
+    ///
+    /// [`RxQueue::busy_poll_recv`]: crate::RxQueue::busy_poll_recv
+    /// [`TxQueue::busy_poll_send`]: crate::TxQueue::busy_poll_send
+    pub fn busy_poll(&mut self, busy_poll: Option<BusyPoll>) -> &mut Self {
+        self.config.busy_poll = busy_poll;
+        self
+    }
+
+    /// Set the most frames the queues' batching helpers handle in one
+    /// call, e.g. to bound how long each iteration of a latency
+    /// sensitive loop can take. Default is `None`, leaving batches as
+    /// large as asked for.
+    ///
+    /// Larger requests are clamped rather than refused, see
+    /// [`RxQueue::max_burst`](crate::RxQueue::max_burst) and
+    /// [`TxQueue::max_burst`](crate::TxQueue::max_burst) for which
+    /// methods respect it. The plain `consume` and `produce` methods
+    /// don't, and handle however many descriptors they're given.
+    pub fn max_burst(&mut self, max_burst: Option<NonZeroUsize>) -> &mut Self {
+        self.config.max_burst = max_burst;
+        self
+    }
+
     /// Build a [`SocketConfig`](Config) instance using the values set
     /// in this builder.
     pub fn build(&self) -> Config {
@@ -162,6 +272,9 @@ pub struct Config {
     libxdp_flags: LibxdpFlags,
     xdp_flags: XdpFlags,
     bind_flags: BindFlags,
+    unknown_desc_options: UnknownDescOptions,
+    busy_poll: Option<BusyPoll>,
+    max_burst: Option<NonZeroUsize>,
 }
 
 impl Config {
@@ -194,6 +307,22 @@ impl Config {
     pub fn bind_flags(&self) -> &BindFlags {
         &self.bind_flags
     }
+
+    /// How unrecognised descriptor option bits are handled on
+    /// receive.
+    pub fn unknown_desc_options(&self) -> UnknownDescOptions {
+        self.unknown_desc_options
+    }
+
+    /// The busy polling options applied on creation, if any.
+    pub fn busy_poll(&self) -> Option<BusyPoll> {
+        self.busy_poll
+    }
+
+    /// The most frames handled per batching helper call, if limited.
+    pub fn max_burst(&self) -> Option<NonZeroUsize> {
+        self.max_burst
+    }
 }
 
 impl Default for Config {
@@ -204,6 +333,9 @@ impl Default for Config {
             libxdp_flags: LibxdpFlags::empty(),
             xdp_flags: XdpFlags::empty(),
             bind_flags: BindFlags::empty(),
+            unknown_desc_options: UnknownDescOptions::default(),
+            busy_poll: None,
+            max_burst: None,
         }
     }
 }
@@ -1,11 +1,33 @@
 use libxdp_sys::{
-    xsk_umem_config, XDP_PACKET_HEADROOM, XSK_RING_CONS__DEFAULT_NUM_DESCS,
-    XSK_RING_PROD__DEFAULT_NUM_DESCS, XSK_UMEM__DEFAULT_FRAME_HEADROOM,
-    XSK_UMEM__DEFAULT_FRAME_SIZE,
+    xsk_umem_config, XDP_PACKET_HEADROOM, XDP_UMEM_UNALIGNED_CHUNK_FLAG,
+    XSK_RING_CONS__DEFAULT_NUM_DESCS, XSK_RING_PROD__DEFAULT_NUM_DESCS,
+    XSK_UMEM__DEFAULT_FRAME_HEADROOM, XSK_UMEM__DEFAULT_FRAME_SIZE,
 };
 use std::{error, fmt};
 
-use super::{FrameSize, QueueSize};
+use crate::portable::{self, FrameLayout};
+
+use super::{FrameSize, HeadroomBudget, QueueSize};
+
+/// Huge page backing for a [`Umem`](crate::umem::Umem)'s memory.
+///
+/// Huge pages need reserving up front, e.g. through
+/// `/sys/kernel/mm/hugepages/hugepages-<size>kB/nr_hugepages`. If too
+/// few are free the region falls back to regular pages, which
+/// [`Umem::huge_pages`](crate::umem::Umem::huge_pages) reports.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HugePages {
+    /// Regular pages.
+    #[default]
+    Off,
+    /// The system's default huge page size, `Hugepagesize` in
+    /// `/proc/meminfo`.
+    Default,
+    /// 2MB huge pages.
+    Size2M,
+    /// 1GB huge pages.
+    Size1G,
+}
 
 /// Builder for a [`UmemConfig`](Config).
 #[derive(Debug, Default, Clone, Copy)]
@@ -21,6 +43,9 @@ impl ConfigBuilder {
 
     /// Set the frame size. Default is
     /// [`XSK_UMEM__DEFAULT_FRAME_SIZE`].
+    ///
+    /// Must be a power of two unless [unaligned
+    /// chunks](Self::unaligned_chunks) are enabled.
     pub fn frame_size(&mut self, size: FrameSize) -> &mut Self {
         self.config.frame_size = size;
         self
@@ -50,6 +75,55 @@ impl ConfigBuilder {
         self
     }
 
+    /// Register the UMEM in unaligned chunk mode, in which the kernel
+    /// accepts descriptors at any address rather than only within
+    /// their frame's bounds. Default is `false`.
+    ///
+    /// Needed to split frames into several smaller tx buffers, see
+    /// [`FramePool::split_frames`](crate::umem::FramePool::split_frames).
+    pub fn unaligned_chunks(&mut self, enabled: bool) -> &mut Self {
+        self.config.unaligned_chunks = enabled;
+        self
+    }
+
+    /// Back the UMEM with huge pages, cutting TLB misses on the hot
+    /// path for large frame counts. Default is [`HugePages::Off`].
+    ///
+    /// Falls back to regular pages if the requested size can't be
+    /// mapped, unlike passing `use_huge_pages` to
+    /// [`Umem::new`](crate::umem::Umem::new), which fails instead.
+    pub fn huge_pages(&mut self, huge_pages: HugePages) -> &mut Self {
+        self.config.huge_pages = huge_pages;
+        self
+    }
+
+    /// Back the UMEM with a memfd rather than anonymous memory, so
+    /// its [fd](crate::Umem::memfd) can be passed to an unrelated
+    /// process, which maps it with
+    /// [`UmemMapping::open`](crate::handoff::UmemMapping::open) to
+    /// read frames handed to it. Default is `false`, in which case only
+    /// processes forked from this one share the memory.
+    pub fn memfd(&mut self, enabled: bool) -> &mut Self {
+        self.config.memfd = enabled;
+        self
+    }
+
+    /// Allocate the UMEM's memory on NUMA node `node`, typically the
+    /// one the NIC is attached to as found by
+    /// [`numa::interface_node`](crate::numa::interface_node). Default
+    /// is `None`, leaving it to the calling thread's memory policy.
+    ///
+    /// The region is bound to the node with `mbind(2)` before any of
+    /// it is faulted in, so [`Umem::new`](crate::umem::Umem::new)
+    /// fails, rather than quietly using other nodes' memory, if the
+    /// node doesn't exist, or with `ENOMEM` if it hasn't enough free.
+    /// Kernels before 5.14 fault it in by locking it, which counts
+    /// against `RLIMIT_MEMLOCK` for the duration.
+    pub fn numa_node(&mut self, node: Option<u32>) -> &mut Self {
+        self.config.numa_node = node;
+        self
+    }
+
     /// Build a [`UmemConfig`](Config) instance using the values set
     /// in this builder.
     ///
@@ -57,16 +131,24 @@ impl ConfigBuilder {
     /// if the requested frame headroom exceeds the frame size.
     pub fn build(&self) -> Result<Config, ConfigBuildError> {
         let frame_size = self.config.frame_size.get();
-        let total_headroom = XDP_PACKET_HEADROOM + self.config.frame_headroom;
+        let frame_headroom = self.config.frame_headroom;
+        let unaligned_chunks = self.config.unaligned_chunks;
 
-        if total_headroom > frame_size {
-            Err(ConfigBuildError {
+        if !portable::is_valid_chunk_size(frame_size, unaligned_chunks) {
+            return Err(ConfigBuildError::FrameSize {
                 frame_size,
-                total_headroom,
-            })
-        } else {
-            Ok(self.config)
+                unaligned_chunks,
+            });
         }
+
+        if FrameLayout::new(frame_size, XDP_PACKET_HEADROOM, frame_headroom).is_none() {
+            return Err(ConfigBuildError::Headroom {
+                frame_size,
+                total_headroom: XDP_PACKET_HEADROOM.saturating_add(frame_headroom),
+            });
+        }
+
+        Ok(self.config)
     }
 }
 
@@ -84,6 +166,10 @@ pub struct Config {
     fill_queue_size: QueueSize,
     comp_queue_size: QueueSize,
     frame_headroom: u32,
+    unaligned_chunks: bool,
+    huge_pages: HugePages,
+    numa_node: Option<u32>,
+    memfd: bool,
 }
 
 impl Config {
@@ -126,6 +212,32 @@ impl Config {
     pub fn mtu(&self) -> u32 {
         self.frame_size.get() - (self.xdp_headroom() + self.frame_headroom)
     }
+
+    /// Whether the [`Umem`](crate::Umem) is registered in unaligned
+    /// chunk mode.
+    pub fn unaligned_chunks(&self) -> bool {
+        self.unaligned_chunks
+    }
+
+    /// The huge pages requested to back the UMEM.
+    pub fn huge_pages(&self) -> HugePages {
+        self.huge_pages
+    }
+
+    /// The NUMA node the UMEM's memory is bound to, if any.
+    pub fn numa_node(&self) -> Option<u32> {
+        self.numa_node
+    }
+
+    /// Whether the UMEM is backed by a memfd.
+    pub fn memfd(&self) -> bool {
+        self.memfd
+    }
+
+    /// A fresh [`HeadroomBudget`] for frames using this config.
+    pub fn headroom_budget(&self) -> HeadroomBudget {
+        HeadroomBudget::new(self)
+    }
 }
 
 impl Default for Config {
@@ -135,6 +247,10 @@ impl Default for Config {
             fill_queue_size: QueueSize(XSK_RING_PROD__DEFAULT_NUM_DESCS),
             comp_queue_size: QueueSize(XSK_RING_CONS__DEFAULT_NUM_DESCS),
             frame_headroom: XSK_UMEM__DEFAULT_FRAME_HEADROOM,
+            unaligned_chunks: false,
+            huge_pages: HugePages::Off,
+            numa_node: None,
+            memfd: false,
         }
     }
 }
@@ -146,25 +262,55 @@ impl From<Config> for xsk_umem_config {
             comp_size: c.comp_queue_size.get(),
             frame_size: c.frame_size.get(),
             frame_headroom: c.frame_headroom,
-            flags: 0,
+            flags: if c.unaligned_chunks {
+                XDP_UMEM_UNALIGNED_CHUNK_FLAG
+            } else {
+                0
+            },
         }
     }
 }
 
 /// Error detailing why [`UmemConfig`](Config) creation failed.
+///
+/// More reasons may be added, so matches need a wildcard arm.
 #[derive(Debug)]
-pub struct ConfigBuildError {
-    frame_size: u32,
-    total_headroom: u32,
+#[non_exhaustive]
+pub enum ConfigBuildError {
+    /// The frame size isn't a power of two, which the kernel requires
+    /// unless the UMEM is in unaligned chunk mode.
+    FrameSize {
+        /// The frame size requested.
+        frame_size: u32,
+        /// Whether unaligned chunk mode was requested.
+        unaligned_chunks: bool,
+    },
+    /// The XDP and user headroom don't fit in a frame.
+    Headroom {
+        /// The frame size requested.
+        frame_size: u32,
+        /// The XDP headroom plus the user headroom requested.
+        total_headroom: u32,
+    },
 }
 
 impl fmt::Display for ConfigBuildError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "total headroom {} cannot be greater than frame size {}",
-            self.total_headroom, self.frame_size
-        )
+        match self {
+            Self::FrameSize { frame_size, .. } => write!(
+                f,
+                "frame size {} must be a power of two unless unaligned chunks are enabled",
+                frame_size
+            ),
+            Self::Headroom {
+                frame_size,
+                total_headroom,
+            } => write!(
+                f,
+                "total headroom {} cannot be greater than frame size {}",
+                total_headroom, frame_size
+            ),
+        }
     }
 }
 
@@ -208,4 +354,39 @@ mod tests {
             XDP_UMEM_MIN_CHUNK_SIZE - (frame_headroom + XDP_PACKET_HEADROOM)
         );
     }
+
+    #[test]
+    fn unaligned_chunks_sets_the_umem_flag() {
+        let config = ConfigBuilder::new().build().unwrap();
+        assert_eq!(xsk_umem_config::from(config).flags, 0);
+
+        let config = ConfigBuilder::new().unaligned_chunks(true).build().unwrap();
+
+        assert!(config.unaligned_chunks());
+        assert_eq!(
+            xsk_umem_config::from(config).flags,
+            XDP_UMEM_UNALIGNED_CHUNK_FLAG
+        );
+    }
+
+    #[test]
+    fn aligned_chunks_must_be_a_power_of_two() {
+        let frame_size = (XDP_UMEM_MIN_CHUNK_SIZE + 64).try_into().unwrap();
+
+        assert!(matches!(
+            ConfigBuilder::new().frame_size(frame_size).build(),
+            Err(ConfigBuildError::FrameSize {
+                unaligned_chunks: false,
+                ..
+            })
+        ));
+
+        let config = ConfigBuilder::new()
+            .frame_size(frame_size)
+            .unaligned_chunks(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(config.frame_size().get(), XDP_UMEM_MIN_CHUNK_SIZE + 64);
+    }
 }
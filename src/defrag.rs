@@ -0,0 +1,608 @@
+//! IPv4 fragment reassembly for received frames.
+//!
+//! AF_XDP hands frames to user space before the kernel's IP layer sees
+//! them, so fragmented datagrams, e.g. large DNS responses or IKE
+//! messages, arrive as separate frames and nothing puts them back
+//! together. A [`Reassembler`] does: each frame is
+//! [pushed](Reassembler::push) to it as it's received, and once every
+//! fragment of a datagram has arrived it returns the whole datagram as
+//! a single frame, with the ethernet and IP headers of its first
+//! fragment and the IP header's length, fragment and checksum fields
+//! fixed up. Frames which aren't fragments are passed straight
+//! through.
+//!
+//! Memory is bounded both in the number of datagrams being reassembled
+//! at once and in the bytes buffered for them, with the oldest
+//! datagram evicted to make room. Datagrams not completed within a
+//! timeout are dropped, either while pushing or by calling
+//! [`expire`](Reassembler::expire). As with the
+//! [`FlowTable`](crate::flow::FlowTable), time is measured in caller
+//! defined ticks:
+//!
+//! ```
+//! use xsk_rs::defrag::{Defrag, Reassembler};
+//! # let frames: Vec<Vec<u8>> = Vec::new();
+//! # let now = 0;
+//! # fn handle(_: &[u8]) {}
+//!
+//! // Up to 64 datagrams or 256KiB at once, each given 2s to complete.
+//! let mut defrag = Reassembler::new(64, 256 * 1024, 2_000);
+//!
+//! for frame in &frames {
+//!     match defrag.push(frame, now) {
+//!         Defrag::NotFragment => handle(frame),
+//!         Defrag::Complete(datagram) => handle(datagram),
+//!         Defrag::Pending | Defrag::Dropped => (),
+//!     }
+//! }
+//! ```
+//!
+//! Fragments are copied out of their frames, which can be handed back
+//! to the fill queue straight away. Overlapping fragments, which have
+//! no legitimate use and are a known evasion technique, cause the
+//! whole datagram to be dropped. IPv6 fragments aren't reassembled and
+//! are reported as [`Defrag::NotFragment`].
+
+use std::{convert::TryFrom, net::Ipv4Addr};
+
+use crate::{
+    addr, checksum,
+    packet::{self, ETH_P_IPV4, IPV4_MIN_HLEN},
+};
+
+/// The More Fragments flag of the IPv4 fragment field.
+const MF: u16 = 0x2000;
+
+/// The Don't Fragment flag of the IPv4 fragment field.
+const DF: u16 = 0x4000;
+
+/// The fragment offset bits of the IPv4 fragment field, in units of 8
+/// bytes.
+const OFFSET_MASK: u16 = 0x1fff;
+
+/// The largest an IPv4 datagram can be.
+const MAX_DATAGRAM: usize = u16::MAX as usize;
+
+/// Identifies the fragments of a datagram, as per RFC 791.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Key {
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    proto: u8,
+    id: u16,
+}
+
+#[derive(Debug)]
+struct Datagram {
+    key: Key,
+    first_seen: u64,
+    /// The headers of the first fragment, up to the end of its IP
+    /// header, once it's arrived.
+    header: Option<(Vec<u8>, usize)>,
+    payload: Vec<u8>,
+    /// Byte ranges of the payload received so far.
+    ranges: Vec<(usize, usize)>,
+    received: usize,
+    /// The payload length, once the last fragment has arrived.
+    total: Option<usize>,
+}
+
+impl Datagram {
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.ranges.iter().any(|&(s, e)| start < e && s < end)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.header.is_some() && self.total == Some(self.received)
+    }
+}
+
+/// What became of a frame [pushed](Reassembler::push) to a
+/// [`Reassembler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Defrag<'a> {
+    /// The frame isn't an IPv4 fragment, so should be handled as is.
+    NotFragment,
+    /// The frame was a fragment and has been buffered, but its
+    /// datagram isn't complete yet.
+    Pending,
+    /// The frame was the last missing fragment of a datagram, which
+    /// is returned as a single frame. Only valid until the next push.
+    Complete(&'a [u8]),
+    /// The frame was a fragment, but malformed, overlapping another or
+    /// too large to buffer, so it and its datagram were dropped.
+    Dropped,
+}
+
+/// Counters kept by a [`Reassembler`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DefragStats {
+    completed: u64,
+    timed_out: u64,
+    evicted: u64,
+    dropped: u64,
+}
+
+impl DefragStats {
+    /// Datagrams fully reassembled.
+    pub fn completed(&self) -> u64 {
+        self.completed
+    }
+
+    /// Datagrams dropped for not completing within the timeout.
+    pub fn timed_out(&self) -> u64 {
+        self.timed_out
+    }
+
+    /// Datagrams dropped to make room for newer ones.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// Datagrams dropped for a malformed or overlapping fragment, or
+    /// for being too large to buffer.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// Reassembles IPv4 datagrams from their fragments, see the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct Reassembler {
+    capacity: usize,
+    max_bytes: usize,
+    timeout: u64,
+    pending: Vec<Datagram>,
+    bytes: usize,
+    out: Vec<u8>,
+    stats: DefragStats,
+}
+
+impl Reassembler {
+    /// Create a reassembler holding up to `capacity` incomplete
+    /// datagrams, with at most `max_bytes` of their payloads buffered
+    /// between them, each dropped if not completed within `timeout`
+    /// ticks of its first fragment arriving.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn new(capacity: usize, max_bytes: usize, timeout: u64) -> Self {
+        assert!(capacity > 0, "reassembler capacity must be non-zero");
+
+        Self {
+            capacity,
+            max_bytes,
+            timeout,
+            pending: Vec::with_capacity(capacity),
+            bytes: 0,
+            out: Vec::new(),
+            stats: DefragStats::default(),
+        }
+    }
+
+    /// Handle a received `frame` at time `now`, see [`Defrag`].
+    pub fn push(&mut self, frame: &[u8], now: u64) -> Defrag<'_> {
+        let frag = match Fragment::parse(frame) {
+            Some(Ok(frag)) => frag,
+            Some(Err(())) => {
+                self.stats.dropped += 1;
+                return Defrag::Dropped;
+            }
+            None => return Defrag::NotFragment,
+        };
+
+        self.expire(now);
+
+        let idx = match self.pending.iter().position(|d| d.key == frag.key) {
+            Some(idx) => idx,
+            None => {
+                if self.pending.len() == self.capacity {
+                    self.evict_oldest();
+                }
+
+                self.pending.push(Datagram {
+                    key: frag.key,
+                    first_seen: now,
+                    header: None,
+                    payload: Vec::new(),
+                    ranges: Vec::new(),
+                    received: 0,
+                    total: None,
+                });
+
+                self.pending.len() - 1
+            }
+        };
+
+        let (start, end) = (frag.offset, frag.offset + frag.payload.len());
+
+        let datagram = &self.pending[idx];
+
+        let invalid = datagram.overlaps(start, end)
+            || (!frag.more && datagram.total.is_some())
+            || datagram.total.is_some_and(|total| end > total)
+            || (!frag.more && datagram.ranges.iter().any(|&(_, e)| e > end));
+
+        if invalid {
+            self.remove(idx);
+            self.stats.dropped += 1;
+            return Defrag::Dropped;
+        }
+
+        let grow = end.saturating_sub(datagram.payload.len());
+
+        if end > self.max_bytes {
+            self.remove(idx);
+            self.stats.dropped += 1;
+            return Defrag::Dropped;
+        }
+
+        // Make room by evicting the oldest datagrams, but not this
+        // one.
+        let mut idx = idx;
+
+        while self.bytes + grow > self.max_bytes {
+            let oldest = self.oldest_except(idx);
+            self.remove(oldest);
+            self.stats.evicted += 1;
+
+            if oldest < idx {
+                idx -= 1;
+            }
+        }
+
+        let datagram = &mut self.pending[idx];
+
+        if datagram.payload.len() < end {
+            datagram.payload.resize(end, 0);
+        }
+
+        datagram.payload[start..end].copy_from_slice(frag.payload);
+        datagram.ranges.push((start, end));
+        datagram.received += end - start;
+        self.bytes += grow;
+
+        if start == 0 {
+            datagram.header = Some((frame[..frag.l4_offset].to_vec(), frag.l3_offset));
+        }
+
+        if !frag.more {
+            datagram.total = Some(end);
+        }
+
+        if !datagram.is_complete() {
+            return Defrag::Pending;
+        }
+
+        let datagram = self.remove(idx);
+
+        let (header, l3_offset) = datagram.header.unwrap();
+        let total = datagram.total.unwrap();
+        let ihl = header.len() - l3_offset;
+
+        // Fragments are only checked against their own header's
+        // length, but the first's may be longer.
+        let total_len = match u16::try_from(ihl + total) {
+            Ok(len) => len,
+            Err(_) => {
+                self.stats.dropped += 1;
+                return Defrag::Dropped;
+            }
+        };
+
+        self.stats.completed += 1;
+
+        self.out.clear();
+        self.out.extend_from_slice(&header);
+        self.out.extend_from_slice(&datagram.payload[..total]);
+
+        let ip = &mut self.out[l3_offset..];
+        let frag_field = u16::from_be_bytes([ip[6], ip[7]]) & DF;
+
+        ip[2..4].copy_from_slice(&total_len.to_be_bytes());
+        ip[6..8].copy_from_slice(&frag_field.to_be_bytes());
+        let check = checksum::ipv4_header(&ip[..ihl]);
+        ip[10..12].copy_from_slice(&check.to_be_bytes());
+
+        Defrag::Complete(&self.out)
+    }
+
+    /// Drop every datagram not completed within the timeout as of
+    /// `now`, returning how many were.
+    pub fn expire(&mut self, now: u64) -> usize {
+        let before = self.pending.len();
+        let timeout = self.timeout;
+        let mut freed = 0;
+
+        self.pending.retain(|d| {
+            let keep = now.saturating_sub(d.first_seen) < timeout;
+
+            if !keep {
+                freed += d.payload.len();
+            }
+
+            keep
+        });
+
+        self.bytes -= freed;
+
+        let expired = before - self.pending.len();
+        self.stats.timed_out += expired as u64;
+
+        expired
+    }
+
+    /// The number of datagrams being reassembled.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The bytes of payload buffered for them.
+    pub fn buffered_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Counts of what became of datagrams so far.
+    pub fn stats(&self) -> DefragStats {
+        self.stats
+    }
+
+    fn remove(&mut self, idx: usize) -> Datagram {
+        let datagram = self.pending.swap_remove(idx);
+        self.bytes -= datagram.payload.len();
+        datagram
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self.oldest_except(usize::MAX);
+        self.remove(oldest);
+        self.stats.evicted += 1;
+    }
+
+    fn oldest_except(&self, except: usize) -> usize {
+        self.pending
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != except)
+            .min_by_key(|(_, d)| d.first_seen)
+            .map(|(i, _)| i)
+            .expect("no other datagram to evict")
+    }
+}
+
+/// An IPv4 fragment within a frame.
+#[derive(Debug)]
+struct Fragment<'a> {
+    key: Key,
+    l3_offset: usize,
+    l4_offset: usize,
+    offset: usize,
+    more: bool,
+    payload: &'a [u8],
+}
+
+impl<'a> Fragment<'a> {
+    /// [`None`] if `frame` isn't an IPv4 fragment, an error if it is
+    /// but is malformed.
+    fn parse(frame: &'a [u8]) -> Option<Result<Self, ()>> {
+        let (ethertype, l3_offset) = packet::l3(frame)?;
+
+        if ethertype != ETH_P_IPV4 {
+            return None;
+        }
+
+        let frag_field = packet::read_u16(frame, l3_offset + 6)?;
+        let offset = (frag_field & OFFSET_MASK) as usize * 8;
+        let more = frag_field & MF != 0;
+
+        if offset == 0 && !more {
+            return None;
+        }
+
+        Some(Self::parse_fragment(frame, l3_offset, offset, more).ok_or(()))
+    }
+
+    fn parse_fragment(
+        frame: &'a [u8],
+        l3_offset: usize,
+        offset: usize,
+        more: bool,
+    ) -> Option<Self> {
+        let ihl = (*frame.get(l3_offset)? & 0x0f) as usize * 4;
+        let total_len = packet::read_u16(frame, l3_offset + 2)? as usize;
+
+        if ihl < IPV4_MIN_HLEN || total_len < ihl {
+            return None;
+        }
+
+        let l4_offset = l3_offset + ihl;
+        let payload = frame.get(l4_offset..l3_offset + total_len)?;
+
+        // Every fragment bar the last must carry a multiple of 8
+        // bytes, and none may reach past the largest datagram.
+        if payload.is_empty()
+            || (more && !payload.len().is_multiple_of(8))
+            || ihl + offset + payload.len() > MAX_DATAGRAM
+        {
+            return None;
+        }
+
+        let ip = &frame[l3_offset..];
+
+        Some(Self {
+            key: Key {
+                src: addr::read_ipv4(ip, 12)?,
+                dst: addr::read_ipv4(ip, 16)?,
+                proto: ip[9],
+                id: u16::from_be_bytes([ip[4], ip[5]]),
+            },
+            l3_offset,
+            l4_offset,
+            offset,
+            more,
+            payload,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::packet::{ETH_HLEN, IPPROTO_UDP};
+
+    /// An ethernet + IPv4 fragment of datagram `id` carrying
+    /// `payload` at byte `offset`.
+    fn fragment(id: u16, offset: usize, more: bool, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0xaa; 12];
+        frame.extend_from_slice(&ETH_P_IPV4.to_be_bytes());
+
+        let frag_field = (offset / 8) as u16 | if more { MF } else { 0 };
+
+        frame.extend_from_slice(&[0x45, 0x00]);
+        frame.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&id.to_be_bytes());
+        frame.extend_from_slice(&frag_field.to_be_bytes());
+        frame.extend_from_slice(&[0x40, IPPROTO_UDP, 0x00, 0x00]);
+        frame.extend_from_slice(&[192, 168, 69, 1]);
+        frame.extend_from_slice(&[192, 168, 69, 2]);
+        frame.extend_from_slice(payload);
+
+        frame
+    }
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn fragments_in_any_order_are_reassembled() {
+        let data = payload(40);
+        let mut defrag = Reassembler::new(4, 1024, 100);
+
+        let frames = [
+            fragment(7, 16, true, &data[16..32]),
+            fragment(7, 32, false, &data[32..]),
+            fragment(7, 0, true, &data[..16]),
+        ];
+
+        assert_eq!(defrag.push(&frames[0], 0), Defrag::Pending);
+        assert_eq!(defrag.push(&frames[1], 1), Defrag::Pending);
+        assert_eq!(defrag.buffered_bytes(), 40);
+
+        let datagram = match defrag.push(&frames[2], 2) {
+            Defrag::Complete(datagram) => datagram.to_vec(),
+            other => panic!("{:?}", other),
+        };
+
+        let ip = &datagram[ETH_HLEN..];
+
+        assert_eq!(&ip[20..], &data[..]);
+        assert_eq!(u16::from_be_bytes([ip[2], ip[3]]), 60);
+        assert_eq!(u16::from_be_bytes([ip[6], ip[7]]), 0);
+        assert_eq!(checksum::checksum(&ip[..20]), 0);
+
+        assert_eq!(defrag.pending(), 0);
+        assert_eq!(defrag.buffered_bytes(), 0);
+        assert_eq!(defrag.stats().completed(), 1);
+    }
+
+    #[test]
+    fn unfragmented_frames_pass_through() {
+        let mut defrag = Reassembler::new(4, 1024, 100);
+
+        assert_eq!(
+            defrag.push(&fragment(1, 0, false, b"whole"), 0),
+            Defrag::NotFragment
+        );
+        assert_eq!(
+            defrag.push(&packet::tests::udp4_frame(1, 1, 2), 0),
+            Defrag::NotFragment
+        );
+    }
+
+    #[test]
+    fn overlapping_and_malformed_fragments_drop_the_datagram() {
+        let data = payload(32);
+        let mut defrag = Reassembler::new(4, 1024, 100);
+
+        assert_eq!(
+            defrag.push(&fragment(1, 0, true, &data[..16]), 0),
+            Defrag::Pending
+        );
+        assert_eq!(
+            defrag.push(&fragment(1, 8, false, &data[8..]), 0),
+            Defrag::Dropped
+        );
+        assert_eq!(defrag.pending(), 0);
+
+        // Not a multiple of 8 but more to come.
+        assert_eq!(
+            defrag.push(&fragment(2, 0, true, &data[..12]), 0),
+            Defrag::Dropped
+        );
+
+        assert_eq!(defrag.stats().dropped(), 2);
+        assert_eq!(defrag.buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn datagrams_too_long_for_ipv4_are_dropped() {
+        let data = payload(65_515);
+        let mut defrag = Reassembler::new(4, 1 << 17, 100);
+
+        // Each fragment fits, but the first's header options push the
+        // reassembled datagram past the IPv4 limit.
+        let mut first = fragment(1, 0, true, &data[..32_000]);
+        first[ETH_HLEN] = 0x46;
+        first[ETH_HLEN + 2..ETH_HLEN + 4].copy_from_slice(&(24u16 + 32_000).to_be_bytes());
+        first.splice(ETH_HLEN + 20..ETH_HLEN + 20, [0; 4]);
+
+        assert_eq!(defrag.push(&first, 0), Defrag::Pending);
+        assert_eq!(
+            defrag.push(&fragment(1, 32_000, true, &data[32_000..65_512]), 0),
+            Defrag::Pending
+        );
+        assert_eq!(
+            defrag.push(&fragment(1, 65_512, false, &data[65_512..]), 0),
+            Defrag::Dropped
+        );
+
+        assert_eq!(defrag.stats().dropped(), 1);
+        assert_eq!(defrag.stats().completed(), 0);
+        assert_eq!(defrag.buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn memory_is_bounded_by_eviction_and_timeout() {
+        let data = payload(64);
+        let mut defrag = Reassembler::new(2, 48, 10);
+
+        defrag.push(&fragment(1, 0, true, &data[..16]), 0);
+        defrag.push(&fragment(2, 0, true, &data[..16]), 1);
+
+        // Over the datagram limit, so the oldest goes.
+        defrag.push(&fragment(3, 0, true, &data[..16]), 2);
+        assert_eq!(defrag.pending(), 2);
+        assert_eq!(defrag.stats().evicted(), 1);
+
+        // Over the byte limit, so the oldest other than 3 goes.
+        defrag.push(&fragment(3, 16, true, &data[16..48]), 3);
+        assert_eq!(defrag.pending(), 1);
+        assert_eq!(defrag.buffered_bytes(), 48);
+        assert_eq!(defrag.stats().evicted(), 2);
+
+        // Larger than the whole budget.
+        assert_eq!(
+            defrag.push(&fragment(4, 0, true, &data), 4),
+            Defrag::Dropped
+        );
+
+        assert_eq!(defrag.expire(12), 1);
+        assert_eq!(defrag.pending(), 0);
+        assert_eq!(defrag.buffered_bytes(), 0);
+        assert_eq!(defrag.stats().timed_out(), 1);
+    }
+}
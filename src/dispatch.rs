@@ -0,0 +1,819 @@
+//! Software flow dispatch, for spreading received packets across
+//! worker threads such that all packets of a flow land on the same
+//! one.
+//!
+//! The default hasher is [`Toeplitz`], which is what most NICs use
+//! for RSS. Configured with the same key and indirection table as the
+//! device (see `ethtool -x <if>`), a [`FlowDispatcher`] picks the same
+//! queue index the NIC would have, so a flow handled in the kernel on
+//! one core and redirected to userspace keeps to that core.
+//!
+//! Where both directions of a flow must meet on one worker, for
+//! example in a gateway dispatching inner flows after tunnel decap
+//! with [`FlowDispatcher::dispatch_ip`], use [`SYMMETRIC_TOEPLITZ_KEY`]
+//! or wrap the hasher in [`Symmetric`].
+//!
+//! Traffic can also be steered between several named consumer
+//! pipelines, e.g. a fast path and a slow-path analyzer, with a
+//! [`Steering`] sorting flows into traffic classes and a shared
+//! [`RouteTable`] saying which pipeline handles each class. Routes
+//! can be changed at any time from any thread, without locking out
+//! the workers looking them up:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use xsk_rs::dispatch::{FlowKey, RouteTable, Steering};
+//!
+//! const NORMAL: u32 = 0;
+//! const SUSPECT: u32 = 1;
+//!
+//! let routes = Arc::new(RouteTable::new(["fast", "analyzer"], 2));
+//!
+//! // Each worker of the socket group gets its own `Steering`.
+//! let steering = Steering::new(
+//!     |key: &FlowKey| if key.protocol() == 1 { SUSPECT } else { NORMAL },
+//!     Arc::clone(&routes),
+//! );
+//!
+//! // Later, from a control thread.
+//! routes.set_route(SUSPECT, "analyzer")?;
+//! # Ok::<(), xsk_rs::dispatch::RouteError>(())
+//! ```
+
+use std::{
+    error, fmt,
+    net::{IpAddr, Ipv6Addr},
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crate::{addr, packet};
+
+/// The addresses, and ports where applicable, identifying a flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: u8,
+    ports: Option<(u16, u16)>,
+}
+
+impl FlowKey {
+    /// Create a key for a flow between `src` and `dst`.
+    ///
+    /// `ports` should be the (source, destination) transport ports,
+    /// if the protocol has any.
+    pub fn new(src: IpAddr, dst: IpAddr, protocol: u8, ports: Option<(u16, u16)>) -> Self {
+        Self {
+            src,
+            dst,
+            protocol,
+            ports,
+        }
+    }
+
+    /// Parse a key out of an ethernet frame, e.g. the contents of a
+    /// received frame's data segment.
+    ///
+    /// Up to two VLAN tags are skipped. Ports are only read for TCP
+    /// and UDP, and never for IPv4 fragments, not even the first, so
+    /// every fragment of a datagram gets the same key. Returns
+    /// [`None`] if the frame isn't IPv4 or IPv6, or is truncated.
+    pub fn from_frame(frame: &[u8]) -> Option<Self> {
+        let (ethertype, l3_offset) = packet::l3(frame)?;
+        Self::parse(frame, ethertype, l3_offset)
+    }
+
+    /// Parse a key out of a bare IPv4 or IPv6 packet, with no
+    /// ethernet header in front. For example the inner packet of an
+    /// IP-in-IP or GRE tunnel once the outer headers are stripped.
+    ///
+    /// The IP version is taken from the first nibble, otherwise the
+    /// same rules as [`from_frame`](Self::from_frame) apply.
+    pub fn from_ip_packet(packet: &[u8]) -> Option<Self> {
+        let ethertype = match packet.first()? >> 4 {
+            4 => packet::ETH_P_IPV4,
+            6 => packet::ETH_P_IPV6,
+            _ => return None,
+        };
+
+        Self::parse(packet, ethertype, 0)
+    }
+
+    fn parse(frame: &[u8], ethertype: u16, l3_offset: usize) -> Option<Self> {
+        let (protocol, l4_offset) = packet::l4(frame, ethertype, l3_offset)?;
+
+        let (src, dst) = match ethertype {
+            packet::ETH_P_IPV4 => (
+                IpAddr::V4(addr::read_ipv4(frame, l3_offset + 12)?),
+                IpAddr::V4(addr::read_ipv4(frame, l3_offset + 16)?),
+            ),
+            _ => (
+                IpAddr::V6(addr::read_ipv6(frame, l3_offset + 8)?),
+                IpAddr::V6(addr::read_ipv6(frame, l3_offset + 24)?),
+            ),
+        };
+
+        let is_fragment =
+            ethertype == packet::ETH_P_IPV4 && packet::ipv4_is_fragmented(frame, l3_offset)?;
+
+        let ports = if packet::has_ports(protocol) && !is_fragment {
+            Some((
+                packet::read_u16(frame, l4_offset)?,
+                packet::read_u16(frame, l4_offset + 2)?,
+            ))
+        } else {
+            None
+        };
+
+        Some(Self {
+            src,
+            dst,
+            protocol,
+            ports,
+        })
+    }
+
+    /// The source address.
+    #[inline]
+    pub fn src(&self) -> IpAddr {
+        self.src
+    }
+
+    /// The destination address.
+    #[inline]
+    pub fn dst(&self) -> IpAddr {
+        self.dst
+    }
+
+    /// The transport protocol number.
+    #[inline]
+    pub fn protocol(&self) -> u8 {
+        self.protocol
+    }
+
+    /// The (source, destination) ports, if the protocol has any.
+    #[inline]
+    pub fn ports(&self) -> Option<(u16, u16)> {
+        self.ports
+    }
+
+    /// This key with its endpoints ordered, so that both directions
+    /// of a flow give the same key.
+    pub fn canonical(&self) -> Self {
+        let (src_port, dst_port) = self.ports.unwrap_or_default();
+
+        if (self.src, src_port) <= (self.dst, dst_port) {
+            *self
+        } else {
+            Self {
+                src: self.dst,
+                dst: self.src,
+                protocol: self.protocol,
+                ports: self.ports.map(|(src, dst)| (dst, src)),
+            }
+        }
+    }
+}
+
+/// A hash function over [`FlowKey`]s.
+///
+/// Implemented for any `Fn(&FlowKey) -> u32`, so a closure may be
+/// passed wherever a hasher is expected.
+pub trait FlowHasher {
+    /// Hash `key`.
+    fn hash(&self, key: &FlowKey) -> u32;
+}
+
+impl<F> FlowHasher for F
+where
+    F: Fn(&FlowKey) -> u32,
+{
+    #[inline]
+    fn hash(&self, key: &FlowKey) -> u32 {
+        self(key)
+    }
+}
+
+/// The number of key bytes needed to hash an IPv6 address pair plus
+/// ports, the longest input a [`Toeplitz`] hasher will see.
+pub const TOEPLITZ_KEY_LEN: usize = 40;
+
+/// The key Microsoft publishes in the RSS specification, used as the
+/// default by many NIC drivers.
+pub const DEFAULT_TOEPLITZ_KEY: [u8; TOEPLITZ_KEY_LEN] = [
+    0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f, 0xb0,
+    0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30, 0xf2, 0x0c,
+    0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+];
+
+/// A key which makes [`Toeplitz`] symmetric: swapping source and
+/// destination, both addresses and ports, gives the same hash.
+///
+/// A 16 bit pattern repeated, so every field is hashed against the
+/// same key bits wherever it sits in the input. Configuring the NIC
+/// with it too (`ethtool -X <if> hkey ...`) keeps both directions of a
+/// flow on the same queue in hardware as well as in software.
+pub const SYMMETRIC_TOEPLITZ_KEY: [u8; TOEPLITZ_KEY_LEN] = [
+    0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a,
+    0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a,
+    0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a, 0x6d, 0x5a,
+];
+
+/// The Toeplitz hash as used by RSS.
+///
+/// Input is laid out as per the RSS specification: source address,
+/// destination address then, if enabled and present, source port and
+/// destination port, all in network byte order.
+#[derive(Debug, Clone)]
+pub struct Toeplitz {
+    key: [u8; TOEPLITZ_KEY_LEN],
+    hash_ports: bool,
+}
+
+impl Toeplitz {
+    /// Create a hasher using `key`, for example as reported by
+    /// `ethtool -x <if>`. Ports are included in the hash.
+    ///
+    /// Keys longer than [`TOEPLITZ_KEY_LEN`] are accepted but only
+    /// the leading bytes are used, since no input is long enough to
+    /// reach the rest.
+    pub fn new(key: &[u8]) -> Result<Self, ToeplitzKeyError> {
+        if key.len() < TOEPLITZ_KEY_LEN {
+            return Err(ToeplitzKeyError { len: key.len() });
+        }
+
+        let mut buf = [0; TOEPLITZ_KEY_LEN];
+        buf.copy_from_slice(&key[..TOEPLITZ_KEY_LEN]);
+
+        Ok(Self {
+            key: buf,
+            hash_ports: true,
+        })
+    }
+
+    /// Set whether ports are included in the hash.
+    ///
+    /// Should mirror the device's flow hash fields for the traffic in
+    /// question, e.g. many NICs only hash addresses for UDP unless
+    /// configured otherwise with `ethtool -N <if> rx-flow-hash udp4
+    /// sdfn`.
+    pub fn hash_ports(mut self, hash_ports: bool) -> Self {
+        self.hash_ports = hash_ports;
+        self
+    }
+
+    /// The key in use.
+    pub fn key(&self) -> &[u8; TOEPLITZ_KEY_LEN] {
+        &self.key
+    }
+
+    fn hash_bytes(&self, input: &[u8]) -> u32 {
+        debug_assert!(input.len() + 4 <= TOEPLITZ_KEY_LEN);
+
+        let key = &self.key;
+        let mut result = 0;
+        let mut window = u32::from_be_bytes([key[0], key[1], key[2], key[3]]);
+
+        for (i, byte) in input.iter().enumerate() {
+            let next = key[i + 4];
+
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    result ^= window;
+                }
+                window = (window << 1) | ((next >> (7 - bit)) & 1) as u32;
+            }
+        }
+
+        result
+    }
+}
+
+impl Default for Toeplitz {
+    fn default() -> Self {
+        Self {
+            key: DEFAULT_TOEPLITZ_KEY,
+            hash_ports: true,
+        }
+    }
+}
+
+impl FlowHasher for Toeplitz {
+    fn hash(&self, key: &FlowKey) -> u32 {
+        let mut input = [0; TOEPLITZ_KEY_LEN - 4];
+        let mut len = 0;
+
+        let mut push = |bytes: &[u8]| {
+            input[len..len + bytes.len()].copy_from_slice(bytes);
+            len += bytes.len();
+        };
+
+        match (key.src, key.dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                push(&src.octets());
+                push(&dst.octets());
+            }
+            (src, dst) => {
+                push(&to_ipv6(src).octets());
+                push(&to_ipv6(dst).octets());
+            }
+        }
+
+        if let (true, Some((src_port, dst_port))) = (self.hash_ports, key.ports) {
+            push(&src_port.to_be_bytes());
+            push(&dst_port.to_be_bytes());
+        }
+
+        self.hash_bytes(&input[..len])
+    }
+}
+
+fn to_ipv6(addr: IpAddr) -> Ipv6Addr {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+        IpAddr::V6(addr) => addr,
+    }
+}
+
+/// Makes any [`FlowHasher`] symmetric, by hashing each key's
+/// [`canonical`](FlowKey::canonical) form.
+///
+/// Useful for gateways that see both directions of a flow, possibly
+/// on different interfaces or inside different tunnels, and need them
+/// on the same worker. Unlike [`SYMMETRIC_TOEPLITZ_KEY`] this works
+/// with any key or hasher, but won't match what the NIC computes.
+#[derive(Debug, Clone, Default)]
+pub struct Symmetric<H>(H);
+
+impl<H: FlowHasher> Symmetric<H> {
+    /// Wrap `hasher`.
+    pub fn new(hasher: H) -> Self {
+        Self(hasher)
+    }
+
+    /// The wrapped hasher.
+    pub fn inner(&self) -> &H {
+        &self.0
+    }
+}
+
+impl<H: FlowHasher> FlowHasher for Symmetric<H> {
+    #[inline]
+    fn hash(&self, key: &FlowKey) -> u32 {
+        self.0.hash(&key.canonical())
+    }
+}
+
+/// Error signifying that a [`Toeplitz`] key was too short.
+#[derive(Debug)]
+pub struct ToeplitzKeyError {
+    len: usize,
+}
+
+impl fmt::Display for ToeplitzKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "toeplitz key must be at least {} bytes, got {}",
+            TOEPLITZ_KEY_LEN, self.len
+        )
+    }
+}
+
+impl error::Error for ToeplitzKeyError {}
+
+/// The indirection table size used by most NIC drivers.
+pub const DEFAULT_INDIRECTION_TABLE_LEN: usize = 128;
+
+/// Maps packets to worker indices by hashing their [`FlowKey`] and
+/// looking the hash up in an indirection table, just as a NIC maps
+/// packets to RX queues.
+#[derive(Debug, Clone)]
+pub struct FlowDispatcher<H = Toeplitz> {
+    hasher: H,
+    table: Vec<u32>,
+}
+
+impl<H: FlowHasher> FlowDispatcher<H> {
+    /// Create a dispatcher spreading flows over `workers` workers,
+    /// using an indirection table of [`DEFAULT_INDIRECTION_TABLE_LEN`]
+    /// entries filled round-robin, matching a driver's default.
+    pub fn new(hasher: H, workers: NonZeroU32) -> Self {
+        let table = (0..DEFAULT_INDIRECTION_TABLE_LEN as u32)
+            .map(|i| i % workers.get())
+            .collect();
+
+        Self { hasher, table }
+    }
+
+    /// Create a dispatcher with an explicit indirection table, for
+    /// example one copied from `ethtool -x <if>`.
+    ///
+    /// # Panics
+    ///
+    /// If `table` is empty.
+    pub fn with_indirection_table(hasher: H, table: Vec<u32>) -> Self {
+        assert!(!table.is_empty(), "indirection table must not be empty");
+
+        Self { hasher, table }
+    }
+
+    /// The hasher in use.
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// The indirection table in use.
+    pub fn indirection_table(&self) -> &[u32] {
+        &self.table
+    }
+
+    /// The worker index for `key`.
+    #[inline]
+    pub fn dispatch_key(&self, key: &FlowKey) -> u32 {
+        let hash = self.hasher.hash(key) as usize;
+        self.table[hash % self.table.len()]
+    }
+
+    /// The worker index for an ethernet frame, or [`None`] if no
+    /// [`FlowKey`] could be parsed from it.
+    #[inline]
+    pub fn dispatch(&self, frame: &[u8]) -> Option<u32> {
+        FlowKey::from_frame(frame).map(|key| self.dispatch_key(&key))
+    }
+
+    /// The worker index for a bare IP packet, e.g. the inner packet
+    /// of a tunnel after decapsulation, or [`None`] if no [`FlowKey`]
+    /// could be parsed from it.
+    #[inline]
+    pub fn dispatch_ip(&self, packet: &[u8]) -> Option<u32> {
+        FlowKey::from_ip_packet(packet).map(|key| self.dispatch_key(&key))
+    }
+}
+
+/// Which of a set of named pipelines handles each traffic class.
+///
+/// Classes are numbered densely from zero and all start out routed to
+/// the first pipeline. The table is meant to be shared in an [`Arc`]
+/// between the [`Steering`]s of every worker and whatever changes the
+/// routes, since each route is a single atomic, so lookups and
+/// updates never block one another.
+#[derive(Debug)]
+pub struct RouteTable {
+    pipelines: Vec<String>,
+    routes: Vec<AtomicU32>,
+    updates: AtomicU64,
+}
+
+impl RouteTable {
+    /// Create a table of `classes` classes, routed between
+    /// `pipelines` in the order given, so the first pipeline is index
+    /// zero.
+    ///
+    /// # Panics
+    ///
+    /// If `pipelines` is empty or a name appears twice.
+    pub fn new<I, S>(pipelines: I, classes: usize) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let pipelines: Vec<String> = pipelines.into_iter().map(Into::into).collect();
+
+        assert!(!pipelines.is_empty(), "at least one pipeline is needed");
+
+        for (i, name) in pipelines.iter().enumerate() {
+            assert!(
+                !pipelines[..i].contains(name),
+                "pipeline {:?} registered twice",
+                name
+            );
+        }
+
+        Self {
+            pipelines,
+            routes: (0..classes).map(|_| AtomicU32::new(0)).collect(),
+            updates: AtomicU64::new(0),
+        }
+    }
+
+    /// The names of the pipelines, in index order.
+    pub fn pipelines(&self) -> &[String] {
+        &self.pipelines
+    }
+
+    /// The index of the pipeline called `name`, if there is one.
+    pub fn pipeline_index(&self, name: &str) -> Option<u32> {
+        self.pipelines
+            .iter()
+            .position(|p| p == name)
+            .map(|idx| idx as u32)
+    }
+
+    /// The number of traffic classes.
+    pub fn classes(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// The index of the pipeline `class` is currently routed to, or
+    /// [`None`] if there's no such class.
+    #[inline]
+    pub fn route(&self, class: u32) -> Option<u32> {
+        self.routes
+            .get(class as usize)
+            .map(|route| route.load(Ordering::Acquire))
+    }
+
+    /// Route `class` to the pipeline called `pipeline`. Frames already
+    /// looked up carry on to the pipeline they were given, new
+    /// lookups see the change straight away.
+    pub fn set_route(&self, class: u32, pipeline: &str) -> Result<(), RouteError> {
+        let idx = self
+            .pipeline_index(pipeline)
+            .ok_or_else(|| RouteError::UnknownPipeline(pipeline.to_owned()))?;
+
+        let route = self
+            .routes
+            .get(class as usize)
+            .ok_or(RouteError::UnknownClass(class))?;
+
+        route.store(idx, Ordering::Release);
+        self.updates.fetch_add(1, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// The number of times a route has been set, for noticing that
+    /// the table changed, e.g. to flush per-flow caches.
+    pub fn updates(&self) -> u64 {
+        self.updates.load(Ordering::Acquire)
+    }
+}
+
+/// Error signifying that a route couldn't be set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    /// There's no traffic class with this number.
+    UnknownClass(u32),
+    /// There's no pipeline with this name.
+    UnknownPipeline(String),
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownClass(class) => write!(f, "no traffic class {}", class),
+            Self::UnknownPipeline(name) => write!(f, "no pipeline named {:?}", name),
+        }
+    }
+}
+
+impl error::Error for RouteError {}
+
+/// Picks the pipeline for each packet, by sorting its [`FlowKey`]
+/// into a traffic class and looking up the class's current route in
+/// a shared [`RouteTable`].
+///
+/// The classifier is any `Fn(&FlowKey) -> u32` returning the class.
+/// Keys it puts in a class the table doesn't have aren't steered
+/// anywhere.
+#[derive(Debug, Clone)]
+pub struct Steering<C> {
+    classify: C,
+    table: Arc<RouteTable>,
+}
+
+impl<C> Steering<C>
+where
+    C: Fn(&FlowKey) -> u32,
+{
+    /// Steer by classes from `classify` and routes from `table`.
+    pub fn new(classify: C, table: Arc<RouteTable>) -> Self {
+        Self { classify, table }
+    }
+
+    /// The route table in use.
+    pub fn table(&self) -> &Arc<RouteTable> {
+        &self.table
+    }
+
+    /// The traffic class of `key`.
+    #[inline]
+    pub fn class(&self, key: &FlowKey) -> u32 {
+        (self.classify)(key)
+    }
+
+    /// The pipeline index for `key`, or [`None`] if its class isn't
+    /// in the table.
+    #[inline]
+    pub fn steer_key(&self, key: &FlowKey) -> Option<u32> {
+        self.table.route(self.class(key))
+    }
+
+    /// The pipeline index for an ethernet frame, or [`None`] if no
+    /// [`FlowKey`] could be parsed from it or its class isn't in the
+    /// table.
+    #[inline]
+    pub fn steer(&self, frame: &[u8]) -> Option<u32> {
+        FlowKey::from_frame(frame).and_then(|key| self.steer_key(&key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::{tests::udp4_frame, ETH_HLEN};
+
+    fn v4(src: [u8; 4], src_port: u16, dst: [u8; 4], dst_port: u16) -> FlowKey {
+        FlowKey::new(
+            IpAddr::V4(src.into()),
+            IpAddr::V4(dst.into()),
+            packet::IPPROTO_TCP,
+            Some((src_port, dst_port)),
+        )
+    }
+
+    #[test]
+    fn toeplitz_matches_rss_spec_verification_suite() {
+        let toeplitz = Toeplitz::default();
+
+        let cases = [
+            (
+                v4([66, 9, 149, 187], 2794, [161, 142, 100, 80], 1766),
+                0x51ccc178,
+                0x323e8fc2,
+            ),
+            (
+                v4([199, 92, 111, 2], 14230, [65, 69, 140, 83], 4739),
+                0xc626b0ea,
+                0xd718262a,
+            ),
+            (
+                v4([24, 19, 198, 95], 12898, [12, 22, 207, 184], 38024),
+                0x5c2b394a,
+                0xd2d0a5de,
+            ),
+            (
+                v4([38, 27, 205, 30], 48228, [209, 142, 163, 6], 2217),
+                0xafc7327f,
+                0x82989176,
+            ),
+            (
+                v4([153, 39, 163, 191], 44251, [202, 188, 127, 2], 1303),
+                0x10e828a2,
+                0x5d1809c5,
+            ),
+        ];
+
+        for (key, with_ports, without_ports) in cases.iter() {
+            assert_eq!(toeplitz.hash(key), *with_ports);
+            assert_eq!(toeplitz.clone().hash_ports(false).hash(key), *without_ports);
+        }
+    }
+
+    #[test]
+    fn toeplitz_matches_rss_spec_for_ipv6() {
+        let key = FlowKey::new(
+            "3ffe:2501:200:1fff::7".parse().unwrap(),
+            "3ffe:2501:200:3::1".parse().unwrap(),
+            packet::IPPROTO_TCP,
+            Some((2794, 1766)),
+        );
+
+        let toeplitz = Toeplitz::default();
+
+        assert_eq!(toeplitz.hash(&key), 0x40207d3d);
+        assert_eq!(toeplitz.hash_ports(false).hash(&key), 0x2cc18cd5);
+    }
+
+    #[test]
+    fn symmetric_hashers_ignore_direction() {
+        let forward = v4([66, 9, 149, 187], 2794, [161, 142, 100, 80], 1766);
+        let reverse = v4([161, 142, 100, 80], 1766, [66, 9, 149, 187], 2794);
+
+        let toeplitz = Toeplitz::new(&SYMMETRIC_TOEPLITZ_KEY).unwrap();
+        assert_eq!(toeplitz.hash(&forward), toeplitz.hash(&reverse));
+
+        let symmetric = Symmetric::new(Toeplitz::default());
+        assert_eq!(symmetric.hash(&forward), symmetric.hash(&reverse));
+        assert_eq!(forward.canonical(), reverse.canonical());
+
+        // Plain Toeplitz with the default key isn't.
+        let toeplitz = Toeplitz::default();
+        assert_ne!(toeplitz.hash(&forward), toeplitz.hash(&reverse));
+    }
+
+    #[test]
+    fn flow_key_is_parsed_from_bare_ip_packet() {
+        let frame = udp4_frame(0, 1234, 53);
+
+        assert_eq!(
+            FlowKey::from_ip_packet(&frame[packet::ETH_HLEN..]),
+            FlowKey::from_frame(&frame)
+        );
+
+        // An ethernet frame isn't mistaken for an IP packet.
+        assert!(FlowKey::from_ip_packet(&frame).is_none());
+    }
+
+    #[test]
+    fn short_keys_are_rejected() {
+        assert!(Toeplitz::new(&DEFAULT_TOEPLITZ_KEY[..39]).is_err());
+        assert!(Toeplitz::new(&[0; 52]).is_ok());
+    }
+
+    #[test]
+    fn flow_key_is_parsed_from_frame() {
+        let key = FlowKey::from_frame(&udp4_frame(1, 1234, 53)).unwrap();
+
+        assert_eq!(key.src(), IpAddr::V4([192, 168, 69, 1].into()));
+        assert_eq!(key.dst(), IpAddr::V4([192, 168, 69, 2].into()));
+        assert_eq!(key.protocol(), packet::IPPROTO_UDP);
+        assert_eq!(key.ports(), Some((1234, 53)));
+    }
+
+    #[test]
+    fn fragments_are_keyed_without_ports() {
+        let mut first = udp4_frame(0, 1234, 53);
+        first[ETH_HLEN + 6] = 0x20;
+
+        let mut rest = first.clone();
+        rest[ETH_HLEN + 6..ETH_HLEN + 8].copy_from_slice(&0x0003u16.to_be_bytes());
+
+        let first = FlowKey::from_frame(&first).unwrap();
+        assert_eq!(first.ports(), None);
+        assert_eq!(FlowKey::from_frame(&rest), Some(first));
+    }
+
+    #[test]
+    fn dispatcher_keeps_flows_on_one_worker() {
+        let dispatcher = FlowDispatcher::new(Toeplitz::default(), NonZeroU32::new(4).unwrap());
+
+        let frame = udp4_frame(0, 40000, 53);
+        let key = FlowKey::from_frame(&frame).unwrap();
+
+        let worker = dispatcher.dispatch(&frame).unwrap();
+
+        assert!(worker < 4);
+        assert_eq!(worker, dispatcher.dispatch_key(&key));
+        assert_eq!(
+            worker,
+            dispatcher.hasher().hash(&key) % DEFAULT_INDIRECTION_TABLE_LEN as u32 % 4
+        );
+    }
+
+    #[test]
+    fn classes_are_steered_to_pipelines_as_routes_change() {
+        let routes = Arc::new(RouteTable::new(["fast", "analyzer"], 2));
+
+        let steering = Steering::new(
+            |key: &FlowKey| match key.ports() {
+                Some((_, 53)) => 1,
+                Some((_, 80)) => 5,
+                _ => 0,
+            },
+            Arc::clone(&routes),
+        );
+
+        let dns = udp4_frame(0, 1234, 53);
+        let web = udp4_frame(0, 1234, 80);
+
+        assert_eq!(steering.steer(&dns), Some(0));
+        assert_eq!(steering.steer(&udp4_frame(0, 1234, 22)), Some(0));
+        assert_eq!(steering.steer(&web), None);
+        assert_eq!(steering.steer(&[0; 10]), None);
+
+        routes.set_route(1, "analyzer").unwrap();
+        assert_eq!(steering.steer(&dns), Some(1));
+        assert_eq!(routes.updates(), 1);
+
+        assert_eq!(
+            routes.set_route(1, "nope"),
+            Err(RouteError::UnknownPipeline("nope".into()))
+        );
+        assert_eq!(
+            routes.set_route(2, "fast"),
+            Err(RouteError::UnknownClass(2))
+        );
+        assert_eq!(steering.steer(&dns), Some(1));
+        assert_eq!(routes.updates(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn pipelines_must_have_unique_names() {
+        RouteTable::new(["fast", "fast"], 1);
+    }
+
+    #[test]
+    fn closures_can_be_used_as_hashers() {
+        let dispatcher = FlowDispatcher::with_indirection_table(|_: &FlowKey| 3, vec![7, 8, 9, 10]);
+
+        assert_eq!(dispatcher.dispatch(&udp4_frame(0, 1, 2)), Some(10));
+        assert_eq!(dispatcher.dispatch(&[0; 10]), None);
+    }
+}
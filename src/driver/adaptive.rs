@@ -0,0 +1,194 @@
+use std::time::{Duration, Instant};
+
+use crate::socket::BusyPoll;
+
+/// The default length of the window the packet rate is measured over.
+pub const DEFAULT_RATE_WINDOW: Duration = Duration::from_millis(100);
+
+/// The default rate, in packets per second, below which busy polling
+/// is given up.
+pub const DEFAULT_INTERRUPT_BELOW_PPS: u64 = 10_000;
+
+/// The default rate, in packets per second, above which busy polling
+/// is taken up again.
+pub const DEFAULT_BUSY_POLL_ABOVE_PPS: u64 = 50_000;
+
+/// How a [`Driver`](super::Driver) waits for packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollMode {
+    /// Spin, having the kernel run the driver's NAPI loop in the
+    /// calling thread each turn. Lowest latency, but takes the whole
+    /// core however little traffic there is.
+    BusyPoll,
+    /// Sleep in `poll` until packets arrive, with the kernel woken
+    /// as needed.
+    Interrupt,
+}
+
+/// A switch between [`PollMode`]s, passed to
+/// [`Driver::on_poll_mode_change`](super::Driver::on_poll_mode_change).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModeChange {
+    mode: PollMode,
+    rate: u64,
+}
+
+impl ModeChange {
+    /// The mode switched to.
+    pub fn mode(&self) -> PollMode {
+        self.mode
+    }
+
+    /// The packet rate, per second, over the window which prompted
+    /// the switch.
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+}
+
+/// Switches a [`Driver`](super::Driver) between busy polling and
+/// interrupt-driven polling by packet rate.
+///
+/// Busy polling only pays off when there's enough traffic to keep the
+/// core busy anyway. When the rate over a window drops below the lower
+/// threshold the driver falls back to [`PollMode::Interrupt`], and
+/// only goes back to [`PollMode::BusyPoll`] once it rises above the
+/// upper one, so a rate hovering around either doesn't flap between
+/// modes.
+#[derive(Debug, Clone)]
+pub struct AdaptivePoll {
+    busy_poll: BusyPoll,
+    window: Duration,
+    interrupt_below: u64,
+    busy_poll_above: u64,
+    mode: PollMode,
+    window_start: Option<Instant>,
+    window_frames: u64,
+}
+
+impl AdaptivePoll {
+    /// Busy poll, with the socket options in `busy_poll`, while the
+    /// rate is high enough. Starts out waiting on interrupts, so a
+    /// core is only taken once traffic calls for it.
+    pub fn new(busy_poll: BusyPoll) -> Self {
+        Self {
+            busy_poll,
+            window: DEFAULT_RATE_WINDOW,
+            interrupt_below: DEFAULT_INTERRUPT_BELOW_PPS,
+            busy_poll_above: DEFAULT_BUSY_POLL_ABOVE_PPS,
+            mode: PollMode::Interrupt,
+            window_start: None,
+            window_frames: 0,
+        }
+    }
+
+    /// Set the length of the window the packet rate is measured over.
+    /// Default is [`DEFAULT_RATE_WINDOW`].
+    pub fn window(&mut self, window: Duration) -> &mut Self {
+        self.window = window;
+        self
+    }
+
+    /// Set the rates, in packets per second, below which busy polling
+    /// is given up and above which it's taken up again. Defaults are
+    /// [`DEFAULT_INTERRUPT_BELOW_PPS`] and
+    /// [`DEFAULT_BUSY_POLL_ABOVE_PPS`].
+    ///
+    /// # Panics
+    ///
+    /// If `interrupt_below` is greater than `busy_poll_above`.
+    pub fn thresholds(&mut self, interrupt_below: u64, busy_poll_above: u64) -> &mut Self {
+        assert!(
+            interrupt_below <= busy_poll_above,
+            "interrupt threshold must not exceed the busy poll one"
+        );
+
+        self.interrupt_below = interrupt_below;
+        self.busy_poll_above = busy_poll_above;
+        self
+    }
+
+    /// The current mode.
+    pub fn mode(&self) -> PollMode {
+        self.mode
+    }
+
+    /// The socket options applied while busy polling.
+    pub fn busy_poll(&self) -> &BusyPoll {
+        &self.busy_poll
+    }
+
+    /// Count `frames` received at `now`, returning the switch to make
+    /// if a window just closed with the rate past a threshold. The
+    /// mode only changes once the switch is made with
+    /// [`switch`](Self::switch), until then each window past the
+    /// threshold returns it again.
+    pub fn record(&mut self, frames: usize, now: Instant) -> Option<ModeChange> {
+        let start = *self.window_start.get_or_insert(now);
+
+        self.window_frames += frames as u64;
+
+        let elapsed = now.saturating_duration_since(start);
+
+        if elapsed < self.window || elapsed.is_zero() {
+            return None;
+        }
+
+        let rate = (self.window_frames as u128 * 1_000_000 / elapsed.as_micros().max(1)) as u64;
+
+        self.window_start = Some(now);
+        self.window_frames = 0;
+
+        let mode = match self.mode {
+            PollMode::BusyPoll if rate < self.interrupt_below => PollMode::Interrupt,
+            PollMode::Interrupt if rate > self.busy_poll_above => PollMode::BusyPoll,
+            _ => return None,
+        };
+
+        Some(ModeChange { mode, rate })
+    }
+
+    /// Make the switch returned by [`record`](Self::record), once the
+    /// socket options are applied.
+    pub fn switch(&mut self, change: &ModeChange) {
+        self.mode = change.mode;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches_with_hysteresis() {
+        let start = Instant::now();
+        let ms = |m| start + Duration::from_millis(m);
+
+        let mut adaptive = AdaptivePoll::new(BusyPoll::new(50));
+        adaptive.thresholds(1_000, 5_000);
+
+        assert_eq!(adaptive.record(0, start), None);
+
+        // 3000pps is in between, so nothing changes.
+        assert_eq!(adaptive.record(150, ms(50)), None);
+        assert_eq!(adaptive.record(150, ms(100)), None);
+        assert_eq!(adaptive.mode(), PollMode::Interrupt);
+
+        let change = adaptive.record(600, ms(200)).unwrap();
+        assert_eq!((change.mode(), change.rate()), (PollMode::BusyPoll, 6_000));
+        assert_eq!(adaptive.mode(), PollMode::Interrupt);
+
+        // Until switched, each busy window asks again.
+        let change = adaptive.record(600, ms(300)).unwrap();
+        adaptive.switch(&change);
+
+        assert_eq!(adaptive.record(300, ms(400)), None);
+        assert_eq!(adaptive.mode(), PollMode::BusyPoll);
+
+        // 50 frames over 100ms is 500pps, too few to busy poll.
+        assert_eq!(adaptive.record(50, ms(450)), None);
+        let change = adaptive.record(0, ms(500)).unwrap();
+        assert_eq!(change.mode(), PollMode::Interrupt);
+        assert_eq!(change.rate(), 500);
+    }
+}
@@ -0,0 +1,659 @@
+//! A callback driven event loop, for handling traffic without writing
+//! any ring code.
+//!
+//! A [`Driver`] takes over an [`Xsk`] and runs its loop: it keeps the
+//! fill queue topped up, waits for packets, reaps completions and
+//! wakes the kernel as needed, calling back into the application as
+//! things happen. Received frames are handed to the `on_rx` callback
+//! a batch at a time and refilled once it returns, and frames sent
+//! from either callback are recycled once the kernel is done with
+//! them, after being reported to `on_tx_complete`:
+//!
+//! ```no_run
+//! use std::convert::TryInto;
+//! use xsk_rs::{config::{SocketConfig, UmemConfig}, driver::Driver, Xsk};
+//!
+//! let xsk = Xsk::build(
+//!     &"eth0".parse().unwrap(),
+//!     0,
+//!     UmemConfig::default(),
+//!     SocketConfig::default(),
+//!     4096.try_into().unwrap(),
+//! )
+//! .unwrap();
+//!
+//! let mut driver = Driver::new(xsk).unwrap();
+//!
+//! // Echo everything back.
+//! driver.on_rx(|batch| {
+//!     for i in 0..batch.len() {
+//!         let pkt = batch.data(i).contents().to_vec();
+//!         batch.send(&pkt);
+//!     }
+//! });
+//!
+//! let stopper = driver.stopper();
+//! ctrlc::set_handler(move || stopper.stop()).unwrap();
+//!
+//! driver.run().unwrap();
+//! ```
+//!
+//! Frames are split evenly between rx and tx when the driver is
+//! created. Payloads sent are copied into tx frames, so no frame is
+//! ever accessible to the application once handed back.
+//!
+//! By default the loop sleeps in `poll` while there's nothing to do.
+//! With an [`AdaptivePoll`] it busy polls instead while the packet
+//! rate justifies spending a core on it, see
+//! [`Driver::adaptive_poll`].
+//!
+//! Loops needing more control than callbacks give, e.g. to run from
+//! another scheduler, can be built on the [`spi`] module instead.
+
+mod adaptive;
+pub use adaptive::{
+    AdaptivePoll, ModeChange, PollMode, DEFAULT_BUSY_POLL_ABOVE_PPS, DEFAULT_INTERRUPT_BELOW_PPS,
+    DEFAULT_RATE_WINDOW,
+};
+
+pub mod spi;
+
+use std::{
+    fmt, io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use crate::{
+    cancel::CancelToken,
+    socket::WakeFd,
+    umem::frame::{self, CopyMode, Data, DataMut, FrameDesc},
+    xsk::Xsk,
+    Umem,
+};
+
+/// The default number of frames consumed from the rx and completion
+/// rings at a time.
+pub const DEFAULT_BATCH_SIZE: usize = 64;
+
+/// The default time [`Driver::run`] waits for packets before checking
+/// on completions again, in milliseconds.
+pub const DEFAULT_POLL_TIMEOUT_MS: i32 = 100;
+
+type RxCallback = Box<dyn FnMut(&mut RxBatch<'_>)>;
+
+type TxCompleteCallback = Box<dyn FnMut(&mut CompBatch<'_>)>;
+
+type ModeChangeCallback = Box<dyn FnMut(&ModeChange)>;
+
+/// Frames free for sending, and those written but not yet on the tx
+/// ring.
+#[derive(Debug)]
+struct TxFrames {
+    free: Vec<FrameDesc>,
+    pending: Vec<FrameDesc>,
+    dropped: u64,
+    copy_mode: CopyMode,
+}
+
+impl TxFrames {
+    fn send(&mut self, umem: &Umem, payload: &[u8]) -> bool {
+        let mut desc = match self.free.pop() {
+            Some(desc) => desc,
+            None => {
+                self.dropped += 1;
+                return false;
+            }
+        };
+
+        // SAFETY: frames on the free list are owned by the driver and
+        // belong to `umem`.
+        let written = unsafe { umem.data_mut(&mut desc) }
+            .cursor()
+            .write_all_with(payload, self.copy_mode)
+            .is_ok();
+
+        if written {
+            self.pending.push(desc);
+        } else {
+            self.free.push(desc);
+            self.dropped += 1;
+        }
+
+        written
+    }
+}
+
+/// A batch of received frames, passed to the [`Driver::on_rx`]
+/// callback.
+///
+/// The frames are returned to the fill queue once the callback
+/// returns, so their contents must be copied out to keep them.
+#[derive(Debug)]
+pub struct RxBatch<'a> {
+    umem: &'a Umem,
+    descs: &'a mut [FrameDesc],
+    tx: &'a mut TxFrames,
+}
+
+impl RxBatch<'_> {
+    /// The number of frames received.
+    pub fn len(&self) -> usize {
+        self.descs.len()
+    }
+
+    /// Whether the batch is empty. The callback is never passed an
+    /// empty batch.
+    pub fn is_empty(&self) -> bool {
+        self.descs.is_empty()
+    }
+
+    /// The packet data of the `idx`th frame.
+    ///
+    /// # Panics
+    ///
+    /// If `idx` is out of bounds.
+    pub fn data(&self, idx: usize) -> Data<'_> {
+        // SAFETY: the frame was just received so is owned by the
+        // driver, and the borrow of `self` rules out writes to it.
+        unsafe { self.umem.data(&self.descs[idx]) }
+    }
+
+    /// The packet data of the `idx`th frame, writeable, e.g. to change
+    /// it before copying it into a frame to [`send`](Self::send).
+    ///
+    /// # Panics
+    ///
+    /// If `idx` is out of bounds.
+    pub fn data_mut(&mut self, idx: usize) -> DataMut<'_> {
+        // SAFETY: as for `data`, with the mutable borrow ruling out
+        // any other access.
+        unsafe { self.umem.data_mut(&mut self.descs[idx]) }
+    }
+
+    /// Copy `payload` into a free tx frame to be sent once the
+    /// callback returns. Returns `false` if there's no free frame or
+    /// the payload doesn't fit in one.
+    pub fn send(&mut self, payload: &[u8]) -> bool {
+        self.tx.send(self.umem, payload)
+    }
+}
+
+/// A batch of sent frames the kernel is done with, passed to the
+/// [`Driver::on_tx_complete`] callback.
+///
+/// The frames are free for sending again once the callback returns.
+#[derive(Debug)]
+pub struct CompBatch<'a> {
+    umem: &'a Umem,
+    descs: &'a [FrameDesc],
+    tx: &'a mut TxFrames,
+}
+
+impl CompBatch<'_> {
+    /// The completed frames.
+    pub fn descs(&self) -> &[FrameDesc] {
+        self.descs
+    }
+
+    /// See [`RxBatch::send`].
+    pub fn send(&mut self, payload: &[u8]) -> bool {
+        self.tx.send(self.umem, payload)
+    }
+}
+
+/// Stops a [`Driver::run`] loop from another thread, or from a signal
+/// handler thread.
+#[derive(Debug, Clone)]
+pub struct Stopper {
+    stopped: Arc<AtomicBool>,
+    wake: WakeFd,
+}
+
+impl Stopper {
+    /// Make the loop return after the current turn, waking it if
+    /// it's waiting for packets.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+
+        // Nothing more can be done if this fails, but the loop still
+        // sees the flag within a poll timeout.
+        let _ = self.wake.wake();
+    }
+}
+
+/// What a [`Driver`] has done so far.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DriverStats {
+    turns: u64,
+    rx: u64,
+    tx: u64,
+    completed: u64,
+    tx_dropped: u64,
+    mode_changes: u64,
+}
+
+impl DriverStats {
+    /// The number of turns of the loop.
+    pub fn turns(&self) -> u64 {
+        self.turns
+    }
+
+    /// The number of frames received and passed to `on_rx`.
+    pub fn rx(&self) -> u64 {
+        self.rx
+    }
+
+    /// The number of frames put on the tx ring.
+    pub fn tx(&self) -> u64 {
+        self.tx
+    }
+
+    /// The number of sent frames completed and passed to
+    /// `on_tx_complete`.
+    pub fn completed(&self) -> u64 {
+        self.completed
+    }
+
+    /// The number of [`RxBatch::send`] and the like calls which
+    /// failed for lack of a free frame, or a big enough one.
+    pub fn tx_dropped(&self) -> u64 {
+        self.tx_dropped
+    }
+
+    /// The number of switches between [`PollMode`]s.
+    pub fn mode_changes(&self) -> u64 {
+        self.mode_changes
+    }
+}
+
+/// Runs an [`Xsk`]'s loop, calling back into the application as
+/// frames are received and sends complete. See the
+/// [module docs](self).
+pub struct Driver {
+    xsk: Xsk,
+    rx_descs: Vec<FrameDesc>,
+    comp_descs: Vec<FrameDesc>,
+    tx: TxFrames,
+    on_rx: Option<RxCallback>,
+    on_tx_complete: Option<TxCompleteCallback>,
+    on_poll_mode_change: Option<ModeChangeCallback>,
+    adaptive: Option<AdaptivePoll>,
+    poll_timeout: i32,
+    stopped: Arc<AtomicBool>,
+    wake: WakeFd,
+    cancel: Option<CancelToken>,
+    stats: DriverStats,
+}
+
+impl Driver {
+    /// Take over `xsk`, putting half of its frames on the fill queue,
+    /// or as many as fit, and keeping the rest for tx.
+    ///
+    /// Fails if an eventfd can't be created for
+    /// [`stopper`](Self::stopper), e.g. when out of file descriptors.
+    pub fn new(mut xsk: Xsk) -> io::Result<Self> {
+        let wake = WakeFd::new()?;
+
+        let mut descs = std::mem::take(&mut xsk.descs);
+
+        let half = descs.len() / 2;
+        let fill = xsk.fq.free_slots(half as u32) as usize;
+
+        let rx = descs.split_off(descs.len() - fill);
+
+        // SAFETY: the frames are fresh from `xsk`'s UMEM, and from
+        // here on only the driver accesses them.
+        let produced = unsafe { xsk.fq.produce(&rx) };
+
+        debug_assert_eq!(produced, rx.len());
+
+        Ok(Self {
+            xsk,
+            rx_descs: rx,
+            comp_descs: vec![FrameDesc::default(); DEFAULT_BATCH_SIZE],
+            tx: TxFrames {
+                pending: Vec::with_capacity(descs.len()),
+                free: descs,
+                dropped: 0,
+                copy_mode: CopyMode::Cached,
+            },
+            on_rx: None,
+            on_tx_complete: None,
+            on_poll_mode_change: None,
+            adaptive: None,
+            poll_timeout: DEFAULT_POLL_TIMEOUT_MS,
+            stopped: Arc::new(AtomicBool::new(false)),
+            wake,
+            cancel: None,
+            stats: DriverStats::default(),
+        })
+    }
+
+    /// Set the callback for received frames. Without one, frames are
+    /// returned to the fill queue unseen.
+    pub fn on_rx<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut(&mut RxBatch<'_>) + 'static,
+    {
+        self.on_rx = Some(Box::new(f));
+        self
+    }
+
+    /// Set the callback for completed sends. Without one, completed
+    /// frames are quietly made free for sending again.
+    pub fn on_tx_complete<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut(&mut CompBatch<'_>) + 'static,
+    {
+        self.on_tx_complete = Some(Box::new(f));
+        self
+    }
+
+    /// Set the callback for switches between [`PollMode`]s made by
+    /// an [`adaptive_poll`](Self::adaptive_poll) policy.
+    pub fn on_poll_mode_change<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnMut(&ModeChange) + 'static,
+    {
+        self.on_poll_mode_change = Some(Box::new(f));
+        self
+    }
+
+    /// Switch between busy polling and waiting on interrupts by
+    /// packet rate, as decided by `adaptive`. `None`, the default,
+    /// always waits on interrupts.
+    ///
+    /// The socket's busy poll options are set when switching to
+    /// [`PollMode::BusyPoll`] and cleared when switching back, so it
+    /// should be created without any.
+    pub fn adaptive_poll(&mut self, adaptive: Option<AdaptivePoll>) -> &mut Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    /// How the loop currently waits for packets.
+    pub fn poll_mode(&self) -> PollMode {
+        match &self.adaptive {
+            Some(adaptive) => adaptive.mode(),
+            None => PollMode::Interrupt,
+        }
+    }
+
+    /// Set how many frames are consumed from the rx and completion
+    /// rings at a time. Default is [`DEFAULT_BATCH_SIZE`]. Receives
+    /// and sends are further capped by the socket's
+    /// [`max_burst`](crate::config::SocketConfigBuilder::max_burst),
+    /// if set.
+    ///
+    /// # Panics
+    ///
+    /// If `batch_size` is zero.
+    pub fn batch_size(&mut self, batch_size: usize) -> &mut Self {
+        assert!(batch_size > 0, "batch size must be non-zero");
+
+        self.comp_descs.resize(batch_size, FrameDesc::default());
+        self
+    }
+
+    /// Set how long [`run`](Self::run) waits for packets before
+    /// checking on completions again, in milliseconds. Default is
+    /// [`DEFAULT_POLL_TIMEOUT_MS`].
+    pub fn poll_timeout(&mut self, poll_timeout: i32) -> &mut Self {
+        self.poll_timeout = poll_timeout;
+        self
+    }
+
+    /// Set how payloads passed to any of the `send` functions are
+    /// copied into tx frames. Default is [`CopyMode::Cached`].
+    ///
+    /// [`CopyMode::NonTemporal`] keeps large payloads from evicting
+    /// the application's working set, and the driver fences before
+    /// submitting them.
+    pub fn copy_mode(&mut self, mode: CopyMode) -> &mut Self {
+        self.tx.copy_mode = mode;
+        self
+    }
+
+    /// A handle for stopping [`run`](Self::run).
+    pub fn stopper(&self) -> Stopper {
+        Stopper {
+            stopped: Arc::clone(&self.stopped),
+            wake: self.wake.clone(),
+        }
+    }
+
+    /// Also stop [`run`](Self::run) once `cancel` is cancelled, as
+    /// with a [`Stopper`], e.g. to shut down along with the rest of
+    /// the application. The token isn't reset when `run` returns, so
+    /// runs after it's cancelled return straight away.
+    pub fn cancel_on(&mut self, cancel: &CancelToken) -> &mut Self {
+        cancel.wake_on_cancel(&self.wake);
+        self.cancel = Some(cancel.clone());
+        self
+    }
+
+    /// Copy `payload` into a free tx frame to be sent on the next
+    /// turn, e.g. to start a conversation before [`run`](Self::run).
+    /// Returns `false` if there's no free frame or the payload doesn't
+    /// fit in one.
+    pub fn send(&mut self, payload: &[u8]) -> bool {
+        self.tx.send(&self.xsk.umem, payload)
+    }
+
+    /// What the driver has done so far.
+    pub fn stats(&self) -> DriverStats {
+        DriverStats {
+            tx_dropped: self.tx.dropped,
+            ..self.stats
+        }
+    }
+
+    /// Turn the loop until stopped by a [`Stopper`] or the
+    /// [`cancel_on`](Self::cancel_on) token, returning what was done.
+    ///
+    /// Stops early on any error waking the kernel or polling, leaving
+    /// the driver as it was so it can be run again.
+    pub fn run(&mut self) -> io::Result<DriverStats> {
+        while !self.stopped.load(Ordering::Acquire) && !self.is_cancelled() {
+            self.turn(self.poll_timeout)?;
+        }
+
+        // Let a later run go ahead.
+        self.stopped.store(false, Ordering::Release);
+
+        Ok(self.stats())
+    }
+
+    /// Turn the loop once: reap completions, send anything pending,
+    /// then wait up to `poll_timeout` milliseconds for packets and
+    /// hand them to `on_rx`. Returns the number of frames received.
+    ///
+    /// For driving the loop from an application's own, say alongside
+    /// other work. Doesn't wait for packets while sends are pending
+    /// or in flight, so completions are reaped promptly, nor while
+    /// busy polling.
+    pub fn turn(&mut self, poll_timeout: i32) -> io::Result<usize> {
+        let cnt = self.turn_once(poll_timeout)?;
+
+        if let Some(adaptive) = self.adaptive.as_mut() {
+            if let Some(change) = adaptive.record(cnt, Instant::now()) {
+                self.change_mode(&change)?;
+            }
+        }
+
+        Ok(cnt)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+    }
+
+    fn change_mode(&mut self, change: &ModeChange) -> io::Result<()> {
+        let adaptive = match self.adaptive.as_mut() {
+            Some(adaptive) => adaptive,
+            None => return Ok(()),
+        };
+
+        let fd = self.xsk.rx_q.fd();
+
+        // Only switch once the socket has, so a failure is retried
+        // when the next window closes.
+        match change.mode() {
+            PollMode::BusyPoll => fd.set_busy_poll(adaptive.busy_poll())?,
+            PollMode::Interrupt => fd.clear_busy_poll(adaptive.busy_poll())?,
+        }
+
+        adaptive.switch(change);
+        self.stats.mode_changes += 1;
+
+        if let Some(on_poll_mode_change) = self.on_poll_mode_change.as_mut() {
+            on_poll_mode_change(change);
+        }
+
+        Ok(())
+    }
+
+    fn turn_once(&mut self, poll_timeout: i32) -> io::Result<usize> {
+        self.stats.turns += 1;
+
+        self.reap();
+        self.submit()?;
+
+        let busy = self.poll_mode() == PollMode::BusyPoll;
+        let nb = self.comp_descs.len().min(self.rx_descs.len());
+        let nb = self
+            .xsk
+            .rx_q
+            .max_burst()
+            .map_or(nb, |max| nb.min(max.get()));
+
+        // SAFETY: only frames of the UMEM ever go on its fill queue.
+        let mut cnt = unsafe {
+            if busy {
+                self.xsk.rx_q.busy_poll_recv(&mut self.rx_descs[..nb])?
+            } else {
+                self.xsk.rx_q.consume(&mut self.rx_descs[..nb])
+            }
+        };
+
+        if cnt == 0 && busy {
+            return Ok(0);
+        }
+
+        if cnt == 0 {
+            let in_flight = self.tx.free.len() + self.tx.pending.len() < self.tx_capacity();
+            let wait = if in_flight { 0 } else { poll_timeout };
+
+            if !self.xsk.rx_q.poll_or_wake(&self.wake, wait)?.is_ready() {
+                return Ok(0);
+            }
+
+            // SAFETY: as above.
+            cnt = unsafe { self.xsk.rx_q.consume(&mut self.rx_descs[..nb]) };
+
+            if cnt == 0 {
+                return Ok(0);
+            }
+        }
+
+        self.stats.rx += cnt as u64;
+
+        if let Some(on_rx) = self.on_rx.as_mut() {
+            on_rx(&mut RxBatch {
+                umem: &self.xsk.umem,
+                descs: &mut self.rx_descs[..cnt],
+                tx: &mut self.tx,
+            });
+        }
+
+        // SAFETY: the frames were consumed from the rx ring and, now
+        // the callback's returned, are no longer accessed.
+        let produced = unsafe { self.xsk.fq.produce(&self.rx_descs[..cnt]) };
+
+        // Room for every frame was made when they were received.
+        debug_assert_eq!(produced, cnt);
+
+        if self.xsk.fq.needs_wakeup() {
+            self.xsk.fq.wakeup(self.xsk.rx_q.fd_mut(), 0)?;
+        }
+
+        self.submit()?;
+
+        Ok(cnt)
+    }
+
+    /// The number of frames set aside for tx.
+    fn tx_capacity(&self) -> usize {
+        self.xsk.umem.frame_count() - self.rx_descs.len()
+    }
+
+    fn reap(&mut self) {
+        // SAFETY: only frames of the UMEM are ever sent.
+        let cnt = unsafe { self.xsk.cq.consume(&mut self.comp_descs) };
+
+        if cnt == 0 {
+            return;
+        }
+
+        self.stats.completed += cnt as u64;
+
+        if let Some(on_tx_complete) = self.on_tx_complete.as_mut() {
+            on_tx_complete(&mut CompBatch {
+                umem: &self.xsk.umem,
+                descs: &self.comp_descs[..cnt],
+                tx: &mut self.tx,
+            });
+        }
+
+        self.tx.free.extend_from_slice(&self.comp_descs[..cnt]);
+    }
+
+    fn submit(&mut self) -> io::Result<()> {
+        if self.tx.pending.is_empty() {
+            return Ok(());
+        }
+
+        // The payloads must have landed before the ring says so.
+        if self.tx.copy_mode == CopyMode::NonTemporal {
+            frame::store_fence();
+        }
+
+        // SAFETY: pending frames are owned by the driver and written,
+        // and no longer accessed once on the ring.
+        let nb = self
+            .xsk
+            .tx_q
+            .max_burst()
+            .map_or(usize::MAX, |max| max.get());
+        let cnt = unsafe { self.xsk.tx_q.extend(self.tx.pending.iter().take(nb)) };
+
+        self.tx.pending.drain(..cnt);
+        self.stats.tx += cnt as u64;
+
+        // While busy polling, the kick is what runs the driver's tx
+        // processing, so it's needed whatever the ring's flag says.
+        if self.poll_mode() == PollMode::BusyPoll {
+            self.xsk.tx_q.wakeup()?;
+        } else {
+            self.xsk.tx_q.commit_wakeup()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Driver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Driver")
+            .field("xsk", &self.xsk)
+            .field("tx", &self.tx)
+            .field("adaptive", &self.adaptive)
+            .field("poll_timeout", &self.poll_timeout)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
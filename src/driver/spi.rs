@@ -0,0 +1,159 @@
+//! The low-level surface for writing a custom ring driving loop, e.g.
+//! to run sockets from an existing scheduler or runtime rather than a
+//! [`Driver`](super::Driver).
+//!
+//! Everything a loop needs is re-exported here:
+//!
+//! - Batch consume and produce with ownership tokens: the
+//!   `consume_owned` methods of [`RxQueue`] and [`CompQueue`] hand out
+//!   [`OwnedFrame`]s, and the `produce_owned` methods of
+//!   [`FillQueue`] and [`TxQueue`] take them back, so frames can be
+//!   moved between the kernel and the application without `unsafe`.
+//! - Wakeups: [`kick`] performs whichever of the rx and tx wakeups the
+//!   kernel asked for, as signalled by the `needs_wakeup` methods.
+//! - Readiness: the socket's [`Fd`] is [`AsRawFd`], so it can be
+//!   registered with any reactor, and [`interest`] says which events
+//!   to wait for. A [`WakeFd`] lets other threads interrupt the wait.
+//!
+//! A loop forwarding frames from rx straight back to tx, waiting with
+//! plain `poll(2)` in place of a reactor:
+//!
+//! ```no_run
+//! use std::os::unix::io::AsRawFd;
+//! use xsk_rs::driver::spi::{self, OwnedFrame};
+//! # use xsk_rs::{CompQueue, FillQueue, RxQueue, TxQueue, Umem};
+//! # fn run(umem: &Umem, rx_q: &mut RxQueue, tx_q: &mut TxQueue, fq: &mut FillQueue, cq: &mut CompQueue) -> std::io::Result<()> {
+//!
+//! let mut frames: Vec<OwnedFrame<'_>> = Vec::new();
+//!
+//! loop {
+//!     rx_q.consume_owned(umem, 64, &mut frames).unwrap();
+//!     tx_q.produce_owned(&mut frames).unwrap();
+//!
+//!     cq.consume_owned(umem, 64, &mut frames).unwrap();
+//!     fq.produce_owned(&mut frames).unwrap();
+//!
+//!     spi::kick(rx_q, tx_q, fq)?;
+//!
+//!     let interest = spi::interest(tx_q);
+//!     let mut pfd = libc::pollfd {
+//!         fd: rx_q.fd().as_raw_fd(),
+//!         events: interest.poll_events(),
+//!         revents: 0,
+//!     };
+//!
+//!     unsafe { libc::poll(&mut pfd, 1, 100) };
+//! }
+//! # }
+//! ```
+
+use bitflags::bitflags;
+use libc::{POLLIN, POLLOUT};
+use std::io;
+
+pub use crate::{
+    socket::{Fd, PollOutcome, RxQueue, TxQueue, WakeFd},
+    umem::{frame::FrameDesc, CompQueue, FillQueue, OwnedFrame, Umem, UmemMismatchError},
+};
+pub use std::os::unix::io::AsRawFd;
+
+bitflags! {
+    /// Which events on a socket's [`Fd`] a loop should wait for.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Interest: u8 {
+        /// Frames may be received, i.e. wait for the fd to become
+        /// readable.
+        const READABLE = 1 << 0;
+        /// Frames are stuck waiting to be sent, i.e. wait for the fd
+        /// to become writable.
+        const WRITABLE = 1 << 1;
+    }
+}
+
+impl Interest {
+    /// The matching `poll(2)` event flags.
+    #[inline]
+    pub fn poll_events(&self) -> i16 {
+        let mut events = 0;
+
+        if self.contains(Self::READABLE) {
+            events |= POLLIN;
+        }
+
+        if self.contains(Self::WRITABLE) {
+            events |= POLLOUT;
+        }
+
+        events
+    }
+}
+
+/// The events to wait for on the socket `tx_q` belongs to.
+///
+/// Always [`READABLE`](Interest::READABLE), plus
+/// [`WRITABLE`](Interest::WRITABLE) if `tx_q` holds frames which
+/// won't go out without it: when the ring is full, or the kernel has
+/// asked to be woken to send them. Otherwise the kernel is already
+/// sending, and a socket with room on its ring is always writable, so
+/// asking would return from every wait at once.
+#[inline]
+pub fn interest(tx_q: &mut TxQueue) -> Interest {
+    let pending = tx_q.fill_level();
+
+    if writable(pending, tx_q.capacity(), tx_q.needs_wakeup()) {
+        Interest::READABLE | Interest::WRITABLE
+    } else {
+        Interest::READABLE
+    }
+}
+
+/// Whether a tx ring of `capacity` holding `pending` frames needs
+/// waiting on for writability.
+#[inline]
+fn writable(pending: usize, capacity: usize, needs_wakeup: bool) -> bool {
+    pending >= capacity || (pending > 0 && needs_wakeup)
+}
+
+/// Wake the kernel to process `tx_q` and `fq` if it has asked to be
+/// woken for either, see [`TxQueue::needs_wakeup`] and
+/// [`FillQueue::needs_wakeup`]. A no-op for sockets bound without
+/// `XDP_USE_NEED_WAKEUP`, which never ask.
+///
+/// `rx_q` must belong to the same socket as `tx_q`.
+#[inline]
+pub fn kick(rx_q: &mut RxQueue, tx_q: &mut TxQueue, fq: &mut FillQueue) -> io::Result<()> {
+    if tx_q.needs_wakeup() && tx_q.fill_level() > 0 {
+        tx_q.wakeup()?;
+    }
+
+    if fq.needs_wakeup() {
+        fq.wakeup(rx_q.fd_mut(), 0)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interest_maps_to_poll_events() {
+        assert_eq!(Interest::READABLE.poll_events(), POLLIN);
+        assert_eq!(
+            (Interest::READABLE | Interest::WRITABLE).poll_events(),
+            POLLIN | POLLOUT
+        );
+        assert_eq!(Interest::empty().poll_events(), 0);
+    }
+
+    #[test]
+    fn only_stuck_frames_ask_for_writable() {
+        assert!(!writable(0, 8, false));
+        assert!(!writable(0, 8, true));
+        assert!(!writable(3, 8, false));
+
+        assert!(writable(3, 8, true));
+        assert!(writable(8, 8, false));
+    }
+}
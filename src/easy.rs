@@ -0,0 +1,239 @@
+//! A socket for sending and receiving packets with no `unsafe` and
+//! no ring handling.
+//!
+//! [`XskSocket`] owns a [`Umem`], a socket bound to it and all four
+//! queues, and keeps track of every frame itself: half of them are
+//! kept on the fill queue to receive into, recycled as soon as each
+//! packet has been copied out, and the other half are held in a
+//! [`FramePool`] to send from, returned to it as the kernel completes
+//! them. Packets are copied in and out of frames, so none is ever
+//! reachable from the application once the kernel might be using it:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use xsk_rs::easy::XskSocket;
+//!
+//! let mut xsk = XskSocket::new(&"eth0".parse().unwrap(), 0).unwrap();
+//! let mut pkt = Vec::new();
+//!
+//! // Echo everything back.
+//! loop {
+//!     if xsk.recv_timeout(&mut pkt, Duration::from_millis(100)).unwrap() {
+//!         xsk.send(&pkt).unwrap();
+//!     }
+//! }
+//! ```
+//!
+//! The copies cost some throughput, as does handling one packet per
+//! call. Once that matters, the same can be done with [`Xsk`] and the
+//! queues directly, or a [`Driver`](crate::driver::Driver).
+//!
+//! Multi-buffer packets aren't put back together, so sockets should be
+//! configured without `XDP_USE_SG`.
+
+use std::{
+    convert::TryInto,
+    io,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{Interface, SocketConfig, UmemConfig},
+    socket::Fd,
+    umem::{
+        frame::{CopyMode, FrameDesc},
+        FramePool, Umem,
+    },
+    xsk::{Xsk, XskBuildError},
+};
+
+/// The number of frames [`XskSocket::new`] creates its [`Umem`] with.
+pub const DEFAULT_FRAME_COUNT: u32 = 4096;
+
+/// How many completions are reaped at a time.
+const REAP_BATCH: usize = 64;
+
+/// A safe, copying AF_XDP socket, see the [module docs](self).
+#[derive(Debug)]
+pub struct XskSocket {
+    xsk: Xsk,
+    tx_pool: FramePool,
+    rx_desc: FrameDesc,
+    scratch: Vec<FrameDesc>,
+}
+
+impl XskSocket {
+    /// Bind a socket to `queue_id` of `if_name` with the default
+    /// [`UmemConfig`] and [`SocketConfig`], and
+    /// [`DEFAULT_FRAME_COUNT`] frames.
+    ///
+    /// May require root permissions to create successfully.
+    pub fn new(if_name: &Interface, queue_id: u32) -> Result<Self, XskBuildError> {
+        Self::with_config(
+            if_name,
+            queue_id,
+            UmemConfig::default(),
+            SocketConfig::default(),
+            DEFAULT_FRAME_COUNT,
+        )
+    }
+
+    /// Bind a socket as in [`new`](Self::new), with the given configs
+    /// and number of frames.
+    ///
+    /// # Panics
+    ///
+    /// If `frame_count` is less than two, since at least one frame is
+    /// needed each for sending and receiving.
+    pub fn with_config(
+        if_name: &Interface,
+        queue_id: u32,
+        umem_config: UmemConfig,
+        socket_config: SocketConfig,
+        frame_count: u32,
+    ) -> Result<Self, XskBuildError> {
+        assert!(frame_count >= 2, "need at least two frames");
+
+        let mut xsk = Xsk::build(
+            if_name,
+            queue_id,
+            umem_config,
+            socket_config,
+            frame_count.try_into().unwrap(),
+        )?;
+
+        let rx_count = (xsk.descs.len() / 2).min(umem_config.fill_queue_size().get() as usize);
+        let tx_descs = xsk.descs.split_off(rx_count);
+
+        // SAFETY: the frames were just created, so are unused, and
+        // belong to the fill queue's UMEM.
+        let filled = unsafe { xsk.fq.produce(&xsk.descs) };
+        debug_assert_eq!(filled, rx_count);
+
+        let rx_desc = xsk.descs[0];
+
+        Ok(Self {
+            tx_pool: FramePool::new(&tx_descs),
+            rx_desc,
+            scratch: vec![rx_desc; REAP_BATCH],
+            xsk,
+        })
+    }
+
+    /// Copy the next received packet into `buf`, replacing its
+    /// contents. Returns `false`, leaving `buf` as is, if nothing has
+    /// been received.
+    pub fn recv(&mut self, buf: &mut Vec<u8>) -> io::Result<bool> {
+        let mut desc = self.rx_desc;
+
+        // SAFETY: every frame on the rx ring was put on the fill queue
+        // by this socket, and is handed straight back below.
+        if unsafe { self.xsk.rx_q.consume_one(&mut desc) } == 0 {
+            if self.xsk.fq.needs_wakeup() {
+                self.xsk.fq.wakeup(self.xsk.rx_q.fd_mut(), 0)?;
+            }
+
+            return Ok(false);
+        }
+
+        buf.clear();
+
+        // SAFETY: the frame was just consumed so is owned by user
+        // space, and belongs to this socket's UMEM.
+        buf.extend_from_slice(unsafe { self.xsk.umem.data(&desc) }.contents());
+
+        // SAFETY: the frame's contents have been copied out and it's
+        // not referenced anywhere else. There's always room, since
+        // only the frames taken off the fill queue are put back on.
+        unsafe { self.xsk.fq.produce_one(&desc) };
+
+        Ok(true)
+    }
+
+    /// Same as [`recv`](Self::recv), but waits up to `timeout` for a
+    /// packet to arrive.
+    pub fn recv_timeout(&mut self, buf: &mut Vec<u8>, timeout: Duration) -> io::Result<bool> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if self.recv(buf)? {
+                return Ok(true);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+
+            let ms = remaining.as_millis().clamp(1, i32::MAX as u128) as i32;
+            self.xsk.rx_q.poll(ms)?;
+        }
+    }
+
+    /// Copy `pkt` into a free frame and submit it for sending. Returns
+    /// `false` if there's no frame free, or no room on the tx ring,
+    /// i.e. the kernel hasn't caught up yet.
+    ///
+    /// # Errors
+    ///
+    /// If `pkt` doesn't fit in a frame, or waking the kernel fails.
+    pub fn send(&mut self, pkt: &[u8]) -> io::Result<bool> {
+        self.reap();
+
+        let mut desc = match self.tx_pool.try_alloc() {
+            Some(desc) => desc,
+            None => return Ok(false),
+        };
+
+        // SAFETY: the frame was just allocated from the pool so is
+        // unused, and belongs to this socket's UMEM.
+        let written = unsafe { self.xsk.umem.data_mut(&mut desc) }
+            .cursor()
+            .write_all_with(pkt, CopyMode::Cached);
+
+        if written.is_err() {
+            self.tx_pool.free(&desc);
+
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "packet larger than a frame",
+            ));
+        }
+
+        // SAFETY: the frame came from the pool and is handed over to
+        // the kernel until reaped.
+        if unsafe { self.tx_pool.send(&mut self.xsk.tx_q, &[desc]) } == 0 {
+            self.tx_pool.free(&desc);
+            return Ok(false);
+        }
+
+        self.xsk.tx_q.commit_wakeup()?;
+
+        Ok(true)
+    }
+
+    /// Return frames the kernel has finished sending to the pool.
+    fn reap(&mut self) {
+        // SAFETY: only frames allocated from the pool are sent, and
+        // the completion queue belongs to their UMEM.
+        unsafe { self.tx_pool.reap(&mut self.xsk.cq, &mut self.scratch) };
+    }
+
+    /// The frames held for sending, e.g. to check how many are in
+    /// flight with [`FramePool::counts`].
+    pub fn tx_pool(&self) -> &FramePool {
+        &self.tx_pool
+    }
+
+    /// The socket's [`Umem`].
+    pub fn umem(&self) -> &Umem {
+        &self.xsk.umem
+    }
+
+    /// The socket's file descriptor, e.g. to read its
+    /// [`XdpStatistics`](crate::socket::XdpStatistics).
+    pub fn fd(&self) -> &Fd {
+        self.xsk.rx_q.fd()
+    }
+}
@@ -0,0 +1,588 @@
+//! A C ABI for embedding in dataplanes written in other languages.
+//!
+//! Enabled with the `ffi` feature. Build a shared library with
+//!
+//! ```text
+//! cargo rustc --release --features ffi --crate-type cdylib
+//! ```
+//!
+//! and include `include/xsk_rs.h`.
+//!
+//! An [`XskRsSocket`] bundles a [`Umem`](crate::Umem), a socket bound
+//! to it and a pool of free frames, and keeps track of who owns every
+//! frame. C code only ever holds frames handed to it, by
+//! [`xsk_rs_rx_burst`] or [`xsk_rs_alloc`], and only those are
+//! accepted back by [`xsk_rs_tx_burst`] and [`xsk_rs_recycle`], so a
+//! frame can't be submitted twice or written while the kernel has it.
+//!
+//! Functions returning `c_int` give a negative `errno` on failure.
+
+use std::{
+    convert::TryFrom,
+    error::Error,
+    ffi::CStr,
+    io, mem,
+    num::NonZeroU32,
+    os::{
+        raw::{c_char, c_int},
+        unix::prelude::AsRawFd,
+    },
+    ptr, slice,
+};
+
+use libc::{EINVAL, EIO};
+
+use crate::{
+    config::{Interface, SocketConfig, UmemConfig},
+    umem::frame::{FrameDesc, SegmentLengths},
+    xsk::{Xsk, XskBuildError},
+};
+
+/// A frame descriptor as seen from C.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct XskRsDesc {
+    /// The address of the frame's packet data within the UMEM.
+    pub addr: u64,
+    /// The length of the packet data.
+    pub len: u32,
+    /// The descriptor's options, see
+    /// [`DescOptions`](crate::umem::frame::DescOptions).
+    pub options: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Owner {
+    Pool,
+    App,
+    Fill,
+    Tx,
+}
+
+/// Who holds each frame, plus the pool of free ones.
+#[derive(Debug)]
+struct Frames {
+    frame_size: usize,
+    // The packet data address of each frame, as handed out by `alloc`.
+    addrs: Vec<usize>,
+    owners: Vec<Owner>,
+    pool: Vec<u32>,
+    in_fill: usize,
+}
+
+impl Frames {
+    fn new(frame_size: usize, addrs: Vec<usize>) -> Self {
+        let owners = vec![Owner::Pool; addrs.len()];
+        let pool = (0..addrs.len() as u32).rev().collect();
+
+        Self {
+            frame_size,
+            addrs,
+            owners,
+            pool,
+            in_fill: 0,
+        }
+    }
+
+    fn index(&self, addr: u64) -> Option<usize> {
+        let frame = addr as usize / self.frame_size;
+
+        if frame < self.owners.len() {
+            Some(frame)
+        } else {
+            None
+        }
+    }
+
+    /// Take up to `nb` frames from the pool for `owner`.
+    fn take(&mut self, nb: usize, owner: Owner, mut f: impl FnMut(FrameDesc)) -> usize {
+        let cnt = nb.min(self.pool.len());
+
+        for frame in self.pool.drain(self.pool.len() - cnt..).rev() {
+            self.owners[frame as usize] = owner;
+
+            f(FrameDesc {
+                addr: self.addrs[frame as usize],
+                options: 0,
+                lengths: SegmentLengths::default(),
+            });
+        }
+
+        if owner == Owner::Fill {
+            self.in_fill += cnt;
+        }
+
+        cnt
+    }
+
+    /// Hand frames from the start of `descs` to `owner`, stopping at
+    /// the first which isn't held by the application or whose `len`
+    /// runs past the end of the frame. Returns the number handed over,
+    /// so a frame repeated in `descs` only counts the first time.
+    fn claim(&mut self, descs: &[XskRsDesc], owner: Owner) -> usize {
+        let mut cnt = 0;
+
+        for d in descs {
+            let held = self.index(d.addr).filter(|&i| {
+                let frame_end = ((i + 1) * self.frame_size) as u64;
+                self.owners[i] == Owner::App && d.addr + u64::from(d.len) <= frame_end
+            });
+
+            if held.is_none() {
+                break;
+            }
+
+            self.set(d.addr, owner);
+            cnt += 1;
+        }
+
+        cnt
+    }
+
+    fn set(&mut self, addr: u64, owner: Owner) {
+        if let Some(frame) = self.index(addr) {
+            let prev = mem::replace(&mut self.owners[frame], owner);
+
+            if prev == Owner::Fill {
+                self.in_fill -= 1;
+            }
+
+            if owner == Owner::Pool {
+                self.pool.push(frame as u32);
+            }
+        }
+    }
+}
+
+/// An AF_XDP socket with its own UMEM, for use from C. See the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct XskRsSocket {
+    xsk: Xsk,
+    frames: Frames,
+    fill_target: usize,
+    scratch: Vec<FrameDesc>,
+}
+
+impl XskRsSocket {
+    fn new(xsk: Xsk, fill_target: usize) -> Self {
+        let frames = Frames::new(
+            xsk.umem.frame_size(),
+            xsk.descs.iter().map(FrameDesc::addr).collect(),
+        );
+
+        let scratch = xsk.descs.clone();
+
+        let mut sock = Self {
+            xsk,
+            frames,
+            fill_target,
+            scratch,
+        };
+
+        sock.top_up_fill_queue();
+
+        sock
+    }
+
+    fn top_up_fill_queue(&mut self) {
+        let want = self.fill_target.saturating_sub(self.frames.in_fill);
+
+        if want == 0 {
+            return;
+        }
+
+        let scratch = &mut self.scratch;
+        scratch.clear();
+
+        self.frames
+            .take(want, Owner::Fill, |desc| scratch.push(desc));
+
+        // SAFETY: the frames came from this socket's UMEM, and never
+        // more than the fill ring holds are outstanding on it.
+        let cnt = unsafe { self.xsk.fq.produce(&self.scratch) };
+        debug_assert_eq!(cnt, self.scratch.len());
+    }
+
+    fn reap_completions(&mut self) {
+        self.scratch
+            .resize(self.frames.owners.len(), FrameDesc::default());
+
+        // SAFETY: the frames came from this socket's UMEM.
+        let cnt = unsafe { self.xsk.cq.consume(&mut self.scratch) };
+
+        for desc in &self.scratch[..cnt] {
+            self.frames.set(desc.addr as u64, Owner::Pool);
+        }
+    }
+
+    pub(crate) fn create(
+        if_name: &Interface,
+        queue_id: u32,
+        frame_count: NonZeroU32,
+    ) -> Result<Self, XskBuildError> {
+        let umem_config = UmemConfig::default();
+        let fill_target =
+            (frame_count.get() as usize / 2).min(umem_config.fill_queue_size().get() as usize);
+
+        let xsk = Xsk::build(
+            if_name,
+            queue_id,
+            umem_config,
+            SocketConfig::default(),
+            frame_count,
+        )?;
+
+        Ok(Self::new(xsk, fill_target))
+    }
+
+    pub(crate) fn fd(&self) -> c_int {
+        self.xsk.rx_q.fd().as_raw_fd()
+    }
+
+    pub(crate) fn rx_burst(&mut self, descs: &mut [XskRsDesc]) -> usize {
+        self.top_up_fill_queue();
+
+        let nb = descs.len().min(self.frames.owners.len());
+        self.scratch.resize(nb, FrameDesc::default());
+
+        // SAFETY: only frames of this socket's UMEM are on its rings.
+        let cnt = unsafe { self.xsk.rx_q.consume(&mut self.scratch) };
+
+        for (desc, out) in self.scratch[..cnt].iter().zip(descs.iter_mut()) {
+            self.frames.set(desc.addr as u64, Owner::App);
+
+            *out = XskRsDesc {
+                addr: desc.addr as u64,
+                len: desc.lengths.data as u32,
+                options: desc.options,
+            };
+        }
+
+        cnt
+    }
+
+    pub(crate) fn alloc(&mut self, descs: &mut [XskRsDesc]) -> usize {
+        self.reap_completions();
+
+        let mut out = descs.iter_mut();
+
+        self.frames.take(out.len(), Owner::App, |desc| {
+            // `take` hands out no more frames than were asked for.
+            if let Some(out) = out.next() {
+                *out = XskRsDesc {
+                    addr: desc.addr as u64,
+                    len: 0,
+                    options: 0,
+                };
+            }
+        })
+    }
+
+    pub(crate) fn tx_burst(&mut self, descs: &[XskRsDesc]) -> usize {
+        let valid = &descs[..self.frames.claim(descs, Owner::Tx)];
+        let mtu = self.xsk.umem.frame_mtu();
+
+        let frames = valid.iter().map(|d| FrameDesc {
+            addr: d.addr as usize,
+            options: d.options,
+            lengths: SegmentLengths {
+                headroom: 0,
+                data: (d.len as usize).min(mtu),
+                meta: 0,
+            },
+        });
+
+        // SAFETY: every frame was claimed from the caller, so belongs
+        // to this socket's UMEM, isn't on any ring and appears once.
+        let cnt = unsafe { self.xsk.tx_q.extend(frames) };
+
+        // Those which didn't fit on the ring stay with the caller.
+        for desc in &valid[cnt..] {
+            self.frames.set(desc.addr, Owner::App);
+        }
+
+        // A failed wakeup leaves the frames on the ring for the next one.
+        let _ = self.xsk.tx_q.commit_wakeup();
+
+        cnt
+    }
+
+    pub(crate) fn recycle(&mut self, descs: &[XskRsDesc]) -> usize {
+        self.frames.claim(descs, Owner::Pool)
+    }
+
+    /// The packet data of the frame at `addr` onwards, to the end of
+    /// the frame, if the caller holds it.
+    pub(crate) fn frame_data(&mut self, addr: u64) -> Option<&mut [u8]> {
+        let frame = self
+            .frames
+            .index(addr)
+            .filter(|&f| self.frames.owners[f] == Owner::App)?;
+
+        let frame_end = (frame + 1) * self.frames.frame_size;
+
+        // SAFETY: `addr` lies within the frame, which lies within the
+        // UMEM, and the frame isn't on any ring so nothing else is
+        // accessing it.
+        Some(unsafe {
+            slice::from_raw_parts_mut(
+                (self.xsk.umem.mem_ptr() as *mut u8).add(addr as usize),
+                frame_end - addr as usize,
+            )
+        })
+    }
+}
+
+pub(crate) fn errno(err: &XskBuildError) -> c_int {
+    let io_err = match err {
+        XskBuildError::Umem(e) => e.source(),
+        XskBuildError::Socket(e) => e.source(),
+        XskBuildError::MissingQueues => None,
+    };
+
+    io_err
+        .and_then(|e| e.downcast_ref::<io::Error>())
+        .and_then(io::Error::raw_os_error)
+        .unwrap_or(EIO)
+}
+
+/// Create a UMEM of `frame_count` frames and bind a socket to it on
+/// `queue_id` of interface `if_name`, storing the handle in `*out`.
+///
+/// Half the frames, up to the fill ring size, are kept on the fill
+/// queue for receiving and the rest pooled for [`xsk_rs_alloc`].
+/// Returns zero on success.
+///
+/// # Safety
+///
+/// `if_name` must be a valid NUL terminated string and `out` valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn xsk_rs_socket_create(
+    if_name: *const c_char,
+    queue_id: u32,
+    frame_count: u32,
+    out: *mut *mut XskRsSocket,
+) -> c_int {
+    if if_name.is_null() || out.is_null() {
+        return -EINVAL;
+    }
+
+    let frame_count = match NonZeroU32::new(frame_count) {
+        Some(count) => count,
+        None => return -EINVAL,
+    };
+
+    // SAFETY: guaranteed by this function's contract.
+    let if_name = match Interface::try_from(unsafe { CStr::from_ptr(if_name) }.to_bytes()) {
+        Ok(if_name) => if_name,
+        Err(_) => return -EINVAL,
+    };
+
+    let sock = match XskRsSocket::create(&if_name, queue_id, frame_count) {
+        Ok(sock) => Box::new(sock),
+        Err(e) => return -errno(&e),
+    };
+
+    // SAFETY: guaranteed by this function's contract.
+    unsafe { *out = Box::into_raw(sock) };
+
+    0
+}
+
+/// Close the socket and free its UMEM. Any frames still held become
+/// invalid.
+///
+/// # Safety
+///
+/// `sock` must have come from [`xsk_rs_socket_create`] and not been
+/// destroyed already, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn xsk_rs_socket_destroy(sock: *mut XskRsSocket) {
+    if !sock.is_null() {
+        // SAFETY: guaranteed by this function's contract.
+        drop(unsafe { Box::from_raw(sock) });
+    }
+}
+
+/// The socket's file descriptor, for adding to an event loop.
+///
+/// # Safety
+///
+/// `sock` must be a live handle from [`xsk_rs_socket_create`].
+#[no_mangle]
+pub unsafe extern "C" fn xsk_rs_socket_fd(sock: *const XskRsSocket) -> c_int {
+    // SAFETY: guaranteed by this function's contract.
+    unsafe { &*sock }.fd()
+}
+
+/// Receive up to `nb` frames into `descs`, returning the number
+/// received. The frames are then held by the caller until passed to
+/// [`xsk_rs_recycle`] or [`xsk_rs_tx_burst`].
+///
+/// Also tops up the fill queue from the pool.
+///
+/// # Safety
+///
+/// `sock` must be a live handle from [`xsk_rs_socket_create`] and
+/// `descs` valid for `nb` writes.
+#[no_mangle]
+pub unsafe extern "C" fn xsk_rs_rx_burst(
+    sock: *mut XskRsSocket,
+    descs: *mut XskRsDesc,
+    nb: u32,
+) -> u32 {
+    // SAFETY: guaranteed by this function's contract.
+    let (sock, descs) = unsafe { (&mut *sock, slice::from_raw_parts_mut(descs, nb as usize)) };
+
+    sock.rx_burst(descs) as u32
+}
+
+/// Take up to `nb` free frames from the pool for transmitting,
+/// writing them to `descs` and returning the number taken. Frames the
+/// kernel has finished sending are returned to the pool first.
+///
+/// # Safety
+///
+/// `sock` must be a live handle from [`xsk_rs_socket_create`] and
+/// `descs` valid for `nb` writes.
+#[no_mangle]
+pub unsafe extern "C" fn xsk_rs_alloc(
+    sock: *mut XskRsSocket,
+    descs: *mut XskRsDesc,
+    nb: u32,
+) -> u32 {
+    // SAFETY: guaranteed by this function's contract.
+    let (sock, descs) = unsafe { (&mut *sock, slice::from_raw_parts_mut(descs, nb as usize)) };
+
+    sock.alloc(descs) as u32
+}
+
+/// Submit frames for transmission, returning the number submitted.
+///
+/// Frames are taken from the start of `descs` until the ring is full
+/// or a frame not held by the caller is met, counting one already
+/// taken earlier in `descs` or whose `len` runs past the end of the
+/// frame as not held. Each is sent with the descriptor's `len` and
+/// `options`.
+///
+/// # Safety
+///
+/// `sock` must be a live handle from [`xsk_rs_socket_create`] and
+/// `descs` valid for `nb` reads.
+#[no_mangle]
+pub unsafe extern "C" fn xsk_rs_tx_burst(
+    sock: *mut XskRsSocket,
+    descs: *const XskRsDesc,
+    nb: u32,
+) -> u32 {
+    // SAFETY: guaranteed by this function's contract.
+    let (sock, descs) = unsafe { (&mut *sock, slice::from_raw_parts(descs, nb as usize)) };
+
+    sock.tx_burst(descs) as u32
+}
+
+/// Hand frames back to the pool, returning the number returned.
+/// Frames are taken from the start of `descs` until one not held by
+/// the caller is met, as for [`xsk_rs_tx_burst`].
+///
+/// # Safety
+///
+/// `sock` must be a live handle from [`xsk_rs_socket_create`] and
+/// `descs` valid for `nb` reads.
+#[no_mangle]
+pub unsafe extern "C" fn xsk_rs_recycle(
+    sock: *mut XskRsSocket,
+    descs: *const XskRsDesc,
+    nb: u32,
+) -> u32 {
+    // SAFETY: guaranteed by this function's contract.
+    let (sock, descs) = unsafe { (&mut *sock, slice::from_raw_parts(descs, nb as usize)) };
+
+    sock.recycle(descs) as u32
+}
+
+/// A pointer to the packet data of the frame at `addr`, storing the
+/// number of bytes available from there in `*cap`. Null if the caller
+/// doesn't hold the frame.
+///
+/// # Safety
+///
+/// `sock` must be a live handle from [`xsk_rs_socket_create`] and
+/// `cap` valid for writes. The pointer is only valid until the frame
+/// is passed to [`xsk_rs_tx_burst`] or [`xsk_rs_recycle`].
+#[no_mangle]
+pub unsafe extern "C" fn xsk_rs_frame_data(
+    sock: *mut XskRsSocket,
+    addr: u64,
+    cap: *mut u32,
+) -> *mut u8 {
+    // SAFETY: guaranteed by this function's contract.
+    let sock = unsafe { &mut *sock };
+
+    match sock.frame_data(addr) {
+        Some(data) => {
+            // SAFETY: guaranteed by this function's contract.
+            unsafe { *cap = data.len() as u32 };
+            data.as_mut_ptr()
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::umem::frame::fixtures::{desc, FRAME_SIZE, HEADROOM};
+
+    fn app_desc(frames: &mut Frames) -> XskRsDesc {
+        let mut out = None;
+        frames.take(1, Owner::App, |d| out = Some(d));
+
+        XskRsDesc {
+            addr: out.unwrap().addr() as u64,
+            ..XskRsDesc::default()
+        }
+    }
+
+    #[test]
+    fn only_frames_held_by_the_app_are_accepted_back() {
+        let mut frames = Frames::new(FRAME_SIZE, (0..4).map(|i| desc(i).addr()).collect());
+
+        assert_eq!(frames.take(2, Owner::Fill, |_| ()), 2);
+        assert_eq!(frames.in_fill, 2);
+
+        let a = app_desc(&mut frames);
+        let b = app_desc(&mut frames);
+        assert_eq!(frames.pool.len(), 0);
+
+        let foreign = XskRsDesc {
+            addr: 4 * FRAME_SIZE as u64,
+            ..XskRsDesc::default()
+        };
+        let in_fill = XskRsDesc {
+            addr: HEADROOM as u64,
+            ..XskRsDesc::default()
+        };
+
+        assert_eq!(frames.claim(&[in_fill, a], Owner::Pool), 0);
+        assert_eq!(frames.claim(&[a, a, b], Owner::Tx), 1);
+        assert_eq!(frames.owners[frames.index(a.addr).unwrap()], Owner::Tx);
+
+        // Once handed over a frame can't be handed back again.
+        assert_eq!(frames.claim(&[a], Owner::Pool), 0);
+
+        let too_long = XskRsDesc {
+            len: (FRAME_SIZE - HEADROOM + 1) as u32,
+            ..b
+        };
+        assert_eq!(frames.claim(&[too_long, b, foreign], Owner::Pool), 0);
+        assert_eq!(frames.claim(&[b, foreign], Owner::Pool), 1);
+        assert_eq!(frames.pool.len(), 1);
+
+        // Received frames arrive at an offset into the frame.
+        frames.set(desc(0).addr() as u64 + 42, Owner::App);
+        assert_eq!(frames.in_fill, 1);
+    }
+}
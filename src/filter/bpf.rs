@@ -0,0 +1,514 @@
+//! The `bpf(2)` calls for creating an XSKMAP, loading a compiled
+//! [`Program`] and attaching it to an interface.
+//!
+//! These go straight to the syscall rather than through libbpf,
+//! since there's no object file to open and only a handful of
+//! commands are needed.
+
+use libc::{c_long, c_void};
+use std::{
+    borrow::Borrow,
+    error::Error,
+    ffi::CString,
+    fmt, io, mem,
+    os::unix::prelude::{AsRawFd, OsStrExt, RawFd},
+    path::Path,
+};
+
+use crate::config::{Interface, XdpFlags};
+
+use super::{Insn, Program};
+
+const BPF_MAP_CREATE: c_long = 0;
+const BPF_MAP_UPDATE_ELEM: c_long = 2;
+const BPF_MAP_DELETE_ELEM: c_long = 3;
+const BPF_PROG_LOAD: c_long = 5;
+const BPF_OBJ_GET: c_long = 7;
+const BPF_OBJ_GET_INFO_BY_FD: c_long = 15;
+const BPF_LINK_CREATE: c_long = 28;
+
+const BPF_MAP_TYPE_XSKMAP: u32 = 17;
+const BPF_PROG_TYPE_XDP: u32 = 6;
+const BPF_XDP: u32 = 37;
+
+const PROG_NAME: &[u8] = b"xsk_rs_filter";
+const LICENSE: &[u8] = b"Dual MIT/GPL\0";
+
+/// Enough for the verifier's account of why a filter was rejected.
+const LOG_SIZE: usize = 64 * 1024;
+
+#[repr(C)]
+#[derive(Default)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct MapElemAttr {
+    map_fd: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ObjGetAttr {
+    pathname: u64,
+    bpf_fd: u32,
+    file_flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct InfoByFdAttr {
+    bpf_fd: u32,
+    info_len: u32,
+    info: u64,
+}
+
+/// The start of the kernel's `bpf_map_info`, which is all that's
+/// needed. The kernel fills in no more than `info_len`.
+#[repr(C)]
+#[derive(Default)]
+struct MapInfo {
+    map_type: u32,
+    id: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct LinkCreateAttr {
+    prog_fd: u32,
+    target_ifindex: u32,
+    attach_type: u32,
+    flags: u32,
+}
+
+/// Issue `cmd`, returning the fd or value the kernel hands back.
+fn sys_bpf<T>(cmd: c_long, attr: &mut T) -> io::Result<RawFd> {
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *mut T as *mut c_void,
+            mem::size_of::<T>(),
+        )
+    };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(ret as RawFd)
+}
+
+struct OwnedFd(RawFd);
+
+impl Drop for OwnedFd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// An XSKMAP, the map a compiled filter redirects matching frames
+/// through, keyed by rx queue index.
+///
+/// A queue's socket must be [`insert`](Self::insert)ed before
+/// anything arriving on that queue can reach it. Frames matching the
+/// filter on a queue with no socket are passed to the kernel stack.
+pub struct XskMap {
+    fd: OwnedFd,
+    max_entries: u32,
+}
+
+impl XskMap {
+    /// Create a map with room for queues `0..max_entries`.
+    pub fn new(max_entries: u32) -> io::Result<Self> {
+        let mut attr = MapCreateAttr {
+            map_type: BPF_MAP_TYPE_XSKMAP,
+            key_size: mem::size_of::<u32>() as u32,
+            value_size: mem::size_of::<u32>() as u32,
+            max_entries,
+            ..Default::default()
+        };
+
+        let fd = sys_bpf(BPF_MAP_CREATE, &mut attr)?;
+
+        Ok(Self {
+            fd: OwnedFd(fd),
+            max_entries,
+        })
+    }
+
+    /// Open the XSKMAP pinned at `path` on a BPF filesystem, e.g. one
+    /// belonging to a program loaded by another process, so sockets
+    /// can be [`insert`](Self::insert)ed into it.
+    ///
+    /// # Errors
+    ///
+    /// If nothing is pinned at `path`, or with
+    /// [`io::ErrorKind::InvalidInput`] if what is isn't an XSKMAP.
+    pub fn from_pinned(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let mut attr = ObjGetAttr {
+            pathname: path.as_ptr() as u64,
+            ..Default::default()
+        };
+
+        let fd = OwnedFd(sys_bpf(BPF_OBJ_GET, &mut attr)?);
+
+        let mut info = MapInfo::default();
+
+        let mut attr = InfoByFdAttr {
+            bpf_fd: fd.0 as u32,
+            info_len: mem::size_of::<MapInfo>() as u32,
+            info: &mut info as *mut MapInfo as u64,
+        };
+
+        sys_bpf(BPF_OBJ_GET_INFO_BY_FD, &mut attr)?;
+
+        if info.map_type != BPF_MAP_TYPE_XSKMAP {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pinned map is not an XSKMAP",
+            ));
+        }
+
+        Ok(Self {
+            fd,
+            max_entries: info.max_entries,
+        })
+    }
+
+    /// The number of queues the map has room for.
+    #[inline]
+    pub fn max_entries(&self) -> u32 {
+        self.max_entries
+    }
+
+    /// Direct frames arriving on `queue_id` to `socket`, replacing
+    /// any socket already there. Takes the fd of the socket's
+    /// [`RxQueue`](crate::RxQueue) or [`TxQueue`](crate::TxQueue).
+    pub fn insert(&self, queue_id: u32, socket: &impl AsRawFd) -> io::Result<()> {
+        let key = queue_id;
+        let value = socket.as_raw_fd() as u32;
+
+        let mut attr = MapElemAttr {
+            map_fd: self.fd.0 as u32,
+            key: &key as *const u32 as u64,
+            value: &value as *const u32 as u64,
+            ..Default::default()
+        };
+
+        sys_bpf(BPF_MAP_UPDATE_ELEM, &mut attr).map(|_| ())
+    }
+
+    /// Remove the socket for `queue_id`, so that queue's matching
+    /// frames go to the kernel stack instead.
+    pub fn remove(&self, queue_id: u32) -> io::Result<()> {
+        let key = queue_id;
+
+        let mut attr = MapElemAttr {
+            map_fd: self.fd.0 as u32,
+            key: &key as *const u32 as u64,
+            ..Default::default()
+        };
+
+        sys_bpf(BPF_MAP_DELETE_ELEM, &mut attr).map(|_| ())
+    }
+}
+
+impl fmt::Debug for XskMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("XskMap")
+            .field("fd", &self.fd.0)
+            .field("max_entries", &self.max_entries)
+            .finish()
+    }
+}
+
+impl AsRawFd for XskMap {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.0
+    }
+}
+
+impl Program {
+    /// Load the program into the kernel, redirecting through `map`.
+    ///
+    /// The loaded program holds its own reference to the map, but
+    /// `map` must be kept to add or remove sockets.
+    pub fn load(&self, map: &XskMap) -> Result<LoadedProgram, ProgramLoadError> {
+        let mut insns = self.insns.clone();
+        insns[self.map_insn].set_imm(map.fd.0);
+
+        // Try without the log first, since asking for it slows the
+        // verifier down, and only load again to explain a rejection.
+        match load(&insns, None) {
+            Ok(fd) => Ok(LoadedProgram { fd: OwnedFd(fd) }),
+            Err(err) => {
+                let mut log = vec![0u8; LOG_SIZE];
+
+                if let Ok(fd) = load(&insns, Some(&mut log)) {
+                    return Ok(LoadedProgram { fd: OwnedFd(fd) });
+                }
+
+                let end = log.iter().position(|b| *b == 0).unwrap_or(log.len());
+                let log = String::from_utf8_lossy(&log[..end]).into_owned();
+
+                Err(ProgramLoadError { err, log })
+            }
+        }
+    }
+}
+
+fn load(insns: &[Insn], log: Option<&mut [u8]>) -> io::Result<RawFd> {
+    let mut prog_name = [0; 16];
+    prog_name[..PROG_NAME.len()].copy_from_slice(PROG_NAME);
+
+    let mut attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_XDP,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: LICENSE.as_ptr() as u64,
+        prog_name,
+        ..Default::default()
+    };
+
+    if let Some(log) = log {
+        attr.log_level = 1;
+        attr.log_size = log.len() as u32;
+        attr.log_buf = log.as_mut_ptr() as u64;
+    }
+
+    sys_bpf(BPF_PROG_LOAD, &mut attr)
+}
+
+/// A filter program loaded into the kernel, not yet attached to any
+/// interface.
+pub struct LoadedProgram {
+    fd: OwnedFd,
+}
+
+impl LoadedProgram {
+    /// Attach the program to `if_name`, in the mode given by `flags`.
+    ///
+    /// The returned link keeps it attached until dropped, which also
+    /// means the program is detached if the process exits. It fails
+    /// with `EBUSY` if another program, e.g. libxdp's default one
+    /// from a socket created without
+    /// [`XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD`], is already attached.
+    ///
+    /// [`XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD`]: crate::config::LibxdpFlags::XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD
+    pub fn attach(&self, if_name: &Interface, flags: XdpFlags) -> io::Result<XdpLink> {
+        let ifindex = unsafe { libc::if_nametoindex(if_name.as_cstr().as_ptr()) };
+
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut attr = LinkCreateAttr {
+            prog_fd: self.fd.0 as u32,
+            target_ifindex: ifindex,
+            attach_type: BPF_XDP,
+            flags: flags.bits(),
+        };
+
+        let fd = sys_bpf(BPF_LINK_CREATE, &mut attr)?;
+
+        Ok(XdpLink {
+            fd: OwnedFd(fd),
+            ifindex,
+        })
+    }
+}
+
+impl fmt::Debug for LoadedProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoadedProgram")
+            .field("fd", &self.fd.0)
+            .finish()
+    }
+}
+
+impl AsRawFd for LoadedProgram {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.0
+    }
+}
+
+/// A filter program attached to an interface. Dropping it detaches
+/// the program.
+pub struct XdpLink {
+    fd: OwnedFd,
+    ifindex: u32,
+}
+
+impl XdpLink {
+    /// The index of the interface the program is attached to.
+    #[inline]
+    pub fn ifindex(&self) -> u32 {
+        self.ifindex
+    }
+}
+
+impl fmt::Debug for XdpLink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("XdpLink")
+            .field("fd", &self.fd.0)
+            .field("ifindex", &self.ifindex)
+            .finish()
+    }
+}
+
+/// A program redirecting every frame on an interface to the socket
+/// bound to its rx queue, attached along with its own [`XskMap`].
+///
+/// The equivalent of libxdp's default program, without going through
+/// its loader or holding a dispatcher. Sockets must be created with
+/// [`XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD`] and then
+/// [`insert`](Self::insert)ed. Frames on queues without a socket are
+/// passed to the kernel stack. Dropping it detaches the program.
+///
+/// [`XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD`]: crate::config::LibxdpFlags::XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD
+#[derive(Debug)]
+pub struct XskRedirect {
+    // `link` must appear before `map` so the program is detached
+    // before its map is closed.
+    link: XdpLink,
+    map: XskMap,
+}
+
+impl XskRedirect {
+    /// Load the program and attach it to `if_name` in the mode given
+    /// by `flags`, with room in its map for queues `0..max_queues`.
+    pub fn attach(
+        if_name: &Interface,
+        flags: XdpFlags,
+        max_queues: u32,
+    ) -> Result<Self, RedirectAttachError> {
+        let map = XskMap::new(max_queues).map_err(|err| RedirectAttachError {
+            reason: "failed to create XSKMAP",
+            err,
+        })?;
+
+        let prog = super::Filter::all()
+            .compile()
+            .expect("match-all filter fits in a program")
+            .load(&map)
+            .map_err(|e| RedirectAttachError {
+                reason: "failed to load redirect program",
+                err: e.err,
+            })?;
+
+        let link = prog
+            .attach(if_name, flags)
+            .map_err(|err| RedirectAttachError {
+                reason: "failed to attach redirect program",
+                err,
+            })?;
+
+        Ok(Self { link, map })
+    }
+
+    /// Direct frames arriving on `queue_id` to `socket`, see
+    /// [`XskMap::insert`].
+    #[inline]
+    pub fn insert(&self, queue_id: u32, socket: &impl AsRawFd) -> io::Result<()> {
+        self.map.insert(queue_id, socket)
+    }
+
+    /// Pass frames arriving on `queue_id` to the kernel stack again,
+    /// see [`XskMap::remove`].
+    #[inline]
+    pub fn remove(&self, queue_id: u32) -> io::Result<()> {
+        self.map.remove(queue_id)
+    }
+
+    /// The program's map.
+    #[inline]
+    pub fn map(&self) -> &XskMap {
+        &self.map
+    }
+
+    /// The link keeping the program attached.
+    #[inline]
+    pub fn link(&self) -> &XdpLink {
+        &self.link
+    }
+}
+
+/// Error attaching an [`XskRedirect`].
+#[derive(Debug)]
+pub struct RedirectAttachError {
+    reason: &'static str,
+    err: io::Error,
+}
+
+impl fmt::Display for RedirectAttachError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for RedirectAttachError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.err.borrow())
+    }
+}
+
+/// Error loading a compiled filter.
+#[derive(Debug)]
+pub struct ProgramLoadError {
+    err: io::Error,
+    log: String,
+}
+
+impl ProgramLoadError {
+    /// The verifier's log, explaining why the program was rejected.
+    /// Empty if the failure had nothing to do with verification,
+    /// e.g. missing privileges.
+    pub fn verifier_log(&self) -> &str {
+        &self.log
+    }
+}
+
+impl fmt::Display for ProgramLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to load filter program")
+    }
+}
+
+impl Error for ProgramLoadError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.err.borrow())
+    }
+}
@@ -0,0 +1,287 @@
+//! eBPF instruction encoding, and an assembler resolving jumps to
+//! labels.
+
+use std::{convert::TryFrom, fmt};
+
+// Instruction classes.
+pub(super) const LD: u8 = 0x00;
+pub(super) const LDX: u8 = 0x01;
+pub(super) const ST: u8 = 0x02;
+pub(super) const STX: u8 = 0x03;
+pub(super) const ALU: u8 = 0x04;
+pub(super) const JMP: u8 = 0x05;
+pub(super) const JMP32: u8 = 0x06;
+pub(super) const ALU64: u8 = 0x07;
+
+// Load and store sizes and modes.
+pub(super) const W: u8 = 0x00;
+pub(super) const H: u8 = 0x08;
+pub(super) const B: u8 = 0x10;
+pub(super) const DW: u8 = 0x18;
+pub(super) const IMM: u8 = 0x00;
+pub(super) const MEM: u8 = 0x60;
+
+// Operand source.
+pub(super) const K: u8 = 0x00;
+pub(super) const X: u8 = 0x08;
+
+// ALU operations.
+pub(super) const ADD: u8 = 0x00;
+pub(super) const AND: u8 = 0x50;
+pub(super) const LSH: u8 = 0x60;
+pub(super) const MOV: u8 = 0xb0;
+pub(super) const END: u8 = 0xd0;
+
+// Jump operations.
+pub(super) const JA: u8 = 0x00;
+pub(super) const JEQ: u8 = 0x10;
+pub(super) const JGT: u8 = 0x20;
+pub(super) const JNE: u8 = 0x50;
+pub(super) const JLT: u8 = 0xa0;
+pub(super) const CALL: u8 = 0x80;
+pub(super) const EXIT: u8 = 0x90;
+
+/// `src` of a 64 bit immediate load whose immediate is a map fd.
+pub(super) const PSEUDO_MAP_FD: u8 = 1;
+
+/// One eBPF instruction, laid out as the kernel's `struct bpf_insn`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Insn {
+    code: u8,
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+impl Insn {
+    pub(super) const fn new(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Self {
+        Self {
+            code,
+            regs: (src << 4) | (dst & 0x0f),
+            off,
+            imm,
+        }
+    }
+
+    /// The opcode.
+    #[inline]
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+
+    /// The destination register.
+    #[inline]
+    pub fn dst(&self) -> u8 {
+        self.regs & 0x0f
+    }
+
+    /// The source register.
+    #[inline]
+    pub fn src(&self) -> u8 {
+        self.regs >> 4
+    }
+
+    /// The signed offset, a jump distance or memory displacement.
+    #[inline]
+    pub fn off(&self) -> i16 {
+        self.off
+    }
+
+    /// The signed immediate.
+    #[inline]
+    pub fn imm(&self) -> i32 {
+        self.imm
+    }
+
+    pub(super) fn set_imm(&mut self, imm: i32) {
+        self.imm = imm;
+    }
+
+    /// The instruction as the kernel reads it, on a little endian
+    /// host.
+    #[inline]
+    pub fn to_le_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        bytes[0] = self.code;
+        bytes[1] = self.regs;
+        bytes[2..4].copy_from_slice(&self.off.to_le_bytes());
+        bytes[4..].copy_from_slice(&self.imm.to_le_bytes());
+        bytes
+    }
+}
+
+impl fmt::Debug for Insn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Insn {{ code: {:#04x}, dst: r{}, src: r{}, off: {}, imm: {} }}",
+            self.code,
+            self.dst(),
+            self.src(),
+            self.off,
+            self.imm
+        )
+    }
+}
+
+/// A jump target, bound to an instruction index with
+/// [`Asm::bind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Label(usize);
+
+/// Builds up a program, patching jump offsets once every label has
+/// been bound.
+#[derive(Debug, Default)]
+pub(super) struct Asm {
+    insns: Vec<Insn>,
+    labels: Vec<Option<usize>>,
+    fixups: Vec<(usize, Label)>,
+}
+
+impl Asm {
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Point `label` at the next instruction emitted.
+    pub fn bind(&mut self, label: Label) {
+        debug_assert!(self.labels[label.0].is_none(), "label bound twice");
+        self.labels[label.0] = Some(self.insns.len());
+    }
+
+    pub fn len(&self) -> usize {
+        self.insns.len()
+    }
+
+    pub fn emit(&mut self, insn: Insn) {
+        self.insns.push(insn);
+    }
+
+    /// `dst = imm`.
+    pub fn mov_imm(&mut self, dst: u8, imm: i32) {
+        self.emit(Insn::new(ALU64 | MOV | K, dst, 0, 0, imm));
+    }
+
+    /// `dst = src`.
+    pub fn mov_reg(&mut self, dst: u8, src: u8) {
+        self.emit(Insn::new(ALU64 | MOV | X, dst, src, 0, 0));
+    }
+
+    /// `dst op= imm`, in 64 bits.
+    pub fn alu64_imm(&mut self, op: u8, dst: u8, imm: i32) {
+        self.emit(Insn::new(ALU64 | op | K, dst, 0, 0, imm));
+    }
+
+    /// `dst op= src`, in 64 bits.
+    pub fn alu64_reg(&mut self, op: u8, dst: u8, src: u8) {
+        self.emit(Insn::new(ALU64 | op | X, dst, src, 0, 0));
+    }
+
+    /// `dst op= imm`, in 32 bits with the upper half zeroed.
+    pub fn alu32_imm(&mut self, op: u8, dst: u8, imm: i32) {
+        self.emit(Insn::new(ALU | op | K, dst, 0, 0, imm));
+    }
+
+    /// Convert the low `bits` of `dst` from network to host order.
+    pub fn be(&mut self, dst: u8, bits: i32) {
+        self.emit(Insn::new(ALU | END | X, dst, 0, 0, bits));
+    }
+
+    /// `dst = *(size *)(src + off)`.
+    pub fn ldx(&mut self, size: u8, dst: u8, src: u8, off: i16) {
+        self.emit(Insn::new(LDX | MEM | size, dst, src, off, 0));
+    }
+
+    /// `*(size *)(dst + off) = imm`.
+    pub fn st(&mut self, size: u8, dst: u8, off: i16, imm: i32) {
+        self.emit(Insn::new(ST | MEM | size, dst, 0, off, imm));
+    }
+
+    /// `*(size *)(dst + off) = src`.
+    pub fn stx(&mut self, size: u8, dst: u8, off: i16, src: u8) {
+        self.emit(Insn::new(STX | MEM | size, dst, src, off, 0));
+    }
+
+    /// Load the map with fd `fd` into `dst`, returning the index of
+    /// the instruction holding the fd.
+    pub fn ld_map_fd(&mut self, dst: u8, fd: i32) -> usize {
+        let at = self.insns.len();
+        self.emit(Insn::new(LD | IMM | DW, dst, PSEUDO_MAP_FD, 0, fd));
+        self.emit(Insn::new(0, 0, 0, 0, 0));
+        at
+    }
+
+    pub fn call(&mut self, helper: i32) {
+        self.emit(Insn::new(JMP | CALL, 0, 0, 0, helper));
+    }
+
+    pub fn exit(&mut self) {
+        self.emit(Insn::new(JMP | EXIT, 0, 0, 0, 0));
+    }
+
+    pub fn ja(&mut self, target: Label) {
+        self.jump(JMP | JA, 0, 0, 0, target);
+    }
+
+    /// `if dst op imm goto target`, comparing the low 32 bits.
+    pub fn jmp32_imm(&mut self, op: u8, dst: u8, imm: i32, target: Label) {
+        self.jump(JMP32 | op | K, dst, 0, imm, target);
+    }
+
+    /// `if dst op src goto target`, comparing all 64 bits.
+    pub fn jmp_reg(&mut self, op: u8, dst: u8, src: u8, target: Label) {
+        self.jump(JMP | op | X, dst, src, 0, target);
+    }
+
+    fn jump(&mut self, code: u8, dst: u8, src: u8, imm: i32, target: Label) {
+        self.fixups.push((self.insns.len(), target));
+        self.emit(Insn::new(code, dst, src, 0, imm));
+    }
+
+    /// Resolve every jump, returning [`None`] if one lands further
+    /// away than an offset can reach.
+    pub fn finish(mut self) -> Option<Vec<Insn>> {
+        for (at, label) in self.fixups {
+            let target = self.labels[label.0].expect("jump to unbound label");
+            let off = target as isize - (at as isize + 1);
+            self.insns[at].off = i16::try_from(off).ok()?;
+        }
+
+        Some(self.insns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jumps_are_relative_to_the_next_instruction() {
+        let mut asm = Asm::default();
+        let back = asm.label();
+        let fwd = asm.label();
+
+        asm.bind(back);
+        asm.mov_imm(0, 1);
+        asm.ja(fwd);
+        asm.jmp32_imm(JEQ, 0, 1, back);
+        asm.bind(fwd);
+        asm.exit();
+
+        let insns = asm.finish().unwrap();
+        assert_eq!(insns[1].off(), 1);
+        assert_eq!(insns[2].off(), -3);
+    }
+
+    #[test]
+    fn registers_and_fields_encode_as_struct_bpf_insn() {
+        let insn = Insn::new(LDX | MEM | H, 3, 6, 12, 0);
+
+        assert_eq!(insn.dst(), 3);
+        assert_eq!(insn.src(), 6);
+        assert_eq!(insn.to_le_bytes(), [0x69, 0x63, 12, 0, 0, 0, 0, 0]);
+        assert_eq!(std::mem::size_of::<Insn>(), 8);
+    }
+}
@@ -0,0 +1,153 @@
+//! Just enough of an eBPF interpreter to run compiled filters over
+//! test frames, for checking them against [`Filter::matches`].
+//!
+//! [`Filter::matches`]: super::Filter::matches
+
+use super::insn::*;
+
+const CTX: u64 = 0x1000_0000;
+const PKT: u64 = 0x2000_0000;
+const STACK: u64 = 0x3000_0000;
+const STACK_SIZE: u64 = 512;
+
+const XDP_PASS: u64 = 2;
+const XDP_REDIRECT: u64 = 4;
+
+/// Run `insns` over `frame` as received on `queue`, returning the
+/// queue it was redirected to, if any. Panics on anything the kernel
+/// verifier would reject, such as an out of bounds packet read.
+pub fn run(insns: &[Insn], frame: &[u8], queue: u32) -> Option<u32> {
+    let mut frame = frame.to_vec();
+    let mut ctx = [0u8; 24];
+    ctx[0..4].copy_from_slice(&(PKT as u32).to_le_bytes());
+    ctx[4..8].copy_from_slice(&((PKT + frame.len() as u64) as u32).to_le_bytes());
+    ctx[16..20].copy_from_slice(&queue.to_le_bytes());
+
+    let mut stack = [0u8; STACK_SIZE as usize];
+    let mut regs = [0u64; 11];
+    regs[1] = CTX;
+    regs[10] = STACK + STACK_SIZE;
+
+    let mut redirected = None;
+    let mut pc = 0;
+
+    loop {
+        let insn = insns[pc];
+        pc += 1;
+
+        let (dst, src) = (insn.dst() as usize, insn.src() as usize);
+        let imm = insn.imm() as i64 as u64;
+        let class = insn.code() & 0x07;
+
+        match class {
+            LD => {
+                assert_eq!(insn.src(), PSEUDO_MAP_FD);
+                regs[dst] = imm;
+                pc += 1;
+            }
+            LDX | ST | STX => {
+                let size = match insn.code() & 0x18 {
+                    W => 4,
+                    H => 2,
+                    B => 1,
+                    _ => 8,
+                };
+                let base = if class == LDX { regs[src] } else { regs[dst] };
+                let addr = base.wrapping_add(insn.off() as i64 as u64);
+
+                let mem: &mut [u8] = if (CTX..CTX + 24).contains(&addr) {
+                    assert_eq!(class, LDX, "ctx is read-only");
+                    &mut ctx[(addr - CTX) as usize..]
+                } else if (STACK..STACK + STACK_SIZE).contains(&addr) {
+                    &mut stack[(addr - STACK) as usize..]
+                } else {
+                    assert_eq!(class, LDX, "packet writes aren't expected");
+                    assert!(addr >= PKT, "read before the packet");
+                    // The verifier only allows reads it has seen
+                    // checked against `data_end`, so anything past the
+                    // frame is a missing bounds check.
+                    let at = (addr - PKT) as usize;
+                    assert!(at + size <= frame.len(), "unchecked packet read");
+                    &mut frame[at..]
+                };
+
+                if class == LDX {
+                    let mut bytes = [0u8; 8];
+                    bytes[..size].copy_from_slice(&mem[..size]);
+                    regs[dst] = u64::from_le_bytes(bytes);
+                } else {
+                    let val = if class == ST { imm } else { regs[src] };
+                    mem[..size].copy_from_slice(&val.to_le_bytes()[..size]);
+                }
+            }
+            ALU | ALU64 => {
+                let op = insn.code() & 0xf0;
+                let operand = if insn.code() & X != 0 && op != END {
+                    regs[src]
+                } else {
+                    imm
+                };
+                let val = regs[dst];
+
+                let res = match op {
+                    ADD => val.wrapping_add(operand),
+                    AND => val & operand,
+                    LSH => val << (operand & 63),
+                    MOV => operand,
+                    END => {
+                        assert!(insn.code() & X != 0, "only to_be is emitted");
+                        match insn.imm() {
+                            16 => (val as u16).swap_bytes() as u64,
+                            32 => (val as u32).swap_bytes() as u64,
+                            _ => val.swap_bytes(),
+                        }
+                    }
+                    _ => panic!("unexpected alu op {:#x}", op),
+                };
+
+                regs[dst] = if class == ALU { res as u32 as u64 } else { res };
+            }
+            JMP | JMP32 => {
+                let op = insn.code() & 0xf0;
+
+                match op {
+                    CALL => {
+                        assert_eq!(insn.imm(), 51, "only bpf_redirect_map is called");
+                        redirected = Some(regs[2] as u32);
+                        regs[0] = XDP_REDIRECT;
+                        continue;
+                    }
+                    EXIT => {
+                        return match regs[0] {
+                            XDP_REDIRECT => redirected,
+                            XDP_PASS => None,
+                            other => panic!("unexpected return code {}", other),
+                        };
+                    }
+                    _ => {}
+                }
+
+                let operand = if insn.code() & X != 0 { regs[src] } else { imm };
+                let (a, b) = if class == JMP32 {
+                    (regs[dst] as u32 as u64, operand as u32 as u64)
+                } else {
+                    (regs[dst], operand)
+                };
+
+                let taken = match op {
+                    JA => true,
+                    JEQ => a == b,
+                    JNE => a != b,
+                    JGT => a > b,
+                    JLT => a < b,
+                    _ => panic!("unexpected jump op {:#x}", op),
+                };
+
+                if taken {
+                    pc = (pc as isize + insn.off() as isize) as usize;
+                }
+            }
+            _ => panic!("unexpected class {:#x}", class),
+        }
+    }
+}
@@ -0,0 +1,884 @@
+//! Ingress steering filters, compiled to an XDP program that
+//! redirects matching frames to the AF_XDP sockets in an
+//! [`XskMap`] and passes everything else up the kernel stack.
+//!
+//! This replaces the default libxdp program, which redirects every
+//! frame arriving on a bound queue, so the steering policy can live
+//! next to the socket setup rather than in a separately built and
+//! maintained C program. A [`Filter`] is an expression over the VLAN
+//! id, ethertype, IP protocol, source and destination prefixes and
+//! transport ports, and [`Filter::compile`] turns it into a
+//! [`Program`] of raw eBPF instructions, with no clang or libbpf
+//! object files involved. [`Filter::matches`] evaluates the same
+//! expression in user space, for testing a policy or for filtering
+//! frames that arrived some other way.
+//!
+//! Headers are located as in the rest of the crate: up to two VLAN
+//! tags are skipped, the VLAN id matched is that of the outermost
+//! one, IPv6 extension headers are not walked, and IPv4 non-initial
+//! fragments carry no ports.
+//!
+//! Sockets must be created with
+//! [`XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD`] so libxdp doesn't attach
+//! its own program, and then inserted into the map by hand.
+//!
+//! ```no_run
+//! use std::{convert::TryInto, net::Ipv4Addr};
+//! use xsk_rs::{
+//!     config::{LibxdpFlags, SocketConfig, UmemConfig, XdpFlags},
+//!     filter::{Filter, XskMap},
+//!     Socket, Umem,
+//! };
+//!
+//! // DNS from 10.0.0.0/8 goes to the sockets, the rest to the kernel.
+//! let filter = Filter::udp()
+//!     .and(Filter::dst_port(53))
+//!     .and(Filter::src_net(Ipv4Addr::new(10, 0, 0, 0).into(), 8));
+//!
+//! let map = XskMap::new(64).unwrap();
+//! let prog = filter.compile().unwrap().load(&map).unwrap();
+//! let _link = prog
+//!     .attach(&"eth0".parse().unwrap(), XdpFlags::XDP_FLAGS_DRV_MODE)
+//!     .unwrap();
+//!
+//! let (umem, _descs) = Umem::new(UmemConfig::default(), 64.try_into().unwrap(), false).unwrap();
+//!
+//! let config = SocketConfig::builder()
+//!     .libxdp_flags(LibxdpFlags::XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD)
+//!     .build();
+//!
+//! let (_tx_q, rx_q, _fq_and_cq) =
+//!     unsafe { Socket::new(config, &umem, &"eth0".parse().unwrap(), 0) }.unwrap();
+//!
+//! map.insert(0, rx_q.fd()).unwrap();
+//! ```
+//!
+//! To redirect everything, as libxdp's default program does but
+//! without its loader, use [`XskRedirect`], which keeps the program
+//! and its map together.
+//!
+//! [`XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD`]: crate::config::LibxdpFlags::XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD
+
+mod bpf;
+pub use bpf::{LoadedProgram, ProgramLoadError, RedirectAttachError, XdpLink, XskMap, XskRedirect};
+
+mod insn;
+pub use insn::Insn;
+
+#[cfg(test)]
+mod interp;
+
+use std::{
+    error, fmt,
+    net::IpAddr,
+    ops::{Not, RangeInclusive},
+};
+
+use crate::packet::{
+    self, ETH_HLEN, ETH_P_8021AD, ETH_P_8021Q, ETH_P_IPV4, ETH_P_IPV6, IPPROTO_TCP, IPPROTO_UDP,
+    IPV4_MIN_HLEN, IPV6_HLEN, VLAN_HLEN,
+};
+
+use insn::{Asm, Label, ADD, AND, B, H, JEQ, JGT, JLT, JNE, LSH, W};
+
+/// A predicate over a frame's headers.
+///
+/// Built from the leaf constructors and combined with
+/// [`and`](Self::and), [`or`](Self::or) and `!`. A leaf on a header
+/// the frame doesn't have, e.g. a port on an ICMP packet, doesn't
+/// match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter(Expr);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Const(bool),
+    Range(Field, u32, u32),
+    Net(Dir, Net),
+    Not(Box<Expr>),
+    All(Vec<Expr>),
+    Any(Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Vlan,
+    Ethertype,
+    Protocol,
+    SrcPort,
+    DstPort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dir {
+    Src,
+    Dst,
+}
+
+/// An address prefix as the words the program compares, most
+/// significant first. IPv4 uses only the first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Net {
+    family: u32,
+    words: [u32; 4],
+    masks: [u32; 4],
+}
+
+impl Net {
+    fn new(addr: IpAddr, prefix_len: u8) -> Self {
+        let (family, bytes, bits) = match addr {
+            IpAddr::V4(addr) => {
+                let mut bytes = [0; 16];
+                bytes[..4].copy_from_slice(&addr.octets());
+                (4, bytes, 32)
+            }
+            IpAddr::V6(addr) => (6, addr.octets(), 128),
+        };
+
+        assert!(
+            prefix_len <= bits,
+            "prefix length {} is longer than the address",
+            prefix_len
+        );
+
+        let mut words = [0; 4];
+        let mut masks = [0; 4];
+
+        for i in 0..4 {
+            let word_bits = (prefix_len as u32).saturating_sub(32 * i as u32).min(32);
+            masks[i] = match word_bits {
+                0 => 0,
+                n => u32::MAX << (32 - n),
+            };
+            let word = [
+                bytes[4 * i],
+                bytes[4 * i + 1],
+                bytes[4 * i + 2],
+                bytes[4 * i + 3],
+            ];
+            words[i] = u32::from_be_bytes(word) & masks[i];
+        }
+
+        Self {
+            family,
+            words,
+            masks,
+        }
+    }
+
+    fn contains(&self, family: u32, addr: &[u32; 4]) -> bool {
+        family == self.family && (0..4).all(|i| addr[i] & self.masks[i] == self.words[i])
+    }
+}
+
+impl Filter {
+    /// Matches every frame.
+    pub fn all() -> Self {
+        Self(Expr::Const(true))
+    }
+
+    /// Matches no frame.
+    pub fn none() -> Self {
+        Self(Expr::Const(false))
+    }
+
+    /// Frames whose outermost VLAN tag has id `vlan_id`. Untagged
+    /// frames never match.
+    pub fn vlan(vlan_id: u16) -> Self {
+        Self::eq(Field::Vlan, (vlan_id & 0x0fff) as u32)
+    }
+
+    /// Frames whose ethertype, after any VLAN tags, is `ethertype`.
+    pub fn ethertype(ethertype: u16) -> Self {
+        Self::eq(Field::Ethertype, ethertype as u32)
+    }
+
+    /// IPv4 frames.
+    pub fn ipv4() -> Self {
+        Self::ethertype(ETH_P_IPV4)
+    }
+
+    /// IPv6 frames.
+    pub fn ipv6() -> Self {
+        Self::ethertype(ETH_P_IPV6)
+    }
+
+    /// IP packets carrying protocol `protocol`, e.g. `17` for UDP.
+    /// For IPv6 this is the first next header value.
+    pub fn protocol(protocol: u8) -> Self {
+        Self::eq(Field::Protocol, protocol as u32)
+    }
+
+    /// TCP segments.
+    pub fn tcp() -> Self {
+        Self::protocol(IPPROTO_TCP)
+    }
+
+    /// UDP datagrams.
+    pub fn udp() -> Self {
+        Self::protocol(IPPROTO_UDP)
+    }
+
+    /// TCP or UDP packets with source port `port`.
+    pub fn src_port(port: u16) -> Self {
+        Self::src_ports(port..=port)
+    }
+
+    /// TCP or UDP packets with destination port `port`.
+    pub fn dst_port(port: u16) -> Self {
+        Self::dst_ports(port..=port)
+    }
+
+    /// TCP or UDP packets with either port `port`.
+    pub fn port(port: u16) -> Self {
+        Self::src_port(port).or(Self::dst_port(port))
+    }
+
+    /// TCP or UDP packets with a source port within `ports`.
+    pub fn src_ports(ports: RangeInclusive<u16>) -> Self {
+        Self::range(Field::SrcPort, ports)
+    }
+
+    /// TCP or UDP packets with a destination port within `ports`.
+    pub fn dst_ports(ports: RangeInclusive<u16>) -> Self {
+        Self::range(Field::DstPort, ports)
+    }
+
+    /// IP packets whose source address is within `addr/prefix_len`.
+    ///
+    /// # Panics
+    ///
+    /// If `prefix_len` is longer than the address, i.e. over 32 for
+    /// IPv4 or 128 for IPv6.
+    pub fn src_net(addr: IpAddr, prefix_len: u8) -> Self {
+        Self(Expr::Net(Dir::Src, Net::new(addr, prefix_len)))
+    }
+
+    /// IP packets whose destination address is within
+    /// `addr/prefix_len`.
+    ///
+    /// # Panics
+    ///
+    /// See [`src_net`](Self::src_net).
+    pub fn dst_net(addr: IpAddr, prefix_len: u8) -> Self {
+        Self(Expr::Net(Dir::Dst, Net::new(addr, prefix_len)))
+    }
+
+    /// IP packets with either address within `addr/prefix_len`.
+    ///
+    /// # Panics
+    ///
+    /// See [`src_net`](Self::src_net).
+    pub fn net(addr: IpAddr, prefix_len: u8) -> Self {
+        Self::src_net(addr, prefix_len).or(Self::dst_net(addr, prefix_len))
+    }
+
+    /// Frames matching both `self` and `other`.
+    pub fn and(self, other: Filter) -> Self {
+        match self.0 {
+            Expr::All(mut exprs) => {
+                exprs.push(other.0);
+                Self(Expr::All(exprs))
+            }
+            expr => Self(Expr::All(vec![expr, other.0])),
+        }
+    }
+
+    /// Frames matching either `self` or `other`.
+    pub fn or(self, other: Filter) -> Self {
+        match self.0 {
+            Expr::Any(mut exprs) => {
+                exprs.push(other.0);
+                Self(Expr::Any(exprs))
+            }
+            expr => Self(Expr::Any(vec![expr, other.0])),
+        }
+    }
+
+    fn eq(field: Field, val: u32) -> Self {
+        Self(Expr::Range(field, val, val))
+    }
+
+    fn range(field: Field, ports: RangeInclusive<u16>) -> Self {
+        Self(Expr::Range(
+            field,
+            *ports.start() as u32,
+            *ports.end() as u32,
+        ))
+    }
+
+    /// Whether `frame` matches, by the same rules as the compiled
+    /// program.
+    pub fn matches(&self, frame: &[u8]) -> bool {
+        self.0.eval(&Fields::parse(frame))
+    }
+
+    /// Compile to an XDP program redirecting matching frames to the
+    /// socket in an [`XskMap`] at their rx queue index, and passing
+    /// the rest.
+    pub fn compile(&self) -> Result<Program, FilterTooLarge> {
+        let mut asm = Asm::default();
+
+        let eval = asm.label();
+        let redirect = asm.label();
+        let pass = asm.label();
+
+        emit_parse(&mut asm, eval);
+
+        asm.bind(eval);
+        self.0.emit(&mut asm, redirect, pass);
+
+        asm.bind(redirect);
+        let map_insn = asm.ld_map_fd(R1, -1);
+        asm.ldx(W, R2, R10, QUEUE);
+        asm.mov_imm(R3, XDP_PASS);
+        asm.call(BPF_FUNC_REDIRECT_MAP);
+        asm.exit();
+
+        asm.bind(pass);
+        asm.mov_imm(R0, XDP_PASS);
+        asm.exit();
+
+        let len = asm.len();
+
+        match asm.finish() {
+            Some(insns) => Ok(Program { insns, map_insn }),
+            None => Err(FilterTooLarge { len }),
+        }
+    }
+}
+
+impl Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Self::Output {
+        match self.0 {
+            Expr::Not(expr) => Self(*expr),
+            expr => Self(Expr::Not(Box::new(expr))),
+        }
+    }
+}
+
+// Helper ids and return codes.
+const BPF_FUNC_REDIRECT_MAP: i32 = 51;
+const XDP_PASS: i32 = 2;
+
+// Registers. `r0` to `r5` are clobbered by calls, `r6` to `r9` are
+// kept and `r10` is the read-only frame pointer.
+const R0: u8 = 0;
+const R1: u8 = 1;
+const R2: u8 = 2;
+const R3: u8 = 3;
+const R4: u8 = 4;
+const R5: u8 = 5;
+const DATA: u8 = 6;
+const DATA_END: u8 = 7;
+const HDR: u8 = 8;
+const R10: u8 = 10;
+
+// Offsets of `struct xdp_md` fields.
+const MD_DATA: i16 = 0;
+const MD_DATA_END: i16 = 4;
+const MD_RX_QUEUE_INDEX: i16 = 16;
+
+// Stack slots the parsed header fields are kept in, as 32 bit host
+// order values. Fields the frame doesn't have hold `UNSET`, which no
+// comparison accepts.
+const VLAN: i16 = -4;
+const ETHERTYPE: i16 = -8;
+const FAMILY: i16 = -12;
+const PROTOCOL: i16 = -16;
+const SRC_PORT: i16 = -20;
+const DST_PORT: i16 = -24;
+const SRC_ADDR: i16 = -40;
+const DST_ADDR: i16 = -56;
+const QUEUE: i16 = -60;
+
+const UNSET: u32 = u32::MAX;
+
+impl Field {
+    fn slot(self) -> i16 {
+        match self {
+            Field::Vlan => VLAN,
+            Field::Ethertype => ETHERTYPE,
+            Field::Protocol => PROTOCOL,
+            Field::SrcPort => SRC_PORT,
+            Field::DstPort => DST_PORT,
+        }
+    }
+}
+
+/// Parse the headers into the stack slots, jumping to `done` as soon
+/// as the frame runs out.
+fn emit_parse(asm: &mut Asm, done: Label) {
+    asm.ldx(W, DATA, R1, MD_DATA);
+    asm.ldx(W, DATA_END, R1, MD_DATA_END);
+    asm.ldx(W, R4, R1, MD_RX_QUEUE_INDEX);
+    asm.stx(W, R10, QUEUE, R4);
+
+    for slot in &[VLAN, ETHERTYPE, FAMILY, PROTOCOL, SRC_PORT, DST_PORT] {
+        asm.st(W, R10, *slot, UNSET as i32);
+    }
+    for i in 0..4 {
+        asm.st(W, R10, SRC_ADDR + 4 * i, 0);
+        asm.st(W, R10, DST_ADDR + 4 * i, 0);
+    }
+
+    let untagged = asm.label();
+    let ipv4 = asm.label();
+    let ipv6 = asm.label();
+    let l4 = asm.label();
+
+    // Ethernet, and up to two VLAN tags. `HDR` points past the last
+    // tag and `r3` holds the ethertype that follows it.
+    emit_bounds(asm, DATA, ETH_HLEN as i32, done);
+    asm.ldx(H, R3, DATA, 12);
+    asm.be(R3, 16);
+    asm.mov_reg(HDR, DATA);
+    asm.alu64_imm(ADD, HDR, ETH_HLEN as i32);
+
+    for tag in 0..2 {
+        let tagged = asm.label();
+        asm.jmp32_imm(JEQ, R3, ETH_P_8021Q as i32, tagged);
+        asm.jmp32_imm(JNE, R3, ETH_P_8021AD as i32, untagged);
+        asm.bind(tagged);
+
+        emit_bounds(asm, HDR, VLAN_HLEN as i32, done);
+        if tag == 0 {
+            asm.ldx(H, R4, HDR, 0);
+            asm.be(R4, 16);
+            asm.alu32_imm(AND, R4, 0x0fff);
+            asm.stx(W, R10, VLAN, R4);
+        }
+        asm.ldx(H, R3, HDR, 2);
+        asm.be(R3, 16);
+        asm.alu64_imm(ADD, HDR, VLAN_HLEN as i32);
+    }
+
+    asm.bind(untagged);
+    asm.stx(W, R10, ETHERTYPE, R3);
+    asm.jmp32_imm(JEQ, R3, ETH_P_IPV4 as i32, ipv4);
+    asm.jmp32_imm(JEQ, R3, ETH_P_IPV6 as i32, ipv6);
+    asm.ja(done);
+
+    // IPv4, leaving `HDR` at the transport header unless this is a
+    // non-initial fragment.
+    asm.bind(ipv4);
+    emit_bounds(asm, HDR, IPV4_MIN_HLEN as i32, done);
+    asm.st(W, R10, FAMILY, 4);
+    asm.ldx(B, R4, HDR, 9);
+    asm.stx(W, R10, PROTOCOL, R4);
+    for (slot, off) in &[(SRC_ADDR, 12), (DST_ADDR, 16)] {
+        asm.ldx(W, R4, HDR, *off);
+        asm.be(R4, 32);
+        asm.stx(W, R10, *slot, R4);
+    }
+    asm.ldx(H, R4, HDR, 6);
+    asm.be(R4, 16);
+    asm.alu32_imm(AND, R4, 0x1fff);
+    asm.jmp32_imm(JNE, R4, 0, done);
+    asm.ldx(B, R5, HDR, 0);
+    asm.alu32_imm(AND, R5, 0x0f);
+    asm.alu32_imm(LSH, R5, 2);
+    asm.jmp32_imm(JLT, R5, IPV4_MIN_HLEN as i32, done);
+    asm.alu64_reg(ADD, HDR, R5);
+    asm.ja(l4);
+
+    asm.bind(ipv6);
+    emit_bounds(asm, HDR, IPV6_HLEN as i32, done);
+    asm.st(W, R10, FAMILY, 6);
+    asm.ldx(B, R4, HDR, 6);
+    asm.stx(W, R10, PROTOCOL, R4);
+    for i in 0..4 {
+        for (slot, off) in &[(SRC_ADDR, 8), (DST_ADDR, 24)] {
+            asm.ldx(W, R4, HDR, *off + 4 * i);
+            asm.be(R4, 32);
+            asm.stx(W, R10, *slot + 4 * i, R4);
+        }
+    }
+    asm.alu64_imm(ADD, HDR, IPV6_HLEN as i32);
+
+    // TCP and UDP ports.
+    asm.bind(l4);
+    let ports = asm.label();
+    asm.ldx(W, R4, R10, PROTOCOL);
+    asm.jmp32_imm(JEQ, R4, IPPROTO_TCP as i32, ports);
+    asm.jmp32_imm(JNE, R4, IPPROTO_UDP as i32, done);
+    asm.bind(ports);
+    emit_bounds(asm, HDR, 4, done);
+    for (slot, off) in &[(SRC_PORT, 0), (DST_PORT, 2)] {
+        asm.ldx(H, R4, HDR, *off);
+        asm.be(R4, 16);
+        asm.stx(W, R10, *slot, R4);
+    }
+}
+
+/// Jump to `short` unless `len` bytes from packet pointer `ptr` are
+/// within the frame.
+fn emit_bounds(asm: &mut Asm, ptr: u8, len: i32, short: Label) {
+    asm.mov_reg(R2, ptr);
+    asm.alu64_imm(ADD, R2, len);
+    asm.jmp_reg(JGT, R2, DATA_END, short);
+}
+
+impl Expr {
+    fn eval(&self, fields: &Fields) -> bool {
+        match self {
+            Expr::Const(val) => *val,
+            Expr::Range(field, lo, hi) => {
+                let val = fields.get(*field);
+                *lo <= val && val <= *hi
+            }
+            Expr::Net(Dir::Src, net) => net.contains(fields.family, &fields.src_addr),
+            Expr::Net(Dir::Dst, net) => net.contains(fields.family, &fields.dst_addr),
+            Expr::Not(expr) => !expr.eval(fields),
+            Expr::All(exprs) => exprs.iter().all(|e| e.eval(fields)),
+            Expr::Any(exprs) => exprs.iter().any(|e| e.eval(fields)),
+        }
+    }
+
+    /// Emit a jump to `on_true` if the expression holds and to
+    /// `on_false` otherwise.
+    fn emit(&self, asm: &mut Asm, on_true: Label, on_false: Label) {
+        match self {
+            Expr::Const(true) => asm.ja(on_true),
+            Expr::Const(false) => asm.ja(on_false),
+            Expr::Range(field, lo, hi) => {
+                asm.ldx(W, R4, R10, field.slot());
+                if lo == hi {
+                    asm.jmp32_imm(JEQ, R4, *lo as i32, on_true);
+                    asm.ja(on_false);
+                } else {
+                    asm.jmp32_imm(JLT, R4, *lo as i32, on_false);
+                    asm.jmp32_imm(JGT, R4, *hi as i32, on_false);
+                    asm.ja(on_true);
+                }
+            }
+            Expr::Net(dir, net) => {
+                let slot = match dir {
+                    Dir::Src => SRC_ADDR,
+                    Dir::Dst => DST_ADDR,
+                };
+
+                asm.ldx(W, R4, R10, FAMILY);
+                asm.jmp32_imm(JNE, R4, net.family as i32, on_false);
+
+                for i in 0..4 {
+                    if net.masks[i] == 0 {
+                        break;
+                    }
+                    asm.ldx(W, R4, R10, slot + 4 * i as i16);
+                    asm.alu32_imm(AND, R4, net.masks[i] as i32);
+                    asm.jmp32_imm(JNE, R4, net.words[i] as i32, on_false);
+                }
+
+                asm.ja(on_true);
+            }
+            Expr::Not(expr) => expr.emit(asm, on_false, on_true),
+            Expr::All(exprs) => {
+                for expr in exprs {
+                    let next = asm.label();
+                    expr.emit(asm, next, on_false);
+                    asm.bind(next);
+                }
+                asm.ja(on_true);
+            }
+            Expr::Any(exprs) => {
+                for expr in exprs {
+                    let next = asm.label();
+                    expr.emit(asm, on_true, next);
+                    asm.bind(next);
+                }
+                asm.ja(on_false);
+            }
+        }
+    }
+}
+
+/// The header fields a [`Filter`] can match on, extracted as the
+/// compiled program does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fields {
+    vlan: u32,
+    ethertype: u32,
+    family: u32,
+    protocol: u32,
+    src_port: u32,
+    dst_port: u32,
+    src_addr: [u32; 4],
+    dst_addr: [u32; 4],
+}
+
+impl Fields {
+    fn parse(frame: &[u8]) -> Self {
+        let mut fields = Self {
+            vlan: UNSET,
+            ethertype: UNSET,
+            family: UNSET,
+            protocol: UNSET,
+            src_port: UNSET,
+            dst_port: UNSET,
+            src_addr: [0; 4],
+            dst_addr: [0; 4],
+        };
+
+        fields.parse_into(frame);
+        fields
+    }
+
+    fn parse_into(&mut self, frame: &[u8]) -> Option<()> {
+        if frame.len() < ETH_HLEN {
+            return None;
+        }
+        let mut ethertype = packet::read_u16(frame, 12)?;
+        let mut offset = ETH_HLEN;
+
+        for tag in 0..2 {
+            if ethertype != ETH_P_8021Q && ethertype != ETH_P_8021AD {
+                break;
+            }
+            if frame.len() < offset + VLAN_HLEN {
+                return None;
+            }
+            if tag == 0 {
+                self.vlan = (packet::read_u16(frame, offset)? & 0x0fff) as u32;
+            }
+            ethertype = packet::read_u16(frame, offset + 2)?;
+            offset += VLAN_HLEN;
+        }
+
+        self.ethertype = ethertype as u32;
+
+        let l4_offset = match ethertype {
+            ETH_P_IPV4 => {
+                if frame.len() < offset + IPV4_MIN_HLEN {
+                    return None;
+                }
+                self.family = 4;
+                self.protocol = frame[offset + 9] as u32;
+                self.src_addr[0] = packet::read_u32(frame, offset + 12)?;
+                self.dst_addr[0] = packet::read_u32(frame, offset + 16)?;
+
+                if packet::ipv4_is_fragment(frame, offset)? {
+                    return None;
+                }
+                let ihl = (frame[offset] & 0x0f) as usize * 4;
+                if ihl < IPV4_MIN_HLEN {
+                    return None;
+                }
+                offset + ihl
+            }
+            ETH_P_IPV6 => {
+                if frame.len() < offset + IPV6_HLEN {
+                    return None;
+                }
+                self.family = 6;
+                self.protocol = frame[offset + 6] as u32;
+                for i in 0..4 {
+                    self.src_addr[i] = packet::read_u32(frame, offset + 8 + 4 * i)?;
+                    self.dst_addr[i] = packet::read_u32(frame, offset + 24 + 4 * i)?;
+                }
+                offset + IPV6_HLEN
+            }
+            _ => return None,
+        };
+
+        if !packet::has_ports(self.protocol as u8) {
+            return None;
+        }
+
+        self.src_port = packet::read_u16(frame, l4_offset)? as u32;
+        self.dst_port = packet::read_u16(frame, l4_offset + 2)? as u32;
+
+        Some(())
+    }
+
+    fn get(&self, field: Field) -> u32 {
+        match field {
+            Field::Vlan => self.vlan,
+            Field::Ethertype => self.ethertype,
+            Field::Protocol => self.protocol,
+            Field::SrcPort => self.src_port,
+            Field::DstPort => self.dst_port,
+        }
+    }
+}
+
+/// A compiled [`Filter`], ready to be loaded with
+/// [`load`](Self::load).
+#[derive(Debug, Clone)]
+pub struct Program {
+    insns: Vec<Insn>,
+    map_insn: usize,
+}
+
+impl Program {
+    /// The program's instructions. The [`XskMap`] fd is filled in at
+    /// load time, so reads as `-1` here.
+    #[inline]
+    pub fn insns(&self) -> &[Insn] {
+        &self.insns
+    }
+}
+
+/// A [`Filter`] compiled to more instructions than a jump can span.
+#[derive(Debug)]
+pub struct FilterTooLarge {
+    len: usize,
+}
+
+impl FilterTooLarge {
+    /// The number of instructions the filter compiled to.
+    pub fn insn_count(&self) -> usize {
+        self.len
+    }
+}
+
+impl fmt::Display for FilterTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "filter compiled to {} instructions, too many to jump across",
+            self.len
+        )
+    }
+}
+
+impl error::Error for FilterTooLarge {}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+    use crate::packet::tests::udp4_frame;
+
+    fn tcp6_frame(vlan: Option<u16>, src: Ipv6Addr, dst_port: u16) -> Vec<u8> {
+        let mut frame = vec![0xaa; 12];
+
+        if let Some(vlan) = vlan {
+            frame.extend_from_slice(&ETH_P_8021AD.to_be_bytes());
+            frame.extend_from_slice(&vlan.to_be_bytes());
+        }
+
+        frame.extend_from_slice(&ETH_P_IPV6.to_be_bytes());
+        frame.extend_from_slice(&[0x60, 0, 0, 0, 0, 20, IPPROTO_TCP, 64]);
+        frame.extend_from_slice(&src.octets());
+        frame.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        frame.extend_from_slice(&4321u16.to_be_bytes());
+        frame.extend_from_slice(&dst_port.to_be_bytes());
+        frame.extend_from_slice(&[0; 16]);
+
+        frame
+    }
+
+    fn frames() -> Vec<Vec<u8>> {
+        let db: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let other: Ipv6Addr = "2001:db9::1".parse().unwrap();
+
+        let mut fragment = udp4_frame(0, 1234, 53);
+        fragment[ETH_HLEN + 7] = 0x10;
+
+        let mut options = udp4_frame(0, 1234, 53);
+        options[ETH_HLEN] = 0x46;
+
+        let mut frames = vec![
+            udp4_frame(0, 1234, 53),
+            udp4_frame(1, 1234, 53),
+            udp4_frame(2, 53, 8080),
+            udp4_frame(0, 1234, 54),
+            tcp6_frame(None, db, 443),
+            tcp6_frame(Some(7), db, 80),
+            tcp6_frame(Some(5), other, 443),
+            fragment,
+            options,
+            vec![0; 10],
+        ];
+
+        let full = udp4_frame(1, 1234, 53);
+        for len in (0..full.len()).step_by(3) {
+            frames.push(full[..len].to_vec());
+        }
+
+        frames
+    }
+
+    fn filters() -> Vec<Filter> {
+        let v4 = |a, b, c, d| IpAddr::V4(Ipv4Addr::new(a, b, c, d));
+        let db: IpAddr = "2001:db8::".parse().unwrap();
+
+        vec![
+            Filter::all(),
+            Filter::none(),
+            Filter::udp().and(Filter::dst_port(53)),
+            Filter::port(53),
+            Filter::dst_ports(1000..=9000),
+            Filter::vlan(5),
+            !Filter::vlan(5),
+            Filter::ipv6().or(Filter::vlan(7)),
+            Filter::tcp().and(!Filter::dst_port(80)),
+            Filter::src_net(v4(192, 168, 0, 0), 16),
+            Filter::dst_net(v4(192, 168, 69, 3), 32),
+            Filter::net(v4(192, 168, 69, 2), 31),
+            Filter::src_net(v4(0, 0, 0, 0), 0),
+            Filter::src_net(db, 32).and(Filter::dst_port(443)),
+            Filter::src_net(db, 0),
+            Filter::dst_net(Ipv6Addr::LOCALHOST.into(), 128),
+            Filter::protocol(IPPROTO_TCP).or(Filter::udp().and(Filter::src_port(53))),
+        ]
+    }
+
+    #[test]
+    fn compiled_programs_agree_with_matches() {
+        for (i, filter) in filters().iter().enumerate() {
+            let prog = filter.compile().unwrap();
+
+            for (j, frame) in frames().iter().enumerate() {
+                let redirected = interp::run(prog.insns(), frame, 3);
+                let expected = if filter.matches(frame) { Some(3) } else { None };
+
+                assert_eq!(redirected, expected, "filter {}, frame {}", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_match_the_expected_frames() {
+        let dns = Filter::udp().and(Filter::dst_port(53));
+
+        assert!(dns.matches(&udp4_frame(0, 1234, 53)));
+        assert!(dns.matches(&udp4_frame(2, 1234, 53)));
+        assert!(!dns.matches(&udp4_frame(0, 53, 1234)));
+
+        let mut fragment = udp4_frame(0, 1234, 53);
+        fragment[ETH_HLEN + 7] = 0x10;
+        assert!(!dns.matches(&fragment));
+        assert!(Filter::udp().matches(&fragment));
+
+        assert!(Filter::vlan(5).matches(&udp4_frame(1, 0, 0)));
+        assert!(!Filter::vlan(5).matches(&udp4_frame(0, 0, 0)));
+
+        let net = Filter::src_net(IpAddr::V4(Ipv4Addr::new(192, 168, 64, 0)), 20);
+        assert!(net.matches(&udp4_frame(0, 0, 0)));
+        assert!(!net.matches(&tcp6_frame(None, Ipv6Addr::UNSPECIFIED, 0)));
+    }
+
+    #[test]
+    fn prefixes_are_masked_to_their_length() {
+        let net = Net::new("2001:db8:ffff::1".parse().unwrap(), 36);
+
+        assert_eq!(net.masks, [u32::MAX, 0xf000_0000, 0, 0]);
+        assert_eq!(net.words, [0x2001_0db8, 0xf000_0000, 0, 0]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn overlong_prefixes_are_rejected() {
+        Filter::src_net(IpAddr::V4(Ipv4Addr::LOCALHOST), 33);
+    }
+
+    #[test]
+    fn double_negation_cancels_out() {
+        assert_eq!(!!Filter::tcp(), Filter::tcp());
+    }
+}
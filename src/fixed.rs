@@ -0,0 +1,302 @@
+//! Queues whose ring size is fixed at compile time.
+//!
+//! The ring operations index into their ring modulo its size, which
+//! for the dynamic queues is read from the ring on every access. Each
+//! of the wrappers here takes a queue whose ring is `N` entries, a
+//! power of two checked when it's wrapped, and uses `N` as a constant
+//! instead, so the masking folds away and the compiler can bound and
+//! unroll the batch loops in their hot path methods:
+//!
+//! ```no_run
+//! # use xsk_rs::{fixed::{FixedFillQueue, FixedRxQueue}, FillQueue, FrameDesc, RxQueue};
+//! # unsafe fn run(rx_q: RxQueue, fq: FillQueue, mut descs: Vec<FrameDesc>) {
+//! // The default ring sizes are 2048 entries, see `UmemConfig` and
+//! // `SocketConfig`.
+//! let mut rx_q = FixedRxQueue::<2048>::new(rx_q).unwrap();
+//! let mut fq = FixedFillQueue::<2048>::new(fq).unwrap();
+//!
+//! loop {
+//!     let cnt = unsafe { rx_q.consume(&mut descs[..64]) };
+//!
+//!     // ... process the frames ...
+//!
+//!     unsafe { fq.recycle_from_rx(&mut descs[..cnt]) };
+//! }
+//! # }
+//! ```
+//!
+//! The wrapped queue stays reachable with `get_mut` for everything
+//! else, e.g. wakeups, and the two may be used interchangeably since
+//! they share the same ring state.
+//!
+//! Any `N` which isn't a power of two fails to compile:
+//!
+//! ```compile_fail
+//! # use xsk_rs::{fixed::FixedRxQueue, RxQueue};
+//! # fn wrap(rx_q: RxQueue) {
+//! let rx_q = FixedRxQueue::<1000>::new(rx_q);
+//! # }
+//! # println!("{:p}", wrap as fn(RxQueue));
+//! ```
+
+use std::{error::Error, fmt};
+
+use crate::{
+    ring::{Fixed, RingSize},
+    socket::{RxQueue, TxQueue},
+    umem::{frame::FrameDesc, CompQueue, FillQueue},
+};
+
+/// Error returned when a queue's ring isn't the size of the wrapper
+/// it's given to. The queue can be recovered with
+/// [`into_queue`](Self::into_queue).
+pub struct RingSizeError<Q> {
+    queue: Box<Q>,
+    expected: usize,
+    actual: usize,
+}
+
+impl<Q> RingSizeError<Q> {
+    /// The size the wrapper needed.
+    pub fn expected(&self) -> usize {
+        self.expected
+    }
+
+    /// The size of the queue's ring.
+    pub fn actual(&self) -> usize {
+        self.actual
+    }
+
+    /// The queue which couldn't be wrapped.
+    pub fn into_queue(self) -> Q {
+        *self.queue
+    }
+}
+
+impl<Q> fmt::Debug for RingSizeError<Q> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RingSizeError")
+            .field("expected", &self.expected)
+            .field("actual", &self.actual)
+            .finish()
+    }
+}
+
+impl<Q> fmt::Display for RingSizeError<Q> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ring has {} entries, expected {}",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl<Q> Error for RingSizeError<Q> {}
+
+/// Check `queue`'s ring of `actual` entries is `N` entries.
+fn check_size<Q, const N: usize>(queue: Q, actual: usize) -> Result<Q, RingSizeError<Q>> {
+    // Referenced so a bad `N` fails to compile even if none of the
+    // ring operations are used.
+    let _ = Fixed::<N>::FIXED;
+
+    if actual == N {
+        Ok(queue)
+    } else {
+        Err(RingSizeError {
+            queue: Box::new(queue),
+            expected: N,
+            actual,
+        })
+    }
+}
+
+/// An [`RxQueue`] whose ring has `N` entries, see the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct FixedRxQueue<const N: usize> {
+    rx_q: RxQueue,
+}
+
+impl<const N: usize> FixedRxQueue<N> {
+    /// Wrap `rx_q`, whose ring must have `N` entries.
+    pub fn new(rx_q: RxQueue) -> Result<Self, RingSizeError<RxQueue>> {
+        let size = rx_q.capacity();
+
+        check_size::<_, N>(rx_q, size).map(|rx_q| Self { rx_q })
+    }
+
+    /// Same as [`RxQueue::consume`].
+    ///
+    /// # Safety
+    ///
+    /// See [`RxQueue::consume`].
+    #[inline]
+    pub unsafe fn consume(&mut self, descs: &mut [FrameDesc]) -> usize {
+        unsafe { self.rx_q.consume_sized::<Fixed<N>>(descs) }
+    }
+
+    /// A reference to the wrapped [`RxQueue`].
+    pub fn get_ref(&self) -> &RxQueue {
+        &self.rx_q
+    }
+
+    /// A mutable reference to the wrapped [`RxQueue`].
+    pub fn get_mut(&mut self) -> &mut RxQueue {
+        &mut self.rx_q
+    }
+
+    /// Return the wrapped [`RxQueue`].
+    pub fn into_inner(self) -> RxQueue {
+        self.rx_q
+    }
+}
+
+/// A [`TxQueue`] whose ring has `N` entries, see the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct FixedTxQueue<const N: usize> {
+    tx_q: TxQueue,
+}
+
+impl<const N: usize> FixedTxQueue<N> {
+    /// Wrap `tx_q`, whose ring must have `N` entries.
+    pub fn new(tx_q: TxQueue) -> Result<Self, RingSizeError<TxQueue>> {
+        let size = tx_q.capacity();
+
+        check_size::<_, N>(tx_q, size).map(|tx_q| Self { tx_q })
+    }
+
+    /// Same as [`TxQueue::produce`].
+    ///
+    /// # Safety
+    ///
+    /// See [`TxQueue::produce`].
+    #[inline]
+    pub unsafe fn produce(&mut self, descs: &[FrameDesc]) -> usize {
+        unsafe { self.tx_q.produce_sized::<Fixed<N>>(descs) }
+    }
+
+    /// A reference to the wrapped [`TxQueue`].
+    pub fn get_ref(&self) -> &TxQueue {
+        &self.tx_q
+    }
+
+    /// A mutable reference to the wrapped [`TxQueue`].
+    pub fn get_mut(&mut self) -> &mut TxQueue {
+        &mut self.tx_q
+    }
+
+    /// Return the wrapped [`TxQueue`].
+    pub fn into_inner(self) -> TxQueue {
+        self.tx_q
+    }
+}
+
+/// A [`FillQueue`] whose ring has `N` entries, see the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct FixedFillQueue<const N: usize> {
+    fq: FillQueue,
+}
+
+impl<const N: usize> FixedFillQueue<N> {
+    /// Wrap `fq`, whose ring must have `N` entries.
+    pub fn new(fq: FillQueue) -> Result<Self, RingSizeError<FillQueue>> {
+        let size = fq.capacity();
+
+        check_size::<_, N>(fq, size).map(|fq| Self { fq })
+    }
+
+    /// Same as [`FillQueue::produce`].
+    ///
+    /// # Safety
+    ///
+    /// See [`FillQueue::produce`].
+    #[inline]
+    pub unsafe fn produce(&mut self, descs: &[FrameDesc]) -> usize {
+        unsafe { self.fq.produce_sized::<Fixed<N>>(descs) }
+    }
+
+    /// Same as [`FillQueue::recycle_from_rx`].
+    ///
+    /// # Safety
+    ///
+    /// See [`FillQueue::recycle_from_rx`].
+    #[inline]
+    pub unsafe fn recycle_from_rx(&mut self, descs: &mut [FrameDesc]) -> usize {
+        unsafe { self.fq.recycle_from_rx_sized::<Fixed<N>>(descs) }
+    }
+
+    /// A reference to the wrapped [`FillQueue`].
+    pub fn get_ref(&self) -> &FillQueue {
+        &self.fq
+    }
+
+    /// A mutable reference to the wrapped [`FillQueue`].
+    pub fn get_mut(&mut self) -> &mut FillQueue {
+        &mut self.fq
+    }
+
+    /// Return the wrapped [`FillQueue`].
+    pub fn into_inner(self) -> FillQueue {
+        self.fq
+    }
+}
+
+/// A [`CompQueue`] whose ring has `N` entries, see the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct FixedCompQueue<const N: usize> {
+    cq: CompQueue,
+}
+
+impl<const N: usize> FixedCompQueue<N> {
+    /// Wrap `cq`, whose ring must have `N` entries.
+    pub fn new(cq: CompQueue) -> Result<Self, RingSizeError<CompQueue>> {
+        let size = cq.capacity();
+
+        check_size::<_, N>(cq, size).map(|cq| Self { cq })
+    }
+
+    /// Same as [`CompQueue::consume`].
+    ///
+    /// # Safety
+    ///
+    /// See [`CompQueue::consume`].
+    #[inline]
+    pub unsafe fn consume(&mut self, descs: &mut [FrameDesc]) -> usize {
+        unsafe { self.cq.consume_sized::<Fixed<N>>(descs) }
+    }
+
+    /// A reference to the wrapped [`CompQueue`].
+    pub fn get_ref(&self) -> &CompQueue {
+        &self.cq
+    }
+
+    /// A mutable reference to the wrapped [`CompQueue`].
+    pub fn get_mut(&mut self) -> &mut CompQueue {
+        &mut self.cq
+    }
+
+    /// Return the wrapped [`CompQueue`].
+    pub fn into_inner(self) -> CompQueue {
+        self.cq
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_mismatch_hands_the_queue_back() {
+        assert_eq!(check_size::<_, 8>("q", 8).unwrap(), "q");
+
+        let err = check_size::<_, 8>("q", 16).unwrap_err();
+
+        assert_eq!(err.expected(), 8);
+        assert_eq!(err.actual(), 16);
+        assert_eq!(err.into_queue(), "q");
+    }
+}
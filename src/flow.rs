@@ -0,0 +1,602 @@
+//! A fixed capacity flow table, for keeping per flow state from the
+//! RX batch loop.
+//!
+//! [`FlowTable`] is keyed by [`FlowKey`] and sized up front, so it
+//! never allocates once created. Lookups probe an open addressed index
+//! of packed (hash, entry) pairs, usually touching a single cache line
+//! before the entry itself. When full, inserting evicts the least
+//! recently used flow, and flows left idle for longer than the table's
+//! timeout are reaped by [`FlowTable::expire`], driven by a hashed
+//! timer wheel so each call only visits flows that may be due.
+//!
+//! Time is measured in caller defined ticks, e.g. milliseconds from a
+//! coarse clock read once per batch:
+//!
+//! ```
+//! use xsk_rs::{dispatch::FlowKey, flow::FlowTable};
+//! # let frames: Vec<Vec<u8>> = Vec::new();
+//! # let now = 0;
+//!
+//! // Up to 64k flows, idle ones expiring after 30s.
+//! let mut flows = FlowTable::new(1 << 16, 30_000);
+//!
+//! for frame in &frames {
+//!     if let Some(key) = FlowKey::from_frame(frame) {
+//!         *flows.get_or_insert_with(&key, now, || 0u64) += 1;
+//!     }
+//! }
+//!
+//! flows.expire(now, |key, packets| println!("{:?}: {} packets", key, packets));
+//! ```
+
+use std::mem;
+
+use crate::dispatch::{FlowHasher, FlowKey, Toeplitz};
+
+const NIL: u32 = u32::MAX;
+
+const WHEEL_SLOTS: usize = 256;
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    hash: u32,
+    entry: u32,
+}
+
+const EMPTY: Slot = Slot {
+    hash: 0,
+    entry: NIL,
+};
+
+#[derive(Debug, Clone)]
+struct Entry<V> {
+    flow: Option<(FlowKey, V)>,
+    hash: u32,
+    prev: u32,
+    next: u32,
+    deadline: u64,
+    gen: u32,
+}
+
+/// A flow table, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct FlowTable<V, H = Toeplitz> {
+    hasher: H,
+    capacity: usize,
+    slots: Vec<Slot>,
+    entries: Vec<Entry<V>>,
+    free: Vec<u32>,
+    len: usize,
+    head: u32,
+    tail: u32,
+    idle_timeout: u64,
+    granularity: u64,
+    wheel: Vec<Vec<(u32, u32)>>,
+    wheel_tick: u64,
+    scratch: Vec<(u32, u32)>,
+    evictions: u64,
+    expirations: u64,
+}
+
+impl<V> FlowTable<V> {
+    /// Create a table holding up to `capacity` flows, hashed with the
+    /// default [`Toeplitz`] hasher, which expire after `idle_timeout`
+    /// ticks without being [touched](Self::touch).
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero or doesn't fit in a `u32`.
+    pub fn new(capacity: usize, idle_timeout: u64) -> Self {
+        Self::with_hasher(Toeplitz::default(), capacity, idle_timeout)
+    }
+}
+
+impl<V, H: FlowHasher> FlowTable<V, H> {
+    /// Create a table as in [`new`](FlowTable::new), but hashing keys
+    /// with `hasher`.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero or doesn't fit in a `u32`.
+    pub fn with_hasher(hasher: H, capacity: usize, idle_timeout: u64) -> Self {
+        assert!(capacity > 0, "flow table capacity must be non-zero");
+        assert!(
+            capacity < NIL as usize,
+            "flow table capacity must fit in a u32"
+        );
+
+        // Keep the load factor at or below a half so probe sequences
+        // stay short.
+        let slots = (capacity * 2).next_power_of_two();
+
+        let granularity = (idle_timeout / WHEEL_SLOTS as u64).max(1);
+
+        Self {
+            hasher,
+            capacity,
+            slots: vec![EMPTY; slots],
+            entries: Vec::with_capacity(capacity),
+            free: Vec::new(),
+            len: 0,
+            head: NIL,
+            tail: NIL,
+            idle_timeout,
+            granularity,
+            wheel: vec![Vec::new(); WHEEL_SLOTS],
+            wheel_tick: 0,
+            scratch: Vec::new(),
+            evictions: 0,
+            expirations: 0,
+        }
+    }
+
+    /// The maximum number of flows the table holds.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of flows in the table.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the table holds no flows.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of ticks a flow may go untouched before expiring.
+    #[inline]
+    pub fn idle_timeout(&self) -> u64 {
+        self.idle_timeout
+    }
+
+    /// The number of flows evicted to make room for new ones so far.
+    #[inline]
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// The number of flows removed by [`expire`](Self::expire) so far.
+    #[inline]
+    pub fn expirations(&self) -> u64 {
+        self.expirations
+    }
+
+    /// The state of the flow `key`, leaving its position in the LRU
+    /// order and its expiry untouched.
+    #[inline]
+    pub fn get(&self, key: &FlowKey) -> Option<&V> {
+        let (_, entry) = self.find(key, self.hasher.hash(key))?;
+
+        self.entries[entry as usize].flow.as_ref().map(|(_, v)| v)
+    }
+
+    /// A mutable reference to the state of the flow `key`, leaving its
+    /// position in the LRU order and its expiry untouched.
+    #[inline]
+    pub fn get_mut(&mut self, key: &FlowKey) -> Option<&mut V> {
+        let (_, entry) = self.find(key, self.hasher.hash(key))?;
+
+        self.entries[entry as usize].flow.as_mut().map(|(_, v)| v)
+    }
+
+    /// A mutable reference to the state of the flow `key`, marking it
+    /// as the most recently used and pushing back its expiry to
+    /// `idle_timeout` ticks after `now`.
+    #[inline]
+    pub fn touch(&mut self, key: &FlowKey, now: u64) -> Option<&mut V> {
+        let (_, entry) = self.find(key, self.hasher.hash(key))?;
+
+        self.refresh(entry, now);
+
+        self.entries[entry as usize].flow.as_mut().map(|(_, v)| v)
+    }
+
+    /// [Touch](Self::touch) the flow `key`, inserting it with the
+    /// state returned by `f` if it's not yet in the table.
+    ///
+    /// If the table is full the least recently used flow is evicted
+    /// and dropped. Use [`insert`](Self::insert) to get it back
+    /// instead.
+    #[inline]
+    pub fn get_or_insert_with<F>(&mut self, key: &FlowKey, now: u64, f: F) -> &mut V
+    where
+        F: FnOnce() -> V,
+    {
+        let hash = self.hasher.hash(key);
+
+        let entry = match self.find(key, hash) {
+            Some((_, entry)) => {
+                self.refresh(entry, now);
+                entry
+            }
+            None => {
+                self.evict_if_full();
+                self.insert_new(*key, hash, f(), now)
+            }
+        };
+
+        match &mut self.entries[entry as usize].flow {
+            Some((_, v)) => v,
+            None => unreachable!("entry was just found or inserted"),
+        }
+    }
+
+    /// Insert the flow `key` with state `value`, marking it as the
+    /// most recently used.
+    ///
+    /// Returns the flow's previous state if it was already present,
+    /// or otherwise the least recently used flow if one had to be
+    /// evicted to make room.
+    pub fn insert(&mut self, key: FlowKey, value: V, now: u64) -> Option<(FlowKey, V)> {
+        let hash = self.hasher.hash(&key);
+
+        if let Some((_, entry)) = self.find(&key, hash) {
+            self.refresh(entry, now);
+
+            return match &mut self.entries[entry as usize].flow {
+                Some((_, v)) => Some((key, mem::replace(v, value))),
+                None => unreachable!("found entries are occupied"),
+            };
+        }
+
+        let evicted = self.evict_if_full();
+        self.insert_new(key, hash, value, now);
+
+        evicted
+    }
+
+    /// Remove the flow `key`, returning its state.
+    pub fn remove(&mut self, key: &FlowKey) -> Option<V> {
+        let (slot, entry) = self.find(key, self.hasher.hash(key))?;
+
+        self.remove_at(slot, entry).map(|(_, v)| v)
+    }
+
+    /// Remove every flow which has been idle for at least the table's
+    /// timeout as of `now`, handing each to `f`. Returns the number
+    /// removed.
+    ///
+    /// Flows expire within one wheel slot, `idle_timeout / 256` ticks,
+    /// of their deadline. Call this regularly, e.g. once per batch or
+    /// whenever the clock ticks over, so each call has little to do.
+    pub fn expire<F>(&mut self, now: u64, mut f: F) -> usize
+    where
+        F: FnMut(FlowKey, V),
+    {
+        let target = now / self.granularity;
+
+        if target <= self.wheel_tick {
+            return 0;
+        }
+
+        // Going round more than once would only revisit the same slots.
+        let last = target.min(self.wheel_tick + WHEEL_SLOTS as u64);
+        let mut expired = 0;
+
+        for tick in self.wheel_tick + 1..=last {
+            let bucket = tick as usize % WHEEL_SLOTS;
+
+            mem::swap(&mut self.scratch, &mut self.wheel[bucket]);
+
+            for i in 0..self.scratch.len() {
+                let (entry, gen) = self.scratch[i];
+                let e = &self.entries[entry as usize];
+
+                if e.gen != gen || e.flow.is_none() {
+                    // Removed, or evicted and reused, since scheduled.
+                    continue;
+                }
+
+                if e.deadline <= now {
+                    let hash = e.hash;
+                    let key = match &e.flow {
+                        Some((key, _)) => *key,
+                        None => unreachable!("checked above"),
+                    };
+
+                    if let Some((slot, _)) = self.find(&key, hash) {
+                        if let Some((key, value)) = self.remove_at(slot, entry) {
+                            f(key, value);
+                            expired += 1;
+                        }
+                    }
+                } else {
+                    // Touched since scheduled, so move it along.
+                    let deadline = e.deadline;
+                    self.schedule(entry, deadline);
+                }
+            }
+
+            self.scratch.clear();
+        }
+
+        self.wheel_tick = target;
+        self.expirations += expired as u64;
+
+        expired
+    }
+
+    /// Iterate over the flows from most to least recently used.
+    pub fn iter(&self) -> impl Iterator<Item = (&FlowKey, &V)> + '_ {
+        let mut next = self.head;
+
+        std::iter::from_fn(move || {
+            let entry = self.entries.get(next as usize)?;
+            next = entry.next;
+            entry.flow.as_ref().map(|(k, v)| (k, v))
+        })
+    }
+
+    fn find(&self, key: &FlowKey, hash: u32) -> Option<(usize, u32)> {
+        let mask = self.slots.len() - 1;
+        let mut i = hash as usize & mask;
+
+        loop {
+            let slot = self.slots[i];
+
+            if slot.entry == NIL {
+                return None;
+            }
+
+            if slot.hash == hash {
+                if let Some((k, _)) = &self.entries[slot.entry as usize].flow {
+                    if k == key {
+                        return Some((i, slot.entry));
+                    }
+                }
+            }
+
+            i = (i + 1) & mask;
+        }
+    }
+
+    fn insert_new(&mut self, key: FlowKey, hash: u32, value: V, now: u64) -> u32 {
+        let deadline = now.saturating_add(self.idle_timeout);
+
+        let entry = match self.free.pop() {
+            Some(entry) => {
+                let e = &mut self.entries[entry as usize];
+                e.flow = Some((key, value));
+                e.hash = hash;
+                e.deadline = deadline;
+                entry
+            }
+            None => {
+                self.entries.push(Entry {
+                    flow: Some((key, value)),
+                    hash,
+                    prev: NIL,
+                    next: NIL,
+                    deadline,
+                    gen: 0,
+                });
+                (self.entries.len() - 1) as u32
+            }
+        };
+
+        let mask = self.slots.len() - 1;
+        let mut i = hash as usize & mask;
+
+        while self.slots[i].entry != NIL {
+            i = (i + 1) & mask;
+        }
+
+        self.slots[i] = Slot { hash, entry };
+
+        self.push_front(entry);
+        self.schedule(entry, deadline);
+        self.len += 1;
+
+        entry
+    }
+
+    fn remove_at(&mut self, slot: usize, entry: u32) -> Option<(FlowKey, V)> {
+        // Backward shift deletion: pull later members of the probe
+        // sequence into the hole, so lookups never need tombstones.
+        let mask = self.slots.len() - 1;
+        let mut hole = slot;
+        let mut i = (slot + 1) & mask;
+
+        loop {
+            let next = self.slots[i];
+
+            if next.entry == NIL {
+                break;
+            }
+
+            let ideal = next.hash as usize & mask;
+
+            if i.wrapping_sub(ideal) & mask >= i.wrapping_sub(hole) & mask {
+                self.slots[hole] = next;
+                hole = i;
+            }
+
+            i = (i + 1) & mask;
+        }
+
+        self.slots[hole] = EMPTY;
+
+        self.unlink(entry);
+        self.free.push(entry);
+        self.len -= 1;
+
+        let e = &mut self.entries[entry as usize];
+        e.gen = e.gen.wrapping_add(1);
+        e.flow.take()
+    }
+
+    fn evict_if_full(&mut self) -> Option<(FlowKey, V)> {
+        if self.len < self.capacity {
+            return None;
+        }
+
+        let entry = self.tail;
+        let e = &self.entries[entry as usize];
+
+        let key = match &e.flow {
+            Some((key, _)) => *key,
+            None => unreachable!("the LRU tail is occupied"),
+        };
+
+        let (slot, _) = self.find(&key, e.hash)?;
+
+        self.evictions += 1;
+        self.remove_at(slot, entry)
+    }
+
+    fn refresh(&mut self, entry: u32, now: u64) {
+        // The wheel is updated lazily, when the entry's old slot comes
+        // round.
+        self.entries[entry as usize].deadline = now.saturating_add(self.idle_timeout);
+
+        if self.head != entry {
+            self.unlink(entry);
+            self.push_front(entry);
+        }
+    }
+
+    fn schedule(&mut self, entry: u32, deadline: u64) {
+        // Round up, so a flow is never visited before it's due.
+        let tick = deadline.div_ceil(self.granularity);
+        let gen = self.entries[entry as usize].gen;
+
+        self.wheel[tick as usize % WHEEL_SLOTS].push((entry, gen));
+    }
+
+    fn push_front(&mut self, entry: u32) {
+        let e = &mut self.entries[entry as usize];
+        e.prev = NIL;
+        e.next = self.head;
+
+        if self.head != NIL {
+            self.entries[self.head as usize].prev = entry;
+        } else {
+            self.tail = entry;
+        }
+
+        self.head = entry;
+    }
+
+    fn unlink(&mut self, entry: u32) {
+        let (prev, next) = {
+            let e = &self.entries[entry as usize];
+            (e.prev, e.next)
+        };
+
+        if prev != NIL {
+            self.entries[prev as usize].next = next;
+        } else {
+            self.head = next;
+        }
+
+        if next != NIL {
+            self.entries[next as usize].prev = prev;
+        } else {
+            self.tail = prev;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use crate::packet::IPPROTO_UDP;
+
+    use super::*;
+
+    fn key(port: u16) -> FlowKey {
+        FlowKey::new(
+            IpAddr::V4([10, 0, 0, 1].into()),
+            IpAddr::V4([10, 0, 0, 2].into()),
+            IPPROTO_UDP,
+            Some((port, 53)),
+        )
+    }
+
+    #[test]
+    fn colliding_keys_survive_removal_from_the_middle_of_a_probe_run() {
+        // Every key hashes to the same slot.
+        let mut flows = FlowTable::with_hasher(|_: &FlowKey| 7, 8, 100);
+
+        for port in 0..8 {
+            assert!(flows.insert(key(port), port, 0).is_none());
+        }
+
+        assert_eq!(flows.remove(&key(3)), Some(3));
+        assert_eq!(flows.remove(&key(0)), Some(0));
+        assert_eq!(flows.remove(&key(3)), None);
+
+        for port in [1, 2, 4, 5, 6, 7] {
+            assert_eq!(flows.get(&key(port)), Some(&port));
+        }
+
+        assert_eq!(flows.len(), 6);
+    }
+
+    #[test]
+    fn least_recently_used_flow_is_evicted_when_full() {
+        let mut flows = FlowTable::new(3, 100);
+
+        for port in 0..3 {
+            *flows.get_or_insert_with(&key(port), 0, || 0) += 1;
+        }
+
+        // Flow 0 becomes the most recently used, leaving 1 the oldest.
+        flows.touch(&key(0), 1);
+
+        assert_eq!(flows.insert(key(3), 0, 2), Some((key(1), 1)));
+        assert_eq!(flows.evictions(), 1);
+
+        let order: Vec<_> = flows.iter().map(|(k, _)| *k).collect();
+        assert_eq!(order, [key(3), key(0), key(2)]);
+
+        // Re-inserting a present flow replaces its state instead.
+        assert_eq!(flows.insert(key(2), 5, 3), Some((key(2), 1)));
+        assert_eq!(flows.len(), 3);
+    }
+
+    #[test]
+    fn idle_flows_expire_unless_touched() {
+        let mut flows = FlowTable::new(16, 1024);
+
+        for port in 0..4 {
+            flows.insert(key(port), port, 0);
+        }
+
+        flows.touch(&key(2), 600);
+
+        assert_eq!(flows.expire(1023, |_, _| ()), 0);
+
+        let mut expired = Vec::new();
+        assert_eq!(flows.expire(1024, |k, v| expired.push((k, v))), 3);
+
+        expired.sort_by_key(|(_, v)| *v);
+        assert_eq!(expired, [(key(0), 0), (key(1), 1), (key(3), 3)]);
+
+        assert_eq!(flows.get(&key(2)), Some(&2));
+
+        // Jumping well past a full revolution still catches it.
+        assert_eq!(flows.expire(1_000_000, |_, _| ()), 1);
+        assert!(flows.is_empty());
+        assert_eq!(flows.expirations(), 4);
+    }
+
+    #[test]
+    fn evicted_entries_are_not_expired_once_reused() {
+        let mut flows = FlowTable::new(1, 10);
+
+        flows.insert(key(0), 0, 0);
+        flows.insert(key(1), 1, 5);
+
+        // Flow 0's wheel slot comes round first, but its entry now
+        // belongs to flow 1 which isn't due yet.
+        assert_eq!(flows.expire(10, |_, _| ()), 0);
+        assert_eq!(flows.expire(15, |_, _| ()), 1);
+    }
+}
@@ -0,0 +1,582 @@
+//! Creating a socket per queue of an interface, with each queue's
+//! [`Umem`] placed on the NIC's NUMA node.
+
+use std::{
+    error::Error,
+    fmt, io,
+    num::NonZeroU32,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{Interface, SocketConfig, UmemConfig},
+    numa::{self, Placement},
+    socket::{Socket, SocketCreateError},
+    umem::{frame::FrameDesc, CompQueue, FillQueue, Umem, UmemCreateError},
+    RxQueue, TxQueue,
+};
+
+/// A [`Umem`] and AF_XDP [`Socket`] for one queue of a
+/// [`QueueGroup`].
+///
+/// Each member has its own [`Umem`], so all four queues are always
+/// present.
+#[derive(Debug)]
+pub struct QueueGroupMember {
+    /// The interface queue id the socket is bound to.
+    pub queue_id: u32,
+    /// The queue's [`Umem`].
+    pub umem: Umem,
+    /// Descriptors for all frames of [`umem`](Self::umem).
+    pub descs: Vec<FrameDesc>,
+    /// The socket's tx queue.
+    pub tx_q: TxQueue,
+    /// The socket's rx queue.
+    pub rx_q: RxQueue,
+    /// The [`umem`](Self::umem)'s fill queue.
+    pub fq: FillQueue,
+    /// The [`umem`](Self::umem)'s completion queue.
+    pub cq: CompQueue,
+    /// Where the queue's worker thread should run, see
+    /// [`Placement::spawn`].
+    pub placement: Placement,
+}
+
+/// How many descriptors are consumed from the completion and rx
+/// queues at a time while shutting down.
+const SHUTDOWN_BATCH: usize = 64;
+
+impl QueueGroupMember {
+    /// Stop the queue and account for every frame of its [`Umem`].
+    ///
+    /// [`descs`](Self::descs) is taken to be the frames the
+    /// application holds free, so any it still has elsewhere should
+    /// be put back first. Nothing more is produced to the tx or fill
+    /// queues. Until everything is accounted for or `timeout` passes,
+    /// frames the kernel finishes sending are consumed from the
+    /// completion queue and frames received are consumed from the rx
+    /// queue, both going back on to [`descs`](Self::descs) rather than
+    /// the fill queue.
+    ///
+    /// The returned report then reconciles [`descs`](Self::descs)
+    /// against the frames still on the tx and fill queues.
+    pub fn shutdown(&mut self, timeout: Duration) -> io::Result<ShutdownReport> {
+        let deadline = Instant::now() + timeout;
+        let total = self.umem.frame_count();
+
+        let mut scratch = self
+            .descs
+            .first()
+            .copied()
+            .map_or_else(Vec::new, |desc| vec![desc; SHUTDOWN_BATCH]);
+
+        loop {
+            let in_fill_ring = self.fq.fill_level();
+            let in_tx_ring = self.tx_q.fill_level();
+
+            if self.descs.len() + in_fill_ring >= total && in_tx_ring == 0 {
+                break;
+            }
+
+            if scratch.is_empty() || Instant::now() >= deadline {
+                break;
+            }
+
+            // SAFETY: the descriptors come from and go back to this
+            // member's own UMEM.
+            let completed = unsafe { self.cq.consume(&mut scratch) };
+            self.descs.extend_from_slice(&scratch[..completed]);
+
+            // SAFETY: as above.
+            let received = unsafe { self.rx_q.consume(&mut scratch) };
+            self.descs.extend_from_slice(&scratch[..received]);
+
+            if in_tx_ring > 0 && self.tx_q.needs_wakeup() {
+                self.tx_q.wakeup()?;
+            }
+
+            if completed == 0 && received == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+
+        let (in_fill_ring, in_tx_ring) = (self.fq.fill_level(), self.tx_q.fill_level());
+
+        Ok(ShutdownReport::tally(
+            total,
+            self.descs
+                .iter()
+                .map(|desc| self.umem.frame_index(desc.addr())),
+            in_fill_ring,
+            in_tx_ring,
+        ))
+    }
+}
+
+/// A set of sockets bound to queues of the same interface.
+///
+/// On creation the interface's NUMA node is looked up and each
+/// queue's [`Umem`] is allocated while pinned to that node, so the
+/// packet memory is local to the NIC. Worker threads should then be
+/// started with each member's [`placement`](QueueGroupMember::placement)
+/// to keep them on the same node.
+#[derive(Debug)]
+pub struct QueueGroup {
+    members: Vec<QueueGroupMember>,
+    report: QueueGroupReport,
+}
+
+impl QueueGroup {
+    /// Create a [`Umem`] with `frame_count` frames and a socket for
+    /// each of `queue_ids` on `if_name`.
+    ///
+    /// May require root permissions to create successfully.
+    pub fn new(
+        if_name: &Interface,
+        queue_ids: &[u32],
+        frame_count: NonZeroU32,
+        umem_config: UmemConfig,
+        socket_config: SocketConfig,
+        use_huge_pages: bool,
+    ) -> Result<Self, QueueGroupError> {
+        let placement = Placement::for_interface(if_name).map_err(QueueGroupError::Placement)?;
+
+        let mut members = Vec::with_capacity(queue_ids.len());
+        let mut queues = Vec::with_capacity(queue_ids.len());
+
+        for &queue_id in queue_ids {
+            let (umem, descs) = placement
+                .run(|| Umem::new(umem_config, frame_count, use_huge_pages))
+                .map_err(QueueGroupError::Placement)?
+                .map_err(|err| QueueGroupError::Umem { queue_id, err })?;
+
+            // SAFETY: the UMEM was just created and is not shared.
+            let (tx_q, rx_q, fq_and_cq) =
+                unsafe { Socket::new(socket_config, &umem, if_name, queue_id) }
+                    .map_err(|err| QueueGroupError::Socket { queue_id, err })?;
+
+            let (fq, cq) = fq_and_cq.ok_or(QueueGroupError::MissingQueues { queue_id })?;
+
+            queues.push(QueueReport {
+                queue_id,
+                cpus: placement.cpus().to_vec(),
+                umem_node: numa::page_node(umem.mem_ptr()).ok().flatten(),
+            });
+
+            members.push(QueueGroupMember {
+                queue_id,
+                umem,
+                descs,
+                tx_q,
+                rx_q,
+                fq,
+                cq,
+                placement: placement.clone(),
+            });
+        }
+
+        Ok(Self {
+            members,
+            report: QueueGroupReport {
+                interface_node: placement.numa_node(),
+                queues,
+            },
+        })
+    }
+
+    /// Shut down every member, see [`QueueGroupMember::shutdown`],
+    /// with `timeout` applying to each in turn.
+    pub fn shutdown(&mut self, timeout: Duration) -> io::Result<GroupShutdownReport> {
+        let queues = self
+            .members
+            .iter_mut()
+            .map(|m| m.shutdown(timeout).map(|report| (m.queue_id, report)))
+            .collect::<io::Result<_>>()?;
+
+        Ok(GroupShutdownReport { queues })
+    }
+
+    /// Where each queue's memory and threads were placed.
+    pub fn report(&self) -> &QueueGroupReport {
+        &self.report
+    }
+
+    /// The group's members, in the order their queue ids were given.
+    pub fn members(&self) -> &[QueueGroupMember] {
+        &self.members
+    }
+
+    /// Take the group's members, e.g. to hand each off to a worker
+    /// thread.
+    pub fn into_members(self) -> Vec<QueueGroupMember> {
+        self.members
+    }
+}
+
+/// Where a [`QueueGroup`]'s queues were placed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueGroupReport {
+    interface_node: Option<u32>,
+    queues: Vec<QueueReport>,
+}
+
+impl QueueGroupReport {
+    /// The NUMA node of the interface's device, if known.
+    pub fn interface_node(&self) -> Option<u32> {
+        self.interface_node
+    }
+
+    /// Per-queue placement.
+    pub fn queues(&self) -> &[QueueReport] {
+        &self.queues
+    }
+
+    /// Whether the memory of every queue whose node could be
+    /// determined is on the interface's node. Always `true` if the
+    /// interface's node is unknown.
+    pub fn is_local(&self) -> bool {
+        match self.interface_node {
+            Some(node) => self
+                .queues
+                .iter()
+                .all(|q| q.umem_node.unwrap_or(node) == node),
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for QueueGroupReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.interface_node {
+            Some(node) => writeln!(f, "interface node: {}", node)?,
+            None => writeln!(f, "interface node: unknown")?,
+        }
+
+        for q in self.queues.iter() {
+            write!(f, "queue {}: umem node ", q.queue_id)?;
+
+            match q.umem_node {
+                Some(node) => write!(f, "{}", node)?,
+                None => write!(f, "unknown")?,
+            }
+
+            writeln!(f, ", cpus {:?}", q.cpus)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Placement of a single [`QueueGroup`] queue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueReport {
+    queue_id: u32,
+    cpus: Vec<usize>,
+    umem_node: Option<u32>,
+}
+
+impl QueueReport {
+    /// The queue id.
+    pub fn queue_id(&self) -> u32 {
+        self.queue_id
+    }
+
+    /// The CPUs the queue's worker should be pinned to. Empty if
+    /// unconstrained.
+    pub fn cpus(&self) -> &[usize] {
+        &self.cpus
+    }
+
+    /// The node the queue's [`Umem`] memory actually lives on, if it
+    /// could be determined.
+    pub fn umem_node(&self) -> Option<u32> {
+        self.umem_node
+    }
+}
+
+/// How a [`QueueGroupMember`]'s frames were accounted for on
+/// [`shutdown`](QueueGroupMember::shutdown).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownReport {
+    total: usize,
+    free: usize,
+    in_fill_ring: usize,
+    in_tx_ring: usize,
+    duplicates: usize,
+    foreign: usize,
+}
+
+impl ShutdownReport {
+    /// Reconcile the frame indices of the free descriptors against
+    /// the ring occupancy.
+    fn tally<I>(total: usize, free: I, in_fill_ring: usize, in_tx_ring: usize) -> Self
+    where
+        I: IntoIterator<Item = Option<usize>>,
+    {
+        let mut seen = vec![false; total];
+        let mut report = Self {
+            total,
+            free: 0,
+            in_fill_ring,
+            in_tx_ring,
+            duplicates: 0,
+            foreign: 0,
+        };
+
+        for idx in free {
+            match idx.and_then(|idx| seen.get_mut(idx)) {
+                Some(seen) if *seen => report.duplicates += 1,
+                Some(seen) => {
+                    *seen = true;
+                    report.free += 1;
+                }
+                None => report.foreign += 1,
+            }
+        }
+
+        report
+    }
+
+    /// The number of frames in the [`Umem`].
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Distinct frames back in [`descs`](QueueGroupMember::descs).
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    /// Frames still on the fill queue, waiting to be received into.
+    pub fn in_fill_ring(&self) -> usize {
+        self.in_fill_ring
+    }
+
+    /// Frames still on the tx queue, not yet sent within the timeout.
+    pub fn in_tx_ring(&self) -> usize {
+        self.in_tx_ring
+    }
+
+    /// Frames held by the kernel, i.e. on the fill or tx queue.
+    pub fn kernel_held(&self) -> usize {
+        self.in_fill_ring + self.in_tx_ring
+    }
+
+    /// Frames neither free nor on a ring.
+    ///
+    /// These are usually frames the application is still holding on
+    /// to, or lost track of. Zero-copy drivers may also have taken
+    /// frames off the fill queue in advance to post to the NIC, which
+    /// only come back once received into, so a few here aren't
+    /// necessarily a leak in that mode.
+    pub fn leaked(&self) -> usize {
+        self.total
+            .saturating_sub(self.free)
+            .saturating_sub(self.kernel_held())
+    }
+
+    /// Descriptors in [`descs`](QueueGroupMember::descs) pointing to
+    /// a frame already counted as free.
+    pub fn duplicates(&self) -> usize {
+        self.duplicates
+    }
+
+    /// Descriptors in [`descs`](QueueGroupMember::descs) pointing
+    /// outside the [`Umem`]'s frames.
+    pub fn foreign(&self) -> usize {
+        self.foreign
+    }
+
+    /// Whether every frame is free, with no duplicate or foreign
+    /// descriptors.
+    pub fn is_clean(&self) -> bool {
+        self.free == self.total && self.duplicates == 0 && self.foreign == 0
+    }
+}
+
+impl fmt::Display for ShutdownReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} frames: {} free, {} kernel-held ({} fill, {} tx), {} leaked",
+            self.total,
+            self.free,
+            self.kernel_held(),
+            self.in_fill_ring,
+            self.in_tx_ring,
+            self.leaked()
+        )?;
+
+        if self.duplicates > 0 || self.foreign > 0 {
+            write!(
+                f,
+                ", {} duplicate and {} foreign descs",
+                self.duplicates, self.foreign
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The [`ShutdownReport`] of each queue of a [`QueueGroup`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GroupShutdownReport {
+    queues: Vec<(u32, ShutdownReport)>,
+}
+
+impl GroupShutdownReport {
+    /// Each queue id and its report, in the group's order.
+    pub fn queues(&self) -> &[(u32, ShutdownReport)] {
+        &self.queues
+    }
+
+    /// Whether every queue's report is
+    /// [clean](ShutdownReport::is_clean).
+    pub fn is_clean(&self) -> bool {
+        self.queues.iter().all(|(_, report)| report.is_clean())
+    }
+
+    /// Frames leaked across all queues.
+    pub fn leaked(&self) -> usize {
+        self.queues.iter().map(|(_, report)| report.leaked()).sum()
+    }
+
+    /// Frames held by the kernel across all queues.
+    pub fn kernel_held(&self) -> usize {
+        self.queues
+            .iter()
+            .map(|(_, report)| report.kernel_held())
+            .sum()
+    }
+}
+
+impl fmt::Display for GroupShutdownReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (queue_id, report) in self.queues.iter() {
+            writeln!(f, "queue {}: {}", queue_id, report)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Error detailing why [`QueueGroup`] creation failed.
+#[derive(Debug)]
+pub enum QueueGroupError {
+    /// Looking up or applying the NUMA placement failed.
+    Placement(io::Error),
+    /// Creating a queue's [`Umem`] failed.
+    Umem {
+        /// The queue in question.
+        queue_id: u32,
+        /// The underlying error.
+        err: UmemCreateError,
+    },
+    /// Creating a queue's socket failed.
+    Socket {
+        /// The queue in question.
+        queue_id: u32,
+        /// The underlying error.
+        err: SocketCreateError,
+    },
+    /// Socket creation didn't return a fill queue and completion
+    /// queue, which implies the queue was already bound to.
+    MissingQueues {
+        /// The queue in question.
+        queue_id: u32,
+    },
+}
+
+impl fmt::Display for QueueGroupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Placement(_) => write!(f, "failed to place queue group on interface's NUMA node"),
+            Self::Umem { queue_id, .. } => {
+                write!(f, "failed to create UMEM for queue {}", queue_id)
+            }
+            Self::Socket { queue_id, .. } => {
+                write!(f, "failed to create socket for queue {}", queue_id)
+            }
+            Self::MissingQueues { queue_id } => write!(
+                f,
+                "no fill queue and completion queue returned for queue {}",
+                queue_id
+            ),
+        }
+    }
+}
+
+impl Error for QueueGroupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Placement(err) => Some(err),
+            Self::Umem { err, .. } => Some(err),
+            Self::Socket { err, .. } => Some(err),
+            Self::MissingQueues { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(interface_node: Option<u32>, umem_nodes: &[Option<u32>]) -> QueueGroupReport {
+        QueueGroupReport {
+            interface_node,
+            queues: umem_nodes
+                .iter()
+                .enumerate()
+                .map(|(i, &umem_node)| QueueReport {
+                    queue_id: i as u32,
+                    cpus: vec![0, 1],
+                    umem_node,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn report_flags_remote_memory() {
+        assert!(report(Some(0), &[Some(0), None]).is_local());
+        assert!(!report(Some(0), &[Some(0), Some(1)]).is_local());
+        assert!(report(None, &[Some(1)]).is_local());
+    }
+
+    #[test]
+    fn report_is_displayed_per_queue() {
+        let s = report(Some(1), &[Some(1), None]).to_string();
+
+        assert_eq!(
+            s,
+            "interface node: 1\n\
+             queue 0: umem node 1, cpus [0, 1]\n\
+             queue 1: umem node unknown, cpus [0, 1]\n"
+        );
+    }
+
+    #[test]
+    fn shutdown_report_reconciles_free_frames_with_rings() {
+        let free = [Some(0), Some(1), Some(1), None, Some(9), Some(3)];
+        let report = ShutdownReport::tally(8, free.iter().copied(), 2, 1);
+
+        assert_eq!(report.free(), 3);
+        assert_eq!(report.duplicates(), 1);
+        assert_eq!(report.foreign(), 2);
+        assert_eq!(report.kernel_held(), 3);
+        assert_eq!(report.leaked(), 2);
+        assert!(!report.is_clean());
+
+        assert_eq!(
+            report.to_string(),
+            "8 frames: 3 free, 3 kernel-held (2 fill, 1 tx), 2 leaked, \
+             1 duplicate and 2 foreign descs"
+        );
+
+        let clean = ShutdownReport::tally(2, vec![Some(1), Some(0)], 0, 0);
+        assert!(clean.is_clean());
+        assert_eq!(clean.leaked(), 0);
+    }
+}
@@ -0,0 +1,781 @@
+//! Handing received frames to another process without copying them.
+//!
+//! A [`Handoff`] is a pair of single producer, single consumer rings
+//! in shared memory: one carries frame descriptors from a
+//! [`HandoffSender`], e.g. a capture process reading from an
+//! [`RxQueue`](crate::RxQueue), to a [`HandoffReceiver`] in another
+//! process, and the other carries them back once the receiver is done
+//! with them, so the sender can recycle them onto its
+//! [`FillQueue`](crate::FillQueue).
+//!
+//! Only descriptors cross the rings, so the receiving process needs
+//! the [`Umem`](crate::Umem) mapped at the same offsets too. A
+//! `Umem`'s memory is shared rather than private, so a process forked
+//! after the `Umem` was created has it already, and can read frames
+//! with its copy of the `Umem`:
+//!
+//! ```no_run
+//! use xsk_rs::{config::QueueSize, handoff::Handoff, FrameDesc, Xsk};
+//! # fn run(mut xsk: Xsk) {
+//! let handoff = Handoff::new(QueueSize::new(2048).unwrap()).unwrap();
+//! let mut descs = vec![FrameDesc::default(); 64];
+//!
+//! if unsafe { libc::fork() } == 0 {
+//!     // Analysis process.
+//!     let mut rx = handoff.into_receiver();
+//!
+//!     loop {
+//!         let cnt = unsafe { rx.recv(&mut descs) };
+//!
+//!         for desc in &descs[..cnt] {
+//!             let pkt = unsafe { xsk.umem.data(desc) };
+//!             // ... inspect the packet ...
+//!         }
+//!
+//!         unsafe { rx.give_back(&descs[..cnt]) };
+//!     }
+//! }
+//!
+//! // Capture process.
+//! let mut tx = handoff.into_sender();
+//!
+//! loop {
+//!     let cnt = unsafe { xsk.rx_q.consume(&mut descs) };
+//!     let sent = unsafe { tx.send(&descs[..cnt]) };
+//!
+//!     // No room, so drop what didn't fit.
+//!     unsafe { xsk.fq.recycle_from_rx(&mut descs[sent..cnt]) };
+//!
+//!     let cnt = unsafe { tx.reclaim(&mut descs) };
+//!     unsafe { xsk.fq.recycle_from_rx(&mut descs[..cnt]) };
+//! }
+//! # }
+//! ```
+//!
+//! An unrelated process can instead be passed the handoff's
+//! [`fd`](Handoff::fd), e.g. over a unix socket or across an `exec`,
+//! and [`open`](Handoff::open) it. It has no copy of the `Umem` to
+//! read frames with, so the `Umem` must be created with
+//! [`memfd`](crate::config::UmemConfigBuilder::memfd) set and its
+//! [`memfd`](crate::Umem::memfd) passed along too, which the process
+//! maps with [`UmemMapping::open`].
+//!
+//! Neither side is woken when the other produces, so both should check
+//! their ring as part of their polling loop.
+
+use std::{
+    ffi::CStr,
+    io, mem,
+    os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use libc::{MAP_FAILED, MAP_SHARED, MFD_CLOEXEC, PROT_READ, PROT_WRITE};
+use log::error;
+
+use crate::{config::QueueSize, portable::FrameLayout, umem::frame::FrameDesc};
+
+/// Identifies a handoff's shared memory, and its layout version.
+const MAGIC: u64 = u64::from_be_bytes(*b"XSKHOFF1");
+
+/// Keeps each index on its own cache line, so the two processes
+/// don't contend over one.
+const CACHE_LINE: usize = 64;
+
+#[repr(C)]
+struct Header {
+    magic: u64,
+    size: u32,
+}
+
+#[repr(C, align(64))]
+struct Index(AtomicU32);
+
+#[repr(C)]
+struct RingIndices {
+    prod: Index,
+    cons: Index,
+}
+
+/// A frame descriptor as laid out in the shared memory.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SharedDesc {
+    addr: u64,
+    options: u32,
+    headroom: u32,
+    data: u32,
+    meta: u32,
+}
+
+impl From<&FrameDesc> for SharedDesc {
+    fn from(desc: &FrameDesc) -> Self {
+        Self {
+            addr: desc.addr as u64,
+            options: desc.options,
+            headroom: desc.lengths.headroom as u32,
+            data: desc.lengths.data as u32,
+            meta: desc.lengths.meta as u32,
+        }
+    }
+}
+
+impl SharedDesc {
+    fn write_to(&self, desc: &mut FrameDesc) {
+        desc.addr = self.addr as usize;
+        desc.options = self.options;
+        desc.lengths.headroom = self.headroom as usize;
+        desc.lengths.data = self.data as usize;
+        desc.lengths.meta = self.meta as usize;
+    }
+}
+
+/// Where each part of the shared memory lives, for rings of `size`
+/// entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    frames: usize,
+    returns: usize,
+    len: usize,
+}
+
+impl Layout {
+    fn new(size: u32) -> Self {
+        let size = size as usize;
+        let indices = mem::size_of::<RingIndices>();
+
+        let frames = CACHE_LINE;
+        let returns = round_up(
+            frames + indices + size * mem::size_of::<SharedDesc>(),
+            CACHE_LINE,
+        );
+        let len = returns + indices + size * mem::size_of::<u64>();
+
+        Self {
+            frames,
+            returns,
+            len,
+        }
+    }
+}
+
+fn round_up(len: usize, align: usize) -> usize {
+    len.div_ceil(align) * align
+}
+
+/// One of the rings, as seen from either end.
+struct Ring<T> {
+    indices: NonNull<RingIndices>,
+    slots: NonNull<T>,
+    size: u32,
+}
+
+impl<T: Copy> Ring<T> {
+    /// # Safety
+    ///
+    /// `base + offset` must be the start of a ring of `size`
+    /// entries within a live mapping.
+    unsafe fn new(base: NonNull<u8>, offset: usize, size: u32) -> Self {
+        // SAFETY: guaranteed by this function's contract.
+        unsafe {
+            let indices = base.as_ptr().add(offset);
+
+            Self {
+                indices: NonNull::new_unchecked(indices.cast()),
+                slots: NonNull::new_unchecked(indices.add(mem::size_of::<RingIndices>()).cast()),
+                size,
+            }
+        }
+    }
+
+    fn indices(&self) -> &RingIndices {
+        // SAFETY: the indices are within the mapping, which outlives
+        // the ring, and only ever accessed atomically.
+        unsafe { self.indices.as_ref() }
+    }
+
+    /// Append as many of `items` as there's room for, returning the
+    /// number appended. May only be called from the producing end.
+    fn push<U, F>(&mut self, items: &[U], f: F) -> usize
+    where
+        F: Fn(&U) -> T,
+    {
+        let indices = self.indices();
+
+        let prod = indices.prod.0.load(Ordering::Relaxed);
+        let cons = indices.cons.0.load(Ordering::Acquire);
+
+        let free = self.size.saturating_sub(prod.wrapping_sub(cons));
+        let cnt = free.min(items.len() as u32);
+
+        for (i, item) in items.iter().take(cnt as usize).enumerate() {
+            let idx = prod.wrapping_add(i as u32) & (self.size - 1);
+
+            // SAFETY: `idx` is within the ring, and the slot is free
+            // so the consumer isn't reading it.
+            unsafe { ptr::write(self.slots.as_ptr().add(idx as usize), f(item)) };
+        }
+
+        indices
+            .prod
+            .0
+            .store(prod.wrapping_add(cnt), Ordering::Release);
+
+        cnt as usize
+    }
+
+    /// Take as many entries into `out` as are waiting and fit,
+    /// returning the number taken. May only be called from the
+    /// consuming end.
+    fn pop<U, F>(&mut self, out: &mut [U], f: F) -> usize
+    where
+        F: Fn(&T, &mut U),
+    {
+        let indices = self.indices();
+
+        let cons = indices.cons.0.load(Ordering::Relaxed);
+        let prod = indices.prod.0.load(Ordering::Acquire);
+
+        // Clamped in case the other process corrupted the index.
+        let avail = prod.wrapping_sub(cons).min(self.size);
+        let cnt = avail.min(out.len() as u32);
+
+        for (i, item) in out.iter_mut().take(cnt as usize).enumerate() {
+            let idx = cons.wrapping_add(i as u32) & (self.size - 1);
+
+            // SAFETY: `idx` is within the ring, and the slot has been
+            // published by the producer.
+            let entry = unsafe { ptr::read(self.slots.as_ptr().add(idx as usize)) };
+
+            f(&entry, item);
+        }
+
+        indices
+            .cons
+            .0
+            .store(cons.wrapping_add(cnt), Ordering::Release);
+
+        cnt as usize
+    }
+
+    /// The number of entries waiting to be consumed.
+    fn len(&self) -> usize {
+        let indices = self.indices();
+
+        let prod = indices.prod.0.load(Ordering::Acquire);
+        let cons = indices.cons.0.load(Ordering::Acquire);
+
+        prod.wrapping_sub(cons).min(self.size) as usize
+    }
+}
+
+/// A mapping of a handoff's shared memory.
+#[derive(Debug)]
+struct Mapping {
+    addr: NonNull<u8>,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: &OwnedFd, len: usize) -> io::Result<Self> {
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+
+        if addr == MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            addr: NonNull::new(addr.cast()).expect("`mmap()` succeeded so non-null"),
+            len,
+        })
+    }
+
+    fn header(&self) -> *mut Header {
+        self.addr.as_ptr().cast()
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        let err = unsafe { libc::munmap(self.addr.as_ptr().cast(), self.len) };
+
+        if err != 0 {
+            error!(
+                "`munmap()` failed with error: {}",
+                io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// The shared memory of a handoff, before it's turned into one of its
+/// ends. See the [module docs](self).
+#[derive(Debug)]
+pub struct Handoff {
+    fd: OwnedFd,
+    map: Mapping,
+    size: u32,
+}
+
+// SAFETY: the mapping is only accessed through the ring indices,
+// atomically, and the slots they guard.
+unsafe impl Send for Handoff {}
+
+impl Handoff {
+    /// Create a handoff whose rings each hold `size` frames, in a new
+    /// memfd.
+    pub fn new(size: QueueSize) -> io::Result<Self> {
+        let name = CStr::from_bytes_with_nul(b"xsk-handoff\0").unwrap();
+
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), MFD_CLOEXEC) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: `fd` was just opened and is owned by nothing else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        let size = size.get();
+        let layout = Layout::new(size);
+
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), layout.len as libc::off_t) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let map = Mapping::new(&fd, layout.len)?;
+
+        // SAFETY: the memfd is new, so zeroed, and no one else has it
+        // mapped yet. Zero is a valid starting point for the indices.
+        unsafe { ptr::write(map.header(), Header { magic: MAGIC, size }) };
+
+        Ok(Self { fd, map, size })
+    }
+
+    /// Map a handoff created by another process with
+    /// [`new`](Self::new), given its [`fd`](Self::fd).
+    ///
+    /// # Errors
+    ///
+    /// If `fd` can't be mapped, or doesn't hold a handoff.
+    pub fn open(fd: OwnedFd) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        // SAFETY: all zeroes is a valid `stat`.
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let len = stat.st_size as usize;
+
+        if len < mem::size_of::<Header>() {
+            return Err(invalid("too small to be a handoff"));
+        }
+
+        let map = Mapping::new(&fd, len)?;
+
+        // SAFETY: the mapping is at least a header long.
+        let Header { magic, size } = unsafe { ptr::read(map.header()) };
+
+        if magic != MAGIC {
+            return Err(invalid("not a handoff"));
+        }
+
+        if !size.is_power_of_two() || Layout::new(size).len != len {
+            return Err(invalid("handoff header doesn't match its size"));
+        }
+
+        Ok(Self { fd, map, size })
+    }
+
+    /// The memfd holding the handoff, for passing to another process
+    /// to [`open`](Self::open).
+    ///
+    /// It's close-on-exec, so must be duplicated without
+    /// `FD_CLOEXEC` to survive an `exec`.
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+
+    /// The number of frames each ring holds.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Become the sending end.
+    pub fn into_sender(self) -> HandoffSender {
+        let layout = Layout::new(self.size);
+
+        // SAFETY: the layout was checked against the mapping's length
+        // when it was created or opened.
+        unsafe {
+            HandoffSender {
+                frames: Ring::new(self.map.addr, layout.frames, self.size),
+                returns: Ring::new(self.map.addr, layout.returns, self.size),
+                _handoff: self,
+            }
+        }
+    }
+
+    /// Become the receiving end.
+    pub fn into_receiver(self) -> HandoffReceiver {
+        let layout = Layout::new(self.size);
+
+        // SAFETY: as in `into_sender`.
+        unsafe {
+            HandoffReceiver {
+                frames: Ring::new(self.map.addr, layout.frames, self.size),
+                returns: Ring::new(self.map.addr, layout.returns, self.size),
+                _handoff: self,
+            }
+        }
+    }
+}
+
+/// The end of a [`Handoff`] which hands frames over and takes them
+/// back, see the [module docs](self).
+///
+/// There must be only one per handoff, across all processes.
+pub struct HandoffSender {
+    frames: Ring<SharedDesc>,
+    returns: Ring<u64>,
+    _handoff: Handoff,
+}
+
+// SAFETY: see `Handoff`.
+unsafe impl Send for HandoffSender {}
+
+impl HandoffSender {
+    /// Hand as many of `descs` over to the receiver as there's room
+    /// for, from the start. Returns the number handed over.
+    ///
+    /// # Safety
+    ///
+    /// The frames must not be used again until they come back via
+    /// [`reclaim`](Self::reclaim), and must belong to the
+    /// [`Umem`](crate::Umem) the receiver reads them from.
+    #[inline]
+    pub unsafe fn send(&mut self, descs: &[FrameDesc]) -> usize {
+        self.frames.push(descs, |desc| SharedDesc::from(desc))
+    }
+
+    /// Take back frames the receiver has finished with, into `descs`.
+    /// Returns the number taken, whose lengths and options are reset.
+    ///
+    /// # Safety
+    ///
+    /// The receiving process is trusted to only return frames it was
+    /// sent, once it's done with them.
+    #[inline]
+    pub unsafe fn reclaim(&mut self, descs: &mut [FrameDesc]) -> usize {
+        self.returns.pop(descs, |&addr, desc| {
+            *desc = FrameDesc {
+                addr: addr as usize,
+                ..FrameDesc::default()
+            }
+        })
+    }
+
+    /// The number of frames sent and not yet taken by the receiver.
+    pub fn pending(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+impl std::fmt::Debug for HandoffSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("HandoffSender")
+            .field("size", &self.frames.size)
+            .finish()
+    }
+}
+
+/// The end of a [`Handoff`] which takes frames and returns them, see
+/// the [module docs](self).
+///
+/// There must be only one per handoff, across all processes.
+pub struct HandoffReceiver {
+    frames: Ring<SharedDesc>,
+    returns: Ring<u64>,
+    _handoff: Handoff,
+}
+
+// SAFETY: see `Handoff`.
+unsafe impl Send for HandoffReceiver {}
+
+impl HandoffReceiver {
+    /// Take frames sent by the sender into `descs`, returning the
+    /// number taken.
+    ///
+    /// # Safety
+    ///
+    /// The sending process is trusted to only send frames of the
+    /// [`Umem`](crate::Umem) they're read from, which it no longer
+    /// uses. Received frames must be [given back](Self::give_back)
+    /// once done with, and not used after.
+    #[inline]
+    pub unsafe fn recv(&mut self, descs: &mut [FrameDesc]) -> usize {
+        self.frames.pop(descs, SharedDesc::write_to)
+    }
+
+    /// Return as many of `descs` to the sender as there's room for,
+    /// from the start. Returns the number returned.
+    ///
+    /// The rings are the same size, so there's room for every frame
+    /// received and not yet given back as long as the sender keeps
+    /// [reclaiming](HandoffSender::reclaim) them. If it stops, this
+    /// returns fewer than `descs.len()` once the ring fills.
+    ///
+    /// # Safety
+    ///
+    /// The frames must have been received from this handoff, and not
+    /// be used again.
+    #[inline]
+    pub unsafe fn give_back(&mut self, descs: &[FrameDesc]) -> usize {
+        self.returns.push(descs, |desc| desc.addr as u64)
+    }
+
+    /// The number of frames waiting to be received.
+    pub fn pending(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+impl std::fmt::Debug for HandoffReceiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("HandoffReceiver")
+            .field("size", &self.frames.size)
+            .finish()
+    }
+}
+
+/// A [`Umem`](crate::Umem)'s memory mapped into a process which
+/// didn't create it, for reading the frames a [`HandoffReceiver`] is
+/// sent. See the [module docs](self).
+#[derive(Debug)]
+pub struct UmemMapping {
+    _fd: OwnedFd,
+    map: Mapping,
+}
+
+// SAFETY: frames are only accessed through the unsafe accessors,
+// whose callers ensure no one else is writing them.
+unsafe impl Send for UmemMapping {}
+
+impl UmemMapping {
+    /// Map the memory of a `Umem` created by another process, given
+    /// its [`memfd`](crate::Umem::memfd).
+    ///
+    /// # Errors
+    ///
+    /// If `fd` is empty or can't be mapped.
+    pub fn open(fd: OwnedFd) -> io::Result<Self> {
+        // SAFETY: all zeroes is a valid `stat`.
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let len = stat.st_size as usize;
+
+        if len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "empty UMEM memfd",
+            ));
+        }
+
+        let map = Mapping::new(&fd, len)?;
+
+        Ok(Self { _fd: fd, map })
+    }
+
+    /// The size of the mapped memory.
+    pub fn len(&self) -> usize {
+        self.map.len
+    }
+
+    /// Always `false`, since empty memfds can't be opened.
+    pub fn is_empty(&self) -> bool {
+        self.map.len == 0
+    }
+
+    /// The packet data of the frame described by `desc`, or [`None`]
+    /// if it lies outside the mapping.
+    ///
+    /// # Safety
+    ///
+    /// `desc` must have been [received](HandoffReceiver::recv) and
+    /// not yet given back, so that neither the sender nor the kernel
+    /// are writing the frame.
+    #[inline]
+    pub unsafe fn data(&self, desc: &FrameDesc) -> Option<&[u8]> {
+        let (start, len) = self.bounds(desc)?;
+
+        // SAFETY: in bounds of the live mapping, and the frame isn't
+        // being written per this function's contract.
+        Some(unsafe { std::slice::from_raw_parts(self.map.addr.as_ptr().add(start), len) })
+    }
+
+    /// The packet data of the frame described by `desc`, mutably, or
+    /// [`None`] if it lies outside the mapping.
+    ///
+    /// # Safety
+    ///
+    /// See [`data`](Self::data).
+    #[inline]
+    pub unsafe fn data_mut(&mut self, desc: &FrameDesc) -> Option<&mut [u8]> {
+        let (start, len) = self.bounds(desc)?;
+
+        // SAFETY: see `data`.
+        Some(unsafe { std::slice::from_raw_parts_mut(self.map.addr.as_ptr().add(start), len) })
+    }
+
+    /// The offset and length of `desc`'s packet data, if within the
+    /// mapping. Resolving the address as in unaligned chunk mode is
+    /// harmless in aligned mode, where the upper bits are never set.
+    fn bounds(&self, desc: &FrameDesc) -> Option<(usize, usize)> {
+        let start = FrameLayout::resolve_addr(desc.addr, true);
+        let end = start.checked_add(desc.lengths.data)?;
+
+        (end <= self.map.len).then_some((start, desc.lengths.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desc(addr: usize, data: usize) -> FrameDesc {
+        let mut desc = FrameDesc {
+            addr,
+            ..FrameDesc::default()
+        };
+
+        desc.lengths.data = data;
+        desc.set_continued(true);
+        desc
+    }
+
+    /// Both ends, each with its own mapping of the memfd, as if in
+    /// separate processes.
+    fn ends(size: u32) -> (HandoffSender, HandoffReceiver) {
+        let handoff = Handoff::new(QueueSize::new(size).unwrap()).unwrap();
+        let fd = handoff.fd().try_clone_to_owned().unwrap();
+
+        let rx = Handoff::open(fd).unwrap().into_receiver();
+
+        (handoff.into_sender(), rx)
+    }
+
+    #[test]
+    fn rings_are_cache_line_aligned_and_fit() {
+        let layout = Layout::new(4);
+
+        assert_eq!(layout.frames % CACHE_LINE, 0);
+        assert_eq!(layout.returns % CACHE_LINE, 0);
+        assert_eq!(mem::size_of::<RingIndices>(), 2 * CACHE_LINE);
+
+        assert_eq!(layout.returns, round_up(64 + 128 + 4 * 24, CACHE_LINE));
+        assert_eq!(layout.len, layout.returns + 128 + 4 * 8);
+    }
+
+    #[test]
+    fn frames_make_a_round_trip_between_mappings() {
+        let (mut tx, mut rx) = ends(4);
+
+        let sent = [desc(0, 60), desc(2048, 1500)];
+        let mut descs = [FrameDesc::default(); 4];
+
+        unsafe {
+            assert_eq!(tx.send(&sent), 2);
+            assert_eq!(rx.pending(), 2);
+
+            assert_eq!(rx.recv(&mut descs), 2);
+            assert_eq!(rx.recv(&mut descs), 0);
+
+            for (got, sent) in descs.iter().zip(&sent) {
+                assert_eq!(got.addr(), sent.addr());
+                assert_eq!(got.lengths().data(), sent.lengths().data());
+                assert!(got.is_continued());
+            }
+
+            assert_eq!(rx.give_back(&descs[..2]), 2);
+
+            let mut back = [FrameDesc::default(); 4];
+
+            assert_eq!(tx.reclaim(&mut back), 2);
+            assert_eq!(back[1].addr(), 2048);
+            assert_eq!(back[1].lengths().data(), 0);
+            assert_eq!(back[1].options(), 0);
+        }
+    }
+
+    #[test]
+    fn send_stops_when_the_ring_is_full_and_wraps() {
+        let (mut tx, mut rx) = ends(4);
+
+        let sent: Vec<_> = (0..6).map(|i| desc(i * 2048, 64)).collect();
+        let mut descs = [FrameDesc::default(); 3];
+
+        unsafe {
+            assert_eq!(tx.send(&sent), 4);
+            assert_eq!(tx.send(&sent[4..]), 0);
+
+            assert_eq!(rx.recv(&mut descs), 3);
+            assert_eq!(tx.send(&sent[4..]), 2);
+            assert_eq!(tx.pending(), 3);
+
+            assert_eq!(rx.recv(&mut descs), 3);
+            assert_eq!(descs[2].addr(), 5 * 2048);
+        }
+    }
+
+    #[test]
+    fn umem_mapping_reads_frames_within_bounds() {
+        let name = CStr::from_bytes_with_nul(b"umem\0").unwrap();
+
+        let fd = unsafe { OwnedFd::from_raw_fd(libc::memfd_create(name.as_ptr(), MFD_CLOEXEC)) };
+        assert_eq!(unsafe { libc::ftruncate(fd.as_raw_fd(), 4096) }, 0);
+
+        let mut writer = UmemMapping::open(fd.try_clone().unwrap()).unwrap();
+        let reader = UmemMapping::open(fd).unwrap();
+
+        assert_eq!(reader.len(), 4096);
+
+        unsafe {
+            writer
+                .data_mut(&desc(2048, 3))
+                .unwrap()
+                .copy_from_slice(b"abc");
+
+            assert_eq!(reader.data(&desc(2048, 3)).unwrap(), b"abc");
+            assert!(reader.data(&desc(4094, 3)).is_none());
+            assert!(reader.data(&desc(usize::MAX, 3)).is_none());
+        }
+    }
+
+    #[test]
+    fn opening_something_other_than_a_handoff_fails() {
+        let name = CStr::from_bytes_with_nul(b"not-a-handoff\0").unwrap();
+
+        let fd = unsafe { OwnedFd::from_raw_fd(libc::memfd_create(name.as_ptr(), MFD_CLOEXEC)) };
+        assert_eq!(unsafe { libc::ftruncate(fd.as_raw_fd(), 4096) }, 0);
+
+        let err = Handoff::open(fd).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,669 @@
+//! Summarising a socket's liveness as a single status, e.g. for the
+//! readiness and liveness probes of a dataplane pod.
+//!
+//! The dataplane loop counts what it receives, submits and has
+//! completed on an [`Activity`], which is cheap to update and can be
+//! shared with whichever thread serves the probes. That thread then
+//! periodically calls [`HealthMonitor::check`], which compares the
+//! counts, the kernel's drop counters and the link state against the
+//! previous check:
+//!
+//! ```no_run
+//! use std::{sync::Arc, time::Instant};
+//! use xsk_rs::health::{Activity, HealthMonitor, LinkState};
+//! # let if_name = "eth0".parse().unwrap();
+//!
+//! let activity = Arc::new(Activity::new());
+//!
+//! // In the dataplane loop, `activity.rx(n)`, `activity.submitted(n)`
+//! // and `activity.completed(n)` after each ring operation.
+//!
+//! let mut monitor = HealthMonitor::new(Instant::now());
+//!
+//! let link = LinkState::of(&if_name).unwrap_or(LinkState::Unknown);
+//! let report = monitor.check(&activity, None, link, Instant::now());
+//!
+//! let code = if report.is_ready() { 200 } else { 503 };
+//! println!("{} {}", code, report);
+//! ```
+//!
+//! Whether the path beyond the link works can be tracked with a
+//! [`Heartbeat`], which hands the dataplane a probe frame to transmit
+//! every interval and recognises the peer's answers among received
+//! frames. It counts both on the shared [`Activity`], and a monitor
+//! with a [`path_timeout`](HealthMonitor::path_timeout) flags
+//! [`PATH_DOWN`](Issues::PATH_DOWN) when probes go unanswered, which
+//! is the signal to fail over to another path or socket.
+
+use std::{
+    fmt, fs, io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use bitflags::bitflags;
+
+use crate::{config::Interface, socket::XdpStatistics};
+
+/// The default time frames may be outstanding on the tx ring with
+/// none completing before tx is considered stalled.
+pub const DEFAULT_TX_STALL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Counts of ring activity, updated by the dataplane and read by a
+/// [`HealthMonitor`]. Every method takes `&self`, so it can be shared
+/// through an `Arc`.
+#[derive(Debug, Default)]
+pub struct Activity {
+    rx: AtomicU64,
+    submitted: AtomicU64,
+    completed: AtomicU64,
+    probes_sent: AtomicU64,
+    probes_answered: AtomicU64,
+}
+
+impl Activity {
+    /// Create a zeroed set of counts.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `n` frames consumed from the rx ring.
+    #[inline]
+    pub fn rx(&self, n: usize) {
+        self.rx.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Record `n` frames submitted to the tx ring.
+    #[inline]
+    pub fn submitted(&self, n: usize) {
+        self.submitted.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Record `n` frames consumed from the completion ring.
+    #[inline]
+    pub fn completed(&self, n: usize) {
+        self.completed.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Record a heartbeat probe handed to the tx ring. Done by
+    /// [`Heartbeat::poll`].
+    #[inline]
+    pub fn probe_sent(&self) {
+        self.probes_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record an answer to a heartbeat probe received. Done by
+    /// [`Heartbeat::on_rx`].
+    #[inline]
+    pub fn probe_answered(&self) {
+        self.probes_answered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Counts {
+        Counts {
+            rx: self.rx.load(Ordering::Relaxed),
+            submitted: self.submitted.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            probes_sent: self.probes_sent.load(Ordering::Relaxed),
+            probes_answered: self.probes_answered.load(Ordering::Relaxed),
+            ..Counts::default()
+        }
+    }
+}
+
+/// An interface's operational state, as read from sysfs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkState {
+    /// The link is up.
+    Up,
+    /// The link is down, or its lower layer is.
+    Down,
+    /// The driver doesn't report a state, as is the case for
+    /// loopback and some virtual devices. Treated as up.
+    Unknown,
+}
+
+impl LinkState {
+    /// Read the state of `if_name` from
+    /// `/sys/class/net/<if_name>/operstate`.
+    pub fn of(if_name: &Interface) -> io::Result<Self> {
+        let name = if_name
+            .as_cstr()
+            .to_str()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let state = fs::read_to_string(format!("/sys/class/net/{}/operstate", name))?;
+
+        Ok(match state.trim() {
+            "up" => Self::Up,
+            "down" | "lowerlayerdown" | "notpresent" | "dormant" => Self::Down,
+            _ => Self::Unknown,
+        })
+    }
+}
+
+bitflags! {
+    /// Problems found by a [`HealthMonitor`] check.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct Issues: u32 {
+        /// The link is down.
+        const LINK_DOWN = 1 << 0;
+        /// Frames have been outstanding on the tx ring for longer
+        /// than the stall timeout without any completing.
+        const TX_STALLED = 1 << 1;
+        /// Nothing has been received for longer than the rx idle
+        /// timeout.
+        const RX_IDLE = 1 << 2;
+        /// The kernel found the fill ring empty when it had frames to
+        /// deliver.
+        const FILL_STARVED = 1 << 3;
+        /// The kernel dropped received frames, e.g. because the rx
+        /// ring was full.
+        const RX_DROPPED = 1 << 4;
+        /// The kernel rejected invalid descriptors on the rx or tx
+        /// path.
+        const INVALID_DESCS = 1 << 5;
+        /// Heartbeat probes have gone unanswered for longer than the
+        /// path timeout.
+        const PATH_DOWN = 1 << 6;
+    }
+}
+
+const UNHEALTHY: Issues = Issues::LINK_DOWN
+    .union(Issues::TX_STALLED)
+    .union(Issues::PATH_DOWN);
+
+const ISSUE_NAMES: [(Issues, &str); 7] = [
+    (Issues::LINK_DOWN, "link down"),
+    (Issues::TX_STALLED, "tx stalled"),
+    (Issues::RX_IDLE, "rx idle"),
+    (Issues::FILL_STARVED, "fill ring starved"),
+    (Issues::RX_DROPPED, "rx dropped"),
+    (Issues::INVALID_DESCS, "invalid descriptors"),
+    (Issues::PATH_DOWN, "path down"),
+];
+
+/// Overall health of a socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    /// No problems found.
+    Healthy,
+    /// Still passing traffic, but losing some or seeing none.
+    Degraded,
+    /// Not passing traffic: the link or path is down, or tx has
+    /// stalled.
+    Unhealthy,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Healthy => write!(f, "healthy"),
+            Self::Degraded => write!(f, "degraded"),
+            Self::Unhealthy => write!(f, "unhealthy"),
+        }
+    }
+}
+
+/// The result of a [`HealthMonitor::check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    status: Status,
+    issues: Issues,
+}
+
+impl Report {
+    /// The overall status.
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Everything found wrong.
+    pub fn issues(&self) -> Issues {
+        self.issues
+    }
+
+    /// Whether the socket should receive traffic, i.e. it isn't
+    /// [`Unhealthy`](Status::Unhealthy). For readiness probes.
+    pub fn is_ready(&self) -> bool {
+        self.status != Status::Unhealthy
+    }
+
+    /// Whether the dataplane is making progress. Only a tx stall fails
+    /// this, since restarting won't bring a link up. For liveness
+    /// probes.
+    pub fn is_live(&self) -> bool {
+        !self.issues.contains(Issues::TX_STALLED)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.status)?;
+
+        let mut sep = ": ";
+
+        for (issue, name) in ISSUE_NAMES.iter() {
+            if self.issues.contains(*issue) {
+                write!(f, "{}{}", sep, name)?;
+                sep = ", ";
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Counts {
+    rx: u64,
+    submitted: u64,
+    completed: u64,
+    fill_empty: u64,
+    dropped: u64,
+    invalid: u64,
+    probes_sent: u64,
+    probes_answered: u64,
+}
+
+impl Counts {
+    fn with_stats(mut self, stats: &XdpStatistics) -> Self {
+        self.fill_empty = stats.rx_fill_ring_empty_descs().unwrap_or(0);
+        self.dropped = stats.rx_dropped() + stats.rx_ring_full().unwrap_or(0);
+        self.invalid = stats.rx_invalid_descs() + stats.tx_invalid_descs();
+        self
+    }
+}
+
+/// Turns [`Activity`], drop counters and link state into a
+/// [`Report`], see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct HealthMonitor {
+    tx_stall_timeout: Duration,
+    rx_idle_timeout: Option<Duration>,
+    last: Counts,
+    last_rx: Instant,
+    last_completion: Instant,
+    path_timeout: Option<Duration>,
+    unanswered_since: Option<Instant>,
+}
+
+impl HealthMonitor {
+    /// Create a monitor, with `now` as the time of the last activity.
+    pub fn new(now: Instant) -> Self {
+        Self {
+            tx_stall_timeout: DEFAULT_TX_STALL_TIMEOUT,
+            rx_idle_timeout: None,
+            last: Counts::default(),
+            last_rx: now,
+            last_completion: now,
+            path_timeout: None,
+            unanswered_since: None,
+        }
+    }
+
+    /// Set how long frames may be outstanding on the tx ring with
+    /// none completing before tx is considered stalled.
+    ///
+    /// Defaults to [`DEFAULT_TX_STALL_TIMEOUT`].
+    pub fn tx_stall_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.tx_stall_timeout = timeout;
+        self
+    }
+
+    /// Set how long may pass with nothing received before the socket
+    /// is considered [`Degraded`](Status::Degraded). `None`, the
+    /// default, never flags it, since a quiet link is often normal.
+    pub fn rx_idle_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.rx_idle_timeout = timeout;
+        self
+    }
+
+    /// Set how long [`Heartbeat`] probes may go unanswered before the
+    /// path is considered down. `None`, the default, ignores probes.
+    ///
+    /// Should be a few probe intervals, so that a single lost probe or
+    /// answer doesn't flag it.
+    pub fn path_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.path_timeout = timeout;
+        self
+    }
+
+    /// Compare `activity`, the kernel's drop counters in `stats` if
+    /// available, e.g. from
+    /// [`Fd::xdp_statistics`](crate::socket::Fd::xdp_statistics), and
+    /// `link` against the previous check.
+    ///
+    /// Activity is only noticed at each check, so the timeouts are
+    /// only as precise as the interval between checks.
+    pub fn check(
+        &mut self,
+        activity: &Activity,
+        stats: Option<&XdpStatistics>,
+        link: LinkState,
+        now: Instant,
+    ) -> Report {
+        let mut counts = activity.snapshot();
+
+        counts = match stats {
+            Some(stats) => counts.with_stats(stats),
+            // Without stats, don't flag anything as having changed.
+            None => Counts {
+                fill_empty: self.last.fill_empty,
+                dropped: self.last.dropped,
+                invalid: self.last.invalid,
+                ..counts
+            },
+        };
+
+        self.evaluate(counts, link, now)
+    }
+
+    fn evaluate(&mut self, counts: Counts, link: LinkState, now: Instant) -> Report {
+        let last = self.last;
+        let mut issues = Issues::empty();
+
+        if link == LinkState::Down {
+            issues |= Issues::LINK_DOWN;
+        }
+
+        if counts.rx != last.rx {
+            self.last_rx = now;
+        }
+
+        if counts.completed != last.completed {
+            self.last_completion = now;
+        }
+
+        let outstanding = counts.submitted > counts.completed;
+
+        if !outstanding {
+            // Nothing to wait on, so nothing can have stalled.
+            self.last_completion = now;
+        } else if now.saturating_duration_since(self.last_completion) > self.tx_stall_timeout {
+            issues |= Issues::TX_STALLED;
+        }
+
+        if let Some(timeout) = self.rx_idle_timeout {
+            if now.saturating_duration_since(self.last_rx) > timeout {
+                issues |= Issues::RX_IDLE;
+            }
+        }
+
+        if counts.probes_answered != last.probes_answered {
+            self.unanswered_since = None;
+        } else if counts.probes_sent != last.probes_sent && self.unanswered_since.is_none() {
+            self.unanswered_since = Some(now);
+        }
+
+        if let (Some(timeout), Some(since)) = (self.path_timeout, self.unanswered_since) {
+            if now.saturating_duration_since(since) > timeout {
+                issues |= Issues::PATH_DOWN;
+            }
+        }
+
+        if counts.fill_empty > last.fill_empty {
+            issues |= Issues::FILL_STARVED;
+        }
+
+        if counts.dropped > last.dropped {
+            issues |= Issues::RX_DROPPED;
+        }
+
+        if counts.invalid > last.invalid {
+            issues |= Issues::INVALID_DESCS;
+        }
+
+        self.last = counts;
+
+        let status = if issues.intersects(UNHEALTHY) {
+            Status::Unhealthy
+        } else if issues.is_empty() {
+            Status::Healthy
+        } else {
+            Status::Degraded
+        };
+
+        Report { status, issues }
+    }
+}
+
+type AnswerFn = dyn Fn(&[u8]) -> bool + Send;
+
+/// Generates heartbeat probes and recognises their answers, see the
+/// [module docs](self).
+///
+/// What a probe is and what counts as an answer are up to the
+/// application, e.g. an ICMP echo request to the next hop and its
+/// reply, or a frame the peer reflects back unchanged. Without an
+/// [`answer`](Self::answer) matcher, probes are only sent, which
+/// still exercises the tx path for keepalive purposes.
+///
+/// ```no_run
+/// use std::{sync::Arc, time::{Duration, Instant}};
+/// use xsk_rs::health::{Activity, Heartbeat};
+/// # let probe = vec![0u8; 64];
+///
+/// let activity = Arc::new(Activity::new());
+/// let mut heartbeat = Heartbeat::new(
+///     Arc::clone(&activity),
+///     probe,
+///     Duration::from_millis(100),
+///     Instant::now(),
+/// );
+/// heartbeat.answer(|frame| frame.get(12..14) == Some(&[0x88, 0xb5]));
+///
+/// // In the dataplane loop:
+/// if let Some(probe) = heartbeat.poll(Instant::now()) {
+///     // Copy `probe` into a free frame and submit it to the tx ring.
+/// }
+///
+/// // And for each received frame, skipping the ones that answer.
+/// # let frame = [0u8; 64];
+/// if !heartbeat.on_rx(&frame) {
+///     // Handle the frame.
+/// }
+/// ```
+pub struct Heartbeat {
+    activity: Arc<Activity>,
+    probe: Vec<u8>,
+    interval: Duration,
+    next: Instant,
+    answer: Option<Box<AnswerFn>>,
+}
+
+impl Heartbeat {
+    /// Create a generator sending `probe` every `interval`, counting
+    /// on `activity`. The first probe is due at `now`.
+    pub fn new(activity: Arc<Activity>, probe: Vec<u8>, interval: Duration, now: Instant) -> Self {
+        Self {
+            activity,
+            probe,
+            interval,
+            next: now,
+            answer: None,
+        }
+    }
+
+    /// Set how to recognise a received frame as an answer to a probe.
+    pub fn answer<F>(&mut self, matcher: F) -> &mut Self
+    where
+        F: Fn(&[u8]) -> bool + Send + 'static,
+    {
+        self.answer = Some(Box::new(matcher));
+        self
+    }
+
+    /// The probe frame.
+    #[inline]
+    pub fn probe(&self) -> &[u8] {
+        &self.probe
+    }
+
+    /// The time between probes.
+    #[inline]
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Return the probe if one is due at `now`, counting it as sent
+    /// and scheduling the next one.
+    ///
+    /// Should only be called when the probe can be transmitted, since
+    /// one dropped here for lack of a free frame would still count as
+    /// unanswered.
+    pub fn poll(&mut self, now: Instant) -> Option<&[u8]> {
+        if now < self.next {
+            return None;
+        }
+
+        // Schedule from now rather than the missed deadline, so a
+        // stalled loop doesn't catch up with a burst of probes.
+        self.next = now + self.interval;
+        self.activity.probe_sent();
+
+        Some(&self.probe)
+    }
+
+    /// Check whether a received `frame` answers a probe, counting it
+    /// if so. Always `false` without an [`answer`](Self::answer)
+    /// matcher.
+    pub fn on_rx(&self, frame: &[u8]) -> bool {
+        match &self.answer {
+            Some(matcher) if matcher(frame) => {
+                self.activity.probe_answered();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for Heartbeat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Heartbeat")
+            .field("probe_len", &self.probe.len())
+            .field("interval", &self.interval)
+            .field("next", &self.next)
+            .field("answer", &self.answer.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tx_stalls_only_while_frames_are_outstanding() {
+        let start = Instant::now();
+        let secs = |s| start + Duration::from_secs(s);
+
+        let mut monitor = HealthMonitor::new(start);
+
+        let mut counts = Counts {
+            submitted: 10,
+            ..Counts::default()
+        };
+
+        let report = monitor.evaluate(counts, LinkState::Up, secs(1));
+        assert_eq!(report.status(), Status::Healthy);
+
+        let report = monitor.evaluate(counts, LinkState::Up, secs(3));
+        assert_eq!(report.issues(), Issues::TX_STALLED);
+        assert!(!report.is_ready() && !report.is_live());
+
+        counts.completed = 10;
+        assert!(monitor
+            .evaluate(counts, LinkState::Up, secs(4))
+            .issues()
+            .is_empty());
+
+        // Idle, with nothing outstanding, for a long while.
+        assert!(monitor
+            .evaluate(counts, LinkState::Up, secs(60))
+            .issues()
+            .is_empty());
+
+        counts.submitted = 11;
+        assert!(monitor
+            .evaluate(counts, LinkState::Up, secs(61))
+            .issues()
+            .is_empty());
+    }
+
+    #[test]
+    fn drops_degrade_and_link_down_is_unhealthy_but_live() {
+        let start = Instant::now();
+        let mut monitor = HealthMonitor::new(start);
+        monitor.rx_idle_timeout(Some(Duration::from_secs(5)));
+
+        let counts = Counts {
+            rx: 100,
+            fill_empty: 3,
+            ..Counts::default()
+        };
+
+        let report = monitor.evaluate(counts, LinkState::Unknown, start);
+        assert_eq!(report.status(), Status::Degraded);
+        assert_eq!(report.issues(), Issues::FILL_STARVED);
+        assert_eq!(report.to_string(), "degraded: fill ring starved");
+
+        // Counters which haven't moved since the last check are fine.
+        let report = monitor.evaluate(counts, LinkState::Up, start + Duration::from_secs(1));
+        assert_eq!(report.status(), Status::Healthy);
+
+        let report = monitor.evaluate(counts, LinkState::Down, start + Duration::from_secs(6));
+        assert_eq!(report.issues(), Issues::LINK_DOWN | Issues::RX_IDLE);
+        assert_eq!(report.to_string(), "unhealthy: link down, rx idle");
+        assert!(!report.is_ready());
+        assert!(report.is_live());
+    }
+
+    #[test]
+    fn unanswered_probes_take_the_path_down() {
+        let start = Instant::now();
+        let ms = |m| start + Duration::from_millis(m);
+
+        let activity = Arc::new(Activity::new());
+        let mut heartbeat = Heartbeat::new(
+            Arc::clone(&activity),
+            vec![0xaa; 60],
+            Duration::from_millis(100),
+            start,
+        );
+        heartbeat.answer(|frame| frame.first() == Some(&0xbb));
+
+        let mut monitor = HealthMonitor::new(start);
+        monitor.path_timeout(Some(Duration::from_millis(250)));
+
+        assert_eq!(heartbeat.poll(start), Some(&[0xaa; 60][..]));
+        assert!(heartbeat.poll(ms(50)).is_none());
+        assert!(heartbeat.on_rx(&[0xbb]));
+        assert!(!heartbeat.on_rx(&[0xcc]));
+
+        let report = monitor.evaluate(activity.snapshot(), LinkState::Up, ms(60));
+        assert!(report.issues().is_empty());
+
+        // Probes keep going out, but nothing comes back.
+        for t in [100, 200, 300] {
+            assert!(heartbeat.poll(ms(t)).is_some());
+            let report = monitor.evaluate(activity.snapshot(), LinkState::Up, ms(t));
+            assert!(report.issues().is_empty(), "at {}ms", t);
+        }
+
+        assert!(heartbeat.poll(ms(400)).is_some());
+        let report = monitor.evaluate(activity.snapshot(), LinkState::Up, ms(400));
+        assert_eq!(report.issues(), Issues::PATH_DOWN);
+        assert_eq!(report.to_string(), "unhealthy: path down");
+        assert!(!report.is_ready());
+        assert!(report.is_live());
+
+        assert!(heartbeat.on_rx(&[0xbb]));
+        let report = monitor.evaluate(activity.snapshot(), LinkState::Up, ms(410));
+        assert_eq!(report.status(), Status::Healthy);
+    }
+}
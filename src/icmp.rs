@@ -0,0 +1,373 @@
+//! Answering pings from user space.
+//!
+//! With an XDP program redirecting everything on a queue to a socket,
+//! the kernel never sees ICMP echo requests arriving there, so the
+//! host stops answering pings. [`reply_in_place`] turns a received
+//! echo request into its reply within the same frame, ready to be
+//! sent straight back out on the [`TxQueue`](crate::TxQueue):
+//!
+//! ```no_run
+//! # use std::convert::TryInto;
+//! # use xsk_rs::{config::{SocketConfig, UmemConfig}, driver::Driver, Xsk};
+//! # let xsk = Xsk::build(
+//! #     &"eth0".parse().unwrap(),
+//! #     0,
+//! #     UmemConfig::default(),
+//! #     SocketConfig::default(),
+//! #     4096.try_into().unwrap(),
+//! # )
+//! # .unwrap();
+//! use xsk_rs::icmp;
+//!
+//! let mut driver = Driver::new(xsk).unwrap();
+//!
+//! driver.on_rx(|batch| {
+//!     for i in 0..batch.len() {
+//!         let mut pkt = batch.data(i).contents().to_vec();
+//!
+//!         if icmp::reply_in_place(&mut pkt) {
+//!             batch.send(&pkt);
+//!         }
+//!     }
+//! });
+//! ```
+//!
+//! Both ICMP over IPv4 and ICMPv6 are handled, behind up to two VLAN
+//! tags. Requests sent to a multicast or broadcast address, arriving
+//! in IPv4 fragments, carrying IPv6 extension headers or failing
+//! their checksum are left alone, as the kernel would.
+
+use crate::{
+    addr, checksum,
+    packet::{self, ETH_P_IPV4, ETH_P_IPV6, IPPROTO_ICMP, IPPROTO_ICMPV6, IPV6_HLEN},
+};
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Smallest ICMP message, the header of an echo with no payload.
+const ICMP_HLEN: usize = 8;
+
+/// The TTL or hop limit replies are sent with.
+const REPLY_TTL: u8 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Family {
+    V4,
+    V6,
+}
+
+/// Where an echo request's headers are in its frame.
+#[derive(Debug, Clone, Copy)]
+struct Echo {
+    family: Family,
+    l3: usize,
+    l4: usize,
+}
+
+/// Whether `frame` holds an ICMP or ICMPv6 echo request that
+/// [`reply_in_place`] would answer.
+pub fn is_echo_request(frame: &[u8]) -> bool {
+    locate(frame).is_some()
+}
+
+/// Rewrite the echo request in `frame` into its reply, returning
+/// whether it was one. Anything else is left untouched.
+///
+/// The MAC and IP addresses are swapped, the TTL or hop limit reset,
+/// and the ICMP type and checksums updated. The reply is the same
+/// length as the request, so the frame can be sent as it is.
+pub fn reply_in_place(frame: &mut [u8]) -> bool {
+    let echo = match locate(frame) {
+        Some(echo) => echo,
+        None => return false,
+    };
+
+    addr::swap_eth_addrs(frame);
+
+    let l3 = echo.l3;
+    let l4 = echo.l4;
+
+    let (addrs, ttl, request, reply) = match echo.family {
+        Family::V4 => (l3 + 12..l3 + 20, l3 + 8, ICMP_ECHO_REQUEST, ICMP_ECHO_REPLY),
+        Family::V6 => (
+            l3 + 8..l3 + 40,
+            l3 + 7,
+            ICMPV6_ECHO_REQUEST,
+            ICMPV6_ECHO_REPLY,
+        ),
+    };
+
+    let half = (addrs.end - addrs.start) / 2;
+    for i in addrs.start..addrs.start + half {
+        frame.swap(i, i + half);
+    }
+
+    frame[ttl] = REPLY_TTL;
+
+    if echo.family == Family::V4 {
+        let check = checksum::ipv4_header(&frame[l3..l4]);
+        frame[l3 + 10..l3 + 12].copy_from_slice(&check.to_be_bytes());
+    }
+
+    // Swapping the addresses leaves the ICMPv6 pseudo header's sum as
+    // it was, so for both only the type needs accounting for.
+    let code = frame[l4 + 1];
+    let check = u16::from_be_bytes([frame[l4 + 2], frame[l4 + 3]]);
+    let check = checksum::update(
+        check,
+        u16::from_be_bytes([request, code]),
+        u16::from_be_bytes([reply, code]),
+    );
+
+    frame[l4] = reply;
+    frame[l4 + 2..l4 + 4].copy_from_slice(&check.to_be_bytes());
+
+    true
+}
+
+fn locate(frame: &[u8]) -> Option<Echo> {
+    // Replying from a multicast MAC would be nonsense.
+    if addr::read_mac(frame, 0)?.is_multicast() {
+        return None;
+    }
+
+    let (ethertype, l3) = packet::l3(frame)?;
+
+    let echo = match ethertype {
+        ETH_P_IPV4 => locate_v4(frame, l3)?,
+        ETH_P_IPV6 => locate_v6(frame, l3)?,
+        _ => return None,
+    };
+
+    // No code is defined for echo requests besides zero.
+    if frame[echo.l4 + 1] != 0 {
+        return None;
+    }
+
+    Some(echo)
+}
+
+fn locate_v4(frame: &[u8], l3: usize) -> Option<Echo> {
+    let header = frame.get(l3..l3 + 20)?;
+
+    let ihl = (header[0] & 0x0f) as usize * 4;
+    let total_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let dst = addr::read_ipv4(header, 16)?;
+
+    if header[0] >> 4 != 4
+        || ihl < 20
+        || header[9] != IPPROTO_ICMP
+        // More fragments or a non-zero offset.
+        || u16::from_be_bytes([header[6], header[7]]) & 0x3fff != 0
+        || dst.is_multicast()
+        || dst.is_broadcast()
+        || total_len < ihl + ICMP_HLEN
+    {
+        return None;
+    }
+
+    let l4 = l3 + ihl;
+    let msg = frame.get(l4..l3 + total_len)?;
+
+    if msg[0] != ICMP_ECHO_REQUEST
+        || checksum::ipv4_header(&frame[l3..l4]) != u16::from_be_bytes([header[10], header[11]])
+        || checksum::checksum(msg) != 0
+    {
+        return None;
+    }
+
+    Some(Echo {
+        family: Family::V4,
+        l3,
+        l4,
+    })
+}
+
+fn locate_v6(frame: &[u8], l3: usize) -> Option<Echo> {
+    let header = frame.get(l3..l3 + IPV6_HLEN)?;
+
+    let payload_len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let dst = addr::read_ipv6(header, 24)?;
+
+    if header[0] >> 4 != 6
+        || header[6] != IPPROTO_ICMPV6
+        || dst.is_multicast()
+        || payload_len < ICMP_HLEN
+    {
+        return None;
+    }
+
+    let l4 = l3 + IPV6_HLEN;
+    let msg = frame.get(l4..l4 + payload_len)?;
+
+    // The pseudo header: addresses, upper layer length and next
+    // header.
+    let mut acc = checksum::sum(&header[8..40], 0);
+    acc = checksum::sum(&(payload_len as u32).to_be_bytes(), acc);
+    acc = checksum::sum(&[0, 0, 0, IPPROTO_ICMPV6], acc);
+
+    if msg[0] != ICMPV6_ECHO_REQUEST || checksum::finish(checksum::sum(msg, acc)) != 0 {
+        return None;
+    }
+
+    Some(Echo {
+        family: Family::V6,
+        l3,
+        l4,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::TryFrom, net::Ipv6Addr};
+
+    use super::*;
+    use crate::packet::{ETH_HLEN, ETH_P_8021Q};
+
+    const MAC_A: [u8; 6] = [0x02, 0, 0, 0, 0, 0x0a];
+    const MAC_B: [u8; 6] = [0x02, 0, 0, 0, 0, 0x0b];
+
+    fn eth(vlan: bool, ethertype: u16) -> Vec<u8> {
+        let mut frame = MAC_A.to_vec();
+        frame.extend_from_slice(&MAC_B);
+
+        if vlan {
+            frame.extend_from_slice(&ETH_P_8021Q.to_be_bytes());
+            frame.extend_from_slice(&[0x00, 0x05]);
+        }
+
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame
+    }
+
+    fn echo4(vlan: bool, dst: [u8; 4]) -> Vec<u8> {
+        let mut icmp = vec![ICMP_ECHO_REQUEST, 0, 0, 0, 0x12, 0x34, 0x00, 0x01];
+        icmp.extend_from_slice(b"abcdefghijk");
+        let check = checksum::checksum(&icmp);
+        icmp[2..4].copy_from_slice(&check.to_be_bytes());
+
+        let mut ip = vec![0x45, 0, 0, 0, 0, 0, 0x40, 0, 17, IPPROTO_ICMP, 0, 0];
+        ip[2..4].copy_from_slice(&((20 + icmp.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[10, 0, 0, 1]);
+        ip.extend_from_slice(&dst);
+        let check = checksum::ipv4_header(&ip);
+        ip[10..12].copy_from_slice(&check.to_be_bytes());
+
+        let mut frame = eth(vlan, ETH_P_IPV4);
+        frame.extend_from_slice(&ip);
+        frame.extend_from_slice(&icmp);
+        // Ethernet padding past the IP packet.
+        frame.extend_from_slice(&[0; 4]);
+        frame
+    }
+
+    fn icmp6_check(header: &[u8], msg: &[u8]) -> u16 {
+        let mut acc = checksum::sum(&header[8..40], 0);
+        acc = checksum::sum(&(msg.len() as u32).to_be_bytes(), acc);
+        acc = checksum::sum(&[0, 0, 0, IPPROTO_ICMPV6], acc);
+        checksum::finish(checksum::sum(msg, acc))
+    }
+
+    fn echo6(dst: Ipv6Addr) -> Vec<u8> {
+        let mut icmp = vec![ICMPV6_ECHO_REQUEST, 0, 0, 0, 0x12, 0x34, 0x00, 0x01];
+        icmp.extend_from_slice(b"ping");
+
+        let mut ip = vec![0x60, 0, 0, 0, 0, icmp.len() as u8, IPPROTO_ICMPV6, 5];
+        ip.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        ip.extend_from_slice(&dst.octets());
+
+        let check = icmp6_check(&ip, &icmp);
+        icmp[2..4].copy_from_slice(&check.to_be_bytes());
+
+        let mut frame = eth(false, ETH_P_IPV6);
+        frame.extend_from_slice(&ip);
+        frame.extend_from_slice(&icmp);
+        frame
+    }
+
+    #[test]
+    fn ipv4_requests_are_turned_into_valid_replies() {
+        for &vlan in &[false, true] {
+            let mut frame = echo4(vlan, [10, 0, 0, 2]);
+            let request = frame.clone();
+
+            assert!(reply_in_place(&mut frame));
+
+            let l3 = if vlan { ETH_HLEN + 4 } else { ETH_HLEN };
+            let ip = &frame[l3..l3 + 20];
+            let icmp = &frame[l3 + 20..frame.len() - 4];
+
+            assert_eq!(&frame[..6], &MAC_B);
+            assert_eq!(&frame[6..12], &MAC_A);
+            assert_eq!(&ip[12..16], &[10, 0, 0, 2]);
+            assert_eq!(&ip[16..20], &[10, 0, 0, 1]);
+            assert_eq!(ip[8], REPLY_TTL);
+            assert_eq!(
+                checksum::ipv4_header(ip),
+                u16::from_be_bytes([ip[10], ip[11]])
+            );
+
+            assert_eq!(icmp[0], ICMP_ECHO_REPLY);
+            assert_eq!(checksum::checksum(icmp), 0);
+            assert_eq!(&icmp[4..], &request[l3 + 24..request.len() - 4]);
+
+            assert!(!is_echo_request(&frame));
+        }
+    }
+
+    #[test]
+    fn ipv6_requests_are_turned_into_valid_replies() {
+        let mut frame = echo6("2001:db8::2".parse().unwrap());
+
+        assert!(reply_in_place(&mut frame));
+
+        let ip = &frame[ETH_HLEN..ETH_HLEN + IPV6_HLEN];
+        let icmp = &frame[ETH_HLEN + IPV6_HLEN..];
+
+        assert_eq!(ip[7], REPLY_TTL);
+        assert_eq!(
+            Ipv6Addr::from(<[u8; 16]>::try_from(&ip[8..24]).unwrap()),
+            "2001:db8::2".parse::<Ipv6Addr>().unwrap()
+        );
+        assert_eq!(icmp[0], ICMPV6_ECHO_REPLY);
+        assert_eq!(icmp6_check(ip, icmp), 0);
+    }
+
+    #[test]
+    fn anything_but_a_valid_unicast_request_is_left_alone() {
+        let mut bad_check = echo4(false, [10, 0, 0, 2]);
+        bad_check[ETH_HLEN + 20 + 8] ^= 0xff;
+
+        let mut fragment = echo4(false, [10, 0, 0, 2]);
+        fragment[ETH_HLEN + 6] |= 0x20;
+
+        let mut reply = echo4(false, [10, 0, 0, 2]);
+        assert!(reply_in_place(&mut reply));
+
+        let mut multicast_mac = echo4(false, [10, 0, 0, 2]);
+        multicast_mac[0] = 0x01;
+
+        let truncated = echo4(false, [10, 0, 0, 2])[..ETH_HLEN + 24].to_vec();
+
+        for frame in [
+            bad_check,
+            fragment,
+            reply,
+            multicast_mac,
+            truncated,
+            echo4(false, [255, 255, 255, 255]),
+            echo4(false, [224, 0, 0, 1]),
+            echo6("ff02::1".parse().unwrap()),
+            packet::tests::udp4_frame(0, 1, 2),
+        ]
+        .iter_mut()
+        {
+            let before = frame.clone();
+            assert!(!reply_in_place(frame));
+            assert_eq!(*frame, before);
+        }
+    }
+}
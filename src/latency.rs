@@ -0,0 +1,440 @@
+//! One-way delay measurement between two hosts, from timestamps
+//! carried in a trailer after each packet's data.
+//!
+//! The sending host appends a [`Trailer`] to each frame with a
+//! [`TrailerStamper`] just before submitting it, and the receiving
+//! host feeds what it receives to a [`DelayRecorder`], which takes
+//! the trailer's timestamp from its own clock's reading to build up
+//! a distribution of delays:
+//!
+//! ```no_run
+//! use xsk_rs::latency::{Clock, DelayRecorder, TrailerStamper};
+//! # let (umem, mut desc): (xsk_rs::Umem, xsk_rs::FrameDesc) = todo!();
+//! # let frame: &[u8] = todo!();
+//!
+//! // The sender, once the packet is written.
+//! let mut stamper = TrailerStamper::new(Clock::Realtime);
+//! stamper.stamp(&mut unsafe { umem.data_mut(&mut desc) })?;
+//!
+//! // The receiver, for each frame received.
+//! let mut recorder = DelayRecorder::new(Clock::Realtime);
+//! recorder.record(frame);
+//!
+//! let delays = recorder.delays();
+//! println!("p50 {}ns p99 {}ns", delays.percentile(0.5), delays.percentile(0.99));
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! One-way delays are only as good as the agreement between the two
+//! hosts' clocks, so they should be synced, e.g. with PTP, and the
+//! [`Clock`] read on both sides be the one being synced. Fixed
+//! offsets, e.g. the time between stamping and the frame hitting the
+//! wire, can be calibrated out on either side.
+//!
+//! The trailer goes in the frame's tailroom, past the end of the
+//! packet's headers as far as the network is concerned, so routers
+//! and the receiving stack ignore it. Short frames are padded to the
+//! ethernet minimum first, so padding added on the wire doesn't push
+//! the trailer away from the end of the frame.
+
+use std::io;
+
+use crate::{stats::Histogram, umem::frame::DataMut};
+
+/// The length of a [`Trailer`] on the wire.
+pub const TRAILER_LEN: usize = 20;
+
+const MAGIC: [u8; 4] = *b"XSKT";
+
+/// The shortest ethernet frame, without the FCS, that's sent without
+/// padding.
+const MIN_FRAME_LEN: usize = 60;
+
+/// How far behind the highest sequence number received a frame can
+/// arrive and still be told apart from a duplicate.
+const WINDOW: u64 = 1 << 16;
+
+/// The clock timestamps are read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Clock {
+    /// `CLOCK_REALTIME`, the one `phc2sys` usually disciplines.
+    Realtime,
+    /// `CLOCK_TAI`, which doesn't jump at leap seconds.
+    Tai,
+}
+
+impl Clock {
+    /// The clock's current reading, in nanoseconds.
+    pub fn now(self) -> u64 {
+        let id = match self {
+            Self::Realtime => libc::CLOCK_REALTIME,
+            Self::Tai => libc::CLOCK_TAI,
+        };
+
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        // SAFETY: `ts` is a valid timespec and both clocks exist on
+        // all supported kernels.
+        unsafe { libc::clock_gettime(id, &mut ts) };
+
+        ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+    }
+}
+
+/// A timestamp and sequence number at the end of a frame, see the
+/// [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trailer {
+    seq: u64,
+    tx_time: u64,
+}
+
+impl Trailer {
+    /// Read the trailer from the end of `frame`, if it ends with one.
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        let start = frame.len().checked_sub(TRAILER_LEN)?;
+        let trailer = &frame[start..];
+
+        if trailer[..4] != MAGIC {
+            return None;
+        }
+
+        let mut seq = [0; 8];
+        let mut tx_time = [0; 8];
+
+        seq.copy_from_slice(&trailer[4..12]);
+        tx_time.copy_from_slice(&trailer[12..]);
+
+        Some(Self {
+            seq: u64::from_be_bytes(seq),
+            tx_time: u64::from_be_bytes(tx_time),
+        })
+    }
+
+    /// The frame's position in the sender's sequence, from zero.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// When the frame was stamped, in nanoseconds by the sender's
+    /// clock.
+    pub fn tx_time(&self) -> u64 {
+        self.tx_time
+    }
+
+    /// Append the trailer to the `len` bytes of `buf`, padding short
+    /// frames first, returning the new length, or [`None`] if `buf`
+    /// has no room for it.
+    fn append(&self, buf: &mut [u8], len: usize) -> Option<usize> {
+        let start = len.max(MIN_FRAME_LEN - TRAILER_LEN);
+        let end = start + TRAILER_LEN;
+
+        if end > buf.len() {
+            return None;
+        }
+
+        buf[len..start].fill(0);
+        buf[start..start + 4].copy_from_slice(&MAGIC);
+        buf[start + 4..start + 12].copy_from_slice(&self.seq.to_be_bytes());
+        buf[start + 12..end].copy_from_slice(&self.tx_time.to_be_bytes());
+
+        Some(end)
+    }
+}
+
+/// Appends a [`Trailer`] to frames before they're sent, numbering
+/// them in order.
+#[derive(Debug, Clone)]
+pub struct TrailerStamper {
+    clock: Clock,
+    seq: u64,
+    offset: i64,
+}
+
+impl TrailerStamper {
+    /// Stamp times from `clock`.
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            seq: 0,
+            offset: 0,
+        }
+    }
+
+    /// Add `offset` nanoseconds to each timestamp, e.g. the measured
+    /// time between stamping a frame and it leaving the interface.
+    /// Default is zero.
+    pub fn calibrate(&mut self, offset: i64) -> &mut Self {
+        self.offset = offset;
+        self
+    }
+
+    /// The sequence number the next frame stamped will get.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Append a trailer stamped with the current time to `data`,
+    /// returning its sequence number.
+    ///
+    /// Stamp as close to submitting the frame as possible, since any
+    /// time in between counts towards the delay.
+    ///
+    /// # Errors
+    ///
+    /// [`io::ErrorKind::WriteZero`] if the frame hasn't room for the
+    /// trailer, in which case `data` is left as it was.
+    pub fn stamp(&mut self, data: &mut DataMut<'_>) -> io::Result<u64> {
+        self.stamp_at(data, self.clock.now())
+    }
+
+    /// Same as [`stamp`](Self::stamp) but with the time given, in
+    /// nanoseconds.
+    pub fn stamp_at(&mut self, data: &mut DataMut<'_>, now: u64) -> io::Result<u64> {
+        let mut cursor = data.cursor();
+        let len = cursor.pos();
+
+        let buf = cursor.buf_mut();
+        let end = self
+            .append(buf, len, now)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::WriteZero, "no tailroom for trailer"))?;
+
+        cursor.set_pos(end);
+
+        Ok(self.seq - 1)
+    }
+
+    fn append(&mut self, buf: &mut [u8], len: usize, now: u64) -> Option<usize> {
+        let trailer = Trailer {
+            seq: self.seq,
+            tx_time: now.wrapping_add(self.offset as u64),
+        };
+
+        let end = trailer.append(buf, len)?;
+        self.seq += 1;
+
+        Some(end)
+    }
+}
+
+/// Builds up the distribution of one-way delays from the
+/// [`Trailer`]s of received frames.
+#[derive(Debug, Clone)]
+pub struct DelayRecorder {
+    clock: Clock,
+    offset: i64,
+    delays: Histogram,
+    // Ring bitmap of the `WINDOW` sequence numbers before `next_seq`.
+    seen: Vec<u64>,
+    next_seq: Option<u64>,
+    received: u64,
+    lost: u64,
+    reordered: u64,
+    duplicated: u64,
+    negative: u64,
+}
+
+impl DelayRecorder {
+    /// Take receive times from `clock`, which should be the same one
+    /// the sender stamps with.
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            offset: 0,
+            delays: Histogram::new(),
+            seen: vec![0; (WINDOW / 64) as usize],
+            next_seq: None,
+            received: 0,
+            lost: 0,
+            reordered: 0,
+            duplicated: 0,
+            negative: 0,
+        }
+    }
+
+    /// Subtract `offset` nanoseconds from each delay, e.g. the
+    /// receive path's own latency or a known offset between the two
+    /// clocks. Default is zero.
+    pub fn calibrate(&mut self, offset: i64) -> &mut Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Record the delay of `frame`, as of now, returning it in
+    /// nanoseconds. [`None`] if the frame has no trailer.
+    pub fn record(&mut self, frame: &[u8]) -> Option<i64> {
+        self.record_at(frame, self.clock.now())
+    }
+
+    /// Same as [`record`](Self::record) but with the receive time
+    /// given, in nanoseconds.
+    pub fn record_at(&mut self, frame: &[u8], now: u64) -> Option<i64> {
+        let trailer = Trailer::parse(frame)?;
+
+        self.received += 1;
+
+        match self.next_seq {
+            Some(next) if trailer.seq < next => {
+                if next - trailer.seq <= WINDOW && self.mark(trailer.seq) {
+                    self.duplicated += 1;
+                } else {
+                    // Counted as lost when the frames after it turned up.
+                    self.reordered += 1;
+                    self.lost = self.lost.saturating_sub(1);
+                }
+            }
+            next => {
+                self.advance(next, trailer.seq);
+                self.lost += next.map_or(0, |next| trailer.seq - next);
+                self.next_seq = Some(trailer.seq + 1);
+            }
+        }
+
+        let delay = (now.wrapping_sub(trailer.tx_time) as i64).wrapping_sub(self.offset);
+
+        if delay < 0 {
+            self.negative += 1;
+        } else {
+            self.delays.record(delay as u64);
+        }
+
+        Some(delay)
+    }
+
+    fn bit(seq: u64) -> (usize, u64) {
+        let idx = seq % WINDOW;
+        ((idx / 64) as usize, 1 << (idx % 64))
+    }
+
+    /// Mark `seq` as received, returning whether it already was.
+    fn mark(&mut self, seq: u64) -> bool {
+        let (word, mask) = Self::bit(seq);
+        let already_seen = self.seen[word] & mask != 0;
+        self.seen[word] |= mask;
+        already_seen
+    }
+
+    /// Move the window on to end at `seq`, marked as received, with
+    /// everything from `next` before it not.
+    fn advance(&mut self, next: Option<u64>, seq: u64) {
+        let from = next.unwrap_or(seq);
+
+        if seq - from >= WINDOW {
+            self.seen.iter_mut().for_each(|w| *w = 0);
+        } else {
+            for s in from..seq {
+                let (word, mask) = Self::bit(s);
+                self.seen[word] &= !mask;
+            }
+        }
+
+        self.mark(seq);
+    }
+
+    /// The distribution of delays recorded, in nanoseconds. Negative
+    /// delays are left out, see [`negative`](Self::negative).
+    pub fn delays(&self) -> &Histogram {
+        &self.delays
+    }
+
+    /// The number of frames with a trailer recorded.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// The number of gaps in the sequence not since filled in by
+    /// late arrivals.
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    /// The number of frames that arrived after one later in the
+    /// sequence. Frames so late they're no longer tracked are counted
+    /// here even if they're duplicates.
+    pub fn reordered(&self) -> u64 {
+        self.reordered
+    }
+
+    /// The number of frames whose sequence number had already been
+    /// received. Their delays are still recorded.
+    pub fn duplicated(&self) -> u64 {
+        self.duplicated
+    }
+
+    /// The number of frames which seemingly arrived before they were
+    /// sent, a sign the clocks aren't synced or the calibration is
+    /// off.
+    pub fn negative(&self) -> u64 {
+        self.negative
+    }
+
+    /// Forget everything recorded, e.g. at the start of a new run.
+    pub fn clear(&mut self) {
+        self.delays.clear();
+        self.seen.iter_mut().for_each(|w| *w = 0);
+        self.next_seq = None;
+        self.received = 0;
+        self.lost = 0;
+        self.reordered = 0;
+        self.duplicated = 0;
+        self.negative = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamped_frames_give_delays_losses_and_reordering() {
+        let mut stamper = TrailerStamper::new(Clock::Realtime);
+        stamper.calibrate(-100);
+
+        let mut frames = Vec::new();
+
+        for (i, len) in [14, 100, 64].iter().enumerate() {
+            let mut buf = vec![0xab; 128];
+            let end = stamper.append(&mut buf, *len, 1_000 * i as u64).unwrap();
+            buf.truncate(end);
+            frames.push(buf);
+        }
+
+        // Short frames are padded to the minimum first.
+        assert_eq!(frames[0].len(), MIN_FRAME_LEN);
+        assert!(frames[0][14..40].iter().all(|b| *b == 0));
+        assert_eq!(frames[1].len(), 100 + TRAILER_LEN);
+
+        assert!(stamper.append(&mut [0; 32], 20, 0).is_none());
+        assert_eq!(stamper.seq(), 3);
+
+        let trailer = Trailer::parse(&frames[2]).unwrap();
+        assert_eq!(trailer.seq(), 2);
+        assert_eq!(trailer.tx_time(), 1_900);
+
+        let mut recorder = DelayRecorder::new(Clock::Realtime);
+        recorder.calibrate(50);
+
+        assert_eq!(recorder.record_at(&frames[0], 500), Some(550));
+        assert_eq!(recorder.record_at(&frames[2], 2_500), Some(550));
+        assert_eq!(recorder.lost(), 1);
+
+        assert_eq!(recorder.record_at(&frames[1], 800), Some(-150));
+        assert_eq!(recorder.lost(), 0);
+        assert_eq!(recorder.reordered(), 1);
+        assert_eq!(recorder.negative(), 1);
+
+        // Duplicates neither fill in gaps nor count as reordered.
+        assert_eq!(recorder.record_at(&frames[1], 900), Some(-50));
+        assert_eq!(recorder.record_at(&frames[2], 2_500), Some(550));
+        assert_eq!(recorder.lost(), 0);
+        assert_eq!(recorder.reordered(), 1);
+        assert_eq!(recorder.duplicated(), 2);
+
+        assert_eq!(recorder.record_at(&[0; 64], 0), None);
+        assert_eq!(recorder.received(), 5);
+        assert_eq!(recorder.delays().count(), 3);
+    }
+}
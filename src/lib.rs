@@ -29,11 +29,7 @@
 //!
 //! ```no_run
 //! use std::{convert::TryInto, io::Write, str};
-//! use xsk_rs::{
-//!     config::{SocketConfig, UmemConfig},
-//!     socket::Socket,
-//!     umem::Umem,
-//! };
+//! use xsk_rs::prelude::*;
 //!
 //! // Create a UMEM for dev1 with 32 frames, whose sizes are
 //! // specified via the `UmemConfig` instance.
@@ -43,12 +39,14 @@
 //!
 //! // Bind an AF_XDP socket to the interface named `xsk_dev1`, on
 //! // queue 0.
-//! let (mut dev1_tx_q, _dev1_rx_q, _dev1_fq_and_cq) = Socket::new(
-//!     SocketConfig::default(),
-//!     &dev1_umem,
-//!     &"xsk_dev1".parse().unwrap(),
-//!     0,
-//! )
+//! let (mut dev1_tx_q, _dev1_rx_q, _dev1_fq_and_cq) = unsafe {
+//!     Socket::new(
+//!         SocketConfig::default(),
+//!         &dev1_umem,
+//!         &"xsk_dev1".parse().unwrap(),
+//!         0,
+//!     )
+//! }
 //! .expect("failed to create dev1 socket");
 //!
 //! // Create a UMEM for dev2. Another option is to use the same UMEM
@@ -62,12 +60,14 @@
 //!
 //! // Bind an AF_XDP socket to the interface named `xsk_dev2`, on
 //! // queue 0.
-//! let (_dev2_tx_q, mut dev2_rx_q, dev2_fq_and_cq) = Socket::new(
-//!     SocketConfig::default(),
-//!     &dev2_umem,
-//!     &"xsk_dev2".parse().unwrap(),
-//!     0,
-//! )
+//! let (_dev2_tx_q, mut dev2_rx_q, dev2_fq_and_cq) = unsafe {
+//!     Socket::new(
+//!         SocketConfig::default(),
+//!         &dev2_umem,
+//!         &"xsk_dev2".parse().unwrap(),
+//!         0,
+//!     )
+//! }
 //! .expect("failed to create dev2 socket");
 //!
 //! let (mut dev2_fq, _dev2_cq) = dev2_fq_and_cq.expect("missing dev2 fill queue and comp queue");
@@ -111,13 +111,18 @@
 //!
 //! panic!("no matching packets received")
 //! ```
+#![cfg_attr(not(feature = "std"), no_std)]
 #![deny(missing_docs)]
 #![deny(missing_debug_implementations)]
 #![deny(unsafe_op_in_unsafe_fn)]
+extern crate alloc;
+
 use cfg_if::cfg_if;
 
+pub mod portable;
+
 cfg_if! {
-    if #[cfg(all(target_pointer_width = "64", target_family = "unix"))] {
+    if #[cfg(all(feature = "std", target_pointer_width = "64", target_family = "unix"))] {
         pub mod umem;
         pub use umem::{frame::FrameDesc, CompQueue, FillQueue, Umem};
 
@@ -125,7 +130,102 @@ cfg_if! {
         pub use socket::{RxQueue, Socket, TxQueue};
 
         pub mod config;
+        pub use config::{SocketConfig, UmemConfig};
+
+        pub mod prelude;
+
+        pub mod xsk;
+        pub use xsk::Xsk;
+
+        pub mod addr;
+
+        pub mod async_io;
+
+        pub mod cancel;
+        pub use cancel::CancelToken;
+
+        pub mod check;
+
+        #[cfg(feature = "parse")]
+        pub mod checksum;
+
+        #[cfg(feature = "parse")]
+        pub mod classify;
+
+        #[cfg(feature = "parse")]
+        pub mod defrag;
+
+        #[cfg(feature = "parse")]
+        pub mod dispatch;
+
+        pub mod driver;
+
+        pub mod easy;
+
+        #[cfg(feature = "xdp-loader")]
+        pub mod filter;
+
+        pub mod fixed;
+
+        #[cfg(feature = "parse")]
+        pub mod flow;
+
+        pub mod group;
+
+        pub mod handoff;
+
+        #[cfg(feature = "metrics")]
+        pub mod health;
+
+        #[cfg(feature = "parse")]
+        pub mod icmp;
+
+        #[cfg(feature = "metrics")]
+        pub mod latency;
+
+        pub mod multi_queue;
+
+        pub mod numa;
+
+        #[cfg(feature = "pcap")]
+        pub mod pcap;
+
+        #[cfg(feature = "parse")]
+        pub mod pipeline;
+
+        #[cfg(feature = "metrics")]
+        pub mod selftest;
+
+        #[cfg(feature = "parse")]
+        pub mod stack;
+
+        pub mod stats;
+
+        #[cfg(feature = "parse")]
+        pub mod steering;
+
+        #[cfg(feature = "parse")]
+        pub mod template;
+
+        #[cfg(feature = "parse")]
+        pub mod trace;
+
+        #[cfg(feature = "parse")]
+        pub mod view;
+
+        #[cfg(feature = "testutil")]
+        pub mod testutil;
+
+        #[cfg(feature = "ffi")]
+        pub mod ffi;
+
+        #[cfg(feature = "python")]
+        pub mod python;
 
+        // Shared by the feature gated modules, so only fully used with
+        // `parse`.
+        #[cfg_attr(not(feature = "parse"), allow(dead_code))]
+        mod packet;
         mod ring;
         mod util;
 
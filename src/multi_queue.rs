@@ -0,0 +1,286 @@
+//! Binding a socket to every receive queue of an interface.
+//!
+//! A NIC spreading traffic over its queues with RSS needs a socket on
+//! each of them to see all of it. [`bind_all`] looks up how many
+//! queues the interface has and creates an [`Xsk`] per queue, each
+//! with its own [`Umem`] or all sharing one:
+//!
+//! ```no_run
+//! use xsk_rs::multi_queue::{self, MultiQueueConfig};
+//!
+//! let xsks = multi_queue::bind_all(&"eth0".parse().unwrap(), &MultiQueueConfig::default())
+//!     .unwrap();
+//!
+//! for xsk in xsks {
+//!     // Hand each off to a worker thread.
+//! }
+//! ```
+//!
+//! For per-queue [`Umem`]s placed on the NIC's NUMA node, see
+//! [`QueueGroup`](crate::group::QueueGroup).
+
+use std::{
+    convert::TryInto,
+    error::Error,
+    fmt, fs, io, mem,
+    num::NonZeroU32,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    path::Path,
+};
+
+use libc::{c_char, AF_INET, EOPNOTSUPP, SIOCETHTOOL, SOCK_CLOEXEC, SOCK_DGRAM};
+
+use crate::{
+    config::{Interface, SocketConfig, UmemConfig},
+    socket::{Socket, SocketCreateError},
+    umem::{Umem, UmemCreateError},
+    xsk::Xsk,
+};
+
+/// The number of frames [`MultiQueueConfig`] gives each queue by
+/// default.
+pub const DEFAULT_FRAMES_PER_QUEUE: u32 = 4096;
+
+/// `ETHTOOL_GCHANNELS` from `linux/ethtool.h`.
+const ETHTOOL_GCHANNELS: u32 = 0x3c;
+
+/// `struct ethtool_channels` from `linux/ethtool.h`.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct EthtoolChannels {
+    cmd: u32,
+    max_rx: u32,
+    max_tx: u32,
+    max_other: u32,
+    max_combined: u32,
+    rx_count: u32,
+    tx_count: u32,
+    other_count: u32,
+    combined_count: u32,
+}
+
+/// The number of receive queues `if_name` currently has, i.e. its
+/// combined plus rx-only channels as reported by `ethtool -l`.
+///
+/// Falls back to counting the interface's `queues/rx-*` entries in
+/// sysfs for drivers which don't report channels.
+pub fn rx_queue_count(if_name: &Interface) -> io::Result<u32> {
+    match ethtool_rx_channels(if_name) {
+        Ok(cnt) if cnt > 0 => Ok(cnt),
+        Ok(_) => sysfs_rx_queues(if_name),
+        Err(e) if e.raw_os_error() == Some(EOPNOTSUPP) => sysfs_rx_queues(if_name),
+        Err(e) => Err(e),
+    }
+}
+
+fn ethtool_rx_channels(if_name: &Interface) -> io::Result<u32> {
+    let fd = unsafe { libc::socket(AF_INET, SOCK_DGRAM | SOCK_CLOEXEC, 0) };
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: `fd` was just opened and is owned by nothing else.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut channels = EthtoolChannels {
+        cmd: ETHTOOL_GCHANNELS,
+        ..EthtoolChannels::default()
+    };
+
+    // SAFETY: all zeroes is a valid `ifreq`.
+    let mut ifr: libc::ifreq = unsafe { mem::zeroed() };
+
+    let name = if_name.as_cstr().to_bytes();
+
+    for (dst, &src) in ifr.ifr_name.iter_mut().zip(name) {
+        *dst = src as c_char;
+    }
+
+    ifr.ifr_ifru.ifru_data = &mut channels as *mut EthtoolChannels as *mut c_char;
+
+    let ret = unsafe { libc::ioctl(fd.as_raw_fd(), SIOCETHTOOL as _, &mut ifr) };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(channels.rx_count + channels.combined_count)
+}
+
+fn sysfs_rx_queues(if_name: &Interface) -> io::Result<u32> {
+    let name = if_name
+        .as_cstr()
+        .to_str()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let path = Path::new("/sys/class/net").join(name).join("queues");
+
+    let mut cnt = 0;
+
+    for entry in fs::read_dir(path)? {
+        if entry?.file_name().to_string_lossy().starts_with("rx-") {
+            cnt += 1;
+        }
+    }
+
+    Ok(cnt)
+}
+
+/// How [`bind_all`] creates its sockets.
+#[derive(Debug, Clone, Copy)]
+pub struct MultiQueueConfig {
+    umem_config: UmemConfig,
+    socket_config: SocketConfig,
+    frames_per_queue: NonZeroU32,
+    shared_umem: bool,
+}
+
+impl Default for MultiQueueConfig {
+    fn default() -> Self {
+        Self {
+            umem_config: UmemConfig::default(),
+            socket_config: SocketConfig::default(),
+            frames_per_queue: DEFAULT_FRAMES_PER_QUEUE.try_into().unwrap(),
+            shared_umem: false,
+        }
+    }
+}
+
+impl MultiQueueConfig {
+    /// Set the config of each [`Umem`], or of the shared one.
+    pub fn umem_config(&mut self, config: UmemConfig) -> &mut Self {
+        self.umem_config = config;
+        self
+    }
+
+    /// Set the config of each socket.
+    pub fn socket_config(&mut self, config: SocketConfig) -> &mut Self {
+        self.socket_config = config;
+        self
+    }
+
+    /// Set the number of frames for each queue. Default is
+    /// [`DEFAULT_FRAMES_PER_QUEUE`].
+    pub fn frames_per_queue(&mut self, frames: NonZeroU32) -> &mut Self {
+        self.frames_per_queue = frames;
+        self
+    }
+
+    /// Whether every socket should share a single [`Umem`], with
+    /// [`frames_per_queue`](Self::frames_per_queue) frames for each
+    /// queue, rather than each having its own. Default is `false`.
+    ///
+    /// Sharing lets frames be passed between queues, e.g. to forward
+    /// what one receives out of another, but means contending for the
+    /// one [`Umem`]'s memory from every queue's thread.
+    pub fn shared_umem(&mut self, shared: bool) -> &mut Self {
+        self.shared_umem = shared;
+        self
+    }
+}
+
+/// Bind a socket to each receive queue of `if_name`, see the
+/// [module docs](self). The returned [`Xsk`]s are in queue id order.
+///
+/// With a [shared](MultiQueueConfig::shared_umem) [`Umem`], each
+/// [`Xsk`]'s [`umem`](Xsk::umem) is a clone of it and its
+/// [`descs`](Xsk::descs) are that queue's share of the frames.
+///
+/// May require root permissions to create successfully.
+pub fn bind_all(if_name: &Interface, config: &MultiQueueConfig) -> Result<Vec<Xsk>, BindAllError> {
+    let queues = rx_queue_count(if_name).map_err(BindAllError::QueueCount)?;
+
+    if queues == 0 {
+        return Err(BindAllError::NoQueues);
+    }
+
+    let shared = if config.shared_umem {
+        let frame_count = config
+            .frames_per_queue
+            .checked_mul(queues.try_into().unwrap())
+            .ok_or(BindAllError::TooManyFrames)?;
+
+        Some(Umem::new(config.umem_config, frame_count, false).map_err(BindAllError::Umem)?)
+    } else {
+        None
+    };
+
+    let (shared_umem, mut shared_descs) = match shared {
+        Some((umem, descs)) => (Some(umem), descs),
+        None => (None, Vec::new()),
+    };
+
+    let mut xsks = Vec::with_capacity(queues as usize);
+
+    for queue_id in 0..queues {
+        let (umem, descs) = match &shared_umem {
+            Some(umem) => {
+                let share = config.frames_per_queue.get() as usize;
+                (umem.clone(), shared_descs.drain(..share).collect())
+            }
+            None => Umem::new(config.umem_config, config.frames_per_queue, false)
+                .map_err(BindAllError::Umem)?,
+        };
+
+        let (tx_q, rx_q, fq, cq) =
+            Socket::new_shared(config.socket_config, &umem, if_name, queue_id)
+                .map_err(|err| BindAllError::Socket { queue_id, err })?;
+
+        xsks.push(Xsk {
+            umem,
+            descs,
+            tx_q,
+            rx_q,
+            fq,
+            cq,
+        });
+    }
+
+    Ok(xsks)
+}
+
+/// Error detailing why [`bind_all`] failed.
+#[derive(Debug)]
+pub enum BindAllError {
+    /// Looking up the interface's queue count failed.
+    QueueCount(io::Error),
+    /// The interface has no receive queues.
+    NoQueues,
+    /// A shared [`Umem`] would need more than `u32::MAX` frames.
+    TooManyFrames,
+    /// Creating a [`Umem`] failed.
+    Umem(UmemCreateError),
+    /// Creating a queue's socket failed.
+    Socket {
+        /// The queue in question.
+        queue_id: u32,
+        /// The underlying error.
+        err: SocketCreateError,
+    },
+}
+
+impl fmt::Display for BindAllError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::QueueCount(_) => write!(f, "failed to look up the interface's queue count"),
+            Self::NoQueues => write!(f, "interface has no receive queues"),
+            Self::TooManyFrames => write!(f, "shared UMEM would have too many frames"),
+            Self::Umem(_) => write!(f, "failed to create UMEM"),
+            Self::Socket { queue_id, .. } => {
+                write!(f, "failed to create socket for queue {}", queue_id)
+            }
+        }
+    }
+}
+
+impl Error for BindAllError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::QueueCount(err) => Some(err),
+            Self::Umem(err) => Some(err),
+            Self::Socket { err, .. } => Some(err),
+            Self::NoQueues | Self::TooManyFrames => None,
+        }
+    }
+}
@@ -0,0 +1,391 @@
+//! NUMA locality helpers.
+//!
+//! Packet memory on a different node to the NIC is silently slow, so
+//! this module offers just enough to find the NIC's node, run
+//! allocations and worker threads on it and check where memory
+//! actually ended up.
+
+use std::{
+    fs, io, mem,
+    path::Path,
+    ptr,
+    thread::{self, JoinHandle},
+};
+
+use crate::config::Interface;
+
+/// The NUMA node an interface's device is attached to.
+///
+/// Returns [`None`] if the kernel doesn't report one, for example
+/// for virtual devices or single-node machines.
+pub fn interface_node(if_name: &Interface) -> io::Result<Option<u32>> {
+    let name = if_name
+        .as_cstr()
+        .to_str()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let path = Path::new("/sys/class/net")
+        .join(name)
+        .join("device/numa_node");
+
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(parse_node(&s)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The CPUs belonging to a NUMA node.
+pub fn node_cpus(node: u32) -> io::Result<Vec<usize>> {
+    let path = format!("/sys/devices/system/node/node{}/cpulist", node);
+
+    parse_cpulist(&fs::read_to_string(path)?)
+}
+
+/// The node backing the page containing `addr`, or [`None`] if the
+/// page hasn't been faulted in yet.
+pub fn page_node(addr: *const libc::c_void) -> io::Result<Option<u32>> {
+    let mut pages = [addr as *mut libc::c_void];
+    let mut status: [libc::c_int; 1] = [-libc::ENOENT];
+
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_move_pages,
+            0,
+            1,
+            pages.as_mut_ptr(),
+            ptr::null::<libc::c_int>(),
+            status.as_mut_ptr(),
+            0,
+        )
+    };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    match status[0] {
+        node if node >= 0 => Ok(Some(node as u32)),
+        err if -err == libc::ENOENT => Ok(None),
+        err => Err(io::Error::from_raw_os_error(-err)),
+    }
+}
+
+/// `MPOL_BIND` from `linux/mempolicy.h`.
+const MPOL_BIND: libc::c_int = 2;
+
+/// `MPOL_MF_STRICT` from `linux/mempolicy.h`.
+const MPOL_MF_STRICT: libc::c_uint = 1 << 0;
+
+/// Restrict the `len` bytes mapped at `addr` to `node`'s memory, so
+/// pages faulted in from here on are allocated there. Fails if any
+/// are already elsewhere.
+pub(crate) fn bind_memory(addr: *mut libc::c_void, len: usize, node: u32) -> io::Result<()> {
+    let bits = 8 * mem::size_of::<libc::c_ulong>();
+    let mut mask: Vec<libc::c_ulong> = vec![0; node as usize / bits + 1];
+
+    mask[node as usize / bits] |= 1 << (node as usize % bits);
+
+    // The kernel reads one bit less than `maxnode`.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            addr,
+            len,
+            MPOL_BIND,
+            mask.as_ptr(),
+            mask.len() * bits + 1,
+            MPOL_MF_STRICT,
+        )
+    };
+
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Where packet memory and the threads working on it should live.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    node: Option<u32>,
+    cpus: Vec<usize>,
+}
+
+impl Placement {
+    /// A placement on the given node.
+    pub fn node(node: u32) -> io::Result<Self> {
+        Ok(Self {
+            node: Some(node),
+            cpus: node_cpus(node)?,
+        })
+    }
+
+    /// A placement on the node local to `if_name`'s device, or an
+    /// unconstrained placement if it has none.
+    pub fn for_interface(if_name: &Interface) -> io::Result<Self> {
+        match interface_node(if_name)? {
+            Some(node) => Self::node(node),
+            None => Ok(Self::anywhere()),
+        }
+    }
+
+    /// A placement with no constraints.
+    pub fn anywhere() -> Self {
+        Self {
+            node: None,
+            cpus: Vec::new(),
+        }
+    }
+
+    /// The target node, if any.
+    pub fn numa_node(&self) -> Option<u32> {
+        self.node
+    }
+
+    /// The CPUs threads are restricted to. Empty if unconstrained.
+    pub fn cpus(&self) -> &[usize] {
+        &self.cpus
+    }
+
+    /// Restrict the calling thread to this placement's CPUs. A no-op
+    /// if unconstrained.
+    pub fn pin_current_thread(&self) -> io::Result<()> {
+        if self.cpus.is_empty() {
+            return Ok(());
+        }
+
+        let mut set = empty_cpu_set();
+
+        for &cpu in self.cpus.iter() {
+            unsafe { libc::CPU_SET(cpu, &mut set) };
+        }
+
+        set_affinity(&set)
+    }
+
+    /// Run `f` on the calling thread while temporarily pinned to this
+    /// placement, restoring the previous affinity afterwards, even if
+    /// `f` panics.
+    ///
+    /// Memory first touched by `f` is then allocated on the target
+    /// node under the default memory policy. This is how per-queue
+    /// [`Umem`](crate::Umem)s get placed, since their regions are
+    /// populated when mapped.
+    pub fn run<F, T>(&self, f: F) -> io::Result<T>
+    where
+        F: FnOnce() -> T,
+    {
+        if self.cpus.is_empty() {
+            return Ok(f());
+        }
+
+        let prev = current_affinity()?;
+
+        self.pin_current_thread()?;
+
+        let guard = RestoreAffinity(Some(prev));
+        let res = f();
+
+        guard.restore()?;
+
+        Ok(res)
+    }
+
+    /// Spawn a thread pinned to this placement.
+    ///
+    /// If pinning fails the thread still runs `f`, the error is
+    /// passed to it so it can decide whether to carry on.
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce(io::Result<()>) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let placement = self.clone();
+
+        thread::spawn(move || f(placement.pin_current_thread()))
+    }
+}
+
+fn empty_cpu_set() -> libc::cpu_set_t {
+    // SAFETY: `cpu_set_t` is a plain bitmask, all zeroes is the empty
+    // set.
+    unsafe { mem::zeroed() }
+}
+
+/// Puts back the calling thread's affinity when dropped, for when
+/// [`Placement::run`]'s closure unwinds.
+struct RestoreAffinity(Option<libc::cpu_set_t>);
+
+impl RestoreAffinity {
+    fn restore(mut self) -> io::Result<()> {
+        match self.0.take() {
+            Some(prev) => set_affinity(&prev),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for RestoreAffinity {
+    fn drop(&mut self) {
+        if let Some(prev) = self.0.take() {
+            let _ = set_affinity(&prev);
+        }
+    }
+}
+
+fn current_affinity() -> io::Result<libc::cpu_set_t> {
+    let mut set = empty_cpu_set();
+
+    let ret = unsafe { libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) };
+
+    if ret != 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(set)
+    }
+}
+
+fn set_affinity(set: &libc::cpu_set_t) -> io::Result<()> {
+    let ret = unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), set) };
+
+    if ret != 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn parse_node(s: &str) -> Option<u32> {
+    // The kernel reports -1 when there's no affinity.
+    s.trim().parse().ok()
+}
+
+/// Parse a kernel cpulist, e.g. `0-3,8,10-11`.
+fn parse_cpulist(s: &str) -> io::Result<Vec<usize>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed cpulist");
+
+    let mut cpus = Vec::new();
+
+    for range in s.trim().split(',').filter(|r| !r.is_empty()) {
+        let mut bounds = range.splitn(2, '-');
+
+        let start: usize = bounds
+            .next()
+            .and_then(|b| b.parse().ok())
+            .ok_or_else(invalid)?;
+
+        let end: usize = match bounds.next() {
+            Some(b) => b.parse().map_err(|_| invalid())?,
+            None => start,
+        };
+
+        if end < start {
+            return Err(invalid());
+        }
+
+        cpus.extend(start..=end);
+    }
+
+    Ok(cpus)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+
+    use super::*;
+
+    #[test]
+    fn cpulists_are_parsed() {
+        assert_eq!(
+            parse_cpulist("0-3,8,10-11\n").unwrap(),
+            [0, 1, 2, 3, 8, 10, 11]
+        );
+        assert_eq!(parse_cpulist("5").unwrap(), [5]);
+        assert!(parse_cpulist("\n").unwrap().is_empty());
+        assert!(parse_cpulist("3-1").is_err());
+        assert!(parse_cpulist("a-b").is_err());
+    }
+
+    #[test]
+    fn negative_numa_node_means_none() {
+        assert_eq!(parse_node("-1\n"), None);
+        assert_eq!(parse_node("1\n"), Some(1));
+        assert_eq!(parse_node(""), None);
+    }
+
+    #[test]
+    fn bound_memory_is_faulted_in_on_the_node() {
+        if !Path::new("/sys/devices/system/node/node0").exists() {
+            return;
+        }
+
+        let len = 4096;
+
+        let addr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_ANONYMOUS | libc::MAP_SHARED,
+                -1,
+                0,
+            )
+        };
+
+        assert_ne!(addr, libc::MAP_FAILED);
+
+        // Sandboxes commonly filter out `mbind(2)`.
+        match bind_memory(addr, len, 0) {
+            Err(e) if matches!(e.raw_os_error(), Some(libc::EPERM | libc::ENOSYS)) => {
+                unsafe { libc::munmap(addr, len) };
+                return;
+            }
+            res => res.unwrap(),
+        }
+
+        assert!(bind_memory(addr, len, u16::MAX as u32).is_err());
+
+        unsafe { ptr::write_volatile(addr as *mut u8, 1) };
+
+        assert_eq!(page_node(addr).unwrap(), Some(0));
+
+        unsafe { libc::munmap(addr, len) };
+    }
+
+    #[test]
+    fn unconstrained_placement_runs_closure_in_place() {
+        let placement = Placement::anywhere();
+
+        assert_eq!(placement.run(|| 7).unwrap(), 7);
+        assert!(placement.pin_current_thread().is_ok());
+        assert!(placement.spawn(|res| res.is_ok()).join().unwrap());
+    }
+
+    #[test]
+    fn affinity_is_restored_when_the_closure_panics() {
+        thread::spawn(|| {
+            let before = current_affinity().unwrap();
+            let cpu = (0..libc::CPU_SETSIZE as usize)
+                .find(|&cpu| unsafe { libc::CPU_ISSET(cpu, &before) })
+                .unwrap();
+
+            let placement = Placement {
+                node: None,
+                cpus: vec![cpu],
+            };
+
+            let res = panic::catch_unwind(|| placement.run(|| panic!("boom")));
+            assert!(res.is_err());
+
+            let after = current_affinity().unwrap();
+            assert!((0..libc::CPU_SETSIZE as usize).all(|cpu| unsafe {
+                libc::CPU_ISSET(cpu, &before) == libc::CPU_ISSET(cpu, &after)
+            }));
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+//! Minimal wire format helpers shared by modules that need to peek
+//! at packet headers sitting in a [`Umem`](crate::Umem) frame.
+//!
+//! Nothing here validates checksums or options, it only locates
+//! headers and reads fixed-position fields, bailing out with [`None`]
+//! as soon as a frame is too short.
+
+pub const ETH_HLEN: usize = 14;
+pub const VLAN_HLEN: usize = 4;
+
+pub const ETH_P_IPV4: u16 = 0x0800;
+pub const ETH_P_8021Q: u16 = 0x8100;
+pub const ETH_P_8021AD: u16 = 0x88a8;
+pub const ETH_P_IPV6: u16 = 0x86dd;
+
+pub const IPPROTO_ICMP: u8 = 1;
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+pub const IPPROTO_ICMPV6: u8 = 58;
+
+pub const IPV4_MIN_HLEN: usize = 20;
+pub const IPV6_HLEN: usize = 40;
+
+#[inline]
+pub fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    let bytes = buf.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[inline]
+pub fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let bytes = buf.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// The ethertype of the network layer and the offset at which its
+/// header starts, skipping over up to two VLAN tags.
+#[inline]
+pub fn l3(frame: &[u8]) -> Option<(u16, usize)> {
+    let mut ethertype = read_u16(frame, 12)?;
+    let mut offset = ETH_HLEN;
+
+    for _ in 0..2 {
+        if ethertype != ETH_P_8021Q && ethertype != ETH_P_8021AD {
+            break;
+        }
+        ethertype = read_u16(frame, offset + 2)?;
+        offset += VLAN_HLEN;
+    }
+
+    Some((ethertype, offset))
+}
+
+/// The transport protocol number and the offset at which its header
+/// starts, given the location of the network layer header.
+///
+/// For IPv4 non-initial fragments the returned offset points at
+/// payload rather than a transport header, so callers reading ports
+/// should check [`ipv4_is_fragment`] first. IPv6 extension headers
+/// are not walked.
+#[inline]
+pub fn l4(frame: &[u8], ethertype: u16, l3_offset: usize) -> Option<(u8, usize)> {
+    match ethertype {
+        ETH_P_IPV4 => {
+            let ihl = (*frame.get(l3_offset)? & 0x0f) as usize * 4;
+            if ihl < IPV4_MIN_HLEN {
+                return None;
+            }
+            let proto = *frame.get(l3_offset + 9)?;
+            Some((proto, l3_offset + ihl))
+        }
+        ETH_P_IPV6 => {
+            let proto = *frame.get(l3_offset + 6)?;
+            Some((proto, l3_offset + IPV6_HLEN))
+        }
+        _ => None,
+    }
+}
+
+/// Whether the IPv4 header at `l3_offset` is a non-initial fragment,
+/// i.e. carries no transport header.
+#[inline]
+pub fn ipv4_is_fragment(frame: &[u8], l3_offset: usize) -> Option<bool> {
+    let frag = read_u16(frame, l3_offset + 6)?;
+    Some(frag & 0x1fff != 0)
+}
+
+/// Whether the IPv4 header at `l3_offset` is any fragment, initial or
+/// not, i.e. has More Fragments set or a non-zero offset.
+#[inline]
+pub fn ipv4_is_fragmented(frame: &[u8], l3_offset: usize) -> Option<bool> {
+    let frag = read_u16(frame, l3_offset + 6)?;
+    Some(frag & 0x3fff != 0)
+}
+
+#[inline]
+pub fn has_ports(proto: u8) -> bool {
+    matches!(proto, IPPROTO_TCP | IPPROTO_UDP)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// An ethernet + IPv4 + UDP frame, with `vlans` 802.1Q tags.
+    pub fn udp4_frame(vlans: usize, src_port: u16, dst_port: u16) -> Vec<u8> {
+        let mut frame = vec![0xaa; 12];
+
+        for _ in 0..vlans {
+            frame.extend_from_slice(&ETH_P_8021Q.to_be_bytes());
+            frame.extend_from_slice(&[0x00, 0x05]);
+        }
+
+        frame.extend_from_slice(&ETH_P_IPV4.to_be_bytes());
+        frame.extend_from_slice(&[
+            0x45,
+            0x00,
+            0x00,
+            0x20,
+            0x00,
+            0x00,
+            0x40,
+            0x00,
+            0x40,
+            IPPROTO_UDP,
+            0x00,
+            0x00,
+        ]);
+        frame.extend_from_slice(&[192, 168, 69, 1]);
+        frame.extend_from_slice(&[192, 168, 69, 2]);
+        frame.extend_from_slice(&src_port.to_be_bytes());
+        frame.extend_from_slice(&dst_port.to_be_bytes());
+        frame.extend_from_slice(&[0x00, 0x0c, 0x00, 0x00]);
+        frame.extend_from_slice(b"ping");
+
+        frame
+    }
+
+    #[test]
+    fn locates_headers_behind_vlan_tags() {
+        for vlans in 0..=2 {
+            let frame = udp4_frame(vlans, 1234, 53);
+
+            let (ethertype, l3_offset) = l3(&frame).unwrap();
+            assert_eq!(ethertype, ETH_P_IPV4);
+            assert_eq!(l3_offset, ETH_HLEN + vlans * VLAN_HLEN);
+
+            let (proto, l4_offset) = l4(&frame, ethertype, l3_offset).unwrap();
+            assert_eq!(proto, IPPROTO_UDP);
+            assert_eq!(read_u16(&frame, l4_offset), Some(1234));
+            assert_eq!(read_u16(&frame, l4_offset + 2), Some(53));
+        }
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let frame = udp4_frame(0, 1, 2);
+
+        assert!(l3(&frame[..13]).is_none());
+        assert!(l4(&frame[..ETH_HLEN + 5], ETH_P_IPV4, ETH_HLEN).is_none());
+        assert!(read_u16(&frame, frame.len() - 1).is_none());
+    }
+}
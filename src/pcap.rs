@@ -0,0 +1,493 @@
+//! Writing frames out in the classic pcap format, for reading with
+//! Wireshark, tcpdump and friends.
+//!
+//! Timestamps are written with nanosecond precision.
+//!
+//! ```no_run
+//! use std::{fs::File, io::BufWriter, time::SystemTime};
+//! use xsk_rs::pcap::PcapWriter;
+//! # fn frames() -> Vec<Vec<u8>> { Vec::new() }
+//!
+//! let file = BufWriter::new(File::create("rx.pcap").unwrap());
+//! let mut pcap = PcapWriter::new(file, 65535).unwrap();
+//!
+//! for frame in frames() {
+//!     pcap.write_packet(SystemTime::now(), &frame).unwrap();
+//! }
+//!
+//! pcap.flush().unwrap();
+//! ```
+//!
+//! Captures in either precision and byte order can be read back with
+//! a [`PcapReader`], e.g. to replay them or take a reference packet
+//! for a [`template`](crate::template).
+//!
+//! A [`FlightRecorder`] keeps the last few packets sent and received,
+//! truncated, in memory, and dumps them as a capture when something
+//! goes wrong, or when asked to from a signal handler through a
+//! [`DumpRequest`].
+
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The link type of every capture written, plain Ethernet.
+pub const LINKTYPE_ETHERNET: u32 = 1;
+
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+
+/// Writes a pcap file header followed by one record per packet.
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    out: W,
+    snaplen: u32,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Write the file header to `out`. Packets longer than `snaplen`
+    /// are truncated to it, keeping their original length in the
+    /// record.
+    pub fn new(mut out: W, snaplen: u32) -> io::Result<Self> {
+        let mut header = [0; 24];
+
+        header[0..4].copy_from_slice(&MAGIC_NANOS.to_le_bytes());
+        header[4..6].copy_from_slice(&2u16.to_le_bytes());
+        header[6..8].copy_from_slice(&4u16.to_le_bytes());
+        // Bytes 8 to 16 are the unused time zone and accuracy fields.
+        header[16..20].copy_from_slice(&snaplen.to_le_bytes());
+        header[20..24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+
+        out.write_all(&header)?;
+
+        Ok(Self { out, snaplen })
+    }
+
+    /// Write a record for a packet captured at `ts`. Times before the
+    /// epoch are written as the epoch.
+    pub fn write_packet(&mut self, ts: SystemTime, data: &[u8]) -> io::Result<()> {
+        self.write_packet_since_epoch(ts.duration_since(UNIX_EPOCH).unwrap_or_default(), data)
+    }
+
+    /// Same as [`write_packet`](Self::write_packet) but with the
+    /// timestamp given as time since the epoch.
+    pub fn write_packet_since_epoch(&mut self, ts: Duration, data: &[u8]) -> io::Result<()> {
+        self.write_record(ts, data, data.len() as u32)
+    }
+
+    /// Write a record of `data`, which may have already been cut
+    /// short of the packet's `orig_len`.
+    fn write_record(&mut self, ts: Duration, data: &[u8], orig_len: u32) -> io::Result<()> {
+        let incl_len = (data.len() as u32).min(self.snaplen);
+
+        let mut header = [0; 16];
+
+        header[0..4].copy_from_slice(&(ts.as_secs() as u32).to_le_bytes());
+        header[4..8].copy_from_slice(&ts.subsec_nanos().to_le_bytes());
+        header[8..12].copy_from_slice(&incl_len.to_le_bytes());
+        header[12..16].copy_from_slice(&orig_len.to_le_bytes());
+
+        self.out.write_all(&header)?;
+        self.out.write_all(&data[..incl_len as usize])
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    /// A reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    /// Unwrap the underlying writer, without flushing it.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Which way a packet recorded by a [`FlightRecorder`] went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Received on an rx ring.
+    Rx,
+    /// Submitted to a tx ring.
+    Tx,
+}
+
+#[derive(Debug)]
+struct Recorded {
+    dir: Direction,
+    ts: Duration,
+    orig_len: u32,
+    data: Vec<u8>,
+}
+
+/// Keeps copies of the last packets sent and received, for dumping as
+/// a pcap capture when something goes wrong.
+///
+/// Each packet is truncated to the recorder's snaplen, and once
+/// `capacity` are held the oldest is overwritten, reusing its buffer,
+/// so recording costs a copy per packet and no allocation once warmed
+/// up.
+#[derive(Debug)]
+pub struct FlightRecorder {
+    capacity: usize,
+    snaplen: u32,
+    packets: VecDeque<Recorded>,
+    recorded: u64,
+    dump_request: DumpRequest,
+}
+
+impl FlightRecorder {
+    /// Keep up to `capacity` packets, truncated to `snaplen` bytes.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn new(capacity: usize, snaplen: u32) -> Self {
+        assert!(capacity > 0, "flight recorder capacity must be non-zero");
+
+        Self {
+            capacity,
+            snaplen,
+            packets: VecDeque::with_capacity(capacity),
+            recorded: 0,
+            dump_request: DumpRequest::default(),
+        }
+    }
+
+    /// Record a packet received at `ts`.
+    #[inline]
+    pub fn record_rx(&mut self, ts: SystemTime, frame: &[u8]) {
+        self.record(Direction::Rx, ts, frame);
+    }
+
+    /// Record a packet submitted at `ts`.
+    #[inline]
+    pub fn record_tx(&mut self, ts: SystemTime, frame: &[u8]) {
+        self.record(Direction::Tx, ts, frame);
+    }
+
+    /// Record a packet going `dir` at `ts`, overwriting the oldest if
+    /// the recorder is full.
+    pub fn record(&mut self, dir: Direction, ts: SystemTime, frame: &[u8]) {
+        let mut data = if self.packets.len() == self.capacity {
+            self.packets.pop_front().map(|p| p.data).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+
+        let incl_len = frame.len().min(self.snaplen as usize);
+
+        data.clear();
+        data.extend_from_slice(&frame[..incl_len]);
+
+        self.packets.push_back(Recorded {
+            dir,
+            ts: ts.duration_since(UNIX_EPOCH).unwrap_or_default(),
+            orig_len: frame.len() as u32,
+            data,
+        });
+
+        self.recorded += 1;
+    }
+
+    /// The number of packets held.
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Whether no packets are held.
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// The most packets held at once.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The length packets are truncated to.
+    pub fn snaplen(&self) -> u32 {
+        self.snaplen
+    }
+
+    /// The number of packets recorded in total, including those since
+    /// overwritten.
+    pub fn recorded(&self) -> u64 {
+        self.recorded
+    }
+
+    /// Write the packets held to `out` as a pcap capture, oldest
+    /// first, returning `out`. Those going `dir` only, if given.
+    ///
+    /// The packets are kept, so a later dump covers them again.
+    pub fn dump<W: Write>(&self, out: W, dir: Option<Direction>) -> io::Result<W> {
+        let mut pcap = PcapWriter::new(out, self.snaplen)?;
+
+        for p in &self.packets {
+            if dir.is_none_or(|dir| dir == p.dir) {
+                pcap.write_record(p.ts, &p.data, p.orig_len)?;
+            }
+        }
+
+        pcap.flush()?;
+
+        Ok(pcap.into_inner())
+    }
+
+    /// Forget every packet held.
+    pub fn clear(&mut self) {
+        self.packets.clear();
+    }
+
+    /// A handle for asking for a dump from elsewhere, see
+    /// [`DumpRequest`].
+    pub fn dump_request(&self) -> DumpRequest {
+        self.dump_request.clone()
+    }
+
+    /// Whether a dump has been asked for through a [`DumpRequest`]
+    /// since this was last called.
+    #[inline]
+    pub fn take_dump_request(&self) -> bool {
+        self.dump_request.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Asks a [`FlightRecorder`] to be dumped, from another thread or a
+/// signal handler, since [`request`](Self::request) is just an atomic
+/// store.
+///
+/// The thread doing the recording checks
+/// [`FlightRecorder::take_dump_request`] in its loop and dumps when
+/// it's set.
+#[derive(Debug, Clone, Default)]
+pub struct DumpRequest(Arc<AtomicBool>);
+
+impl DumpRequest {
+    /// Ask for a dump.
+    #[inline]
+    pub fn request(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Reads packets from a classic pcap file, as written by a
+/// [`PcapWriter`] or tcpdump.
+#[derive(Debug)]
+pub struct PcapReader<R: Read> {
+    input: R,
+    swapped: bool,
+    nanos: bool,
+    link_type: u32,
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Read and check the file header from `input`.
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0; 24];
+        input.read_exact(&mut header)?;
+
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+
+        let (swapped, nanos) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            m if m.swap_bytes() == MAGIC_MICROS => (true, false),
+            m if m.swap_bytes() == MAGIC_NANOS => (true, true),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a pcap file",
+                ))
+            }
+        };
+
+        let mut reader = Self {
+            input,
+            swapped,
+            nanos,
+            link_type: 0,
+        };
+        reader.link_type = reader.u32_at(&header, 20);
+
+        Ok(reader)
+    }
+
+    /// The link type of every packet in the file, usually
+    /// [`LINKTYPE_ETHERNET`].
+    pub fn link_type(&self) -> u32 {
+        self.link_type
+    }
+
+    /// Read the next packet into `buf`, replacing its contents, and
+    /// return its timestamp as time since the epoch. `None` at the end
+    /// of the file.
+    pub fn read_packet(&mut self, buf: &mut Vec<u8>) -> io::Result<Option<Duration>> {
+        let mut header = [0; 16];
+
+        match self.input.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let secs = self.u32_at(&header, 0) as u64;
+        let frac = self.u32_at(&header, 4);
+        let incl_len = self.u32_at(&header, 8) as usize;
+
+        let ts = if self.nanos {
+            Duration::new(secs, frac)
+        } else {
+            Duration::new(secs, frac.saturating_mul(1000))
+        };
+
+        buf.clear();
+        buf.resize(incl_len, 0);
+        self.input.read_exact(buf)?;
+
+        Ok(Some(ts))
+    }
+
+    fn u32_at(&self, buf: &[u8], pos: usize) -> u32 {
+        let v = u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]]);
+        if self.swapped {
+            v.swap_bytes()
+        } else {
+            v
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(buf: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]])
+    }
+
+    #[test]
+    fn records_are_truncated_to_snaplen_keeping_the_original_length() {
+        let mut pcap = PcapWriter::new(Vec::new(), 4).unwrap();
+
+        pcap.write_packet_since_epoch(Duration::new(7, 42), &[1, 2, 3, 4, 5, 6])
+            .unwrap();
+        pcap.write_packet_since_epoch(Duration::new(8, 0), &[9])
+            .unwrap();
+
+        let buf = pcap.into_inner();
+
+        assert_eq!(u32_at(&buf, 0), MAGIC_NANOS);
+        assert_eq!(u32_at(&buf, 16), 4);
+        assert_eq!(u32_at(&buf, 20), LINKTYPE_ETHERNET);
+
+        let rec = &buf[24..];
+        assert_eq!(u32_at(rec, 0), 7);
+        assert_eq!(u32_at(rec, 4), 42);
+        assert_eq!(u32_at(rec, 8), 4);
+        assert_eq!(u32_at(rec, 12), 6);
+        assert_eq!(&rec[16..20], [1, 2, 3, 4]);
+
+        let rec = &rec[20..];
+        assert_eq!((u32_at(rec, 8), u32_at(rec, 12)), (1, 1));
+        assert_eq!(rec[16..], [9]);
+    }
+
+    #[test]
+    fn reader_reads_back_written_packets() {
+        let mut pcap = PcapWriter::new(Vec::new(), 65535).unwrap();
+        pcap.write_packet_since_epoch(Duration::new(7, 42), &[1, 2, 3])
+            .unwrap();
+        pcap.write_packet_since_epoch(Duration::new(8, 0), &[9])
+            .unwrap();
+
+        let buf = pcap.into_inner();
+        let mut reader = PcapReader::new(&buf[..]).unwrap();
+        assert_eq!(reader.link_type(), LINKTYPE_ETHERNET);
+
+        let mut packet = Vec::new();
+        assert_eq!(
+            reader.read_packet(&mut packet).unwrap(),
+            Some(Duration::new(7, 42))
+        );
+        assert_eq!(packet, [1, 2, 3]);
+        assert_eq!(
+            reader.read_packet(&mut packet).unwrap(),
+            Some(Duration::new(8, 0))
+        );
+        assert_eq!(packet, [9]);
+        assert_eq!(reader.read_packet(&mut packet).unwrap(), None);
+
+        // A big-endian, microsecond capture as written elsewhere.
+        let mut be = Vec::new();
+        be.extend_from_slice(&MAGIC_MICROS.to_be_bytes());
+        be.extend_from_slice(&[0, 2, 0, 4]);
+        be.extend_from_slice(&[0; 8]);
+        be.extend_from_slice(&65535u32.to_be_bytes());
+        be.extend_from_slice(&LINKTYPE_ETHERNET.to_be_bytes());
+        for v in [3u32, 5, 1, 1] {
+            be.extend_from_slice(&v.to_be_bytes());
+        }
+        be.push(0xff);
+
+        let mut reader = PcapReader::new(&be[..]).unwrap();
+        assert_eq!(reader.link_type(), LINKTYPE_ETHERNET);
+        assert_eq!(
+            reader.read_packet(&mut packet).unwrap(),
+            Some(Duration::new(3, 5000))
+        );
+        assert_eq!(packet, [0xff]);
+
+        assert!(PcapReader::new(&[0u8; 24][..]).is_err());
+    }
+
+    #[test]
+    fn flight_recorder_keeps_the_latest_packets_truncated() {
+        let mut recorder = FlightRecorder::new(2, 2);
+        let ts = |secs| UNIX_EPOCH + Duration::from_secs(secs);
+
+        recorder.record_rx(ts(1), &[1, 1, 1]);
+        recorder.record_tx(ts(2), &[2]);
+        recorder.record_rx(ts(3), &[3, 3, 3, 3]);
+
+        assert_eq!(recorder.len(), 2);
+        assert_eq!(recorder.recorded(), 3);
+
+        let buf = recorder.dump(Vec::new(), None).unwrap();
+        let mut reader = PcapReader::new(&buf[..]).unwrap();
+        let mut packet = Vec::new();
+
+        assert_eq!(
+            reader.read_packet(&mut packet).unwrap(),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(packet, [2]);
+
+        assert_eq!(
+            reader.read_packet(&mut packet).unwrap(),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(packet, [3, 3]);
+        assert_eq!(u32_at(&buf, 24 + 17 + 12), 4);
+        assert_eq!(reader.read_packet(&mut packet).unwrap(), None);
+
+        let buf = recorder.dump(Vec::new(), Some(Direction::Tx)).unwrap();
+        assert_eq!(buf.len(), 24 + 16 + 1);
+
+        let request = recorder.dump_request();
+        assert!(!recorder.take_dump_request());
+        request.request();
+        assert!(recorder.take_dump_request());
+        assert!(!recorder.take_dump_request());
+    }
+}
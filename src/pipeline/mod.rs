@@ -0,0 +1,351 @@
+//! Composable per-frame processing stages.
+//!
+//! A stage is anything implementing [`Middleware`], including plain
+//! `FnMut(&mut FrameView) -> Action` closures and functions. Stages
+//! are chained by putting them in a tuple, which is itself
+//! [`Middleware`], so a whole chain is statically dispatched and can
+//! be inlined into one loop with no allocation or virtual calls:
+//!
+//! ```
+//! use xsk_rs::pipeline::{stages, Pipeline};
+//!
+//! let pipeline = Pipeline::new((
+//!     stages::strip_vlan,
+//!     stages::filter(|frame: &[u8]| frame.len() >= 60),
+//!     stages::decrement_ttl,
+//! ));
+//! ```
+//!
+//! Some common stages are provided in [`stages`], and any stage can
+//! be wrapped in [`timing::Timed`] to record the cycles it takes.
+
+pub mod stages;
+pub mod timing;
+
+use std::ops::Range;
+
+use crate::umem::{frame::FrameDesc, Umem};
+
+/// What should happen to a frame after a stage has seen it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Hand the frame to the next stage, or keep it if this was the
+    /// last one.
+    Continue,
+    /// Keep the frame, skipping any remaining stages.
+    Accept,
+    /// Discard the frame, skipping any remaining stages.
+    Drop,
+}
+
+/// A mutable view of a frame's packet data, handed to each stage.
+#[derive(Debug)]
+pub struct FrameView<'a> {
+    len: &'a mut usize,
+    buf: &'a mut [u8],
+}
+
+impl<'a> FrameView<'a> {
+    pub(crate) fn from_parts(len: &'a mut usize, buf: &'a mut [u8]) -> Self {
+        debug_assert!(*len <= buf.len());
+        Self { len, buf }
+    }
+
+    /// The frame's contents.
+    #[inline]
+    pub fn contents(&self) -> &[u8] {
+        &self.buf[..*self.len]
+    }
+
+    /// A mutable view of the frame's contents.
+    #[inline]
+    pub fn contents_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[..*self.len]
+    }
+
+    /// The length of the frame's contents.
+    #[inline]
+    pub fn len(&self) -> usize {
+        *self.len
+    }
+
+    /// Whether the frame is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        *self.len == 0
+    }
+
+    /// Shorten the frame to `len` bytes. Has no effect if it's
+    /// already no longer than that.
+    #[inline]
+    pub fn truncate(&mut self, len: usize) {
+        *self.len = (*self.len).min(len);
+    }
+
+    /// Cut `range` out of the frame, moving everything after it down
+    /// to close the gap.
+    ///
+    /// # Panics
+    ///
+    /// If `range` extends beyond the frame's contents.
+    pub fn remove(&mut self, range: Range<usize>) {
+        let len = *self.len;
+
+        assert!(
+            range.start <= range.end && range.end <= len,
+            "range {:?} out of bounds for frame of length {}",
+            range,
+            len
+        );
+
+        self.buf.copy_within(range.end..len, range.start);
+        *self.len -= range.end - range.start;
+    }
+}
+
+/// A frame processing stage, see the [module docs](self).
+pub trait Middleware {
+    /// Process `frame`, returning what should happen to it next.
+    fn process(&mut self, frame: &mut FrameView<'_>) -> Action;
+
+    /// Called by [`Pipeline::process`] once every frame of a batch
+    /// has been through the chain. Does nothing by default.
+    #[inline]
+    fn end_batch(&mut self) {}
+}
+
+impl<F> Middleware for F
+where
+    F: FnMut(&mut FrameView<'_>) -> Action,
+{
+    #[inline]
+    fn process(&mut self, frame: &mut FrameView<'_>) -> Action {
+        self(frame)
+    }
+}
+
+/// The empty chain, which keeps everything.
+impl Middleware for () {
+    #[inline]
+    fn process(&mut self, _frame: &mut FrameView<'_>) -> Action {
+        Action::Continue
+    }
+}
+
+macro_rules! impl_middleware_for_tuple {
+    ($($name:ident),+) => {
+        /// Runs each stage in turn until one returns something other
+        /// than [`Action::Continue`].
+        impl<$($name: Middleware),+> Middleware for ($($name,)+) {
+            #[inline]
+            #[allow(non_snake_case)]
+            fn process(&mut self, frame: &mut FrameView<'_>) -> Action {
+                let ($($name,)+) = self;
+
+                $(
+                    match $name.process(frame) {
+                        Action::Continue => (),
+                        action => return action,
+                    }
+                )+
+
+                Action::Continue
+            }
+
+            #[inline]
+            #[allow(non_snake_case)]
+            fn end_batch(&mut self) {
+                let ($($name,)+) = self;
+
+                $($name.end_batch();)+
+            }
+        }
+    };
+}
+
+impl_middleware_for_tuple!(A);
+impl_middleware_for_tuple!(A, B);
+impl_middleware_for_tuple!(A, B, C);
+impl_middleware_for_tuple!(A, B, C, D);
+impl_middleware_for_tuple!(A, B, C, D, E);
+impl_middleware_for_tuple!(A, B, C, D, E, F);
+impl_middleware_for_tuple!(A, B, C, D, E, F, G);
+impl_middleware_for_tuple!(A, B, C, D, E, F, G, H);
+
+/// Runs a [`Middleware`] chain over batches of frames.
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline<M> {
+    middleware: M,
+    kept: u64,
+    dropped: u64,
+}
+
+impl<M: Middleware> Pipeline<M> {
+    /// Create a pipeline running `middleware` on every frame.
+    pub fn new(middleware: M) -> Self {
+        Self {
+            middleware,
+            kept: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Run a single frame through the chain, returning whether it
+    /// should be kept.
+    ///
+    /// # Safety
+    ///
+    /// See [`Umem::data_mut`].
+    #[inline]
+    pub unsafe fn process_one(&mut self, umem: &Umem, desc: &mut FrameDesc) -> bool {
+        // SAFETY: guaranteed by this function's contract.
+        let (len, buf) = unsafe { umem.data_mut(desc) }.into_parts();
+
+        let keep = self
+            .middleware
+            .process(&mut FrameView::from_parts(len, buf))
+            != Action::Drop;
+
+        if keep {
+            self.kept += 1;
+        } else {
+            self.dropped += 1;
+        }
+
+        keep
+    }
+
+    /// Run each frame of `descs` through the chain, then reorder
+    /// `descs` so that kept frames come first, in their original
+    /// order. Returns the number kept.
+    ///
+    /// The kept frames, `descs[..n]`, are typically passed on to a
+    /// [`TxQueue`](crate::TxQueue) and the dropped ones, `descs[n..]`,
+    /// returned to the [`FillQueue`](crate::FillQueue).
+    ///
+    /// # Safety
+    ///
+    /// See [`Umem::data_mut`]. Every descriptor in `descs` must
+    /// satisfy its requirements.
+    pub unsafe fn process(&mut self, umem: &Umem, descs: &mut [FrameDesc]) -> usize {
+        let mut kept = 0;
+
+        for i in 0..descs.len() {
+            // SAFETY: guaranteed by this function's contract.
+            if unsafe { self.process_one(umem, &mut descs[i]) } {
+                descs.swap(kept, i);
+                kept += 1;
+            }
+        }
+
+        self.middleware.end_batch();
+
+        kept
+    }
+
+    /// Tell the chain a batch is complete. Only needed when frames are
+    /// passed through [`process_one`](Self::process_one) rather than
+    /// [`process`](Self::process).
+    #[inline]
+    pub fn end_batch(&mut self) {
+        self.middleware.end_batch();
+    }
+
+    /// The middleware chain.
+    pub fn middleware(&self) -> &M {
+        &self.middleware
+    }
+
+    /// A mutable reference to the middleware chain, e.g. to update a
+    /// stage's configuration between batches.
+    pub fn middleware_mut(&mut self) -> &mut M {
+        &mut self.middleware
+    }
+
+    /// The number of frames kept so far.
+    pub fn kept(&self) -> u64 {
+        self.kept
+    }
+
+    /// The number of frames dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn chain_stops_at_first_non_continue() {
+        let seen = [Cell::new(0), Cell::new(0), Cell::new(0)];
+
+        {
+            let mut chain = (
+                |_: &mut FrameView<'_>| {
+                    seen[0].set(seen[0].get() + 1);
+                    Action::Continue
+                },
+                |frame: &mut FrameView<'_>| {
+                    seen[1].set(seen[1].get() + 1);
+                    if frame.contents()[0] == 0 {
+                        Action::Drop
+                    } else {
+                        Action::Continue
+                    }
+                },
+                |_: &mut FrameView<'_>| {
+                    seen[2].set(seen[2].get() + 1);
+                    Action::Accept
+                },
+            );
+
+            for first in [0, 1] {
+                let mut buf = [first; 4];
+                let mut len = 4;
+
+                let expected = if first == 0 {
+                    Action::Drop
+                } else {
+                    Action::Accept
+                };
+
+                assert_eq!(
+                    chain.process(&mut FrameView::from_parts(&mut len, &mut buf)),
+                    expected
+                );
+            }
+        }
+
+        assert_eq!(seen.map(|c| c.get()), [2, 2, 1]);
+    }
+
+    #[test]
+    fn remove_closes_the_gap() {
+        let mut buf = [0, 1, 2, 3, 4, 5, 6, 7];
+        let mut len = 6;
+
+        let mut frame = FrameView::from_parts(&mut len, &mut buf);
+
+        frame.remove(1..3);
+        assert_eq!(frame.contents(), [0, 3, 4, 5]);
+
+        frame.truncate(10);
+        assert_eq!(frame.len(), 4);
+
+        frame.truncate(2);
+        assert_eq!(frame.contents(), [0, 3]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn remove_beyond_contents_panics() {
+        let mut buf = [0; 8];
+        let mut len = 4;
+
+        FrameView::from_parts(&mut len, &mut buf).remove(2..6);
+    }
+}
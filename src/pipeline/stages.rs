@@ -0,0 +1,151 @@
+//! Ready made [`Middleware`] stages.
+
+use crate::{
+    checksum,
+    packet::{self, ETH_P_8021AD, ETH_P_8021Q, ETH_P_IPV4, ETH_P_IPV6, VLAN_HLEN},
+};
+
+use super::{Action, FrameView, Middleware};
+
+/// Remove the outermost VLAN tag, if there is one.
+pub fn strip_vlan(frame: &mut FrameView<'_>) -> Action {
+    match packet::read_u16(frame.contents(), 12) {
+        Some(ETH_P_8021Q) | Some(ETH_P_8021AD) if frame.len() >= 16 + VLAN_HLEN => {
+            frame.remove(12..12 + VLAN_HLEN);
+        }
+        _ => (),
+    }
+
+    Action::Continue
+}
+
+/// Decrement the IPv4 TTL or IPv6 hop limit, as a router forwarding
+/// the frame would, updating the IPv4 header checksum to match.
+///
+/// Frames whose TTL would reach zero are dropped. Non-IP frames are
+/// passed through untouched.
+pub fn decrement_ttl(frame: &mut FrameView<'_>) -> Action {
+    let (ethertype, l3_offset) = match packet::l3(frame.contents()) {
+        Some(l3) => l3,
+        None => return Action::Continue,
+    };
+
+    let ttl_offset = match ethertype {
+        ETH_P_IPV4 => l3_offset + 8,
+        ETH_P_IPV6 => l3_offset + 7,
+        _ => return Action::Continue,
+    };
+
+    let contents = frame.contents_mut();
+
+    let ttl = match contents.get(ttl_offset) {
+        Some(&ttl) => ttl,
+        None => return Action::Continue,
+    };
+
+    if ttl <= 1 {
+        return Action::Drop;
+    }
+
+    contents[ttl_offset] = ttl - 1;
+
+    if ethertype == ETH_P_IPV4 {
+        if let Some(check) = contents.get(l3_offset + 10..l3_offset + 12) {
+            // The TTL is the high byte of its 16 bit word, the
+            // protocol the low byte, which doesn't change.
+            let check = u16::from_be_bytes([check[0], check[1]]);
+            let proto = u16::from(contents[ttl_offset + 1]);
+
+            let old = u16::from(ttl) << 8 | proto;
+            let new = u16::from(ttl - 1) << 8 | proto;
+
+            contents[l3_offset + 10..l3_offset + 12]
+                .copy_from_slice(&checksum::update(check, old, new).to_be_bytes());
+        }
+    }
+
+    Action::Continue
+}
+
+/// Drop frames for which `keep` returns `false`.
+pub fn filter<P>(mut keep: P) -> impl Middleware
+where
+    P: FnMut(&[u8]) -> bool,
+{
+    move |frame: &mut FrameView<'_>| {
+        if keep(frame.contents()) {
+            Action::Continue
+        } else {
+            Action::Drop
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{tests::udp4_frame, ETH_HLEN, IPV4_MIN_HLEN};
+
+    use super::*;
+
+    fn run<M: Middleware>(stage: &mut M, frame: &mut Vec<u8>) -> Action {
+        let mut len = frame.len();
+        let action = stage.process(&mut FrameView::from_parts(&mut len, frame));
+        frame.truncate(len);
+        action
+    }
+
+    fn with_valid_checksum(mut frame: Vec<u8>, l3_offset: usize) -> Vec<u8> {
+        let header = l3_offset..l3_offset + IPV4_MIN_HLEN;
+        let check = checksum::ipv4_header(&frame[header.clone()]);
+        frame[l3_offset + 10..l3_offset + 12].copy_from_slice(&check.to_be_bytes());
+        assert_eq!(checksum::checksum(&frame[header]), 0);
+        frame
+    }
+
+    #[test]
+    fn vlan_tag_is_stripped() {
+        let mut frame = udp4_frame(2, 1234, 53);
+
+        assert_eq!(run(&mut strip_vlan, &mut frame), Action::Continue);
+        assert_eq!(frame, udp4_frame(1, 1234, 53));
+
+        run(&mut strip_vlan, &mut frame);
+        assert_eq!(frame, udp4_frame(0, 1234, 53));
+
+        run(&mut strip_vlan, &mut frame);
+        assert_eq!(frame, udp4_frame(0, 1234, 53));
+    }
+
+    #[test]
+    fn ttl_is_decremented_and_checksum_stays_valid() {
+        for vlans in 0..=1 {
+            let l3_offset = ETH_HLEN + vlans * VLAN_HLEN;
+            let mut frame = with_valid_checksum(udp4_frame(vlans, 1, 2), l3_offset);
+            let ttl = frame[l3_offset + 8];
+
+            assert_eq!(run(&mut decrement_ttl, &mut frame), Action::Continue);
+
+            assert_eq!(frame[l3_offset + 8], ttl - 1);
+            assert_eq!(
+                checksum::checksum(&frame[l3_offset..l3_offset + IPV4_MIN_HLEN]),
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn expiring_ttl_is_dropped() {
+        let mut frame = udp4_frame(0, 1, 2);
+        frame[ETH_HLEN + 8] = 1;
+
+        assert_eq!(run(&mut decrement_ttl, &mut frame), Action::Drop);
+    }
+
+    #[test]
+    fn filter_drops_rejected_frames() {
+        let mut stage = filter(|frame: &[u8]| frame.len() > 20);
+
+        assert_eq!(run(&mut stage, &mut vec![0; 32]), Action::Continue);
+        assert_eq!(run(&mut stage, &mut vec![0; 8]), Action::Drop);
+    }
+}
@@ -0,0 +1,83 @@
+use alloc::vec::Vec;
+
+use super::FrameLayout;
+
+/// A stack of unused frame addresses.
+///
+/// Pops hand out the most recently pushed address first, which keeps
+/// the hottest frames in use and so in cache.
+#[derive(Debug, Clone, Default)]
+pub struct FreeList {
+    addrs: Vec<usize>,
+}
+
+impl FreeList {
+    /// An empty list with room for `capacity` addresses.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            addrs: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// A list holding the descriptor address of each of the
+    /// `frame_count` frames laid out as per `layout`, with frame zero
+    /// on top.
+    pub fn for_frames(layout: &FrameLayout, frame_count: usize) -> Self {
+        Self {
+            addrs: (0..frame_count)
+                .rev()
+                .map(|i| layout.data_addr(i))
+                .collect(),
+        }
+    }
+
+    /// Take an address off the list.
+    #[inline]
+    pub fn pop(&mut self) -> Option<usize> {
+        self.addrs.pop()
+    }
+
+    /// Return an address to the list.
+    #[inline]
+    pub fn push(&mut self, addr: usize) {
+        self.addrs.push(addr);
+    }
+
+    /// Whether `addr` is on the list. Takes time linear in its length.
+    pub fn contains(&self, addr: usize) -> bool {
+        self.addrs.contains(&addr)
+    }
+
+    /// The number of addresses on the list.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.addrs.len()
+    }
+
+    /// Whether the list is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_handed_out_lowest_first_then_last_in_first_out() {
+        let layout = FrameLayout::new(2048, 256, 0).unwrap();
+        let mut list = FreeList::for_frames(&layout, 3);
+
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.pop(), Some(256));
+        assert_eq!(list.pop(), Some(2048 + 256));
+
+        list.push(256);
+
+        assert_eq!(list.pop(), Some(256));
+        assert_eq!(list.pop(), Some(2 * 2048 + 256));
+        assert_eq!(list.pop(), None);
+    }
+}
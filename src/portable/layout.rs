@@ -0,0 +1,210 @@
+/// How far the offset the kernel adds to descriptor addresses in
+/// unaligned chunk mode is shifted up.
+const UNALIGNED_OFFSET_SHIFT: u32 = 48;
+
+/// Dimensions of a UMEM frame, and the address maths that follows from
+/// them.
+///
+/// A frame is laid out as the XDP headroom, then the user's headroom,
+/// then the packet data segment. Descriptor addresses point at the
+/// start of the packet data segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLayout {
+    xdp_headroom: usize,
+    frame_headroom: usize,
+    mtu: usize,
+}
+
+impl FrameLayout {
+    /// Lay out a frame of `frame_size` bytes with the given headroom.
+    /// Returns [`None`] if the headroom doesn't fit in the frame.
+    pub fn new(frame_size: u32, xdp_headroom: u32, frame_headroom: u32) -> Option<Self> {
+        let total_headroom = xdp_headroom.checked_add(frame_headroom)?;
+        let mtu = frame_size.checked_sub(total_headroom)?;
+
+        Some(Self {
+            xdp_headroom: xdp_headroom as usize,
+            frame_headroom: frame_headroom as usize,
+            mtu: mtu as usize,
+        })
+    }
+
+    /// The total size of a frame.
+    #[inline]
+    pub fn frame_size(&self) -> usize {
+        self.xdp_headroom + self.frame_headroom + self.mtu
+    }
+
+    /// The headroom reserved for the XDP program.
+    #[inline]
+    pub fn xdp_headroom(&self) -> usize {
+        self.xdp_headroom
+    }
+
+    /// The headroom available to the user.
+    #[inline]
+    pub fn frame_headroom(&self) -> usize {
+        self.frame_headroom
+    }
+
+    /// The length of the packet data segment.
+    #[inline]
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// The descriptor address of the frame at `idx`, i.e. the offset
+    /// of its packet data segment from the start of the UMEM.
+    #[inline]
+    pub fn data_addr(&self, idx: usize) -> usize {
+        idx * self.frame_size() + self.xdp_headroom + self.frame_headroom
+    }
+
+    /// The offset of the user headroom segment of the frame whose
+    /// descriptor address is `addr`.
+    #[inline]
+    pub fn headroom_addr(&self, addr: usize) -> usize {
+        addr - self.frame_headroom
+    }
+
+    /// The index of the frame containing `addr`.
+    #[inline]
+    pub fn frame_index(&self, addr: usize) -> usize {
+        addr / self.frame_size()
+    }
+
+    /// The offset from the start of the UMEM that `addr` refers to.
+    ///
+    /// In unaligned chunk mode the kernel hands out descriptors with
+    /// the chunk's base address in the lower 48 bits and the packet's
+    /// offset from it in the upper 16, which are added together here.
+    /// In aligned mode `addr` is returned as is.
+    #[inline]
+    pub fn resolve_addr(addr: usize, unaligned_chunks: bool) -> usize {
+        if unaligned_chunks {
+            let (base, offset) = Self::split_unaligned(addr);
+            base + offset as usize
+        } else {
+            addr
+        }
+    }
+
+    /// The address to put on the fill ring for the frame whose
+    /// descriptor address is `addr`.
+    ///
+    /// In unaligned chunk mode the kernel takes a fill address as the
+    /// start of the chunk and adds the headroom itself, so the headroom
+    /// is taken off here, or for a descriptor carrying an offset, its
+    /// base is used. In aligned mode the kernel masks the address down
+    /// to the frame, so `addr` is returned as is.
+    #[inline]
+    pub fn fill_addr(&self, addr: usize, unaligned_chunks: bool) -> usize {
+        if !unaligned_chunks {
+            return addr;
+        }
+
+        match Self::split_unaligned(addr) {
+            (base, 0) => base.saturating_sub(self.xdp_headroom + self.frame_headroom),
+            (base, _) => base,
+        }
+    }
+
+    /// The descriptor address of the frame whose fill ring address is
+    /// `addr`, the inverse of [`fill_addr`](Self::fill_addr).
+    #[inline]
+    pub fn addr_from_fill(&self, addr: usize, unaligned_chunks: bool) -> usize {
+        if unaligned_chunks {
+            addr + self.xdp_headroom + self.frame_headroom
+        } else {
+            addr
+        }
+    }
+
+    /// Encode `offset` bytes on from the chunk at `base` as an unaligned
+    /// chunk mode descriptor address. Returns [`None`] if `base` doesn't
+    /// fit in the lower 48 bits.
+    #[inline]
+    pub fn encode_unaligned(base: usize, offset: u16) -> Option<usize> {
+        let base = base as u64;
+
+        if base >> UNALIGNED_OFFSET_SHIFT != 0 {
+            return None;
+        }
+
+        Some((base | (u64::from(offset) << UNALIGNED_OFFSET_SHIFT)) as usize)
+    }
+
+    /// Split an unaligned chunk mode descriptor address into the
+    /// chunk's base address and the offset from it, the inverse of
+    /// [`encode_unaligned`](Self::encode_unaligned).
+    #[inline]
+    pub fn split_unaligned(addr: usize) -> (usize, u16) {
+        let addr = addr as u64;
+        let base = addr & ((1 << UNALIGNED_OFFSET_SHIFT) - 1);
+
+        (base as usize, (addr >> UNALIGNED_OFFSET_SHIFT) as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_round_trip_through_frame_index() {
+        let layout = FrameLayout::new(2048, 256, 32).unwrap();
+
+        assert_eq!(layout.mtu(), 2048 - 256 - 32);
+        assert_eq!(layout.data_addr(0), 288);
+        assert_eq!(layout.data_addr(3), 3 * 2048 + 288);
+        assert_eq!(layout.headroom_addr(layout.data_addr(3)), 3 * 2048 + 256);
+        assert_eq!(layout.frame_index(layout.data_addr(3)), 3);
+    }
+
+    #[test]
+    fn unaligned_addresses_have_their_offset_added() {
+        let layout = FrameLayout::new(2048, 256, 0).unwrap();
+        let addr = (64 << UNALIGNED_OFFSET_SHIFT) | (5 * 2048 + 256);
+
+        assert_eq!(FrameLayout::resolve_addr(addr, true), 5 * 2048 + 320);
+        assert_eq!(layout.frame_index(FrameLayout::resolve_addr(addr, true)), 5);
+        assert_eq!(FrameLayout::resolve_addr(4096, true), 4096);
+        assert_eq!(FrameLayout::resolve_addr(addr, false), addr);
+    }
+
+    #[test]
+    fn unaligned_addresses_round_trip_through_encoding() {
+        let addr = FrameLayout::encode_unaligned(5 * 2048 + 256, 64).unwrap();
+
+        assert_eq!(addr, (64 << UNALIGNED_OFFSET_SHIFT) | (5 * 2048 + 256));
+        assert_eq!(FrameLayout::split_unaligned(addr), (5 * 2048 + 256, 64));
+        assert_eq!(FrameLayout::split_unaligned(4096), (4096, 0));
+        assert!(FrameLayout::encode_unaligned(1 << UNALIGNED_OFFSET_SHIFT, 0).is_none());
+    }
+
+    #[test]
+    fn unaligned_fill_addresses_are_the_chunk_start() {
+        let layout = FrameLayout::new(2048, 256, 32).unwrap();
+        let rx_addr = FrameLayout::encode_unaligned(3 * 2048, 256 + 32 + 14).unwrap();
+
+        assert_eq!(layout.fill_addr(layout.data_addr(3), true), 3 * 2048);
+        assert_eq!(layout.fill_addr(rx_addr, true), 3 * 2048);
+        assert_eq!(layout.addr_from_fill(3 * 2048, true), layout.data_addr(3));
+
+        assert_eq!(
+            layout.fill_addr(layout.data_addr(3), false),
+            layout.data_addr(3)
+        );
+        assert_eq!(
+            layout.addr_from_fill(layout.data_addr(3), false),
+            layout.data_addr(3)
+        );
+    }
+
+    #[test]
+    fn headroom_larger_than_frame_is_rejected() {
+        assert!(FrameLayout::new(2048, 256, 1792).is_some());
+        assert!(FrameLayout::new(2048, 256, 1793).is_none());
+        assert!(FrameLayout::new(2048, u32::MAX, 1).is_none());
+    }
+}
@@ -0,0 +1,23 @@
+//! The parts of the crate which don't need `std` or any syscalls:
+//! frame layout and descriptor address maths, config validation, ring
+//! index arithmetic and a simple frame free list.
+//!
+//! Only `core` and `alloc` are used here, so this module is all that's
+//! built when the default `std` feature is disabled. That lets
+//! stripped down embedded systems or custom runtimes reuse the logic
+//! while providing the socket, UMEM and polling layer themselves. The
+//! rest of the crate is built on top of it.
+
+mod layout;
+pub use layout::FrameLayout;
+
+mod free_list;
+pub use free_list::FreeList;
+
+mod ring;
+pub use ring::RingIndices;
+
+mod validate;
+pub use validate::{
+    is_valid_chunk_size, is_valid_frame_size, is_valid_queue_size, XDP_UMEM_MIN_CHUNK_SIZE,
+};
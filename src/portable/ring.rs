@@ -0,0 +1,70 @@
+/// Index arithmetic for an AF_XDP ring of some power of two size.
+///
+/// Producer and consumer indices run freely and wrap at `u32::MAX`,
+/// only being masked down to a slot when the ring is accessed. This
+/// mirrors what libxdp does for the rings it manages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingIndices {
+    mask: u32,
+}
+
+impl RingIndices {
+    /// Arithmetic for a ring of `size` entries. Returns [`None`] if
+    /// `size` isn't a valid ring size, see
+    /// [`is_valid_queue_size`](super::is_valid_queue_size).
+    pub fn new(size: u32) -> Option<Self> {
+        if super::is_valid_queue_size(size) {
+            Some(Self { mask: size - 1 })
+        } else {
+            None
+        }
+    }
+
+    /// The number of entries the ring holds.
+    #[inline]
+    pub fn size(&self) -> u32 {
+        self.mask + 1
+    }
+
+    /// The ring slot which index `idx` refers to.
+    #[inline]
+    pub fn slot(&self, idx: u32) -> u32 {
+        idx & self.mask
+    }
+
+    /// The number of entries a producer at `producer` may still write
+    /// before catching up with a consumer at `consumer`.
+    #[inline]
+    pub fn free(&self, producer: u32, consumer: u32) -> u32 {
+        self.size() - self.available(producer, consumer)
+    }
+
+    /// The number of entries written by a producer at `producer` which
+    /// a consumer at `consumer` has yet to read.
+    #[inline]
+    pub fn available(&self, producer: u32, consumer: u32) -> u32 {
+        producer.wrapping_sub(consumer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indices_wrap_around() {
+        let ring = RingIndices::new(8).unwrap();
+
+        assert_eq!(ring.free(0, 0), 8);
+        assert_eq!(ring.available(5, 2), 3);
+
+        let consumer = u32::MAX - 2;
+        let producer = consumer.wrapping_add(6);
+
+        assert_eq!(ring.available(producer, consumer), 6);
+        assert_eq!(ring.free(producer, consumer), 2);
+        assert_eq!(ring.slot(producer), 3);
+
+        assert!(RingIndices::new(6).is_none());
+    }
+}
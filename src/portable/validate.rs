@@ -0,0 +1,50 @@
+/// The minimum [`Umem`](crate::Umem) frame size.
+///
+/// Matches the constant of the same name defined in the linux source
+/// at `net/xdp/xdp_umem.c`
+pub const XDP_UMEM_MIN_CHUNK_SIZE: u32 = 2048;
+
+/// Whether `size` may be used as a ring size, i.e. is a non-zero
+/// power of two.
+#[inline]
+pub fn is_valid_queue_size(size: u32) -> bool {
+    size.is_power_of_two()
+}
+
+/// Whether `size` may be used as a frame size, i.e. is at least
+/// [`XDP_UMEM_MIN_CHUNK_SIZE`].
+#[inline]
+pub fn is_valid_frame_size(size: u32) -> bool {
+    size >= XDP_UMEM_MIN_CHUNK_SIZE
+}
+
+/// Whether `size` may be used as the frame size of a UMEM registered
+/// in the given chunk mode. Aligned mode needs a power of two on top
+/// of [`is_valid_frame_size`], since the kernel finds a frame's start
+/// by masking off the low bits of an address.
+#[inline]
+pub fn is_valid_chunk_size(size: u32, unaligned_chunks: bool) -> bool {
+    is_valid_frame_size(size) && (unaligned_chunks || size.is_power_of_two())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_powers_of_two() {
+        assert!(!is_valid_queue_size(0));
+        assert!(is_valid_queue_size(1));
+        assert!(is_valid_queue_size(2));
+        assert!(!is_valid_queue_size(13));
+        assert!(is_valid_queue_size(1 << 31));
+    }
+
+    #[test]
+    fn only_unaligned_chunks_may_be_any_size() {
+        assert!(is_valid_chunk_size(4096, false));
+        assert!(!is_valid_chunk_size(3000, false));
+        assert!(is_valid_chunk_size(3000, true));
+        assert!(!is_valid_chunk_size(1024, true));
+    }
+}
@@ -0,0 +1,19 @@
+//! The types needed for the common path of creating a [`Umem`] and
+//! [`Socket`] and moving frames through their queues.
+//!
+//! ```
+//! use xsk_rs::prelude::*;
+//! ```
+
+pub use crate::{
+    config::{
+        BindFlags, FrameSize, Interface, LibxdpFlags, QueueSize, SocketConfig, SocketConfigBuilder,
+        UmemConfig, UmemConfigBuilder, XdpFlags,
+    },
+    socket::{RxQueue, Socket, TxQueue},
+    umem::{
+        frame::{DescBatch, FrameDesc},
+        CompQueue, FillQueue, Umem,
+    },
+    xsk::Xsk,
+};
@@ -0,0 +1,296 @@
+//! Python bindings, for scripting traffic scenarios and checks against
+//! the same code paths used from Rust and C.
+//!
+//! Enabled with the `python` feature. Build the extension module with
+//!
+//! ```text
+//! cargo rustc --release --features python,pyo3/extension-module --crate-type cdylib
+//! cp target/release/libxsk_rs.so xsk_rs.so
+//! ```
+//!
+//! after which, as root,
+//!
+//! ```text
+//! import xsk_rs
+//!
+//! sock = xsk_rs.Socket("veth0", queue_id=0, frame_count=4096)
+//!
+//! for addr, data in sock.recv(64):
+//!     assert data[12:14] == b"\x08\x00"
+//!     data[0:6] = data[6:12]
+//!     sock.transmit([(addr, len(data))])
+//!
+//! sock.send([b"\xff" * 60])
+//! ```
+//!
+//! wraps an [`XskRsSocket`], so frames are tracked the same way as in
+//! the [C ABI](crate::ffi).
+
+// The wrappers generated by `#[pymethods]` trip this.
+#![allow(unsafe_op_in_unsafe_fn)]
+
+use std::{
+    convert::TryFrom,
+    num::NonZeroU32,
+    os::raw::{c_int, c_void},
+    rc::Rc,
+};
+
+use pyo3::{
+    exceptions::{PyBufferError, PyOSError, PyValueError},
+    ffi,
+    prelude::*,
+    types::PyMemoryView,
+};
+
+use crate::{
+    config::Interface,
+    ffi::{errno, XskRsDesc, XskRsSocket},
+    xsk::XskBuildError,
+};
+
+fn build_error(err: XskBuildError) -> PyErr {
+    PyOSError::new_err((errno(&err), err.to_string()))
+}
+
+/// An AF_XDP socket with its own UMEM.
+///
+/// Frames received by `recv` stay held by the caller until passed to
+/// `release` or `transmit`.
+#[pyclass(name = "Socket", module = "xsk_rs", unsendable)]
+#[derive(Debug)]
+pub struct Socket {
+    inner: Option<XskRsSocket>,
+    descs: Vec<XskRsDesc>,
+    // Cloned into every `Frame`, so the count above one is the number
+    // of them alive.
+    frames: Rc<()>,
+}
+
+impl Socket {
+    fn inner(&mut self) -> PyResult<&mut XskRsSocket> {
+        self.inner
+            .as_mut()
+            .ok_or_else(|| PyValueError::new_err("socket is closed"))
+    }
+}
+
+#[pymethods]
+impl Socket {
+    /// Create a UMEM of `frame_count` frames and bind a socket to it on
+    /// `queue_id` of interface `if_name`.
+    #[new]
+    #[pyo3(signature = (if_name, queue_id = 0, frame_count = 4096))]
+    fn new(if_name: &str, queue_id: u32, frame_count: u32) -> PyResult<Self> {
+        let if_name = Interface::try_from(if_name.as_bytes())
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+        let frame_count = NonZeroU32::new(frame_count)
+            .ok_or_else(|| PyValueError::new_err("frame_count must be non-zero"))?;
+
+        let inner = XskRsSocket::create(&if_name, queue_id, frame_count).map_err(build_error)?;
+
+        Ok(Self {
+            inner: Some(inner),
+            descs: Vec::new(),
+            frames: Rc::new(()),
+        })
+    }
+
+    /// The socket's file descriptor, for `select` and friends.
+    fn fileno(&mut self) -> PyResult<c_int> {
+        Ok(self.inner()?.fd())
+    }
+
+    /// Receive up to `max` frames, returning a list of `(addr, data)`
+    /// pairs where `data` is a writable memoryview over the frame's
+    /// packet data in the UMEM.
+    ///
+    /// A view must not be used once its frame is released or
+    /// transmitted, though it keeps the socket's memory mapped, and
+    /// `close` fails while any remain.
+    #[pyo3(signature = (max = 64))]
+    fn recv<'py>(
+        slf: &Bound<'py, Self>,
+        max: usize,
+    ) -> PyResult<Vec<(u64, Bound<'py, PyMemoryView>)>> {
+        let py = slf.py();
+
+        let (frames, live) = {
+            let mut this = slf.borrow_mut();
+            let this = &mut *this;
+
+            let sock = this
+                .inner
+                .as_mut()
+                .ok_or_else(|| PyValueError::new_err("socket is closed"))?;
+
+            this.descs.resize(max, XskRsDesc::default());
+            let cnt = sock.rx_burst(&mut this.descs);
+
+            let frames = this.descs[..cnt]
+                .iter()
+                .map(|desc| {
+                    let data = sock
+                        .frame_data(desc.addr)
+                        .expect("received frames are held by the caller");
+
+                    let len = (desc.len as usize).min(data.len());
+
+                    (desc.addr, data.as_mut_ptr(), len)
+                })
+                .collect::<Vec<_>>();
+
+            (frames, Rc::clone(&this.frames))
+        };
+
+        frames
+            .into_iter()
+            .map(|(addr, ptr, len)| {
+                let frame = Bound::new(
+                    py,
+                    Frame {
+                        _sock: slf.clone().unbind(),
+                        _live: Rc::clone(&live),
+                        ptr,
+                        len,
+                    },
+                )?;
+
+                Ok((addr, PyMemoryView::from_bound(frame.as_any())?))
+            })
+            .collect()
+    }
+
+    /// Hand received frames back, given their addresses, returning
+    /// the number accepted. Stops at the first frame not held by the
+    /// caller.
+    fn release(&mut self, addrs: Vec<u64>) -> PyResult<usize> {
+        let descs: Vec<_> = addrs
+            .into_iter()
+            .map(|addr| XskRsDesc {
+                addr,
+                ..XskRsDesc::default()
+            })
+            .collect();
+
+        Ok(self.inner()?.recycle(&descs))
+    }
+
+    /// Transmit received frames, given `(addr, len)` pairs, returning
+    /// the number submitted. Stops when the ring is full or at the
+    /// first frame not held by the caller.
+    fn transmit(&mut self, frames: Vec<(u64, u32)>) -> PyResult<usize> {
+        let descs: Vec<_> = frames
+            .into_iter()
+            .map(|(addr, len)| XskRsDesc {
+                addr,
+                len,
+                options: 0,
+            })
+            .collect();
+
+        Ok(self.inner()?.tx_burst(&descs))
+    }
+
+    /// Copy each packet into a free frame and transmit them, returning
+    /// the number submitted. May be less than `len(packets)` if free
+    /// frames or ring space run out. Packets longer than a frame are
+    /// truncated.
+    fn send(&mut self, packets: Vec<Vec<u8>>) -> PyResult<usize> {
+        let Self { inner, descs, .. } = self;
+        let sock = inner
+            .as_mut()
+            .ok_or_else(|| PyValueError::new_err("socket is closed"))?;
+
+        descs.resize(packets.len(), XskRsDesc::default());
+        let cnt = sock.alloc(descs);
+
+        for (desc, pkt) in descs[..cnt].iter_mut().zip(&packets) {
+            let data = sock
+                .frame_data(desc.addr)
+                .expect("allocated frames are held by the caller");
+
+            let len = pkt.len().min(data.len());
+            data[..len].copy_from_slice(&pkt[..len]);
+
+            desc.len = len as u32;
+        }
+
+        let sent = sock.tx_burst(&descs[..cnt]);
+
+        // Anything that didn't fit goes back to the pool.
+        sock.recycle(&descs[sent..cnt]);
+
+        Ok(sent)
+    }
+
+    /// Close the socket and free its UMEM. Raises `BufferError` while
+    /// any memoryviews from `recv` remain, as they'd be left pointing
+    /// at unmapped memory.
+    fn close(&mut self) -> PyResult<()> {
+        if Rc::strong_count(&self.frames) > 1 {
+            return Err(PyBufferError::new_err(
+                "memoryviews of received frames are still alive",
+            ));
+        }
+
+        self.inner = None;
+        Ok(())
+    }
+}
+
+/// Exports a frame's packet data through the buffer protocol, keeping
+/// the socket alive while any view of it is.
+#[pyclass(module = "xsk_rs", unsendable)]
+#[derive(Debug)]
+struct Frame {
+    _sock: Py<Socket>,
+    _live: Rc<()>,
+    ptr: *mut u8,
+    len: usize,
+}
+
+#[pymethods]
+impl Frame {
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        let (ptr, len) = {
+            let this = slf.borrow();
+            (this.ptr, this.len)
+        };
+
+        // SAFETY: `view` comes from the interpreter, and `ptr` points
+        // to `len` bytes of UMEM, which stays mapped while `slf` is
+        // alive: it keeps the socket alive, and `close` refuses to
+        // unmap it. The view holds a reference to `slf`.
+        let ret = unsafe {
+            ffi::PyBuffer_FillInfo(
+                view,
+                slf.as_ptr(),
+                ptr as *mut c_void,
+                len as ffi::Py_ssize_t,
+                0,
+                flags,
+            )
+        };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(PyErr::fetch(slf.py()))
+        }
+    }
+
+    unsafe fn __releasebuffer__(&self, _view: *mut ffi::Py_buffer) {}
+}
+
+/// The `xsk_rs` Python module.
+#[pymodule]
+fn xsk_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Socket>()?;
+    Ok(())
+}
@@ -1,6 +1,43 @@
-use std::ptr;
+use std::{
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
-use libxdp_sys::{xsk_ring_cons, xsk_ring_prod};
+use libxdp_sys::{xdp_desc, xsk_ring_cons, xsk_ring_prod};
+
+/// Whether a ring's size is read from the ring at runtime or fixed at
+/// compile time, which the ring accessors below are generic over.
+///
+/// Which is resolved when the accessors are monomorphised, so the
+/// [`Dynamic`] ones are the plain `libxdp` calls and the [`Fixed`]
+/// ones mask indices with a constant.
+pub trait RingSize {
+    /// The size of the ring, or `None` if it's only known at runtime.
+    const FIXED: Option<u32>;
+}
+
+/// Ring size read from the ring.
+#[derive(Debug)]
+pub struct Dynamic;
+
+impl RingSize for Dynamic {
+    const FIXED: Option<u32> = None;
+}
+
+/// Ring size of `N`, which must be a power of two.
+#[derive(Debug)]
+pub struct Fixed<const N: usize>;
+
+impl<const N: usize> RingSize for Fixed<N> {
+    const FIXED: Option<u32> = {
+        assert!(
+            N.is_power_of_two() && N <= 1 << 31,
+            "ring size must be a power of two no greater than 2^31"
+        );
+
+        Some(N as u32)
+    };
+}
 
 #[derive(Debug)]
 pub struct XskRingCons(xsk_ring_cons);
@@ -17,6 +54,37 @@ impl XskRingCons {
     pub fn is_ring_null(&self) -> bool {
         self.0.ring.is_null()
     }
+
+    /// Same as `xsk_ring_cons__peek`.
+    #[inline]
+    pub unsafe fn peek<S: RingSize>(&mut self, nb: u32, idx: &mut u32) -> u32 {
+        let nb = match S::FIXED {
+            Some(size) => nb.min(size),
+            None => nb,
+        };
+
+        unsafe { libxdp_sys::xsk_ring_cons__peek(&mut self.0, nb, idx) }
+    }
+
+    /// Same as `xsk_ring_cons__rx_desc`.
+    #[inline]
+    pub unsafe fn rx_desc<S: RingSize>(&self, idx: u32) -> *const xdp_desc {
+        match S::FIXED {
+            Some(size) => unsafe {
+                (self.0.ring as *const xdp_desc).add((idx & (size - 1)) as usize)
+            },
+            None => unsafe { libxdp_sys::xsk_ring_cons__rx_desc(&self.0, idx) },
+        }
+    }
+
+    /// Same as `xsk_ring_cons__comp_addr`.
+    #[inline]
+    pub unsafe fn comp_addr<S: RingSize>(&self, idx: u32) -> *const u64 {
+        match S::FIXED {
+            Some(size) => unsafe { (self.0.ring as *const u64).add((idx & (size - 1)) as usize) },
+            None => unsafe { libxdp_sys::xsk_ring_cons__comp_addr(&self.0, idx) },
+        }
+    }
 }
 
 impl Default for XskRingCons {
@@ -51,6 +119,54 @@ impl XskRingProd {
     pub fn is_ring_null(&self) -> bool {
         self.0.ring.is_null()
     }
+
+    /// Same as `xsk_ring_prod__reserve`.
+    #[inline]
+    pub unsafe fn reserve<S: RingSize>(&mut self, nb: u32, idx: &mut u32) -> u32 {
+        let size = match S::FIXED {
+            Some(size) => size,
+            None => return unsafe { libxdp_sys::xsk_ring_prod__reserve(&mut self.0, nb, idx) },
+        };
+
+        let r = &mut self.0;
+
+        if r.cached_cons.wrapping_sub(r.cached_prod) < nb {
+            // SAFETY: `consumer` points at the ring's consumer index,
+            // which the kernel updates concurrently.
+            let cons = unsafe { (*(r.consumer as *const AtomicU32)).load(Ordering::Acquire) };
+
+            r.cached_cons = cons.wrapping_add(size);
+
+            if r.cached_cons.wrapping_sub(r.cached_prod) < nb {
+                return 0;
+            }
+        }
+
+        *idx = r.cached_prod;
+        r.cached_prod = r.cached_prod.wrapping_add(nb);
+
+        nb
+    }
+
+    /// Same as `xsk_ring_prod__fill_addr`.
+    #[inline]
+    pub unsafe fn fill_addr<S: RingSize>(&mut self, idx: u32) -> *mut u64 {
+        match S::FIXED {
+            Some(size) => unsafe { (self.0.ring as *mut u64).add((idx & (size - 1)) as usize) },
+            None => unsafe { libxdp_sys::xsk_ring_prod__fill_addr(&mut self.0, idx) },
+        }
+    }
+
+    /// Same as `xsk_ring_prod__tx_desc`.
+    #[inline]
+    pub unsafe fn tx_desc<S: RingSize>(&mut self, idx: u32) -> *mut xdp_desc {
+        match S::FIXED {
+            Some(size) => unsafe {
+                (self.0.ring as *mut xdp_desc).add((idx & (size - 1)) as usize)
+            },
+            None => unsafe { libxdp_sys::xsk_ring_prod__tx_desc(&mut self.0, idx) },
+        }
+    }
 }
 
 impl Default for XskRingProd {
@@ -69,3 +185,105 @@ impl Default for XskRingProd {
 }
 
 unsafe impl Send for XskRingProd {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: u32 = 8;
+
+    /// A producer ring backed by `entries`, with the consumer at
+    /// `cons` and nothing yet produced.
+    fn prod_ring(entries: &mut [u64], prod: &mut u32, cons: &mut u32) -> XskRingProd {
+        let mut ring = XskRingProd::default();
+
+        ring.0.mask = SIZE - 1;
+        ring.0.size = SIZE;
+        ring.0.cached_prod = *prod;
+        ring.0.cached_cons = cons.wrapping_add(SIZE);
+        ring.0.producer = prod;
+        ring.0.consumer = cons;
+        ring.0.ring = entries.as_mut_ptr().cast();
+
+        ring
+    }
+
+    #[test]
+    fn fixed_size_reserve_matches_dynamic() {
+        let mut entries = [0u64; SIZE as usize];
+
+        for start in [0, 5, u32::MAX - 2] {
+            for nb in [1, 4, SIZE, SIZE + 1] {
+                let (mut prod, mut cons) = (start, start);
+                let mut dynamic = prod_ring(&mut entries, &mut prod, &mut cons);
+
+                let (mut prod, mut cons) = (start, start);
+                let mut fixed = prod_ring(&mut entries, &mut prod, &mut cons);
+
+                let (mut dyn_idx, mut fixed_idx) = (0, 0);
+
+                unsafe {
+                    assert_eq!(
+                        dynamic.reserve::<Dynamic>(nb, &mut dyn_idx),
+                        fixed.reserve::<Fixed<8>>(nb, &mut fixed_idx)
+                    );
+
+                    assert_eq!(dyn_idx, fixed_idx);
+
+                    assert_eq!(
+                        dynamic.fill_addr::<Dynamic>(dyn_idx),
+                        fixed.fill_addr::<Fixed<8>>(fixed_idx)
+                    );
+                }
+
+                assert_eq!(dynamic.0.cached_prod, fixed.0.cached_prod);
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_size_reserve_refreshes_the_consumer() {
+        let mut entries = [0u64; SIZE as usize];
+        let (mut prod, mut cons) = (0, 0);
+
+        let mut ring = prod_ring(&mut entries, &mut prod, &mut cons);
+        let mut idx = 0;
+
+        unsafe {
+            assert_eq!(ring.reserve::<Fixed<8>>(SIZE, &mut idx), SIZE);
+            assert_eq!(ring.reserve::<Fixed<8>>(1, &mut idx), 0);
+
+            // The kernel consumes a couple of entries.
+            *ring.0.consumer = 2;
+
+            assert_eq!(ring.reserve::<Fixed<8>>(2, &mut idx), 2);
+            assert_eq!(idx, SIZE);
+            assert_eq!(
+                ring.fill_addr::<Fixed<8>>(idx),
+                ring.fill_addr::<Fixed<8>>(0)
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_size_peek_is_capped_at_the_ring_size() {
+        let mut entries = [xdp_desc::default(); SIZE as usize];
+        let (mut prod, mut cons) = (u32::MAX - 1, u32::MAX - 1 - SIZE);
+
+        let mut ring = XskRingCons::default();
+
+        ring.0.mask = SIZE - 1;
+        ring.0.size = SIZE;
+        ring.0.cached_cons = cons;
+        ring.0.producer = &mut prod;
+        ring.0.consumer = &mut cons;
+        ring.0.ring = entries.as_mut_ptr().cast();
+
+        let mut idx = 0;
+
+        unsafe {
+            assert_eq!(ring.peek::<Fixed<8>>(64, &mut idx), SIZE);
+            assert_eq!(ring.rx_desc::<Fixed<8>>(idx), ring.rx_desc::<Dynamic>(idx));
+        }
+    }
+}
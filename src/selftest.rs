@@ -0,0 +1,655 @@
+//! Checking whether an interface and its driver are ready for AF_XDP,
+//! the first thing to do when a new NIC doesn't behave.
+//!
+//! [`run`] tries binding a socket in each [`BindMode`], sends a probe
+//! frame in the best mode that bound and checks that it completes,
+//! and, if the interface is one end of a veth pair, injects a probe
+//! on the other end and checks that it arrives. It also looks at the
+//! `RLIMIT_MEMLOCK` limit and whether it's running as root. The
+//! resulting [`Report`] prints as a readable summary:
+//!
+//! ```no_run
+//! use xsk_rs::selftest;
+//!
+//! let report = selftest::run(&"eth0".parse().unwrap());
+//!
+//! println!("{}", report);
+//!
+//! if !report.is_ready() {
+//!     std::process::exit(1);
+//! }
+//! ```
+//!
+//! Each bind takes over the queue with the default XDP program while
+//! it's held, so traffic to that queue doesn't reach the kernel stack
+//! for the duration. The `xsk-selftest` tool,
+//! built with the `tools` feature, wraps this.
+
+use std::{
+    convert::TryInto,
+    error::Error,
+    ffi::CString,
+    fmt, fs,
+    io::{self, Write},
+    mem,
+    num::NonZeroU32,
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use libxdp_sys::XSK_UMEM__DEFAULT_FRAME_SIZE;
+
+pub use crate::socket::BindMode;
+
+use crate::{
+    config::{Interface, UmemConfig},
+    health::LinkState,
+    xsk::Xsk,
+    CompQueue, FillQueue, FrameDesc, RxQueue, TxQueue, Umem,
+};
+
+/// The number of frames in each probe socket's UMEM.
+const FRAME_COUNT: u32 = 64;
+
+/// The size of each probe socket's UMEM, ignoring its rings.
+const UMEM_BYTES: u64 = FRAME_COUNT as u64 * XSK_UMEM__DEFAULT_FRAME_SIZE as u64;
+
+/// How long to wait for a probe to complete or arrive.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The IEEE's local experimental ethertype, so the probe isn't
+/// mistaken for real traffic.
+const PROBE_ETHERTYPE: u16 = 0x88b5;
+
+const PROBE_MARKER: &[u8] = b"xsk-rs selftest";
+
+/// The outcome of a single check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The check passed.
+    Passed,
+    /// The check failed, for the given reason.
+    Failed(String),
+    /// The check couldn't be carried out, for the given reason.
+    Skipped(String),
+}
+
+impl Outcome {
+    /// Whether the check passed.
+    pub fn is_passed(&self) -> bool {
+        matches!(self, Self::Passed)
+    }
+
+    /// Whether the check failed.
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Self::Failed(_))
+    }
+
+    fn from_result(result: Result<(), String>) -> Self {
+        match result {
+            Ok(()) => Self::Passed,
+            Err(reason) => Self::Failed(reason),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Passed => write!(f, "ok"),
+            Self::Failed(reason) => write!(f, "FAILED: {}", reason),
+            Self::Skipped(reason) => write!(f, "skipped: {}", reason),
+        }
+    }
+}
+
+/// The `RLIMIT_MEMLOCK` limits, `None` meaning unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Memlock {
+    soft: Option<u64>,
+    hard: Option<u64>,
+}
+
+impl Memlock {
+    /// The current process's limits.
+    pub fn current() -> io::Result<Self> {
+        let mut rlim: libc::rlimit = unsafe { mem::zeroed() };
+
+        if unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut rlim) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let limit = |l: libc::rlim_t| {
+            if l == libc::RLIM_INFINITY {
+                None
+            } else {
+                Some(l)
+            }
+        };
+
+        Ok(Self {
+            soft: limit(rlim.rlim_cur),
+            hard: limit(rlim.rlim_max),
+        })
+    }
+
+    /// The soft limit in bytes, which is the one enforced.
+    pub fn soft(&self) -> Option<u64> {
+        self.soft
+    }
+
+    /// The hard limit in bytes, up to which the soft limit may be
+    /// raised.
+    pub fn hard(&self) -> Option<u64> {
+        self.hard
+    }
+
+    /// Whether `bytes` of UMEM fit under the soft limit.
+    ///
+    /// Since Linux 5.11 UMEMs are charged to the memory cgroup rather
+    /// than this limit, so there a low limit doesn't matter.
+    pub fn allows(&self, bytes: u64) -> bool {
+        match self.soft {
+            Some(soft) => bytes <= soft,
+            None => true,
+        }
+    }
+}
+
+/// Whether binding in a particular [`BindMode`] worked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindCheck {
+    mode: BindMode,
+    outcome: Outcome,
+}
+
+impl BindCheck {
+    /// The mode tried.
+    pub fn mode(&self) -> BindMode {
+        self.mode
+    }
+
+    /// Whether it bound.
+    pub fn outcome(&self) -> &Outcome {
+        &self.outcome
+    }
+}
+
+/// The results of [`run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    if_name: String,
+    queue_id: u32,
+    root: bool,
+    memlock: Option<Memlock>,
+    binds: Vec<BindCheck>,
+    tx: Outcome,
+    rx: Outcome,
+}
+
+impl Report {
+    /// The most performant mode that bound, if any did.
+    pub fn best_mode(&self) -> Option<BindMode> {
+        self.binds
+            .iter()
+            .find(|check| check.outcome.is_passed())
+            .map(|check| check.mode)
+    }
+
+    /// Whether binding in each mode worked, most performant first.
+    pub fn binds(&self) -> &[BindCheck] {
+        &self.binds
+    }
+
+    /// Whether a probe sent in the [`best_mode`](Self::best_mode)
+    /// completed.
+    pub fn tx(&self) -> &Outcome {
+        &self.tx
+    }
+
+    /// Whether a probe injected from a veth peer arrived in the
+    /// [`best_mode`](Self::best_mode). Skipped for other interfaces.
+    pub fn rx(&self) -> &Outcome {
+        &self.rx
+    }
+
+    /// The `RLIMIT_MEMLOCK` limits, if they could be read.
+    pub fn memlock(&self) -> Option<&Memlock> {
+        self.memlock.as_ref()
+    }
+
+    /// Whether the checks were run as root.
+    pub fn is_root(&self) -> bool {
+        self.root
+    }
+
+    /// Whether some mode bound, and nothing sent or received in it
+    /// failed.
+    pub fn is_ready(&self) -> bool {
+        self.best_mode().is_some() && self.tx.is_passed() && !self.rx.is_failed()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "AF_XDP self-test for {} queue {}",
+            self.if_name, self.queue_id
+        )?;
+
+        if !self.root {
+            writeln!(
+                f,
+                "  note: not running as root, binds need CAP_NET_ADMIN, CAP_NET_RAW and CAP_BPF"
+            )?;
+        }
+
+        let fmt_limit = |l: Option<u64>| l.map_or("unlimited".into(), |l| format!("{} bytes", l));
+
+        match &self.memlock {
+            Some(memlock) => {
+                writeln!(
+                    f,
+                    "  memlock limit: {} (hard {})",
+                    fmt_limit(memlock.soft),
+                    fmt_limit(memlock.hard)
+                )?;
+
+                if !memlock.allows(UMEM_BYTES) {
+                    writeln!(
+                        f,
+                        "  note: memlock limit is below even the probe's {} byte UMEM, \
+                         raise it with `ulimit -l` on kernels before 5.11",
+                        UMEM_BYTES
+                    )?;
+                }
+            }
+            None => writeln!(f, "  memlock limit: unknown")?,
+        }
+
+        for check in &self.binds {
+            writeln!(f, "  bind ({}): {}", check.mode, check.outcome)?;
+        }
+
+        writeln!(f, "  tx probe: {}", self.tx)?;
+        writeln!(f, "  rx probe: {}", self.rx)?;
+
+        match self.best_mode() {
+            Some(mode) if self.is_ready() => write!(f, "ready, best mode {}", mode),
+            _ => write!(f, "NOT ready"),
+        }
+    }
+}
+
+/// Check queue 0 of `if_name`, see the [module docs](self).
+pub fn run(if_name: &Interface) -> Report {
+    run_on_queue(if_name, 0)
+}
+
+/// Check `queue_id` of `if_name`, see the [module docs](self).
+pub fn run_on_queue(if_name: &Interface, queue_id: u32) -> Report {
+    let name = if_name.as_cstr().to_string_lossy().into_owned();
+
+    let binds = BindMode::ALL
+        .iter()
+        .map(|&mode| BindCheck {
+            mode,
+            outcome: Outcome::from_result(bind(if_name, queue_id, mode).map(|_| ())),
+        })
+        .collect::<Vec<_>>();
+
+    let best_mode = binds
+        .iter()
+        .find(|check| check.outcome.is_passed())
+        .map(|check| check.mode);
+
+    let (tx, rx) = match best_mode {
+        Some(mode) => probe(if_name, &name, queue_id, mode),
+        None => {
+            let skipped = Outcome::Skipped("no mode bound".into());
+            (skipped.clone(), skipped)
+        }
+    };
+
+    Report {
+        if_name: name,
+        queue_id,
+        root: unsafe { libc::geteuid() } == 0,
+        memlock: Memlock::current().ok(),
+        binds,
+        tx,
+        rx,
+    }
+}
+
+fn bind(if_name: &Interface, queue_id: u32, mode: BindMode) -> Result<Xsk, String> {
+    Xsk::build(
+        if_name,
+        queue_id,
+        UmemConfig::default(),
+        mode.socket_config(),
+        NonZeroU32::new(FRAME_COUNT).unwrap(),
+    )
+    .map_err(|e| error_chain(&e))
+}
+
+/// An error and its sources, on one line.
+fn error_chain(err: &dyn Error) -> String {
+    let mut msg = err.to_string();
+    let mut source = err.source();
+
+    while let Some(err) = source {
+        msg.push_str(": ");
+        msg.push_str(&err.to_string());
+        source = err.source();
+    }
+
+    msg
+}
+
+fn probe(if_name: &Interface, name: &str, queue_id: u32, mode: BindMode) -> (Outcome, Outcome) {
+    let mut xsk = match bind(if_name, queue_id, mode) {
+        Ok(xsk) => xsk,
+        Err(e) => {
+            let failed = Outcome::Failed(format!("rebinding failed: {}", e));
+            return (failed.clone(), failed);
+        }
+    };
+
+    let (rx_descs, tx_descs) = xsk.descs.split_at_mut(FRAME_COUNT as usize / 2);
+
+    let tx = Outcome::from_result(probe_tx(&mut xsk.tx_q, &mut xsk.cq, &xsk.umem, tx_descs));
+
+    let rx = match veth_peer(name) {
+        Ok(Some(peer)) => Outcome::from_result(probe_rx(
+            &mut xsk.rx_q,
+            &mut xsk.fq,
+            &xsk.umem,
+            rx_descs,
+            &peer,
+        )),
+        Ok(None) => Outcome::Skipped(
+            "not a veth, send traffic to the queue and watch for it to confirm rx".into(),
+        ),
+        Err(e) => Outcome::Skipped(format!("couldn't look for a veth peer: {}", e)),
+    };
+
+    (tx, rx)
+}
+
+fn probe_frame(nonce: u32) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(64);
+
+    frame.extend_from_slice(&[0xff; 6]);
+    frame.extend_from_slice(&[0x02, 0, 0, 0, 0, 0x01]);
+    frame.extend_from_slice(&PROBE_ETHERTYPE.to_be_bytes());
+    frame.extend_from_slice(PROBE_MARKER);
+    frame.extend_from_slice(&nonce.to_be_bytes());
+
+    // Pad to the minimum Ethernet frame length, less the FCS.
+    frame.resize(60, 0);
+
+    frame
+}
+
+fn nonce() -> u32 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+
+    std::process::id() ^ nanos
+}
+
+fn probe_tx(
+    tx_q: &mut TxQueue,
+    cq: &mut CompQueue,
+    umem: &Umem,
+    descs: &mut [FrameDesc],
+) -> Result<(), String> {
+    let frame = probe_frame(nonce());
+
+    unsafe { umem.data_mut(&mut descs[0]) }
+        .cursor()
+        .write_all(&frame)
+        .map_err(|e| format!("writing probe failed: {}", e))?;
+
+    // SAFETY: the frame is ours and in `umem`.
+    let sent = unsafe { tx_q.produce_and_wakeup(&descs[..1]) }
+        .map_err(|e| format!("waking the kernel failed: {}", e))?;
+
+    if sent != 1 {
+        return Err("tx ring full".into());
+    }
+
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+
+    while Instant::now() < deadline {
+        // SAFETY: only frames of `umem` were sent.
+        if unsafe { cq.consume(&mut descs[1..2]) } == 1 {
+            return Ok(());
+        }
+
+        if tx_q.needs_wakeup() {
+            tx_q.wakeup()
+                .map_err(|e| format!("waking the kernel failed: {}", e))?;
+        }
+
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    Err(format!(
+        "probe not completed within {}ms, is the link up?",
+        PROBE_TIMEOUT.as_millis()
+    ))
+}
+
+fn probe_rx(
+    rx_q: &mut RxQueue,
+    fq: &mut FillQueue,
+    umem: &Umem,
+    descs: &mut [FrameDesc],
+    peer: &str,
+) -> Result<(), String> {
+    let peer_if: Interface = peer
+        .parse()
+        .map_err(|e| format!("bad peer name {}: {}", peer, e))?;
+
+    if LinkState::of(&peer_if).ok() != Some(LinkState::Up) {
+        return Err(format!("veth peer {} isn't up", peer));
+    }
+
+    // SAFETY: the frames are ours and in `umem`.
+    if unsafe { fq.produce(descs) } != descs.len() {
+        return Err("fill ring full".into());
+    }
+
+    let frame = probe_frame(nonce());
+
+    inject(peer, &frame).map_err(|e| format!("sending on veth peer {} failed: {}", peer, e))?;
+
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+
+    while Instant::now() < deadline {
+        // SAFETY: as above.
+        let cnt = unsafe { rx_q.poll_and_consume(descs, 10) }
+            .map_err(|e| format!("polling failed: {}", e))?;
+
+        // SAFETY: the frames were just consumed so are ours.
+        if descs[..cnt]
+            .iter()
+            .any(|desc| unsafe { umem.data(desc) }.contents() == &frame[..])
+        {
+            return Ok(());
+        }
+
+        // SAFETY: as above.
+        unsafe { fq.produce(&descs[..cnt]) };
+    }
+
+    Err(format!(
+        "probe from veth peer {} not received within {}ms",
+        peer,
+        PROBE_TIMEOUT.as_millis()
+    ))
+}
+
+fn read_index(name: &str, attr: &str) -> io::Result<u32> {
+    fs::read_to_string(format!("/sys/class/net/{}/{}", name, attr))?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// The other end of the veth pair `name` is one end of, if it is.
+///
+/// A veth's `iflink` is its peer's index, and the two point at each
+/// other, unlike e.g. a VLAN and the device it sits on.
+fn veth_peer(name: &str) -> io::Result<Option<String>> {
+    let ifindex = read_index(name, "ifindex")?;
+    let iflink = read_index(name, "iflink")?;
+
+    if ifindex == iflink {
+        return Ok(None);
+    }
+
+    let mut buf = [0; libc::IF_NAMESIZE];
+
+    if unsafe { libc::if_indextoname(iflink, buf.as_mut_ptr()) }.is_null() {
+        // The peer's in another namespace.
+        return Ok(None);
+    }
+
+    let peer = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned();
+
+    if read_index(&peer, "iflink")? == ifindex {
+        Ok(Some(peer))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Send `frame` out of `name` with a raw AF_PACKET socket.
+fn inject(name: &str, frame: &[u8]) -> io::Result<()> {
+    let c_name = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let ifindex = unsafe { libc::if_nametoindex(c_name.as_ptr()) };
+
+    if ifindex == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) };
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+    addr.sll_family = libc::AF_PACKET as u16;
+    addr.sll_ifindex = ifindex.try_into().unwrap();
+    addr.sll_halen = 6;
+    addr.sll_addr[..6].copy_from_slice(&frame[..6]);
+
+    let ret = unsafe {
+        libc::sendto(
+            fd,
+            frame.as_ptr() as *const libc::c_void,
+            frame.len(),
+            0,
+            &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+        )
+    };
+
+    let result = if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    };
+
+    unsafe { libc::close(fd) };
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(binds: &[Outcome], tx: Outcome, rx: Outcome) -> Report {
+        Report {
+            if_name: "veth0".into(),
+            queue_id: 0,
+            root: true,
+            memlock: None,
+            binds: BindMode::ALL
+                .iter()
+                .zip(binds)
+                .map(|(&mode, outcome)| BindCheck {
+                    mode,
+                    outcome: outcome.clone(),
+                })
+                .collect(),
+            tx,
+            rx,
+        }
+    }
+
+    #[test]
+    fn probe_frames_are_minimum_length_and_marked() {
+        let frame = probe_frame(7);
+
+        assert_eq!(frame.len(), 60);
+        assert_eq!(&frame[12..14], &PROBE_ETHERTYPE.to_be_bytes());
+        assert_eq!(&frame[14..14 + PROBE_MARKER.len()], PROBE_MARKER);
+        assert_ne!(probe_frame(8), frame);
+    }
+
+    #[test]
+    fn ready_needs_a_bind_and_a_completed_probe() {
+        let failed = || Outcome::Failed("nope".into());
+        let skipped = || Outcome::Skipped("n/a".into());
+
+        let r = report(
+            &[failed(), Outcome::Passed, Outcome::Passed],
+            Outcome::Passed,
+            skipped(),
+        );
+        assert_eq!(r.best_mode(), Some(BindMode::DriverCopy));
+        assert!(r.is_ready());
+        assert!(r.to_string().ends_with("ready, best mode driver copy"));
+
+        let r = report(
+            &[failed(), failed(), Outcome::Passed],
+            Outcome::Passed,
+            failed(),
+        );
+        assert!(!r.is_ready());
+
+        let r = report(&[failed(), failed(), failed()], skipped(), skipped());
+        assert_eq!(r.best_mode(), None);
+        assert!(!r.is_ready());
+        assert!(r.to_string().ends_with("NOT ready"));
+    }
+
+    #[test]
+    fn memlock_limits_are_checked_against_the_soft_limit() {
+        let memlock = Memlock {
+            soft: Some(64 * 1024),
+            hard: None,
+        };
+
+        assert!(memlock.allows(64 * 1024));
+        assert!(!memlock.allows(64 * 1024 + 1));
+
+        let unlimited = Memlock {
+            soft: None,
+            hard: None,
+        };
+
+        assert!(unlimited.allows(u64::MAX));
+    }
+}
@@ -0,0 +1,75 @@
+//! The modes a socket can be bound in, and finding out which one it
+//! got.
+
+use std::{fmt, io};
+
+use crate::config::{BindFlags, SocketConfig, XdpFlags};
+
+use super::{xdp_prog, Fd};
+
+/// A way of binding an AF_XDP socket, from most to least performant.
+///
+/// What a bound socket actually ended up with is reported by
+/// [`RxQueue::bind_mode`](crate::RxQueue::bind_mode) and
+/// [`TxQueue::bind_mode`](crate::TxQueue::bind_mode), since without
+/// [`XDP_ZEROCOPY`](BindFlags::XDP_ZEROCOPY) or
+/// [`XDP_COPY`](BindFlags::XDP_COPY) the kernel quietly picks
+/// whichever the driver supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindMode {
+    /// Native XDP with the driver's zero-copy support.
+    ZeroCopy,
+    /// Native XDP, copying frames between the driver and the UMEM.
+    DriverCopy,
+    /// Generic XDP, which works with any driver but is slowest.
+    Skb,
+}
+
+impl BindMode {
+    /// Every mode, most performant first.
+    pub const ALL: [BindMode; 3] = [Self::ZeroCopy, Self::DriverCopy, Self::Skb];
+
+    /// A config binding in this mode.
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub(crate) fn socket_config(&self) -> SocketConfig {
+        let (xdp_flags, bind_flags) = match self {
+            Self::ZeroCopy => (XdpFlags::XDP_FLAGS_DRV_MODE, BindFlags::XDP_ZEROCOPY),
+            Self::DriverCopy => (XdpFlags::XDP_FLAGS_DRV_MODE, BindFlags::XDP_COPY),
+            Self::Skb => (XdpFlags::XDP_FLAGS_SKB_MODE, BindFlags::XDP_COPY),
+        };
+
+        SocketConfig::builder()
+            .xdp_flags(xdp_flags)
+            .bind_flags(bind_flags)
+            .build()
+    }
+}
+
+impl fmt::Display for BindMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::ZeroCopy => "zero-copy",
+            Self::DriverCopy => "driver copy",
+            Self::Skb => "skb",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// The mode of a socket bound on `ifindex` with `xdp_flags`.
+pub(super) fn query(fd: &Fd, ifindex: u32, xdp_flags: XdpFlags) -> io::Result<BindMode> {
+    if fd.is_zero_copy()? {
+        return Ok(BindMode::ZeroCopy);
+    }
+
+    // Copy mode is either native or generic XDP, depending on how the
+    // program feeding the socket is attached.
+    if xdp_flags.contains(XdpFlags::XDP_FLAGS_SKB_MODE)
+        || xdp_prog::query_prog_id(ifindex, XdpFlags::XDP_FLAGS_SKB_MODE)? != 0
+    {
+        Ok(BindMode::Skb)
+    } else {
+        Ok(BindMode::DriverCopy)
+    }
+}
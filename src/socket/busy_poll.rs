@@ -0,0 +1,124 @@
+//! Busy polling configuration and NAPI context checks.
+
+use std::{error::Error, fmt, io, num::NonZeroU32};
+
+/// Socket options for busy polling, i.e. having the kernel run the
+/// driver's NAPI poll loop in the context of the calling thread on
+/// receive or send, rather than waiting on interrupts.
+///
+/// Applied with [`Fd::set_busy_poll`](super::Fd::set_busy_poll), or
+/// on creation with
+/// [`SocketConfigBuilder::busy_poll`](crate::config::SocketConfigBuilder::busy_poll), see
+/// `SO_BUSY_POLL` in `socket(7)` for more details. Use
+/// [`Fd::check_napi_id`](super::Fd::check_napi_id) to confirm it's
+/// polling the context traffic actually arrives on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusyPoll {
+    timeout_us: u32,
+    budget: Option<u16>,
+    prefer: bool,
+}
+
+impl BusyPoll {
+    /// Busy poll for up to `timeout_us` microseconds when there's
+    /// nothing to receive, using the kernel's default budget and
+    /// without preferring busy polling over interrupts.
+    pub fn new(timeout_us: u32) -> Self {
+        Self {
+            timeout_us,
+            budget: None,
+            prefer: false,
+        }
+    }
+
+    /// Set the maximum number of packets processed per busy poll.
+    pub fn budget(&mut self, budget: u16) -> &mut Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Set whether busy polling should be preferred, suppressing
+    /// interrupts while the application keeps polling. Requires the
+    /// interface's `napi_defer_hard_irqs` and `gro_flush_timeout` to
+    /// be set to have an effect.
+    pub fn prefer(&mut self, prefer: bool) -> &mut Self {
+        self.prefer = prefer;
+        self
+    }
+
+    /// The busy poll timeout, in microseconds.
+    pub fn timeout_us(&self) -> u32 {
+        self.timeout_us
+    }
+
+    /// The busy poll budget, if set.
+    pub fn get_budget(&self) -> Option<u16> {
+        self.budget
+    }
+
+    /// Whether busy polling is preferred.
+    pub fn is_preferred(&self) -> bool {
+        self.prefer
+    }
+}
+
+/// Error returned by [`Fd::check_napi_id`](super::Fd::check_napi_id).
+#[derive(Debug)]
+pub enum NapiIdError {
+    /// Nothing has been received on the socket yet, so the kernel
+    /// hasn't recorded a NAPI context for it. Try again once traffic
+    /// is flowing.
+    NoTraffic,
+    /// Traffic is arriving on a different NAPI context to the one
+    /// expected, e.g. because flow steering is sending it to another
+    /// queue. Busy polling the socket won't drive the right context.
+    Mismatch {
+        /// The NAPI id expected.
+        expected: NonZeroU32,
+        /// The NAPI id the socket's traffic is arriving on.
+        actual: NonZeroU32,
+    },
+    /// Querying the socket failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for NapiIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoTraffic => write!(f, "no traffic received yet, napi id unknown"),
+            Self::Mismatch { expected, actual } => write!(
+                f,
+                "traffic arriving on napi id {}, expected {}",
+                actual, expected
+            ),
+            Self::Io(_) => write!(f, "failed to retrieve napi id"),
+        }
+    }
+}
+
+impl Error for NapiIdError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mismatch_reports_both_ids() {
+        let err = NapiIdError::Mismatch {
+            expected: NonZeroU32::new(8193).unwrap(),
+            actual: NonZeroU32::new(8195).unwrap(),
+        };
+
+        assert_eq!(
+            err.to_string(),
+            "traffic arriving on napi id 8195, expected 8193"
+        );
+    }
+}
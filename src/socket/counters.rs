@@ -0,0 +1,58 @@
+use crate::umem::frame::DescOptions;
+
+/// Packet and byte counts for one direction of a [`Socket`], as seen
+/// by user space.
+///
+/// These count what passed through the ring and complement the
+/// kernel's drop counters in [`XdpStatistics`]. Packets split across
+/// several descriptors are only counted once, on their last
+/// descriptor, while bytes are summed over all of them.
+///
+/// [`Socket`]: crate::Socket
+/// [`XdpStatistics`]: super::XdpStatistics
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct QueueCounters {
+    packets: u64,
+    bytes: u64,
+}
+
+impl QueueCounters {
+    #[inline]
+    pub(super) fn add(&mut self, len: usize, options: u32) {
+        self.bytes += len as u64;
+
+        if options & DescOptions::XDP_PKT_CONTD.bits() == 0 {
+            self.packets += 1;
+        }
+    }
+
+    /// The number of packets.
+    #[inline]
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// The number of bytes, not including any frame headroom.
+    #[inline]
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn continued_descriptors_count_bytes_but_not_packets() {
+        let mut counters = QueueCounters::default();
+
+        counters.add(100, 0);
+        counters.add(4096, DescOptions::XDP_PKT_CONTD.bits());
+        counters.add(50, 0);
+
+        assert_eq!(counters.packets(), 2);
+        assert_eq!(counters.bytes(), 4246);
+    }
+}
@@ -0,0 +1,193 @@
+use std::{iter::FusedIterator, mem::ManuallyDrop, time::Duration};
+
+use crate::umem::{frame::FrameDesc, Recycler, Umem};
+
+use super::RxQueue;
+
+/// Iterator over received frames, created by [`RxQueue::drain`].
+///
+/// Frames are read off the ring as the iterator advances. Those
+/// yielded are released back to the kernel when the iterator is
+/// dropped, and any not yet yielded are left on the ring for the next
+/// read.
+///
+/// For workloads which decide what to do with most frames from their
+/// headers alone, [`prefetch`](Self::prefetch) has the start of each
+/// frame pulled into cache a step ahead of it being yielded, and
+/// [`RxFrame::header`] then reads just those bytes before the frame
+/// is either [kept](RxFrame::keep) or dropped to be recycled.
+#[derive(Debug)]
+pub struct Drain<'a> {
+    rx_q: &'a mut RxQueue,
+    recycler: &'a Recycler,
+    prefetch: Option<&'a Umem>,
+    idx: u32,
+    taken: u32,
+    remaining: u32,
+}
+
+impl<'a> Drain<'a> {
+    pub(super) fn new(rx_q: &'a mut RxQueue, recycler: &'a Recycler, nb: u32) -> Self {
+        let (idx, remaining) = rx_q.peek(nb);
+
+        Self {
+            rx_q,
+            recycler,
+            prefetch: None,
+            idx,
+            taken: 0,
+            remaining,
+        }
+    }
+
+    /// Prefetch the start of each frame's packet data from `umem` one
+    /// frame ahead of yielding it, see [`Umem::prefetch`]. The first
+    /// frame is prefetched straight away.
+    pub fn prefetch(mut self, umem: &'a Umem) -> Self {
+        if self.remaining > 0 {
+            umem.prefetch(&self.rx_q.peek_desc(self.idx));
+        }
+
+        self.prefetch = Some(umem);
+        self
+    }
+}
+
+impl<'a> Iterator for Drain<'a> {
+    type Item = RxFrame<'a>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let desc = self.rx_q.read_desc(self.idx);
+
+        self.idx = self.idx.wrapping_add(1);
+        self.taken += 1;
+        self.remaining -= 1;
+
+        if let (Some(umem), true) = (self.prefetch, self.remaining > 0) {
+            umem.prefetch(&self.rx_q.peek_desc(self.idx));
+        }
+
+        Some(RxFrame {
+            desc: ManuallyDrop::new(desc),
+            recycler: self.recycler,
+        })
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining as usize, Some(self.remaining as usize))
+    }
+}
+
+impl ExactSizeIterator for Drain<'_> {}
+
+impl FusedIterator for Drain<'_> {}
+
+impl Drop for Drain<'_> {
+    fn drop(&mut self) {
+        self.rx_q.release(self.taken, self.remaining);
+    }
+}
+
+/// A received frame, yielded by [`Drain`].
+///
+/// When dropped the frame is scheduled on the [`Recycler`] passed to
+/// [`RxQueue::drain`], to go back on the fill queue. Use
+/// [`keep`](Self::keep) to take ownership of it instead, say to pass
+/// it on to a [`TxQueue`](crate::TxQueue).
+#[derive(Debug)]
+pub struct RxFrame<'a> {
+    desc: ManuallyDrop<FrameDesc>,
+    recycler: &'a Recycler,
+}
+
+impl RxFrame<'_> {
+    /// The frame's descriptor.
+    #[inline]
+    pub fn desc(&self) -> &FrameDesc {
+        &self.desc
+    }
+
+    /// A mutable reference to the frame's descriptor, e.g. to pass to
+    /// [`Umem::data_mut`](crate::Umem::data_mut).
+    #[inline]
+    pub fn desc_mut(&mut self) -> &mut FrameDesc {
+        &mut self.desc
+    }
+
+    /// The first `len` bytes of the frame's packet data, or all of it
+    /// if shorter. Enough to decide whether the frame is worth
+    /// [keeping](Self::keep) without touching the rest of it.
+    ///
+    /// # Safety
+    ///
+    /// See [`Umem::data`]. `umem` must be the [`Umem`] the frame was
+    /// received into.
+    #[inline]
+    pub unsafe fn header<'u>(&self, umem: &'u Umem, len: usize) -> &'u [u8] {
+        // SAFETY: guaranteed by this function's contract.
+        let data = unsafe { umem.data(&self.desc) }.contents();
+
+        &data[..len.min(data.len())]
+    }
+
+    /// Take the frame's descriptor, so it's not recycled on drop.
+    #[inline]
+    pub fn keep(self) -> FrameDesc {
+        let mut this = ManuallyDrop::new(self);
+
+        // SAFETY: `this` is never dropped, so `desc` isn't touched
+        // again.
+        unsafe { ManuallyDrop::take(&mut this.desc) }
+    }
+}
+
+impl Drop for RxFrame<'_> {
+    fn drop(&mut self) {
+        // SAFETY: `desc` isn't used again after this.
+        self.recycler
+            .push(unsafe { ManuallyDrop::take(&mut self.desc) });
+    }
+}
+
+/// What a call to [`RxQueue::drain_for`] got through.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DrainStats {
+    pub(super) batches: usize,
+    pub(super) frames: usize,
+    pub(super) elapsed: Duration,
+    pub(super) emptied: bool,
+}
+
+impl DrainStats {
+    /// The number of non-empty batches handed to the callback.
+    #[inline]
+    pub fn batches(&self) -> usize {
+        self.batches
+    }
+
+    /// The total number of frames consumed.
+    #[inline]
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// How long the call took, including time spent in the callback.
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Whether draining stopped because the ring ran dry, as opposed
+    /// to the time slice running out. If `false` there are most likely
+    /// still frames waiting.
+    #[inline]
+    pub fn emptied(&self) -> bool {
+        self.emptied
+    }
+}
@@ -0,0 +1,360 @@
+use libc::{EINTR, POLLIN};
+use std::{io, num::NonZeroUsize, os::unix::io::AsRawFd};
+
+use crate::{umem::frame::FrameDesc, util};
+
+use super::RxQueue;
+
+/// A received frame descriptor and the [`FanIn`] source it came from.
+#[derive(Debug, Clone, Copy)]
+pub struct FanInDesc {
+    source: usize,
+    desc: FrameDesc,
+}
+
+impl FanInDesc {
+    /// The index of the source queue, as returned by [`FanIn::add`].
+    #[inline]
+    pub fn source(&self) -> usize {
+        self.source
+    }
+
+    /// The received frame.
+    #[inline]
+    pub fn desc(&self) -> &FrameDesc {
+        &self.desc
+    }
+}
+
+#[derive(Debug)]
+struct Source {
+    rx_q: RxQueue,
+    received: u64,
+}
+
+/// Shares out each batch between the sources by weight.
+#[derive(Debug, Default)]
+struct Scheduler {
+    weights: Vec<usize>,
+    total_weight: usize,
+    filled: Vec<bool>,
+    start: usize,
+}
+
+impl Scheduler {
+    fn add(&mut self, weight: usize) {
+        self.weights.push(weight);
+        self.filled.push(false);
+        self.total_weight += weight;
+    }
+
+    /// Take up to `batch_size` frames with `take(source, nb)`, which
+    /// returns how many of the `nb` asked for it got.
+    fn run<F>(&mut self, batch_size: usize, mut take: F)
+    where
+        F: FnMut(usize, usize) -> usize,
+    {
+        let n = self.weights.len();
+
+        if n == 0 {
+            return;
+        }
+
+        let mut budget = batch_size;
+
+        // First pass, everyone gets up to their share of the batch.
+        for k in 0..n {
+            let i = (self.start + k) % n;
+
+            let share = (batch_size * self.weights[i] / self.total_weight).max(1);
+            let quota = share.min(budget);
+
+            let cnt = if quota > 0 { take(i, quota) } else { 0 };
+
+            self.filled[i] = quota > 0 && cnt == quota;
+            budget -= cnt;
+        }
+
+        // Second pass, what's left goes to those with more waiting.
+        for k in 0..n {
+            if budget == 0 {
+                break;
+            }
+
+            let i = (self.start + k) % n;
+
+            if self.filled[i] {
+                budget -= take(i, budget);
+            }
+        }
+
+        // Rotate who goes first, so rounding in the shares and the
+        // leftovers are spread evenly over time.
+        self.start = (self.start + 1) % n;
+    }
+}
+
+/// Merges several [`RxQueue`]s into one stream of batches, for a
+/// single worker serving many queues.
+///
+/// Each call to [`consume`](Self::consume) takes up to the batch size
+/// in frames across all the queues, every frame tagged with the index
+/// of the queue it came from. The batch is shared out by weight: a
+/// queue with weight 2 may take twice as many frames per batch as one
+/// with weight 1, so a flood on one queue can't crowd the others out.
+/// Whatever the quieter queues leave unused goes to the busier ones,
+/// so no frames are left waiting while there's room.
+///
+/// ```no_run
+/// # use std::num::NonZeroUsize;
+/// # use xsk_rs::{socket::FanIn, FillQueue, RxQueue};
+/// # unsafe fn run(rx_qs: Vec<RxQueue>, fqs: &mut [FillQueue]) {
+/// let mut fan_in = FanIn::new(NonZeroUsize::new(64).unwrap());
+///
+/// for rx_q in rx_qs {
+///     fan_in.add(rx_q, NonZeroUsize::new(1).unwrap());
+/// }
+///
+/// loop {
+///     for frame in unsafe { fan_in.poll_and_consume(100) }.unwrap() {
+///         // ... process the frame, then hand it back ...
+///         unsafe { fqs[frame.source()].produce_one(frame.desc()) };
+///     }
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FanIn {
+    sources: Vec<Source>,
+    pollfds: Vec<libc::pollfd>,
+    sched: Scheduler,
+    scratch: Vec<FrameDesc>,
+    batch: Vec<FanInDesc>,
+}
+
+impl FanIn {
+    /// An empty fan-in handing out batches of at most `batch_size`
+    /// frames.
+    pub fn new(batch_size: NonZeroUsize) -> Self {
+        Self {
+            sources: Vec::new(),
+            pollfds: Vec::new(),
+            sched: Scheduler::default(),
+            scratch: vec![FrameDesc::default(); batch_size.get()],
+            batch: Vec::with_capacity(batch_size.get()),
+        }
+    }
+
+    /// Add `rx_q` as a source with the given fairness `weight`,
+    /// returning the index its frames are tagged with.
+    pub fn add(&mut self, rx_q: RxQueue, weight: NonZeroUsize) -> usize {
+        self.pollfds.push(libc::pollfd {
+            fd: rx_q.fd().as_raw_fd(),
+            events: POLLIN,
+            revents: 0,
+        });
+
+        self.sources.push(Source { rx_q, received: 0 });
+        self.sched.add(weight.get());
+
+        self.sources.len() - 1
+    }
+
+    /// Take the next batch of frames from the sources, see the
+    /// [type docs](Self).
+    ///
+    /// The batch is only valid until the next call, so frames should
+    /// be dealt with or their descriptors copied out before then.
+    ///
+    /// # Safety
+    ///
+    /// See [`RxQueue::consume`]. Each frame's descriptor must only be
+    /// used with the [`Umem`](crate::Umem) of its source queue.
+    pub unsafe fn consume(&mut self) -> &[FanInDesc] {
+        self.batch.clear();
+
+        let Self {
+            sources,
+            sched,
+            scratch,
+            batch,
+            ..
+        } = self;
+
+        sched.run(scratch.len(), |i, nb| {
+            let source = &mut sources[i];
+            let descs = &mut scratch[..nb];
+
+            // SAFETY: guaranteed by this function's contract.
+            let cnt = unsafe { source.rx_q.consume(descs) };
+
+            source.received += cnt as u64;
+
+            batch.extend(
+                descs[..cnt]
+                    .iter()
+                    .map(|&desc| FanInDesc { source: i, desc }),
+            );
+
+            cnt
+        });
+
+        &self.batch
+    }
+
+    /// Same as [`consume`](Self::consume), but if nothing's waiting
+    /// first wait up to `poll_timeout` milliseconds for any of the
+    /// sources to receive something.
+    ///
+    /// # Safety
+    ///
+    /// See [`consume`](Self::consume).
+    pub unsafe fn poll_and_consume(&mut self, poll_timeout: i32) -> io::Result<&[FanInDesc]> {
+        // SAFETY: guaranteed by this function's contract.
+        if unsafe { !self.consume().is_empty() } {
+            return Ok(&self.batch);
+        }
+
+        if self.poll(poll_timeout)? {
+            // SAFETY: as above.
+            unsafe { self.consume() };
+        }
+
+        Ok(&self.batch)
+    }
+
+    /// Wait up to `poll_timeout` milliseconds for any of the sources to
+    /// have frames to read, returning whether one does.
+    pub fn poll(&mut self, poll_timeout: i32) -> io::Result<bool> {
+        if self.pollfds.is_empty() {
+            return Ok(false);
+        }
+
+        let ret = unsafe {
+            libc::poll(
+                self.pollfds.as_mut_ptr(),
+                self.pollfds.len() as libc::nfds_t,
+                poll_timeout,
+            )
+        };
+
+        if ret < 0 {
+            if util::get_errno() != EINTR {
+                return Err(io::Error::last_os_error());
+            } else {
+                return Ok(false);
+            }
+        }
+
+        Ok(ret > 0)
+    }
+
+    /// The number of sources.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Whether there are no sources.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// The most frames handed out per batch.
+    #[inline]
+    pub fn batch_size(&self) -> usize {
+        self.scratch.len()
+    }
+
+    /// The total number of frames taken from source `i` so far.
+    ///
+    /// # Panics
+    ///
+    /// If `i` is out of range.
+    #[inline]
+    pub fn received(&self, i: usize) -> u64 {
+        self.sources[i].received
+    }
+
+    /// Source `i`'s queue, e.g. to kick it or read its counters.
+    ///
+    /// # Panics
+    ///
+    /// If `i` is out of range.
+    #[inline]
+    pub fn rx_queue_mut(&mut self, i: usize) -> &mut RxQueue {
+        &mut self.sources[i].rx_q
+    }
+
+    /// Take the queues back, in the order they were added.
+    pub fn into_queues(self) -> Vec<RxQueue> {
+        self.sources.into_iter().map(|s| s.rx_q).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run one batch against sources with `pending` frames waiting,
+    /// returning how many each gave.
+    fn run(sched: &mut Scheduler, batch_size: usize, pending: &mut [usize]) -> Vec<usize> {
+        let mut taken = vec![0; pending.len()];
+
+        sched.run(batch_size, |i, nb| {
+            let cnt = nb.min(pending[i]);
+            pending[i] -= cnt;
+            taken[i] += cnt;
+            cnt
+        });
+
+        taken
+    }
+
+    #[test]
+    fn busy_sources_share_the_batch_by_weight() {
+        let mut sched = Scheduler::default();
+        sched.add(1);
+        sched.add(3);
+
+        let mut pending = [100, 100];
+
+        assert_eq!(run(&mut sched, 64, &mut pending), [16, 48]);
+        assert_eq!(run(&mut sched, 64, &mut pending), [16, 48]);
+    }
+
+    #[test]
+    fn leftovers_go_to_sources_with_more_waiting() {
+        let mut sched = Scheduler::default();
+        sched.add(1);
+        sched.add(1);
+        sched.add(1);
+
+        let mut pending = [2, 100, 0];
+
+        let taken = run(&mut sched, 30, &mut pending);
+
+        assert_eq!(taken, [2, 28, 0]);
+    }
+
+    #[test]
+    fn every_source_gets_a_turn_when_the_batch_is_smaller_than_the_sources() {
+        let mut sched = Scheduler::default();
+
+        for _ in 0..4 {
+            sched.add(1);
+        }
+
+        let mut pending = [10; 4];
+        let mut total = [0; 4];
+
+        for _ in 0..4 {
+            for (t, n) in total.iter_mut().zip(run(&mut sched, 2, &mut pending)) {
+                *t += n;
+            }
+        }
+
+        assert_eq!(total, [2; 4]);
+    }
+}
@@ -1,18 +1,39 @@
 //! File descriptor utilities.
 
-use libc::{EINTR, POLLIN, POLLOUT, SOL_XDP};
-use libxdp_sys::{xdp_statistics, XDP_STATISTICS};
+use libc::{c_int, EINTR, POLLIN, POLLOUT, SOL_SOCKET, SOL_XDP};
+use libxdp_sys::{
+    xdp_desc, xdp_options, xdp_statistics, XDP_MMAP_OFFSETS, XDP_OPTIONS, XDP_OPTIONS_ZEROCOPY,
+    XDP_STATISTICS,
+};
 use std::{
-    fmt,
-    io::{self, ErrorKind},
-    mem,
+    fmt, io, mem,
+    num::NonZeroU32,
     os::unix::prelude::{AsRawFd, RawFd},
 };
 
 use crate::util;
 
+use super::{BusyPoll, NapiIdError};
+
 const XDP_STATISTICS_SIZEOF: u32 = mem::size_of::<xdp_statistics>() as u32;
 
+/// Size of `struct xdp_statistics` before the ring full and ring empty
+/// counters were added in linux 5.9, i.e. just its first three fields.
+const XDP_STATISTICS_V1_SIZEOF: u32 = 3 * mem::size_of::<u64>() as u32;
+
+// Busy polling socket options, not all of which are exported by
+// `libc`. These are the asm-generic values, which x86_64 and aarch64
+// both use.
+const SO_BUSY_POLL: c_int = 46;
+const SO_INCOMING_NAPI_ID: c_int = 56;
+const SO_PREFER_BUSY_POLL: c_int = 69;
+const SO_BUSY_POLL_BUDGET: c_int = 70;
+
+/// Number of `u64`s in each ring's entry of `struct xdp_mmap_offsets`,
+/// before and after the `flags` field was added in linux 5.4.
+const RING_OFFSET_FIELDS_V1: usize = 3;
+const RING_OFFSET_FIELDS: usize = 4;
+
 #[derive(Clone, Copy)]
 struct PollFd(libc::pollfd);
 
@@ -95,7 +116,7 @@ impl Fd {
                 self.as_raw_fd(),
                 SOL_XDP,
                 XDP_STATISTICS as i32,
-                &mut stats.0 as *mut _ as *mut libc::c_void,
+                &mut stats.raw as *mut _ as *mut libc::c_void,
                 &mut optlen,
             )
         };
@@ -104,14 +125,159 @@ impl Fd {
             return Err(io::Error::last_os_error());
         }
 
-        if optlen == XDP_STATISTICS_SIZEOF {
-            Ok(stats)
-        } else {
-            Err(io::Error::new(
-                ErrorKind::Other,
-                "`optlen` returned from `getsockopt` does not match `xdp_statistics` struct size",
-            ))
+        stats.set_layout(optlen).map(|()| stats).ok_or_else(|| {
+            io::Error::other(
+                "`optlen` returned from `getsockopt` does not match any known `xdp_statistics` layout",
+            )
+        })
+    }
+
+    /// Whether the socket is bound in zero-copy mode, as reported by
+    /// the `XDP_OPTIONS` socket option.
+    pub fn is_zero_copy(&self) -> io::Result<bool> {
+        let mut opts = xdp_options::default();
+        let mut optlen = mem::size_of::<xdp_options>() as u32;
+
+        let err = unsafe {
+            libc::getsockopt(
+                self.as_raw_fd(),
+                SOL_XDP,
+                XDP_OPTIONS as i32,
+                &mut opts as *mut _ as *mut libc::c_void,
+                &mut optlen,
+            )
+        };
+
+        if err != 0 {
+            return Err(io::Error::last_os_error());
         }
+
+        Ok(opts.flags & XDP_OPTIONS_ZEROCOPY != 0)
+    }
+
+    /// Enable busy polling on the socket as per `busy_poll`.
+    ///
+    /// Raising the timeout above the `net.core.busy_read` sysctl
+    /// requires `CAP_NET_ADMIN`. Preferring busy polling or setting a
+    /// budget needs Linux 5.11 or later, otherwise only the timeout
+    /// is set and kernels as old as 4.18 are fine.
+    pub fn set_busy_poll(&self, busy_poll: &BusyPoll) -> io::Result<()> {
+        if busy_poll.is_preferred() {
+            self.set_opt(SO_PREFER_BUSY_POLL, 1)?;
+        } else if let Err(e) = self.set_opt(SO_PREFER_BUSY_POLL, 0) {
+            // Kernels without the option can't have it set to clear.
+            if !matches!(e.raw_os_error(), Some(libc::ENOPROTOOPT | libc::EINVAL)) {
+                return Err(e);
+            }
+        }
+
+        self.set_opt(SO_BUSY_POLL, busy_poll.timeout_us() as c_int)?;
+
+        if let Some(budget) = busy_poll.get_budget() {
+            self.set_opt(SO_BUSY_POLL_BUDGET, c_int::from(budget))?;
+        }
+
+        Ok(())
+    }
+
+    /// Turn busy polling off again after setting it with
+    /// [`set_busy_poll`](Self::set_busy_poll) as per `busy_poll`,
+    /// leaving alone options it didn't set.
+    pub(crate) fn clear_busy_poll(&self, busy_poll: &BusyPoll) -> io::Result<()> {
+        if busy_poll.is_preferred() {
+            self.set_opt(SO_PREFER_BUSY_POLL, 0)?;
+        }
+
+        self.set_opt(SO_BUSY_POLL, 0)
+    }
+
+    /// The id of the NAPI context the socket's most recently received
+    /// traffic arrived on, or [`None`] if nothing has been received
+    /// yet.
+    ///
+    /// Busy polling only drives the NAPI context recorded here, so if
+    /// flow steering sends traffic to a different queue than the one
+    /// the socket is bound to, busy polling quietly achieves nothing.
+    pub fn napi_id(&self) -> io::Result<Option<NonZeroU32>> {
+        let mut napi_id: u32 = 0;
+        let mut optlen = mem::size_of::<u32>() as u32;
+
+        let err = unsafe {
+            libc::getsockopt(
+                self.as_raw_fd(),
+                SOL_SOCKET,
+                SO_INCOMING_NAPI_ID,
+                &mut napi_id as *mut _ as *mut libc::c_void,
+                &mut optlen,
+            )
+        };
+
+        if err != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(NonZeroU32::new(napi_id))
+    }
+
+    /// Check that the socket's traffic is arriving on the NAPI context
+    /// `expected`, returning it if so.
+    ///
+    /// `expected` would typically be taken from
+    /// [`napi_id`](Self::napi_id) once traffic known to be on the right
+    /// queue has been seen, or from the kernel's netdev netlink family.
+    pub fn check_napi_id(&self, expected: NonZeroU32) -> Result<NonZeroU32, NapiIdError> {
+        match self.napi_id() {
+            Ok(Some(actual)) if actual == expected => Ok(actual),
+            Ok(Some(actual)) => Err(NapiIdError::Mismatch { expected, actual }),
+            Ok(None) => Err(NapiIdError::NoTraffic),
+            Err(e) => Err(NapiIdError::Io(e)),
+        }
+    }
+
+    fn set_opt(&self, opt: c_int, val: c_int) -> io::Result<()> {
+        let err = unsafe {
+            libc::setsockopt(
+                self.as_raw_fd(),
+                SOL_SOCKET,
+                opt,
+                &val as *const _ as *const libc::c_void,
+                mem::size_of::<c_int>() as u32,
+            )
+        };
+
+        if err != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the offsets of each ring's fields within its mmap'd
+    /// region, as reported by the kernel.
+    pub fn mmap_offsets(&self) -> io::Result<MmapOffsets> {
+        let mut raw = [0u64; 4 * RING_OFFSET_FIELDS];
+
+        let mut optlen = mem::size_of_val(&raw) as u32;
+
+        let err = unsafe {
+            libc::getsockopt(
+                self.as_raw_fd(),
+                SOL_XDP,
+                XDP_MMAP_OFFSETS as i32,
+                raw.as_mut_ptr() as *mut libc::c_void,
+                &mut optlen,
+            )
+        };
+
+        if err != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        MmapOffsets::from_raw(&raw, optlen as usize).ok_or_else(|| {
+            io::Error::other(
+                "`optlen` returned from `getsockopt` does not match any known `xdp_mmap_offsets` layout",
+            )
+        })
     }
 }
 
@@ -140,56 +306,335 @@ impl AsRawFd for Fd {
 /// AF_XDP [`Socket`](crate::Socket) statistics.
 ///
 /// Can be retrieved by calling [`xdp_statistics`](Fd::xdp_statistics).
+///
+/// Kernels older than 5.9 only report the first three counters, in
+/// which case the accessors for the rest return [`None`].
 #[derive(Debug, Clone, Copy)]
-pub struct XdpStatistics(xdp_statistics);
+pub struct XdpStatistics {
+    raw: xdp_statistics,
+    extended: bool,
+}
 
 impl Default for XdpStatistics {
     fn default() -> Self {
-        Self(xdp_statistics {
-            rx_dropped: 0,
-            rx_invalid_descs: 0,
-            tx_invalid_descs: 0,
-            rx_ring_full: 0,
-            rx_fill_ring_empty_descs: 0,
-            tx_ring_empty_descs: 0,
-        })
+        Self {
+            raw: xdp_statistics {
+                rx_dropped: 0,
+                rx_invalid_descs: 0,
+                tx_invalid_descs: 0,
+                rx_ring_full: 0,
+                rx_fill_ring_empty_descs: 0,
+                tx_ring_empty_descs: 0,
+            },
+            extended: true,
+        }
     }
 }
 
 impl XdpStatistics {
+    /// Record which layout the kernel filled in, given the `optlen` it
+    /// returned. Returns `None` if it matches neither.
+    fn set_layout(&mut self, optlen: u32) -> Option<()> {
+        self.extended = if optlen == XDP_STATISTICS_SIZEOF {
+            true
+        } else if optlen == XDP_STATISTICS_V1_SIZEOF {
+            false
+        } else {
+            return None;
+        };
+
+        Some(())
+    }
+
+    #[inline]
+    fn extended(&self, value: u64) -> Option<u64> {
+        if self.extended {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
     /// Received packets dropped due to an invalid descriptor.
     #[inline]
     pub fn rx_invalid_descs(&self) -> u64 {
-        self.0.rx_invalid_descs
+        self.raw.rx_invalid_descs
     }
 
-    /// Received packets dropped due to rx ring being full.
+    /// Received packets dropped due to rx ring being full. [`None`] if
+    /// the kernel doesn't report it.
     #[inline]
-    pub fn rx_ring_full(&self) -> u64 {
-        self.0.rx_ring_full
+    pub fn rx_ring_full(&self) -> Option<u64> {
+        self.extended(self.raw.rx_ring_full)
     }
 
     /// Received packets dropped for other reasons.
     #[inline]
     pub fn rx_dropped(&self) -> u64 {
-        self.0.rx_dropped
+        self.raw.rx_dropped
     }
 
     /// Packets to be sent but dropped due to an invalid desccriptor.
     #[inline]
     pub fn tx_invalid_descs(&self) -> u64 {
-        self.0.tx_invalid_descs
+        self.raw.tx_invalid_descs
+    }
+
+    /// Items failed to be retrieved from fill ring. [`None`] if the
+    /// kernel doesn't report it.
+    #[inline]
+    pub fn rx_fill_ring_empty_descs(&self) -> Option<u64> {
+        self.extended(self.raw.rx_fill_ring_empty_descs)
+    }
+
+    /// Items failed to be retrieved from tx ring. [`None`] if the
+    /// kernel doesn't report it.
+    #[inline]
+    pub fn tx_ring_empty_descs(&self) -> Option<u64> {
+        self.extended(self.raw.tx_ring_empty_descs)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for XdpStatistics {
+    /// As a struct of the accessors, with counters the kernel doesn't
+    /// report as `None`.
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut s = serializer.serialize_struct("XdpStatistics", 6)?;
+
+        s.serialize_field("rx_dropped", &self.rx_dropped())?;
+        s.serialize_field("rx_invalid_descs", &self.rx_invalid_descs())?;
+        s.serialize_field("tx_invalid_descs", &self.tx_invalid_descs())?;
+        s.serialize_field("rx_ring_full", &self.rx_ring_full())?;
+        s.serialize_field("rx_fill_ring_empty_descs", &self.rx_fill_ring_empty_descs())?;
+        s.serialize_field("tx_ring_empty_descs", &self.tx_ring_empty_descs())?;
+
+        s.end()
+    }
+}
+
+/// Offsets of a ring's fields from the start of its mmap'd region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingOffsets {
+    producer: u64,
+    consumer: u64,
+    desc: u64,
+    flags: Option<u64>,
+}
+
+impl RingOffsets {
+    /// Offset of the producer index.
+    #[inline]
+    pub fn producer(&self) -> u64 {
+        self.producer
+    }
+
+    /// Offset of the consumer index.
+    #[inline]
+    pub fn consumer(&self) -> u64 {
+        self.consumer
+    }
+
+    /// Offset of the first descriptor.
+    #[inline]
+    pub fn desc(&self) -> u64 {
+        self.desc
+    }
+
+    /// Offset of the flags word, which holds
+    /// [`XDP_RING_NEED_WAKEUP`](libxdp_sys::XDP_RING_NEED_WAKEUP).
+    ///
+    /// [`None`] on kernels older than 5.4, which don't report it.
+    #[inline]
+    pub fn flags(&self) -> Option<u64> {
+        self.flags
+    }
+}
+
+/// The mmap offsets of all four rings, as returned by
+/// [`Fd::mmap_offsets`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmapOffsets {
+    rx: RingOffsets,
+    tx: RingOffsets,
+    fill: RingOffsets,
+    comp: RingOffsets,
+}
+
+impl MmapOffsets {
+    fn from_raw(raw: &[u64], optlen: usize) -> Option<Self> {
+        let ring_len = |fields: usize| 4 * fields * mem::size_of::<u64>();
+
+        let fields = if optlen == ring_len(RING_OFFSET_FIELDS) {
+            RING_OFFSET_FIELDS
+        } else if optlen == ring_len(RING_OFFSET_FIELDS_V1) {
+            RING_OFFSET_FIELDS_V1
+        } else {
+            return None;
+        };
+
+        let ring = |i: usize| {
+            let r = &raw[i * fields..(i + 1) * fields];
+
+            RingOffsets {
+                producer: r[0],
+                consumer: r[1],
+                desc: r[2],
+                flags: r.get(3).copied(),
+            }
+        };
+
+        Some(Self {
+            rx: ring(0),
+            tx: ring(1),
+            fill: ring(2),
+            comp: ring(3),
+        })
+    }
+
+    /// Offsets for the rx ring.
+    #[inline]
+    pub fn rx(&self) -> &RingOffsets {
+        &self.rx
+    }
+
+    /// Offsets for the tx ring.
+    #[inline]
+    pub fn tx(&self) -> &RingOffsets {
+        &self.tx
     }
 
-    /// Items failed to be retrieved from fill ring.
+    /// Offsets for the fill ring.
     #[inline]
-    pub fn rx_fill_ring_empty_descs(&self) -> u64 {
-        self.0.rx_fill_ring_empty_descs
+    pub fn fill(&self) -> &RingOffsets {
+        &self.fill
     }
 
-    /// Items failed to be retrieved from tx ring.
+    /// Offsets for the completion ring.
     #[inline]
-    pub fn tx_ring_empty_descs(&self) -> u64 {
-        self.0.tx_ring_empty_descs
+    pub fn comp(&self) -> &RingOffsets {
+        &self.comp
+    }
+}
+
+/// Where a ring lives and how big it is, for diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingGeometry {
+    offsets: RingOffsets,
+    pgoff: u64,
+    entries: u32,
+    desc_size: usize,
+}
+
+impl RingGeometry {
+    pub(super) fn rx(offsets: &MmapOffsets, entries: u32) -> Self {
+        Self {
+            offsets: offsets.rx,
+            pgoff: libxdp_sys::XDP_PGOFF_RX_RING as u64,
+            entries,
+            desc_size: mem::size_of::<xdp_desc>(),
+        }
+    }
+
+    pub(super) fn tx(offsets: &MmapOffsets, entries: u32) -> Self {
+        Self {
+            offsets: offsets.tx,
+            pgoff: libxdp_sys::XDP_PGOFF_TX_RING as u64,
+            entries,
+            desc_size: mem::size_of::<xdp_desc>(),
+        }
+    }
+
+    /// The field offsets within the ring's region.
+    #[inline]
+    pub fn offsets(&self) -> &RingOffsets {
+        &self.offsets
+    }
+
+    /// The page offset passed to `mmap` to map the ring.
+    #[inline]
+    pub fn pgoff(&self) -> u64 {
+        self.pgoff
+    }
+
+    /// The number of descriptors the ring holds.
+    #[inline]
+    pub fn entries(&self) -> u32 {
+        self.entries
+    }
+
+    /// The size of a single descriptor in bytes.
+    #[inline]
+    pub fn desc_size(&self) -> usize {
+        self.desc_size
+    }
+
+    /// The length of the ring's mmap'd region.
+    #[inline]
+    pub fn mmap_len(&self) -> u64 {
+        self.offsets.desc + self.entries as u64 * self.desc_size as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mmap_offsets_with_flags_are_parsed() {
+        let raw: Vec<u64> = (0..16).collect();
+
+        let offsets = MmapOffsets::from_raw(&raw, 128).unwrap();
+
+        assert_eq!(offsets.rx().producer(), 0);
+        assert_eq!(offsets.rx().flags(), Some(3));
+        assert_eq!(offsets.comp().desc(), 14);
+        assert_eq!(offsets.comp().flags(), Some(15));
+    }
+
+    #[test]
+    fn mmap_offsets_without_flags_are_parsed() {
+        let raw: Vec<u64> = (0..16).collect();
+
+        let offsets = MmapOffsets::from_raw(&raw, 96).unwrap();
+
+        assert_eq!(offsets.tx().producer(), 3);
+        assert_eq!(offsets.tx().flags(), None);
+        assert_eq!(offsets.comp().desc(), 11);
+    }
+
+    #[test]
+    fn unknown_mmap_offsets_layout_is_rejected() {
+        assert!(MmapOffsets::from_raw(&[0; 16], 100).is_none());
+    }
+
+    #[test]
+    fn short_xdp_statistics_hide_newer_counters() {
+        let mut stats = XdpStatistics::default();
+        stats.raw.rx_dropped = 1;
+        stats.raw.rx_ring_full = 2;
+
+        stats.set_layout(XDP_STATISTICS_SIZEOF).unwrap();
+        assert_eq!(stats.rx_ring_full(), Some(2));
+        assert_eq!(stats.tx_ring_empty_descs(), Some(0));
+
+        stats.set_layout(24).unwrap();
+        assert_eq!(stats.rx_dropped(), 1);
+        assert_eq!(stats.rx_ring_full(), None);
+        assert_eq!(stats.rx_fill_ring_empty_descs(), None);
+
+        assert!(stats.set_layout(32).is_none());
+    }
+
+    #[test]
+    fn ring_mmap_len_covers_all_descs() {
+        let raw: Vec<u64> = (0..16).map(|i| i * 64).collect();
+        let offsets = MmapOffsets::from_raw(&raw, 128).unwrap();
+
+        let geometry = RingGeometry::rx(&offsets, 4);
+
+        assert_eq!(geometry.mmap_len(), 128 + 4 * 16);
     }
 }
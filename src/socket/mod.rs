@@ -1,25 +1,65 @@
 //! Types for creating and using an AF_XDP [`Socket`].
 
+mod bind_mode;
+pub use bind_mode::BindMode;
+
+mod busy_poll;
+pub use busy_poll::{BusyPoll, NapiIdError};
+
+mod counters;
+pub use counters::QueueCounters;
+
 mod fd;
-pub use fd::{Fd, XdpStatistics};
+pub use fd::{Fd, MmapOffsets, RingGeometry, RingOffsets, XdpStatistics};
 
 mod rx_queue;
 pub use rx_queue::RxQueue;
 
+mod drain;
+pub use drain::{Drain, DrainStats, RxFrame};
+
+mod fan_in;
+pub use fan_in::{FanIn, FanInDesc};
+
 mod tx_queue;
 pub use tx_queue::TxQueue;
 
+mod size_check;
+
+mod shutdown;
+pub use shutdown::SocketShutdown;
+
+mod shared_tx_queue;
+pub use shared_tx_queue::{SharedTxQueue, TxCommitter};
+
+mod priority_tx_queue;
+pub use priority_tx_queue::{PriorityTxQueue, DEFAULT_STARVATION_LIMIT};
+
+mod poll_set;
+pub use poll_set::{Interest, PollSet, Readiness};
+
+mod wake;
+pub(crate) use wake::WeakWakeFd;
+pub use wake::{PollOutcome, WakeFd};
+
+mod xdp_prog;
+use xdp_prog::XdpProgState;
+pub use xdp_prog::{RebindError, XdpProgEvent, XdpProgWatcher};
+
 use libxdp_sys::xsk_socket;
 use std::{
     borrow::Borrow,
     error::Error,
     fmt, io,
+    os::unix::prelude::RawFd,
     ptr::{self, NonNull},
     sync::{Arc, Mutex},
 };
 
 use crate::{
-    config::{Interface, SocketConfig},
+    check::Bounds,
+    config::{BindFlags, Interface, SocketConfig},
+    packet::{ETH_HLEN, VLAN_HLEN},
     ring::{XskRingCons, XskRingProd},
     umem::{CompQueue, FillQueue, Umem},
 };
@@ -53,22 +93,58 @@ impl Drop for XskSocket {
 
 unsafe impl Send for XskSocket {}
 
+/// A socket's place among those bound to its [`Umem`]'s queues,
+/// given up on drop.
+#[derive(Debug)]
+struct QueueBinding {
+    umem: Umem,
+    ifindex: u32,
+    queue_id: u32,
+}
+
+impl Drop for QueueBinding {
+    fn drop(&mut self) {
+        self.umem.unbind_queue(self.ifindex, self.queue_id);
+    }
+}
+
 #[derive(Debug)]
 struct SocketInner {
-    // `ptr` must appear before `umem` to ensure correct drop order.
-    _ptr: XskSocket,
-    _umem: Umem,
+    // `ptr` must appear before `_binding`, which holds the UMEM, to
+    // ensure correct drop order.
+    ptr: XskSocket,
+    _binding: QueueBinding,
+    xdp_prog: XdpProgState,
 }
 
 impl SocketInner {
-    fn new(ptr: XskSocket, umem: Umem) -> Self {
+    fn new(ptr: XskSocket, binding: QueueBinding, xdp_prog: XdpProgState) -> Self {
         Self {
-            _ptr: ptr,
-            _umem: umem,
+            ptr,
+            _binding: binding,
+            xdp_prog,
         }
     }
 }
 
+/// Which of a socket's rings to create.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rings {
+    Both,
+    RxOnly,
+    TxOnly,
+}
+
+impl Rings {
+    fn rx(self) -> bool {
+        self != Self::TxOnly
+    }
+
+    fn tx(self) -> bool {
+        self != Self::RxOnly
+    }
+}
+
 /// An AF_XDP socket.
 ///
 /// More details can be found in the
@@ -76,7 +152,8 @@ impl SocketInner {
 #[derive(Debug)]
 pub struct Socket {
     fd: Fd,
-    _inner: Arc<Mutex<SocketInner>>,
+    inner: Arc<Mutex<SocketInner>>,
+    umem_id: usize,
 }
 
 impl Socket {
@@ -103,6 +180,8 @@ impl Socket {
     ///
     /// For further details on using a shared [`Umem`] please see the
     /// [docs](https://www.kernel.org/doc/html/latest/networking/af_xdp.html#xdp-shared-umem-bind-flag).
+    /// To have the [`FillQueue`] and [`CompQueue`] guaranteed, use
+    /// [`new_shared`](Self::new_shared).
     ///
     /// # Safety
     ///
@@ -121,32 +200,196 @@ impl Socket {
         if_name: &Interface,
         queue_id: u32,
     ) -> Result<(TxQueue, RxQueue, Option<(FillQueue, CompQueue)>), SocketCreateError> {
+        // SAFETY: guaranteed by this function's contract.
+        let (tx_q, rx_q, fq_and_cq) =
+            unsafe { Self::create(config, umem, if_name, queue_id, Rings::Both, false) }?;
+
+        Ok((
+            tx_q.expect("tx ring requested"),
+            rx_q.expect("rx ring requested"),
+            fq_and_cq,
+        ))
+    }
+
+    /// Same as [`new`](Self::new) but the socket always comes with
+    /// its own [`FillQueue`] and [`CompQueue`], for adding a socket to
+    /// a shared [`Umem`] on an interface queue none of its sockets are
+    /// bound to yet.
+    ///
+    /// The kernel requires each such socket to have its own fill and
+    /// comp queues, and handing over one socket's to another fails
+    /// with `EINVAL`, or on older kernels just receives on the wrong
+    /// queue. Here the only queues there are to use came from the same
+    /// call that created the socket.
+    ///
+    /// Since a socket's queue can't already be bound to, this needn't
+    /// be `unsafe` like [`new`](Self::new).
+    ///
+    /// # Errors
+    ///
+    /// As [`new`](Self::new), and with [`EBUSY`] if a socket using
+    /// `umem` is already bound to `(if_name, queue_id)`. Further
+    /// sockets on that queue share its fill and comp queues, so must
+    /// be created with [`new`](Self::new).
+    ///
+    /// [`EBUSY`]: libc::EBUSY
+    #[allow(clippy::type_complexity)]
+    pub fn new_shared(
+        config: SocketConfig,
+        umem: &Umem,
+        if_name: &Interface,
+        queue_id: u32,
+    ) -> Result<(TxQueue, RxQueue, FillQueue, CompQueue), SocketCreateError> {
+        // SAFETY: the queue isn't bound to yet, otherwise creation
+        // fails before any socket is made.
+        let (tx_q, rx_q, fq_and_cq) =
+            unsafe { Self::create(config, umem, if_name, queue_id, Rings::Both, true) }?;
+
+        let (fq, cq) = fq_and_cq.ok_or_else(|| SocketCreateError {
+            reason: "no fill queue or comp queue returned for unbound interface queue",
+            err: io::Error::from_raw_os_error(libc::EINVAL),
+        })?;
+
+        Ok((
+            tx_q.expect("tx ring requested"),
+            rx_q.expect("rx ring requested"),
+            fq,
+            cq,
+        ))
+    }
+
+    /// Same as [`new`](Self::new) but without a tx ring, for capture
+    /// only applications. The config's tx queue size is ignored.
+    ///
+    /// The [`CompQueue`] is still returned when a [`FillQueue`] is,
+    /// since the kernel creates both along with the [`Umem`], but
+    /// nothing will ever complete on it.
+    ///
+    /// # Safety
+    ///
+    /// See [`new`](Self::new).
+    #[allow(clippy::type_complexity)]
+    pub unsafe fn new_rx_only(
+        config: SocketConfig,
+        umem: &Umem,
+        if_name: &Interface,
+        queue_id: u32,
+    ) -> Result<(RxQueue, Option<(FillQueue, CompQueue)>), SocketCreateError> {
+        // SAFETY: guaranteed by this function's contract.
+        let (_, rx_q, fq_and_cq) =
+            unsafe { Self::create(config, umem, if_name, queue_id, Rings::RxOnly, false) }?;
+
+        Ok((rx_q.expect("rx ring requested"), fq_and_cq))
+    }
+
+    /// Same as [`new`](Self::new) but without an rx ring, for
+    /// generator only applications. The config's rx queue size is
+    /// ignored.
+    ///
+    /// Since nothing can be received, libxdp doesn't load its default
+    /// XDP program, so the interface's traffic is left alone. The
+    /// [`FillQueue`] is still returned when a [`CompQueue`] is, since
+    /// the kernel creates both along with the [`Umem`], but needn't
+    /// be given any frames.
+    ///
+    /// # Safety
+    ///
+    /// See [`new`](Self::new).
+    #[allow(clippy::type_complexity)]
+    pub unsafe fn new_tx_only(
+        config: SocketConfig,
+        umem: &Umem,
+        if_name: &Interface,
+        queue_id: u32,
+    ) -> Result<(TxQueue, Option<(FillQueue, CompQueue)>), SocketCreateError> {
+        // SAFETY: guaranteed by this function's contract.
+        let (tx_q, _, fq_and_cq) =
+            unsafe { Self::create(config, umem, if_name, queue_id, Rings::TxOnly, false) }?;
+
+        Ok((tx_q.expect("tx ring requested"), fq_and_cq))
+    }
+
+    /// Create a socket with the rings asked for in `rings`, which are
+    /// then guaranteed to be [`Some`]. If `unbound_only`, fails
+    /// without creating anything if the queue is already bound to.
+    #[allow(clippy::type_complexity)]
+    unsafe fn create(
+        config: SocketConfig,
+        umem: &Umem,
+        if_name: &Interface,
+        queue_id: u32,
+        rings: Rings,
+        unbound_only: bool,
+    ) -> Result<
+        (
+            Option<TxQueue>,
+            Option<RxQueue>,
+            Option<(FillQueue, CompQueue)>,
+        ),
+        SocketCreateError,
+    > {
+        let ifindex = unsafe { libc::if_nametoindex(if_name.as_cstr().as_ptr()) };
+
+        if ifindex == 0 {
+            return Err(SocketCreateError {
+                reason: "failed to retrieve interface index",
+                err: io::Error::last_os_error(),
+            });
+        }
+
         let mut socket_ptr = ptr::null_mut();
         let mut tx_q = XskRingProd::default();
         let mut rx_q = XskRingCons::default();
 
-        let (err, fq, cq) = unsafe {
-            umem.with_ptr_and_saved_queues(|xsk_umem, saved_fq_and_cq| {
+        let created = unsafe {
+            umem.with_ptr_and_saved_queues(|xsk_umem, saved_fq_and_cq, bound_queues| {
+                let key = (ifindex, queue_id);
+
+                if unbound_only && bound_queues.contains_key(&key) {
+                    return None;
+                }
+
                 let (mut fq, mut cq) = saved_fq_and_cq
                     .take()
                     .unwrap_or_else(|| (Box::default(), Box::default()));
 
+                let rx_ptr = if rings.rx() {
+                    rx_q.as_mut() as *mut _
+                } else {
+                    ptr::null_mut()
+                };
+
+                let tx_ptr = if rings.tx() {
+                    tx_q.as_mut() as *mut _
+                } else {
+                    ptr::null_mut()
+                };
+
                 let err = libxdp_sys::xsk_socket__create_shared(
                     &mut socket_ptr,
                     if_name.as_cstr().as_ptr(),
                     queue_id,
                     xsk_umem,
-                    rx_q.as_mut(),
-                    tx_q.as_mut(),
+                    rx_ptr,
+                    tx_ptr,
                     fq.as_mut().as_mut(), // double deref due to Box
                     cq.as_mut().as_mut(),
                     &config.into(),
                 );
 
-                (err, fq, cq)
+                if err == 0 {
+                    *bound_queues.entry(key).or_insert(0) += 1;
+                }
+
+                Some((err, fq, cq))
             })
         };
 
+        let (err, fq, cq) = created.ok_or_else(|| SocketCreateError {
+            reason: "interface queue already bound to by a socket using this UMEM",
+            err: io::Error::from_raw_os_error(libc::EBUSY),
+        })?;
+
         if err != 0 {
             return Err(SocketCreateError {
                 reason: "non-zero error code returned when creating AF_XDP socket",
@@ -154,6 +397,14 @@ impl Socket {
             });
         }
 
+        // Declared before the socket so it's dropped after it on early
+        // returns.
+        let binding = QueueBinding {
+            umem: umem.clone(),
+            ifindex,
+            queue_id,
+        };
+
         let socket_ptr = match NonNull::new(socket_ptr) {
             Some(init_xsk) => {
                 // SAFETY: this is the only `XskSocket` instance for
@@ -178,27 +429,62 @@ impl Socket {
             });
         }
 
+        let xdp_prog = XdpProgState::new(ifindex, *config.xdp_flags());
+
         let socket = Socket {
             fd: Fd::new(fd),
-            _inner: Arc::new(Mutex::new(SocketInner::new(socket_ptr, umem.clone()))),
+            inner: Arc::new(Mutex::new(SocketInner::new(socket_ptr, binding, xdp_prog))),
+            umem_id: umem.id(),
         };
 
-        let tx_q = if tx_q.is_ring_null() {
+        if let Some(busy_poll) = config.busy_poll() {
+            socket
+                .fd
+                .set_busy_poll(&busy_poll)
+                .map_err(|err| SocketCreateError {
+                    reason: "failed to set busy poll socket options",
+                    err,
+                })?;
+        }
+
+        let tx_q = if !rings.tx() {
+            None
+        } else if tx_q.is_ring_null() {
             return Err(SocketCreateError {
                 reason: "returned tx queue ring is null",
                 err: io::Error::from_raw_os_error(-err),
             });
         } else {
-            TxQueue::new(tx_q, socket.clone())
+            let max_frame_len = if_name
+                .mtu()
+                .ok()
+                .map(|mtu| mtu as usize + ETH_HLEN + VLAN_HLEN);
+
+            Some(TxQueue::new(
+                tx_q,
+                socket.clone(),
+                max_frame_len,
+                config.bind_flags().contains(BindFlags::XDP_USE_NEED_WAKEUP),
+                config.max_burst(),
+                Bounds::of(umem),
+            ))
         };
 
-        let rx_q = if rx_q.is_ring_null() {
+        let rx_q = if !rings.rx() {
+            None
+        } else if rx_q.is_ring_null() {
             return Err(SocketCreateError {
                 reason: "returned rx queue ring is null",
                 err: io::Error::from_raw_os_error(-err),
             });
         } else {
-            RxQueue::new(rx_q, socket)
+            Some(RxQueue::new(
+                rx_q,
+                socket,
+                config.unknown_desc_options(),
+                umem.frame_mtu(),
+                config.max_burst(),
+            ))
         };
 
         let fq_and_cq = match (fq.is_ring_null(), cq.is_ring_null()) {
@@ -219,13 +505,78 @@ impl Socket {
 
         Ok((tx_q, rx_q, fq_and_cq))
     }
+
+    fn bind_mode(&self) -> io::Result<BindMode> {
+        let state = self.xdp_prog_state();
+        bind_mode::query(&self.fd, state.ifindex, state.xdp_flags)
+    }
+
+    fn xdp_prog_state(&self) -> XdpProgState {
+        self.inner.lock().unwrap().xdp_prog
+    }
+
+    fn set_xdp_prog_id(&self, prog_id: u32) {
+        self.inner.lock().unwrap().xdp_prog.prog_id = prog_id;
+    }
+
+    fn update_xsk_map(&self, map_fd: RawFd) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let err = unsafe { libxdp_sys::xsk_socket__update_xskmap(inner.ptr.0.as_mut(), map_fd) };
+
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(-err));
+        }
+
+        Ok(())
+    }
+
+    /// Make sure the default XDP program is attached to the socket's
+    /// interface and that this socket is in its XSKMAP, returning the
+    /// id of the attached program.
+    fn rebind(&self) -> Result<u32, RebindError> {
+        let mut inner = self.inner.lock().unwrap();
+        let ifindex = inner.xdp_prog.ifindex;
+
+        let mut xsks_map_fd = -1;
+
+        let err = unsafe { libxdp_sys::xsk_setup_xdp_prog(ifindex as i32, &mut xsks_map_fd) };
+
+        if err != 0 {
+            return Err(RebindError::new(
+                "failed to set up XDP program on interface",
+                io::Error::from_raw_os_error(-err),
+            ));
+        }
+
+        let err =
+            unsafe { libxdp_sys::xsk_socket__update_xskmap(inner.ptr.0.as_mut(), xsks_map_fd) };
+
+        // The program holds its own reference to the map.
+        unsafe { libc::close(xsks_map_fd) };
+
+        if err != 0 {
+            return Err(RebindError::new(
+                "failed to insert socket into XSKMAP",
+                io::Error::from_raw_os_error(-err),
+            ));
+        }
+
+        let prog_id = xdp_prog::query_prog_id(ifindex, inner.xdp_prog.xdp_flags)
+            .map_err(|e| RebindError::new("failed to query attached XDP program", e))?;
+
+        inner.xdp_prog.prog_id = prog_id;
+
+        Ok(prog_id)
+    }
 }
 
 impl Clone for Socket {
     fn clone(&self) -> Self {
         Self {
             fd: self.fd.clone(),
-            _inner: self._inner.clone(),
+            inner: self.inner.clone(),
+            umem_id: self.umem_id,
         }
     }
 }
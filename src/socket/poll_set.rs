@@ -0,0 +1,316 @@
+use libc::{
+    EINTR, EPOLLERR, EPOLLHUP, EPOLLIN, EPOLLOUT, EPOLL_CLOEXEC, EPOLL_CTL_ADD, EPOLL_CTL_DEL,
+};
+use std::{
+    io,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+};
+
+use crate::util;
+
+/// Which readiness a [`PollSet`] waits for on an fd.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interest {
+    /// Frames to read, e.g. on an [`RxQueue`](crate::RxQueue).
+    Readable,
+    /// Room to write, e.g. on a [`TxQueue`](crate::TxQueue).
+    Writable,
+    /// Either.
+    Both,
+}
+
+impl Interest {
+    fn events(self) -> u32 {
+        match self {
+            Self::Readable => EPOLLIN as u32,
+            Self::Writable => EPOLLOUT as u32,
+            Self::Both => (EPOLLIN | EPOLLOUT) as u32,
+        }
+    }
+}
+
+/// Which way an fd returned by [`PollSet::poll_all`] is ready.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Readiness {
+    readable: bool,
+    writable: bool,
+    error: bool,
+}
+
+impl Readiness {
+    fn from_events(events: u32) -> Self {
+        Self {
+            readable: events & EPOLLIN as u32 != 0,
+            writable: events & EPOLLOUT as u32 != 0,
+            error: events & (EPOLLERR | EPOLLHUP) as u32 != 0,
+        }
+    }
+
+    /// Whether there's something to read.
+    #[inline]
+    pub fn is_readable(&self) -> bool {
+        self.readable
+    }
+
+    /// Whether there's room to write.
+    #[inline]
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Whether the fd reported an error or hang up, e.g. because the
+    /// device went away.
+    #[inline]
+    pub fn is_error(&self) -> bool {
+        self.error
+    }
+}
+
+/// Waits on many sockets at once, for a single thread serving several
+/// queues.
+///
+/// Each fd is [added](Self::add) with the readiness to wait for, and
+/// given a key. [`poll_all`](Self::poll_all) then makes a single
+/// `epoll_wait` call for the lot, rather than polling each socket in
+/// turn, and returns the key of every fd which is ready:
+///
+/// ```no_run
+/// # use xsk_rs::{socket::{Interest, PollSet}, RxQueue};
+/// # fn run(mut rx_qs: Vec<RxQueue>) -> std::io::Result<()> {
+/// let mut set = PollSet::new()?;
+///
+/// for rx_q in &rx_qs {
+///     set.add(rx_q.fd(), Interest::Readable)?;
+/// }
+///
+/// loop {
+///     for (key, readiness) in set.poll_all(100)? {
+///         if readiness.is_readable() {
+///             // ... consume from `rx_qs[key]` ...
+///         }
+///     }
+/// }
+/// # }
+/// ```
+///
+/// Readiness is level triggered, as with [`RxQueue::poll`]: an fd is
+/// returned for as long as it's ready, not only when it becomes so.
+///
+/// Unlike [`RxQueue::poll`], waiting doesn't wake the kernel for
+/// sockets bound with
+/// [`XDP_USE_NEED_WAKEUP`](crate::config::BindFlags::XDP_USE_NEED_WAKEUP):
+/// `poll` calls into the socket on every wait, which kicks rx and tx
+/// processing if the kernel asked for it, while `epoll_wait` only
+/// does once the socket signals an event, which a stalled socket
+/// never will. Kick any queue which [needs it](crate::FillQueue::needs_wakeup)
+/// before each wait, e.g. with [`spi::kick`](crate::driver::spi::kick).
+///
+/// [`RxQueue::poll`]: crate::RxQueue::poll
+#[derive(Debug)]
+pub struct PollSet {
+    epfd: OwnedFd,
+    fds: Vec<Option<RawFd>>,
+    events: Vec<libc::epoll_event>,
+}
+
+impl PollSet {
+    /// An empty set.
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::epoll_create1(EPOLL_CLOEXEC) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            // SAFETY: `fd` was just opened and is owned by nothing
+            // else.
+            epfd: unsafe { OwnedFd::from_raw_fd(fd) },
+            fds: Vec::new(),
+            events: Vec::new(),
+        })
+    }
+
+    /// Wait for `interest` on `fd`, returning the key it's reported
+    /// under. Keys are handed out in order from zero, so with sockets
+    /// added in queue order a socket's key is its index.
+    ///
+    /// The fd must stay open for as long as it's in the set, and
+    /// mustn't already be in it.
+    pub fn add<F: AsRawFd>(&mut self, fd: &F, interest: Interest) -> io::Result<usize> {
+        let key = self.fds.len();
+        let fd = fd.as_raw_fd();
+
+        let mut event = libc::epoll_event {
+            events: interest.events(),
+            u64: key as u64,
+        };
+
+        if unsafe { libc::epoll_ctl(self.epfd.as_raw_fd(), EPOLL_CTL_ADD, fd, &mut event) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        self.fds.push(Some(fd));
+        self.events.push(libc::epoll_event { events: 0, u64: 0 });
+
+        Ok(key)
+    }
+
+    /// Stop waiting on the fd added under `key`. Its key isn't reused.
+    ///
+    /// # Panics
+    ///
+    /// If `key` wasn't returned by [`add`](Self::add).
+    pub fn remove(&mut self, key: usize) -> io::Result<()> {
+        let fd = match self.fds[key].take() {
+            Some(fd) => fd,
+            None => return Ok(()),
+        };
+
+        let ret = unsafe {
+            libc::epoll_ctl(
+                self.epfd.as_raw_fd(),
+                EPOLL_CTL_DEL,
+                fd,
+                std::ptr::null_mut(),
+            )
+        };
+
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// The number of fds in the set.
+    pub fn len(&self) -> usize {
+        self.fds.iter().filter(|fd| fd.is_some()).count()
+    }
+
+    /// Whether the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wait up to `timeout_ms` milliseconds, or forever if negative,
+    /// for any fd in the set to be ready, then return the key and
+    /// readiness of each one that is. Returns nothing if the wait
+    /// timed out or was interrupted by a signal.
+    ///
+    /// An empty set sleeps out the timeout.
+    ///
+    /// # Errors
+    ///
+    /// If the set is empty and `timeout_ms` is negative, as nothing
+    /// could ever end the wait.
+    pub fn poll_all(
+        &mut self,
+        timeout_ms: i32,
+    ) -> io::Result<impl Iterator<Item = (usize, Readiness)> + '_> {
+        if timeout_ms < 0 && self.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "waiting forever on an empty poll set",
+            ));
+        }
+
+        // `epoll_wait` needs room for at least one event.
+        if self.events.is_empty() {
+            self.events.push(libc::epoll_event { events: 0, u64: 0 });
+        }
+
+        let ret = unsafe {
+            libc::epoll_wait(
+                self.epfd.as_raw_fd(),
+                self.events.as_mut_ptr(),
+                self.events.len() as i32,
+                timeout_ms,
+            )
+        };
+
+        let cnt = if ret < 0 {
+            match util::get_errno() {
+                EINTR => 0,
+                _ => return Err(io::Error::last_os_error()),
+            }
+        } else {
+            ret as usize
+        };
+
+        Ok(self.events[..cnt].iter().map(|event| {
+            let events = event.events;
+            let key = event.u64;
+
+            (key as usize, Readiness::from_events(events))
+        }))
+    }
+}
+
+impl AsRawFd for PollSet {
+    /// The epoll fd, which is readable when any fd in the set is
+    /// ready, e.g. for nesting in another event loop.
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.epfd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::WakeFd;
+
+    #[test]
+    fn only_ready_fds_are_returned_under_their_keys() {
+        let mut set = PollSet::new().unwrap();
+        let wakes = [WakeFd::new().unwrap(), WakeFd::new().unwrap()];
+
+        for (i, wake) in wakes.iter().enumerate() {
+            assert_eq!(set.add(wake, Interest::Readable).unwrap(), i);
+        }
+
+        assert_eq!(set.poll_all(0).unwrap().count(), 0);
+
+        wakes[1].wake().unwrap();
+
+        let ready: Vec<_> = set.poll_all(0).unwrap().collect();
+
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].0, 1);
+        assert!(ready[0].1.is_readable());
+        assert!(!ready[0].1.is_writable());
+    }
+
+    #[test]
+    fn removed_fds_are_no_longer_returned() {
+        let mut set = PollSet::new().unwrap();
+        let wake = WakeFd::new().unwrap();
+
+        let key = set.add(&wake, Interest::Both).unwrap();
+        assert_eq!(set.len(), 1);
+
+        wake.wake().unwrap();
+        assert_eq!(set.poll_all(0).unwrap().count(), 1);
+
+        set.remove(key).unwrap();
+        set.remove(key).unwrap();
+
+        assert!(set.is_empty());
+        assert_eq!(set.poll_all(0).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn empty_sets_sleep_out_the_timeout_or_refuse_to_wait_forever() {
+        let mut set = PollSet::new().unwrap();
+
+        let start = std::time::Instant::now();
+        assert_eq!(set.poll_all(20).unwrap().count(), 0);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(20));
+
+        assert_eq!(
+            set.poll_all(-1).map(|r| r.count()).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+}
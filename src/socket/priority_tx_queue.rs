@@ -0,0 +1,237 @@
+use std::{cmp::Reverse, collections::VecDeque, io, iter, num::NonZeroUsize};
+
+use crate::umem::frame::FrameDesc;
+
+use super::TxQueue;
+
+/// The default number of frames a waiting class may be passed over
+/// before it's allowed to send one, see
+/// [`PriorityTxQueue::starvation_limit`].
+pub const DEFAULT_STARVATION_LIMIT: usize = 64;
+
+#[derive(Debug)]
+struct Class {
+    queue: VecDeque<FrameDesc>,
+    capacity: usize,
+    passed_over: usize,
+    sent: u64,
+}
+
+#[derive(Debug)]
+struct Scheduler {
+    classes: Vec<Class>,
+    starvation_limit: Option<usize>,
+}
+
+impl Scheduler {
+    fn new(classes: NonZeroUsize, class_capacity: usize) -> Self {
+        Self {
+            classes: (0..classes.get())
+                .map(|_| Class {
+                    queue: VecDeque::with_capacity(class_capacity),
+                    capacity: class_capacity,
+                    passed_over: 0,
+                    sent: 0,
+                })
+                .collect(),
+            starvation_limit: Some(DEFAULT_STARVATION_LIMIT),
+        }
+    }
+
+    /// Pick the next frame to send: from the highest priority class
+    /// with anything queued, unless a lower one has waited too long,
+    /// in which case the longest waiting goes.
+    fn next(&mut self) -> Option<FrameDesc> {
+        let highest = self.classes.iter().position(|c| !c.queue.is_empty())?;
+
+        let chosen = self
+            .starvation_limit
+            .and_then(|limit| {
+                self.classes[highest + 1..]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| !c.queue.is_empty() && c.passed_over >= limit)
+                    .max_by_key(|(i, c)| (c.passed_over, Reverse(*i)))
+                    .map(|(i, _)| highest + 1 + i)
+            })
+            .unwrap_or(highest);
+
+        for class in self.classes[chosen + 1..].iter_mut() {
+            if !class.queue.is_empty() {
+                class.passed_over += 1;
+            }
+        }
+
+        let class = &mut self.classes[chosen];
+        class.passed_over = 0;
+        class.sent += 1;
+
+        class.queue.pop_front()
+    }
+}
+
+/// Multiplexes several priority classes of traffic onto one
+/// [`TxQueue`].
+///
+/// Frames are staged per class, class `0` being the highest priority,
+/// and moved onto the ring by [`flush`](Self::flush) in strict
+/// priority order. So control traffic, e.g. ARP replies or keepalives,
+/// put in a higher class than bulk data goes out ahead of whatever
+/// bulk data is waiting, rather than queueing behind it in the ring.
+///
+/// To keep a busy higher class from starving the rest, a class with
+/// frames waiting is allowed to send one once it's been passed over
+/// [`starvation_limit`](Self::starvation_limit) times.
+///
+/// Frames within a class are transmitted in order.
+#[derive(Debug)]
+pub struct PriorityTxQueue {
+    tx_q: TxQueue,
+    sched: Scheduler,
+}
+
+impl PriorityTxQueue {
+    /// Wrap `tx_q` with `classes` priority classes, each staging at
+    /// most `class_capacity` frames before further produces are
+    /// refused.
+    pub fn new(tx_q: TxQueue, classes: NonZeroUsize, class_capacity: usize) -> Self {
+        Self {
+            tx_q,
+            sched: Scheduler::new(classes, class_capacity),
+        }
+    }
+
+    /// Set the number of frames a class with frames waiting may be
+    /// passed over by higher priority ones before it gets to send one.
+    /// `None` gives pure strict priority, where a lower class only
+    /// sends when every higher one is empty.
+    ///
+    /// Defaults to [`DEFAULT_STARVATION_LIMIT`].
+    pub fn starvation_limit(&mut self, limit: Option<NonZeroUsize>) -> &mut Self {
+        self.sched.starvation_limit = limit.map(NonZeroUsize::get);
+        self
+    }
+
+    /// Stage the frames described by `descs` for transmission in
+    /// `class`, returning the number staged.
+    ///
+    /// Frames are taken from the start of `descs` until the class is
+    /// full, so the returned count may be less than `descs.len()`.
+    ///
+    /// # Safety
+    ///
+    /// See [`TxQueue::produce`]. Frames are considered submitted as
+    /// soon as they are staged.
+    ///
+    /// # Panics
+    ///
+    /// If `class` is not less than the number of classes.
+    #[inline]
+    pub unsafe fn produce(&mut self, class: usize, descs: &[FrameDesc]) -> usize {
+        let class = &mut self.sched.classes[class];
+
+        let cnt = descs
+            .len()
+            .min(class.capacity.saturating_sub(class.queue.len()));
+
+        class.queue.extend(&descs[..cnt]);
+
+        cnt
+    }
+
+    /// Move as many staged frames as will fit onto the ring, highest
+    /// priority first, and wake the kernel if required. Returns the
+    /// number of frames moved.
+    pub fn flush(&mut self) -> io::Result<usize> {
+        let sched = &mut self.sched;
+
+        // SAFETY: frames were handed over via `produce`, whose
+        // contract matches that of `TxQueue::produce`. `extend` only
+        // pulls a frame once there's room for it, so none are lost.
+        let cnt = unsafe { self.tx_q.extend(iter::from_fn(|| sched.next())) };
+
+        self.tx_q.commit_wakeup()?;
+
+        Ok(cnt)
+    }
+
+    /// The number of priority classes.
+    #[inline]
+    pub fn classes(&self) -> usize {
+        self.sched.classes.len()
+    }
+
+    /// The number of frames staged in `class` but not yet moved onto
+    /// the ring.
+    ///
+    /// # Panics
+    ///
+    /// If `class` is not less than the number of classes.
+    #[inline]
+    pub fn pending(&self, class: usize) -> usize {
+        self.sched.classes[class].queue.len()
+    }
+
+    /// The number of frames from `class` moved onto the ring so far.
+    ///
+    /// # Panics
+    ///
+    /// If `class` is not less than the number of classes.
+    #[inline]
+    pub fn sent(&self, class: usize) -> u64 {
+        self.sched.classes[class].sent
+    }
+
+    /// A reference to the underlying [`TxQueue`].
+    #[inline]
+    pub fn tx_q(&self) -> &TxQueue {
+        &self.tx_q
+    }
+
+    /// A mutable reference to the underlying [`TxQueue`].
+    #[inline]
+    pub fn tx_q_mut(&mut self) -> &mut TxQueue {
+        &mut self.tx_q
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::umem::frame::fixtures::desc_at;
+
+    use super::*;
+
+    fn stage(sched: &mut Scheduler, class: usize, addrs: impl IntoIterator<Item = usize>) {
+        sched.classes[class]
+            .queue
+            .extend(addrs.into_iter().map(desc_at));
+    }
+
+    fn drain(sched: &mut Scheduler) -> Vec<usize> {
+        iter::from_fn(|| sched.next()).map(|d| d.addr()).collect()
+    }
+
+    #[test]
+    fn higher_classes_go_first_without_starving_lower_ones() {
+        let mut sched = Scheduler::new(NonZeroUsize::new(3).unwrap(), 16);
+
+        stage(&mut sched, 2, [200, 201]);
+        stage(&mut sched, 1, [100]);
+        stage(&mut sched, 0, 0..6);
+
+        sched.starvation_limit = None;
+        assert_eq!(drain(&mut sched), [0, 1, 2, 3, 4, 5, 100, 200, 201]);
+
+        stage(&mut sched, 2, [200, 201]);
+        stage(&mut sched, 1, [100]);
+        stage(&mut sched, 0, 0..6);
+
+        // Class 2 is passed over by class 1's frame too, so follows
+        // straight after it.
+        sched.starvation_limit = Some(2);
+        assert_eq!(drain(&mut sched), [0, 1, 100, 200, 2, 3, 201, 4, 5]);
+
+        assert_eq!(sched.classes[0].sent, 12);
+        assert_eq!(sched.classes[2].sent, 4);
+    }
+}
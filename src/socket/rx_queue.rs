@@ -1,8 +1,27 @@
-use std::io;
+use libc::{EAGAIN, EBUSY, ENETDOWN, ENOBUFS, MSG_DONTWAIT};
+use std::{
+    io,
+    num::NonZeroUsize,
+    os::unix::prelude::AsRawFd,
+    ptr,
+    time::{Duration, Instant},
+};
 
-use crate::{ring::XskRingCons, umem::frame::FrameDesc};
+use crate::{
+    cancel::CancelToken,
+    config::UnknownDescOptions,
+    ring::{Dynamic, RingSize, XskRingCons},
+    umem::{
+        frame::{DescBatch, DescOptions, FrameDesc, SegmentLengths},
+        OwnedFrame, Recycler, Umem, UmemMismatchError,
+    },
+    util,
+};
 
-use super::{fd::Fd, Socket};
+use super::{
+    fd::Fd, size_check::TruncationCheck, wake, BindMode, Drain, DrainStats, PollOutcome,
+    QueueCounters, RebindError, RingGeometry, Socket, WakeFd, XdpProgWatcher,
+};
 
 /// The receiving side of an AF_XDP [`Socket`].
 ///
@@ -12,11 +31,62 @@ use super::{fd::Fd, Socket};
 pub struct RxQueue {
     ring: XskRingCons,
     socket: Socket,
+    unknown_options: UnknownDescOptions,
+    unknown_options_count: u64,
+    counters: QueueCounters,
+    truncation: TruncationCheck,
+    meta_len: usize,
+    max_burst: Option<NonZeroUsize>,
 }
 
 impl RxQueue {
-    pub(super) fn new(ring: XskRingCons, socket: Socket) -> Self {
-        Self { ring, socket }
+    pub(super) fn new(
+        ring: XskRingCons,
+        socket: Socket,
+        unknown_options: UnknownDescOptions,
+        frame_mtu: usize,
+        max_burst: Option<NonZeroUsize>,
+    ) -> Self {
+        Self {
+            ring,
+            socket,
+            unknown_options,
+            unknown_options_count: 0,
+            counters: QueueCounters::default(),
+            truncation: TruncationCheck::new(frame_mtu),
+            meta_len: 0,
+            max_burst,
+        }
+    }
+
+    /// Clamp a batch of `nb` frames to [`max_burst`](Self::max_burst).
+    #[inline]
+    fn burst(&self, nb: usize) -> usize {
+        self.max_burst.map_or(nb, |max| nb.min(max.get()))
+    }
+
+    /// Apply the configured [`UnknownDescOptions`] policy to a
+    /// received descriptor's options.
+    #[inline]
+    fn filter_options(&mut self, options: u32) -> u32 {
+        let known = DescOptions::all().bits();
+
+        if options & !known == 0 {
+            return options;
+        }
+
+        self.unknown_options_count += 1;
+
+        match self.unknown_options {
+            UnknownDescOptions::Preserve => options,
+            UnknownDescOptions::Strip => options & known,
+        }
+    }
+
+    #[inline]
+    fn record(&mut self, len: usize, options: u32) {
+        self.counters.add(len, options);
+        self.truncation.check(len, options);
     }
 
     /// Update `descs` with information on which [`Umem`] frames have
@@ -41,6 +111,12 @@ impl RxQueue {
     /// [`TxQueue`]: crate::TxQueue
     #[inline]
     pub unsafe fn consume(&mut self, descs: &mut [FrameDesc]) -> usize {
+        unsafe { self.consume_sized::<Dynamic>(descs) }
+    }
+
+    /// [`consume`](Self::consume) for a ring of size `S`.
+    #[inline]
+    pub(crate) unsafe fn consume_sized<S: RingSize>(&mut self, descs: &mut [FrameDesc]) -> usize {
         let nb = descs.len() as u32;
 
         if nb == 0 {
@@ -49,19 +125,70 @@ impl RxQueue {
 
         let mut idx = 0;
 
-        let cnt = unsafe { libxdp_sys::xsk_ring_cons__peek(self.ring.as_mut(), nb, &mut idx) };
+        let cnt = unsafe { self.ring.peek::<S>(nb, &mut idx) };
 
         if cnt > 0 {
             for desc in descs.iter_mut().take(cnt as usize) {
-                let recv_pkt_desc =
-                    unsafe { libxdp_sys::xsk_ring_cons__rx_desc(self.ring.as_ref(), idx) };
+                let recv_pkt_desc = unsafe { self.ring.rx_desc::<S>(idx) };
 
-                unsafe {
+                let options = unsafe {
                     desc.addr = (*recv_pkt_desc).addr as usize;
                     desc.lengths.data = (*recv_pkt_desc).len as usize;
                     desc.lengths.headroom = 0;
-                    desc.options = (*recv_pkt_desc).options;
-                }
+                    desc.lengths.meta = self.meta_len;
+                    (*recv_pkt_desc).options
+                };
+
+                desc.options = self.filter_options(options);
+                self.record(desc.lengths.data, desc.options);
+
+                idx += 1;
+            }
+
+            unsafe { libxdp_sys::xsk_ring_cons__release(self.ring.as_mut(), cnt) };
+        }
+
+        cnt as usize
+    }
+
+    /// Same as [`consume`] but fills `batch`, replacing its contents,
+    /// with up to [`batch.capacity()`](DescBatch::capacity)
+    /// descriptors.
+    ///
+    /// # Safety
+    ///
+    /// See [`consume`].
+    ///
+    /// [`consume`]: Self::consume
+    #[inline]
+    pub unsafe fn consume_batch(&mut self, batch: &mut DescBatch) -> usize {
+        batch.clear();
+
+        let nb = batch.capacity() as u32;
+
+        if nb == 0 {
+            return 0;
+        }
+
+        let mut idx = 0;
+
+        let cnt = unsafe { libxdp_sys::xsk_ring_cons__peek(self.ring.as_mut(), nb, &mut idx) };
+
+        if cnt > 0 {
+            for _ in 0..cnt {
+                let recv_pkt_desc =
+                    unsafe { &*libxdp_sys::xsk_ring_cons__rx_desc(self.ring.as_ref(), idx) };
+
+                let lengths = SegmentLengths {
+                    headroom: 0,
+                    data: recv_pkt_desc.len as usize,
+                    meta: self.meta_len,
+                };
+
+                let options = self.filter_options(recv_pkt_desc.options);
+                self.record(lengths.data, options);
+
+                batch.push_unchecked(recv_pkt_desc.addr as usize, lengths, options);
 
                 idx += 1;
             }
@@ -89,12 +216,16 @@ impl RxQueue {
             let recv_pkt_desc =
                 unsafe { libxdp_sys::xsk_ring_cons__rx_desc(self.ring.as_ref(), idx) };
 
-            unsafe {
+            let options = unsafe {
                 desc.addr = (*recv_pkt_desc).addr as usize;
                 desc.lengths.data = (*recv_pkt_desc).len as usize;
                 desc.lengths.headroom = 0;
-                desc.options = (*recv_pkt_desc).options;
-            }
+                desc.lengths.meta = self.meta_len;
+                (*recv_pkt_desc).options
+            };
+
+            desc.options = self.filter_options(options);
+            self.record(desc.lengths.data, desc.options);
 
             unsafe { libxdp_sys::xsk_ring_cons__release(self.ring.as_mut(), cnt) };
         }
@@ -102,6 +233,220 @@ impl RxQueue {
         cnt as usize
     }
 
+    /// Take up to `nb` received frames, appending them to `frames`.
+    /// Returns the number taken.
+    ///
+    /// The safe counterpart to [`consume`], see [`OwnedFrame`].
+    ///
+    /// # Errors
+    ///
+    /// If `umem` isn't the [`Umem`] this queue's socket was created
+    /// with, in which case nothing is taken.
+    ///
+    /// [`consume`]: Self::consume
+    #[inline]
+    pub fn consume_owned<'umem>(
+        &mut self,
+        umem: &'umem Umem,
+        nb: usize,
+        frames: &mut Vec<OwnedFrame<'umem>>,
+    ) -> Result<usize, UmemMismatchError> {
+        if umem.id() != self.socket.umem_id {
+            return Err(UmemMismatchError);
+        }
+
+        let nb = self.burst(nb);
+        let (idx, cnt) = self.peek(nb.min(u32::MAX as usize) as u32);
+
+        frames.reserve(cnt as usize);
+
+        for i in 0..cnt {
+            let desc = self.read_desc(idx.wrapping_add(i));
+
+            // SAFETY: the kernel has handed the frame over, and it's
+            // in `umem` as checked above.
+            frames.push(unsafe { OwnedFrame::new(umem, desc) });
+        }
+
+        self.release(cnt, 0);
+
+        Ok(cnt as usize)
+    }
+
+    /// Iterate over up to `nb` received frames, as an alternative to
+    /// [`consume`].
+    ///
+    /// Each frame yielded is scheduled on `recycler` once dropped,
+    /// ready to be handed back to the [`FillQueue`] with
+    /// [`Recycler::flush`], unless [`kept`](super::RxFrame::keep). For
+    /// instance, to forward some frames and recycle the rest:
+    ///
+    /// ```no_run
+    /// # use xsk_rs::{umem::Recycler, FillQueue, RxQueue, TxQueue};
+    /// # unsafe fn forward(rx_q: &mut RxQueue, tx_q: &mut TxQueue, fq: &mut FillQueue) {
+    /// let recycler = Recycler::with_capacity(64);
+    ///
+    /// let frames = unsafe { rx_q.drain(&recycler, 64) }
+    ///     .filter(|frame| frame.desc().lengths().data() >= 60)
+    ///     .map(|frame| frame.keep());
+    ///
+    /// unsafe { tx_q.extend(frames) };
+    ///
+    /// unsafe { recycler.flush(fq) };
+    /// # }
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// See [`consume`]. The same goes for `recycler`'s fill queue.
+    ///
+    /// [`consume`]: Self::consume
+    /// [`FillQueue`]: crate::FillQueue
+    #[inline]
+    pub unsafe fn drain<'a>(&'a mut self, recycler: &'a Recycler, nb: usize) -> Drain<'a> {
+        let nb = self.burst(nb);
+        Drain::new(self, recycler, nb.min(u32::MAX as usize) as u32)
+    }
+
+    /// Repeatedly [`consume`] batches of up to `descs.len()` frames,
+    /// or [`max_burst`](Self::max_burst) if fewer, handing each to
+    /// `f`, until either the ring runs dry or `slice` has elapsed.
+    ///
+    /// Meant for loops which share a core with other work, so a burst
+    /// of traffic can't hold it up for longer than `slice` plus the
+    /// time to process one batch. The ring is taken to be dry as soon
+    /// as a batch comes back short. Whatever `f` doesn't move on, e.g.
+    /// onto a [`TxQueue`], should be returned to the [`FillQueue`]
+    /// before it returns, otherwise a long enough slice will use up
+    /// every frame.
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use xsk_rs::{FillQueue, FrameDesc, RxQueue};
+    /// # unsafe fn run(rx_q: &mut RxQueue, fq: &mut FillQueue, descs: &mut [FrameDesc]) {
+    /// loop {
+    ///     let stats = unsafe {
+    ///         rx_q.drain_for(descs, Duration::from_micros(200), |batch| {
+    ///             // ... process `batch` ...
+    ///             fq.produce(batch);
+    ///         })
+    ///     };
+    ///
+    ///     if stats.emptied() {
+    ///         // ... get on with other work, or poll ...
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// See [`consume`].
+    ///
+    /// [`consume`]: Self::consume
+    /// [`FillQueue`]: crate::FillQueue
+    /// [`TxQueue`]: crate::TxQueue
+    pub unsafe fn drain_for<F>(
+        &mut self,
+        descs: &mut [FrameDesc],
+        slice: Duration,
+        mut f: F,
+    ) -> DrainStats
+    where
+        F: FnMut(&mut [FrameDesc]),
+    {
+        let start = Instant::now();
+        let mut stats = DrainStats::default();
+
+        let nb = self.burst(descs.len());
+        let descs = &mut descs[..nb];
+
+        loop {
+            // SAFETY: guaranteed by this function's contract.
+            let cnt = unsafe { self.consume(descs) };
+
+            if cnt > 0 {
+                stats.batches += 1;
+                stats.frames += cnt;
+
+                f(&mut descs[..cnt]);
+            }
+
+            if cnt < descs.len() || descs.is_empty() {
+                stats.emptied = true;
+                break;
+            }
+
+            if start.elapsed() >= slice {
+                break;
+            }
+        }
+
+        stats.elapsed = start.elapsed();
+
+        stats
+    }
+
+    /// Reserve up to `nb` descriptors for reading, returning the index
+    /// of the first and the number reserved.
+    #[inline]
+    pub(super) fn peek(&mut self, nb: u32) -> (u32, u32) {
+        if nb == 0 {
+            return (0, 0);
+        }
+
+        let mut idx = 0;
+
+        let cnt = unsafe { libxdp_sys::xsk_ring_cons__peek(self.ring.as_mut(), nb, &mut idx) };
+
+        (idx, cnt)
+    }
+
+    /// Read the descriptor at `idx`, which must have been reserved by
+    /// [`peek`](Self::peek).
+    #[inline]
+    pub(super) fn read_desc(&mut self, idx: u32) -> FrameDesc {
+        let mut desc = self.peek_desc(idx);
+
+        desc.options = self.filter_options(desc.options);
+        self.record(desc.lengths.data, desc.options);
+
+        desc
+    }
+
+    /// Read the descriptor at `idx` without applying the options
+    /// policy or counting it, which must have been reserved by
+    /// [`peek`](Self::peek).
+    #[inline]
+    pub(super) fn peek_desc(&self, idx: u32) -> FrameDesc {
+        let recv_pkt_desc =
+            unsafe { &*libxdp_sys::xsk_ring_cons__rx_desc(self.ring.as_ref(), idx) };
+
+        FrameDesc {
+            addr: recv_pkt_desc.addr as usize,
+            options: recv_pkt_desc.options,
+            lengths: SegmentLengths {
+                headroom: 0,
+                data: recv_pkt_desc.len as usize,
+                meta: self.meta_len,
+            },
+        }
+    }
+
+    /// Release the first `read` descriptors reserved by
+    /// [`peek`](Self::peek) back to the kernel, and return the
+    /// `unread` after them to the ring.
+    #[inline]
+    pub(super) fn release(&mut self, read: u32, unread: u32) {
+        if read > 0 {
+            unsafe { libxdp_sys::xsk_ring_cons__release(self.ring.as_mut(), read) };
+        }
+
+        if unread > 0 {
+            unsafe { libxdp_sys::xsk_ring_cons__cancel(self.ring.as_mut(), unread) };
+        }
+    }
+
     /// Same as [`consume`] but poll first to check if there is
     /// anything to read beforehand.
     ///
@@ -116,6 +461,9 @@ impl RxQueue {
         descs: &mut [FrameDesc],
         poll_timeout: i32,
     ) -> io::Result<usize> {
+        let nb = self.burst(descs.len());
+        let descs = &mut descs[..nb];
+
         match self.poll(poll_timeout)? {
             true => Ok(unsafe { self.consume(descs) }),
             false => Ok(0),
@@ -142,12 +490,201 @@ impl RxQueue {
         }
     }
 
+    /// Same as [`consume`] but, if nothing is waiting, have the
+    /// kernel run the driver's receive processing in this thread with
+    /// a non-blocking `recvfrom` and try again.
+    ///
+    /// For sockets configured with
+    /// [`busy_poll`](crate::config::SocketConfigBuilder::busy_poll),
+    /// where with [`prefer`](super::BusyPoll::prefer) set the driver
+    /// is only run this way, so it must be called often, and the
+    /// fill ring kept stocked, for anything to be received.
+    ///
+    /// # Safety
+    ///
+    /// See [`consume`].
+    ///
+    /// [`consume`]: Self::consume
+    #[inline]
+    pub unsafe fn busy_poll_recv(&mut self, descs: &mut [FrameDesc]) -> io::Result<usize> {
+        let nb = self.burst(descs.len());
+        let descs = &mut descs[..nb];
+
+        let cnt = unsafe { self.consume(descs) };
+
+        if cnt > 0 || descs.is_empty() {
+            return Ok(cnt);
+        }
+
+        self.wakeup()?;
+
+        Ok(unsafe { self.consume(descs) })
+    }
+
+    /// Wake up the kernel to continue processing received data, with
+    /// a non-blocking `recvfrom`. Unlike [`FillQueue::wakeup`] this
+    /// never waits, so kicks can be issued exactly when wanted, e.g.
+    /// once after producing to several fill rings.
+    ///
+    /// The kernel flags the need for this on the fill ring rather
+    /// than the rx ring, so check [`FillQueue::needs_wakeup`] first.
+    ///
+    /// [`FillQueue::wakeup`]: crate::FillQueue::wakeup
+    /// [`FillQueue::needs_wakeup`]: crate::FillQueue::needs_wakeup
+    #[inline]
+    pub fn wakeup(&self) -> io::Result<()> {
+        let ret = unsafe {
+            libc::recvfrom(
+                self.socket.fd.as_raw_fd(),
+                ptr::null_mut(),
+                0,
+                MSG_DONTWAIT,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+
+        if ret < 0 {
+            match util::get_errno() {
+                ENOBUFS | EAGAIN | EBUSY | ENETDOWN => (),
+                _ => return Err(io::Error::last_os_error()),
+            }
+        }
+
+        Ok(())
+    }
+
     /// Polls the socket, returning `true` if there is data to read.
     #[inline]
     pub fn poll(&mut self, poll_timeout: i32) -> io::Result<bool> {
         self.socket.fd.poll_read(poll_timeout)
     }
 
+    /// Same as [`poll`](Self::poll), but also returns early if `wake`
+    /// is woken from another thread, see [`WakeFd`].
+    #[inline]
+    pub fn poll_or_wake(&mut self, wake: &WakeFd, poll_timeout: i32) -> io::Result<PollOutcome> {
+        wake::poll(self.socket.fd.as_raw_fd(), libc::POLLIN, wake, poll_timeout)
+    }
+
+    /// Same as [`poll`](Self::poll), but also returns early, with
+    /// [`PollOutcome::is_woken`] set, once `cancel` is cancelled. Keeps
+    /// returning straight away from then on, see [`CancelToken`].
+    #[inline]
+    pub fn poll_or_cancel(
+        &mut self,
+        cancel: &CancelToken,
+        poll_timeout: i32,
+    ) -> io::Result<PollOutcome> {
+        wake::poll_or_cancel(
+            self.socket.fd.as_raw_fd(),
+            libc::POLLIN,
+            cancel,
+            poll_timeout,
+        )
+    }
+
+    /// The number of descriptors received with option bits set that
+    /// aren't covered by [`DescOptions`], regardless of the configured
+    /// [`UnknownDescOptions`] policy.
+    #[inline]
+    pub fn unknown_options_count(&self) -> u64 {
+        self.unknown_options_count
+    }
+
+    /// The number of descriptors received which fill their frame's
+    /// entire packet data segment, and so were most likely truncated.
+    ///
+    /// This happens in copy mode when the interface MTU is larger than
+    /// the [`Umem`](crate::Umem) frames have room for, see
+    /// [`UmemConfig::mtu`](crate::config::UmemConfig::mtu). The end of
+    /// each such packet is silently cut off, so a warning is also
+    /// logged the first time it's seen. Continued descriptors of a
+    /// multi-buffer packet aren't counted.
+    #[inline]
+    pub fn truncated_count(&self) -> u64 {
+        self.truncation.count()
+    }
+
+    /// Mark every frame received from here on as starting with `len`
+    /// bytes of XDP metadata, i.e. set each received descriptor's
+    /// [`SegmentLengths::meta`], so it can be read with
+    /// [`Umem::meta`]. Default is zero.
+    ///
+    /// The kernel doesn't pass on how much metadata the XDP program
+    /// reserved, so this should match whatever it always writes.
+    #[inline]
+    pub fn set_meta_len(&mut self, len: usize) -> &mut Self {
+        self.meta_len = len;
+        self
+    }
+
+    /// The length of XDP metadata received frames are marked with,
+    /// see [`set_meta_len`](Self::set_meta_len).
+    #[inline]
+    pub fn meta_len(&self) -> usize {
+        self.meta_len
+    }
+
+    /// The most frames [`consume_owned`], [`drain`], [`drain_for`],
+    /// [`poll_and_consume`] and [`busy_poll_recv`] take per call,
+    /// taken from the socket's
+    /// [`max_burst`](crate::config::SocketConfigBuilder::max_burst).
+    /// Asking for more takes at most this many.
+    ///
+    /// [`consume_owned`]: Self::consume_owned
+    /// [`drain`]: Self::drain
+    /// [`drain_for`]: Self::drain_for
+    /// [`poll_and_consume`]: Self::poll_and_consume
+    /// [`busy_poll_recv`]: Self::busy_poll_recv
+    #[inline]
+    pub fn max_burst(&self) -> Option<NonZeroUsize> {
+        self.max_burst
+    }
+
+    /// Change the [`max_burst`](Self::max_burst) of this queue.
+    #[inline]
+    pub fn set_max_burst(&mut self, max_burst: Option<NonZeroUsize>) {
+        self.max_burst = max_burst;
+    }
+
+    /// Packets and bytes consumed from the ring so far.
+    #[inline]
+    pub fn counters(&self) -> QueueCounters {
+        self.counters
+    }
+
+    /// The size of the ring.
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.ring.as_ref().size as usize
+    }
+
+    /// The number of entries on the ring waiting to be consumed.
+    #[inline]
+    pub(crate) fn fill_level(&mut self) -> usize {
+        let size = self.ring.as_ref().size;
+
+        // SAFETY: the ring is initialised and lives as long as `self`,
+        // and this only refreshes the cached producer index, which
+        // the `&mut self` borrow keeps anything else from touching.
+        unsafe { libxdp_sys::xsk_cons_nb_avail(self.ring.as_mut(), size) as usize }
+    }
+
+    /// The mode the socket ended up bound in, e.g. to check that it
+    /// got zero-copy rather than quietly falling back to copying.
+    pub fn bind_mode(&self) -> io::Result<BindMode> {
+        self.socket.bind_mode()
+    }
+
+    /// The kernel's view of this queue's ring layout, for diagnosing
+    /// kernel or driver mismatches.
+    pub fn ring_geometry(&self) -> io::Result<RingGeometry> {
+        let offsets = self.socket.fd.mmap_offsets()?;
+
+        Ok(RingGeometry::rx(&offsets, self.ring.as_ref().size))
+    }
+
     /// A reference to the underlying [`Socket`]'s file descriptor.
     #[inline]
     pub fn fd(&self) -> &Fd {
@@ -159,4 +696,50 @@ impl RxQueue {
     pub fn fd_mut(&mut self) -> &mut Fd {
         &mut self.socket.fd
     }
+
+    /// A watcher which reports when the XDP program on this socket's
+    /// interface has changed since it was bound, for example because
+    /// another agent on the host replaced it.
+    ///
+    /// The watcher may be moved to another thread.
+    pub fn xdp_prog_watcher(&self) -> XdpProgWatcher {
+        XdpProgWatcher::new(self.socket.clone())
+    }
+
+    /// Recover from an [`XdpProgEvent`](crate::socket::XdpProgEvent)
+    /// by making sure libxdp's default program is attached to the
+    /// interface and adding this socket to its XSKMAP. Returns the id
+    /// of the attached program, which watchers will from then on
+    /// expect.
+    ///
+    /// This will fail if the interface is now running a program
+    /// libxdp can't share the hook with, in which case the only
+    /// option is to tear the socket down and create it again once the
+    /// interface is in a usable state.
+    ///
+    /// Should not be used on sockets created with
+    /// [`XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD`], whose program is
+    /// managed elsewhere. Instead add the socket's
+    /// [`fd`](Self::fd) to the right XSKMAP directly.
+    ///
+    /// [`XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD`]: crate::config::LibxdpFlags::XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD
+    pub fn rebind(&mut self) -> Result<u32, RebindError> {
+        self.socket.rebind()
+    }
+
+    /// Insert this socket into the XSKMAP `map`, at the index of the
+    /// queue it's bound to, so a program managed elsewhere, e.g. by
+    /// `xdp-dispatcher` or another process's loader, can redirect to
+    /// it.
+    ///
+    /// The socket should be created with
+    /// [`XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD`] so libxdp doesn't load a
+    /// program of its own. A pinned map can be opened with
+    /// [`XskMap::from_pinned`](crate::filter::XskMap::from_pinned), or
+    /// any other fd referring to an XSKMAP passed.
+    ///
+    /// [`XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD`]: crate::config::LibxdpFlags::XSK_LIBXDP_FLAGS_INHIBIT_PROG_LOAD
+    pub fn insert_into_map(&self, map: &impl AsRawFd) -> io::Result<()> {
+        self.socket.update_xsk_map(map.as_raw_fd())
+    }
 }
@@ -0,0 +1,175 @@
+use std::{
+    io, mem,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::umem::frame::FrameDesc;
+
+use super::TxQueue;
+
+#[derive(Debug)]
+struct Shards {
+    shards: Vec<Mutex<Vec<FrameDesc>>>,
+    shard_capacity: usize,
+    next_shard: AtomicUsize,
+}
+
+/// A handle for submitting frames to a [`TxQueue`] from any thread.
+///
+/// AF_XDP rings are single producer, so rather than writing to the
+/// ring directly frames are staged in one of several shards and later
+/// moved onto the ring by the [`TxCommitter`]. Each clone of a handle
+/// is assigned its own shard, round-robin, so giving every thread its
+/// own clone keeps lock contention down to the committer.
+///
+/// Frames staged through one handle are transmitted in order.
+/// Ordering between handles is not guaranteed.
+#[derive(Debug)]
+pub struct SharedTxQueue {
+    shards: Arc<Shards>,
+    shard: usize,
+}
+
+impl SharedTxQueue {
+    /// Split `tx_q` into a [`TxCommitter`] and an initial
+    /// `SharedTxQueue` handle.
+    ///
+    /// Each of the `shards` staging buffers holds at most
+    /// `shard_capacity` frames before further produces are refused.
+    pub fn new(
+        tx_q: TxQueue,
+        shards: NonZeroUsize,
+        shard_capacity: usize,
+    ) -> (TxCommitter, SharedTxQueue) {
+        let shards = Arc::new(Shards {
+            shards: (0..shards.get())
+                .map(|_| Mutex::new(Vec::with_capacity(shard_capacity)))
+                .collect(),
+            shard_capacity,
+            next_shard: AtomicUsize::new(1),
+        });
+
+        let committer = TxCommitter {
+            tx_q,
+            shards: shards.clone(),
+            backlog: Vec::new(),
+            spare: Vec::with_capacity(shard_capacity),
+        };
+
+        (committer, SharedTxQueue { shards, shard: 0 })
+    }
+
+    /// Stage the frames described by `descs` for transmission,
+    /// returning the number staged.
+    ///
+    /// Frames are taken from the start of `descs` until this handle's
+    /// shard is full, so the returned count may be less than
+    /// `descs.len()`.
+    ///
+    /// # Safety
+    ///
+    /// See [`TxQueue::produce`]. Frames are considered submitted as
+    /// soon as they are staged.
+    #[inline]
+    pub unsafe fn produce(&self, descs: &[FrameDesc]) -> usize {
+        let mut shard = self.shards.shards[self.shard].lock().unwrap();
+
+        let cnt = descs
+            .len()
+            .min(self.shards.shard_capacity.saturating_sub(shard.len()));
+
+        shard.extend_from_slice(&descs[..cnt]);
+
+        cnt
+    }
+
+    /// The index of the shard this handle stages into.
+    #[inline]
+    pub fn shard(&self) -> usize {
+        self.shard
+    }
+}
+
+impl Clone for SharedTxQueue {
+    /// Create a new handle staging into the next shard.
+    fn clone(&self) -> Self {
+        let shard =
+            self.shards.next_shard.fetch_add(1, Ordering::Relaxed) % self.shards.shards.len();
+
+        Self {
+            shards: self.shards.clone(),
+            shard,
+        }
+    }
+}
+
+/// Moves frames staged via [`SharedTxQueue`] handles onto the
+/// underlying [`TxQueue`]. Should be driven by a single thread.
+#[derive(Debug)]
+pub struct TxCommitter {
+    tx_q: TxQueue,
+    shards: Arc<Shards>,
+    // Frames taken from the shards that didn't fit on the ring yet.
+    backlog: Vec<FrameDesc>,
+    spare: Vec<FrameDesc>,
+}
+
+impl TxCommitter {
+    /// Move as many staged frames as will fit onto the ring and wake
+    /// the kernel if required. Returns the number of frames moved.
+    ///
+    /// Frames which don't fit are held back and tried first on the
+    /// next flush, so a shard's frames stay in order.
+    pub fn flush(&mut self) -> io::Result<usize> {
+        for shard in self.shards.shards.iter() {
+            let mut shard = shard.lock().unwrap();
+
+            if shard.is_empty() {
+                continue;
+            }
+
+            // Swap rather than copy under the lock so producers are
+            // blocked for as short a time as possible.
+            mem::swap(&mut *shard, &mut self.spare);
+            drop(shard);
+
+            self.backlog.append(&mut self.spare);
+        }
+
+        let free = self.tx_q.free_slots(self.backlog.len() as u32) as usize;
+        let cnt = free.min(self.backlog.len());
+
+        // SAFETY: frames were handed over via `SharedTxQueue::produce`,
+        // whose contract matches that of `TxQueue::produce`.
+        let cnt = unsafe { self.tx_q.produce(&self.backlog[..cnt]) };
+
+        self.backlog.drain(..cnt);
+
+        self.tx_q.commit_wakeup()?;
+
+        Ok(cnt)
+    }
+
+    /// The number of frames taken from the shards but not yet moved
+    /// onto the ring.
+    #[inline]
+    pub fn backlog(&self) -> usize {
+        self.backlog.len()
+    }
+
+    /// A reference to the underlying [`TxQueue`].
+    #[inline]
+    pub fn tx_q(&self) -> &TxQueue {
+        &self.tx_q
+    }
+
+    /// A mutable reference to the underlying [`TxQueue`].
+    #[inline]
+    pub fn tx_q_mut(&mut self) -> &mut TxQueue {
+        &mut self.tx_q
+    }
+}
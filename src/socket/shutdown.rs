@@ -0,0 +1,138 @@
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+use crate::umem::{frame::FrameDesc, CompQueue, OwnedFrame, UmemMismatchError};
+
+use super::{RxQueue, Socket, TxQueue};
+
+/// How long [`Socket::shutdown`] waits for another completion once
+/// the tx ring is empty, since the kernel takes frames off the ring
+/// before it completes them.
+const COMPLETION_GRACE: Duration = Duration::from_millis(10);
+
+/// The frames reclaimed by [`Socket::shutdown`].
+#[derive(Debug, Clone)]
+pub struct SocketShutdown {
+    completed: Vec<FrameDesc>,
+    received: Vec<FrameDesc>,
+    unsent: usize,
+    timed_out: bool,
+}
+
+impl SocketShutdown {
+    /// Frames that finished sending, consumed from the completion
+    /// queue.
+    pub fn completed(&self) -> &[FrameDesc] {
+        &self.completed
+    }
+
+    /// Frames received while shutting down, consumed from the rx
+    /// queue.
+    pub fn received(&self) -> &[FrameDesc] {
+        &self.received
+    }
+
+    /// Frames still on the tx ring when the timeout passed. They
+    /// weren't sent, and no descriptors are returned for them.
+    pub fn unsent(&self) -> usize {
+        self.unsent
+    }
+
+    /// Whether the timeout passed before the tx ring emptied and the
+    /// last completions came in.
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// All reclaimed frames, completed then received.
+    pub fn into_descs(self) -> Vec<FrameDesc> {
+        let mut descs = self.completed;
+        descs.extend(self.received);
+        descs
+    }
+}
+
+impl Socket {
+    /// Stop a socket, reclaiming the frames it has in flight.
+    ///
+    /// Taking `tx_q` and `rx_q` stops anything more being sent or
+    /// received through them. Until the tx ring is empty and no more
+    /// completions arrive, or `timeout` passes, the kernel is woken to
+    /// send what's left on the tx ring and completions and received
+    /// frames are consumed. Both queues are then dropped, deleting the
+    /// socket unless other clones of them are still around.
+    ///
+    /// Frames still on the fill queue, and any completions arriving
+    /// late, can then be reclaimed with
+    /// [`Umem::drain`](crate::Umem::drain) once every socket using the
+    /// [`Umem`](crate::Umem) has been shut down and traffic to their
+    /// queues has stopped.
+    ///
+    /// # Errors
+    ///
+    /// If `cq` belongs to a different [`Umem`](crate::Umem) to the
+    /// queues, or a wakeup fails. The queues are dropped either way.
+    pub fn shutdown(
+        mut tx_q: TxQueue,
+        mut rx_q: RxQueue,
+        cq: &mut CompQueue,
+        timeout: Duration,
+    ) -> io::Result<SocketShutdown> {
+        if cq.umem().id() != tx_q.umem_id() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                UmemMismatchError,
+            ));
+        }
+
+        let umem = cq.umem().clone();
+        let deadline = Instant::now() + timeout;
+
+        let mut frames: Vec<OwnedFrame<'_>> = Vec::new();
+        let mut completed = Vec::new();
+        let mut received = Vec::new();
+        let mut last_completion = Instant::now();
+
+        let timed_out = loop {
+            let now = Instant::now();
+
+            if tx_q.fill_level() == 0 && now.duration_since(last_completion) >= COMPLETION_GRACE {
+                break false;
+            }
+
+            if now >= deadline {
+                break true;
+            }
+
+            let cnt = cq
+                .consume_owned(&umem, usize::MAX, &mut frames)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+            if cnt > 0 {
+                last_completion = now;
+            }
+
+            completed.extend(frames.drain(..).map(OwnedFrame::into_desc));
+
+            rx_q.consume_owned(&umem, usize::MAX, &mut frames)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+
+            received.extend(frames.drain(..).map(OwnedFrame::into_desc));
+
+            if tx_q.fill_level() > 0 {
+                tx_q.wakeup()?;
+            } else if cnt == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+        };
+
+        Ok(SocketShutdown {
+            completed,
+            received,
+            unsent: tx_q.fill_level(),
+            timed_out,
+        })
+    }
+}
@@ -0,0 +1,148 @@
+use log::warn;
+
+use crate::umem::frame::DescOptions;
+
+/// Counts received descriptors which fill their frame's entire packet
+/// data segment.
+///
+/// In copy mode the kernel truncates packets too big for a frame
+/// rather than dropping them, so a descriptor exactly as long as the
+/// segment most likely lost the end of its packet. The usual cause is
+/// an interface MTU larger than the UMEM's frame size allows for.
+#[derive(Debug)]
+pub(super) struct TruncationCheck {
+    capacity: usize,
+    count: u64,
+}
+
+impl TruncationCheck {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, count: 0 }
+    }
+
+    #[inline]
+    pub fn check(&mut self, len: usize, options: u32) {
+        // Continued descriptors of a multi-buffer packet fill their
+        // frames as a matter of course.
+        if len < self.capacity || options & DescOptions::XDP_PKT_CONTD.bits() != 0 {
+            return;
+        }
+
+        if self.count == 0 {
+            warn!(
+                "received a {} byte frame, filling its buffer, which was likely truncated; \
+                 check the interface MTU fits within the UMEM frame size",
+                len
+            );
+        }
+
+        self.count += 1;
+    }
+
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+/// Counts submitted packets longer than the interface will carry.
+///
+/// Packets are measured over all of their descriptors and compared
+/// against the interface MTU plus room for an ethernet header and one
+/// VLAN tag. Such packets are dropped by the kernel or driver, usually
+/// without any error making its way back.
+#[derive(Debug)]
+pub(super) struct OversizeCheck {
+    max_len: Option<usize>,
+    pkt_len: usize,
+    count: u64,
+}
+
+impl OversizeCheck {
+    pub fn new(max_len: Option<usize>) -> Self {
+        Self {
+            max_len,
+            pkt_len: 0,
+            count: 0,
+        }
+    }
+
+    #[inline]
+    pub fn check(&mut self, len: usize, options: u32) {
+        self.pkt_len += len;
+
+        if options & DescOptions::XDP_PKT_CONTD.bits() != 0 {
+            return;
+        }
+
+        let pkt_len = self.pkt_len;
+        self.pkt_len = 0;
+
+        match self.max_len {
+            Some(max_len) if pkt_len > max_len => {
+                if self.count == 0 {
+                    warn!(
+                        "submitted a {} byte packet, over the interface's limit of {} bytes; \
+                         it will be dropped",
+                        pkt_len, max_len
+                    );
+                }
+
+                self.count += 1;
+            }
+            _ => (),
+        }
+    }
+
+    #[inline]
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    #[inline]
+    pub fn set_max_len(&mut self, max_len: Option<usize>) {
+        self.max_len = max_len;
+    }
+
+    #[inline]
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTD: u32 = DescOptions::XDP_PKT_CONTD.bits();
+
+    #[test]
+    fn only_final_descriptors_filling_the_frame_count_as_truncated() {
+        let mut check = TruncationCheck::new(3840);
+
+        check.check(1500, 0);
+        check.check(3840, CONTD);
+        check.check(100, 0);
+        assert_eq!(check.count(), 0);
+
+        check.check(3840, 0);
+        assert_eq!(check.count(), 1);
+    }
+
+    #[test]
+    fn multi_buffer_packets_are_measured_as_a_whole() {
+        let mut check = OversizeCheck::new(Some(1518));
+
+        check.check(1000, CONTD);
+        check.check(518, 0);
+        assert_eq!(check.count(), 0);
+
+        check.check(1000, CONTD);
+        check.check(519, 0);
+        assert_eq!(check.count(), 1);
+
+        check.set_max_len(None);
+        check.check(9000, 0);
+        assert_eq!(check.count(), 1);
+    }
+}
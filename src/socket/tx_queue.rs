@@ -1,9 +1,24 @@
 use libc::{EAGAIN, EBUSY, ENETDOWN, ENOBUFS, MSG_DONTWAIT};
-use std::{io, os::unix::prelude::AsRawFd, ptr};
-
-use crate::{ring::XskRingProd, umem::frame::FrameDesc, util};
-
-use super::{fd::Fd, Socket};
+use std::{borrow::Borrow, cell::Cell, io, num::NonZeroUsize, os::unix::prelude::AsRawFd, ptr};
+
+#[cfg(debug_assertions)]
+use std::time::{Duration, Instant};
+
+use crate::{
+    cancel::CancelToken,
+    check::{self, Bounds},
+    ring::{Dynamic, RingSize, XskRingProd},
+    umem::{
+        frame::{DescBatch, FrameDesc},
+        CompQueue, FramePool, OwnedFrame, SendBatchError, SentBatch, UmemMismatchError,
+    },
+    util,
+};
+
+use super::{
+    fd::Fd, size_check::OversizeCheck, wake, BindMode, PollOutcome, QueueCounters, RingGeometry,
+    Socket, WakeFd,
+};
 
 /// The transmitting side of an AF_XDP [`Socket`].
 ///
@@ -13,11 +28,90 @@ use super::{fd::Fd, Socket};
 pub struct TxQueue {
     ring: XskRingProd,
     socket: Socket,
+    uncommitted: Uncommitted,
+    counters: QueueCounters,
+    oversize: OversizeCheck,
+    uses_need_wakeup: bool,
+    max_burst: Option<NonZeroUsize>,
+    bounds: Bounds,
+}
+
+/// How long frames may sit produced but uncommitted before a debug
+/// build panics on the next produce.
+#[cfg(debug_assertions)]
+const MAX_UNCOMMITTED_AGE: Duration = Duration::from_secs(1);
+
+/// Frames produced since the kernel was last woken, or last found not
+/// to need waking.
+#[derive(Debug, Default)]
+struct Uncommitted {
+    count: Cell<usize>,
+    #[cfg(debug_assertions)]
+    since: Cell<Option<Instant>>,
+}
+
+impl Uncommitted {
+    #[inline]
+    fn add(&self, cnt: usize) {
+        if cnt == 0 {
+            return;
+        }
+
+        #[cfg(debug_assertions)]
+        match self.since.get() {
+            Some(since) => debug_assert!(
+                since.elapsed() <= MAX_UNCOMMITTED_AGE,
+                "{} tx frames produced but not committed for over {:?}, \
+                 missing a call to `TxQueue::commit_wakeup`?",
+                self.count.get(),
+                MAX_UNCOMMITTED_AGE
+            ),
+            None => self.since.set(Some(Instant::now())),
+        }
+
+        self.count.set(self.count.get() + cnt);
+    }
+
+    #[inline]
+    fn clear(&self) {
+        self.count.set(0);
+
+        #[cfg(debug_assertions)]
+        self.since.set(None);
+    }
 }
 
 impl TxQueue {
-    pub(super) fn new(ring: XskRingProd, socket: Socket) -> Self {
-        Self { ring, socket }
+    pub(super) fn new(
+        ring: XskRingProd,
+        socket: Socket,
+        max_frame_len: Option<usize>,
+        uses_need_wakeup: bool,
+        max_burst: Option<NonZeroUsize>,
+        bounds: Bounds,
+    ) -> Self {
+        Self {
+            ring,
+            socket,
+            uncommitted: Uncommitted::default(),
+            counters: QueueCounters::default(),
+            oversize: OversizeCheck::new(max_frame_len),
+            uses_need_wakeup,
+            max_burst,
+            bounds,
+        }
+    }
+
+    /// Clamp a batch of `nb` frames to [`max_burst`](Self::max_burst).
+    #[inline]
+    fn burst(&self, nb: usize) -> usize {
+        self.max_burst.map_or(nb, |max| nb.min(max.get()))
+    }
+
+    #[inline]
+    fn record(&mut self, len: usize, options: u32) {
+        self.counters.add(len, options);
+        self.oversize.check(len, options);
     }
 
     /// Let the kernel know that the frames described by `descs` are
@@ -45,25 +139,37 @@ impl TxQueue {
     /// [`Umem`]: crate::Umem
     #[inline]
     pub unsafe fn produce(&mut self, descs: &[FrameDesc]) -> usize {
+        unsafe { self.produce_sized::<Dynamic>(descs) }
+    }
+
+    /// [`produce`](Self::produce) for a ring of size `S`.
+    #[inline]
+    pub(crate) unsafe fn produce_sized<S: RingSize>(&mut self, descs: &[FrameDesc]) -> usize {
         let nb = descs.len() as u32;
 
         if nb == 0 {
             return 0;
         }
 
+        // Checked as a whole before reserving, so a violation leaves
+        // the ring untouched.
+        check::unique("tx queue", descs.iter().map(|d| d.addr));
+        self.bounds
+            .check_all("tx queue", descs.iter().map(|d| (d.addr, d.lengths.data)));
+
         let mut idx = 0;
 
-        let cnt = unsafe { libxdp_sys::xsk_ring_prod__reserve(self.ring.as_mut(), nb, &mut idx) };
+        let cnt = unsafe { self.ring.reserve::<S>(nb, &mut idx) };
 
         if cnt > 0 {
             for desc in descs.iter().take(cnt as usize) {
-                let send_pkt_desc =
-                    unsafe { libxdp_sys::xsk_ring_prod__tx_desc(self.ring.as_mut(), idx) };
+                let send_pkt_desc = unsafe { self.ring.tx_desc::<S>(idx) };
 
                 // SAFETY: unsafe contract of this function guarantees
                 // `desc` describes a frame belonging to the same UMEM as
                 // this queue.
                 unsafe { desc.write_xdp_desc(&mut *send_pkt_desc) };
+                self.record(desc.lengths.data, desc.options);
 
                 idx += 1;
             }
@@ -71,9 +177,182 @@ impl TxQueue {
             unsafe { libxdp_sys::xsk_ring_prod__submit(self.ring.as_mut(), cnt) };
         }
 
+        self.uncommitted.add(cnt as usize);
+
         cnt as usize
     }
 
+    /// Same as [`produce`] but submits the descriptors in `batch`.
+    /// As with [`produce`], either all of them are submitted or none
+    /// are.
+    ///
+    /// # Safety
+    ///
+    /// See [`produce`].
+    ///
+    /// [`produce`]: Self::produce
+    #[inline]
+    pub unsafe fn produce_batch(&mut self, batch: &DescBatch) -> usize {
+        let nb = batch.len() as u32;
+
+        if nb == 0 {
+            return 0;
+        }
+
+        // As in `produce_sized`.
+        check::unique("tx queue", batch.addrs().iter().copied());
+        self.bounds.check_all(
+            "tx queue",
+            batch
+                .addrs()
+                .iter()
+                .copied()
+                .zip(batch.data_lens().iter().copied()),
+        );
+
+        let mut idx = 0;
+
+        let cnt = unsafe { libxdp_sys::xsk_ring_prod__reserve(self.ring.as_mut(), nb, &mut idx) };
+
+        if cnt > 0 {
+            let cols = batch
+                .addrs()
+                .iter()
+                .zip(batch.data_lens())
+                .zip(batch.options());
+
+            for ((&addr, &len), &options) in cols.take(cnt as usize) {
+                let send_pkt_desc =
+                    unsafe { &mut *libxdp_sys::xsk_ring_prod__tx_desc(self.ring.as_mut(), idx) };
+
+                send_pkt_desc.addr = addr as u64;
+                send_pkt_desc.len = len as u32;
+                send_pkt_desc.options = options;
+
+                self.record(len, options);
+
+                idx += 1;
+            }
+
+            unsafe { libxdp_sys::xsk_ring_prod__submit(self.ring.as_mut(), cnt) };
+        }
+
+        self.uncommitted.add(cnt as usize);
+
+        cnt as usize
+    }
+
+    /// Same as [`produce`] but takes descriptors from an iterator, say
+    /// of frames [kept](crate::socket::RxFrame::keep) from an
+    /// [`RxQueue::drain`](crate::RxQueue::drain). Returns the number
+    /// submitted.
+    ///
+    /// Unlike [`produce`], as many descriptors as fit are submitted.
+    /// Items are only taken from `descs` once there's space for them,
+    /// so pass [`by_ref`](Iterator::by_ref) to hold on to the rest.
+    ///
+    /// # Safety
+    ///
+    /// See [`produce`].
+    ///
+    /// [`produce`]: Self::produce
+    #[inline]
+    pub unsafe fn extend<I>(&mut self, descs: I) -> usize
+    where
+        I: IntoIterator,
+        I::Item: Borrow<FrameDesc>,
+    {
+        let mut descs = descs.into_iter();
+        let mut cnt = 0;
+        let mut violation = None;
+
+        while self.free_slots(1) > 0 {
+            let desc = match descs.next() {
+                Some(desc) => desc,
+                None => break,
+            };
+
+            let desc = desc.borrow();
+
+            // Submit what's been written so far before panicking, so
+            // the ring isn't left with slots reserved.
+            if !self.bounds.allows(desc.addr, desc.lengths.data) {
+                violation = Some((desc.addr, desc.lengths.data));
+                break;
+            }
+
+            let mut idx = 0;
+
+            // Only moves the cached producer index, so the single
+            // submit below covers every slot reserved here.
+            let reserved =
+                unsafe { libxdp_sys::xsk_ring_prod__reserve(self.ring.as_mut(), 1, &mut idx) };
+
+            debug_assert_eq!(reserved, 1);
+
+            let send_pkt_desc =
+                unsafe { libxdp_sys::xsk_ring_prod__tx_desc(self.ring.as_mut(), idx) };
+
+            // SAFETY: unsafe contract of this function guarantees
+            // `desc` describes a frame belonging to the same UMEM as
+            // this queue.
+            unsafe { desc.write_xdp_desc(&mut *send_pkt_desc) };
+            self.record(desc.lengths.data, desc.options);
+
+            cnt += 1;
+        }
+
+        if cnt > 0 {
+            unsafe { libxdp_sys::xsk_ring_prod__submit(self.ring.as_mut(), cnt) };
+        }
+
+        self.uncommitted.add(cnt as usize);
+
+        if let Some((addr, len)) = violation {
+            self.bounds.check("tx queue", addr, len);
+        }
+
+        cnt as usize
+    }
+
+    /// Submit frames from the start of `frames` for transmission,
+    /// removing them from `frames`. Returns the number submitted,
+    /// which is as many as there's room for on the ring.
+    ///
+    /// The safe counterpart to [`extend`], and like it the kernel
+    /// still needs waking once the frames are submitted, see
+    /// [`commit_wakeup`](Self::commit_wakeup). Frames come back via
+    /// [`CompQueue::consume_owned`](crate::CompQueue::consume_owned).
+    /// See [`OwnedFrame`].
+    ///
+    /// # Errors
+    ///
+    /// If any of `frames` belongs to a different [`Umem`] to the one
+    /// this queue's socket was created with, in which case none are
+    /// submitted.
+    ///
+    /// [`extend`]: Self::extend
+    /// [`Umem`]: crate::Umem
+    #[inline]
+    pub fn produce_owned(
+        &mut self,
+        frames: &mut Vec<OwnedFrame<'_>>,
+    ) -> Result<usize, UmemMismatchError> {
+        if !frames.iter().all(|f| f.umem().id() == self.socket.umem_id) {
+            return Err(UmemMismatchError);
+        }
+
+        // SAFETY: the frames are owned by user space and belong to
+        // this queue's UMEM, and those submitted are moved out of
+        // `frames` so can't be accessed again until completed.
+        let nb = self.burst(frames.len());
+        let cnt = unsafe { self.extend(frames[..nb].iter().map(OwnedFrame::desc)) };
+
+        frames.drain(..cnt);
+
+        Ok(cnt)
+    }
+
     /// Same as [`produce`] but for a single frame descriptor.
     ///
     /// # Safety
@@ -83,6 +362,8 @@ impl TxQueue {
     /// [`produce`]: Self::produce
     #[inline]
     pub unsafe fn produce_one(&mut self, desc: &FrameDesc) -> usize {
+        self.bounds.check("tx queue", desc.addr, desc.lengths.data);
+
         let mut idx = 0;
 
         let cnt = unsafe { libxdp_sys::xsk_ring_prod__reserve(self.ring.as_mut(), 1, &mut idx) };
@@ -95,10 +376,13 @@ impl TxQueue {
             // `desc` describes a frame belonging to the same UMEM as
             // this queue.
             unsafe { desc.write_xdp_desc(&mut *send_pkt_desc) };
+            self.record(desc.lengths.data, desc.options);
 
             unsafe { libxdp_sys::xsk_ring_prod__submit(self.ring.as_mut(), cnt) };
         }
 
+        self.uncommitted.add(cnt as usize);
+
         cnt as usize
     }
 
@@ -115,15 +399,82 @@ impl TxQueue {
     /// [`produce`]: Self::produce
     #[inline]
     pub unsafe fn produce_and_wakeup(&mut self, descs: &[FrameDesc]) -> io::Result<usize> {
-        let cnt = unsafe { self.produce(descs) };
+        let cnt = unsafe { self.produce(&descs[..self.burst(descs.len())]) };
 
-        if self.needs_wakeup() {
-            self.wakeup()?;
-        }
+        self.commit_wakeup()?;
+
+        Ok(cnt)
+    }
+
+    /// Same as [`produce`] but always follow with a [`wakeup`], so
+    /// the kernel runs the driver's transmit processing in this
+    /// thread, regardless of [`needs_wakeup`].
+    ///
+    /// For sockets configured with
+    /// [`busy_poll`](crate::config::SocketConfigBuilder::busy_poll),
+    /// where the driver may otherwise not get around to the ring, and
+    /// to reaping completions, until the next interrupt.
+    ///
+    /// # Safety
+    ///
+    /// See [`produce`].
+    ///
+    /// [`produce`]: Self::produce
+    /// [`wakeup`]: Self::wakeup
+    /// [`needs_wakeup`]: Self::needs_wakeup
+    #[inline]
+    pub unsafe fn busy_poll_send(&mut self, descs: &[FrameDesc]) -> io::Result<usize> {
+        let cnt = unsafe { self.produce(&descs[..self.burst(descs.len())]) };
+
+        self.wakeup()?;
 
         Ok(cnt)
     }
 
+    /// Transmit `descs`, allocated from `pool`, for as long as the
+    /// kernel keeps making progress with them.
+    ///
+    /// Submits as many frames as there's room for on the ring, wakes
+    /// the kernel if needed and reaps completions from `cq` back into
+    /// `pool` using `scratch`, over and over until every frame is
+    /// submitted or a round neither submits nor reaps anything. Those
+    /// left over, past [`SentBatch::submitted`], are for the caller to
+    /// retry or free.
+    ///
+    /// # Errors
+    ///
+    /// If waking the kernel fails, stopping there. The error's
+    /// [`sent`](SendBatchError::sent) says how far things got: frames
+    /// put on the ring before the wakeup are counted as submitted, and
+    /// the kernel will send them on a later wakeup.
+    ///
+    /// # Safety
+    ///
+    /// See [`produce`] and [`FramePool::reap`]. `cq` must be the
+    /// completion queue of this queue's [`Umem`](crate::Umem).
+    ///
+    /// [`produce`]: Self::produce
+    pub unsafe fn send_batch(
+        &mut self,
+        pool: &FramePool,
+        cq: &mut CompQueue,
+        descs: &[FrameDesc],
+        scratch: &mut [FrameDesc],
+    ) -> Result<SentBatch, SendBatchError> {
+        let descs = &descs[..self.burst(descs.len())];
+
+        pool.send_batch_with(
+            descs,
+            scratch,
+            |descs| {
+                // SAFETY: guaranteed by this function's contract.
+                let cnt = unsafe { self.extend(descs) };
+                (cnt, self.commit_wakeup().err())
+            },
+            |scratch| unsafe { cq.consume(scratch) },
+        )
+    }
+
     /// Same as [`produce_and_wakeup`] but for a single frame
     /// descriptor.
     ///
@@ -137,6 +488,31 @@ impl TxQueue {
     pub unsafe fn produce_one_and_wakeup(&mut self, desc: &FrameDesc) -> io::Result<usize> {
         let cnt = unsafe { self.produce_one(desc) };
 
+        self.commit_wakeup()?;
+
+        Ok(cnt)
+    }
+
+    /// Wake up the kernel, if required, to process all frames
+    /// produced since the last commit. Returns the number of frames
+    /// that were awaiting a commit.
+    ///
+    /// This allows frames to be enqueued via [`produce`] from several
+    /// places and then kicked off together at a natural batching
+    /// boundary, paying for at most one syscall.
+    ///
+    /// In debug builds, producing more frames while earlier ones have
+    /// been left uncommitted for over a second will panic, to catch
+    /// code paths that forget to commit. Calling [`wakeup`] or
+    /// checking [`needs_wakeup`] directly also counts as a commit.
+    ///
+    /// [`produce`]: Self::produce
+    /// [`wakeup`]: Self::wakeup
+    /// [`needs_wakeup`]: Self::needs_wakeup
+    #[inline]
+    pub fn commit_wakeup(&mut self) -> io::Result<usize> {
+        let cnt = self.uncommitted();
+
         if self.needs_wakeup() {
             self.wakeup()?;
         }
@@ -144,6 +520,72 @@ impl TxQueue {
         Ok(cnt)
     }
 
+    /// The number of frames produced since the last commit, see
+    /// [`commit_wakeup`](Self::commit_wakeup).
+    #[inline]
+    pub fn uncommitted(&self) -> usize {
+        self.uncommitted.count.get()
+    }
+
+    /// The number of packets submitted which were longer than
+    /// [`max_frame_len`](Self::max_frame_len), and so will most likely
+    /// be dropped.
+    ///
+    /// Such packets are discarded by the kernel or driver without any
+    /// error reaching user space, so a warning is also logged the first
+    /// time one is seen.
+    #[inline]
+    pub fn oversized_count(&self) -> u64 {
+        self.oversize.count()
+    }
+
+    /// The longest packet, including its ethernet header, which the
+    /// interface is expected to send. Submissions are checked against
+    /// this, see [`oversized_count`](Self::oversized_count).
+    ///
+    /// Taken from the interface MTU when the socket is created,
+    /// allowing for an ethernet header and a single VLAN tag. [`None`]
+    /// if the MTU couldn't be read, in which case no check is made.
+    #[inline]
+    pub fn max_frame_len(&self) -> Option<usize> {
+        self.oversize.max_len()
+    }
+
+    /// Set the length submissions are checked against, e.g. after the
+    /// interface MTU changes. [`None`] disables the check.
+    #[inline]
+    pub fn set_max_frame_len(&mut self, max_frame_len: Option<usize>) {
+        self.oversize.set_max_len(max_frame_len);
+    }
+
+    /// The most frames [`produce_owned`], [`produce_and_wakeup`],
+    /// [`busy_poll_send`] and [`send_batch`] submit per call, taken
+    /// from the socket's
+    /// [`max_burst`](crate::config::SocketConfigBuilder::max_burst).
+    /// Only the first this many frames passed are considered, the rest
+    /// are left for the next call.
+    ///
+    /// [`produce_owned`]: Self::produce_owned
+    /// [`produce_and_wakeup`]: Self::produce_and_wakeup
+    /// [`busy_poll_send`]: Self::busy_poll_send
+    /// [`send_batch`]: Self::send_batch
+    #[inline]
+    pub fn max_burst(&self) -> Option<NonZeroUsize> {
+        self.max_burst
+    }
+
+    /// Change the [`max_burst`](Self::max_burst) of this queue.
+    #[inline]
+    pub fn set_max_burst(&mut self, max_burst: Option<NonZeroUsize>) {
+        self.max_burst = max_burst;
+    }
+
+    /// Packets and bytes submitted to the ring so far.
+    #[inline]
+    pub fn counters(&self) -> QueueCounters {
+        self.counters
+    }
+
     /// Wake up the kernel to continue processing produced frames.
     ///
     /// See [`produce_and_wakeup`] for a link to docs with further
@@ -170,6 +612,8 @@ impl TxQueue {
             }
         }
 
+        self.uncommitted.clear();
+
         Ok(())
     }
 
@@ -185,7 +629,56 @@ impl TxQueue {
     /// [`produce_and_wakeup`]: Self::produce_and_wakeup
     #[inline]
     pub fn needs_wakeup(&self) -> bool {
-        unsafe { libxdp_sys::xsk_ring_prod__needs_wakeup(self.ring.as_ref()) != 0 }
+        let needs_wakeup =
+            unsafe { libxdp_sys::xsk_ring_prod__needs_wakeup(self.ring.as_ref()) != 0 };
+
+        if !needs_wakeup {
+            // The kernel is already processing the ring so anything
+            // produced so far will be picked up without a kick.
+            self.uncommitted.clear();
+        }
+
+        needs_wakeup
+    }
+
+    /// Whether the socket was bound with [`XDP_USE_NEED_WAKEUP`], in
+    /// which case [`needs_wakeup`] returning `false` really does mean
+    /// the kernel is working through the ring without being kicked.
+    /// Otherwise it always returns `false`, regardless.
+    ///
+    /// [`XDP_USE_NEED_WAKEUP`]: crate::config::BindFlags::XDP_USE_NEED_WAKEUP
+    /// [`needs_wakeup`]: Self::needs_wakeup
+    #[inline]
+    pub fn uses_need_wakeup(&self) -> bool {
+        self.uses_need_wakeup
+    }
+
+    /// The number of descriptors the ring can hold.
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.ring.as_ref().size as usize
+    }
+
+    /// The number of free slots on the ring, refreshing the cached
+    /// consumer index only if fewer than `nb` are known to be free.
+    #[inline]
+    pub(crate) fn free_slots(&mut self, nb: u32) -> u32 {
+        unsafe { libxdp_sys::xsk_prod_nb_free(self.ring.as_mut(), nb) }
+    }
+
+    /// Identifies the [`Umem`](crate::Umem) the queue's socket was
+    /// created with.
+    #[inline]
+    pub(super) fn umem_id(&self) -> usize {
+        self.socket.umem_id
+    }
+
+    /// The number of entries on the ring the kernel has yet to
+    /// consume.
+    #[inline]
+    pub(crate) fn fill_level(&mut self) -> usize {
+        let size = self.capacity();
+        size - self.free_slots(size as u32) as usize
     }
 
     /// Polls the socket, returning `true` if it is ready to write.
@@ -194,6 +687,49 @@ impl TxQueue {
         self.socket.fd.poll_write(poll_timeout)
     }
 
+    /// Same as [`poll`](Self::poll), but also returns early if `wake`
+    /// is woken from another thread, see [`WakeFd`].
+    #[inline]
+    pub fn poll_or_wake(&mut self, wake: &WakeFd, poll_timeout: i32) -> io::Result<PollOutcome> {
+        wake::poll(
+            self.socket.fd.as_raw_fd(),
+            libc::POLLOUT,
+            wake,
+            poll_timeout,
+        )
+    }
+
+    /// Same as [`poll`](Self::poll), but also returns early, with
+    /// [`PollOutcome::is_woken`] set, once `cancel` is cancelled. Keeps
+    /// returning straight away from then on, see [`CancelToken`].
+    #[inline]
+    pub fn poll_or_cancel(
+        &mut self,
+        cancel: &CancelToken,
+        poll_timeout: i32,
+    ) -> io::Result<PollOutcome> {
+        wake::poll_or_cancel(
+            self.socket.fd.as_raw_fd(),
+            libc::POLLOUT,
+            cancel,
+            poll_timeout,
+        )
+    }
+
+    /// The mode the socket ended up bound in, e.g. to check that it
+    /// got zero-copy rather than quietly falling back to copying.
+    pub fn bind_mode(&self) -> io::Result<BindMode> {
+        self.socket.bind_mode()
+    }
+
+    /// The kernel's view of this queue's ring layout, for diagnosing
+    /// kernel or driver mismatches.
+    pub fn ring_geometry(&self) -> io::Result<RingGeometry> {
+        let offsets = self.socket.fd.mmap_offsets()?;
+
+        Ok(RingGeometry::tx(&offsets, self.ring.as_ref().size))
+    }
+
     /// A reference to the underlying [`Socket`]'s file descriptor.
     #[inline]
     pub fn fd(&self) -> &Fd {
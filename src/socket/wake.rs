@@ -0,0 +1,287 @@
+use libc::{c_void, EAGAIN, EFD_CLOEXEC, EFD_NONBLOCK, EINTR, POLLIN};
+use std::{
+    fmt, io, mem,
+    os::unix::prelude::{AsRawFd, RawFd},
+    sync::{Arc, Weak},
+};
+
+use crate::{cancel::CancelToken, util};
+
+struct EventFd(RawFd);
+
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// An eventfd for waking a thread blocked polling an [`RxQueue`] or
+/// [`TxQueue`] from elsewhere in the application, e.g. to pick up a
+/// config change or shut down.
+///
+/// Clones share the same eventfd and may be sent to other threads.
+/// Pass one to [`RxQueue::poll_or_wake`] or [`TxQueue::poll_or_wake`]
+/// and call [`wake`](Self::wake) on another, and the poll returns
+/// with [`PollOutcome::is_woken`] set.
+///
+/// Wakeups aren't counted: several made before the poll sees them
+/// are reported as one.
+///
+/// [`RxQueue`]: crate::RxQueue
+/// [`TxQueue`]: crate::TxQueue
+/// [`RxQueue::poll_or_wake`]: crate::RxQueue::poll_or_wake
+/// [`TxQueue::poll_or_wake`]: crate::TxQueue::poll_or_wake
+#[derive(Clone)]
+pub struct WakeFd {
+    inner: Arc<EventFd>,
+}
+
+impl WakeFd {
+    /// Create a new eventfd.
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, EFD_CLOEXEC | EFD_NONBLOCK) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            inner: Arc::new(EventFd(fd)),
+        })
+    }
+
+    /// Wake whoever is polling with this eventfd, or the next to poll
+    /// if nobody is right now.
+    pub fn wake(&self) -> io::Result<()> {
+        let val: u64 = 1;
+
+        let ret = unsafe {
+            libc::write(
+                self.inner.0,
+                &val as *const u64 as *const c_void,
+                mem::size_of::<u64>(),
+            )
+        };
+
+        // The counter's full, so a wakeup is already pending.
+        if ret < 0 && util::get_errno() != EAGAIN {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// A handle which doesn't keep the eventfd open.
+    pub(crate) fn downgrade(&self) -> WeakWakeFd {
+        WeakWakeFd(Arc::downgrade(&self.inner))
+    }
+
+    /// Reset the eventfd, returning whether a wakeup was pending.
+    fn clear(&self) -> io::Result<bool> {
+        let mut val: u64 = 0;
+
+        let ret = unsafe {
+            libc::read(
+                self.inner.0,
+                &mut val as *mut u64 as *mut c_void,
+                mem::size_of::<u64>(),
+            )
+        };
+
+        if ret < 0 {
+            return match util::get_errno() {
+                EAGAIN => Ok(false),
+                _ => Err(io::Error::last_os_error()),
+            };
+        }
+
+        Ok(val > 0)
+    }
+}
+
+/// A [`WakeFd`] held without keeping it open, for registries which
+/// outlive what they wake.
+pub(crate) struct WeakWakeFd(Weak<EventFd>);
+
+impl WeakWakeFd {
+    /// The eventfd, if any [`WakeFd`] clone of it is still alive.
+    pub(crate) fn upgrade(&self) -> Option<WakeFd> {
+        self.0.upgrade().map(|inner| WakeFd { inner })
+    }
+
+    /// Whether every [`WakeFd`] clone of it has been dropped.
+    pub(crate) fn is_dead(&self) -> bool {
+        self.0.strong_count() == 0
+    }
+}
+
+impl fmt::Debug for WakeFd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WakeFd").field("fd", &self.inner.0).finish()
+    }
+}
+
+impl AsRawFd for WakeFd {
+    /// The eventfd, for adding to an application's own poll set.
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.0
+    }
+}
+
+/// The outcome of [`RxQueue::poll_or_wake`] or
+/// [`TxQueue::poll_or_wake`].
+///
+/// Both may be set, if the socket became ready around the same time
+/// as the wakeup. Neither means the poll timed out.
+///
+/// [`RxQueue::poll_or_wake`]: crate::RxQueue::poll_or_wake
+/// [`TxQueue::poll_or_wake`]: crate::TxQueue::poll_or_wake
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PollOutcome {
+    ready: bool,
+    woken: bool,
+}
+
+impl PollOutcome {
+    /// Whether the socket is ready to read or write, as per
+    /// [`RxQueue::poll`](crate::RxQueue::poll) and
+    /// [`TxQueue::poll`](crate::TxQueue::poll).
+    #[inline]
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    /// Whether [`WakeFd::wake`] was called, or for the
+    /// `poll_or_cancel` methods whether the [`CancelToken`] is
+    /// cancelled.
+    #[inline]
+    pub fn is_woken(&self) -> bool {
+        self.woken
+    }
+
+    /// Whether the poll timed out with nothing to report.
+    #[inline]
+    pub fn is_timeout(&self) -> bool {
+        !self.ready && !self.woken
+    }
+}
+
+/// Poll `fd` for `events` alongside `wake`, clearing `wake` if it
+/// fired.
+pub(super) fn poll(
+    fd: RawFd,
+    events: i16,
+    wake: &WakeFd,
+    timeout_ms: i32,
+) -> io::Result<PollOutcome> {
+    let (ready, woken) = poll_with(fd, events, wake.as_raw_fd(), timeout_ms)?;
+
+    Ok(PollOutcome {
+        ready,
+        woken: woken && wake.clear()?,
+    })
+}
+
+/// Poll `fd` for `events` alongside `cancel`, whose eventfd is never
+/// cleared.
+pub(super) fn poll_or_cancel(
+    fd: RawFd,
+    events: i16,
+    cancel: &CancelToken,
+    timeout_ms: i32,
+) -> io::Result<PollOutcome> {
+    let (ready, woken) = poll_with(fd, events, cancel.as_raw_fd(), timeout_ms)?;
+
+    Ok(PollOutcome { ready, woken })
+}
+
+/// Poll `fd` for `events` and `other` for readability, returning
+/// which fired.
+fn poll_with(fd: RawFd, events: i16, other: RawFd, timeout_ms: i32) -> io::Result<(bool, bool)> {
+    let mut fds = [
+        libc::pollfd {
+            fd,
+            events,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: other,
+            events: POLLIN,
+            revents: 0,
+        },
+    ];
+
+    let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+
+    if ret < 0 {
+        return match util::get_errno() {
+            EINTR => Ok((false, false)),
+            _ => Err(io::Error::last_os_error()),
+        };
+    }
+
+    Ok((fds[0].revents & events != 0, fds[1].revents & POLLIN != 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wakeups_are_reported_once_and_alongside_readiness() {
+        let wake = WakeFd::new().unwrap();
+        let other = wake.clone();
+
+        let mut pipe = [0; 2];
+        assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+
+        assert!(poll(pipe[0], POLLIN, &wake, 0).unwrap().is_timeout());
+
+        other.wake().unwrap();
+        other.wake().unwrap();
+
+        let outcome = poll(pipe[0], POLLIN, &wake, -1).unwrap();
+        assert!(outcome.is_woken() && !outcome.is_ready());
+
+        assert!(poll(pipe[0], POLLIN, &wake, 0).unwrap().is_timeout());
+
+        assert_eq!(
+            unsafe { libc::write(pipe[1], [1u8].as_ptr() as *const c_void, 1) },
+            1
+        );
+        other.wake().unwrap();
+
+        let outcome = poll(pipe[0], POLLIN, &wake, -1).unwrap();
+        assert!(outcome.is_woken() && outcome.is_ready());
+
+        unsafe {
+            libc::close(pipe[0]);
+            libc::close(pipe[1]);
+        }
+    }
+
+    #[test]
+    fn cancelled_tokens_keep_polls_returning() {
+        let cancel = CancelToken::new().unwrap();
+
+        let mut pipe = [0; 2];
+        assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+
+        assert!(poll_or_cancel(pipe[0], POLLIN, &cancel, 0)
+            .unwrap()
+            .is_timeout());
+
+        cancel.cancel();
+
+        for _ in 0..2 {
+            let outcome = poll_or_cancel(pipe[0], POLLIN, &cancel, -1).unwrap();
+            assert!(outcome.is_woken() && !outcome.is_ready());
+        }
+
+        unsafe {
+            libc::close(pipe[0]);
+            libc::close(pipe[1]);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Detecting and recovering from the XDP program on a socket's
+//! interface being replaced from elsewhere.
+
+use std::{borrow::Borrow, error::Error, fmt, io};
+
+use crate::config::XdpFlags;
+
+use super::Socket;
+
+/// The XDP program a [`Socket`] was bound alongside.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct XdpProgState {
+    pub ifindex: u32,
+    pub xdp_flags: XdpFlags,
+    pub prog_id: u32,
+}
+
+impl XdpProgState {
+    /// Record the program currently attached to `ifindex`.
+    ///
+    /// A failed query records no program rather than failing, since a
+    /// socket is perfectly usable without the watcher. The first poll
+    /// will then report whatever is attached as [`XdpProgEvent::Attached`].
+    pub fn new(ifindex: u32, xdp_flags: XdpFlags) -> Self {
+        Self {
+            ifindex,
+            xdp_flags,
+            prog_id: query_prog_id(ifindex, xdp_flags).unwrap_or(0),
+        }
+    }
+}
+
+/// The id of the XDP program attached to `ifindex` in the mode given
+/// by `xdp_flags`, or `0` if there is none.
+pub(super) fn query_prog_id(ifindex: u32, xdp_flags: XdpFlags) -> io::Result<u32> {
+    let mode = xdp_flags
+        & (XdpFlags::XDP_FLAGS_SKB_MODE
+            | XdpFlags::XDP_FLAGS_DRV_MODE
+            | XdpFlags::XDP_FLAGS_HW_MODE);
+
+    let mut prog_id = 0;
+
+    let err =
+        unsafe { libxdp_sys::bpf_xdp_query_id(ifindex as i32, mode.bits() as i32, &mut prog_id) };
+
+    if err != 0 {
+        return Err(io::Error::from_raw_os_error(-err));
+    }
+
+    Ok(prog_id)
+}
+
+/// A change to the XDP program attached to a [`Socket`]'s interface
+/// since the socket was bound, or last rebound.
+///
+/// Any of these mean packets are likely no longer being redirected to
+/// the socket, since the socket's entry lives in the XSKMAP of the
+/// original program. See [`RxQueue::rebind`](crate::RxQueue::rebind).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XdpProgEvent {
+    /// A program was attached where there previously was none.
+    Attached {
+        /// The id of the newly attached program.
+        new_prog_id: u32,
+    },
+    /// The program was replaced by a different one.
+    Replaced {
+        /// The id of the program the socket was bound alongside.
+        old_prog_id: u32,
+        /// The id of the program now attached.
+        new_prog_id: u32,
+    },
+    /// The program was detached and nothing took its place.
+    Detached {
+        /// The id of the program the socket was bound alongside.
+        old_prog_id: u32,
+    },
+}
+
+/// Polls the XDP program attached to a [`Socket`]'s interface,
+/// reporting when it no longer matches the one the socket was bound
+/// alongside.
+///
+/// Uses netlink under the hood, so is far too slow for the data path
+/// but fine to run every second or so from a housekeeping thread.
+#[derive(Debug, Clone)]
+pub struct XdpProgWatcher {
+    socket: Socket,
+}
+
+impl XdpProgWatcher {
+    pub(super) fn new(socket: Socket) -> Self {
+        Self { socket }
+    }
+
+    /// The index of the interface being watched.
+    pub fn ifindex(&self) -> u32 {
+        self.socket.xdp_prog_state().ifindex
+    }
+
+    /// The id of the program the socket is expected to be running
+    /// alongside, or `0` if none.
+    pub fn expected_prog_id(&self) -> u32 {
+        self.socket.xdp_prog_state().prog_id
+    }
+
+    /// Check the currently attached program, returning [`None`] if it
+    /// is still the expected one.
+    ///
+    /// An event will keep being reported on every poll until the
+    /// socket is rebound, or [`accept`](Self::accept) is called.
+    pub fn poll(&self) -> io::Result<Option<XdpProgEvent>> {
+        let state = self.socket.xdp_prog_state();
+        let current = query_prog_id(state.ifindex, state.xdp_flags)?;
+
+        let event = match (state.prog_id, current) {
+            (old, new) if old == new => None,
+            (0, new_prog_id) => Some(XdpProgEvent::Attached { new_prog_id }),
+            (old_prog_id, 0) => Some(XdpProgEvent::Detached { old_prog_id }),
+            (old_prog_id, new_prog_id) => Some(XdpProgEvent::Replaced {
+                old_prog_id,
+                new_prog_id,
+            }),
+        };
+
+        Ok(event)
+    }
+
+    /// Treat the currently attached program as the expected one
+    /// without rebinding, e.g. if the replacement is known to
+    /// redirect into an XSKMAP the socket has already been added to.
+    pub fn accept(&self) -> io::Result<()> {
+        let state = self.socket.xdp_prog_state();
+        let current = query_prog_id(state.ifindex, state.xdp_flags)?;
+
+        self.socket.set_xdp_prog_id(current);
+
+        Ok(())
+    }
+}
+
+/// Error detailing why rebinding a [`Socket`] to its interface's XDP
+/// program failed.
+#[derive(Debug)]
+pub struct RebindError {
+    reason: &'static str,
+    err: io::Error,
+}
+
+impl RebindError {
+    pub(super) fn new(reason: &'static str, err: io::Error) -> Self {
+        Self { reason, err }
+    }
+}
+
+impl fmt::Display for RebindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl Error for RebindError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.err.borrow())
+    }
+}
@@ -0,0 +1,298 @@
+//! Hooks for building reliable protocols, e.g. TCP via smoltcp or a
+//! custom stack, on top of a socket.
+//!
+//! A [`TxTracker`] follows frames from submission on the
+//! [`TxQueue`](crate::TxQueue) to their return on the
+//! [`CompQueue`](crate::CompQueue). It attaches a token of the stack's
+//! choosing to each frame sent, say a sequence number, and hands it
+//! back along with timestamps once the kernel is done with the frame.
+//! Frames holding data which may need retransmitting can be
+//! [pinned](TxTracker::pin), keeping them out of the pool of frames to
+//! recycle until the stack releases them.
+//!
+//! Timestamps are taken in software, on submission and when the
+//! completion is seen. Hardware TX timestamps need the kernel's TX
+//! metadata support, which is enabled per UMEM and not yet exposed by
+//! [`UmemConfig`](crate::UmemConfig).
+
+use std::time::{Duration, Instant};
+
+use crate::umem::{frame::FrameDesc, MetaTable, Umem};
+
+#[derive(Debug, Clone)]
+struct Slot<T> {
+    token: Option<T>,
+    submitted_at: Option<Instant>,
+    pinned: bool,
+}
+
+impl<T> Default for Slot<T> {
+    fn default() -> Self {
+        Self {
+            token: None,
+            submitted_at: None,
+            pinned: false,
+        }
+    }
+}
+
+/// A frame returned on the completion queue, as reported by
+/// [`TxTracker::complete`].
+#[derive(Debug, Clone)]
+pub struct Completion<T> {
+    desc: FrameDesc,
+    token: Option<T>,
+    submitted_at: Option<Instant>,
+    completed_at: Instant,
+    pinned: bool,
+}
+
+impl<T> Completion<T> {
+    /// The completed frame.
+    #[inline]
+    pub fn desc(&self) -> &FrameDesc {
+        &self.desc
+    }
+
+    /// The token the frame was [tracked](TxTracker::track) with, if
+    /// any.
+    #[inline]
+    pub fn token(&self) -> Option<&T> {
+        self.token.as_ref()
+    }
+
+    /// Take the token, leaving `None` in its place.
+    #[inline]
+    pub fn take_token(&mut self) -> Option<T> {
+        self.token.take()
+    }
+
+    /// When the frame was tracked, if it was.
+    #[inline]
+    pub fn submitted_at(&self) -> Option<Instant> {
+        self.submitted_at
+    }
+
+    /// When the completion was seen. Shared by all completions handled
+    /// in the same call to [`TxTracker::complete`].
+    #[inline]
+    pub fn completed_at(&self) -> Instant {
+        self.completed_at
+    }
+
+    /// Time from submission to completion, if the frame was tracked.
+    #[inline]
+    pub fn latency(&self) -> Option<Duration> {
+        self.submitted_at
+            .map(|t| self.completed_at.saturating_duration_since(t))
+    }
+
+    /// Whether the frame is pinned and so won't be recycled.
+    #[inline]
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+}
+
+/// Tracks frames through transmission, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct TxTracker<T> {
+    slots: MetaTable<Slot<T>>,
+    in_flight: usize,
+    pinned: usize,
+}
+
+impl<T> TxTracker<T> {
+    /// Create a tracker for frames of `umem`.
+    pub fn new(umem: &Umem) -> Self {
+        Self::from_table(MetaTable::new(umem, |_| Slot::default()))
+    }
+
+    fn from_table(slots: MetaTable<Slot<T>>) -> Self {
+        Self {
+            slots,
+            in_flight: 0,
+            pinned: 0,
+        }
+    }
+
+    /// Record that the frame described by `desc` is being submitted
+    /// for transmission, attaching `token` to it. Returns the previous
+    /// token if the frame's last completion wasn't handled.
+    ///
+    /// Call this as the frame is produced on the
+    /// [`TxQueue`](crate::TxQueue), including when resending a pinned
+    /// frame.
+    ///
+    /// # Panics
+    ///
+    /// If `desc` lies beyond the end of the tracker's [`Umem`].
+    #[inline]
+    pub fn track(&mut self, desc: &FrameDesc, token: T) -> Option<T> {
+        let slot = self.slots.meta(desc);
+
+        let prev = slot.token.replace(token);
+        slot.submitted_at = Some(Instant::now());
+
+        if prev.is_none() {
+            self.in_flight += 1;
+        }
+
+        prev
+    }
+
+    /// Pin the frame described by `desc`, so [`complete`] keeps it
+    /// back rather than returning it for recycling. Returns `false` if
+    /// it was already pinned.
+    ///
+    /// [`complete`]: Self::complete
+    #[inline]
+    pub fn pin(&mut self, desc: &FrameDesc) -> bool {
+        let slot = self.slots.meta(desc);
+
+        if slot.pinned {
+            return false;
+        }
+
+        slot.pinned = true;
+        self.pinned += 1;
+
+        true
+    }
+
+    /// Unpin the frame described by `desc`, e.g. once the data it
+    /// holds has been acknowledged. Returns `false` if it wasn't
+    /// pinned.
+    ///
+    /// A frame unpinned after its completion has been handled is not
+    /// returned by [`complete`](Self::complete) again, it's up to the
+    /// caller to recycle it.
+    #[inline]
+    pub fn unpin(&mut self, desc: &FrameDesc) -> bool {
+        let slot = self.slots.meta(desc);
+
+        if !slot.pinned {
+            return false;
+        }
+
+        slot.pinned = false;
+        self.pinned -= 1;
+
+        true
+    }
+
+    /// Whether the frame described by `desc` is pinned.
+    #[inline]
+    pub fn is_pinned(&self, desc: &FrameDesc) -> bool {
+        self.slots.get(desc).pinned
+    }
+
+    /// The number of tracked frames whose completion hasn't been
+    /// handled yet.
+    #[inline]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// The number of pinned frames.
+    #[inline]
+    pub fn pinned(&self) -> usize {
+        self.pinned
+    }
+
+    /// Handle the completions in `descs`, as filled by
+    /// [`CompQueue::consume`](crate::CompQueue::consume), calling
+    /// `on_complete` for each in order.
+    ///
+    /// Then reorders `descs` so that frames free to be recycled come
+    /// first, keeping pinned ones at the end, and returns the number
+    /// free.
+    pub fn complete<F>(&mut self, descs: &mut [FrameDesc], mut on_complete: F) -> usize
+    where
+        F: FnMut(Completion<T>),
+    {
+        let completed_at = Instant::now();
+        let mut free = 0;
+
+        for i in 0..descs.len() {
+            let desc = descs[i];
+            let slot = self.slots.meta(&desc);
+
+            let token = slot.token.take();
+            let pinned = slot.pinned;
+
+            if token.is_some() {
+                self.in_flight -= 1;
+            }
+
+            on_complete(Completion {
+                desc,
+                token,
+                submitted_at: slot.submitted_at.take(),
+                completed_at,
+                pinned,
+            });
+
+            if !pinned {
+                descs.swap(free, i);
+                free += 1;
+            }
+        }
+
+        free
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::umem::frame::fixtures::{desc, FRAME_SIZE};
+
+    use super::*;
+
+    fn tracker() -> TxTracker<u32> {
+        TxTracker::from_table(MetaTable::with_geometry(FRAME_SIZE, 4, |_| Slot::default()))
+    }
+
+    #[test]
+    fn completions_return_tokens_and_hold_back_pinned_frames() {
+        let mut tracker = tracker();
+
+        for (frame, seq) in [(0, 100), (1, 200), (2, 300)] {
+            assert_eq!(tracker.track(&desc(frame), seq), None);
+        }
+
+        assert!(tracker.pin(&desc(1)));
+        assert!(!tracker.pin(&desc(1)));
+        assert_eq!(tracker.in_flight(), 3);
+
+        let mut completed = [desc(0), desc(1), desc(2)];
+        let mut tokens = Vec::new();
+
+        let free = tracker.complete(&mut completed, |mut c| {
+            assert!(c.latency().is_some());
+            tokens.push((c.take_token(), c.is_pinned()));
+        });
+
+        assert_eq!(
+            tokens,
+            [(Some(100), false), (Some(200), true), (Some(300), false)]
+        );
+
+        assert_eq!(free, 2);
+        assert_eq!(completed[0].addr(), desc(0).addr());
+        assert_eq!(completed[1].addr(), desc(2).addr());
+        assert_eq!(completed[2].addr(), desc(1).addr());
+
+        assert_eq!(tracker.in_flight(), 0);
+        assert_eq!(tracker.pinned(), 1);
+
+        // Retransmit the pinned frame, then release it once acked.
+        tracker.track(&desc(1), 201);
+
+        let mut completed = [desc(1)];
+        assert_eq!(tracker.complete(&mut completed, |_| ()), 0);
+
+        assert!(tracker.unpin(&desc(1)));
+        assert_eq!(tracker.pinned(), 0);
+    }
+}
@@ -0,0 +1,529 @@
+//! Building blocks for collecting statistics.
+//!
+//! [`Histogram`] records a distribution of `u64` values, e.g. wait
+//! times or cycle counts, in a fixed amount of memory, with each value
+//! kept to within an eighth, and answers percentile queries.
+//!
+//! [`StatsTracker`] takes periodic [`StatsSnapshot`]s of a socket's
+//! packet counters, ring fill levels and kernel
+//! [`XdpStatistics`], and turns each pair into a [`StatsDelta`] with
+//! packet rates and the drop rate over the interval in between.
+//!
+//! With the `serde` feature, the snapshot and delta types, along with
+//! [`Histogram`], are [`Serialize`](serde::Serialize) for export.
+//!
+//! ```no_run
+//! # use std::{thread, time::Duration};
+//! # use xsk_rs::{stats::StatsTracker, CompQueue, FillQueue, RxQueue, TxQueue};
+//! # fn run(rx_q: &mut RxQueue, tx_q: &mut TxQueue, fq: &mut FillQueue, cq: &mut CompQueue) {
+//! let mut tracker = StatsTracker::new();
+//!
+//! loop {
+//!     if let Some(delta) = tracker.sample(rx_q, tx_q, fq, cq).unwrap() {
+//!         println!("{}", delta);
+//!     }
+//!
+//!     thread::sleep(Duration::from_secs(1));
+//! }
+//! # }
+//! ```
+
+use std::{
+    fmt, io,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    socket::{QueueCounters, XdpStatistics},
+    CompQueue, FillQueue, RxQueue, TxQueue,
+};
+
+// Each power of two range is split into `SUB_BUCKETS` buckets.
+const SUB_BITS: u32 = 3;
+const SUB_BUCKETS: usize = 1 << SUB_BITS;
+const BUCKETS: usize = (65 - SUB_BITS as usize) * SUB_BUCKETS;
+
+fn bucket(v: u64) -> usize {
+    if v < SUB_BUCKETS as u64 {
+        v as usize
+    } else {
+        let exp = 63 - v.leading_zeros();
+        let sub = (v >> (exp - SUB_BITS)) as usize & (SUB_BUCKETS - 1);
+
+        (exp - SUB_BITS + 1) as usize * SUB_BUCKETS + sub
+    }
+}
+
+/// The largest value falling in bucket `i`.
+fn bucket_max(i: usize) -> u64 {
+    if i < SUB_BUCKETS {
+        i as u64
+    } else {
+        let shift = (i / SUB_BUCKETS - 1) as u32;
+        let lower = ((SUB_BUCKETS + i % SUB_BUCKETS) as u64) << shift;
+
+        lower + ((1 << shift) - 1)
+    }
+}
+
+/// A log-linear histogram of `u64` values, see the
+/// [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Histogram {
+    buckets: Box<[u64]>,
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; BUCKETS].into_boxed_slice(),
+            count: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    /// An empty histogram.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record `value`.
+    #[inline]
+    pub fn record(&mut self, value: u64) {
+        self.buckets[bucket(value)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.max = self.max.max(value);
+    }
+
+    /// The value that a fraction `q` of recorded values are at or
+    /// under, e.g. `0.99` for the 99th percentile, to within an
+    /// eighth. Zero if nothing has been recorded.
+    pub fn percentile(&self, q: f64) -> u64 {
+        let rank = ((q.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+
+        for (i, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+
+            if seen >= rank {
+                return bucket_max(i).min(self.max);
+            }
+        }
+
+        0
+    }
+
+    /// The number of values recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The mean of the recorded values, rounded down.
+    pub fn mean(&self) -> u64 {
+        self.sum.checked_div(self.count).unwrap_or(0)
+    }
+
+    /// The largest value recorded.
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Add the values recorded in `other` to this histogram.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (a, b) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *a += b;
+        }
+
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        self.max = self.max.max(other.max);
+    }
+
+    /// Forget everything recorded.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// How many entries are on each of a socket's rings.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RingLevels {
+    rx: usize,
+    tx: usize,
+    fill: usize,
+    comp: usize,
+}
+
+impl RingLevels {
+    /// Received frames waiting to be consumed from the
+    /// [`RxQueue`].
+    pub fn rx(&self) -> usize {
+        self.rx
+    }
+
+    /// Frames on the [`TxQueue`] the kernel has yet to send.
+    pub fn tx(&self) -> usize {
+        self.tx
+    }
+
+    /// Frames on the [`FillQueue`] the kernel has yet to receive
+    /// into.
+    pub fn fill(&self) -> usize {
+        self.fill
+    }
+
+    /// Sent frames waiting to be consumed from the [`CompQueue`].
+    pub fn comp(&self) -> usize {
+        self.comp
+    }
+}
+
+/// A socket's counters at a point in time, see [`StatsTracker`].
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StatsSnapshot {
+    // Only meaningful within this process.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    at: Instant,
+    rx: QueueCounters,
+    tx: QueueCounters,
+    rings: RingLevels,
+    xdp: XdpStatistics,
+    rx_drops: u64,
+}
+
+impl StatsSnapshot {
+    /// Read the counters of the socket `rx_q` and `tx_q` belong to,
+    /// along with its [`Umem`](crate::Umem)'s `fq` and `cq`.
+    pub fn take(
+        rx_q: &mut RxQueue,
+        tx_q: &mut TxQueue,
+        fq: &mut FillQueue,
+        cq: &mut CompQueue,
+    ) -> io::Result<Self> {
+        let xdp = rx_q.fd().xdp_statistics()?;
+
+        Ok(Self {
+            at: Instant::now(),
+            rx: rx_q.counters(),
+            tx: tx_q.counters(),
+            rings: RingLevels {
+                rx: rx_q.fill_level(),
+                tx: tx_q.fill_level(),
+                fill: fq.fill_level(),
+                comp: cq.fill_level(),
+            },
+            xdp,
+            rx_drops: rx_drops(&xdp),
+        })
+    }
+
+    /// When the snapshot was taken.
+    pub fn at(&self) -> Instant {
+        self.at
+    }
+
+    /// Packets and bytes consumed from the [`RxQueue`] so far.
+    pub fn rx(&self) -> QueueCounters {
+        self.rx
+    }
+
+    /// Packets and bytes submitted to the [`TxQueue`] so far.
+    pub fn tx(&self) -> QueueCounters {
+        self.tx
+    }
+
+    /// How many entries were on each ring.
+    pub fn rings(&self) -> RingLevels {
+        self.rings
+    }
+
+    /// The kernel's statistics for the socket.
+    pub fn xdp(&self) -> &XdpStatistics {
+        &self.xdp
+    }
+
+    /// Received packets the kernel dropped so far, whether for an
+    /// invalid descriptor, a full rx ring or any other reason.
+    pub fn rx_drops(&self) -> u64 {
+        self.rx_drops
+    }
+
+    /// The change in counters from `earlier` to this snapshot.
+    pub fn delta(&self, earlier: &StatsSnapshot) -> StatsDelta {
+        StatsDelta {
+            interval: self.at.saturating_duration_since(earlier.at),
+            rx_packets: self.rx.packets().saturating_sub(earlier.rx.packets()),
+            rx_bytes: self.rx.bytes().saturating_sub(earlier.rx.bytes()),
+            tx_packets: self.tx.packets().saturating_sub(earlier.tx.packets()),
+            tx_bytes: self.tx.bytes().saturating_sub(earlier.tx.bytes()),
+            rx_drops: self.rx_drops.saturating_sub(earlier.rx_drops),
+            rings: self.rings,
+        }
+    }
+}
+
+fn rx_drops(xdp: &XdpStatistics) -> u64 {
+    xdp.rx_dropped() + xdp.rx_invalid_descs() + xdp.rx_ring_full().unwrap_or(0)
+}
+
+/// The change in a socket's counters between two
+/// [`StatsSnapshot`]s.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StatsDelta {
+    interval: Duration,
+    rx_packets: u64,
+    rx_bytes: u64,
+    tx_packets: u64,
+    tx_bytes: u64,
+    rx_drops: u64,
+    rings: RingLevels,
+}
+
+impl StatsDelta {
+    /// The time between the two snapshots.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Packets received in the interval.
+    pub fn rx_packets(&self) -> u64 {
+        self.rx_packets
+    }
+
+    /// Bytes received in the interval.
+    pub fn rx_bytes(&self) -> u64 {
+        self.rx_bytes
+    }
+
+    /// Packets sent in the interval.
+    pub fn tx_packets(&self) -> u64 {
+        self.tx_packets
+    }
+
+    /// Bytes sent in the interval.
+    pub fn tx_bytes(&self) -> u64 {
+        self.tx_bytes
+    }
+
+    /// Received packets the kernel dropped in the interval.
+    pub fn rx_drops(&self) -> u64 {
+        self.rx_drops
+    }
+
+    /// Ring fill levels as of the later snapshot.
+    pub fn rings(&self) -> RingLevels {
+        self.rings
+    }
+
+    /// Packets received per second. Zero if the interval was empty.
+    pub fn rx_pps(&self) -> f64 {
+        self.per_sec(self.rx_packets)
+    }
+
+    /// Packets sent per second. Zero if the interval was empty.
+    pub fn tx_pps(&self) -> f64 {
+        self.per_sec(self.tx_packets)
+    }
+
+    /// The fraction of packets arriving at the socket in the interval
+    /// that the kernel dropped rather than delivered, between zero and
+    /// one.
+    pub fn drop_rate(&self) -> f64 {
+        let arrived = self.rx_packets + self.rx_drops;
+
+        if arrived == 0 {
+            0.0
+        } else {
+            self.rx_drops as f64 / arrived as f64
+        }
+    }
+
+    fn per_sec(&self, n: u64) -> f64 {
+        let secs = self.interval.as_secs_f64();
+
+        if secs == 0.0 {
+            0.0
+        } else {
+            n as f64 / secs
+        }
+    }
+}
+
+impl fmt::Display for StatsDelta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rx {:.0} pps, tx {:.0} pps, drops {:.2}%, rings rx {} tx {} fill {} comp {}",
+            self.rx_pps(),
+            self.tx_pps(),
+            self.drop_rate() * 100.0,
+            self.rings.rx,
+            self.rings.tx,
+            self.rings.fill,
+            self.rings.comp
+        )
+    }
+}
+
+/// Keeps the last [`StatsSnapshot`] so each new one can be turned
+/// into a [`StatsDelta`], see the [module docs](self).
+#[derive(Debug, Default, Clone)]
+pub struct StatsTracker {
+    last: Option<StatsSnapshot>,
+}
+
+impl StatsTracker {
+    /// A tracker with no snapshot yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a snapshot, see [`StatsSnapshot::take`], and
+    /// [`update`](Self::update) with it.
+    pub fn sample(
+        &mut self,
+        rx_q: &mut RxQueue,
+        tx_q: &mut TxQueue,
+        fq: &mut FillQueue,
+        cq: &mut CompQueue,
+    ) -> io::Result<Option<StatsDelta>> {
+        StatsSnapshot::take(rx_q, tx_q, fq, cq).map(|snapshot| self.update(snapshot))
+    }
+
+    /// Replace the last snapshot with `snapshot`, returning the change
+    /// since it. [`None`] the first time.
+    pub fn update(&mut self, snapshot: StatsSnapshot) -> Option<StatsDelta> {
+        self.last
+            .replace(snapshot)
+            .map(|earlier| snapshot.delta(&earlier))
+    }
+
+    /// The last snapshot taken, if any.
+    pub fn last(&self) -> Option<&StatsSnapshot> {
+        self.last.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn snapshots_and_deltas_are_serializable() {
+        fn serializable<T: serde::Serialize>() {}
+
+        serializable::<Histogram>();
+        serializable::<RingLevels>();
+        serializable::<StatsSnapshot>();
+        serializable::<StatsDelta>();
+    }
+
+    #[test]
+    fn buckets_cover_every_value_in_order() {
+        for v in (0..4096).chain([u64::MAX / 3, u64::MAX]) {
+            let i = bucket(v);
+
+            assert!(i < BUCKETS);
+            assert!(v <= bucket_max(i), "{}", v);
+            assert!(i == 0 || v > bucket_max(i - 1), "{}", v);
+        }
+    }
+
+    #[test]
+    fn percentiles_are_within_an_eighth() {
+        let mut hist = Histogram::new();
+
+        for v in 1..=1000 {
+            hist.record(v);
+        }
+
+        for (q, exact) in [(0.5, 500), (0.9, 900), (0.99, 990)] {
+            let p = hist.percentile(q);
+            assert!(p >= exact && p <= exact + exact / 8, "{}: {}", q, p);
+        }
+
+        assert_eq!(hist.percentile(1.0), 1000);
+        assert_eq!(hist.mean(), 500);
+
+        let mut merged = Histogram::new();
+        merged.record(5000);
+        merged.merge(&hist);
+
+        assert_eq!(merged.count(), 1001);
+        assert_eq!(merged.max(), 5000);
+    }
+
+    fn snapshot(at: Instant, rx_drops: u64, fill: usize) -> StatsSnapshot {
+        StatsSnapshot {
+            at,
+            rx: QueueCounters::default(),
+            tx: QueueCounters::default(),
+            rings: RingLevels {
+                fill,
+                ..RingLevels::default()
+            },
+            xdp: XdpStatistics::default(),
+            rx_drops,
+        }
+    }
+
+    #[test]
+    fn tracker_reports_deltas_between_snapshots() {
+        let start = Instant::now();
+        let mut tracker = StatsTracker::new();
+
+        assert!(tracker.update(snapshot(start, 10, 64)).is_none());
+
+        let delta = tracker
+            .update(snapshot(start + Duration::from_secs(2), 16, 32))
+            .unwrap();
+
+        assert_eq!(delta.interval(), Duration::from_secs(2));
+        assert_eq!(delta.rx_drops(), 6);
+        assert_eq!(delta.rings().fill(), 32);
+        assert_eq!(tracker.last().unwrap().rx_drops(), 16);
+    }
+
+    #[test]
+    fn delta_rates_are_per_second_and_per_arrival() {
+        let delta = StatsDelta {
+            interval: Duration::from_millis(500),
+            rx_packets: 300,
+            rx_bytes: 0,
+            tx_packets: 50,
+            tx_bytes: 0,
+            rx_drops: 100,
+            rings: RingLevels::default(),
+        };
+
+        assert_eq!(delta.rx_pps(), 600.0);
+        assert_eq!(delta.tx_pps(), 100.0);
+        assert_eq!(delta.drop_rate(), 0.25);
+        assert_eq!(
+            delta.to_string(),
+            "rx 600 pps, tx 100 pps, drops 25.00%, rings rx 0 tx 0 fill 0 comp 0"
+        );
+
+        let empty = StatsDelta {
+            interval: Duration::ZERO,
+            rx_drops: 0,
+            rx_packets: 0,
+            ..delta
+        };
+
+        assert_eq!(empty.rx_pps(), 0.0);
+        assert_eq!(empty.drop_rate(), 0.0);
+    }
+}
@@ -0,0 +1,392 @@
+//! Spotting traffic that shouldn't have reached a socket.
+//!
+//! When NIC steering rules or the XDP program bound to a queue don't
+//! do what was intended, the socket usually still receives plenty of
+//! frames, just not the right ones. A [`SteeringCheck`] samples
+//! received frames, matches them against an [`ExpectedTraffic`]
+//! description and counts those which don't fit, and why:
+//!
+//! ```
+//! use std::num::NonZeroU32;
+//! use xsk_rs::{
+//!     addr::MacAddr,
+//!     steering::{ExpectedTraffic, SteeringCheck},
+//! };
+//! # let frames: Vec<Vec<u8>> = Vec::new();
+//!
+//! let mut expected = ExpectedTraffic::new();
+//! expected
+//!     .dst_mac(MacAddr::new(0x02, 0, 0, 0, 0, 1))
+//!     .vlan(100)
+//!     .dst_port(4789);
+//!
+//! // Look at one frame in 64.
+//! let mut check = SteeringCheck::new(expected, NonZeroU32::new(64).unwrap());
+//!
+//! for frame in &frames {
+//!     check.observe(frame);
+//! }
+//!
+//! let report = check.report();
+//!
+//! if report.unexpected_fraction() > 0.01 {
+//!     eprintln!("steering looks wrong: {}", report);
+//! }
+//! ```
+//!
+//! Each criterion left unset matches anything. A frame is checked
+//! against the destination MAC first, then the VLAN and then the port,
+//! and counted under the first it fails.
+
+use std::{fmt, num::NonZeroU32};
+
+use crate::{
+    addr::{self, MacAddr},
+    packet::{self, ETH_HLEN, ETH_P_8021AD, ETH_P_8021Q, ETH_P_IPV4},
+    umem::{frame::FrameDesc, Umem},
+};
+
+/// Why a frame didn't match an [`ExpectedTraffic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The destination MAC isn't one of those expected, or the frame
+    /// is too short to hold one.
+    DstMac,
+    /// The frame's outer VLAN ID, or lack of one, isn't expected.
+    Vlan,
+    /// The frame isn't TCP or UDP to one of the expected ports.
+    DstPort,
+}
+
+/// The traffic a socket is meant to receive, see the
+/// [module docs](self).
+#[derive(Debug, Default, Clone)]
+pub struct ExpectedTraffic {
+    dst_macs: Vec<MacAddr>,
+    vlans: Vec<Option<u16>>,
+    dst_ports: Vec<u16>,
+}
+
+impl ExpectedTraffic {
+    /// A description matching any frame.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expect frames sent to `mac`. May be called several times to
+    /// expect any of several addresses.
+    pub fn dst_mac(&mut self, mac: impl Into<MacAddr>) -> &mut Self {
+        self.dst_macs.push(mac.into());
+        self
+    }
+
+    /// Expect frames whose outer VLAN tag has ID `id`. May be called
+    /// several times, and combined with [`untagged`](Self::untagged).
+    pub fn vlan(&mut self, id: u16) -> &mut Self {
+        self.vlans.push(Some(id & 0x0fff));
+        self
+    }
+
+    /// Expect frames without VLAN tags.
+    pub fn untagged(&mut self) -> &mut Self {
+        self.vlans.push(None);
+        self
+    }
+
+    /// Expect TCP or UDP frames to port `port`. May be called several
+    /// times.
+    ///
+    /// Non-initial IPv4 fragments carry no ports, so aren't held
+    /// against this.
+    pub fn dst_port(&mut self, port: u16) -> &mut Self {
+        self.dst_ports.push(port);
+        self
+    }
+
+    /// Check `frame` against the description.
+    pub fn check(&self, frame: &[u8]) -> Result<(), Mismatch> {
+        let dst = addr::read_mac(frame, 0).ok_or(Mismatch::DstMac)?;
+
+        if !self.dst_macs.is_empty() && !self.dst_macs.contains(&dst) {
+            return Err(Mismatch::DstMac);
+        }
+
+        if !self.vlans.is_empty() && !self.vlans.contains(&outer_vlan(frame)) {
+            return Err(Mismatch::Vlan);
+        }
+
+        if !self.dst_ports.is_empty() {
+            match dst_port(frame) {
+                Some(None) => (),
+                Some(Some(port)) if self.dst_ports.contains(&port) => (),
+                _ => return Err(Mismatch::DstPort),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The ID of `frame`'s outer VLAN tag, if it has one.
+fn outer_vlan(frame: &[u8]) -> Option<u16> {
+    match packet::read_u16(frame, 12)? {
+        ETH_P_8021Q | ETH_P_8021AD => packet::read_u16(frame, ETH_HLEN).map(|tci| tci & 0x0fff),
+        _ => None,
+    }
+}
+
+/// The TCP or UDP destination port of `frame`, `Some(None)` for a
+/// non-initial IPv4 fragment, or `None` if it's neither.
+fn dst_port(frame: &[u8]) -> Option<Option<u16>> {
+    let (ethertype, l3) = packet::l3(frame)?;
+    let (protocol, l4) = packet::l4(frame, ethertype, l3)?;
+
+    if ethertype == ETH_P_IPV4 && packet::ipv4_is_fragment(frame, l3)? {
+        return Some(None);
+    }
+
+    if !packet::has_ports(protocol) {
+        return None;
+    }
+
+    packet::read_u16(frame, l4 + 2).map(Some)
+}
+
+/// Samples received frames and counts those not matching an
+/// [`ExpectedTraffic`], see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct SteeringCheck {
+    expected: ExpectedTraffic,
+    sample_every: u32,
+    countdown: u32,
+    report: SteeringReport,
+}
+
+impl SteeringCheck {
+    /// Check one frame in every `sample_every` against `expected`.
+    pub fn new(expected: ExpectedTraffic, sample_every: NonZeroU32) -> Self {
+        Self {
+            expected,
+            sample_every: sample_every.get(),
+            countdown: 0,
+            report: SteeringReport::default(),
+        }
+    }
+
+    /// Note a received frame, checking it if it's due to be sampled.
+    ///
+    /// Every frame received should be passed in, so that one in every
+    /// `sample_every` is checked.
+    #[inline]
+    pub fn observe(&mut self, frame: &[u8]) {
+        if self.due() {
+            self.record(frame);
+        }
+    }
+
+    /// Note each frame of a batch received on an
+    /// [`RxQueue`](crate::RxQueue), as with [`observe`](Self::observe).
+    /// Only the sampled frames are read.
+    ///
+    /// # Safety
+    ///
+    /// See [`Umem::data`]. Every descriptor in `descs` must satisfy
+    /// its requirements.
+    pub unsafe fn observe_batch(&mut self, umem: &Umem, descs: &[FrameDesc]) {
+        for desc in descs {
+            if self.due() {
+                // SAFETY: guaranteed by this function's contract.
+                let data = unsafe { umem.data(desc) };
+                self.record(data.contents());
+            }
+        }
+    }
+
+    /// Count a frame, returning whether it's to be sampled.
+    #[inline]
+    fn due(&mut self) -> bool {
+        self.report.observed += 1;
+
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            false
+        } else {
+            self.countdown = self.sample_every - 1;
+            true
+        }
+    }
+
+    fn record(&mut self, frame: &[u8]) {
+        self.report.sampled += 1;
+
+        match self.expected.check(frame) {
+            Ok(()) => (),
+            Err(Mismatch::DstMac) => self.report.dst_mac += 1,
+            Err(Mismatch::Vlan) => self.report.vlan += 1,
+            Err(Mismatch::DstPort) => self.report.dst_port += 1,
+        }
+    }
+
+    /// The counts so far.
+    pub fn report(&self) -> SteeringReport {
+        self.report
+    }
+
+    /// Zero the counts, e.g. after reporting them for an interval.
+    pub fn reset(&mut self) {
+        self.report = SteeringReport::default();
+    }
+
+    /// The traffic frames are checked against.
+    pub fn expected(&self) -> &ExpectedTraffic {
+        &self.expected
+    }
+}
+
+/// Counts from a [`SteeringCheck`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SteeringReport {
+    observed: u64,
+    sampled: u64,
+    dst_mac: u64,
+    vlan: u64,
+    dst_port: u64,
+}
+
+impl SteeringReport {
+    /// The number of frames observed.
+    #[inline]
+    pub fn observed(&self) -> u64 {
+        self.observed
+    }
+
+    /// The number of frames checked.
+    #[inline]
+    pub fn sampled(&self) -> u64 {
+        self.sampled
+    }
+
+    /// The number of frames checked which didn't match.
+    #[inline]
+    pub fn unexpected(&self) -> u64 {
+        self.dst_mac + self.vlan + self.dst_port
+    }
+
+    /// The number of frames checked which didn't match for `reason`.
+    #[inline]
+    pub fn mismatches(&self, reason: Mismatch) -> u64 {
+        match reason {
+            Mismatch::DstMac => self.dst_mac,
+            Mismatch::Vlan => self.vlan,
+            Mismatch::DstPort => self.dst_port,
+        }
+    }
+
+    /// The fraction of frames checked which didn't match, zero if none
+    /// were checked.
+    pub fn unexpected_fraction(&self) -> f64 {
+        if self.sampled == 0 {
+            0.0
+        } else {
+            self.unexpected() as f64 / self.sampled as f64
+        }
+    }
+}
+
+impl fmt::Display for SteeringReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}% of {} sampled frames unexpected (dst mac: {}, vlan: {}, dst port: {})",
+            self.unexpected_fraction() * 100.0,
+            self.sampled,
+            self.dst_mac,
+            self.vlan,
+            self.dst_port
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::tests::udp4_frame;
+
+    // `udp4_frame` sends to aa:aa:aa:aa:aa:aa, with tags of VLAN 5.
+    const DST: MacAddr = MacAddr::new(0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa);
+
+    #[test]
+    fn frames_fail_on_the_first_mismatch() {
+        let mut expected = ExpectedTraffic::new();
+        assert_eq!(expected.check(&udp4_frame(0, 1000, 53)), Ok(()));
+        assert_eq!(expected.check(&[0; 5]), Err(Mismatch::DstMac));
+
+        expected.dst_mac(DST).vlan(5).dst_port(53);
+
+        assert_eq!(expected.check(&udp4_frame(1, 1000, 53)), Ok(()));
+        assert_eq!(
+            expected.check(&udp4_frame(0, 1000, 53)),
+            Err(Mismatch::Vlan)
+        );
+        assert_eq!(
+            expected.check(&udp4_frame(2, 1000, 54)),
+            Err(Mismatch::DstPort)
+        );
+
+        let mut frame = udp4_frame(0, 1000, 54);
+        frame[0] = 0x02;
+        assert_eq!(expected.check(&frame), Err(Mismatch::DstMac));
+
+        expected.untagged();
+        assert_eq!(expected.check(&udp4_frame(0, 1000, 53)), Ok(()));
+    }
+
+    #[test]
+    fn non_initial_fragments_pass_the_port_check() {
+        let mut expected = ExpectedTraffic::new();
+        expected.dst_port(53);
+
+        let mut frame = udp4_frame(0, 1000, 54);
+        assert_eq!(expected.check(&frame), Err(Mismatch::DstPort));
+
+        // A fragment offset of 8 bytes.
+        frame[ETH_HLEN + 7] = 1;
+        assert_eq!(expected.check(&frame), Ok(()));
+
+        // Not TCP or UDP.
+        frame[ETH_HLEN + 7] = 0;
+        frame[ETH_HLEN + 9] = packet::IPPROTO_ICMP;
+        assert_eq!(expected.check(&frame), Err(Mismatch::DstPort));
+    }
+
+    #[test]
+    fn one_in_n_frames_is_sampled() {
+        let mut expected = ExpectedTraffic::new();
+        expected.dst_port(53);
+
+        let mut check = SteeringCheck::new(expected, NonZeroU32::new(4).unwrap());
+
+        for i in 0..16 {
+            // Every other sampled frame goes to the wrong port.
+            let port = if i % 8 == 0 { 54 } else { 53 };
+            check.observe(&udp4_frame(0, 1000, port));
+        }
+
+        let report = check.report();
+
+        assert_eq!(report.observed(), 16);
+        assert_eq!(report.sampled(), 4);
+        assert_eq!(report.unexpected(), 2);
+        assert_eq!(report.mismatches(Mismatch::DstPort), 2);
+        assert_eq!(report.mismatches(Mismatch::Vlan), 0);
+        assert_eq!(report.unexpected_fraction(), 0.5);
+        assert_eq!(
+            report.to_string(),
+            "50.00% of 4 sampled frames unexpected (dst mac: 0, vlan: 0, dst port: 2)"
+        );
+
+        check.reset();
+        assert_eq!(check.report(), SteeringReport::default());
+        assert_eq!(check.report().unexpected_fraction(), 0.0);
+    }
+}
@@ -0,0 +1,853 @@
+//! Generating packet templates from a build script.
+//!
+//! A [`Template`] is a reference packet, either described layer by
+//! layer with a [`TemplateBuilder`] or taken from a pcap capture,
+//! together with the offsets of its interesting fields. Written out
+//! with [`write`](fn@write), it becomes a module holding the packet as a const
+//! byte array plus a typed getter and setter per field, so the
+//! dataplane can copy the template into a frame and patch only what
+//! changes:
+//!
+//! ```no_run
+//! // build.rs
+//! use std::{env, path::Path};
+//! use xsk_rs::template::Template;
+//!
+//! let probe = Template::builder()
+//!     .ether([0x02, 0, 0, 0, 0, 1], [0x02, 0, 0, 0, 0, 2])
+//!     .ipv4([10, 0, 0, 2].into(), [10, 0, 0, 1].into())
+//!     .udp(4000, 4001)
+//!     .payload(&[0; 32])
+//!     .finish();
+//!
+//! let out = Path::new(&env::var("OUT_DIR").unwrap()).join("templates.rs");
+//! xsk_rs::template::write(out, &[("probe", &probe)]).unwrap();
+//! ```
+//!
+//! and then in the crate itself:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/templates.rs"));
+//!
+//! let frame = &mut data.contents_mut()[..probe::LEN];
+//! frame.copy_from_slice(&probe::TEMPLATE);
+//! probe::set_dst_port(frame, 5000);
+//! ```
+//!
+//! The generated code depends on nothing but this crate, for the
+//! [`MacAddr`] of MAC fields. Setters don't touch checksums, patch
+//! those with [`checksum::update`] where needed. UDP checksums over
+//! IPv4 are left zero by the builder, i.e. unused.
+//!
+//! [`checksum::update`]: crate::checksum::update
+//! [`MacAddr`]: crate::addr::MacAddr
+
+use std::{
+    error::Error,
+    fmt::{self, Write as _},
+    fs, io,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+use crate::{
+    addr::{self, MacAddr},
+    checksum,
+    packet::{self, ETH_P_8021AD, ETH_P_8021Q, ETH_P_IPV4, ETH_P_IPV6, IPPROTO_TCP, IPPROTO_UDP},
+};
+
+/// How a field's bytes are read and written by the generated
+/// accessors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    /// A single byte, as a `u8`.
+    U8,
+    /// Two bytes in network order, as a `u16`.
+    U16,
+    /// Four bytes in network order, as a `u32`.
+    U32,
+    /// A MAC address, as an [`addr::MacAddr`].
+    Mac,
+    /// An IPv4 address, as a `[u8; 4]`.
+    Ipv4,
+    /// An IPv6 address, as a `[u8; 16]`.
+    Ipv6,
+    /// That many bytes, as a slice.
+    Bytes(usize),
+}
+
+impl FieldKind {
+    /// The number of bytes the field covers.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::U8 => 1,
+            Self::U16 => 2,
+            Self::U32 | Self::Ipv4 => 4,
+            Self::Mac => 6,
+            Self::Ipv6 => 16,
+            Self::Bytes(n) => *n,
+        }
+    }
+
+    /// Whether the field covers no bytes, only possible for
+    /// [`Bytes`](Self::Bytes).
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn value_type(&self) -> &'static str {
+        match self {
+            Self::U8 => "u8",
+            Self::U16 => "u16",
+            Self::U32 => "u32",
+            Self::Mac => "::xsk_rs::addr::MacAddr",
+            Self::Ipv4 => "[u8; 4]",
+            Self::Ipv6 => "[u8; 16]",
+            Self::Bytes(_) => "&[u8]",
+        }
+    }
+}
+
+/// A named field of a [`Template`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    name: String,
+    offset: usize,
+    kind: FieldKind,
+}
+
+impl Field {
+    /// The field's name, also used for its accessors.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The offset of the field's first byte in the packet.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// How the field is accessed.
+    pub fn kind(&self) -> FieldKind {
+        self.kind
+    }
+}
+
+/// A reference packet and its fields, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    bytes: Vec<u8>,
+    fields: Vec<Field>,
+}
+
+impl Template {
+    /// Start describing a packet layer by layer.
+    pub fn builder() -> TemplateBuilder {
+        TemplateBuilder::default()
+    }
+
+    /// Use `bytes` as the template, finding the Ethernet, VLAN, IP
+    /// and TCP or UDP header fields and the payload it contains.
+    /// Anything unrecognised is left to [`field`](Self::field).
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        let fields = discover(&bytes);
+        Self { bytes, fields }
+    }
+
+    /// Use the `index`th packet of the pcap capture at `path` as the
+    /// template, see [`from_bytes`](Self::from_bytes). Needs the
+    /// `pcap` feature.
+    #[cfg(feature = "pcap")]
+    pub fn from_pcap(path: impl AsRef<Path>, index: usize) -> io::Result<Self> {
+        use std::{fs::File, io::BufReader};
+
+        use crate::pcap::{PcapReader, LINKTYPE_ETHERNET};
+
+        let mut reader = PcapReader::new(BufReader::new(File::open(path)?))?;
+
+        if reader.link_type() != LINKTYPE_ETHERNET {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "capture isn't of ethernet frames",
+            ));
+        }
+
+        let mut packet = Vec::new();
+
+        for _ in 0..=index {
+            if reader.read_packet(&mut packet)?.is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "capture has too few packets",
+                ));
+            }
+        }
+
+        Ok(Self::from_bytes(packet))
+    }
+
+    /// The packet.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The fields found or added, in order of offset.
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Add a field, e.g. one inside the payload, replacing any found
+    /// field with the same name.
+    ///
+    /// # Errors
+    ///
+    /// If `name` can't be used for the generated accessors, i.e. isn't
+    /// a lowercase identifier, is a keyword or is `len` or `template`,
+    /// or the field doesn't fit inside the packet.
+    pub fn field(
+        &mut self,
+        name: &str,
+        offset: usize,
+        kind: FieldKind,
+    ) -> Result<&mut Self, FieldError> {
+        if !is_field_name(name) {
+            return Err(FieldError::Name(name.to_owned()));
+        }
+
+        if offset.saturating_add(kind.len()) > self.bytes.len() {
+            return Err(FieldError::OutOfBounds {
+                name: name.to_owned(),
+                packet_len: self.bytes.len(),
+            });
+        }
+
+        self.fields.retain(|f| f.name != name);
+        self.fields.push(Field {
+            name: name.to_owned(),
+            offset,
+            kind,
+        });
+        self.fields.sort_by_key(|f| f.offset);
+        Ok(self)
+    }
+
+    /// The source of a module `name` holding the template as `TEMPLATE`,
+    /// its length as `LEN`, and per field an offset constant, getter and
+    /// setter, or for [`Bytes`](FieldKind::Bytes) fields a getter and
+    /// `_mut` getter.
+    pub fn to_rust(&self, name: &str) -> String {
+        let mut out = String::new();
+
+        // Writing to a `String` can't fail.
+        let _ = self.write_rust(name, &mut out);
+        out
+    }
+
+    fn write_rust(&self, name: &str, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "/// Generated by `xsk_rs::template`.")?;
+        writeln!(out, "#[allow(dead_code)]")?;
+        writeln!(out, "pub mod {} {{", name)?;
+        writeln!(out, "    /// The template's length in bytes.")?;
+        writeln!(out, "    pub const LEN: usize = {};", self.bytes.len())?;
+        writeln!(out)?;
+        writeln!(out, "    /// The template packet.")?;
+        write!(out, "    pub const TEMPLATE: [u8; LEN] = [")?;
+
+        for (i, b) in self.bytes.iter().enumerate() {
+            if i % 12 == 0 {
+                write!(out, "\n       ")?;
+            }
+            write!(out, " {:#04x},", b)?;
+        }
+
+        writeln!(out, "\n    ];")?;
+
+        for field in &self.fields {
+            let (fname, at) = (&field.name, field.offset);
+            let end = at + field.kind.len();
+            let ty = field.kind.value_type();
+
+            writeln!(out)?;
+            writeln!(out, "    /// The offset of `{}`.", fname)?;
+            writeln!(
+                out,
+                "    pub const {}: usize = {};",
+                fname.to_uppercase(),
+                at
+            )?;
+            writeln!(out)?;
+
+            match field.kind {
+                FieldKind::Bytes(_) => {
+                    writeln!(out, "    #[inline]")?;
+                    writeln!(out, "    pub fn {}(frame: &[u8]) -> {} {{", fname, ty)?;
+                    writeln!(out, "        &frame[{}..{}]", at, end)?;
+                    writeln!(out, "    }}")?;
+                    writeln!(out)?;
+                    writeln!(out, "    #[inline]")?;
+                    writeln!(
+                        out,
+                        "    pub fn {}_mut(frame: &mut [u8]) -> &mut [u8] {{",
+                        fname
+                    )?;
+                    writeln!(out, "        &mut frame[{}..{}]", at, end)?;
+                    writeln!(out, "    }}")?;
+                }
+                FieldKind::U8 => {
+                    writeln!(out, "    #[inline]")?;
+                    writeln!(out, "    pub fn {}(frame: &[u8]) -> u8 {{", fname)?;
+                    writeln!(out, "        frame[{}]", at)?;
+                    writeln!(out, "    }}")?;
+                    writeln!(out)?;
+                    writeln!(out, "    #[inline]")?;
+                    writeln!(
+                        out,
+                        "    pub fn set_{}(frame: &mut [u8], value: u8) {{",
+                        fname
+                    )?;
+                    writeln!(out, "        frame[{}] = value;", at)?;
+                    writeln!(out, "    }}")?;
+                }
+                FieldKind::U16 | FieldKind::U32 => {
+                    writeln!(out, "    #[inline]")?;
+                    writeln!(out, "    pub fn {}(frame: &[u8]) -> {} {{", fname, ty)?;
+                    writeln!(out, "        let mut b = [0; {}];", field.kind.len())?;
+                    writeln!(out, "        b.copy_from_slice(&frame[{}..{}]);", at, end)?;
+                    writeln!(out, "        {}::from_be_bytes(b)", ty)?;
+                    writeln!(out, "    }}")?;
+                    writeln!(out)?;
+                    writeln!(out, "    #[inline]")?;
+                    writeln!(
+                        out,
+                        "    pub fn set_{}(frame: &mut [u8], value: {}) {{",
+                        fname, ty
+                    )?;
+                    writeln!(
+                        out,
+                        "        frame[{}..{}].copy_from_slice(&value.to_be_bytes());",
+                        at, end
+                    )?;
+                    writeln!(out, "    }}")?;
+                }
+                FieldKind::Mac => {
+                    writeln!(out, "    #[inline]")?;
+                    writeln!(out, "    pub fn {}(frame: &[u8]) -> {} {{", fname, ty)?;
+                    writeln!(out, "        let mut b = [0; 6];")?;
+                    writeln!(out, "        b.copy_from_slice(&frame[{}..{}]);", at, end)?;
+                    writeln!(out, "        {}::from(b)", ty)?;
+                    writeln!(out, "    }}")?;
+                    writeln!(out)?;
+                    writeln!(out, "    #[inline]")?;
+                    writeln!(
+                        out,
+                        "    pub fn set_{}(frame: &mut [u8], value: {}) {{",
+                        fname, ty
+                    )?;
+                    writeln!(
+                        out,
+                        "        frame[{}..{}].copy_from_slice(&value.octets());",
+                        at, end
+                    )?;
+                    writeln!(out, "    }}")?;
+                }
+                FieldKind::Ipv4 | FieldKind::Ipv6 => {
+                    writeln!(out, "    #[inline]")?;
+                    writeln!(out, "    pub fn {}(frame: &[u8]) -> {} {{", fname, ty)?;
+                    writeln!(out, "        let mut b = [0; {}];", field.kind.len())?;
+                    writeln!(out, "        b.copy_from_slice(&frame[{}..{}]);", at, end)?;
+                    writeln!(out, "        b")?;
+                    writeln!(out, "    }}")?;
+                    writeln!(out)?;
+                    writeln!(out, "    #[inline]")?;
+                    writeln!(
+                        out,
+                        "    pub fn set_{}(frame: &mut [u8], value: {}) {{",
+                        fname, ty
+                    )?;
+                    writeln!(
+                        out,
+                        "        frame[{}..{}].copy_from_slice(&value);",
+                        at, end
+                    )?;
+                    writeln!(out, "    }}")?;
+                }
+            }
+        }
+
+        writeln!(out, "}}")
+    }
+}
+
+/// Write the modules for `templates`, each given with its module
+/// name, to `path`, only touching the file if its contents changed so
+/// as not to trigger needless rebuilds.
+pub fn write(path: impl AsRef<Path>, templates: &[(&str, &Template)]) -> io::Result<()> {
+    let mut src = String::new();
+
+    for (name, template) in templates {
+        src.push_str(&template.to_rust(name));
+    }
+
+    let path = path.as_ref();
+
+    match fs::read_to_string(path) {
+        Ok(existing) if existing == src => Ok(()),
+        _ => fs::write(path, src),
+    }
+}
+
+/// Keywords, strict and reserved, which can't name a function.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Whether `name` is a lowercase identifier which the generated
+/// accessors and offset constant can be named after.
+fn is_field_name(name: &str) -> bool {
+    let ident = name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && name != "_"
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+    // The offset constants would clash with `LEN` and `TEMPLATE`.
+    ident && !KEYWORDS.contains(&name) && name != "len" && name != "template"
+}
+
+/// Error returned by [`Template::field`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldError {
+    /// The name can't be used for the generated accessors.
+    Name(String),
+    /// The field runs past the end of the packet.
+    OutOfBounds {
+        /// The field's name.
+        name: String,
+        /// The length of the packet.
+        packet_len: usize,
+    },
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Name(name) => write!(
+                f,
+                "field name {:?} isn't a lowercase identifier other than a keyword, \
+                 `len` or `template`",
+                name
+            ),
+            Self::OutOfBounds { name, packet_len } => write!(
+                f,
+                "field {:?} runs past the end of the {} byte packet",
+                name, packet_len
+            ),
+        }
+    }
+}
+
+impl Error for FieldError {}
+
+#[derive(Debug, Clone, Copy)]
+enum Ip {
+    V4(Ipv4Addr, Ipv4Addr),
+    V6(Ipv6Addr, Ipv6Addr),
+}
+
+/// Describes a packet layer by layer, à la scapy's
+/// `Ether()/IP()/UDP()/Raw()`, for [`Template::builder`].
+///
+/// Lengths, the IPv4 header checksum, TCP checksums and UDP ones
+/// over IPv6 are filled in by [`finish`](Self::finish). Layers left out
+/// are simply missing from the packet.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateBuilder {
+    ether: (MacAddr, MacAddr),
+    vlans: Vec<u16>,
+    ip: Option<Ip>,
+    ttl: Option<u8>,
+    ports: Option<(u8, u16, u16)>,
+    payload: Vec<u8>,
+}
+
+impl TemplateBuilder {
+    /// Set the destination and source MAC addresses, as [`MacAddr`]s
+    /// or their octets. Defaults to all zeroes.
+    pub fn ether(&mut self, dst: impl Into<MacAddr>, src: impl Into<MacAddr>) -> &mut Self {
+        self.ether = (dst.into(), src.into());
+        self
+    }
+
+    /// Add an 802.1Q tag with the given VLAN ID. A second call adds an
+    /// inner tag, making the first an 802.1ad outer one.
+    pub fn vlan(&mut self, id: u16) -> &mut Self {
+        self.vlans.push(id & 0x0fff);
+        self
+    }
+
+    /// Add an IPv4 header, with a TTL of 64 by default.
+    pub fn ipv4(&mut self, src: Ipv4Addr, dst: Ipv4Addr) -> &mut Self {
+        self.ip = Some(Ip::V4(src, dst));
+        self
+    }
+
+    /// Add an IPv6 header, with a hop limit of 64 by default.
+    pub fn ipv6(&mut self, src: Ipv6Addr, dst: Ipv6Addr) -> &mut Self {
+        self.ip = Some(Ip::V6(src, dst));
+        self
+    }
+
+    /// Set the IPv4 TTL or IPv6 hop limit.
+    pub fn ttl(&mut self, ttl: u8) -> &mut Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Add a UDP header.
+    pub fn udp(&mut self, src_port: u16, dst_port: u16) -> &mut Self {
+        self.ports = Some((IPPROTO_UDP, src_port, dst_port));
+        self
+    }
+
+    /// Add a TCP header with no options and only the ACK flag set.
+    pub fn tcp(&mut self, src_port: u16, dst_port: u16) -> &mut Self {
+        self.ports = Some((IPPROTO_TCP, src_port, dst_port));
+        self
+    }
+
+    /// Set the transport payload.
+    pub fn payload(&mut self, payload: &[u8]) -> &mut Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    /// Build the packet and find its fields, as
+    /// [`Template::from_bytes`] would.
+    pub fn finish(&self) -> Template {
+        let mut l4 = Vec::new();
+
+        if let Some((proto, sport, dport)) = self.ports {
+            l4.extend_from_slice(&sport.to_be_bytes());
+            l4.extend_from_slice(&dport.to_be_bytes());
+
+            if proto == IPPROTO_UDP {
+                let len = (8 + self.payload.len()) as u16;
+                l4.extend_from_slice(&len.to_be_bytes());
+                l4.extend_from_slice(&[0, 0]);
+            } else {
+                // Sequence and ack numbers, then a 5 word header with
+                // ACK set and a full window.
+                l4.extend_from_slice(&[0; 8]);
+                l4.extend_from_slice(&[0x50, 0x10, 0xff, 0xff, 0, 0, 0, 0]);
+            }
+        }
+
+        l4.extend_from_slice(&self.payload);
+
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&self.ether.0.octets());
+        frame.extend_from_slice(&self.ether.1.octets());
+
+        for (i, id) in self.vlans.iter().enumerate() {
+            let tpid = if i + 1 < self.vlans.len() {
+                ETH_P_8021AD
+            } else {
+                ETH_P_8021Q
+            };
+            frame.extend_from_slice(&tpid.to_be_bytes());
+            frame.extend_from_slice(&id.to_be_bytes());
+        }
+
+        let ttl = self.ttl.unwrap_or(64);
+        let proto = self.ports.map(|(p, _, _)| p).unwrap_or(0xfd);
+        let check_at = match proto {
+            IPPROTO_UDP => 6,
+            _ => 16,
+        };
+
+        match self.ip {
+            Some(Ip::V4(src, dst)) => {
+                frame.extend_from_slice(&ETH_P_IPV4.to_be_bytes());
+
+                let mut ip = [0u8; 20];
+                ip[0] = 0x45;
+                ip[2..4].copy_from_slice(&((20 + l4.len()) as u16).to_be_bytes());
+                // Don't fragment.
+                ip[6] = 0x40;
+                ip[8] = ttl;
+                ip[9] = proto;
+                addr::write_ipv4(&mut ip, 12, src);
+                addr::write_ipv4(&mut ip, 16, dst);
+
+                let check = checksum::ipv4_header(&ip);
+                ip[10..12].copy_from_slice(&check.to_be_bytes());
+
+                if proto == IPPROTO_TCP {
+                    let check = checksum::ipv4_l4(src, dst, proto, &l4);
+                    l4[check_at..check_at + 2].copy_from_slice(&check.to_be_bytes());
+                }
+
+                frame.extend_from_slice(&ip);
+            }
+            Some(Ip::V6(src, dst)) => {
+                frame.extend_from_slice(&ETH_P_IPV6.to_be_bytes());
+
+                let mut ip = [0u8; 40];
+                ip[0] = 0x60;
+                ip[4..6].copy_from_slice(&(l4.len() as u16).to_be_bytes());
+                ip[6] = proto;
+                ip[7] = ttl;
+                addr::write_ipv6(&mut ip, 8, src);
+                addr::write_ipv6(&mut ip, 24, dst);
+
+                if self.ports.is_some() {
+                    let check = checksum::ipv6_l4(src, dst, proto, &l4);
+                    l4[check_at..check_at + 2].copy_from_slice(&check.to_be_bytes());
+                }
+
+                frame.extend_from_slice(&ip);
+            }
+            None => {
+                // An experimental ethertype, so the frame is still
+                // well formed.
+                frame.extend_from_slice(&0x88b5u16.to_be_bytes());
+            }
+        }
+
+        frame.extend_from_slice(&l4);
+
+        Template::from_bytes(frame)
+    }
+}
+
+fn discover(frame: &[u8]) -> Vec<Field> {
+    let mut fields = Vec::new();
+    let mut add = |name: &str, offset: usize, kind: FieldKind| {
+        if offset + kind.len() <= frame.len() {
+            fields.push(Field {
+                name: name.to_owned(),
+                offset,
+                kind,
+            });
+        }
+    };
+
+    add("eth_dst", 0, FieldKind::Mac);
+    add("eth_src", 6, FieldKind::Mac);
+
+    let mut tags = Vec::new();
+    while tags.len() < 2 {
+        match packet::read_u16(frame, 12 + tags.len() * packet::VLAN_HLEN) {
+            Some(ETH_P_8021Q) | Some(ETH_P_8021AD) => {
+                tags.push(14 + tags.len() * packet::VLAN_HLEN)
+            }
+            _ => break,
+        }
+    }
+
+    // A single tag is the inner one, as far as naming goes.
+    let names = if tags.len() == 2 {
+        &["outer_vlan_tci", "vlan_tci"][..]
+    } else {
+        &["vlan_tci"][..]
+    };
+
+    for (name, at) in names.iter().zip(tags) {
+        add(name, at, FieldKind::U16);
+    }
+
+    let (ethertype, l3) = match packet::l3(frame) {
+        Some(l3) => l3,
+        None => return fields,
+    };
+
+    match ethertype {
+        ETH_P_IPV4 => {
+            add("ipv4_total_len", l3 + 2, FieldKind::U16);
+            add("ipv4_id", l3 + 4, FieldKind::U16);
+            add("ipv4_ttl", l3 + 8, FieldKind::U8);
+            add("ipv4_checksum", l3 + 10, FieldKind::U16);
+            add("ipv4_src", l3 + 12, FieldKind::Ipv4);
+            add("ipv4_dst", l3 + 16, FieldKind::Ipv4);
+        }
+        ETH_P_IPV6 => {
+            add("ipv6_payload_len", l3 + 4, FieldKind::U16);
+            add("ipv6_hop_limit", l3 + 7, FieldKind::U8);
+            add("ipv6_src", l3 + 8, FieldKind::Ipv6);
+            add("ipv6_dst", l3 + 24, FieldKind::Ipv6);
+        }
+        _ => return fields,
+    }
+
+    let (proto, l4) = match packet::l4(frame, ethertype, l3) {
+        Some(l4) => l4,
+        None => return fields,
+    };
+
+    if ethertype == ETH_P_IPV4 && packet::ipv4_is_fragment(frame, l3) != Some(false) {
+        return fields;
+    }
+
+    let payload = match proto {
+        IPPROTO_UDP => {
+            add("src_port", l4, FieldKind::U16);
+            add("dst_port", l4 + 2, FieldKind::U16);
+            add("udp_len", l4 + 4, FieldKind::U16);
+            add("udp_checksum", l4 + 6, FieldKind::U16);
+            l4 + 8
+        }
+        IPPROTO_TCP => {
+            add("src_port", l4, FieldKind::U16);
+            add("dst_port", l4 + 2, FieldKind::U16);
+            add("tcp_seq", l4 + 4, FieldKind::U32);
+            add("tcp_ack", l4 + 8, FieldKind::U32);
+            add("tcp_checksum", l4 + 16, FieldKind::U16);
+            match frame.get(l4 + 12) {
+                Some(off) => l4 + (*off >> 4) as usize * 4,
+                None => return fields,
+            }
+        }
+        _ => l4,
+    };
+
+    if payload <= frame.len() {
+        add("payload", payload, FieldKind::Bytes(frame.len() - payload));
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offset(template: &Template, name: &str) -> usize {
+        template
+            .fields()
+            .iter()
+            .find(|f| f.name() == name)
+            .unwrap_or_else(|| panic!("no field {}", name))
+            .offset()
+    }
+
+    #[test]
+    fn builder_lays_out_fields_and_fills_in_lengths_and_checksums() {
+        let template = Template::builder()
+            .ether([1; 6], [2; 6])
+            .vlan(100)
+            .ipv4([10, 0, 0, 1].into(), [10, 0, 0, 2].into())
+            .udp(1234, 53)
+            .payload(b"hello")
+            .finish();
+
+        let bytes = template.bytes();
+        assert_eq!(bytes.len(), 14 + 4 + 20 + 8 + 5);
+
+        assert_eq!(offset(&template, "vlan_tci"), 14);
+        assert_eq!(packet::read_u16(bytes, 14), Some(100));
+        assert_eq!(offset(&template, "ipv4_src"), 30);
+        assert_eq!(packet::read_u16(bytes, 20), Some(33));
+        assert_eq!(checksum::checksum(&bytes[18..38]), 0);
+        assert_eq!(offset(&template, "dst_port"), 40);
+        assert_eq!(packet::read_u16(bytes, 42), Some(13));
+        assert_eq!(&bytes[offset(&template, "payload")..], b"hello");
+
+        let template = Template::builder()
+            .ipv6("fe80::1".parse().unwrap(), "fe80::2".parse().unwrap())
+            .tcp(80, 8080)
+            .finish();
+
+        let bytes = template.bytes();
+        let l4 = offset(&template, "src_port");
+        assert_eq!(l4, 54);
+        assert_eq!(offset(&template, "tcp_checksum"), l4 + 16);
+        assert_eq!(offset(&template, "payload"), bytes.len());
+
+        let (src, dst) = (addr::read_ipv6(bytes, 22), addr::read_ipv6(bytes, 38));
+        assert_eq!(
+            packet::read_u16(bytes, l4 + 16),
+            Some(checksum::ipv6_l4(
+                src.unwrap(),
+                dst.unwrap(),
+                IPPROTO_TCP,
+                &bytes[l4..]
+            ))
+        );
+    }
+
+    #[test]
+    fn from_bytes_matches_double_tagged_frames() {
+        let frame = packet::tests::udp4_frame(2, 7, 9);
+        let template = Template::from_bytes(frame);
+
+        assert_eq!(offset(&template, "outer_vlan_tci"), 14);
+        assert_eq!(offset(&template, "vlan_tci"), 18);
+        assert_eq!(offset(&template, "src_port"), 14 + 8 + 20);
+        assert_eq!(packet::read_u16(template.bytes(), 14 + 8 + 22), Some(9));
+    }
+
+    #[test]
+    fn generates_accessors_for_every_field() {
+        let mut template = Template::builder()
+            .ether([1; 6], [2; 6])
+            .ipv4([10, 0, 0, 1].into(), [10, 0, 0, 2].into())
+            .udp(1, 2)
+            .payload(&[0; 8])
+            .finish();
+        template.field("seq", 42, FieldKind::U32).unwrap();
+
+        let src = template.to_rust("probe");
+
+        assert!(src.starts_with("/// Generated by `xsk_rs::template`."));
+        assert!(src.contains("pub mod probe {"));
+        assert!(src.contains("pub const LEN: usize = 50;"));
+        assert!(src.contains("pub const TEMPLATE: [u8; LEN] = [\n        0x01, 0x01,"));
+        assert!(src.contains("pub const DST_PORT: usize = 36;"));
+        assert!(src.contains("pub fn set_dst_port(frame: &mut [u8], value: u16) {"));
+        assert!(src.contains("pub fn ipv4_src(frame: &[u8]) -> [u8; 4] {"));
+        assert!(
+            src.contains("pub fn set_eth_dst(frame: &mut [u8], value: ::xsk_rs::addr::MacAddr) {")
+        );
+        assert!(src.contains("frame[0..6].copy_from_slice(&value.octets());"));
+        assert!(src.contains("pub fn set_ipv4_ttl(frame: &mut [u8], value: u8) {"));
+        assert!(src.contains("pub fn set_seq(frame: &mut [u8], value: u32) {"));
+        assert!(src.contains("frame[42..46].copy_from_slice(&value.to_be_bytes());"));
+        assert!(src.contains("pub fn payload_mut(frame: &mut [u8]) -> &mut [u8] {"));
+        assert!(src.ends_with("}\n"));
+
+        let offsets: Vec<_> = template.fields().iter().map(|f| f.offset()).collect();
+        let mut sorted = offsets.clone();
+        sorted.sort_unstable();
+        assert_eq!(offsets, sorted);
+    }
+
+    #[test]
+    fn fields_must_fit_and_be_usable_names() {
+        let mut template = Template::from_bytes(vec![0; 8]);
+
+        assert!(matches!(
+            template.field("x", 6, FieldKind::U32),
+            Err(FieldError::OutOfBounds { packet_len: 8, .. })
+        ));
+        assert!(matches!(
+            template.field("x", usize::MAX, FieldKind::U8),
+            Err(FieldError::OutOfBounds { .. })
+        ));
+
+        for name in [
+            "", "_", "Seq", "1st", "a-b", "type", "self", "len", "template",
+        ] {
+            assert_eq!(
+                template.field(name, 0, FieldKind::U8).unwrap_err(),
+                FieldError::Name(name.to_owned())
+            );
+        }
+
+        assert!(template.field("_type", 0, FieldKind::U8).is_ok());
+        assert!(template.fields().iter().any(|f| f.name() == "_type"));
+    }
+}
@@ -0,0 +1,9 @@
+//! Helpers for validating traffic in tests and benchmarks.
+//!
+//! Enabled with the `testutil` feature.
+
+pub mod soak;
+pub use soak::{FrameLedger, FrameOwner, LedgerError, Soak, SoakConfig};
+
+mod sequence;
+pub use sequence::{SequenceCheck, SequenceReport, SequenceStamper, SequenceVerifier, STAMP_LEN};
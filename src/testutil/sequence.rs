@@ -0,0 +1,428 @@
+//! Sequence numbering and verification of test traffic.
+
+use std::fmt;
+
+const MAGIC: [u8; 4] = *b"XSKQ";
+
+/// The number of bytes at the start of a payload taken up by a
+/// stamp.
+///
+/// A stamp is a 4 byte magic, an 8 byte big-endian sequence number and
+/// a 4 byte big-endian checksum, which covers the sequence number and
+/// everything in the payload after the stamp.
+pub const STAMP_LEN: usize = 16;
+
+// How many sequence numbers behind the highest seen are tracked for
+// duplicates.
+const WINDOW: u64 = 1 << 16;
+
+// 32 bit FNV-1a, plenty for catching bit flips and truncation.
+fn checksum(seq: &[u8], rest: &[u8]) -> u32 {
+    seq.iter().chain(rest).fold(0x811c_9dc5, |hash, b| {
+        (hash ^ u32::from(*b)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Writes increasing sequence numbers, along with a checksum, into
+/// outgoing payloads for a [`SequenceVerifier`] to check on the
+/// receive side.
+#[derive(Debug, Default, Clone)]
+pub struct SequenceStamper {
+    next: u64,
+}
+
+impl SequenceStamper {
+    /// Create a stamper starting from sequence number zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stamp `payload` with the next sequence number, returning it.
+    ///
+    /// The stamp occupies the first [`STAMP_LEN`] bytes and its
+    /// checksum covers the rest of the payload, so `payload` must not
+    /// be modified afterwards. Returns `None`, leaving `payload`
+    /// untouched, if it's too short to hold a stamp.
+    pub fn stamp(&mut self, payload: &mut [u8]) -> Option<u64> {
+        if payload.len() < STAMP_LEN {
+            return None;
+        }
+
+        let seq = self.next;
+        let seq_bytes = seq.to_be_bytes();
+
+        let (stamp, rest) = payload.split_at_mut(STAMP_LEN);
+
+        stamp[..4].copy_from_slice(&MAGIC);
+        stamp[4..12].copy_from_slice(&seq_bytes);
+        stamp[12..].copy_from_slice(&checksum(&seq_bytes, rest).to_be_bytes());
+
+        self.next += 1;
+
+        Some(seq)
+    }
+
+    /// The sequence number the next stamped payload will carry, which
+    /// is also the number stamped so far.
+    #[inline]
+    pub fn next_seq(&self) -> u64 {
+        self.next
+    }
+}
+
+/// The outcome of checking a single payload with
+/// [`SequenceVerifier::check`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceCheck {
+    /// Higher than any sequence number seen so far. Any numbers
+    /// skipped are presumed lost unless they turn up later.
+    InOrder {
+        /// The payload's sequence number.
+        seq: u64,
+    },
+    /// Lower than the highest sequence number seen so far, but not
+    /// seen before.
+    Reordered {
+        /// The payload's sequence number.
+        seq: u64,
+    },
+    /// A sequence number which has already been seen.
+    Duplicate {
+        /// The payload's sequence number.
+        seq: u64,
+    },
+    /// So far behind the highest sequence number seen that whether
+    /// it's a duplicate can no longer be told. Counted as reordered.
+    Stale {
+        /// The payload's sequence number.
+        seq: u64,
+    },
+    /// Carries a stamp but the checksum doesn't match, so the payload
+    /// was damaged in flight.
+    Corrupted,
+    /// Doesn't carry a stamp at all, e.g. unrelated traffic.
+    Unrecognised,
+}
+
+/// Checks payloads stamped by a [`SequenceStamper`] for loss,
+/// reordering, duplication and corruption.
+#[derive(Debug, Clone)]
+pub struct SequenceVerifier {
+    // Ring bitmap of the `WINDOW` sequence numbers up to `highest`.
+    seen: Vec<u64>,
+    highest: Option<u64>,
+    report: SequenceReport,
+}
+
+impl Default for SequenceVerifier {
+    fn default() -> Self {
+        Self {
+            seen: vec![0; (WINDOW / 64) as usize],
+            highest: None,
+            report: SequenceReport::default(),
+        }
+    }
+}
+
+impl SequenceVerifier {
+    /// Create a verifier which has seen nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn bit(seq: u64) -> (usize, u64) {
+        let idx = seq % WINDOW;
+        ((idx / 64) as usize, 1 << (idx % 64))
+    }
+
+    fn mark(&mut self, seq: u64) -> bool {
+        let (word, mask) = Self::bit(seq);
+        let already_seen = self.seen[word] & mask != 0;
+        self.seen[word] |= mask;
+        already_seen
+    }
+
+    fn advance(&mut self, seq: u64) {
+        let from = self.highest.map_or(0, |h| h + 1);
+
+        if seq - from >= WINDOW {
+            self.seen.iter_mut().for_each(|w| *w = 0);
+        } else {
+            for s in from..=seq {
+                let (word, mask) = Self::bit(s);
+                self.seen[word] &= !mask;
+            }
+        }
+
+        self.highest = Some(seq);
+    }
+
+    /// Check a received payload, recording the outcome in the
+    /// verifier's report.
+    pub fn check(&mut self, payload: &[u8]) -> SequenceCheck {
+        self.report.received += 1;
+
+        if payload.len() < STAMP_LEN || payload[..4] != MAGIC {
+            self.report.unrecognised += 1;
+            return SequenceCheck::Unrecognised;
+        }
+
+        let (stamp, rest) = payload.split_at(STAMP_LEN);
+
+        let mut seq_bytes = [0; 8];
+        seq_bytes.copy_from_slice(&stamp[4..12]);
+
+        let mut sum_bytes = [0; 4];
+        sum_bytes.copy_from_slice(&stamp[12..]);
+
+        if checksum(&seq_bytes, rest) != u32::from_be_bytes(sum_bytes) {
+            self.report.corrupted += 1;
+            return SequenceCheck::Corrupted;
+        }
+
+        let seq = u64::from_be_bytes(seq_bytes);
+
+        let check = match self.highest {
+            Some(highest) if seq <= highest => {
+                if highest - seq >= WINDOW {
+                    SequenceCheck::Stale { seq }
+                } else if self.mark(seq) {
+                    SequenceCheck::Duplicate { seq }
+                } else {
+                    SequenceCheck::Reordered { seq }
+                }
+            }
+            _ => {
+                self.advance(seq);
+                self.mark(seq);
+                SequenceCheck::InOrder { seq }
+            }
+        };
+
+        match check {
+            SequenceCheck::InOrder { .. } => self.report.unique += 1,
+            SequenceCheck::Reordered { .. } | SequenceCheck::Stale { .. } => {
+                self.report.unique += 1;
+                self.report.reordered += 1;
+            }
+            SequenceCheck::Duplicate { .. } => self.report.duplicated += 1,
+            SequenceCheck::Corrupted | SequenceCheck::Unrecognised => unreachable!(),
+        }
+
+        check
+    }
+
+    /// A report on everything checked so far.
+    ///
+    /// Loss is judged against the highest sequence number seen, so
+    /// frames missing from the tail of the stream aren't counted. Use
+    /// [`report_for`](Self::report_for) if the number sent is known.
+    pub fn report(&self) -> SequenceReport {
+        let mut report = self.report.clone();
+        report.expected = self.highest.map_or(0, |h| h + 1);
+        report
+    }
+
+    /// A report on everything checked so far, judging loss against
+    /// `sent` frames having been stamped, e.g. as given by
+    /// [`SequenceStamper::next_seq`].
+    pub fn report_for(&self, sent: u64) -> SequenceReport {
+        let mut report = self.report.clone();
+        report.expected = sent;
+        report
+    }
+}
+
+/// A summary of the payloads checked by a [`SequenceVerifier`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SequenceReport {
+    expected: u64,
+    received: u64,
+    unique: u64,
+    reordered: u64,
+    duplicated: u64,
+    corrupted: u64,
+    unrecognised: u64,
+}
+
+impl SequenceReport {
+    /// The number of frames the stream should have contained.
+    #[inline]
+    pub fn expected(&self) -> u64 {
+        self.expected
+    }
+
+    /// The total number of payloads checked, valid or not.
+    #[inline]
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// The number of distinct sequence numbers received intact.
+    #[inline]
+    pub fn unique(&self) -> u64 {
+        self.unique
+    }
+
+    /// The number of expected frames which never arrived intact.
+    #[inline]
+    pub fn lost(&self) -> u64 {
+        self.expected.saturating_sub(self.unique)
+    }
+
+    /// The number of frames which arrived after a higher sequence
+    /// number.
+    #[inline]
+    pub fn reordered(&self) -> u64 {
+        self.reordered
+    }
+
+    /// The number of repeat arrivals of an already seen sequence
+    /// number.
+    #[inline]
+    pub fn duplicated(&self) -> u64 {
+        self.duplicated
+    }
+
+    /// The number of stamped payloads whose checksum didn't match.
+    #[inline]
+    pub fn corrupted(&self) -> u64 {
+        self.corrupted
+    }
+
+    /// The number of payloads which carried no stamp.
+    #[inline]
+    pub fn unrecognised(&self) -> u64 {
+        self.unrecognised
+    }
+
+    /// Whether every expected frame arrived exactly once, in order and
+    /// intact. Unrecognised payloads are ignored.
+    pub fn is_clean(&self) -> bool {
+        self.lost() == 0 && self.reordered == 0 && self.duplicated == 0 && self.corrupted == 0
+    }
+}
+
+impl fmt::Display for SequenceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected {}, received {} ({} unique): {} lost, {} reordered, \
+             {} duplicated, {} corrupted, {} unrecognised",
+            self.expected,
+            self.received,
+            self.unique,
+            self.lost(),
+            self.reordered,
+            self.duplicated,
+            self.corrupted,
+            self.unrecognised
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamped(stamper: &mut SequenceStamper) -> Vec<u8> {
+        let mut payload = vec![0xab; 64];
+        stamper.stamp(&mut payload).unwrap();
+        payload
+    }
+
+    #[test]
+    fn clean_stream_is_reported_clean() {
+        let mut stamper = SequenceStamper::new();
+        let mut verifier = SequenceVerifier::new();
+
+        for seq in 0..10 {
+            let payload = stamped(&mut stamper);
+            assert_eq!(verifier.check(&payload), SequenceCheck::InOrder { seq });
+        }
+
+        let report = verifier.report_for(stamper.next_seq());
+
+        assert!(report.is_clean(), "{}", report);
+        assert_eq!(report.unique(), 10);
+    }
+
+    #[test]
+    fn loss_reordering_and_duplication_are_detected() {
+        let mut stamper = SequenceStamper::new();
+        let payloads: Vec<_> = (0..6).map(|_| stamped(&mut stamper)).collect();
+
+        let mut verifier = SequenceVerifier::new();
+
+        // 3 never arrives, 1 arrives late and then again.
+        for idx in [0, 2, 1, 1, 4, 5] {
+            verifier.check(&payloads[idx]);
+        }
+
+        let report = verifier.report_for(6);
+
+        assert_eq!(report.received(), 6);
+        assert_eq!(report.unique(), 5);
+        assert_eq!(report.lost(), 1);
+        assert_eq!(report.reordered(), 1);
+        assert_eq!(report.duplicated(), 1);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn tail_loss_is_only_seen_when_sent_count_is_known() {
+        let mut stamper = SequenceStamper::new();
+        let payloads: Vec<_> = (0..4).map(|_| stamped(&mut stamper)).collect();
+
+        let mut verifier = SequenceVerifier::new();
+        verifier.check(&payloads[0]);
+        verifier.check(&payloads[1]);
+
+        assert_eq!(verifier.report().lost(), 0);
+        assert_eq!(verifier.report_for(4).lost(), 2);
+    }
+
+    #[test]
+    fn damaged_payloads_are_corrupted_and_unstamped_are_unrecognised() {
+        let mut stamper = SequenceStamper::new();
+        let mut verifier = SequenceVerifier::new();
+
+        let mut payload = stamped(&mut stamper);
+        payload[40] ^= 1;
+
+        assert_eq!(verifier.check(&payload), SequenceCheck::Corrupted);
+        assert_eq!(verifier.check(&[0; 64]), SequenceCheck::Unrecognised);
+        assert_eq!(verifier.check(&MAGIC), SequenceCheck::Unrecognised);
+
+        let report = verifier.report_for(1);
+
+        assert_eq!(report.corrupted(), 1);
+        assert_eq!(report.unrecognised(), 2);
+        assert_eq!(report.lost(), 1);
+    }
+
+    #[test]
+    fn short_payloads_are_not_stamped() {
+        let mut stamper = SequenceStamper::new();
+        let mut payload = [0; STAMP_LEN - 1];
+
+        assert_eq!(stamper.stamp(&mut payload), None);
+        assert_eq!(payload, [0; STAMP_LEN - 1]);
+        assert_eq!(stamper.next_seq(), 0);
+    }
+
+    #[test]
+    fn frames_beyond_the_window_are_stale() {
+        let mut stamper = SequenceStamper::new();
+        let first = stamped(&mut stamper);
+
+        stamper.next = WINDOW + 1;
+        let later = stamped(&mut stamper);
+
+        let mut verifier = SequenceVerifier::new();
+        verifier.check(&later);
+
+        assert_eq!(verifier.check(&first), SequenceCheck::Stale { seq: 0 });
+        assert_eq!(verifier.report().reordered(), 1);
+    }
+}
@@ -0,0 +1,431 @@
+//! Building blocks for long running randomised tests.
+//!
+//! A soak test forwards traffic for a while with batch sizes, pauses
+//! and ring sizes drawn at random, checking as it goes that every
+//! frame is accounted for. Ring accounting bugs tend to need an
+//! unlucky interleaving to show up, which fixed sizes and timings
+//! rarely hit.
+//!
+//! [`Soak`] supplies the randomness and decides when to stop, and a
+//! [`FrameLedger`] per [`Umem`] tracks which side of each ring every
+//! frame is on, failing on the first frame moved out of turn.
+
+use std::{
+    error, fmt, thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config::QueueSize,
+    umem::{frame::FrameDesc, Umem},
+};
+
+/// Where a [`FrameLedger`] last saw a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOwner {
+    /// Held by user space, free to write or submit.
+    User,
+    /// Submitted to the [`FillQueue`](crate::FillQueue), awaiting a
+    /// packet on the [`RxQueue`](crate::RxQueue).
+    Fill,
+    /// Submitted to the [`TxQueue`](crate::TxQueue), awaiting the
+    /// [`CompQueue`](crate::CompQueue).
+    Tx,
+}
+
+/// A frame moved between rings out of turn, as found by a
+/// [`FrameLedger`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerError {
+    /// A descriptor pointed outside the [`Umem`].
+    OutOfBounds {
+        /// The descriptor's address.
+        addr: usize,
+    },
+    /// A frame wasn't where the move expected it to be. For example
+    /// submitted twice without coming back in between, or returned by
+    /// a ring it was never given to.
+    WrongOwner {
+        /// The frame's index in the [`Umem`].
+        frame: usize,
+        /// Where the frame had to be for the move.
+        expected: FrameOwner,
+        /// Where the frame actually was.
+        actual: FrameOwner,
+    },
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfBounds { addr } => write!(f, "address {} is outside the UMEM", addr),
+            Self::WrongOwner {
+                frame,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "frame {} expected to be owned by {:?} but was owned by {:?}",
+                frame, expected, actual
+            ),
+        }
+    }
+}
+
+impl error::Error for LedgerError {}
+
+/// Tracks the owner of every frame in a [`Umem`] to catch frames
+/// leaking or being handed out twice.
+///
+/// Record each batch as it crosses a ring, e.g. [`to_fill`] just
+/// before producing it on the [`FillQueue`] and [`from_rx`] just after
+/// consuming it from the [`RxQueue`]. A frame in the wrong place fails
+/// the move, and [`owned_by`] shows what's outstanding once traffic
+/// stops.
+///
+/// [`to_fill`]: Self::to_fill
+/// [`from_rx`]: Self::from_rx
+/// [`owned_by`]: Self::owned_by
+/// [`FillQueue`]: crate::FillQueue
+/// [`RxQueue`]: crate::RxQueue
+#[derive(Debug, Clone)]
+pub struct FrameLedger {
+    frame_size: usize,
+    owners: Vec<FrameOwner>,
+}
+
+impl FrameLedger {
+    /// Create a ledger for `umem`, with every frame starting out in
+    /// user space, as returned by [`Umem::new`].
+    pub fn new(umem: &Umem) -> Self {
+        Self::with_geometry(umem.frame_size(), umem.frame_count())
+    }
+
+    fn with_geometry(frame_size: usize, frame_count: usize) -> Self {
+        Self {
+            frame_size,
+            owners: vec![FrameOwner::User; frame_count],
+        }
+    }
+
+    /// Record `descs` being produced on the fill queue.
+    pub fn to_fill(&mut self, descs: &[FrameDesc]) -> Result<(), LedgerError> {
+        self.mv(descs, FrameOwner::User, FrameOwner::Fill)
+    }
+
+    /// Record `descs` being consumed from the RX queue.
+    pub fn from_rx(&mut self, descs: &[FrameDesc]) -> Result<(), LedgerError> {
+        self.mv(descs, FrameOwner::Fill, FrameOwner::User)
+    }
+
+    /// Record `descs` being produced on the TX queue.
+    pub fn to_tx(&mut self, descs: &[FrameDesc]) -> Result<(), LedgerError> {
+        self.mv(descs, FrameOwner::User, FrameOwner::Tx)
+    }
+
+    /// Record `descs` being consumed from the completion queue.
+    pub fn from_comp(&mut self, descs: &[FrameDesc]) -> Result<(), LedgerError> {
+        self.mv(descs, FrameOwner::Tx, FrameOwner::User)
+    }
+
+    /// The current owner of the frame described by `desc`.
+    pub fn owner(&self, desc: &FrameDesc) -> Option<FrameOwner> {
+        self.owners.get(desc.addr() / self.frame_size).copied()
+    }
+
+    /// The number of frames currently held by `owner`.
+    pub fn owned_by(&self, owner: FrameOwner) -> usize {
+        self.owners.iter().filter(|o| **o == owner).count()
+    }
+
+    /// Move every frame of `descs` from `from` to `to`, or none of
+    /// them if any is out of bounds or not owned by `from`.
+    fn mv(
+        &mut self,
+        descs: &[FrameDesc],
+        from: FrameOwner,
+        to: FrameOwner,
+    ) -> Result<(), LedgerError> {
+        let mut frames = Vec::with_capacity(descs.len());
+
+        for desc in descs {
+            let frame = desc.addr() / self.frame_size;
+
+            let owner = *self
+                .owners
+                .get(frame)
+                .ok_or(LedgerError::OutOfBounds { addr: desc.addr() })?;
+
+            if owner != from {
+                return Err(LedgerError::WrongOwner {
+                    frame,
+                    expected: from,
+                    actual: owner,
+                });
+            }
+
+            frames.push(frame);
+        }
+
+        frames.sort_unstable();
+
+        // A frame twice in one batch would already have moved by its
+        // second appearance.
+        if let Some(pair) = frames.windows(2).find(|pair| pair[0] == pair[1]) {
+            if from != to {
+                return Err(LedgerError::WrongOwner {
+                    frame: pair[0],
+                    expected: from,
+                    actual: to,
+                });
+            }
+        }
+
+        for frame in frames {
+            self.owners[frame] = to;
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings for a [`Soak`].
+#[derive(Debug, Clone)]
+pub struct SoakConfig {
+    duration: Duration,
+    seed: Option<u64>,
+    max_batch: usize,
+    max_delay: Duration,
+    min_ring_size: u32,
+    max_ring_size: u32,
+}
+
+impl SoakConfig {
+    /// Soak for `duration`, with batches of up to 64 frames, pauses of
+    /// up to a millisecond and ring sizes of 16 to 2048.
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            seed: None,
+            max_batch: 64,
+            max_delay: Duration::from_millis(1),
+            min_ring_size: 16,
+            max_ring_size: 2048,
+        }
+    }
+
+    /// Seed the random number generator, to replay an earlier run.
+    /// Otherwise a seed is taken from the clock.
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// The largest batch [`Soak::batch_size`] returns. Clamped to at
+    /// least one.
+    pub fn max_batch(&mut self, max_batch: usize) -> &mut Self {
+        self.max_batch = max_batch.max(1);
+        self
+    }
+
+    /// The longest pause [`Soak::pause`] takes.
+    pub fn max_delay(&mut self, max_delay: Duration) -> &mut Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// The range [`Soak::ring_size`] picks from.
+    pub fn ring_sizes(&mut self, min: QueueSize, max: QueueSize) -> &mut Self {
+        self.min_ring_size = min.get().min(max.get());
+        self.max_ring_size = min.get().max(max.get());
+        self
+    }
+}
+
+/// Drives a soak test, see the [module docs](crate::testutil::soak).
+#[derive(Debug, Clone)]
+pub struct Soak {
+    config: SoakConfig,
+    seed: u64,
+    state: u64,
+    started: Instant,
+    rounds: u64,
+}
+
+impl Soak {
+    /// Start a soak with the settings in `config`. The clock starts
+    /// now.
+    pub fn new(config: &SoakConfig) -> Self {
+        let seed = config.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or_default()
+        });
+
+        Self {
+            config: config.clone(),
+            seed,
+            // xorshift gets stuck on zero.
+            state: seed | 1,
+            started: Instant::now(),
+            rounds: 0,
+        }
+    }
+
+    /// The seed in use, to pass to [`SoakConfig::seed`] when
+    /// reproducing a failure.
+    #[inline]
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// A random number, from xorshift64*.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A random number in `lo..=hi`.
+    pub fn between(&mut self, lo: u64, hi: u64) -> u64 {
+        debug_assert!(lo <= hi);
+
+        match (hi - lo).checked_add(1) {
+            Some(span) => lo + self.next_u64() % span,
+            None => self.next_u64(),
+        }
+    }
+
+    /// A random ring size, a power of two within the configured range.
+    pub fn ring_size(&mut self) -> QueueSize {
+        let lo = self.config.min_ring_size.trailing_zeros();
+        let hi = self.config.max_ring_size.trailing_zeros();
+
+        let size = 1 << self.between(lo.into(), hi.into());
+
+        QueueSize::new(size).expect("power of two is a valid queue size")
+    }
+
+    /// A random batch size, from one up to the configured maximum.
+    pub fn batch_size(&mut self) -> usize {
+        self.between(1, self.config.max_batch as u64) as usize
+    }
+
+    /// Sleep for a random time up to the configured maximum. Half the
+    /// time doesn't sleep at all, so both hot and cold rings get
+    /// exercised.
+    pub fn pause(&mut self) {
+        if self.next_u64() & 1 == 0 {
+            return;
+        }
+
+        let max = self.config.max_delay.as_nanos().min(u64::MAX as u128) as u64;
+
+        thread::sleep(Duration::from_nanos(self.between(0, max)));
+    }
+
+    /// Count another round, returning `false` once the configured
+    /// duration is up.
+    pub fn next_round(&mut self) -> bool {
+        if self.started.elapsed() >= self.config.duration {
+            return false;
+        }
+
+        self.rounds += 1;
+
+        true
+    }
+
+    /// The number of rounds run so far.
+    #[inline]
+    pub fn rounds(&self) -> u64 {
+        self.rounds
+    }
+
+    /// The time since the soak started.
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::umem::frame::fixtures::desc;
+
+    use super::*;
+
+    #[test]
+    fn frames_moved_out_of_turn_are_caught() {
+        let mut ledger = FrameLedger::with_geometry(2048, 4);
+
+        ledger.to_fill(&[desc(0), desc(1)]).unwrap();
+        ledger.to_tx(&[desc(2)]).unwrap();
+
+        assert_eq!(
+            ledger.to_tx(&[desc(3), desc(1)]),
+            Err(LedgerError::WrongOwner {
+                frame: 1,
+                expected: FrameOwner::User,
+                actual: FrameOwner::Fill,
+            })
+        );
+
+        assert_eq!(
+            ledger.from_comp(&[desc(0)]).unwrap_err(),
+            LedgerError::WrongOwner {
+                frame: 0,
+                expected: FrameOwner::Tx,
+                actual: FrameOwner::Fill,
+            }
+        );
+
+        assert_eq!(
+            ledger.from_rx(&[desc(4)]),
+            Err(LedgerError::OutOfBounds {
+                addr: desc(4).addr()
+            })
+        );
+
+        assert_eq!(
+            ledger.to_tx(&[desc(3), desc(3)]),
+            Err(LedgerError::WrongOwner {
+                frame: 3,
+                expected: FrameOwner::User,
+                actual: FrameOwner::Tx,
+            })
+        );
+
+        ledger.from_rx(&[desc(1), desc(0)]).unwrap();
+
+        // Failed batches moved nothing, frame 3 included.
+        assert_eq!(ledger.owned_by(FrameOwner::Fill), 0);
+        assert_eq!(ledger.owned_by(FrameOwner::Tx), 1);
+        assert_eq!(ledger.owner(&desc(3)), Some(FrameOwner::User));
+        assert_eq!(ledger.owner(&desc(1)), Some(FrameOwner::User));
+    }
+
+    #[test]
+    fn runs_with_the_same_seed_match() {
+        let mut config = SoakConfig::new(Duration::from_secs(1));
+        config
+            .seed(42)
+            .max_batch(8)
+            .ring_sizes(QueueSize::new(4).unwrap(), QueueSize::new(64).unwrap());
+
+        let mut a = Soak::new(&config);
+        let mut b = Soak::new(&config);
+
+        for _ in 0..100 {
+            let size = a.ring_size().get();
+            assert_eq!(size, b.ring_size().get());
+            assert!(size.is_power_of_two() && (4..=64).contains(&size));
+
+            let batch = a.batch_size();
+            assert_eq!(batch, b.batch_size());
+            assert!((1..=8).contains(&batch));
+        }
+    }
+}
@@ -0,0 +1,484 @@
+//! Recording which ring each frame passes through, and rendering a
+//! per-frame ownership timeline for debugging stalls and leaks.
+//!
+//! The application records each ring operation on a [`FrameTrace`]
+//! with the descriptors involved, and [`FrameTrace::timeline`] pulls
+//! out every frame's spans in some window of interest, rendered with
+//! [`Timeline::to_json`] or [`Timeline::to_dot`], the latter for
+//! `dot -Tsvg`:
+//!
+//! ```no_run
+//! use std::{convert::TryInto, time::{Duration, Instant}};
+//! use xsk_rs::{trace::{FrameTrace, Stage}, Umem, UmemConfig};
+//!
+//! let (umem, descs) = Umem::new(UmemConfig::default(), 64.try_into().unwrap(), false).unwrap();
+//! let mut trace = FrameTrace::new(&umem, 4096, Instant::now());
+//!
+//! // After each ring operation, e.g. `fq.produce(&descs)`:
+//! trace.record(Stage::Fill, &descs, Instant::now());
+//!
+//! let timeline = trace.timeline(Duration::ZERO..Duration::from_secs(1));
+//! std::fs::write("frames.dot", timeline.to_dot()).unwrap();
+//! ```
+//!
+//! A frame whose last span is still open at the end of the trace is
+//! where it was left: sitting with [`Stage::Rx`] or [`Stage::App`]
+//! for long points at a descriptor leak, and with [`Stage::Tx`] at a
+//! stalled tx ring.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::{self, Write},
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use crate::umem::{frame::FrameDesc, Umem};
+
+/// Where a frame went, as recorded by [`FrameTrace::record`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Produced to the fill queue, awaiting a packet.
+    Fill,
+    /// Consumed from the rx queue with a packet.
+    Rx,
+    /// Picked up by the application for processing.
+    App,
+    /// Produced to the tx queue, awaiting transmission.
+    Tx,
+    /// Consumed from the completion queue, free for reuse.
+    Comp,
+}
+
+impl Stage {
+    /// Whether the kernel owns frames in this stage.
+    #[inline]
+    pub fn is_kernel_owned(&self) -> bool {
+        matches!(self, Stage::Fill | Stage::Tx)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Stage::Fill => "fill",
+            Stage::Rx => "rx",
+            Stage::App => "app",
+            Stage::Tx => "tx",
+            Stage::Comp => "comp",
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Event {
+    frame: usize,
+    stage: Stage,
+    at: Duration,
+}
+
+/// A bounded log of frame stage transitions, see the
+/// [module docs](self).
+///
+/// Once full, the oldest events are dropped to make room.
+#[derive(Debug, Clone)]
+pub struct FrameTrace {
+    frame_size: usize,
+    start: Instant,
+    capacity: usize,
+    events: VecDeque<Event>,
+    dropped: u64,
+}
+
+impl FrameTrace {
+    /// Create a trace of `umem`'s frames holding up to `capacity`
+    /// events, with times measured from `start`.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn new(umem: &Umem, capacity: usize, start: Instant) -> Self {
+        Self::with_frame_size(umem.frame_size(), capacity, start)
+    }
+
+    pub(crate) fn with_frame_size(frame_size: usize, capacity: usize, start: Instant) -> Self {
+        assert!(capacity > 0, "trace capacity must be non-zero");
+
+        Self {
+            frame_size,
+            start,
+            capacity,
+            events: VecDeque::with_capacity(capacity),
+            dropped: 0,
+        }
+    }
+
+    /// Record the frames of `descs` entering `stage` at `now`.
+    pub fn record(&mut self, stage: Stage, descs: &[FrameDesc], now: Instant) {
+        let at = now.saturating_duration_since(self.start);
+
+        for desc in descs {
+            if self.events.len() == self.capacity {
+                self.events.pop_front();
+                self.dropped += 1;
+            }
+
+            self.events.push_back(Event {
+                frame: desc.unaligned_base() / self.frame_size,
+                stage,
+                at,
+            });
+        }
+    }
+
+    /// The number of events held.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Whether no events are held.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// The number of events dropped to make room for newer ones.
+    #[inline]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Forget every event, keeping the start time.
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    /// Each frame's spans overlapping `window`, measured from the
+    /// trace's start. A span runs from one event for a frame to the
+    /// next, so spans starting before the window are included if
+    /// they're still open inside it.
+    pub fn timeline(&self, window: Range<Duration>) -> Timeline {
+        let mut spans: BTreeMap<usize, Vec<Span>> = BTreeMap::new();
+
+        for event in &self.events {
+            let frame = spans.entry(event.frame).or_default();
+
+            if let Some(last) = frame.last_mut() {
+                last.end = Some(event.at);
+            }
+
+            frame.push(Span {
+                stage: event.stage,
+                start: event.at,
+                end: None,
+            });
+        }
+
+        for frame in spans.values_mut() {
+            frame.retain(|span| span.overlaps(&window));
+        }
+        spans.retain(|_, frame| !frame.is_empty());
+
+        Timeline { window, spans }
+    }
+}
+
+/// The time a frame spent in one [`Stage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    stage: Stage,
+    start: Duration,
+    end: Option<Duration>,
+}
+
+impl Span {
+    /// The stage the frame was in.
+    #[inline]
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    /// When the frame entered the stage.
+    #[inline]
+    pub fn start(&self) -> Duration {
+        self.start
+    }
+
+    /// When the frame left the stage, or [`None`] if it hadn't by the
+    /// last event recorded.
+    #[inline]
+    pub fn end(&self) -> Option<Duration> {
+        self.end
+    }
+
+    fn overlaps(&self, window: &Range<Duration>) -> bool {
+        let ends_after_start = match self.end {
+            Some(end) => end > window.start,
+            None => true,
+        };
+
+        self.start < window.end && ends_after_start
+    }
+}
+
+/// Per-frame spans over a window of a [`FrameTrace`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timeline {
+    window: Range<Duration>,
+    spans: BTreeMap<usize, Vec<Span>>,
+}
+
+impl Timeline {
+    /// The window the timeline covers.
+    #[inline]
+    pub fn window(&self) -> &Range<Duration> {
+        &self.window
+    }
+
+    /// Each frame index with its spans, oldest first, in frame order.
+    pub fn frames(&self) -> impl Iterator<Item = (usize, &[Span])> + '_ {
+        self.spans.iter().map(|(frame, spans)| (*frame, &spans[..]))
+    }
+
+    /// The frames whose last span is still open and began at least
+    /// `min_age` before the window's end, i.e. that haven't moved on
+    /// in that long. Frames last seen completing are done with rather
+    /// than stuck, so aren't included.
+    pub fn stuck(&self, min_age: Duration) -> Vec<(usize, Stage)> {
+        self.spans
+            .iter()
+            .filter_map(|(frame, spans)| {
+                let last = spans.last()?;
+
+                if last.end.is_none()
+                    && last.stage != Stage::Comp
+                    && last.start + min_age <= self.window.end
+                {
+                    Some((*frame, last.stage))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// The timeline as JSON, with times in nanoseconds and open spans
+    /// ending in `null`:
+    ///
+    /// ```text
+    /// {"window":[0,1000],"frames":[{"frame":3,"spans":[
+    ///   {"stage":"fill","kernel":true,"start":0,"end":120}, ...]}]}
+    /// ```
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+
+        let _ = write!(
+            out,
+            "{{\"window\":[{},{}],\"frames\":[",
+            self.window.start.as_nanos(),
+            self.window.end.as_nanos()
+        );
+
+        for (i, (frame, spans)) in self.frames().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{{\"frame\":{},\"spans\":[", frame);
+
+            for (j, span) in spans.iter().enumerate() {
+                if j > 0 {
+                    out.push(',');
+                }
+                let _ = write!(
+                    out,
+                    "{{\"stage\":\"{}\",\"kernel\":{},\"start\":{},\"end\":",
+                    span.stage,
+                    span.stage.is_kernel_owned(),
+                    span.start.as_nanos()
+                );
+                match span.end {
+                    Some(end) => {
+                        let _ = write!(out, "{}}}", end.as_nanos());
+                    }
+                    None => out.push_str("null}"),
+                }
+            }
+
+            out.push_str("]}");
+        }
+
+        out.push_str("]}");
+        out
+    }
+
+    /// The timeline as a Graphviz digraph, one row per frame with a
+    /// node per span. Kernel owned spans are shaded and open spans
+    /// outlined in red.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from(
+            "digraph frames {\n  rankdir=LR;\n  node [shape=box, fontname=monospace];\n",
+        );
+
+        for (frame, spans) in self.frames() {
+            let _ = writeln!(out, "  subgraph cluster_{} {{", frame);
+            let _ = writeln!(out, "    label=\"frame {}\";", frame);
+
+            for (i, span) in spans.iter().enumerate() {
+                let end = match span.end {
+                    Some(end) => format!("{}us", end.as_micros()),
+                    None => String::from("..."),
+                };
+
+                let mut style = Vec::new();
+                if span.stage.is_kernel_owned() {
+                    style.push("style=filled, fillcolor=lightgrey");
+                }
+                if span.end.is_none() {
+                    style.push("color=red, penwidth=2");
+                }
+
+                let _ = write!(
+                    out,
+                    "    f{}_{} [label=\"{}\\n{}us - {}\"",
+                    frame,
+                    i,
+                    span.stage,
+                    span.start.as_micros(),
+                    end
+                );
+                for attr in style {
+                    let _ = write!(out, ", {}", attr);
+                }
+                out.push_str("];\n");
+
+                if i > 0 {
+                    let _ = writeln!(out, "    f{}_{} -> f{}_{};", frame, i - 1, frame, i);
+                }
+            }
+
+            out.push_str("  }\n");
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::umem::frame::fixtures::{desc, FRAME_SIZE};
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn trace() -> FrameTrace {
+        let start = Instant::now();
+        let mut trace = FrameTrace::with_frame_size(FRAME_SIZE, 64, start);
+
+        trace.record(Stage::Fill, &[desc(0), desc(1)], start);
+        trace.record(Stage::Rx, &[desc(0), desc(1)], start + ms(10));
+        trace.record(Stage::Tx, &[desc(0)], start + ms(20));
+        trace.record(Stage::Comp, &[desc(0)], start + ms(30));
+
+        trace
+    }
+
+    #[test]
+    fn spans_run_from_one_event_to_the_next() {
+        let timeline = trace().timeline(ms(0)..ms(100));
+        let frames: Vec<_> = timeline.frames().collect();
+
+        assert_eq!(frames.len(), 2);
+
+        let (frame, spans) = frames[0];
+        assert_eq!(frame, 0);
+        let stages: Vec<_> = spans.iter().map(|s| s.stage()).collect();
+        assert_eq!(stages, [Stage::Fill, Stage::Rx, Stage::Tx, Stage::Comp]);
+        assert_eq!(spans[2].start(), ms(20));
+        assert_eq!(spans[2].end(), Some(ms(30)));
+        assert_eq!(spans[3].end(), None);
+
+        assert_eq!(timeline.stuck(ms(50)), [(1, Stage::Rx)]);
+        assert_eq!(timeline.stuck(ms(95)), []);
+    }
+
+    #[test]
+    fn windows_keep_spans_open_within_them() {
+        let timeline = trace().timeline(ms(15)..ms(25));
+        let frames: Vec<_> = timeline.frames().collect();
+
+        let stages: Vec<_> = frames[0].1.iter().map(|s| s.stage()).collect();
+        assert_eq!(stages, [Stage::Rx, Stage::Tx]);
+
+        let stages: Vec<_> = frames[1].1.iter().map(|s| s.stage()).collect();
+        assert_eq!(stages, [Stage::Rx]);
+    }
+
+    #[test]
+    fn unaligned_offsets_stay_with_their_frame() {
+        let start = Instant::now();
+        let mut trace = FrameTrace::with_frame_size(FRAME_SIZE, 8, start);
+
+        let mut received = desc(5);
+        received.set_unaligned_offset(64);
+
+        trace.record(Stage::Fill, &[desc(5)], start);
+        trace.record(Stage::Rx, &[received], start + ms(1));
+
+        let timeline = trace.timeline(ms(0)..ms(2));
+        let frames: Vec<_> = timeline.frames().map(|(f, s)| (f, s.len())).collect();
+
+        assert_eq!(frames, [(5, 2)]);
+    }
+
+    #[test]
+    fn full_traces_drop_the_oldest_events() {
+        let start = Instant::now();
+        let mut trace = FrameTrace::with_frame_size(FRAME_SIZE, 2, start);
+
+        trace.record(Stage::Fill, &[desc(0), desc(1), desc(2)], start);
+
+        assert_eq!(trace.len(), 2);
+        assert_eq!(trace.dropped(), 1);
+
+        let frames: Vec<_> = trace
+            .timeline(ms(0)..ms(1))
+            .frames()
+            .map(|(f, _)| f)
+            .collect();
+        assert_eq!(frames, [1, 2]);
+    }
+
+    #[test]
+    fn renders_json_and_dot() {
+        let start = Instant::now();
+        let mut trace = FrameTrace::with_frame_size(FRAME_SIZE, 8, start);
+
+        trace.record(Stage::Fill, &[desc(3)], start);
+        trace.record(Stage::Rx, &[desc(3)], start + Duration::from_micros(5));
+
+        let timeline = trace.timeline(ms(0)..ms(1));
+
+        assert_eq!(
+            timeline.to_json(),
+            "{\"window\":[0,1000000],\"frames\":[{\"frame\":3,\"spans\":[\
+             {\"stage\":\"fill\",\"kernel\":true,\"start\":0,\"end\":5000},\
+             {\"stage\":\"rx\",\"kernel\":false,\"start\":5000,\"end\":null}]}]}"
+        );
+
+        let dot = timeline.to_dot();
+        assert!(dot.contains("subgraph cluster_3"));
+        assert!(
+            dot.contains("f3_0 [label=\"fill\\n0us - 5us\", style=filled, fillcolor=lightgrey];")
+        );
+        assert!(dot.contains("f3_1 [label=\"rx\\n5us - ...\", color=red, penwidth=2];"));
+        assert!(dot.contains("f3_0 -> f3_1;"));
+    }
+}
@@ -1,6 +1,9 @@
-use crate::ring::XskRingCons;
+use crate::ring::{Dynamic, RingSize, XskRingCons};
 
-use super::{frame::FrameDesc, Umem};
+use super::{
+    frame::{FrameDesc, SegmentLengths},
+    OwnedFrame, Umem, UmemMismatchError,
+};
 
 /// Used to transfer ownership of [`Umem`](super::Umem) frames from
 /// kernel-space to user-space.
@@ -8,17 +11,27 @@ use super::{frame::FrameDesc, Umem};
 /// Frames received in this queue are those that have been sent via
 /// the [`TxQueue`](crate::socket::TxQueue).
 ///
+/// If the [`Umem`] is shared then the frames consumed here may be
+/// handed to the [`FillQueue`](crate::FillQueue) of any socket using
+/// it, see [`FillQueue::recycle_from`](crate::FillQueue::recycle_from).
+///
 /// For more information see the
 /// [docs](https://www.kernel.org/doc/html/latest/networking/af_xdp.html#umem-completion-ring).
 #[derive(Debug)]
 pub struct CompQueue {
     ring: XskRingCons,
-    _umem: Umem,
+    umem: Umem,
 }
 
 impl CompQueue {
     pub(crate) fn new(ring: XskRingCons, umem: Umem) -> Self {
-        Self { ring, _umem: umem }
+        Self { ring, umem }
+    }
+
+    /// The [`Umem`] whose frames this queue returns.
+    #[inline]
+    pub fn umem(&self) -> &Umem {
+        &self.umem
     }
 
     /// Update `descs` with details of frames whose contents have been
@@ -42,6 +55,12 @@ impl CompQueue {
     /// [`FillQueue`]: crate::FillQueue
     #[inline]
     pub unsafe fn consume(&mut self, descs: &mut [FrameDesc]) -> usize {
+        unsafe { self.consume_sized::<Dynamic>(descs) }
+    }
+
+    /// [`consume`](Self::consume) for a ring of size `S`.
+    #[inline]
+    pub(crate) unsafe fn consume_sized<S: RingSize>(&mut self, descs: &mut [FrameDesc]) -> usize {
         let nb = descs.len() as u32;
 
         if nb == 0 {
@@ -50,16 +69,16 @@ impl CompQueue {
 
         let mut idx = 0;
 
-        let cnt = unsafe { libxdp_sys::xsk_ring_cons__peek(self.ring.as_mut(), nb, &mut idx) };
+        let cnt = unsafe { self.ring.peek::<S>(nb, &mut idx) };
 
         if cnt > 0 {
             for desc in descs.iter_mut().take(cnt as usize) {
-                let addr =
-                    unsafe { *libxdp_sys::xsk_ring_cons__comp_addr(self.ring.as_ref(), idx) };
+                let addr = unsafe { *self.ring.comp_addr::<S>(idx) };
 
                 desc.addr = addr as usize;
                 desc.lengths.data = 0;
                 desc.lengths.headroom = 0;
+                desc.lengths.meta = 0;
                 desc.options = 0;
 
                 idx += 1;
@@ -71,6 +90,63 @@ impl CompQueue {
         cnt as usize
     }
 
+    /// Take up to `nb` frames whose transmission has completed,
+    /// appending them to `frames`. Returns the number taken.
+    ///
+    /// The safe counterpart to [`consume`], see [`OwnedFrame`].
+    ///
+    /// # Errors
+    ///
+    /// If `umem` isn't the [`Umem`] this queue is tied to, in which
+    /// case nothing is taken.
+    ///
+    /// [`consume`]: Self::consume
+    #[inline]
+    pub fn consume_owned<'umem>(
+        &mut self,
+        umem: &'umem Umem,
+        nb: usize,
+        frames: &mut Vec<OwnedFrame<'umem>>,
+    ) -> Result<usize, UmemMismatchError> {
+        if !self.umem.is_shared_with(umem) {
+            return Err(UmemMismatchError);
+        }
+
+        let nb = nb.min(u32::MAX as usize) as u32;
+
+        if nb == 0 {
+            return Ok(0);
+        }
+
+        let mut idx = 0;
+
+        let cnt = unsafe { libxdp_sys::xsk_ring_cons__peek(self.ring.as_mut(), nb, &mut idx) };
+
+        frames.reserve(cnt as usize);
+
+        for _ in 0..cnt {
+            let addr = unsafe { *libxdp_sys::xsk_ring_cons__comp_addr(self.ring.as_ref(), idx) };
+
+            let desc = FrameDesc {
+                addr: addr as usize,
+                options: 0,
+                lengths: SegmentLengths::default(),
+            };
+
+            // SAFETY: the kernel has finished with the frame, which is
+            // in `umem` as checked above.
+            frames.push(unsafe { OwnedFrame::new(umem, desc) });
+
+            idx += 1;
+        }
+
+        if cnt > 0 {
+            unsafe { libxdp_sys::xsk_ring_cons__release(self.ring.as_mut(), cnt) };
+        }
+
+        Ok(cnt as usize)
+    }
+
     /// Same as [`consume`] but for a single frame descriptor.
     ///
     /// # Safety
@@ -90,6 +166,7 @@ impl CompQueue {
             desc.addr = addr as usize;
             desc.lengths.data = 0;
             desc.lengths.headroom = 0;
+            desc.lengths.meta = 0;
             desc.options = 0;
 
             unsafe { libxdp_sys::xsk_ring_cons__release(self.ring.as_mut(), cnt) };
@@ -97,4 +174,21 @@ impl CompQueue {
 
         cnt as usize
     }
+
+    /// The size of the ring.
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.ring.as_ref().size as usize
+    }
+
+    /// The number of entries on the ring waiting to be consumed.
+    #[inline]
+    pub(crate) fn fill_level(&mut self) -> usize {
+        let size = self.ring.as_ref().size;
+
+        // SAFETY: the ring is initialised and lives as long as `self`,
+        // and this only refreshes the cached producer index, which
+        // the `&mut self` borrow keeps anything else from touching.
+        unsafe { libxdp_sys::xsk_cons_nb_avail(self.ring.as_mut(), size) as usize }
+    }
 }
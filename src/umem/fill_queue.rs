@@ -1,8 +1,13 @@
-use std::io;
+use std::{error::Error, fmt, io, ptr};
 
-use crate::{ring::XskRingProd, socket::Fd};
+use crate::{
+    check::{self, Bounds},
+    portable::FrameLayout,
+    ring::{Dynamic, RingSize, XskRingProd},
+    socket::Fd,
+};
 
-use super::{frame::FrameDesc, Umem};
+use super::{frame::FrameDesc, CompQueue, OwnedFrame, Umem};
 
 /// Used to transfer ownership of [`Umem`](super::Umem) frames from
 /// user-space to kernel-space.
@@ -10,17 +15,40 @@ use super::{frame::FrameDesc, Umem};
 /// These frames will be used to receive packets, and will eventually
 /// be returned via the [`RxQueue`](crate::socket::RxQueue).
 ///
+/// When several sockets share a [`Umem`], frames may be moved freely
+/// between their queues, so long as they stay within that [`Umem`].
+/// A common case is forwarding, where packets received on one socket
+/// are sent on another: the frames then come back via the sending
+/// socket's [`CompQueue`] and need returning to the receiving socket's
+/// `FillQueue`. [`recycle_from`](Self::recycle_from) does this
+/// safely, checking the two queues really do share a [`Umem`].
+///
 /// For more information see the
 /// [docs](https://www.kernel.org/doc/html/latest/networking/af_xdp.html#umem-fill-ring).
 #[derive(Debug)]
 pub struct FillQueue {
     ring: XskRingProd,
-    _umem: Umem,
+    umem: Umem,
+    bounds: Bounds,
+    layout: FrameLayout,
+    unaligned_chunks: bool,
 }
 
 impl FillQueue {
     pub(crate) fn new(ring: XskRingProd, umem: Umem) -> Self {
-        Self { ring, _umem: umem }
+        Self {
+            ring,
+            bounds: Bounds::of(&umem),
+            layout: umem.mem.layout(),
+            unaligned_chunks: umem.unaligned_chunks(),
+            umem,
+        }
+    }
+
+    /// The [`Umem`] this queue hands frames to the kernel from.
+    #[inline]
+    pub fn umem(&self) -> &Umem {
+        &self.umem
     }
 
     /// Let the kernel know that the [`Umem`] frames described by
@@ -48,22 +76,29 @@ impl FillQueue {
     /// [`RxQueue`]: crate::RxQueue
     #[inline]
     pub unsafe fn produce(&mut self, descs: &[FrameDesc]) -> usize {
+        unsafe { self.produce_sized::<Dynamic>(descs) }
+    }
+
+    /// [`produce`](Self::produce) for a ring of size `S`.
+    #[inline]
+    pub(crate) unsafe fn produce_sized<S: RingSize>(&mut self, descs: &[FrameDesc]) -> usize {
         let nb = descs.len() as u32;
 
         if nb == 0 {
             return 0;
         }
 
+        self.check(descs);
+
         let mut idx = 0;
 
-        let cnt = unsafe { libxdp_sys::xsk_ring_prod__reserve(self.ring.as_mut(), nb, &mut idx) };
+        let cnt = unsafe { self.ring.reserve::<S>(nb, &mut idx) };
 
         if cnt > 0 {
             for desc in descs.iter().take(cnt as usize) {
-                unsafe {
-                    *libxdp_sys::xsk_ring_prod__fill_addr(self.ring.as_mut(), idx) =
-                        desc.addr as u64
-                };
+                let addr = self.layout.fill_addr(desc.addr, self.unaligned_chunks);
+
+                unsafe { *self.ring.fill_addr::<S>(idx) = addr as u64 };
 
                 idx += 1;
             }
@@ -83,14 +118,179 @@ impl FillQueue {
     /// [`produce`]: Self::produce
     #[inline]
     pub unsafe fn produce_one(&mut self, desc: &FrameDesc) -> usize {
+        self.bounds.check("fill queue", desc.addr, 1);
+
         let mut idx = 0;
 
         let cnt = unsafe { libxdp_sys::xsk_ring_prod__reserve(self.ring.as_mut(), 1, &mut idx) };
 
         if cnt > 0 {
-            unsafe {
-                *libxdp_sys::xsk_ring_prod__fill_addr(self.ring.as_mut(), idx) = desc.addr as u64
-            };
+            let addr = self.layout.fill_addr(desc.addr, self.unaligned_chunks);
+
+            unsafe { *libxdp_sys::xsk_ring_prod__fill_addr(self.ring.as_mut(), idx) = addr as u64 };
+
+            unsafe { libxdp_sys::xsk_ring_prod__submit(self.ring.as_mut(), cnt) };
+        }
+
+        cnt as usize
+    }
+
+    /// Hand frames from the start of `frames` to the kernel to receive
+    /// into, removing them from `frames`. Returns the number handed
+    /// over, which is as many as there's room for on the ring.
+    ///
+    /// The safe counterpart to [`produce`], see [`OwnedFrame`].
+    ///
+    /// # Errors
+    ///
+    /// If any of `frames` belongs to a different [`Umem`] to this
+    /// queue, in which case none are handed over.
+    ///
+    /// [`produce`]: Self::produce
+    #[inline]
+    pub fn produce_owned(
+        &mut self,
+        frames: &mut Vec<OwnedFrame<'_>>,
+    ) -> Result<usize, UmemMismatchError> {
+        if !frames.iter().all(|f| self.umem.is_shared_with(f.umem())) {
+            return Err(UmemMismatchError);
+        }
+
+        let nb = self
+            .free_slots(frames.len() as u32)
+            .min(frames.len() as u32);
+
+        if nb == 0 {
+            return Ok(0);
+        }
+
+        let mut idx = 0;
+
+        let cnt = unsafe { libxdp_sys::xsk_ring_prod__reserve(self.ring.as_mut(), nb, &mut idx) };
+
+        // SAFETY: the frames are owned by user space and belong to
+        // this queue's UMEM, and are moved out of `frames` so can't be
+        // accessed again until the kernel returns them.
+        for frame in frames.drain(..cnt as usize) {
+            let addr = self
+                .layout
+                .fill_addr(frame.desc().addr, self.unaligned_chunks);
+
+            unsafe { *libxdp_sys::xsk_ring_prod__fill_addr(self.ring.as_mut(), idx) = addr as u64 };
+
+            idx += 1;
+        }
+
+        unsafe { libxdp_sys::xsk_ring_prod__submit(self.ring.as_mut(), cnt) };
+
+        Ok(cnt as usize)
+    }
+
+    /// Move frames whose transmission has completed from `cq` straight
+    /// on to this queue, using `descs` as scratch space. Returns the
+    /// number of frames moved, which is at most the length of `descs`
+    /// and the number of free spaces on this queue.
+    ///
+    /// `cq` may belong to any socket sharing this queue's [`Umem`],
+    /// including the socket this queue belongs to. On return
+    /// `descs[..n]` hold the addresses of the `n` frames moved, which
+    /// are now owned by the kernel again.
+    ///
+    /// Unlike pairing [`CompQueue::consume`] with [`produce`], this is
+    /// safe: frames never leave the [`Umem`] and are handed back to
+    /// the kernel before the caller has a chance to use them.
+    ///
+    /// Note that wakeups are not handled here, see
+    /// [`needs_wakeup`](Self::needs_wakeup).
+    ///
+    /// # Errors
+    ///
+    /// If `cq` is tied to a different [`Umem`] to this queue, in which
+    /// case no frames are moved.
+    ///
+    /// [`produce`]: Self::produce
+    #[inline]
+    pub fn recycle_from(
+        &mut self,
+        cq: &mut CompQueue,
+        descs: &mut [FrameDesc],
+    ) -> Result<usize, UmemMismatchError> {
+        if !self.umem.is_shared_with(cq.umem()) {
+            return Err(UmemMismatchError);
+        }
+
+        let nb = descs
+            .len()
+            .min(self.free_slots(descs.len() as u32) as usize);
+
+        // SAFETY: both queues are tied to the same UMEM, and the frames
+        // consumed are handed straight back to the kernel, so there's
+        // no opportunity for them to be used elsewhere in the
+        // meantime.
+        let cnt = unsafe { cq.consume(&mut descs[..nb]) };
+        let produced = unsafe { self.produce(&descs[..cnt]) };
+
+        debug_assert_eq!(produced, cnt);
+
+        Ok(cnt)
+    }
+
+    /// Hand frames just consumed from an [`RxQueue`] straight back to
+    /// the kernel to receive into, in one batch. Returns the number of
+    /// frames submitted, which as with [`produce`] is either all of
+    /// `descs` or, if there isn't room for them all, none.
+    ///
+    /// This is the usual last step of a forwarding or dropping loop,
+    /// so it's kept to a single pass over `descs`: each frame's
+    /// address is written to the ring and its lengths and options are
+    /// reset in place, leaving `descs` ready to be consumed into again
+    /// without carrying stale values over from the previous packet.
+    ///
+    /// # Safety
+    ///
+    /// `descs` must have been consumed from an [`RxQueue`] of a socket
+    /// bound to this queue's [`Umem`], and no longer be in use
+    /// elsewhere, e.g. not also about to be sent on a [`TxQueue`]. See
+    /// also [`produce`].
+    ///
+    /// [`produce`]: Self::produce
+    /// [`RxQueue`]: crate::RxQueue
+    /// [`TxQueue`]: crate::TxQueue
+    #[inline]
+    pub unsafe fn recycle_from_rx(&mut self, descs: &mut [FrameDesc]) -> usize {
+        unsafe { self.recycle_from_rx_sized::<Dynamic>(descs) }
+    }
+
+    /// [`recycle_from_rx`](Self::recycle_from_rx) for a ring of size
+    /// `S`.
+    #[inline]
+    pub(crate) unsafe fn recycle_from_rx_sized<S: RingSize>(
+        &mut self,
+        descs: &mut [FrameDesc],
+    ) -> usize {
+        let nb = descs.len() as u32;
+
+        if nb == 0 {
+            return 0;
+        }
+
+        self.check(descs);
+
+        let mut idx = 0;
+
+        let cnt = unsafe { self.ring.reserve::<S>(nb, &mut idx) };
+
+        if cnt > 0 {
+            for desc in descs.iter_mut().take(cnt as usize) {
+                let addr = self.layout.fill_addr(desc.addr, self.unaligned_chunks);
+
+                unsafe { *self.ring.fill_addr::<S>(idx) = addr as u64 };
+
+                desc.options = 0;
+                desc.lengths = Default::default();
+
+                idx += 1;
+            }
 
             unsafe { libxdp_sys::xsk_ring_prod__submit(self.ring.as_mut(), cnt) };
         }
@@ -154,6 +354,9 @@ impl FillQueue {
     /// Wake up the kernel to let it know it can continue using the
     /// fill ring to process received data.
     ///
+    /// This polls `fd` for up to `poll_timeout` ms. To kick without
+    /// waiting, use [`RxQueue::wakeup`](crate::RxQueue::wakeup).
+    ///
     /// See [`produce_and_wakeup`] for link to docs with further
     /// explanation.
     ///
@@ -178,4 +381,81 @@ impl FillQueue {
     pub fn needs_wakeup(&self) -> bool {
         unsafe { libxdp_sys::xsk_ring_prod__needs_wakeup(self.ring.as_ref()) != 0 }
     }
+
+    /// Push the descriptors the kernel hasn't taken off the ring to
+    /// `descs`, dropping the queue.
+    ///
+    /// # Safety
+    ///
+    /// The kernel must no longer be taking frames off the ring, see
+    /// [`Umem::drain`].
+    pub(crate) unsafe fn into_unconsumed(mut self, descs: &mut Vec<FrameDesc>) {
+        let (layout, unaligned_chunks) = (self.layout, self.unaligned_chunks);
+        let ring = self.ring.as_mut();
+
+        // SAFETY: the consumer index is mapped for as long as the
+        // ring is, and only read here.
+        let cons = unsafe { ptr::read_volatile(ring.consumer) };
+        let prod = ring.cached_prod;
+
+        let mut idx = cons;
+
+        while idx != prod {
+            // SAFETY: `idx` lies between the consumer and producer so
+            // is a filled-in entry, which the kernel no longer reads.
+            let addr = unsafe { *libxdp_sys::xsk_ring_prod__fill_addr(ring, idx) };
+
+            descs.push(FrameDesc::new(
+                layout.addr_from_fill(addr as usize, unaligned_chunks),
+            ));
+            idx = idx.wrapping_add(1);
+        }
+    }
+
+    /// The number of entries the ring can hold.
+    #[inline]
+    pub(crate) fn capacity(&self) -> usize {
+        self.ring.as_ref().size as usize
+    }
+
+    /// The number of free spaces on the ring, checking no further than
+    /// `nb`.
+    #[inline]
+    pub(crate) fn free_slots(&mut self, nb: u32) -> u32 {
+        unsafe { libxdp_sys::xsk_prod_nb_free(self.ring.as_mut(), nb) }
+    }
+
+    /// Check a whole batch before any of it is reserved, so a
+    /// violation leaves the ring untouched.
+    #[inline]
+    #[track_caller]
+    fn check(&self, descs: &[FrameDesc]) {
+        check::unique("fill queue", descs.iter().map(|d| d.addr));
+        self.bounds
+            .check_all("fill queue", descs.iter().map(|d| (d.addr, 1)));
+    }
+
+    /// The number of entries on the ring the kernel has yet to
+    /// consume.
+    #[inline]
+    pub(crate) fn fill_level(&mut self) -> usize {
+        let size = self.capacity();
+        size - self.free_slots(size as u32) as usize
+    }
 }
+
+/// Error returned by [`FillQueue::recycle_from`] when the
+/// [`CompQueue`] is tied to a different [`Umem`] to the
+/// [`FillQueue`], and by the `produce_owned` and `consume_owned`
+/// queue methods when an [`OwnedFrame`] or [`Umem`] passed in isn't
+/// the one the queue is tied to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UmemMismatchError;
+
+impl fmt::Display for UmemMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "frames or queues belong to different UMEMs")
+    }
+}
+
+impl Error for UmemMismatchError {}
@@ -0,0 +1,234 @@
+use std::iter::FusedIterator;
+
+use super::{FrameDesc, SegmentLengths};
+
+/// A fixed capacity batch of frame descriptors, stored as a structure
+/// of arrays.
+///
+/// Holds the same information as a `[FrameDesc]` but with addresses,
+/// lengths and options each in their own contiguous array. Code which
+/// works on one field across a whole batch, say summing lengths or
+/// checking options, then touches only the memory it needs and is
+/// easier for the compiler to vectorise.
+///
+/// Filled by [`RxQueue::consume_batch`] and submitted with
+/// [`TxQueue::produce_batch`].
+///
+/// [`RxQueue::consume_batch`]: crate::RxQueue::consume_batch
+/// [`TxQueue::produce_batch`]: crate::TxQueue::produce_batch
+#[derive(Debug, Clone)]
+pub struct DescBatch {
+    capacity: usize,
+    addrs: Vec<usize>,
+    data_lens: Vec<usize>,
+    headroom_lens: Vec<usize>,
+    meta_lens: Vec<usize>,
+    options: Vec<u32>,
+}
+
+impl DescBatch {
+    /// Create an empty batch which can hold up to `capacity`
+    /// descriptors.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            addrs: Vec::with_capacity(capacity),
+            data_lens: Vec::with_capacity(capacity),
+            headroom_lens: Vec::with_capacity(capacity),
+            meta_lens: Vec::with_capacity(capacity),
+            options: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// The maximum number of descriptors the batch can hold.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of descriptors in the batch.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.addrs.len()
+    }
+
+    /// Whether the batch holds no descriptors.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+
+    /// Whether the batch is at capacity.
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.len() == self.capacity
+    }
+
+    /// Remove all descriptors.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.addrs.clear();
+        self.data_lens.clear();
+        self.headroom_lens.clear();
+        self.meta_lens.clear();
+        self.options.clear();
+    }
+
+    /// Append `desc` to the batch. Returns `false`, leaving the batch
+    /// unchanged, if it's already full.
+    #[inline]
+    pub fn push(&mut self, desc: &FrameDesc) -> bool {
+        if self.is_full() {
+            return false;
+        }
+
+        self.push_unchecked(desc.addr, desc.lengths, desc.options);
+
+        true
+    }
+
+    #[inline]
+    pub(crate) fn push_unchecked(&mut self, addr: usize, lengths: SegmentLengths, options: u32) {
+        self.addrs.push(addr);
+        self.data_lens.push(lengths.data);
+        self.headroom_lens.push(lengths.headroom);
+        self.meta_lens.push(lengths.meta);
+        self.options.push(options);
+    }
+
+    /// The descriptor at `idx`, if there is one.
+    #[inline]
+    pub fn get(&self, idx: usize) -> Option<FrameDesc> {
+        if idx >= self.len() {
+            return None;
+        }
+
+        Some(FrameDesc {
+            addr: self.addrs[idx],
+            options: self.options[idx],
+            lengths: SegmentLengths {
+                headroom: self.headroom_lens[idx],
+                data: self.data_lens[idx],
+                meta: self.meta_lens[idx],
+            },
+        })
+    }
+
+    /// Iterate over the batch's descriptors in order.
+    #[inline]
+    pub fn iter(&self) -> DescBatchIter<'_> {
+        DescBatchIter {
+            batch: self,
+            idx: 0,
+        }
+    }
+
+    /// The starting address of each frame's packet data segment, see
+    /// [`FrameDesc::addr`].
+    #[inline]
+    pub fn addrs(&self) -> &[usize] {
+        &self.addrs
+    }
+
+    /// The length of each frame's packet data segment.
+    #[inline]
+    pub fn data_lens(&self) -> &[usize] {
+        &self.data_lens
+    }
+
+    /// The length of each frame's headroom segment.
+    #[inline]
+    pub fn headroom_lens(&self) -> &[usize] {
+        &self.headroom_lens
+    }
+
+    /// The length of the XDP metadata in front of each frame's packet
+    /// data, see [`SegmentLengths::meta`].
+    #[inline]
+    pub fn meta_lens(&self) -> &[usize] {
+        &self.meta_lens
+    }
+
+    /// Each frame's options, see [`FrameDesc::options`].
+    #[inline]
+    pub fn options(&self) -> &[u32] {
+        &self.options
+    }
+
+    /// Mutable access to each frame's options, e.g. to mark all of
+    /// them as needing TX metadata before submitting the batch.
+    #[inline]
+    pub fn options_mut(&mut self) -> &mut [u32] {
+        &mut self.options
+    }
+}
+
+/// Iterator over the descriptors of a [`DescBatch`], created by
+/// [`DescBatch::iter`].
+#[derive(Debug, Clone)]
+pub struct DescBatchIter<'a> {
+    batch: &'a DescBatch,
+    idx: usize,
+}
+
+impl Iterator for DescBatchIter<'_> {
+    type Item = FrameDesc;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let desc = self.batch.get(self.idx)?;
+        self.idx += 1;
+        Some(desc)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let rem = self.batch.len() - self.idx;
+        (rem, Some(rem))
+    }
+}
+
+impl ExactSizeIterator for DescBatchIter<'_> {}
+
+impl FusedIterator for DescBatchIter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desc(addr: usize, data: usize, options: u32) -> FrameDesc {
+        let mut desc = FrameDesc::new(addr);
+        desc.lengths.data = data;
+        desc.lengths.headroom = 4;
+        desc.options = options;
+        desc
+    }
+
+    #[test]
+    fn descs_round_trip_through_columns() {
+        let mut batch = DescBatch::new(2);
+
+        assert!(batch.push(&desc(256, 60, 0)));
+        assert!(batch.push(&desc(2304, 1500, 1)));
+        assert!(!batch.push(&desc(4352, 42, 0)));
+
+        assert!(batch.is_full());
+        assert_eq!(batch.addrs(), [256, 2304]);
+        assert_eq!(batch.data_lens(), [60, 1500]);
+        assert_eq!(batch.headroom_lens(), [4, 4]);
+        assert_eq!(batch.options(), [0, 1]);
+
+        let descs: Vec<_> = batch.iter().collect();
+
+        assert_eq!(descs.len(), 2);
+        assert_eq!(descs[1].addr(), 2304);
+        assert_eq!(descs[1].lengths().data(), 1500);
+        assert_eq!(descs[1].options(), 1);
+        assert!(batch.get(2).is_none());
+
+        batch.clear();
+
+        assert!(batch.is_empty());
+        assert_eq!(batch.capacity(), 2);
+    }
+}
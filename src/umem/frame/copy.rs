@@ -0,0 +1,102 @@
+//! Copying payloads into frames without pulling them into cache.
+
+/// How payloads are copied into [`Umem`](crate::Umem) frames, see
+/// [`Cursor::write_all_with`](super::Cursor::write_all_with).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CopyMode {
+    /// Ordinary stores, leaving the frame in cache.
+    #[default]
+    Cached,
+    /// Non-temporal stores, which go around the cache straight to
+    /// memory. Worth it for generators sending large packets at high
+    /// rates, whose frames are next touched by the NIC rather than the
+    /// CPU, so would only evict more useful lines.
+    ///
+    /// Non-temporal stores aren't ordered with the ring's producer
+    /// update, so [`store_fence`] must be called after the last copy
+    /// and before the frames are produced to the
+    /// [`TxQueue`](crate::TxQueue).
+    ///
+    /// Only x86_64 has them, elsewhere this is the same as
+    /// [`Cached`](Self::Cached).
+    NonTemporal,
+}
+
+/// Order any non-temporal stores made by copies with
+/// [`CopyMode::NonTemporal`] before later stores, i.e. make sure the
+/// payloads are in memory before the kernel's told about the frames.
+/// Nothing on targets without non-temporal stores.
+#[inline]
+pub fn store_fence() {
+    #[cfg(target_arch = "x86_64")]
+    // SAFETY: `sfence` is available on every x86_64 CPU.
+    unsafe {
+        core::arch::x86_64::_mm_sfence()
+    };
+}
+
+/// Copy `src` to the start of `dst`, which must be at least as long.
+#[inline]
+pub(crate) fn copy(dst: &mut [u8], src: &[u8], mode: CopyMode) {
+    let dst = &mut dst[..src.len()];
+
+    match mode {
+        #[cfg(target_arch = "x86_64")]
+        CopyMode::NonTemporal => copy_non_temporal(dst, src),
+        _ => dst.copy_from_slice(src),
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[inline]
+fn copy_non_temporal(dst: &mut [u8], src: &[u8]) {
+    use core::arch::x86_64::{__m128i, _mm_loadu_si128, _mm_stream_si128};
+
+    const LANE: usize = 16;
+
+    // Streaming stores need an aligned destination, so the bytes up
+    // to the first 16 byte boundary go the ordinary way.
+    let head = dst.as_ptr().align_offset(LANE).min(dst.len());
+    let (dst_head, dst) = dst.split_at_mut(head);
+    let (src_head, src) = src.split_at(head);
+
+    dst_head.copy_from_slice(src_head);
+
+    let lanes = dst.len() / LANE;
+
+    for i in 0..lanes {
+        // SAFETY: both offsets are in bounds of slices of equal
+        // length, and `dst` is 16 byte aligned from `head` on.
+        // `sse2` is available on every x86_64 CPU.
+        unsafe {
+            let v = _mm_loadu_si128(src.as_ptr().add(i * LANE) as *const __m128i);
+            _mm_stream_si128(dst.as_mut_ptr().add(i * LANE) as *mut __m128i, v);
+        }
+    }
+
+    let tail = lanes * LANE;
+    dst[tail..].copy_from_slice(&src[tail..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_temporal_copies_match_ordinary_ones_at_any_alignment() {
+        let src: Vec<u8> = (0..200).map(|i| i as u8).collect();
+
+        for offset in 0..16 {
+            for len in [0, 1, 15, 16, 17, 64, 183] {
+                let mut dst = vec![0xee; 256];
+
+                copy(&mut dst[offset..], &src[..len], CopyMode::NonTemporal);
+                store_fence();
+
+                assert_eq!(dst[offset..offset + len], src[..len]);
+                assert!(dst[offset + len..].iter().all(|b| *b == 0xee));
+                assert!(dst[..offset].iter().all(|b| *b == 0xee));
+            }
+        }
+    }
+}
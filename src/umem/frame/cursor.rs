@@ -4,22 +4,43 @@ use std::io::{self, IoSlice, Write};
 
 use crate::util;
 
+use super::copy::{self, CopyMode};
+#[cfg(feature = "parse")]
+use super::UdpHeaders;
+
+fn write_zero() -> io::Error {
+    io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer")
+}
+
 /// Wraps a buffer and a value denoting its current write position and
 /// provides a convenient [`Write`] implementation.
 ///
 /// Practically it allows us to write to a [`Umem`](crate::umem::Umem) frame
 /// and update its descriptor's length at the same time, avoiding some
 /// potentially error prone logic.
+///
+/// Writes move the descriptor's length straight away. To avoid
+/// leaving a half-written packet's length behind when building one
+/// fails partway, [`commit`](Self::commit) once a packet is complete
+/// and [`rollback`](Self::rollback) on failure, or write it within
+/// [`transaction`](Self::transaction). The length the cursor was
+/// created with counts as committed.
 #[derive(Debug)]
 pub struct Cursor<'a> {
     pos: &'a mut usize,
+    committed: usize,
     buf: &'a mut [u8],
 }
 
 impl<'a> Cursor<'a> {
     #[inline]
     pub(super) fn new(pos: &'a mut usize, buf: &'a mut [u8]) -> Self {
-        Self { pos, buf }
+        let committed = *pos;
+        Self {
+            pos,
+            committed,
+            buf,
+        }
     }
 
     /// The cursor's current write position in the buffer.
@@ -40,6 +61,13 @@ impl<'a> Cursor<'a> {
         self.buf.len()
     }
 
+    /// The whole underlying buffer, regardless of the write position.
+    #[inline]
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    pub(crate) fn buf_mut(&mut self) -> &mut [u8] {
+        self.buf
+    }
+
     /// Fills the buffer with zeroes and sets the cursor's write
     /// position to the start of the buffer.
     #[inline]
@@ -47,6 +75,130 @@ impl<'a> Cursor<'a> {
         self.buf.fill(0);
         self.set_pos(0);
     }
+
+    /// The write position as of the last [`commit`](Self::commit), or
+    /// when the cursor was created.
+    #[inline]
+    pub fn committed_pos(&self) -> usize {
+        self.committed
+    }
+
+    /// Keep everything written so far, making the current position
+    /// the one [`rollback`](Self::rollback) returns to. Also done by
+    /// [`flush`](Write::flush).
+    #[inline]
+    pub fn commit(&mut self) {
+        self.committed = *self.pos;
+    }
+
+    /// Discard the length written since the last
+    /// [`commit`](Self::commit) by moving the write position back to
+    /// it. The bytes themselves aren't restored, they're just no
+    /// longer part of the frame.
+    #[inline]
+    pub fn rollback(&mut self) {
+        *self.pos = self.committed;
+    }
+
+    /// Write all of `buf` at the current position using `mode`, see
+    /// [`CopyMode`]. Unlike [`write_all`](Write::write_all), nothing
+    /// is written if `buf` doesn't fit.
+    ///
+    /// # Errors
+    ///
+    /// [`io::ErrorKind::WriteZero`] if there isn't room for `buf`.
+    #[inline]
+    pub fn write_all_with(&mut self, buf: &[u8], mode: CopyMode) -> io::Result<()> {
+        let pos = util::min_usize(*self.pos, self.buf.len());
+
+        if buf.len() > self.buf.len() - pos {
+            return Err(write_zero());
+        }
+
+        copy::copy(&mut self.buf[pos..], buf, mode);
+        *self.pos = pos + buf.len();
+
+        Ok(())
+    }
+
+    /// Write each of `bufs` in turn at the current position, e.g. a
+    /// header kept elsewhere then a payload. Unlike
+    /// [`write_vectored`](Write::write_vectored), nothing is written if
+    /// they don't all fit.
+    ///
+    /// # Errors
+    ///
+    /// [`io::ErrorKind::WriteZero`] if there isn't room for `bufs`.
+    #[inline]
+    pub fn extend_from_slices(&mut self, bufs: &[&[u8]]) -> io::Result<()> {
+        let mut pos = util::min_usize(*self.pos, self.buf.len());
+        let len: usize = bufs.iter().map(|buf| buf.len()).sum();
+
+        if len > self.buf.len() - pos {
+            return Err(write_zero());
+        }
+
+        for buf in bufs {
+            self.buf[pos..pos + buf.len()].copy_from_slice(buf);
+            pos += buf.len();
+        }
+
+        *self.pos = pos;
+
+        Ok(())
+    }
+
+    /// Write a UDP datagram carrying `payload` at the current
+    /// position, behind `headers` with their lengths and checksums
+    /// filled in, see [`UdpHeaders::write`]. Nothing is written if it
+    /// doesn't fit.
+    ///
+    /// # Errors
+    ///
+    /// [`io::ErrorKind::WriteZero`] if there isn't room for the
+    /// datagram, or [`io::ErrorKind::InvalidInput`] if `payload` is
+    /// longer than a UDP datagram can carry.
+    #[cfg(feature = "parse")]
+    pub fn write_udp(&mut self, headers: &UdpHeaders, payload: &[u8]) -> io::Result<()> {
+        let pos = util::min_usize(*self.pos, self.buf.len());
+        let hlen = headers.header_len();
+        let len = hlen + payload.len();
+
+        headers
+            .check(len)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        if len > self.buf.len() - pos {
+            return Err(write_zero());
+        }
+
+        let frame = &mut self.buf[pos..pos + len];
+        frame[hlen..].copy_from_slice(payload);
+        headers
+            .write(frame)
+            .expect("datagram checked before writing");
+
+        *self.pos = pos + len;
+
+        Ok(())
+    }
+
+    /// Run `f` on the cursor, committing if it returns `Ok` and rolling
+    /// back if it returns `Err`.
+    #[inline]
+    pub fn transaction<T, E, F>(&mut self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Self) -> Result<T, E>,
+    {
+        let res = f(self);
+
+        match res {
+            Ok(_) => self.commit(),
+            Err(_) => self.rollback(),
+        }
+
+        res
+    }
 }
 
 // Taken almost verbatim from
@@ -77,6 +229,7 @@ impl Write for Cursor<'_> {
 
     #[inline]
     fn flush(&mut self) -> io::Result<()> {
+        self.commit();
         Ok(())
     }
 }
@@ -149,4 +302,100 @@ mod tests {
         cursor.set_pos(33);
         assert_eq!(cursor.pos(), 32);
     }
+
+    #[test]
+    fn rollback_restores_the_committed_length() {
+        let mut pos = 0;
+        let mut buf = [0; 32];
+
+        {
+            let mut cursor = Cursor::new(&mut pos, &mut buf[..]);
+
+            cursor.write_all(b"hello").unwrap();
+            cursor.flush().unwrap();
+            assert_eq!(cursor.committed_pos(), 5);
+
+            cursor.write_all(b", wor").unwrap();
+            cursor.rollback();
+            assert_eq!(cursor.pos(), 5);
+
+            let res: io::Result<()> = cursor.transaction(|c| {
+                c.write_all(b", world")?;
+                c.write_all(&[0; 32])
+            });
+            assert!(res.is_err());
+            assert_eq!(cursor.pos(), 5);
+
+            cursor.transaction(|c| c.write_all(b", world")).unwrap();
+            assert_eq!(cursor.committed_pos(), 12);
+        }
+
+        assert_eq!(&buf[..pos], b"hello, world");
+
+        // A new cursor's starting length counts as committed.
+        let mut cursor = Cursor::new(&mut pos, &mut buf[..]);
+        cursor.write_all(b"!").unwrap();
+        cursor.rollback();
+        assert_eq!(cursor.pos(), 12);
+    }
+
+    #[test]
+    fn write_all_with_writes_all_or_nothing() {
+        let mut pos = 0;
+        let mut buf = [0; 32];
+
+        let mut cursor = Cursor::new(&mut pos, &mut buf[..]);
+
+        for mode in [CopyMode::Cached, CopyMode::NonTemporal] {
+            cursor.write_all_with(&[7; 12], mode).unwrap();
+        }
+        copy::store_fence();
+
+        let err = cursor.write_all_with(&[9; 9], CopyMode::NonTemporal);
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::WriteZero);
+        assert_eq!(cursor.pos(), 24);
+
+        assert_eq!(buf[..24], [7; 24]);
+        assert_eq!(buf[24..], [0; 8]);
+    }
+
+    #[test]
+    fn extend_from_slices_writes_all_or_nothing() {
+        let mut pos = 0;
+        let mut buf = [0; 8];
+
+        let mut cursor = Cursor::new(&mut pos, &mut buf[..]);
+
+        cursor.extend_from_slices(&[b"ab", b"", b"cde"]).unwrap();
+        assert_eq!(cursor.pos(), 5);
+
+        let err = cursor.extend_from_slices(&[b"fg", b"hi"]);
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::WriteZero);
+        assert_eq!(cursor.pos(), 5);
+
+        assert_eq!(&buf, b"abcde\0\0\0");
+    }
+
+    #[cfg(feature = "parse")]
+    #[test]
+    fn write_udp_puts_the_payload_behind_the_headers() {
+        let mut pos = 0;
+        let mut buf = [0; 64];
+
+        let headers = UdpHeaders::v4(
+            "10.0.0.1:1234".parse().unwrap(),
+            "10.0.0.2:53".parse().unwrap(),
+        );
+
+        let mut cursor = Cursor::new(&mut pos, &mut buf[..]);
+
+        cursor.write_udp(&headers, b"hello").unwrap();
+        assert_eq!(cursor.pos(), 42 + 5);
+
+        let err = cursor.write_udp(&headers, b"hello");
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::WriteZero);
+        assert_eq!(cursor.pos(), 42 + 5);
+
+        assert_eq!(&buf[42..47], b"hello");
+    }
 }
@@ -0,0 +1,140 @@
+use std::iter::FusedIterator;
+
+use super::FrameDesc;
+
+/// Splits a run of descriptors into packets, each being one or more
+/// fragments chained with [`XDP_PKT_CONTD`].
+///
+/// Without multi-buffer ([`XDP_USE_SG`]) every packet is a single
+/// descriptor. With it, a packet larger than a frame is received as
+/// several descriptors, all but the last marked as continued. The
+/// kernel only ever puts whole packets on the rx ring, but a consume
+/// of a fixed number of descriptors can still stop partway through
+/// one. Such a trailing partial packet isn't yielded, see
+/// [`remainder`](Self::remainder).
+///
+/// [`XDP_PKT_CONTD`]: super::DescOptions::XDP_PKT_CONTD
+/// [`XDP_USE_SG`]: crate::config::BindFlags::XDP_USE_SG
+#[derive(Debug, Clone)]
+pub struct Packets<'a> {
+    descs: &'a [FrameDesc],
+}
+
+impl<'a> Packets<'a> {
+    /// Iterate over the packets in `descs`.
+    #[inline]
+    pub fn new(descs: &'a [FrameDesc]) -> Self {
+        Self { descs }
+    }
+
+    /// The descriptors not yet yielded. Once iteration has finished
+    /// this is either empty or the start of a packet whose remaining
+    /// fragments are still on the ring, and which should be held on
+    /// to until they've been consumed.
+    #[inline]
+    pub fn remainder(&self) -> &'a [FrameDesc] {
+        self.descs
+    }
+}
+
+impl<'a> Iterator for Packets<'a> {
+    type Item = &'a [FrameDesc];
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let end = self.descs.iter().position(|d| !d.is_continued())?;
+        let (packet, rest) = self.descs.split_at(end + 1);
+        self.descs = rest;
+
+        Some(packet)
+    }
+}
+
+impl FusedIterator for Packets<'_> {}
+
+/// The total packet data length across a packet's fragments.
+#[inline]
+pub fn packet_len(fragments: &[FrameDesc]) -> usize {
+    fragments.iter().map(|d| d.lengths.data).sum()
+}
+
+/// Mark `fragments` as a single packet for transmission, setting
+/// [`XDP_PKT_CONTD`] on all but the last and clearing it on the last.
+///
+/// The kernel limits how many fragments a packet may have, by
+/// default 18 (`MAX_SKB_FRAGS + 1`), and drops the whole packet,
+/// counting an invalid descriptor, if it's exceeded.
+///
+/// [`XDP_PKT_CONTD`]: super::DescOptions::XDP_PKT_CONTD
+pub fn chain(fragments: &mut [FrameDesc]) {
+    if let Some((last, init)) = fragments.split_last_mut() {
+        for desc in init {
+            desc.set_continued(true);
+        }
+        last.set_continued(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::umem::frame::SegmentLengths;
+
+    use super::*;
+
+    fn desc(addr: usize, data: usize, contd: bool) -> FrameDesc {
+        let mut desc = FrameDesc {
+            addr,
+            options: 0,
+            lengths: SegmentLengths {
+                headroom: 0,
+                data,
+                meta: 0,
+            },
+        };
+        desc.set_continued(contd);
+        desc
+    }
+
+    #[test]
+    fn packets_split_on_contd_and_leave_partial_tail() {
+        let descs = [
+            desc(0, 100, false),
+            desc(1, 4096, true),
+            desc(2, 4096, true),
+            desc(3, 10, false),
+            desc(4, 4096, true),
+        ];
+
+        let mut packets = Packets::new(&descs);
+
+        let first = packets.next().unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(packet_len(first), 100);
+
+        let second = packets.next().unwrap();
+        assert_eq!(
+            second.iter().map(|d| d.addr()).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        assert_eq!(packet_len(second), 8202);
+
+        assert!(packets.next().is_none());
+        assert_eq!(packets.remainder().len(), 1);
+        assert_eq!(packets.remainder()[0].addr(), 4);
+    }
+
+    #[test]
+    fn chain_marks_all_but_last() {
+        let mut descs = [desc(0, 1, false), desc(1, 1, false), desc(2, 1, true)];
+        descs[2].set_options(descs[2].options() | 1 << 7);
+
+        chain(&mut descs);
+
+        let contd: Vec<_> = descs.iter().map(|d| d.is_continued()).collect();
+        assert_eq!(contd, [true, true, false]);
+        assert_eq!(descs[2].unknown_options(), 1 << 7);
+        assert_eq!(Packets::new(&descs).count(), 1);
+
+        chain(&mut []);
+    }
+}
@@ -0,0 +1,375 @@
+//! Ethernet, IP and UDP headers for building frames in place.
+
+use std::{
+    error, fmt,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6},
+};
+
+use crate::{
+    addr::{self, MacAddr},
+    checksum,
+    packet::{
+        ETH_HLEN, ETH_P_8021Q, ETH_P_IPV4, ETH_P_IPV6, IPPROTO_UDP, IPV4_MIN_HLEN, IPV6_HLEN,
+        VLAN_HLEN,
+    },
+};
+
+const UDP_HLEN: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+enum Ip {
+    V4(Ipv4Addr, Ipv4Addr),
+    V6(Ipv6Addr, Ipv6Addr),
+}
+
+/// The headers of a UDP datagram, written in front of its payload by
+/// [`Cursor::write_udp`](super::Cursor::write_udp) or
+/// [`write`](Self::write) with the lengths and checksums filled in:
+///
+/// ```
+/// # use xsk_rs::umem::frame::UdpHeaders;
+/// let src = "10.0.0.1:4000".parse().unwrap();
+/// let dst = "10.0.0.2:53".parse().unwrap();
+///
+/// let mut headers = UdpHeaders::v4(src, dst);
+/// headers.ether([0x02, 0, 0, 0, 0, 2], [0x02, 0, 0, 0, 0, 1]).vlan(100);
+///
+/// let mut frame = vec![0; headers.header_len() + 5];
+/// frame[headers.header_len()..].copy_from_slice(b"hello");
+/// headers.write(&mut frame).unwrap();
+///
+/// assert_eq!(frame.len(), 14 + 4 + 20 + 8 + 5);
+/// ```
+///
+/// The IPv4 header has no options, an ID of zero and don't fragment
+/// set. Fields not covered here can be patched in the written frame,
+/// adjusting the checksums with [`checksum::update`].
+///
+/// [`checksum::update`]: crate::checksum::update
+#[derive(Debug, Clone, Copy)]
+pub struct UdpHeaders {
+    ether: (MacAddr, MacAddr),
+    vlan: Option<u16>,
+    ip: Ip,
+    ttl: u8,
+    ports: (u16, u16),
+    udp_checksum: bool,
+}
+
+impl UdpHeaders {
+    fn new(ip: Ip, src_port: u16, dst_port: u16) -> Self {
+        Self {
+            ether: (MacAddr::UNSPECIFIED, MacAddr::UNSPECIFIED),
+            vlan: None,
+            ip,
+            ttl: 64,
+            ports: (src_port, dst_port),
+            udp_checksum: true,
+        }
+    }
+
+    /// Headers for a datagram over IPv4 from `src` to `dst`.
+    pub fn v4(src: SocketAddrV4, dst: SocketAddrV4) -> Self {
+        Self::new(Ip::V4(*src.ip(), *dst.ip()), src.port(), dst.port())
+    }
+
+    /// Headers for a datagram over IPv6 from `src` to `dst`.
+    pub fn v6(src: SocketAddrV6, dst: SocketAddrV6) -> Self {
+        Self::new(Ip::V6(*src.ip(), *dst.ip()), src.port(), dst.port())
+    }
+
+    /// Set the destination and source MAC addresses, as [`MacAddr`]s
+    /// or their octets. Defaults to all zeroes.
+    pub fn ether(&mut self, dst: impl Into<MacAddr>, src: impl Into<MacAddr>) -> &mut Self {
+        self.ether = (dst.into(), src.into());
+        self
+    }
+
+    /// Add an 802.1Q tag with the given VLAN ID.
+    pub fn vlan(&mut self, id: u16) -> &mut Self {
+        self.vlan = Some(id & 0x0fff);
+        self
+    }
+
+    /// Set the IPv4 TTL or IPv6 hop limit. Default is 64.
+    pub fn ttl(&mut self, ttl: u8) -> &mut Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Whether to fill in the UDP checksum over IPv4, where it's
+    /// optional, e.g. to leave it to checksum offload. Default is
+    /// `true`. Always filled in over IPv6, which requires it.
+    pub fn udp_checksum(&mut self, enabled: bool) -> &mut Self {
+        self.udp_checksum = enabled;
+        self
+    }
+
+    /// The length of the headers, i.e. the offset of the payload in
+    /// the frame.
+    pub fn header_len(&self) -> usize {
+        let vlan = if self.vlan.is_some() { VLAN_HLEN } else { 0 };
+
+        let ip = match self.ip {
+            Ip::V4(..) => IPV4_MIN_HLEN,
+            Ip::V6(..) => IPV6_HLEN,
+        };
+
+        ETH_HLEN + vlan + ip + UDP_HLEN
+    }
+
+    /// The longest payload the IP and UDP length fields allow: 65,507
+    /// bytes over IPv4, whose total length counts the IP header too,
+    /// and 65,527 over IPv6.
+    pub fn max_payload_len(&self) -> usize {
+        let ip = match self.ip {
+            Ip::V4(..) => IPV4_MIN_HLEN,
+            Ip::V6(..) => 0,
+        };
+
+        u16::MAX as usize - ip - UDP_HLEN
+    }
+
+    /// Check a datagram of `frame_len` bytes with these headers can be
+    /// written, without writing it.
+    ///
+    /// # Errors
+    ///
+    /// As for [`write`](Self::write).
+    pub fn check(&self, frame_len: usize) -> Result<(), UdpHeadersError> {
+        let header_len = self.header_len();
+
+        let payload_len = frame_len
+            .checked_sub(header_len)
+            .ok_or(UdpHeadersError::TooShort {
+                frame_len,
+                header_len,
+            })?;
+
+        if payload_len > self.max_payload_len() {
+            return Err(UdpHeadersError::TooLong {
+                payload_len,
+                max_payload_len: self.max_payload_len(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Write the headers to the start of `frame`, whose remainder past
+    /// [`header_len`](Self::header_len) is the payload, filling in the
+    /// lengths and checksums to match.
+    ///
+    /// # Errors
+    ///
+    /// If `frame` is shorter than [`header_len`](Self::header_len), or
+    /// its payload longer than
+    /// [`max_payload_len`](Self::max_payload_len), in which case
+    /// nothing is written.
+    pub fn write(&self, frame: &mut [u8]) -> Result<(), UdpHeadersError> {
+        self.check(frame.len())?;
+
+        let hlen = self.header_len();
+        let mut at = ETH_HLEN;
+
+        addr::write_mac(frame, 0, self.ether.0);
+        addr::write_mac(frame, 6, self.ether.1);
+
+        if let Some(id) = self.vlan {
+            frame[12..14].copy_from_slice(&ETH_P_8021Q.to_be_bytes());
+            frame[14..16].copy_from_slice(&id.to_be_bytes());
+            at += VLAN_HLEN;
+        }
+
+        let ethertype = match self.ip {
+            Ip::V4(..) => ETH_P_IPV4,
+            Ip::V6(..) => ETH_P_IPV6,
+        };
+        frame[at - 2..at].copy_from_slice(&ethertype.to_be_bytes());
+
+        let (ip, udp) = frame.split_at_mut(hlen - UDP_HLEN);
+        let ip = &mut ip[at..];
+
+        // Both fit in 16 bits, as checked above.
+        let udp_len = udp.len() as u16;
+        let ip_len = (ip.len() + udp.len()) as u16;
+
+        udp[0..2].copy_from_slice(&self.ports.0.to_be_bytes());
+        udp[2..4].copy_from_slice(&self.ports.1.to_be_bytes());
+        udp[4..6].copy_from_slice(&udp_len.to_be_bytes());
+
+        let check = match self.ip {
+            Ip::V4(src, dst) => {
+                ip.fill(0);
+                ip[0] = 0x45;
+                ip[2..4].copy_from_slice(&ip_len.to_be_bytes());
+                // Don't fragment.
+                ip[6] = 0x40;
+                ip[8] = self.ttl;
+                ip[9] = IPPROTO_UDP;
+                addr::write_ipv4(ip, 12, src);
+                addr::write_ipv4(ip, 16, dst);
+
+                let check = checksum::ipv4_header(ip);
+                ip[10..12].copy_from_slice(&check.to_be_bytes());
+
+                if self.udp_checksum {
+                    checksum::ipv4_l4(src, dst, IPPROTO_UDP, udp)
+                } else {
+                    0
+                }
+            }
+            Ip::V6(src, dst) => {
+                ip[0..4].copy_from_slice(&[0x60, 0, 0, 0]);
+                ip[4..6].copy_from_slice(&udp_len.to_be_bytes());
+                ip[6] = IPPROTO_UDP;
+                ip[7] = self.ttl;
+                addr::write_ipv6(ip, 8, src);
+                addr::write_ipv6(ip, 24, dst);
+
+                checksum::ipv6_l4(src, dst, IPPROTO_UDP, udp)
+            }
+        };
+
+        udp[6..8].copy_from_slice(&check.to_be_bytes());
+
+        Ok(())
+    }
+}
+
+/// Error returned by [`UdpHeaders::write`] when a frame can't hold a
+/// datagram with the headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdpHeadersError {
+    /// The frame is shorter than the headers.
+    TooShort {
+        /// The length of the frame.
+        frame_len: usize,
+        /// The length of the headers.
+        header_len: usize,
+    },
+    /// The payload is too long for the IP and UDP length fields.
+    TooLong {
+        /// The length of the payload.
+        payload_len: usize,
+        /// The longest payload the headers allow, see
+        /// [`UdpHeaders::max_payload_len`].
+        max_payload_len: usize,
+    },
+}
+
+impl fmt::Display for UdpHeadersError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort {
+                frame_len,
+                header_len,
+            } => write!(
+                f,
+                "{} byte frame too short for {} bytes of headers",
+                frame_len, header_len
+            ),
+            Self::TooLong {
+                payload_len,
+                max_payload_len,
+            } => write!(
+                f,
+                "{} byte payload longer than the {} a UDP datagram can carry",
+                payload_len, max_payload_len
+            ),
+        }
+    }
+}
+
+impl error::Error for UdpHeadersError {}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::*;
+    use crate::packet;
+
+    #[test]
+    fn headers_carry_lengths_and_valid_checksums() {
+        let mut headers = UdpHeaders::v4(
+            "10.0.0.1:1234".parse().unwrap(),
+            "10.0.0.2:53".parse().unwrap(),
+        );
+        headers.ether([1; 6], [2; 6]).vlan(5);
+
+        let hlen = headers.header_len();
+        let mut frame = vec![0xff; hlen + 3];
+        frame[hlen..].copy_from_slice(b"abc");
+        headers.write(&mut frame).unwrap();
+
+        assert_eq!(hlen, 14 + 4 + 20 + 8);
+        assert_eq!(addr::read_mac(&frame, 0), Some(MacAddr::from([1; 6])));
+        assert_eq!(packet::read_u16(&frame, 14), Some(5));
+        assert_eq!(packet::read_u16(&frame, 20), Some(20 + 8 + 3));
+        assert_eq!(checksum::checksum(&frame[18..38]), 0);
+        assert_eq!(packet::read_u16(&frame, 42), Some(8 + 3));
+        assert_eq!(
+            addr::ip_addrs(&frame),
+            Some((IpAddr::from([10, 0, 0, 1]), IpAddr::from([10, 0, 0, 2])))
+        );
+        assert_eq!(
+            packet::read_u16(&frame, 44),
+            Some(checksum::ipv4_l4(
+                [10, 0, 0, 1],
+                [10, 0, 0, 2],
+                IPPROTO_UDP,
+                &frame[38..]
+            ))
+        );
+
+        headers.udp_checksum(false).write(&mut frame).unwrap();
+        assert_eq!(packet::read_u16(&frame, 44), Some(0));
+
+        let headers = UdpHeaders::v6(
+            "[fe80::1]:1234".parse().unwrap(),
+            "[fe80::2]:53".parse().unwrap(),
+        );
+
+        let mut frame = vec![0; headers.header_len()];
+        headers.write(&mut frame).unwrap();
+
+        assert_eq!(frame.len(), 14 + 40 + 8);
+        assert_eq!(packet::read_u16(&frame, 18), Some(8));
+        // Worked by hand over the pseudo header and UDP header.
+        assert_eq!(packet::read_u16(&frame, 60), Some(0xfdd2));
+    }
+
+    #[test]
+    fn frames_which_dont_fit_the_length_fields_are_left_alone() {
+        let headers = UdpHeaders::v4(
+            "10.0.0.1:1234".parse().unwrap(),
+            "10.0.0.2:53".parse().unwrap(),
+        );
+        let hlen = headers.header_len();
+
+        assert_eq!(headers.max_payload_len(), 65507);
+
+        let mut frame = vec![0xaa; hlen + 65507];
+        headers.write(&mut frame).unwrap();
+        assert_eq!(packet::read_u16(&frame, 16), Some(u16::MAX));
+
+        let mut frame = vec![0xaa; hlen + 65508];
+        assert_eq!(
+            headers.write(&mut frame),
+            Err(UdpHeadersError::TooLong {
+                payload_len: 65508,
+                max_payload_len: 65507,
+            })
+        );
+        assert!(frame.iter().all(|&b| b == 0xaa));
+
+        assert_eq!(
+            headers.write(&mut [0; 10]),
+            Err(UdpHeadersError::TooShort {
+                frame_len: 10,
+                header_len: hlen,
+            })
+        );
+    }
+}
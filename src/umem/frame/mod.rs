@@ -1,16 +1,53 @@
 //! Types for representing and working with a [`Umem`](super::Umem)
 //! frame.
 
+mod batch;
+pub use batch::{DescBatch, DescBatchIter};
+
+mod copy;
+pub use copy::{store_fence, CopyMode};
+
 mod cursor;
 pub use cursor::Cursor;
 
+#[cfg(feature = "parse")]
+mod headers;
+#[cfg(feature = "parse")]
+pub use headers::{UdpHeaders, UdpHeadersError};
+
+mod fragments;
+pub use fragments::{chain, packet_len, Packets};
+
+mod rx_meta;
+pub use rx_meta::{RxMetadata, RxMetadataFields, RX_METADATA_LEN};
+
+use bitflags::bitflags;
 use std::{
     borrow::{Borrow, BorrowMut},
     ops::{Deref, DerefMut},
 };
 
+use crate::portable::FrameLayout;
+
+bitflags! {
+    /// Descriptor option bits understood by this library.
+    ///
+    /// Values match those in the linux source at
+    /// `include/uapi/linux/if_xdp.h`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct DescOptions: u32 {
+        /// The packet continues in the next descriptor. Only set
+        /// when multi-buffer ([`XDP_USE_SG`]) is in use.
+        ///
+        /// [`XDP_USE_SG`]: crate::config::BindFlags::XDP_USE_SG
+        const XDP_PKT_CONTD = 1 << 0;
+        /// The frame's headroom contains TX metadata.
+        const XDP_TX_METADATA = 1 << 1;
+    }
+}
+
 /// The length (in bytes) of data in a frame's packet data and
-/// headroom segments.
+/// headroom segments, and of any XDP metadata in front of the packet.
 ///
 /// Not to be confused with the [`frame_headroom`] and [`mtu`], the
 /// lengths here describe the amount of data that has been written to
@@ -21,12 +58,19 @@ use std::{
 /// will always be less than or equal to [`frame_headroom`], and
 /// `data` less than or equal to [`mtu`].
 ///
+/// The kernel doesn't report the length of the metadata an XDP
+/// program reserved with `bpf_xdp_adjust_meta`, so `meta` is whatever
+/// was agreed with the program, set with [`FrameDesc::set_meta_len`]
+/// or for each received frame with [`RxQueue::set_meta_len`].
+///
 /// [`frame_headroom`]: crate::config::UmemConfig::frame_headroom
 /// [`mtu`]: crate::config::UmemConfig::mtu
+/// [`RxQueue::set_meta_len`]: crate::RxQueue::set_meta_len
 #[derive(Debug, Default, Clone, Copy)]
 pub struct SegmentLengths {
     pub(crate) headroom: usize,
     pub(crate) data: usize,
+    pub(crate) meta: usize,
 }
 
 impl SegmentLengths {
@@ -41,6 +85,12 @@ impl SegmentLengths {
     pub fn data(&self) -> usize {
         self.data
     }
+
+    /// Length of the XDP metadata immediately before the packet data.
+    #[inline]
+    pub fn meta(&self) -> usize {
+        self.meta
+    }
 }
 
 /// A [`Umem`](super::Umem) frame descriptor.
@@ -78,6 +128,38 @@ impl FrameDesc {
         self.addr
     }
 
+    /// The chunk address in the lower 48 bits of [`addr`](Self::addr),
+    /// which is all of it unless the [`Umem`](super::Umem) is in
+    /// [unaligned chunk mode](super::Umem::unaligned_chunks).
+    #[inline]
+    pub fn unaligned_base(&self) -> usize {
+        FrameLayout::split_unaligned(self.addr).0
+    }
+
+    /// The offset from [`unaligned_base`](Self::unaligned_base) in the
+    /// upper 16 bits of [`addr`](Self::addr), at which the kernel puts
+    /// received packets in unaligned chunk mode. Always zero otherwise.
+    #[inline]
+    pub fn unaligned_offset(&self) -> u16 {
+        FrameLayout::split_unaligned(self.addr).1
+    }
+
+    /// Place the packet `offset` bytes on from
+    /// [`unaligned_base`](Self::unaligned_base), by setting the upper
+    /// 16 bits of [`addr`](Self::addr). The frame accessors on
+    /// [`Umem`](super::Umem) follow the offset, and the kernel adds it
+    /// to the base when sending.
+    ///
+    /// Only for a [`Umem`](super::Umem) in [unaligned chunk
+    /// mode](super::Umem::unaligned_chunks), the kernel rejects such
+    /// addresses otherwise. The writeable data segment is cut short by
+    /// the offset, so still ends with the chunk at the base.
+    #[inline]
+    pub fn set_unaligned_offset(&mut self, offset: u16) {
+        // The base is already in the lower 48 bits, so always fits.
+        self.addr = FrameLayout::encode_unaligned(self.unaligned_base(), offset).unwrap();
+    }
+
     /// Current headroom and packet data lengths for the frame pointed
     /// at by this descriptor.
     #[inline]
@@ -91,12 +173,52 @@ impl FrameDesc {
         self.options
     }
 
+    /// The frame options this library knows about. Any other bits
+    /// are ignored, see [`unknown_options`](Self::unknown_options).
+    #[inline]
+    pub fn desc_options(&self) -> DescOptions {
+        DescOptions::from_bits_truncate(self.options)
+    }
+
+    /// Any set option bits not covered by [`DescOptions`], for
+    /// example ones introduced by a newer kernel.
+    #[inline]
+    pub fn unknown_options(&self) -> u32 {
+        self.options & !DescOptions::all().bits()
+    }
+
+    /// Set the length of the XDP metadata in front of the packet
+    /// data, as written by the XDP program that redirected the frame,
+    /// see [`Umem::meta`](super::Umem::meta).
+    #[inline]
+    pub fn set_meta_len(&mut self, len: usize) {
+        self.lengths.meta = len
+    }
+
     /// Set the frame options.
     #[inline]
     pub fn set_options(&mut self, options: u32) {
         self.options = options
     }
 
+    /// Whether the packet continues in the next descriptor, i.e.
+    /// [`XDP_PKT_CONTD`](DescOptions::XDP_PKT_CONTD) is set.
+    #[inline]
+    pub fn is_continued(&self) -> bool {
+        self.options & DescOptions::XDP_PKT_CONTD.bits() != 0
+    }
+
+    /// Set or clear [`XDP_PKT_CONTD`](DescOptions::XDP_PKT_CONTD),
+    /// leaving other option bits alone. See also [`chain`].
+    #[inline]
+    pub fn set_continued(&mut self, continued: bool) {
+        if continued {
+            self.options |= DescOptions::XDP_PKT_CONTD.bits();
+        } else {
+            self.options &= !DescOptions::XDP_PKT_CONTD.bits();
+        }
+    }
+
     #[inline]
     pub(crate) fn write_xdp_desc(&self, desc: &mut libxdp_sys::xdp_desc) {
         desc.addr = self.addr as u64;
@@ -168,6 +290,49 @@ impl Deref for Headroom<'_> {
     }
 }
 
+/// XDP metadata in front of the packet data of a
+/// [`Umem`](crate::umem::Umem) frame, as reserved by an XDP program
+/// with `bpf_xdp_adjust_meta`, e.g. RX hash or timestamp hints.
+#[derive(Debug)]
+pub struct Meta<'umem> {
+    contents: &'umem [u8],
+}
+
+impl<'umem> Meta<'umem> {
+    pub(super) fn new(contents: &'umem [u8]) -> Self {
+        Self { contents }
+    }
+
+    /// Returns the metadata, up to its current length.
+    #[inline]
+    pub fn contents(&self) -> &'umem [u8] {
+        self.contents
+    }
+}
+
+impl AsRef<[u8]> for Meta<'_> {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        self.contents
+    }
+}
+
+impl Borrow<[u8]> for Meta<'_> {
+    #[inline]
+    fn borrow(&self) -> &[u8] {
+        self.contents
+    }
+}
+
+impl Deref for Meta<'_> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.contents
+    }
+}
+
 /// Mutable headroom segment of a [`Umem`](crate::umem::Umem) frame.
 #[derive(Debug)]
 pub struct HeadroomMut<'umem> {
@@ -269,6 +434,15 @@ impl<'umem> Data<'umem> {
     pub fn contents(&self) -> &'umem [u8] {
         self.contents
     }
+
+    /// Typed views of the packet's headers, borrowing from the frame.
+    /// Returns [`None`] if it doesn't hold a whole ethernet header, see
+    /// [`PacketView::parse`](crate::view::PacketView::parse).
+    #[cfg(feature = "parse")]
+    #[inline]
+    pub fn view(&self) -> Option<crate::view::PacketView<'umem>> {
+        crate::view::PacketView::parse(self.contents)
+    }
 }
 
 impl AsRef<[u8]> for Data<'_> {
@@ -324,6 +498,14 @@ impl<'umem> DataMut<'umem> {
         &mut self.buf[..*self.len]
     }
 
+    /// Split into the length of the segment's contents and the whole
+    /// writeable segment.
+    #[inline]
+    #[cfg_attr(not(feature = "parse"), allow(dead_code))]
+    pub(crate) fn into_parts(self) -> (&'umem mut usize, &'umem mut [u8]) {
+        (self.len, self.buf)
+    }
+
     /// A cursor for writing to this segment.
     ///
     /// Modifications via the cursor will change the length of the
@@ -383,6 +565,28 @@ impl DerefMut for DataMut<'_> {
     }
 }
 
+/// Descriptors for tests, laid out as in a UMEM of 2048 byte frames
+/// with the default headroom.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::FrameDesc;
+
+    pub(crate) const FRAME_SIZE: usize = 2048;
+
+    pub(crate) const HEADROOM: usize = 256;
+
+    /// A descriptor for the data segment of frame number `frame`.
+    pub(crate) fn desc(frame: usize) -> FrameDesc {
+        FrameDesc::new(frame * FRAME_SIZE + HEADROOM)
+    }
+
+    /// A descriptor for `addr` as is, for tests which only use the
+    /// address as a label.
+    pub(crate) fn desc_at(addr: usize) -> FrameDesc {
+        FrameDesc::new(addr)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use core::slice;
@@ -393,24 +597,41 @@ mod tests {
 
     use libxdp_sys::xdp_desc;
 
-    use crate::umem::{FrameDesc, FrameLayout, UmemRegion};
+    use crate::{
+        config::HugePages,
+        portable::FrameLayout,
+        umem::{FrameDesc, UmemRegion},
+    };
+
+    use super::DescOptions;
+
+    #[test]
+    fn unknown_option_bits_are_separated_from_known_ones() {
+        let mut desc = FrameDesc::default();
+
+        desc.set_options(DescOptions::XDP_PKT_CONTD.bits() | 1 << 7);
+
+        assert_eq!(desc.desc_options(), DescOptions::XDP_PKT_CONTD);
+        assert_eq!(desc.unknown_options(), 1 << 7);
+
+        desc.set_options(DescOptions::all().bits());
+
+        assert_eq!(desc.unknown_options(), 0);
+    }
 
     #[test]
     fn writes_persist() {
-        let layout = FrameLayout {
-            xdp_headroom: 0,
-            frame_headroom: 512,
-            mtu: 2048,
-        };
+        let layout = FrameLayout::new(2560, 0, 512).unwrap();
 
         let frame_count = 16.try_into().unwrap();
         let frame_size = layout.frame_size();
 
-        let umem_region = UmemRegion::new(frame_count, layout, false).unwrap();
+        let umem_region =
+            UmemRegion::new(frame_count, layout, HugePages::Off, false, None, false).unwrap();
 
-        let mut desc_0 = FrameDesc::new(0 * frame_size + layout.frame_headroom);
+        let mut desc_0 = FrameDesc::new(0 * frame_size + layout.frame_headroom());
 
-        let mut desc_1 = FrameDesc::new(1 * frame_size + layout.frame_headroom);
+        let mut desc_1 = FrameDesc::new(1 * frame_size + layout.frame_headroom());
 
         let mut xdp_desc = xdp_desc {
             addr: 0,
@@ -427,7 +648,7 @@ mod tests {
 
         assert_eq!(
             xdp_desc.addr,
-            (0 * frame_size + layout.frame_headroom) as u64
+            (0 * frame_size + layout.frame_headroom()) as u64
         );
         assert_eq!(xdp_desc.len, 5);
         assert_eq!(xdp_desc.options, 0);
@@ -441,7 +662,7 @@ mod tests {
 
         assert_eq!(
             xdp_desc.addr,
-            (1 * frame_size + layout.frame_headroom) as u64
+            (1 * frame_size + layout.frame_headroom()) as u64
         );
         assert_eq!(xdp_desc.len, 6);
         assert_eq!(xdp_desc.options, 0);
@@ -451,7 +672,7 @@ mod tests {
                 slice::from_raw_parts(
                     umem_region
                         .as_ptr()
-                        .add(0 * frame_size + layout.frame_headroom)
+                        .add(0 * frame_size + layout.frame_headroom())
                         as *const u8,
                     5,
                 )
@@ -464,7 +685,7 @@ mod tests {
                 slice::from_raw_parts(
                     umem_region
                         .as_ptr()
-                        .add(1 * frame_size + layout.frame_headroom)
+                        .add(1 * frame_size + layout.frame_headroom())
                         as *const u8,
                     6,
                 )
@@ -473,16 +694,64 @@ mod tests {
         );
     }
 
+    #[test]
+    fn unaligned_offsets_move_the_packet_data() {
+        let layout = FrameLayout::new(4096, 0, 0).unwrap();
+
+        let umem_region = UmemRegion::new(
+            2.try_into().unwrap(),
+            layout,
+            HugePages::Off,
+            false,
+            None,
+            false,
+        )
+        .unwrap();
+
+        // The last frame, so a segment left at the MTU would run past
+        // the end of the region.
+        let mut desc = FrameDesc::new(4096);
+        assert_eq!(
+            unsafe { umem_region.data_mut(&mut desc) }
+                .into_parts()
+                .1
+                .len(),
+            4096
+        );
+
+        desc.set_unaligned_offset(100);
+
+        assert_eq!(desc.unaligned_base(), 4096);
+        assert_eq!(desc.unaligned_offset(), 100);
+
+        let mut data = unsafe { umem_region.data_mut(&mut desc) };
+        data.cursor().write_all(b"hello").unwrap();
+        assert_eq!(data.into_parts().1.len(), 4096 - 100);
+
+        assert_eq!(
+            unsafe { slice::from_raw_parts(umem_region.as_ptr().add(4196) as *const u8, 5) },
+            b"hello"
+        );
+
+        let (_, data) = unsafe { umem_region.frame_mut(&mut desc) };
+        assert_eq!(data.into_parts().1.len(), 4096 - 100);
+
+        // Metadata can reach back to the chunk start but not past it.
+        desc.set_meta_len(200);
+        assert_eq!(unsafe { umem_region.meta(&desc) }.len(), 100);
+        desc.set_meta_len(0);
+
+        desc.set_unaligned_offset(0);
+        assert_eq!(desc.addr(), 4096);
+    }
+
     #[test]
     fn writes_are_contiguous() {
-        let layout = FrameLayout {
-            xdp_headroom: 4,
-            frame_headroom: 8,
-            mtu: 12,
-        };
+        let layout = FrameLayout::new(24, 4, 8).unwrap();
 
         let frame_count = 4.try_into().unwrap();
-        let umem_region = UmemRegion::new(frame_count, layout, false).unwrap();
+        let umem_region =
+            UmemRegion::new(frame_count, layout, HugePages::Off, false, None, false).unwrap();
 
         // An arbitrary layout
         let xdp_headroom_segment = [0, 0, 0, 0];
@@ -510,7 +779,7 @@ mod tests {
 
         (0..frame_count.get() as usize).into_iter().for_each(|i| {
             let mut desc = FrameDesc::new(
-                (i * layout.frame_size()) + layout.xdp_headroom + layout.frame_headroom,
+                (i * layout.frame_size()) + layout.xdp_headroom() + layout.frame_headroom(),
             );
 
             let (mut headroom, mut data) = unsafe { umem_region.frame_mut(&mut desc) };
@@ -0,0 +1,153 @@
+//! Hardware RX hints an XDP program has copied into the metadata area
+//! in front of a frame's packet data.
+
+use bitflags::bitflags;
+use std::convert::TryInto;
+
+/// The length of [`RxMetadata`] in the metadata area. Pass to
+/// [`RxQueue::set_meta_len`](crate::RxQueue::set_meta_len) so received
+/// frames are marked as carrying it.
+pub const RX_METADATA_LEN: usize = 32;
+
+bitflags! {
+    /// Which fields of an [`RxMetadata`] the driver filled in.
+    ///
+    /// Values match `enum xdp_meta_field` in the linux source at
+    /// `tools/testing/selftests/bpf/xdp_metadata.h`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct RxMetadataFields: u32 {
+        /// The RX timestamp, from `bpf_xdp_metadata_rx_timestamp`.
+        const TIMESTAMP = 1 << 0;
+        /// The RX hash and its type, from `bpf_xdp_metadata_rx_hash`.
+        const HASH = 1 << 1;
+        /// The VLAN tag, from `bpf_xdp_metadata_rx_vlan_tag`.
+        const VLAN_TAG = 1 << 2;
+    }
+}
+
+/// The hints a driver provides through the XDP RX metadata kfuncs,
+/// as laid out by an XDP program in the metadata area.
+///
+/// The kernel itself doesn't fix a layout, so this follows the one
+/// used by its `xdp_hw_metadata` selftest, which programs written for
+/// AF_XDP have largely adopted: the `struct xdp_meta` of
+/// `tools/testing/selftests/bpf/xdp_metadata.h`, placed immediately
+/// before the packet data with `bpf_xdp_adjust_meta`. Fields are in
+/// host byte order bar the VLAN protocol, which is big-endian as
+/// returned by the kfunc.
+///
+/// Read it from a received frame with
+/// [`Umem::rx_metadata`](crate::Umem::rx_metadata).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RxMetadata {
+    rx_timestamp: u64,
+    xdp_timestamp: u64,
+    rx_hash: u32,
+    rx_hash_type: u32,
+    vlan_proto: u16,
+    vlan_tci: u16,
+    fields: RxMetadataFields,
+}
+
+impl RxMetadata {
+    /// Read the metadata from the last [`RX_METADATA_LEN`] bytes of
+    /// `meta`, i.e. those just before the packet data, or [`None`] if
+    /// it's shorter than that.
+    pub fn parse(meta: &[u8]) -> Option<Self> {
+        let start = meta.len().checked_sub(RX_METADATA_LEN)?;
+        let meta = &meta[start..];
+
+        let u64_at = |off: usize| u64::from_ne_bytes(meta[off..off + 8].try_into().unwrap());
+        let u32_at = |off: usize| u32::from_ne_bytes(meta[off..off + 4].try_into().unwrap());
+
+        Some(Self {
+            rx_timestamp: u64_at(0),
+            xdp_timestamp: u64_at(8),
+            rx_hash: u32_at(16),
+            rx_hash_type: u32_at(20),
+            vlan_proto: u16::from_be_bytes([meta[24], meta[25]]),
+            vlan_tci: u16::from_ne_bytes([meta[26], meta[27]]),
+            fields: RxMetadataFields::from_bits_truncate(u32_at(28)),
+        })
+    }
+
+    /// The fields the driver filled in.
+    #[inline]
+    pub fn fields(&self) -> RxMetadataFields {
+        self.fields
+    }
+
+    /// When the NIC received the packet, in nanoseconds by its clock,
+    /// if the driver provided it.
+    #[inline]
+    pub fn rx_timestamp(&self) -> Option<u64> {
+        self.fields
+            .contains(RxMetadataFields::TIMESTAMP)
+            .then_some(self.rx_timestamp)
+    }
+
+    /// When the XDP program ran, in nanoseconds of `CLOCK_TAI`, if it
+    /// recorded it, else zero.
+    #[inline]
+    pub fn xdp_timestamp(&self) -> u64 {
+        self.xdp_timestamp
+    }
+
+    /// The hash the NIC computed for RSS, if the driver provided it.
+    #[inline]
+    pub fn rx_hash(&self) -> Option<u32> {
+        self.fields
+            .contains(RxMetadataFields::HASH)
+            .then_some(self.rx_hash)
+    }
+
+    /// What [`rx_hash`](Self::rx_hash) was computed over, as the
+    /// kernel's `enum xdp_rss_hash_type`.
+    #[inline]
+    pub fn rx_hash_type(&self) -> Option<u32> {
+        self.fields
+            .contains(RxMetadataFields::HASH)
+            .then_some(self.rx_hash_type)
+    }
+
+    /// The VLAN protocol and TCI the NIC stripped from the packet, if
+    /// the driver provided them.
+    #[inline]
+    pub fn vlan(&self) -> Option<(u16, u16)> {
+        self.fields
+            .contains(RxMetadataFields::VLAN_TAG)
+            .then_some((self.vlan_proto, self.vlan_tci))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_fields_the_driver_filled_in_are_returned() {
+        let mut meta = vec![0xff; 4];
+
+        meta.extend_from_slice(&1_000u64.to_ne_bytes());
+        meta.extend_from_slice(&2_000u64.to_ne_bytes());
+        meta.extend_from_slice(&0xdead_beefu32.to_ne_bytes());
+        meta.extend_from_slice(&3u32.to_ne_bytes());
+        meta.extend_from_slice(&0x8100u16.to_be_bytes());
+        meta.extend_from_slice(&42u16.to_ne_bytes());
+        meta.extend_from_slice(
+            &(RxMetadataFields::TIMESTAMP | RxMetadataFields::VLAN_TAG)
+                .bits()
+                .to_ne_bytes(),
+        );
+
+        let hints = RxMetadata::parse(&meta).unwrap();
+
+        assert_eq!(hints.rx_timestamp(), Some(1_000));
+        assert_eq!(hints.xdp_timestamp(), 2_000);
+        assert_eq!(hints.rx_hash(), None);
+        assert_eq!(hints.rx_hash_type(), None);
+        assert_eq!(hints.vlan(), Some((0x8100, 42)));
+
+        assert!(RxMetadata::parse(&meta[5..]).is_none());
+    }
+}
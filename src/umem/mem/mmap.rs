@@ -1,37 +1,121 @@
 pub use inner::Mmap;
 
-use std::{io, ptr::NonNull};
+use std::{fs, io, ptr::NonNull};
+
+use crate::config::HugePages;
+
+const SIZE_2M: usize = 2 << 20;
+const SIZE_1G: usize = 1 << 30;
+
+/// The size of the huge pages `huge_pages` asks for, or `None` for
+/// regular pages.
+fn huge_page_size(huge_pages: HugePages) -> Option<usize> {
+    match huge_pages {
+        HugePages::Off => None,
+        HugePages::Size2M => Some(SIZE_2M),
+        HugePages::Size1G => Some(SIZE_1G),
+        HugePages::Default => Some(
+            fs::read_to_string("/proc/meminfo")
+                .ok()
+                .and_then(|meminfo| default_huge_page_size(&meminfo))
+                .unwrap_or(SIZE_2M),
+        ),
+    }
+}
+
+fn default_huge_page_size(meminfo: &str) -> Option<usize> {
+    let line = meminfo.lines().find(|l| l.starts_with("Hugepagesize:"))?;
+    let kb: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+
+    Some(kb * 1024)
+}
+
+/// `len` rounded up to a whole number of `page_size` pages, as
+/// `munmap` needs for huge page mappings.
+fn round_up(len: usize, page_size: usize) -> usize {
+    len.div_ceil(page_size) * page_size
+}
 
 #[cfg(not(test))]
 mod inner {
     use libc::{
-        MAP_ANONYMOUS, MAP_FAILED, MAP_HUGETLB, MAP_POPULATE, MAP_SHARED, PROT_READ, PROT_WRITE,
+        MAP_ANONYMOUS, MAP_FAILED, MAP_HUGETLB, MAP_HUGE_1GB, MAP_HUGE_2MB, MAP_POPULATE,
+        MAP_SHARED, MFD_CLOEXEC, MFD_HUGETLB, MFD_HUGE_1GB, MFD_HUGE_2MB, PROT_READ, PROT_WRITE,
     };
     use log::error;
-    use std::ptr;
+    use std::{
+        ffi::CStr,
+        os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        ptr,
+    };
 
     use super::*;
+    use crate::numa;
+
+    /// `MADV_POPULATE_WRITE` from `linux/mman.h`, since 5.14.
+    const MADV_POPULATE_WRITE: libc::c_int = 23;
 
     /// An anonymous memory mapped region.
     #[derive(Debug)]
     pub struct Mmap {
         addr: NonNull<libc::c_void>,
         len: usize,
+        memfd: Option<OwnedFd>,
     }
 
     unsafe impl Send for Mmap {}
 
     impl Mmap {
-        pub fn new(len: usize, use_huge_pages: bool) -> io::Result<Self> {
-            // MAP_ANONYMOUS: mapping not backed by a file.
+        pub fn new(
+            len: usize,
+            huge_pages: HugePages,
+            numa_node: Option<u32>,
+            memfd: bool,
+        ) -> io::Result<Self> {
+            // MAP_ANONYMOUS: mapping not backed by a file, unless a
+            // memfd is asked for.
             // MAP_SHARED: shares this mapping, so changes are visible
             // to other processes mapping the same file.
             // MAP_POPULATE: pre-populate page tables, reduces
-            // blocking on page faults later.
-            let mut flags = MAP_ANONYMOUS | MAP_SHARED | MAP_POPULATE;
+            // blocking on page faults later. Left for after binding
+            // the region if a NUMA node is given, otherwise the pages
+            // would be allocated before the policy applies.
+            let mut flags = MAP_SHARED;
+            let mut memfd_flags = MFD_CLOEXEC;
+            let mut len = len;
+
+            if numa_node.is_none() {
+                flags |= MAP_POPULATE;
+            }
 
-            if use_huge_pages {
+            if let Some(huge_page_size) = huge_page_size(huge_pages) {
                 flags |= MAP_HUGETLB;
+                memfd_flags |= MFD_HUGETLB;
+                len = round_up(len, huge_page_size);
+
+                match huge_pages {
+                    HugePages::Size2M => {
+                        flags |= MAP_HUGE_2MB;
+                        memfd_flags |= MFD_HUGE_2MB;
+                    }
+                    HugePages::Size1G => {
+                        flags |= MAP_HUGE_1GB;
+                        memfd_flags |= MFD_HUGE_1GB;
+                    }
+                    _ => (),
+                }
+            }
+
+            let memfd = if memfd {
+                Some(Self::create_memfd(len, memfd_flags)?)
+            } else {
+                flags |= MAP_ANONYMOUS;
+                None
+            };
+
+            // The memfd's own huge page flags decide its pages.
+            if memfd.is_some() {
+                flags &= !(MAP_HUGETLB | MAP_HUGE_2MB | MAP_HUGE_1GB);
             }
 
             let addr = unsafe {
@@ -40,8 +124,8 @@ mod inner {
                     len,
                     PROT_READ | PROT_WRITE, // prot
                     flags,
-                    -1, // file
-                    0,  // offset
+                    memfd.as_ref().map_or(-1, |fd| fd.as_raw_fd()), // file
+                    0,                                              // offset
                 )
             };
 
@@ -51,15 +135,77 @@ mod inner {
                 let addr =
                     NonNull::new(addr).expect("ptr non-null since we confirmed `mmap()` succeeded");
 
-                Ok(Mmap { addr, len })
+                let mmap = Mmap { addr, len, memfd };
+
+                if let Some(node) = numa_node {
+                    numa::bind_memory(addr.as_ptr(), len, node)?;
+                    mmap.populate()?;
+                }
+
+                Ok(mmap)
             }
         }
 
+        /// A memfd of `len` bytes created with `flags`.
+        fn create_memfd(len: usize, flags: libc::c_uint) -> io::Result<OwnedFd> {
+            let name = CStr::from_bytes_with_nul(b"xsk-umem\0").unwrap();
+
+            let fd = unsafe { libc::memfd_create(name.as_ptr(), flags) };
+
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // SAFETY: `fd` was just created and nothing else owns it.
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+            if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(fd)
+        }
+
+        /// Fault in every page of the region, as `MAP_POPULATE` would,
+        /// but failing, with `ENOMEM` if there isn't the memory to back
+        /// it, rather than leaving a later fault to raise `SIGBUS`.
+        fn populate(&self) -> io::Result<()> {
+            let addr = self.addr.as_ptr();
+
+            if unsafe { libc::madvise(addr, self.len, MADV_POPULATE_WRITE) } == 0 {
+                return Ok(());
+            }
+
+            let err = io::Error::last_os_error();
+
+            // Kernels before 5.14 don't know the advice, but locking
+            // the region faults it in too.
+            if err.raw_os_error() != Some(libc::EINVAL) {
+                return Err(err);
+            }
+
+            if unsafe { libc::mlock(addr, self.len) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // The pages stay put once faulted in, and registering the
+            // UMEM pins them again anyway.
+            unsafe { libc::munlock(addr, self.len) };
+
+            Ok(())
+        }
+
         /// Returns a pointer to the start of the mmap'd region.
         #[inline]
         pub fn addr(&self) -> NonNull<libc::c_void> {
             self.addr
         }
+
+        /// The memfd backing the region, if it has one.
+        #[inline]
+        pub fn memfd(&self) -> Option<RawFd> {
+            self.memfd.as_ref().map(AsRawFd::as_raw_fd)
+        }
     }
 
     impl Drop for Mmap {
@@ -114,7 +260,12 @@ mod inner {
     pub struct Mmap(VecParts<u8>);
 
     impl Mmap {
-        pub fn new(len: usize, _use_huge_pages: bool) -> io::Result<Self> {
+        pub fn new(
+            len: usize,
+            _huge_pages: HugePages,
+            _numa_node: Option<u32>,
+            _memfd: bool,
+        ) -> io::Result<Self> {
             Ok(Self(VecParts::new(vec![0; len])))
         }
 
@@ -123,11 +274,33 @@ mod inner {
         pub fn addr(&self) -> NonNull<libc::c_void> {
             NonNull::new(self.0.ptr.as_ptr() as *mut libc::c_void).unwrap()
         }
+
+        /// The heap has no fd to hand out.
+        #[inline]
+        pub fn memfd(&self) -> Option<std::os::unix::io::RawFd> {
+            None
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn huge_page_mappings_are_whole_pages() {
+        let meminfo = "MemTotal:       16318108 kB\nHugepagesize:    1048576 kB\n";
+        assert_eq!(default_huge_page_size(meminfo), Some(SIZE_1G));
+        assert_eq!(default_huge_page_size("MemTotal: 1 kB\n"), None);
+
+        assert_eq!(huge_page_size(HugePages::Off), None);
+        assert_eq!(huge_page_size(HugePages::Size2M), Some(SIZE_2M));
+
+        assert_eq!(round_up(1, SIZE_2M), SIZE_2M);
+        assert_eq!(round_up(SIZE_2M, SIZE_2M), SIZE_2M);
+        assert_eq!(round_up(SIZE_2M + 1, SIZE_2M), 2 * SIZE_2M);
+    }
+
     #[test]
     fn confirm_pointer_offset_is_a_single_byte() {
         assert_eq!(std::mem::size_of::<libc::c_void>(), 1);
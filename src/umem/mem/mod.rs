@@ -4,15 +4,17 @@ use mmap::Mmap;
 use std::{
     io,
     num::NonZeroU32,
+    os::unix::io::{BorrowedFd, RawFd},
     ptr::NonNull,
     slice,
     sync::{Arc, Mutex},
 };
 
-use super::{
-    frame::{Data, DataMut, FrameDesc, Headroom, HeadroomMut},
-    FrameLayout,
-};
+use log::warn;
+
+use crate::{config::HugePages, portable::FrameLayout};
+
+use super::frame::{Data, DataMut, FrameDesc, Headroom, HeadroomMut, Meta};
 
 /// A framed, memory mapped region which functions as the working
 /// memory for some UMEM.
@@ -25,6 +27,9 @@ pub struct UmemRegion {
     // region.
     addr: NonNull<libc::c_void>,
     len: usize,
+    huge_pages: HugePages,
+    // Valid for as long as `_mmap`, which owns it.
+    memfd: Option<RawFd>,
     _mmap: Arc<Mutex<Mmap>>,
 }
 
@@ -37,19 +42,41 @@ unsafe impl Send for UmemRegion {}
 unsafe impl Sync for UmemRegion {}
 
 impl UmemRegion {
+    /// Map a region for `frame_count` frames, backed by `huge_pages`
+    /// and bound to `numa_node` if given, in a memfd if `memfd`. If
+    /// huge pages can't be had, falls back to regular pages unless
+    /// `required`.
     pub(super) fn new(
         frame_count: NonZeroU32,
         frame_layout: FrameLayout,
-        use_huge_pages: bool,
+        huge_pages: HugePages,
+        required: bool,
+        numa_node: Option<u32>,
+        memfd: bool,
     ) -> io::Result<Self> {
         let len = (frame_count.get() as usize) * frame_layout.frame_size();
 
-        let mmap = Mmap::new(len, use_huge_pages)?;
+        let (mmap, huge_pages) = match Mmap::new(len, huge_pages, numa_node, memfd) {
+            Ok(mmap) => (mmap, huge_pages),
+            Err(e) if huge_pages != HugePages::Off && !required => {
+                warn!(
+                    "failed to map UMEM with {:?} huge pages, using regular pages: {}",
+                    huge_pages, e
+                );
+                (
+                    Mmap::new(len, HugePages::Off, numa_node, memfd)?,
+                    HugePages::Off,
+                )
+            }
+            Err(e) => return Err(e),
+        };
 
         Ok(Self {
             layout: frame_layout,
             addr: mmap.addr(),
             len,
+            huge_pages,
+            memfd: mmap.memfd(),
             _mmap: Arc::new(Mutex::new(mmap)),
         })
     }
@@ -60,12 +87,79 @@ impl UmemRegion {
         self.len
     }
 
+    /// The pages backing the region.
+    #[inline]
+    pub fn huge_pages(&self) -> HugePages {
+        self.huge_pages
+    }
+
+    /// The memfd backing the region, if it was mapped from one.
+    #[inline]
+    pub fn memfd(&self) -> Option<BorrowedFd<'_>> {
+        // SAFETY: the fd is owned by the mmap, which lives as long as
+        // `self`.
+        self.memfd.map(|fd| unsafe { BorrowedFd::borrow_raw(fd) })
+    }
+
+    /// The layout of each frame in the region.
+    #[inline]
+    pub fn layout(&self) -> FrameLayout {
+        self.layout
+    }
+
+    /// The size of each frame in the region.
+    #[inline]
+    pub fn frame_size(&self) -> usize {
+        self.layout.frame_size()
+    }
+
+    /// The size of each frame's packet data segment.
+    #[inline]
+    pub fn frame_mtu(&self) -> usize {
+        self.layout.mtu()
+    }
+
     /// Get a pointer to the start of the memory region.
     #[inline]
     pub fn as_ptr(&self) -> *mut libc::c_void {
         self.addr.as_ptr()
     }
 
+    /// The offset of `desc`'s packet data from the start of the
+    /// region. Resolving the address as in unaligned chunk mode is
+    /// harmless in aligned mode, where the upper bits are never set.
+    #[inline]
+    fn resolve(desc: &FrameDesc) -> usize {
+        FrameLayout::resolve_addr(desc.addr, true)
+    }
+
+    /// The room for packet data at `desc`, at most the MTU but cut
+    /// short so it ends with the chunk at `desc`'s unaligned base, or
+    /// the region if that comes first.
+    #[inline]
+    fn data_capacity(&self, desc: &FrameDesc) -> usize {
+        let (base, offset) = FrameLayout::split_unaligned(desc.addr);
+        let chunk_end = base.saturating_add(self.frame_size()).min(self.len);
+
+        self.layout
+            .mtu()
+            .min(chunk_end.saturating_sub(base + offset as usize))
+    }
+
+    /// How far in front of `desc`'s packet data its chunk starts, so
+    /// the most metadata there can be room for.
+    ///
+    /// Descriptors with an unaligned offset are relative to the chunk
+    /// start, as the kernel hands them out. Otherwise the address is
+    /// that of the packet data segment, with the headroom in front.
+    #[inline]
+    fn chunk_lead(&self, desc: &FrameDesc) -> usize {
+        match FrameLayout::split_unaligned(desc.addr) {
+            (base, 0) => base.min(self.layout.xdp_headroom() + self.layout.frame_headroom()),
+            (_, offset) => offset as usize,
+        }
+    }
+
     /// A pointer to the headroom segment of the frame described by
     /// `desc`.
     ///
@@ -74,7 +168,7 @@ impl UmemRegion {
     /// `desc` must describe a frame belonging to this [`UmemRegion`].
     #[inline]
     unsafe fn headroom_ptr(&self, desc: &FrameDesc) -> *mut u8 {
-        let addr = desc.addr - self.layout.frame_headroom;
+        let addr = self.layout.headroom_addr(Self::resolve(desc));
         unsafe { self.as_ptr().add(addr) as *mut u8 }
     }
 
@@ -86,7 +180,7 @@ impl UmemRegion {
     /// `desc` must describe a frame belonging to this [`UmemRegion`].
     #[inline]
     unsafe fn data_ptr(&self, desc: &FrameDesc) -> *mut u8 {
-        unsafe { self.as_ptr().add(desc.addr) as *mut u8 }
+        unsafe { self.as_ptr().add(Self::resolve(desc)) as *mut u8 }
     }
 
     /// See docs for [`super::Umem::frame`].
@@ -114,6 +208,18 @@ impl UmemRegion {
         Data::new(unsafe { slice::from_raw_parts(data_ptr, desc.lengths.data) })
     }
 
+    /// See docs for [`super::Umem::meta`].
+    #[inline]
+    pub unsafe fn meta(&self, desc: &FrameDesc) -> Meta<'_> {
+        // The metadata can't reach back past the start of the chunk.
+        let len = desc.lengths.meta.min(self.chunk_lead(desc));
+
+        // SAFETY: see `frame`, and `len` keeps the slice in the chunk.
+        let data_ptr = unsafe { self.data_ptr(desc) };
+
+        Meta::new(unsafe { slice::from_raw_parts(data_ptr.sub(len), len) })
+    }
+
     /// See docs for [`super::Umem::frame_mut`].
     #[inline]
     pub unsafe fn frame_mut<'a>(
@@ -125,9 +231,10 @@ impl UmemRegion {
         let data_ptr = unsafe { self.data_ptr(desc) };
 
         let headroom =
-            unsafe { slice::from_raw_parts_mut(headroom_ptr, self.layout.frame_headroom) };
+            unsafe { slice::from_raw_parts_mut(headroom_ptr, self.layout.frame_headroom()) };
 
-        let data = unsafe { slice::from_raw_parts_mut(data_ptr, self.layout.mtu) };
+        // SAFETY: the capacity keeps the slice in the chunk and region.
+        let data = unsafe { slice::from_raw_parts_mut(data_ptr, self.data_capacity(desc)) };
 
         (
             HeadroomMut::new(&mut desc.lengths.headroom, headroom),
@@ -142,7 +249,7 @@ impl UmemRegion {
         let headroom_ptr = unsafe { self.headroom_ptr(desc) };
 
         let headroom =
-            unsafe { slice::from_raw_parts_mut(headroom_ptr, self.layout.frame_headroom) };
+            unsafe { slice::from_raw_parts_mut(headroom_ptr, self.layout.frame_headroom()) };
 
         HeadroomMut::new(&mut desc.lengths.headroom, headroom)
     }
@@ -153,7 +260,23 @@ impl UmemRegion {
         // SAFETY: see `frame_mut`.
         let data_ptr = unsafe { self.data_ptr(desc) };
 
-        let data = unsafe { slice::from_raw_parts_mut(data_ptr, self.layout.mtu) };
+        // SAFETY: the capacity keeps the slice in the chunk and region.
+        let data = unsafe { slice::from_raw_parts_mut(data_ptr, self.data_capacity(desc)) };
+
+        DataMut::new(&mut desc.lengths.data, data)
+    }
+
+    /// See docs for [`super::Umem::slot_data_mut`].
+    #[inline]
+    pub unsafe fn slot_data_mut<'a>(
+        &'a self,
+        desc: &'a mut FrameDesc,
+        slot_size: usize,
+    ) -> DataMut<'a> {
+        // SAFETY: see `frame_mut`.
+        let data_ptr = unsafe { self.data_ptr(desc) };
+
+        let data = unsafe { slice::from_raw_parts_mut(data_ptr, slot_size) };
 
         DataMut::new(&mut desc.lengths.data, data)
     }
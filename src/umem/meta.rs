@@ -0,0 +1,127 @@
+//! Per-frame application state.
+
+use std::slice;
+
+use super::{frame::FrameDesc, Umem};
+
+/// A side table holding one `T` for every frame of a [`Umem`],
+/// indexed directly by frame.
+///
+/// Lookups are a division and an index, so per-packet state (a
+/// timestamp, a flow handle, a reference count) can travel with a
+/// frame through the application without a hashmap on the hot path.
+///
+/// The table does not keep the [`Umem`] alive and knows nothing of
+/// which frames are in use, it's up to the caller to reset entries
+/// as frames are recycled.
+#[derive(Debug, Clone)]
+pub struct MetaTable<T> {
+    frame_size: usize,
+    entries: Vec<T>,
+}
+
+impl<T> MetaTable<T> {
+    /// Create a table for `umem`, with each entry initialised by
+    /// calling `init` with the entry's frame index.
+    pub fn new<F>(umem: &Umem, init: F) -> Self
+    where
+        F: FnMut(usize) -> T,
+    {
+        Self::with_geometry(umem.frame_size(), umem.frame_count(), init)
+    }
+
+    pub(crate) fn with_geometry<F>(frame_size: usize, frame_count: usize, init: F) -> Self
+    where
+        F: FnMut(usize) -> T,
+    {
+        Self {
+            frame_size,
+            entries: (0..frame_count).map(init).collect(),
+        }
+    }
+
+    /// The index of the frame described by `desc`.
+    #[inline]
+    pub fn index(&self, desc: &FrameDesc) -> usize {
+        desc.addr / self.frame_size
+    }
+
+    /// The entry for the frame described by `desc`.
+    ///
+    /// # Panics
+    ///
+    /// If `desc` does not belong to the [`Umem`] this table was
+    /// created for and lies beyond its end.
+    #[inline]
+    pub fn get(&self, desc: &FrameDesc) -> &T {
+        &self.entries[self.index(desc)]
+    }
+
+    /// A mutable reference to the entry for the frame described by
+    /// `desc`.
+    ///
+    /// # Panics
+    ///
+    /// See [`get`](Self::get).
+    #[inline]
+    pub fn meta(&mut self, desc: &FrameDesc) -> &mut T {
+        let idx = self.index(desc);
+        &mut self.entries[idx]
+    }
+
+    /// The number of entries, equal to the number of frames.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the table has no entries.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Iterate over all entries in frame order.
+    pub fn iter(&self) -> slice::Iter<'_, T> {
+        self.entries.iter()
+    }
+
+    /// Mutably iterate over all entries in frame order.
+    pub fn iter_mut(&mut self) -> slice::IterMut<'_, T> {
+        self.entries.iter_mut()
+    }
+}
+
+impl<T: Default> MetaTable<T> {
+    /// Create a table for `umem` with every entry set to
+    /// `T::default()`.
+    pub fn with_default(umem: &Umem) -> Self {
+        Self::new(umem, |_| T::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::umem::frame::fixtures::{desc, FRAME_SIZE};
+
+    #[test]
+    fn entries_are_indexed_by_frame() {
+        let mut table = MetaTable::with_geometry(FRAME_SIZE, 4, |i| i * 10);
+
+        assert_eq!(table.len(), 4);
+        assert_eq!(*table.get(&desc(2)), 20);
+
+        *table.meta(&desc(3)) += 1;
+
+        assert_eq!(table.iter().copied().collect::<Vec<_>>(), [0, 10, 20, 31]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn descs_beyond_the_umem_panic() {
+        let table = MetaTable::with_geometry(FRAME_SIZE, 4, |_| ());
+
+        table.get(&desc(4));
+    }
+}
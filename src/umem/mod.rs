@@ -4,27 +4,45 @@ mod mem;
 use mem::UmemRegion;
 
 pub mod frame;
-use frame::{Data, DataMut, FrameDesc, Headroom, HeadroomMut};
+use frame::{Data, DataMut, FrameDesc, Headroom, HeadroomMut, Meta, RxMetadata};
 
 mod fill_queue;
-pub use fill_queue::FillQueue;
+pub use fill_queue::{FillQueue, UmemMismatchError};
 
 mod comp_queue;
 pub use comp_queue::CompQueue;
 
+mod meta;
+pub use meta::MetaTable;
+
+mod recycler;
+pub use recycler::Recycler;
+
+mod pool;
+pub use pool::{
+    AliasError, CompOverflow, FrameCounts, FramePool, PoolStats, SendBatchError, SentBatch,
+    SplitFramesError,
+};
+
+mod owned;
+pub use owned::OwnedFrame;
+
 use libxdp_sys::xsk_umem;
 use log::error;
 use std::{
     borrow::Borrow,
+    collections::HashMap,
     error::Error,
     fmt, io,
     num::NonZeroU32,
+    os::unix::io::BorrowedFd,
     ptr::{self, NonNull},
     sync::{Arc, Mutex},
 };
 
 use crate::{
-    config::UmemConfig,
+    config::{HugePages, UmemConfig},
+    portable::FrameLayout,
     ring::{XskRingCons, XskRingProd},
 };
 
@@ -76,10 +94,15 @@ impl Drop for XskUmem {
 /// socket for the first time with this [`Umem`]. Hence we store them
 /// here so we don't prematurely clear up the rings' memory between
 /// creating the [`Umem`] and creating the socket.
+///
+/// It also counts the sockets bound to each interface queue, since
+/// those on the same queue share a fill queue and comp queue while
+/// each other queue gets its own.
 #[derive(Debug)]
 struct UmemInner {
     ptr: XskUmem,
     saved_fq_and_cq: Option<(Box<XskRingProd>, Box<XskRingCons>)>,
+    bound_queues: BoundQueues,
 }
 
 impl UmemInner {
@@ -87,10 +110,15 @@ impl UmemInner {
         Self {
             ptr,
             saved_fq_and_cq,
+            bound_queues: BoundQueues::new(),
         }
     }
 }
 
+/// The number of sockets using a [`Umem`] bound to each (interface
+/// index, queue id) pair.
+pub(crate) type BoundQueues = HashMap<(u32, u32), usize>;
+
 /// A region of virtual contiguous memory divided into equal-sized
 /// frames. It provides the underlying working memory for an AF_XDP
 /// [`Socket`](crate::socket::Socket).
@@ -99,6 +127,7 @@ pub struct Umem {
     // `inner` must appear before `mem` to ensure correct drop order.
     inner: Arc<Mutex<UmemInner>>,
     mem: UmemRegion,
+    unaligned_chunks: bool,
 }
 
 impl Umem {
@@ -109,19 +138,43 @@ impl Umem {
     /// allocate the underlying memory using huge pages. If you are
     /// getting errors as a result of this, check that the
     /// `HugePages_Total` setting is non-zero when you run `cat
-    /// /proc/meminfo`.
+    /// /proc/meminfo`. The size is the one set in
+    /// [`UmemConfigBuilder::huge_pages`], or the system default if
+    /// none was. To fall back to regular pages rather than fail,
+    /// leave this `false` and only set the config option.
+    ///
+    /// For large UMEMs most of the time spent here goes on faulting
+    /// in the region, which the kernel zero-fills as it does so. The
+    /// memory is never zeroed in user space, so there's no zeroing to
+    /// skip, and registering the UMEM pins (and so faults in) every
+    /// page whether or not it was populated up front. Huge pages cut
+    /// the cost considerably, since far fewer faults are needed.
+    ///
+    /// [`UmemConfigBuilder::huge_pages`]: crate::config::UmemConfigBuilder::huge_pages
     pub fn new(
         config: UmemConfig,
         frame_count: NonZeroU32,
         use_huge_pages: bool,
     ) -> Result<(Self, Vec<FrameDesc>), UmemCreateError> {
         let frame_layout = config.into();
+        let unaligned_chunks = config.unaligned_chunks();
 
-        let mem = UmemRegion::new(frame_count, frame_layout, use_huge_pages).map_err(|e| {
-            UmemCreateError {
-                reason: "failed to create mmap'd UMEM region",
-                err: e,
-            }
+        let (huge_pages, required) = match (config.huge_pages(), use_huge_pages) {
+            (HugePages::Off, true) => (HugePages::Default, true),
+            (huge_pages, required) => (huge_pages, required),
+        };
+
+        let mem = UmemRegion::new(
+            frame_count,
+            frame_layout,
+            huge_pages,
+            required,
+            config.numa_node(),
+            config.memfd(),
+        )
+        .map_err(|e| UmemCreateError {
+            reason: "failed to create mmap'd UMEM region",
+            err: e,
         })?;
 
         let mut umem_ptr = ptr::null_mut();
@@ -182,16 +235,13 @@ impl Umem {
         let mut frame_descs: Vec<FrameDesc> = Vec::with_capacity(frame_count);
 
         for i in 0..frame_count {
-            let addr = (i * frame_layout.frame_size())
-                + frame_layout.xdp_headroom
-                + frame_layout.frame_headroom;
-
-            frame_descs.push(FrameDesc::new(addr));
+            frame_descs.push(FrameDesc::new(frame_layout.data_addr(i)));
         }
 
         let umem = Umem {
             inner: Arc::new(Mutex::new(inner)),
             mem,
+            unaligned_chunks,
         };
 
         Ok((umem, frame_descs))
@@ -251,6 +301,44 @@ impl Umem {
         unsafe { self.mem.data(desc) }
     }
 
+    /// The XDP metadata in front of the packet data of the `Umem`
+    /// frame pointed at by `desc`, as written by an XDP program with
+    /// `bpf_xdp_adjust_meta`. Contents are read-only.
+    ///
+    /// Its length is [`desc.lengths().meta()`](frame::SegmentLengths::meta),
+    /// capped to the space before the packet data in the frame, which
+    /// the kernel doesn't report so must be set by the caller, see
+    /// [`RxQueue::set_meta_len`](crate::RxQueue::set_meta_len). The
+    /// metadata shares its bytes with the end of the frame's headroom,
+    /// so isn't kept once the frame is reused.
+    ///
+    /// # Safety
+    ///
+    /// See [`frame`](Self::frame).
+    #[inline]
+    pub unsafe fn meta(&self, desc: &FrameDesc) -> Meta<'_> {
+        // SAFETY: see `frame`.
+        unsafe { self.mem.meta(desc) }
+    }
+
+    /// The hardware RX hints, e.g. timestamp and RSS hash, an XDP
+    /// program copied into the metadata area of the `Umem` frame
+    /// pointed at by `desc`, or [`None`] if the frame's
+    /// [`meta`](Self::meta) is too short to hold them.
+    ///
+    /// Received frames are only marked as carrying the hints once
+    /// [`RxQueue::set_meta_len`](crate::RxQueue::set_meta_len) has
+    /// been passed [`RX_METADATA_LEN`](frame::RX_METADATA_LEN).
+    ///
+    /// # Safety
+    ///
+    /// See [`frame`](Self::frame).
+    #[inline]
+    pub unsafe fn rx_metadata(&self, desc: &FrameDesc) -> Option<RxMetadata> {
+        // SAFETY: see `frame`.
+        RxMetadata::parse(&unsafe { self.meta(desc) })
+    }
+
     /// The headroom and packet data segments of the `Umem` frame
     /// pointed at by `desc`. Contents are writeable.
     ///
@@ -308,6 +396,208 @@ impl Umem {
         unsafe { self.mem.data_mut(desc) }
     }
 
+    /// The packet data segment of a slot handed out by
+    /// [`FramePool::try_alloc_slot`], at most `slot_size` bytes long.
+    /// Contents are writeable.
+    ///
+    /// Unlike [`data_mut`](Self::data_mut) the segment ends at the end
+    /// of the slot rather than of the frame, so it can't spill into
+    /// the frame's other slots.
+    ///
+    /// # Safety
+    ///
+    /// See [`frame_mut`](Self::frame_mut). In addition, `slot_size`
+    /// must be no more than the pool's
+    /// [`slot_size`](FramePool::slot_size).
+    #[inline]
+    pub unsafe fn slot_data_mut<'a>(
+        &'a self,
+        desc: &'a mut FrameDesc,
+        slot_size: usize,
+    ) -> DataMut<'a> {
+        // SAFETY: see `frame_mut`.
+        unsafe { self.mem.slot_data_mut(desc, slot_size) }
+    }
+
+    /// The pages actually backing the UMEM, which is
+    /// [`HugePages::Off`] if huge pages were asked for in the config
+    /// but couldn't be had.
+    #[inline]
+    pub fn huge_pages(&self) -> HugePages {
+        self.mem.huge_pages()
+    }
+
+    /// The memfd backing the UMEM if it was created with
+    /// [`UmemConfigBuilder::memfd`], for passing to another process
+    /// along with a [`handoff::Handoff`](crate::handoff::Handoff).
+    ///
+    /// [`UmemConfigBuilder::memfd`]: crate::config::UmemConfigBuilder::memfd
+    #[inline]
+    pub fn memfd(&self) -> Option<BorrowedFd<'_>> {
+        self.mem.memfd()
+    }
+
+    /// Whether the UMEM was registered in unaligned chunk mode, see
+    /// [`UmemConfigBuilder::unaligned_chunks`].
+    ///
+    /// [`UmemConfigBuilder::unaligned_chunks`]: crate::config::UmemConfigBuilder::unaligned_chunks
+    #[inline]
+    pub fn unaligned_chunks(&self) -> bool {
+        self.unaligned_chunks
+    }
+
+    /// The index of the frame that descriptor address `addr` points
+    /// into, counting from zero at the start of the UMEM, or [`None`]
+    /// if it's past the end.
+    ///
+    /// This is the inverse of [`frame_addr`](Self::frame_addr), and
+    /// accepts any address within a frame, e.g. one moved by
+    /// adjusting the headroom. In [unaligned
+    /// mode](Self::unaligned_chunks) the offset the kernel encodes in
+    /// the upper bits of received descriptors' addresses is taken
+    /// into account.
+    #[inline]
+    pub fn frame_index(&self, addr: usize) -> Option<usize> {
+        let addr = FrameLayout::resolve_addr(addr, self.unaligned_chunks);
+        let idx = self.mem.layout().frame_index(addr);
+
+        (idx < self.frame_count()).then_some(idx)
+    }
+
+    /// The descriptor address of the frame at `index`, as given to
+    /// the descriptors returned by [`new`](Self::new), i.e. the start
+    /// of its packet data segment past any headroom. [`None`] if the
+    /// UMEM has no such frame.
+    #[inline]
+    pub fn frame_addr(&self, index: usize) -> Option<usize> {
+        (index < self.frame_count()).then(|| self.mem.layout().data_addr(index))
+    }
+
+    /// Hint to the CPU that the start of the packet data segment of
+    /// the frame pointed at by `desc` will be read soon, so it can be
+    /// pulled into cache ahead of time.
+    ///
+    /// This only issues a prefetch instruction and never accesses the
+    /// frame, so is safe whatever `desc` is. It does nothing on
+    /// architectures other than x86_64.
+    #[inline]
+    pub fn prefetch(&self, desc: &FrameDesc) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+
+            let ptr = (self.mem_ptr() as *const i8).wrapping_add(desc.addr);
+
+            // SAFETY: prefetching is only a hint and can't fault, even
+            // for an invalid address.
+            unsafe { _mm_prefetch(ptr, _MM_HINT_T0) };
+        }
+
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = desc;
+    }
+
+    /// Take ownership of the frames described by `descs`, as
+    /// [`OwnedFrame`]s whose contents may be accessed without
+    /// `unsafe`. Typically called once, on the descriptors returned
+    /// by [`new`](Self::new).
+    ///
+    /// # Safety
+    ///
+    /// See [`OwnedFrame::new`], for each of `descs`.
+    pub unsafe fn own<I>(&self, descs: I) -> Vec<OwnedFrame<'_>>
+    where
+        I: IntoIterator<Item = FrameDesc>,
+    {
+        descs
+            .into_iter()
+            // SAFETY: guaranteed by this function's contract.
+            .map(|desc| unsafe { OwnedFrame::new(self, desc) })
+            .collect()
+    }
+
+    /// Reclaim the frames left on `fq` and `cq` once the kernel is
+    /// done with them, e.g. after
+    /// [`Socket::shutdown`](crate::Socket::shutdown).
+    ///
+    /// Returns the descriptors of frames the kernel never took off the
+    /// fill queue, followed by those of any completions not yet
+    /// consumed, all of which user space owns again. Frames a
+    /// zero-copy driver had already taken off the fill queue aren't
+    /// returned, they're user space's too but can't be told apart from
+    /// frames still in use by the application.
+    ///
+    /// Both queues are dropped, either way, and the memory is unmapped
+    /// once the last `Umem` clone goes.
+    ///
+    /// # Safety
+    ///
+    /// The kernel must no longer be taking frames off `fq`. Dropping
+    /// every socket using the UMEM isn't enough for this: the first
+    /// socket's fd is the UMEM's own, which stays bound to its queue
+    /// until the last `Umem` clone goes, and a socket may still be
+    /// redirected to from an XSKMAP. Traffic to the bound queues must
+    /// have stopped too, e.g. by detaching the XDP program.
+    ///
+    /// # Errors
+    ///
+    /// If a socket is known to still be bound to the UMEM, or `fq` or
+    /// `cq` belong to a different one, in which case nothing is
+    /// reclaimed.
+    pub unsafe fn drain(
+        &self,
+        fq: FillQueue,
+        mut cq: CompQueue,
+    ) -> Result<Vec<FrameDesc>, UmemDrainError> {
+        if !fq.umem().is_shared_with(self) || !cq.umem().is_shared_with(self) {
+            return Err(UmemDrainError::UmemMismatch);
+        }
+
+        let bound: usize = self
+            .inner
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .bound_queues
+            .values()
+            .sum();
+
+        if bound > 0 {
+            return Err(UmemDrainError::StillBound { sockets: bound });
+        }
+
+        let mut descs = Vec::new();
+
+        // SAFETY: guaranteed by this function's contract.
+        unsafe { fq.into_unconsumed(&mut descs) };
+
+        let mut completed = Vec::new();
+
+        while cq
+            .consume_owned(self, usize::MAX, &mut completed)
+            .map_err(|_| UmemDrainError::UmemMismatch)?
+            > 0
+        {
+            descs.extend(completed.drain(..).map(OwnedFrame::into_desc));
+        }
+
+        Ok(descs)
+    }
+
+    /// Whether `self` and `other` refer to the same UMEM, i.e. one was
+    /// cloned from the other. Frames may only be passed between the
+    /// queues of sockets whose `Umem`s are shared in this way.
+    #[inline]
+    pub fn is_shared_with(&self, other: &Umem) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// Identifies the UMEM, equal for any two `Umem`s which are
+    /// [shared](Self::is_shared_with).
+    #[inline]
+    pub(crate) fn id(&self) -> usize {
+        Arc::as_ptr(&self.inner) as usize
+    }
+
     /// Intended to be called on socket creation, this passes the
     /// create function a pointer to the UMEM and any saved fill queue
     /// or completion queue.
@@ -315,14 +605,64 @@ impl Umem {
     /// Regarding the saved queues, this is a byproduct of how the
     /// UMEM is created in the C code and we save them here to avoid
     /// leaking memory.
+    ///
+    /// The queues sockets are bound to are passed too, so a socket
+    /// can be checked for and recorded as it's created.
     #[inline]
     pub(crate) fn with_ptr_and_saved_queues<F, T>(&self, mut f: F) -> T
     where
-        F: FnMut(*mut xsk_umem, &mut Option<(Box<XskRingProd>, Box<XskRingCons>)>) -> T,
+        F: FnMut(
+            *mut xsk_umem,
+            &mut Option<(Box<XskRingProd>, Box<XskRingCons>)>,
+            &mut BoundQueues,
+        ) -> T,
     {
         let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+
+        f(
+            inner.ptr.as_mut_ptr(),
+            &mut inner.saved_fq_and_cq,
+            &mut inner.bound_queues,
+        )
+    }
+
+    /// Record that a socket bound to interface `ifindex`'s queue
+    /// `queue_id` has gone.
+    pub(crate) fn unbind_queue(&self, ifindex: u32, queue_id: u32) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(cnt) = inner.bound_queues.get_mut(&(ifindex, queue_id)) {
+            *cnt -= 1;
 
-        f(inner.ptr.as_mut_ptr(), &mut inner.saved_fq_and_cq)
+            if *cnt == 0 {
+                inner.bound_queues.remove(&(ifindex, queue_id));
+            }
+        }
+    }
+
+    /// A pointer to the start of the underlying memory region.
+    #[inline]
+    pub(crate) fn mem_ptr(&self) -> *mut libc::c_void {
+        self.mem.as_ptr()
+    }
+
+    /// The size of each frame.
+    #[inline]
+    pub(crate) fn frame_size(&self) -> usize {
+        self.mem.frame_size()
+    }
+
+    /// The size of each frame's packet data segment.
+    #[inline]
+    pub(crate) fn frame_mtu(&self) -> usize {
+        self.mem.frame_mtu()
+    }
+
+    /// The number of frames in the `Umem`.
+    #[inline]
+    pub(crate) fn frame_count(&self) -> usize {
+        self.mem.len() / self.mem.frame_size()
     }
 }
 
@@ -345,26 +685,37 @@ impl Error for UmemCreateError {
     }
 }
 
-/// Dimensions of a [`Umem`] frame.
-#[derive(Debug, Clone, Copy)]
-struct FrameLayout {
-    xdp_headroom: usize,
-    frame_headroom: usize,
-    mtu: usize,
+/// Error returned by [`Umem::drain`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UmemDrainError {
+    /// Sockets are still bound to the [`Umem`], so the kernel may
+    /// still be using its queues.
+    StillBound {
+        /// How many.
+        sockets: usize,
+    },
+    /// The queues passed in belong to a different [`Umem`].
+    UmemMismatch,
 }
 
-impl FrameLayout {
-    fn frame_size(&self) -> usize {
-        self.xdp_headroom + self.frame_headroom + self.mtu
+impl fmt::Display for UmemDrainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::StillBound { sockets } => {
+                write!(f, "{} sockets still bound to the UMEM", sockets)
+            }
+            Self::UmemMismatch => write!(f, "queues belong to a different UMEM"),
+        }
     }
 }
 
+impl Error for UmemDrainError {}
+
 impl From<UmemConfig> for FrameLayout {
     fn from(c: UmemConfig) -> Self {
-        Self {
-            xdp_headroom: c.xdp_headroom() as usize,
-            frame_headroom: c.frame_headroom() as usize,
-            mtu: c.mtu() as usize,
+        match FrameLayout::new(c.frame_size().get(), c.xdp_headroom(), c.frame_headroom()) {
+            Some(layout) => layout,
+            None => unreachable!("headroom is checked to fit when the config is built"),
         }
     }
 }
@@ -0,0 +1,125 @@
+use std::fmt;
+
+use super::{
+    frame::{Data, DataMut, FrameDesc, Headroom, HeadroomMut},
+    Umem,
+};
+
+/// A [`Umem`] frame which user space is known to own, i.e. one that
+/// isn't sitting on any ring waiting for the kernel.
+///
+/// Whereas a raw [`FrameDesc`] says nothing about who owns the frame
+/// it points at, and so every access through it is `unsafe`, an
+/// `OwnedFrame` can only be had while user space holds the frame:
+/// it's handed out by the `consume_owned` methods of the [`RxQueue`]
+/// and [`CompQueue`](super::CompQueue), and taken back by value by
+/// the `produce_owned` methods of the [`FillQueue`](super::FillQueue)
+/// and [`TxQueue`]. It's neither `Copy` nor `Clone`, so once a frame
+/// is handed to the kernel there's nothing left to access it through,
+/// and its contents can be read and written without `unsafe`.
+///
+/// The frame is tied to its [`Umem`] by lifetime, and each queue
+/// checks that its frames belong to the [`Umem`] it's bound to.
+///
+/// [`RxQueue`]: crate::RxQueue
+/// [`TxQueue`]: crate::TxQueue
+pub struct OwnedFrame<'umem> {
+    umem: &'umem Umem,
+    desc: FrameDesc,
+}
+
+impl<'umem> OwnedFrame<'umem> {
+    /// Assert that user space owns the frame `desc` points at.
+    ///
+    /// Usually only needed once, for the descriptors returned by
+    /// [`Umem::new`], see [`Umem::own`].
+    ///
+    /// # Safety
+    ///
+    /// `desc` must describe a frame of `umem`, and the frame must not
+    /// be on any ring, nor be accessed other than through the returned
+    /// `OwnedFrame` for as long as it exists.
+    #[inline]
+    pub unsafe fn new(umem: &'umem Umem, desc: FrameDesc) -> Self {
+        Self { umem, desc }
+    }
+
+    /// The frame's descriptor.
+    #[inline]
+    pub fn desc(&self) -> &FrameDesc {
+        &self.desc
+    }
+
+    /// The [`Umem`] the frame belongs to.
+    #[inline]
+    pub fn umem(&self) -> &'umem Umem {
+        self.umem
+    }
+
+    /// Set the frame's descriptor options.
+    #[inline]
+    pub fn set_options(&mut self, options: u32) {
+        self.desc.set_options(options)
+    }
+
+    /// The frame's headroom and packet data segments. Contents are
+    /// read-only.
+    #[inline]
+    pub fn frame(&self) -> (Headroom<'_>, Data<'_>) {
+        // SAFETY: the frame is in `umem` and owned by user space, and
+        // the borrow of `self` covers any other access through it.
+        unsafe { self.umem.frame(&self.desc) }
+    }
+
+    /// The frame's headroom segment. Contents are read-only.
+    #[inline]
+    pub fn headroom(&self) -> Headroom<'_> {
+        // SAFETY: see `frame`.
+        unsafe { self.umem.headroom(&self.desc) }
+    }
+
+    /// The frame's packet data segment. Contents are read-only.
+    #[inline]
+    pub fn data(&self) -> Data<'_> {
+        // SAFETY: see `frame`.
+        unsafe { self.umem.data(&self.desc) }
+    }
+
+    /// The frame's headroom and packet data segments. Contents are
+    /// writeable.
+    #[inline]
+    pub fn frame_mut(&mut self) -> (HeadroomMut<'_>, DataMut<'_>) {
+        // SAFETY: the frame is in `umem` and owned by user space, and
+        // the mutable borrow of `self` rules out any other access.
+        unsafe { self.umem.frame_mut(&mut self.desc) }
+    }
+
+    /// The frame's headroom segment. Contents are writeable.
+    #[inline]
+    pub fn headroom_mut(&mut self) -> HeadroomMut<'_> {
+        // SAFETY: see `frame_mut`.
+        unsafe { self.umem.headroom_mut(&mut self.desc) }
+    }
+
+    /// The frame's packet data segment. Contents are writeable.
+    #[inline]
+    pub fn data_mut(&mut self) -> DataMut<'_> {
+        // SAFETY: see `frame_mut`.
+        unsafe { self.umem.data_mut(&mut self.desc) }
+    }
+
+    /// Give up the ownership tracking, leaving just the descriptor for
+    /// use with the `unsafe` queue methods.
+    #[inline]
+    pub fn into_desc(self) -> FrameDesc {
+        self.desc
+    }
+}
+
+impl fmt::Debug for OwnedFrame<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OwnedFrame")
+            .field("desc", &self.desc)
+            .finish()
+    }
+}
@@ -0,0 +1,1782 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt, io, slice,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    check,
+    portable::{FrameLayout, FreeList},
+    stats::Histogram,
+};
+
+use super::{
+    frame::{FrameDesc, SegmentLengths},
+    CompQueue, FillQueue, Umem,
+};
+use crate::{RxQueue, TxQueue};
+
+/// Book-keeping for frames split into slots, see
+/// [`FramePool::split_frames`].
+#[derive(Debug, Clone)]
+struct Split {
+    frame_size: usize,
+    slot_size: usize,
+    slots_per_frame: usize,
+    /// The frame slots are currently being carved from, and the index
+    /// of the next slot.
+    open: Option<(usize, usize)>,
+    /// For each frame with slots handed out, keyed by frame index,
+    /// the frame's address and the number of its slots not yet
+    /// returned.
+    outstanding: HashMap<usize, (usize, usize)>,
+}
+
+impl Split {
+    /// Hand out the next slot of the open frame, if there's one with
+    /// slots left.
+    fn next_slot(&mut self) -> Option<usize> {
+        let (frame_addr, next) = self.open.as_mut()?;
+        let addr = *frame_addr + *next * self.slot_size;
+
+        self.outstanding
+            .entry(*frame_addr / self.frame_size)
+            .or_insert((*frame_addr, 0))
+            .1 += 1;
+
+        *next += 1;
+
+        if *next == self.slots_per_frame {
+            self.open = None;
+        }
+
+        Some(addr)
+    }
+
+    /// Account for `addr` coming back, returning the address to put
+    /// back on the free list, if any. That's `addr` itself unless it's
+    /// a slot, in which case it's its frame's address once the frame's
+    /// last outstanding slot is back and no more are to be carved.
+    fn release(&mut self, addr: usize) -> Option<usize> {
+        let index = addr / self.frame_size;
+
+        let (frame_addr, out) = match self.outstanding.get_mut(&index) {
+            Some(entry) => entry,
+            None => return Some(addr),
+        };
+
+        *out -= 1;
+
+        if *out > 0 {
+            return None;
+        }
+
+        let frame_addr = *frame_addr;
+
+        self.outstanding.remove(&index);
+
+        match self.open {
+            Some((open, _)) if open == frame_addr => None,
+            _ => Some(frame_addr),
+        }
+    }
+}
+
+/// A frame with descriptors aliasing it, see [`FramePool::alias`].
+#[derive(Debug, Clone)]
+struct Aliased {
+    /// Where the frame's packet data ended when first aliased, all
+    /// aliases lying before it.
+    end: usize,
+    /// The frame's address as handed out by the pool.
+    addr: usize,
+    /// The number of descriptors for the frame, the original
+    /// included, not yet returned.
+    refs: usize,
+}
+
+/// Account for `addr` coming back, returning the address to carry on
+/// releasing, if any. That's `addr` itself unless it lies within an
+/// aliased frame, in which case it's the frame's address once the
+/// last of its descriptors is back.
+fn unalias(aliases: &mut BTreeMap<usize, Aliased>, addr: usize) -> Option<usize> {
+    let start = FrameLayout::resolve_addr(addr, true);
+
+    let (&key, frame) = match aliases.range_mut(..=start).next_back() {
+        Some(entry) if start < entry.1.end => entry,
+        _ => return Some(addr),
+    };
+
+    frame.refs -= 1;
+
+    if frame.refs > 0 {
+        return None;
+    }
+
+    aliases.remove(&key).map(|frame| frame.addr)
+}
+
+/// Account for `addr` coming back to a pool with `split` and
+/// `aliases`, returning the address to put back on its free list, if
+/// any, and whether it was the last reference to an aliased frame.
+fn release(
+    split: Option<&mut Split>,
+    aliases: Option<&mut BTreeMap<usize, Aliased>>,
+    addr: usize,
+) -> (Option<usize>, bool) {
+    let (addr, unaliased) = match aliases {
+        Some(aliases) => {
+            let len = aliases.len();
+            let addr = unalias(aliases, addr);
+
+            (addr, aliases.len() < len)
+        }
+        None => (Some(addr), false),
+    };
+
+    let addr = match (addr, split) {
+        (Some(addr), Some(split)) => split.release(addr),
+        (addr, _) => addr,
+    };
+
+    (addr, unaliased)
+}
+
+/// What [`FramePool::reap`] does with completions beyond the pool's
+/// recycle budget, see [`FramePool::with_overflow`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompOverflow {
+    /// Leave them on the completion ring. Once the ring fills the
+    /// kernel stops completing that socket's sends, so its tx is held
+    /// back until a later reap catches up.
+    Backpressure,
+    /// Consume them anyway and set them aside on an overflow list,
+    /// returned to the pool ahead of new completions by later reaps.
+    /// The socket's tx keeps going, but the set aside frames can't be
+    /// allocated in the meantime.
+    Spill,
+}
+
+/// A pool of free frames shared between threads, e.g. tx workers
+/// allocating frames to build packets in and a completion reaper
+/// returning them.
+///
+/// Allocation failures because the pool is empty are counted, and
+/// the time spent waiting by [`alloc_timeout`](Self::alloc_timeout)
+/// and [`alloc_blocking`](Self::alloc_blocking) recorded, see
+/// [`stats`](Self::stats). Lots of failures or long
+/// waits suggest the UMEM needs more frames.
+///
+/// When several sockets share the pool, a busy one's completions can
+/// crowd out the others' at the reaper. A pool created with
+/// [`with_overflow`](Self::with_overflow) caps how many frames each
+/// [`reap`](Self::reap) returns, and how completions past the cap are
+/// handled is counted in the stats too.
+///
+/// A few frames can be [reserved](Self::reserve_for_control) for
+/// control traffic, so it still gets through while bulk traffic has
+/// the pool drained.
+///
+/// Frames moved through the rings with [`fill`](Self::fill),
+/// [`recv`](Self::recv), [`send`](Self::send) and
+/// [`reap`](Self::reap) are also tracked by where they are, see
+/// [`counts`](Self::counts), so a leak shows up as frames stuck with
+/// the application rather than a pool slowly running dry.
+#[derive(Debug)]
+pub struct FramePool {
+    free: Mutex<FreeList>,
+    available: Condvar,
+    capacity: usize,
+    reserved: AtomicUsize,
+    strategy: CompOverflow,
+    recycle_budget: usize,
+    overflow: Mutex<Vec<usize>>,
+    split: Option<Mutex<Split>>,
+    aliases: Mutex<BTreeMap<usize, Aliased>>,
+    aliased: AtomicUsize,
+    owned: AtomicUsize,
+    filling: AtomicUsize,
+    sending: AtomicUsize,
+    allocs: AtomicU64,
+    failures: AtomicU64,
+    shortfall: AtomicU64,
+    timeouts: AtomicU64,
+    spilled: AtomicU64,
+    overflow_peak: AtomicU64,
+    backpressured: AtomicU64,
+    slot_allocs: AtomicU64,
+    alias_allocs: AtomicU64,
+    control_allocs: AtomicU64,
+    waits: Mutex<Histogram>,
+}
+
+impl FramePool {
+    /// Create a pool holding the frames in `descs`, typically all
+    /// those returned by [`Umem::new`](super::Umem::new).
+    ///
+    /// [`reap`](Self::reap) returns as many frames as it's given room
+    /// for.
+    pub fn new(descs: &[FrameDesc]) -> Self {
+        Self::with_overflow(descs, CompOverflow::Backpressure, usize::MAX)
+    }
+
+    /// Create a pool holding the frames in `descs` whose
+    /// [`reap`](Self::reap) returns at most `recycle_budget` frames
+    /// per call, handling any completions over that as per `strategy`.
+    ///
+    /// # Panics
+    ///
+    /// If `recycle_budget` is zero, since then nothing would ever be
+    /// returned.
+    pub fn with_overflow(
+        descs: &[FrameDesc],
+        strategy: CompOverflow,
+        recycle_budget: usize,
+    ) -> Self {
+        assert!(recycle_budget > 0, "recycle budget must be non-zero");
+
+        let mut free = FreeList::with_capacity(descs.len());
+
+        for desc in descs.iter().rev() {
+            free.push(desc.addr());
+        }
+
+        Self {
+            free: Mutex::new(free),
+            available: Condvar::new(),
+            capacity: descs.len(),
+            reserved: AtomicUsize::new(0),
+            strategy,
+            recycle_budget,
+            overflow: Mutex::new(Vec::new()),
+            split: None,
+            aliases: Mutex::new(BTreeMap::new()),
+            aliased: AtomicUsize::new(0),
+            owned: AtomicUsize::new(0),
+            filling: AtomicUsize::new(0),
+            sending: AtomicUsize::new(0),
+            allocs: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            shortfall: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+            spilled: AtomicU64::new(0),
+            overflow_peak: AtomicU64::new(0),
+            backpressured: AtomicU64::new(0),
+            slot_allocs: AtomicU64::new(0),
+            alias_allocs: AtomicU64::new(0),
+            control_allocs: AtomicU64::new(0),
+            waits: Mutex::new(Histogram::new()),
+        }
+    }
+
+    /// Let frames be split into slots of `slot_size` bytes for small
+    /// tx packets, handed out by [`try_alloc_slot`](Self::try_alloc_slot).
+    ///
+    /// Each frame's packet data segment is carved into as many slots
+    /// as fit, each with its own address, so a frame can carry that
+    /// many packets on the tx ring at once rather than one. The frame
+    /// only goes back in the pool once all of its slots have been
+    /// freed or [reaped](Self::reap), so slots can be returned in any
+    /// order, and whole frames and slots can be allocated side by
+    /// side.
+    ///
+    /// Only for tx: slots must never be put on a fill queue, since
+    /// the kernel would write whole frames into them.
+    ///
+    /// Fails if `umem` isn't in [unaligned chunk
+    /// mode](Umem::unaligned_chunks) or a frame couldn't hold two
+    /// slots, or if `slot_size` is zero.
+    pub fn split_frames(&mut self, umem: &Umem, slot_size: usize) -> Result<(), SplitFramesError> {
+        if !umem.unaligned_chunks() {
+            return Err(SplitFramesError::AlignedChunks);
+        }
+
+        let slots_per_frame = umem.frame_mtu().checked_div(slot_size).unwrap_or(0);
+
+        if slots_per_frame < 2 {
+            return Err(SplitFramesError::SlotSize {
+                slot_size,
+                frame_mtu: umem.frame_mtu(),
+            });
+        }
+
+        self.split = Some(Mutex::new(Split {
+            frame_size: umem.frame_size(),
+            slot_size,
+            slots_per_frame,
+            open: None,
+            outstanding: HashMap::new(),
+        }));
+
+        Ok(())
+    }
+
+    /// Hold back `n` of the pool's frames for control traffic, e.g.
+    /// ARP replies and heartbeats, so bulk traffic draining the pool
+    /// can't starve what keeps the link usable.
+    ///
+    /// The ordinary allocation methods, and [`fill`](Self::fill), then
+    /// leave the last `n` free frames alone, and only
+    /// [`try_alloc_control`](Self::try_alloc_control) can take them.
+    /// Control frames are returned like any other, topping up the
+    /// reserve before freeing frames for bulk use again.
+    ///
+    /// # Panics
+    ///
+    /// If `n` is more than the pool's capacity.
+    pub fn reserve_for_control(&self, n: usize) {
+        assert!(
+            n <= self.capacity,
+            "cannot reserve {} of {} frames",
+            n,
+            self.capacity
+        );
+
+        self.reserved.store(n, Ordering::Relaxed);
+
+        // A smaller reserve may free enough frames for a waiter.
+        self.available.notify_all();
+    }
+
+    /// The number of frames held back for control traffic, see
+    /// [`reserve_for_control`](Self::reserve_for_control).
+    pub fn reserved(&self) -> usize {
+        self.reserved.load(Ordering::Relaxed)
+    }
+
+    /// Take a frame for control traffic, from the
+    /// [reserve](Self::reserve_for_control) if need be, or `None` if
+    /// the pool's empty.
+    pub fn try_alloc_control(&self) -> Option<FrameDesc> {
+        let addr = self.lock().pop();
+
+        match addr {
+            Some(addr) => {
+                self.control_allocs.fetch_add(1, Ordering::Relaxed);
+                self.owned.fetch_add(1, Ordering::Relaxed);
+                Some(FrameDesc::new(addr))
+            }
+            None => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                self.shortfall.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// The size of each slot, if frames are
+    /// [split](Self::split_frames).
+    pub fn slot_size(&self) -> Option<usize> {
+        self.lock_split().map(|split| split.slot_size)
+    }
+
+    /// Take a slot from a split frame, or `None` if frames aren't
+    /// [split](Self::split_frames) or the pool's empty. Its contents
+    /// are accessed through [`Umem::slot_data_mut`].
+    ///
+    /// Slots are carved from one frame until it runs out, then the
+    /// next frame is taken from the pool, counted as an allocation
+    /// like any other.
+    pub fn try_alloc_slot(&self) -> Option<FrameDesc> {
+        let mut split = self.lock_split()?;
+
+        // A freshly carved frame is already counted as owned, and
+        // stands for its first slot.
+        if split.open.is_none() {
+            split.open = Some((self.try_alloc()?.addr(), 0));
+        } else {
+            self.owned.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let addr = split.next_slot()?;
+
+        self.slot_allocs.fetch_add(1, Ordering::Relaxed);
+
+        Some(FrameDesc::new(addr))
+    }
+
+    /// Make a descriptor for the `len` bytes at `offset` into `desc`'s
+    /// packet data, sharing its frame rather than copying, e.g. to
+    /// send a packet on several sockets or retry it while the original
+    /// is still in flight.
+    ///
+    /// The frame is reference counted: `desc` and each of its aliases
+    /// are freed or [reaped](Self::reap) like any other frame, and the
+    /// frame only goes back in the pool once all of them have. Aliases
+    /// may themselves be aliased, within the original's data.
+    ///
+    /// The frame's contents are shared, so mustn't be written to while
+    /// any of its descriptors is on a tx ring. Aliases start without
+    /// options, so can't carry TX metadata, and must never be put on a
+    /// fill queue. Sending two aliases at
+    /// the same offset to one [`TxQueue`] in the same batch trips the
+    /// [full checks](crate::check::CheckLevel::Full)' duplicate frame
+    /// check, so send those in separate batches.
+    ///
+    /// Fails if the range is empty or runs past `desc`'s data.
+    pub fn alias(
+        &self,
+        desc: &FrameDesc,
+        offset: usize,
+        len: usize,
+    ) -> Result<FrameDesc, AliasError> {
+        let data = desc.lengths().data();
+
+        if len == 0 || offset.checked_add(len).is_none_or(|end| end > data) {
+            return Err(AliasError { offset, len, data });
+        }
+
+        let start = FrameLayout::resolve_addr(desc.addr(), true);
+
+        {
+            let mut aliases = self.aliases.lock().unwrap_or_else(|e| e.into_inner());
+
+            match aliases.range_mut(..=start).next_back() {
+                Some((_, frame)) if start < frame.end => frame.refs += 1,
+                _ => {
+                    aliases.insert(
+                        start,
+                        Aliased {
+                            end: start + data,
+                            addr: desc.addr(),
+                            refs: 2,
+                        },
+                    );
+                    self.aliased.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+
+        self.owned.fetch_add(1, Ordering::Relaxed);
+        self.alias_allocs.fetch_add(1, Ordering::Relaxed);
+
+        Ok(FrameDesc {
+            addr: desc.addr() + offset,
+            options: 0,
+            lengths: SegmentLengths {
+                data: len,
+                ..SegmentLengths::default()
+            },
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FreeList> {
+        // The list is never left in an inconsistent state, so carry
+        // on if another thread panicked holding it.
+        self.free.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_split(&self) -> Option<MutexGuard<'_, Split>> {
+        self.split
+            .as_ref()
+            .map(|split| split.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// The aliased frames, if there are any. An alias only comes back
+    /// after it was made, so if none are recorded none of the frames
+    /// being returned are aliases.
+    fn lock_aliases(&self) -> Option<MutexGuard<'_, BTreeMap<usize, Aliased>>> {
+        if self.aliased.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+
+        Some(self.aliases.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Put each of `addrs` back on `free`, bar aliases, or slots,
+    /// whose frame still has others outstanding.
+    ///
+    /// The whole batch is checked before any of it is applied, so a
+    /// violation leaves the pool as it was.
+    fn push<I>(
+        &self,
+        free: &mut FreeList,
+        mut split: Option<&mut Split>,
+        mut aliases: Option<&mut BTreeMap<usize, Aliased>>,
+        addrs: I,
+    ) where
+        I: IntoIterator<Item = usize> + Clone,
+    {
+        self.check_push(free, split.as_deref(), aliases.as_deref(), addrs.clone());
+
+        for addr in addrs {
+            let (addr, unaliased) = release(split.as_deref_mut(), aliases.as_deref_mut(), addr);
+
+            if unaliased {
+                sub(&self.aliased, 1);
+            }
+
+            if let Some(addr) = addr {
+                free.push(addr);
+            }
+        }
+    }
+
+    #[track_caller]
+    fn check_push<I>(
+        &self,
+        free: &FreeList,
+        split: Option<&Split>,
+        aliases: Option<&BTreeMap<usize, Aliased>>,
+        addrs: I,
+    ) where
+        I: IntoIterator<Item = usize> + Clone,
+    {
+        // Every frame being pushed is the most that can fit, so only
+        // work out which are when that could overfill the pool.
+        let bound = addrs.clone().into_iter().count();
+
+        let may_overfill = check::cheap() && free.len() + bound > self.capacity;
+
+        if !(check::full() || may_overfill) {
+            return;
+        }
+
+        // Released against copies, as the real state must only change
+        // once the whole batch has passed.
+        let mut split = split.cloned();
+        let mut aliases = aliases.cloned();
+
+        let violation = check::with_scratch(|pushed| {
+            for addr in addrs {
+                if let (Some(addr), _) = release(split.as_mut(), aliases.as_mut(), addr) {
+                    if free.len() + pushed.len() >= self.capacity {
+                        return Some(format!(
+                            "frame {:#x} returned to a full pool of {} frames",
+                            addr, self.capacity
+                        ));
+                    }
+
+                    pushed.push(addr);
+                }
+            }
+
+            if !check::full() {
+                return None;
+            }
+
+            pushed
+                .iter()
+                .find(|&&addr| free.contains(addr))
+                .copied()
+                .or_else(|| check::first_duplicate(pushed))
+                .map(|addr| format!("frame {:#x} returned to a pool it's already in", addr))
+        });
+
+        if let Some(msg) = violation {
+            check::violated(format_args!("{}", msg));
+        }
+    }
+
+    /// Take a frame from the pool, or `None` if it's empty.
+    #[inline]
+    pub fn try_alloc(&self) -> Option<FrameDesc> {
+        let addr = {
+            let mut free = self.lock();
+
+            if free.len() > self.reserved() {
+                free.pop()
+            } else {
+                None
+            }
+        };
+
+        match addr {
+            Some(addr) => {
+                self.allocs.fetch_add(1, Ordering::Relaxed);
+                self.owned.fetch_add(1, Ordering::Relaxed);
+                Some(FrameDesc::new(addr))
+            }
+            None => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+                self.shortfall.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Take up to `n` frames from the pool, appending them to `descs`.
+    /// Returns the number taken.
+    pub fn try_alloc_batch(&self, n: usize, descs: &mut Vec<FrameDesc>) -> usize {
+        let taken = {
+            let mut free = self.lock();
+            let taken = n.min(free.len().saturating_sub(self.reserved()));
+
+            descs.extend((0..taken).filter_map(|_| free.pop()).map(FrameDesc::new));
+
+            taken
+        };
+
+        self.allocs.fetch_add(taken as u64, Ordering::Relaxed);
+        self.owned.fetch_add(taken, Ordering::Relaxed);
+
+        if taken < n {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+            self.shortfall
+                .fetch_add((n - taken) as u64, Ordering::Relaxed);
+        }
+
+        taken
+    }
+
+    /// Take a frame from the pool, waiting up to `timeout` for one to
+    /// be freed if it's empty.
+    ///
+    /// If it has to wait, the failure is counted and the time spent
+    /// waiting recorded, whether or not a frame turns up.
+    pub fn alloc_timeout(&self, timeout: Duration) -> Option<FrameDesc> {
+        let mut free = self.wait_for(1, timeout)?;
+        free.pop().map(FrameDesc::new)
+    }
+
+    /// Take `n` frames from the pool, appending them to `descs`,
+    /// waiting up to `timeout` for enough to be freed, e.g. by a
+    /// completion reaper calling [`reap`](Self::reap). Returns
+    /// whether they were taken. Either all `n` are taken or none.
+    ///
+    /// Gives up straight away if `n` is more than the pool's
+    /// capacity, less any [reserve](Self::reserve_for_control).
+    /// Waiting is counted and recorded as for
+    /// [`alloc_timeout`](Self::alloc_timeout).
+    ///
+    /// Waiters aren't served in order, so a large request may keep
+    /// waiting while smaller ones are satisfied.
+    pub fn alloc_blocking(&self, n: usize, timeout: Duration, descs: &mut Vec<FrameDesc>) -> bool {
+        if n > self.capacity - self.reserved() {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+            self.shortfall.fetch_add(n as u64, Ordering::Relaxed);
+            return false;
+        }
+
+        match self.wait_for(n, timeout) {
+            Some(mut free) => {
+                descs.extend((0..n).filter_map(|_| free.pop()).map(FrameDesc::new));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Lock the list once it holds at least `n` frames beyond the
+    /// reserve, waiting up to `timeout` for that, and count them as
+    /// allocated. `None` if it timed out.
+    fn wait_for(&self, n: usize, timeout: Duration) -> Option<MutexGuard<'_, FreeList>> {
+        let needed = n + self.reserved();
+        let free = self.lock();
+
+        let free = if free.len() >= needed {
+            free
+        } else {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+
+            let start = Instant::now();
+
+            let (free, _) = self
+                .available
+                .wait_timeout_while(free, timeout, |free| free.len() < needed)
+                .unwrap_or_else(|e| e.into_inner());
+
+            self.waits
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .record(start.elapsed().as_nanos() as u64);
+
+            if free.len() < needed {
+                self.timeouts.fetch_add(1, Ordering::Relaxed);
+                self.shortfall.fetch_add(n as u64, Ordering::Relaxed);
+                return None;
+            }
+
+            free
+        };
+
+        self.allocs.fetch_add(n as u64, Ordering::Relaxed);
+        self.owned.fetch_add(n, Ordering::Relaxed);
+
+        Some(free)
+    }
+
+    /// Return a frame or slot to the pool, waking any waiting
+    /// allocations.
+    ///
+    /// The frame must have come from this pool and not be in use.
+    #[inline]
+    pub fn free(&self, desc: &FrameDesc) {
+        self.free_batch(slice::from_ref(desc))
+    }
+
+    /// Return several frames or slots to the pool.
+    pub fn free_batch(&self, descs: &[FrameDesc]) {
+        {
+            let mut split = self.lock_split();
+            let mut free = self.lock();
+            let mut aliases = self.lock_aliases();
+
+            self.push(
+                &mut free,
+                split.as_deref_mut(),
+                aliases.as_deref_mut(),
+                descs.iter().map(FrameDesc::addr),
+            );
+        }
+
+        sub(&self.owned, descs.len());
+
+        self.available.notify_all();
+    }
+
+    /// Allocate up to `n` frames and hand them to `fq` to receive
+    /// into, returning the number handed over. `scratch` is cleared
+    /// and used to hold the frames in between.
+    ///
+    /// Since [`FillQueue::produce`] takes all or nothing, if there's
+    /// no room on the ring for what could be allocated nothing is
+    /// handed over and the frames go straight back in the pool.
+    ///
+    /// # Safety
+    ///
+    /// `fq` must belong to the [`Umem`] the pool's frames are from.
+    pub unsafe fn fill(&self, fq: &mut FillQueue, n: usize, scratch: &mut Vec<FrameDesc>) -> usize {
+        // SAFETY: the frames were just allocated so are unused, and
+        // belong to `fq`'s `Umem` by this function's contract.
+        self.fill_with(n, scratch, |descs| unsafe { fq.produce(descs) })
+    }
+
+    fn fill_with<F>(&self, n: usize, scratch: &mut Vec<FrameDesc>, produce: F) -> usize
+    where
+        F: FnOnce(&[FrameDesc]) -> usize,
+    {
+        scratch.clear();
+
+        if self.try_alloc_batch(n, scratch) == 0 {
+            return 0;
+        }
+
+        let cnt = produce(scratch);
+
+        if cnt == 0 {
+            self.free_batch(scratch);
+        } else {
+            sub(&self.owned, cnt);
+            self.filling.fetch_add(cnt, Ordering::Relaxed);
+        }
+
+        cnt
+    }
+
+    /// Consume received frames from `rx` into `descs`, as per
+    /// [`RxQueue::consume`], counting them as back with the
+    /// application. Returns the number consumed.
+    ///
+    /// # Safety
+    ///
+    /// See [`RxQueue::consume`]. The frames on `rx` must have been put
+    /// on the fill queue with [`fill`](Self::fill).
+    pub unsafe fn recv(&self, rx: &mut RxQueue, descs: &mut [FrameDesc]) -> usize {
+        // SAFETY: guaranteed by this function's contract.
+        self.recv_with(descs, |descs| unsafe { rx.consume(descs) })
+    }
+
+    fn recv_with<F>(&self, descs: &mut [FrameDesc], consume: F) -> usize
+    where
+        F: FnOnce(&mut [FrameDesc]) -> usize,
+    {
+        let cnt = consume(descs);
+
+        sub(&self.filling, cnt);
+        self.owned.fetch_add(cnt, Ordering::Relaxed);
+
+        cnt
+    }
+
+    /// Submit `descs` to `tx`, as per [`TxQueue::produce`], counting
+    /// them as in flight until [reaped](Self::reap). Returns the
+    /// number submitted.
+    ///
+    /// # Safety
+    ///
+    /// See [`TxQueue::produce`]. The frames must have come from this
+    /// pool.
+    pub unsafe fn send(&self, tx: &mut TxQueue, descs: &[FrameDesc]) -> usize {
+        // SAFETY: guaranteed by this function's contract.
+        self.send_with(descs, |descs| unsafe { tx.produce(descs) })
+    }
+
+    fn send_with<F>(&self, descs: &[FrameDesc], produce: F) -> usize
+    where
+        F: FnOnce(&[FrameDesc]) -> usize,
+    {
+        let cnt = produce(descs);
+
+        sub(&self.owned, cnt);
+        self.sending.fetch_add(cnt, Ordering::Relaxed);
+
+        cnt
+    }
+
+    /// Consume completed frames from `cq` into `scratch` and return
+    /// them to the pool, waking any waiting allocations. Returns the
+    /// number consumed from `cq`.
+    ///
+    /// Typically called in a loop by a thread dedicated to reaping,
+    /// so blocked writers resume as soon as the kernel is done with
+    /// enough frames.
+    ///
+    /// If the pool has a recycle budget, frames previously spilled
+    /// are returned first, and at most the budget's worth of frames
+    /// in total. See [`CompOverflow`] for what happens to the rest.
+    ///
+    /// # Safety
+    ///
+    /// The frames on `cq` must belong to this pool, i.e. the pool was
+    /// created from the frames of `cq`'s [`Umem`](super::Umem) and
+    /// only frames allocated from it were sent.
+    pub unsafe fn reap(&self, cq: &mut CompQueue, scratch: &mut [FrameDesc]) -> usize {
+        // SAFETY: guaranteed by this function's contract.
+        self.reap_with(scratch, |scratch| unsafe { cq.consume(scratch) })
+    }
+
+    fn reap_with<F>(&self, scratch: &mut [FrameDesc], consume: F) -> usize
+    where
+        F: FnOnce(&mut [FrameDesc]) -> usize,
+    {
+        let mut overflow = self.overflow.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Spilled frames have been waiting longest, so go first.
+        let unspilled = overflow.len().min(self.recycle_budget);
+        let room = self.recycle_budget - unspilled;
+
+        let (cnt, kept) = match self.strategy {
+            CompOverflow::Backpressure => {
+                let limit = room.min(scratch.len());
+                let cnt = consume(&mut scratch[..limit]);
+
+                // There may well be more on the ring, left for later.
+                if cnt == limit && limit < scratch.len() {
+                    self.backpressured.fetch_add(1, Ordering::Relaxed);
+                }
+
+                (cnt, cnt)
+            }
+            CompOverflow::Spill => {
+                let cnt = consume(scratch);
+                (cnt, cnt.min(room))
+            }
+        };
+
+        if unspilled + kept > 0 {
+            let mut split = self.lock_split();
+            let mut free = self.lock();
+            let mut aliases = self.lock_aliases();
+
+            let addrs = overflow[..unspilled]
+                .iter()
+                .copied()
+                .chain(scratch[..kept].iter().map(FrameDesc::addr));
+
+            self.push(
+                &mut free,
+                split.as_deref_mut(),
+                aliases.as_deref_mut(),
+                addrs,
+            );
+
+            overflow.drain(..unspilled);
+
+            self.available.notify_all();
+        }
+
+        if cnt > kept {
+            overflow.extend(scratch[kept..cnt].iter().map(|desc| desc.addr()));
+
+            self.spilled
+                .fetch_add((cnt - kept) as u64, Ordering::Relaxed);
+            self.overflow_peak
+                .fetch_max(overflow.len() as u64, Ordering::Relaxed);
+        }
+
+        sub(&self.sending, cnt);
+
+        cnt
+    }
+
+    /// Submit `descs` to a tx ring through `produce`, as many as
+    /// fit each time round, reaping completions into the pool through
+    /// `consume` in between, until all are submitted or a round makes
+    /// no progress. See [`TxQueue::send_batch`].
+    ///
+    /// `produce` returns the number of frames it put on the ring along
+    /// with any error met after, so those frames are still counted.
+    pub(crate) fn send_batch_with<P, C>(
+        &self,
+        descs: &[FrameDesc],
+        scratch: &mut [FrameDesc],
+        mut produce: P,
+        mut consume: C,
+    ) -> Result<SentBatch, SendBatchError>
+    where
+        P: FnMut(&[FrameDesc]) -> (usize, Option<io::Error>),
+        C: FnMut(&mut [FrameDesc]) -> usize,
+    {
+        let mut sent = SentBatch::default();
+
+        loop {
+            let mut err = None;
+
+            let cnt = self.send_with(&descs[sent.submitted..], |descs| {
+                let (cnt, e) = produce(descs);
+                err = e;
+                cnt
+            });
+
+            sent.submitted += cnt;
+
+            if let Some(err) = err {
+                return Err(SendBatchError { sent, err });
+            }
+
+            let reaped = self.reap_with(scratch, &mut consume);
+            sent.completed += reaped;
+
+            if sent.submitted == descs.len() || (cnt == 0 && reaped == 0) {
+                return Ok(sent);
+            }
+        }
+    }
+
+    /// The number of frames currently in the pool.
+    pub fn available(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// The number of frames the pool was created with.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of frames spilled by [`reap`](Self::reap) and not
+    /// yet returned to the pool. They count towards neither
+    /// [`available`](Self::available) nor what's allocated.
+    pub fn overflow_len(&self) -> usize {
+        self.overflow
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+
+    /// Where the pool's frames currently are.
+    pub fn counts(&self) -> FrameCounts {
+        FrameCounts {
+            free: self.available(),
+            in_fill: self.filling.load(Ordering::Relaxed),
+            in_tx: self.sending.load(Ordering::Relaxed),
+            in_app: self.owned.load(Ordering::Relaxed),
+            in_overflow: self.overflow_len(),
+        }
+    }
+
+    /// A snapshot of the pool's allocation statistics.
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            allocs: self.allocs.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            shortfall: self.shortfall.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            spilled: self.spilled.load(Ordering::Relaxed),
+            overflow_peak: self.overflow_peak.load(Ordering::Relaxed),
+            backpressured: self.backpressured.load(Ordering::Relaxed),
+            slot_allocs: self.slot_allocs.load(Ordering::Relaxed),
+            alias_allocs: self.alias_allocs.load(Ordering::Relaxed),
+            control_allocs: self.control_allocs.load(Ordering::Relaxed),
+            waits: self.waits.lock().unwrap_or_else(|e| e.into_inner()).clone(),
+        }
+    }
+}
+
+/// Decrement `counter` by `n`, stopping at zero should frames have
+/// reached a ring other than through the pool.
+fn sub(counter: &AtomicUsize, n: usize) {
+    let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |cnt| {
+        Some(cnt.saturating_sub(n))
+    });
+}
+
+/// Where a [`FramePool`]'s frames are, from [`FramePool::counts`].
+///
+/// Only frames moved through the pool's own ring methods are tracked
+/// on the rings. Once frames are [split](FramePool::split_frames),
+/// each slot handed out counts as one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameCounts {
+    free: usize,
+    in_fill: usize,
+    in_tx: usize,
+    in_app: usize,
+    in_overflow: usize,
+}
+
+impl FrameCounts {
+    /// Frames in the pool, ready to be allocated.
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    /// Frames handed to the fill queue by [`FramePool::fill`] and
+    /// not yet received.
+    pub fn in_fill(&self) -> usize {
+        self.in_fill
+    }
+
+    /// Frames submitted by [`FramePool::send`] and not yet reaped.
+    pub fn in_tx(&self) -> usize {
+        self.in_tx
+    }
+
+    /// Frames allocated or received and not yet freed or sent, i.e.
+    /// held by the application.
+    pub fn in_app(&self) -> usize {
+        self.in_app
+    }
+
+    /// Completed frames set aside by [`FramePool::reap`], see
+    /// [`CompOverflow::Spill`].
+    pub fn in_overflow(&self) -> usize {
+        self.in_overflow
+    }
+}
+
+/// The outcome of a [`TxQueue::send_batch`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SentBatch {
+    submitted: usize,
+    completed: usize,
+}
+
+impl SentBatch {
+    /// The number of frames submitted to the tx ring, taken from the
+    /// front of those passed in. The rest are still held by the
+    /// application.
+    pub fn submitted(&self) -> usize {
+        self.submitted
+    }
+
+    /// The number of frames fully transmitted and returned to the
+    /// pool. May include frames sent before the batch.
+    pub fn completed(&self) -> usize {
+        self.completed
+    }
+}
+
+/// Error returned when a [`TxQueue::send_batch`] fails part way,
+/// e.g. on waking the kernel.
+#[derive(Debug)]
+pub struct SendBatchError {
+    sent: SentBatch,
+    err: io::Error,
+}
+
+impl SendBatchError {
+    /// What was done before the failure. Frames past
+    /// [`SentBatch::submitted`] are still held by the application,
+    /// those before it belong to the kernel.
+    pub fn sent(&self) -> SentBatch {
+        self.sent
+    }
+
+    /// The underlying error.
+    pub fn io_error(&self) -> &io::Error {
+        &self.err
+    }
+}
+
+impl fmt::Display for SendBatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "failed after submitting {} frames: {}",
+            self.sent.submitted, self.err
+        )
+    }
+}
+
+impl Error for SendBatchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.err)
+    }
+}
+
+impl From<SendBatchError> for io::Error {
+    fn from(e: SendBatchError) -> Self {
+        e.err
+    }
+}
+
+/// Allocation statistics for a [`FramePool`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    allocs: u64,
+    failures: u64,
+    shortfall: u64,
+    timeouts: u64,
+    spilled: u64,
+    overflow_peak: u64,
+    backpressured: u64,
+    slot_allocs: u64,
+    alias_allocs: u64,
+    control_allocs: u64,
+    waits: Histogram,
+}
+
+impl PoolStats {
+    /// The number of frames handed out.
+    pub fn allocs(&self) -> u64 {
+        self.allocs
+    }
+
+    /// The number of allocations which found the pool without enough
+    /// frames, including those that then waited.
+    pub fn failures(&self) -> u64 {
+        self.failures
+    }
+
+    /// The total number of frames asked for but not handed out.
+    pub fn shortfall(&self) -> u64 {
+        self.shortfall
+    }
+
+    /// The number of [`FramePool::alloc_timeout`] and
+    /// [`FramePool::alloc_blocking`] calls which gave up.
+    pub fn timeouts(&self) -> u64 {
+        self.timeouts
+    }
+
+    /// The total number of completed frames [`FramePool::reap`] set
+    /// aside on the overflow list under [`CompOverflow::Spill`].
+    pub fn spilled(&self) -> u64 {
+        self.spilled
+    }
+
+    /// The most frames the overflow list has held at once.
+    pub fn overflow_peak(&self) -> u64 {
+        self.overflow_peak
+    }
+
+    /// The number of [`FramePool::reap`] calls under
+    /// [`CompOverflow::Backpressure`] which stopped at the recycle
+    /// budget, likely leaving completions on the ring.
+    pub fn backpressured(&self) -> u64 {
+        self.backpressured
+    }
+
+    /// The number of slots handed out by
+    /// [`FramePool::try_alloc_slot`]. The frames they were carved from
+    /// are counted in [`allocs`](Self::allocs).
+    pub fn slot_allocs(&self) -> u64 {
+        self.slot_allocs
+    }
+
+    /// The number of aliases made by [`FramePool::alias`]. They share
+    /// frames counted in [`allocs`](Self::allocs).
+    pub fn alias_allocs(&self) -> u64 {
+        self.alias_allocs
+    }
+
+    /// The number of frames handed out by
+    /// [`FramePool::try_alloc_control`]. They're not counted in
+    /// [`allocs`](Self::allocs).
+    pub fn control_allocs(&self) -> u64 {
+        self.control_allocs
+    }
+
+    /// How long, in nanoseconds, each [`FramePool::alloc_timeout`] or
+    /// [`FramePool::alloc_blocking`] call finding too few frames in the
+    /// pool waited.
+    pub fn waits(&self) -> &Histogram {
+        &self.waits
+    }
+}
+
+/// Error detailing why [`FramePool::split_frames`] failed.
+#[derive(Debug)]
+pub enum SplitFramesError {
+    /// The [`Umem`] isn't in unaligned chunk mode.
+    AlignedChunks,
+    /// Fewer than two slots of `slot_size` fit in a frame's packet
+    /// data segment of `frame_mtu` bytes.
+    SlotSize {
+        /// The slot size asked for.
+        slot_size: usize,
+        /// The size of each frame's packet data segment.
+        frame_mtu: usize,
+    },
+}
+
+impl fmt::Display for SplitFramesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::AlignedChunks => write!(f, "UMEM is not in unaligned chunk mode"),
+            Self::SlotSize {
+                slot_size,
+                frame_mtu,
+            } => write!(
+                f,
+                "fewer than two {} byte slots fit in a {} byte frame",
+                slot_size, frame_mtu
+            ),
+        }
+    }
+}
+
+impl Error for SplitFramesError {}
+
+/// Error returned when [`FramePool::alias`] is asked for a range
+/// outside the descriptor's packet data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AliasError {
+    offset: usize,
+    len: usize,
+    data: usize,
+}
+
+impl AliasError {
+    /// The offset asked for.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The length asked for.
+    pub fn length(&self) -> usize {
+        self.len
+    }
+
+    /// The length of the descriptor's packet data.
+    pub fn data_len(&self) -> usize {
+        self.data
+    }
+}
+
+impl fmt::Display for AliasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cannot alias {} bytes at offset {} of a frame holding {}",
+            self.len, self.offset, self.data
+        )
+    }
+}
+
+impl Error for AliasError {}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, sync::Arc, thread};
+
+    use super::*;
+    use crate::umem::frame::fixtures::desc_at;
+
+    fn pool(frames: usize) -> FramePool {
+        let descs: Vec<_> = (0..frames).map(|i| desc_at(i * 2048)).collect();
+        FramePool::new(&descs)
+    }
+
+    #[test]
+    fn double_frees_are_caught_by_the_matching_check_level() {
+        let _guard = check::TEST_LEVEL_LOCK
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let before = check::level();
+
+        let double_free = |level| {
+            check::set_level(level);
+
+            let pool = pool(3);
+            let desc = pool.try_alloc().unwrap();
+            let _held = pool.try_alloc().unwrap();
+
+            pool.free(&desc);
+
+            std::panic::catch_unwind(|| pool.free(&desc)).is_err()
+        };
+
+        let overfill = |level| {
+            check::set_level(level);
+
+            let pool = pool(1);
+            let desc = pool.try_alloc().unwrap();
+
+            pool.free(&desc);
+
+            std::panic::catch_unwind(|| pool.free(&desc_at(2048))).is_err()
+        };
+
+        assert!(!double_free(check::CheckLevel::Off));
+        assert!(!double_free(check::CheckLevel::Cheap));
+        assert!(double_free(check::CheckLevel::Full));
+
+        assert!(!overfill(check::CheckLevel::Off));
+        assert!(overfill(check::CheckLevel::Cheap));
+
+        // A bad frame late in a batch leaves the earlier ones out.
+        check::set_level(check::CheckLevel::Full);
+
+        let pool = pool(3);
+        let a = pool.try_alloc().unwrap();
+        let b = pool.try_alloc().unwrap();
+
+        assert!(std::panic::catch_unwind(|| pool.free_batch(&[a, b, a])).is_err());
+        assert_eq!(pool.available(), 1);
+
+        pool.free_batch(&[a, b]);
+        assert_eq!(pool.available(), 3);
+
+        check::set_level(before);
+    }
+
+    #[test]
+    fn failures_and_shortfall_are_counted() {
+        let pool = pool(3);
+        let mut descs = Vec::new();
+
+        assert_eq!(pool.try_alloc().map(|d| d.addr()), Some(0));
+        assert_eq!(pool.try_alloc_batch(4, &mut descs), 2);
+        assert!(pool.try_alloc().is_none());
+
+        let stats = pool.stats();
+        assert_eq!(stats.allocs(), 3);
+        assert_eq!(stats.failures(), 2);
+        assert_eq!(stats.shortfall(), 3);
+
+        pool.free_batch(&descs);
+        assert_eq!(pool.available(), 2);
+        assert_eq!(pool.capacity(), 3);
+    }
+
+    #[test]
+    fn waits_are_recorded_whether_or_not_a_frame_turns_up() {
+        let pool = Arc::new(pool(1));
+        let desc = pool.try_alloc().unwrap();
+
+        assert!(pool.alloc_timeout(Duration::from_millis(1)).is_none());
+
+        let freer = {
+            let pool = Arc::clone(&pool);
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                pool.free(&desc);
+            })
+        };
+
+        assert!(pool.alloc_timeout(Duration::from_secs(10)).is_some());
+        freer.join().unwrap();
+
+        let stats = pool.stats();
+        assert_eq!(stats.failures(), 2);
+        assert_eq!(stats.timeouts(), 1);
+        assert_eq!(stats.waits().count(), 2);
+        assert!(stats.waits().max() >= 20_000_000);
+    }
+
+    /// Stands in for a completion queue holding `pending`.
+    fn consume_from(pending: &mut Vec<FrameDesc>) -> impl FnOnce(&mut [FrameDesc]) -> usize + '_ {
+        move |scratch| {
+            let cnt = scratch.len().min(pending.len());
+
+            for (slot, desc) in scratch.iter_mut().zip(pending.drain(..cnt)) {
+                *slot = desc;
+            }
+
+            cnt
+        }
+    }
+
+    fn drain_pool(pool: &FramePool) -> Vec<FrameDesc> {
+        let mut descs = Vec::new();
+        pool.try_alloc_batch(pool.capacity(), &mut descs);
+        descs
+    }
+
+    #[test]
+    fn frames_are_tracked_around_the_rings() {
+        let pool = pool(8);
+        let mut scratch = Vec::new();
+
+        // A full fill ring takes nothing, and the frames go back.
+        assert_eq!(pool.fill_with(4, &mut scratch, |_| 0), 0);
+        assert_eq!(pool.counts().free(), 8);
+
+        assert_eq!(pool.fill_with(6, &mut scratch, |descs| descs.len()), 6);
+        let mut filled = scratch.clone();
+
+        let mut rx = vec![desc_at(0); 4];
+        assert_eq!(pool.recv_with(&mut rx, consume_from(&mut filled)), 4);
+
+        assert_eq!(pool.send_with(&rx[..3], |descs| descs.len()), 3);
+        pool.free(&rx[3]);
+
+        let counts = pool.counts();
+        assert_eq!(counts.free(), 3);
+        assert_eq!(counts.in_fill(), 2);
+        assert_eq!(counts.in_tx(), 3);
+        assert_eq!(counts.in_app(), 0);
+
+        let mut sent = rx[..3].to_vec();
+        let mut comp = vec![desc_at(0); 8];
+        assert_eq!(pool.reap_with(&mut comp, consume_from(&mut sent)), 3);
+
+        let counts = pool.counts();
+        assert_eq!(counts.free(), 6);
+        assert_eq!(counts.in_tx(), 0);
+
+        // Reaping frames sent some other way doesn't underflow.
+        let mut stray = vec![pool.try_alloc().unwrap()];
+        pool.reap_with(&mut comp, consume_from(&mut stray));
+        assert_eq!(pool.counts().in_tx(), 0);
+    }
+
+    #[test]
+    fn batches_are_sent_as_room_on_the_ring_comes_back() {
+        let pool = pool(8);
+        let descs = drain_pool(&pool);
+        let mut scratch = vec![desc_at(0); 8];
+
+        // A ring with room for three, which the kernel completes
+        // straight after each wakeup.
+        let mut ring = Vec::new();
+        let done = RefCell::new(Vec::new());
+
+        let sent = pool
+            .send_batch_with(
+                &descs[..7],
+                &mut scratch,
+                |descs| {
+                    let cnt = descs.len().min(3 - ring.len());
+                    ring.extend_from_slice(&descs[..cnt]);
+                    done.borrow_mut().append(&mut ring);
+                    (cnt, None)
+                },
+                |scratch| consume_from(&mut done.borrow_mut())(scratch),
+            )
+            .unwrap();
+
+        assert_eq!(sent.submitted(), 7);
+        assert_eq!(sent.completed(), 7);
+
+        let counts = pool.counts();
+        assert_eq!(counts.free(), 7);
+        assert_eq!(counts.in_tx(), 0);
+        assert_eq!(counts.in_app(), 1);
+
+        // A stalled ring gives up rather than spin.
+        let descs = drain_pool(&pool);
+        let sent = pool
+            .send_batch_with(&descs, &mut scratch, |_| (0, None), |_| 0)
+            .unwrap();
+
+        assert_eq!(sent, SentBatch::default());
+        assert_eq!(pool.counts().in_app(), 8);
+
+        // Frames put on the ring before a failed wakeup still count.
+        let err = pool
+            .send_batch_with(
+                &descs,
+                &mut scratch,
+                |_| (2, Some(io::ErrorKind::Other.into())),
+                |_| 0,
+            )
+            .unwrap_err();
+
+        assert_eq!(err.sent().submitted(), 2);
+        assert_eq!(pool.counts().in_tx(), 2);
+        assert_eq!(pool.counts().in_app(), 6);
+    }
+
+    #[test]
+    fn each_slot_counts_as_held_by_the_application() {
+        let mut pool = pool(2);
+        pool.split = Some(Mutex::new(Split {
+            frame_size: 2048,
+            slot_size: 1024,
+            slots_per_frame: 2,
+            open: None,
+            outstanding: HashMap::new(),
+        }));
+
+        let slots: Vec<_> = (0..3).filter_map(|_| pool.try_alloc_slot()).collect();
+        assert_eq!(pool.counts().in_app(), 3);
+
+        pool.free_batch(&slots);
+        assert_eq!(pool.counts().in_app(), 0);
+    }
+
+    #[test]
+    fn backpressure_leaves_completions_past_the_budget_on_the_ring() {
+        let descs: Vec<_> = (0..8).map(|i| desc_at(i * 2048)).collect();
+        let pool = FramePool::with_overflow(&descs, CompOverflow::Backpressure, 3);
+
+        let mut pending = drain_pool(&pool);
+        let mut scratch = vec![desc_at(0); 8];
+
+        assert_eq!(pool.reap_with(&mut scratch, consume_from(&mut pending)), 3);
+        assert_eq!(pending.len(), 5);
+        assert_eq!(pool.available(), 3);
+
+        assert_eq!(pool.reap_with(&mut scratch, consume_from(&mut pending)), 3);
+        assert_eq!(pool.reap_with(&mut scratch, consume_from(&mut pending)), 2);
+        assert_eq!(pool.available(), 8);
+        assert_eq!(pool.overflow_len(), 0);
+
+        let stats = pool.stats();
+        assert_eq!(stats.backpressured(), 2);
+        assert_eq!(stats.spilled(), 0);
+    }
+
+    #[test]
+    fn spilled_completions_are_returned_ahead_of_new_ones() {
+        let descs: Vec<_> = (0..8).map(|i| desc_at(i * 2048)).collect();
+        let pool = FramePool::with_overflow(&descs, CompOverflow::Spill, 3);
+
+        let mut pending = drain_pool(&pool);
+        let mut scratch = vec![desc_at(0); 8];
+
+        assert_eq!(pool.reap_with(&mut scratch, consume_from(&mut pending)), 8);
+        assert!(pending.is_empty());
+        assert_eq!(pool.available(), 3);
+        assert_eq!(pool.overflow_len(), 5);
+
+        // The budget's taken up by spilled frames alone.
+        pending.extend(drain_pool(&pool));
+
+        assert_eq!(pool.reap_with(&mut scratch, consume_from(&mut pending)), 3);
+        assert_eq!(pool.available(), 3);
+        assert_eq!(pool.overflow_len(), 5);
+
+        assert_eq!(pool.reap_with(&mut scratch, consume_from(&mut pending)), 0);
+        assert_eq!(pool.reap_with(&mut scratch, consume_from(&mut pending)), 0);
+        assert_eq!(pool.available(), 8);
+        assert_eq!(pool.overflow_len(), 0);
+
+        let stats = pool.stats();
+        assert_eq!(stats.spilled(), 8);
+        assert_eq!(stats.overflow_peak(), 5);
+        assert_eq!(stats.backpressured(), 0);
+    }
+
+    #[test]
+    fn split_frames_go_back_once_all_their_slots_have() {
+        let mut pool = pool(2);
+
+        // Four 256 byte slots to each 2048 byte frame, standing in for
+        // `split_frames`, which needs a real UMEM.
+        pool.split = Some(Mutex::new(Split {
+            frame_size: 2048,
+            slot_size: 256,
+            slots_per_frame: 4,
+            open: None,
+            outstanding: HashMap::new(),
+        }));
+
+        let slots: Vec<_> = (0..5).map(|_| pool.try_alloc_slot().unwrap()).collect();
+        let addrs: Vec<_> = slots.iter().map(|d| d.addr()).collect();
+
+        assert_eq!(addrs, [0, 256, 512, 768, 2048]);
+        assert_eq!(pool.available(), 0);
+
+        // Slots return in any order, and the first frame only once
+        // all four have.
+        pool.free_batch(&[slots[2], slots[0], slots[3]]);
+        assert_eq!(pool.available(), 0);
+
+        pool.free(&slots[1]);
+        assert_eq!(pool.available(), 1);
+
+        // The second frame's still being carved from, so stays out
+        // even with none of its slots outstanding.
+        pool.free(&slots[4]);
+        assert_eq!(pool.available(), 1);
+        assert_eq!(pool.try_alloc_slot().map(|d| d.addr()), Some(2048 + 256));
+
+        let stats = pool.stats();
+        assert_eq!(stats.allocs(), 2);
+        assert_eq!(stats.slot_allocs(), 6);
+    }
+
+    fn with_data(mut desc: FrameDesc, len: usize) -> FrameDesc {
+        desc.lengths.data = len;
+        desc
+    }
+
+    #[test]
+    fn reserved_frames_are_only_handed_to_control_traffic() {
+        let pool = pool(4);
+        pool.reserve_for_control(2);
+
+        let mut descs = Vec::new();
+        assert_eq!(pool.try_alloc_batch(4, &mut descs), 2);
+        assert!(pool.try_alloc().is_none());
+        assert!(pool.alloc_timeout(Duration::from_millis(1)).is_none());
+        assert!(!pool.alloc_blocking(3, Duration::from_millis(1), &mut descs));
+
+        let mut scratch = Vec::new();
+        assert_eq!(pool.fill_with(4, &mut scratch, |descs| descs.len()), 0);
+
+        let control: Vec<_> = (0..2).map(|_| pool.try_alloc_control().unwrap()).collect();
+        assert!(pool.try_alloc_control().is_none());
+
+        // Returned frames top the reserve up first.
+        pool.free(&control[0]);
+        assert!(pool.try_alloc().is_none());
+
+        pool.free_batch(&[control[1], descs[0]]);
+        assert!(pool.try_alloc().is_some());
+
+        let stats = pool.stats();
+        assert_eq!(stats.allocs(), 3);
+        assert_eq!(stats.control_allocs(), 2);
+
+        pool.reserve_for_control(0);
+        assert!(pool.try_alloc().is_some());
+    }
+
+    #[test]
+    fn aliased_frames_go_back_once_every_alias_has() {
+        let pool = pool(2);
+        let desc = with_data(pool.try_alloc().unwrap(), 100);
+
+        let whole = pool.alias(&desc, 0, 100).unwrap();
+        let tail = pool.alias(&desc, 60, 40).unwrap();
+        let tail_of_tail = pool.alias(&tail, 20, 20).unwrap();
+
+        assert_eq!(whole.addr(), desc.addr());
+        assert_eq!((tail.addr(), tail.lengths().data()), (desc.addr() + 60, 40));
+        assert_eq!(tail_of_tail.addr(), desc.addr() + 80);
+        assert_eq!(pool.counts().in_app(), 4);
+
+        // The original can go back first, and completions come back
+        // with the alias's address.
+        pool.free(&desc);
+        pool.free(&tail);
+
+        assert_eq!(pool.send_with(&[tail_of_tail], |descs| descs.len()), 1);
+
+        let mut pending = vec![tail_of_tail];
+        let mut scratch = vec![FrameDesc::default(); 2];
+        assert_eq!(pool.reap_with(&mut scratch, consume_from(&mut pending)), 1);
+        assert_eq!(pool.available(), 1);
+
+        pool.free(&whole);
+        assert_eq!(pool.available(), 2);
+        assert_eq!(pool.counts().in_app(), 0);
+        assert_eq!(pool.aliased.load(Ordering::Relaxed), 0);
+
+        // Unaliased frames are untouched by the book-keeping.
+        let other = pool.try_alloc().unwrap();
+        pool.free(&other);
+        assert_eq!(pool.available(), 2);
+
+        assert_eq!(pool.stats().alias_allocs(), 3);
+    }
+
+    #[test]
+    fn aliases_must_lie_within_the_packet_data() {
+        let pool = pool(1);
+        let desc = with_data(pool.try_alloc().unwrap(), 100);
+
+        let err = pool.alias(&desc, 50, 51).unwrap_err();
+        assert_eq!((err.offset(), err.length(), err.data_len()), (50, 51, 100));
+
+        assert!(pool.alias(&desc, 100, 0).is_err());
+        assert!(pool.alias(&desc, usize::MAX, 2).is_err());
+
+        // Nothing was recorded, so the frame goes straight back.
+        pool.free(&desc);
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn aliased_slots_release_their_slot() {
+        let mut pool = pool(1);
+
+        pool.split = Some(Mutex::new(Split {
+            frame_size: 2048,
+            slot_size: 1024,
+            slots_per_frame: 2,
+            open: None,
+            outstanding: HashMap::new(),
+        }));
+
+        let first = with_data(pool.try_alloc_slot().unwrap(), 64);
+        let second = with_data(pool.try_alloc_slot().unwrap(), 64);
+        let alias = pool.alias(&second, 32, 32).unwrap();
+
+        pool.free_batch(&[first, second]);
+        assert_eq!(pool.available(), 0);
+
+        pool.free(&alias);
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn reaped_slots_are_accounted_for_like_freed_ones() {
+        let descs: Vec<_> = (0..1).map(|i| desc_at(i * 2048)).collect();
+        let mut pool = FramePool::with_overflow(&descs, CompOverflow::Spill, 1);
+
+        pool.split = Some(Mutex::new(Split {
+            frame_size: 2048,
+            slot_size: 1024,
+            slots_per_frame: 2,
+            open: None,
+            outstanding: HashMap::new(),
+        }));
+
+        let mut pending: Vec<_> = (0..2).map(|_| pool.try_alloc_slot().unwrap()).collect();
+        assert!(pool.try_alloc_slot().is_none());
+
+        let mut scratch = vec![desc_at(0); 2];
+
+        // One slot's returned, the other spilled.
+        assert_eq!(pool.reap_with(&mut scratch, consume_from(&mut pending)), 2);
+        assert_eq!(pool.available(), 0);
+        assert_eq!(pool.overflow_len(), 1);
+
+        assert_eq!(pool.reap_with(&mut scratch, consume_from(&mut pending)), 0);
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn blocking_batches_wait_for_enough_frames() {
+        let pool = Arc::new(pool(4));
+        let mut held = Vec::new();
+
+        assert!(!pool.alloc_blocking(5, Duration::from_secs(10), &mut held));
+        assert!(pool.alloc_blocking(3, Duration::ZERO, &mut held));
+
+        let freer = {
+            let pool = Arc::clone(&pool);
+            let held = held.clone();
+
+            thread::spawn(move || {
+                for desc in &held {
+                    thread::sleep(Duration::from_millis(5));
+                    pool.free(desc);
+                }
+            })
+        };
+
+        let mut descs = Vec::new();
+        assert!(pool.alloc_blocking(4, Duration::from_secs(10), &mut descs));
+        assert_eq!(descs.len(), 4);
+
+        freer.join().unwrap();
+
+        let stats = pool.stats();
+        assert_eq!(stats.allocs(), 7);
+        assert_eq!(stats.failures(), 2);
+        assert_eq!(stats.shortfall(), 5);
+        assert_eq!(stats.waits().count(), 1);
+    }
+}
@@ -0,0 +1,78 @@
+use std::cell::RefCell;
+
+use super::{frame::FrameDesc, FillQueue};
+
+/// A holding area for frames waiting to go back on a [`FillQueue`].
+///
+/// Frames handed out by [`RxQueue::drain`] are scheduled here when
+/// dropped, and [`flush`](Self::flush) then returns them to the kernel
+/// in one go. Frames may also be scheduled directly with
+/// [`push`](Self::push).
+///
+/// [`RxQueue::drain`]: crate::RxQueue::drain
+#[derive(Debug, Default)]
+pub struct Recycler {
+    pending: RefCell<Vec<FrameDesc>>,
+}
+
+impl Recycler {
+    /// Create an empty recycler.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty recycler with room for `capacity` frames before
+    /// it needs to allocate.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            pending: RefCell::new(Vec::with_capacity(capacity)),
+        }
+    }
+
+    /// Schedule `desc` to go back on the fill queue at the next
+    /// [`flush`](Self::flush).
+    #[inline]
+    pub fn push(&self, desc: FrameDesc) {
+        self.pending.borrow_mut().push(desc);
+    }
+
+    /// The number of frames waiting to be flushed.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.pending.borrow().len()
+    }
+
+    /// Whether there are no frames waiting to be flushed.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.pending.borrow().is_empty()
+    }
+
+    /// Hand as many of the waiting frames to `fq` as it has room for,
+    /// oldest first. Returns the number handed over, the rest stay
+    /// scheduled for the next flush.
+    ///
+    /// Unlike [`FillQueue::produce`], this doesn't fail outright when
+    /// there's not room for everything.
+    ///
+    /// # Safety
+    ///
+    /// See [`FillQueue::produce`]. Every frame scheduled must satisfy
+    /// its requirements.
+    pub unsafe fn flush(&self, fq: &mut FillQueue) -> usize {
+        let mut pending = self.pending.borrow_mut();
+
+        let nb = pending
+            .len()
+            .min(fq.free_slots(pending.len() as u32) as usize);
+
+        // SAFETY: guaranteed by this function's contract.
+        let cnt = unsafe { fq.produce(&pending[..nb]) };
+
+        debug_assert_eq!(cnt, nb);
+
+        pending.drain(..cnt);
+
+        cnt
+    }
+}
@@ -0,0 +1,663 @@
+//! Typed views of the headers in a frame, without copying.
+//!
+//! [`PacketView::parse`] locates the ethernet, IP and UDP or TCP
+//! headers of a frame, e.g. one received into a [`Umem`], and hands
+//! each out as a view borrowing from the frame, with a getter for
+//! every field. Frames are usually viewed straight from the UMEM with
+//! [`Data::view`]:
+//!
+//! ```no_run
+//! # use xsk_rs::{view::TransportView, FrameDesc, Umem};
+//! # unsafe fn handle(umem: &Umem, desc: &FrameDesc) {
+//! let data = unsafe { umem.data(desc) };
+//!
+//! if let Some(pkt) = data.view() {
+//!     if let Some(TransportView::Udp(udp)) = pkt.transport() {
+//!         println!("{} bytes to port {}", pkt.payload().len(), udp.dst_port());
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! Only as much as is there is parsed: a frame whose IP header is cut
+//! short still has an ethernet view, and so on. Up to two VLAN tags
+//! are skipped, IPv4 options are covered by the header but IPv6
+//! extension headers aren't walked, and non-initial IPv4 fragments
+//! have no transport view. Checksums aren't verified.
+//!
+//! [`Data`] also dereferences to the frame's bytes, so it can be
+//! passed to crates like `etherparse` as is.
+//!
+//! [`Umem`]: crate::Umem
+//! [`Data`]: crate::umem::frame::Data
+//! [`Data::view`]: crate::umem::frame::Data::view
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::{
+    addr::{self, MacAddr},
+    packet::{
+        self, ETH_HLEN, ETH_P_IPV4, ETH_P_IPV6, IPPROTO_TCP, IPPROTO_UDP, IPV4_MIN_HLEN, IPV6_HLEN,
+        VLAN_HLEN,
+    },
+};
+
+const UDP_HLEN: usize = 8;
+const TCP_MIN_HLEN: usize = 20;
+
+#[inline]
+fn be16(buf: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([buf[at], buf[at + 1]])
+}
+
+#[inline]
+fn be32(buf: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+}
+
+/// The headers found in a frame, see the [module docs](self).
+#[derive(Debug, Clone, Copy)]
+pub struct PacketView<'a> {
+    frame: &'a [u8],
+    l3: usize,
+    net: Option<NetView<'a>>,
+    transport: Option<TransportView<'a>>,
+    payload: &'a [u8],
+}
+
+impl<'a> PacketView<'a> {
+    /// Find the headers in `frame`. Returns [`None`] if it doesn't
+    /// even hold a whole ethernet header and its VLAN tags.
+    pub fn parse(frame: &'a [u8]) -> Option<Self> {
+        let (ethertype, l3) = packet::l3(frame)?;
+
+        let net = match ethertype {
+            ETH_P_IPV4 => Ipv4View::parse(&frame[l3..]).map(NetView::Ipv4),
+            ETH_P_IPV6 => Ipv6View::parse(&frame[l3..]).map(NetView::Ipv6),
+            _ => None,
+        };
+
+        let (transport, payload) = match net {
+            Some(net) => {
+                // Anything past the IP datagram is ethernet padding.
+                let l4 = l3 + net.header().len();
+                let end = (l3 + net.datagram_len()).min(frame.len()).max(l4);
+                let segment = &frame[l4..end];
+
+                let transport = match (net.protocol(), net.is_fragment()) {
+                    (_, true) => None,
+                    (IPPROTO_UDP, _) => UdpView::parse(segment).map(TransportView::Udp),
+                    (IPPROTO_TCP, _) => TcpView::parse(segment).map(TransportView::Tcp),
+                    _ => None,
+                };
+
+                let payload = match transport {
+                    Some(transport) => &segment[transport.header().len()..],
+                    None => segment,
+                };
+
+                (transport, payload)
+            }
+            None => (None, &frame[l3..]),
+        };
+
+        Some(Self {
+            frame,
+            l3,
+            net,
+            transport,
+            payload,
+        })
+    }
+
+    /// The whole frame.
+    #[inline]
+    pub fn frame(&self) -> &'a [u8] {
+        self.frame
+    }
+
+    /// The ethernet header, including any VLAN tags.
+    #[inline]
+    pub fn ethernet(&self) -> EthernetView<'a> {
+        EthernetView(&self.frame[..self.l3])
+    }
+
+    /// The IP header, if the frame carries IPv4 or IPv6 and the header
+    /// is whole.
+    #[inline]
+    pub fn net(&self) -> Option<NetView<'a>> {
+        self.net
+    }
+
+    /// The UDP or TCP header, if the IP datagram carries one and it's
+    /// whole.
+    #[inline]
+    pub fn transport(&self) -> Option<TransportView<'a>> {
+        self.transport
+    }
+
+    /// Whatever follows the last header found, up to the end of the IP
+    /// datagram if there is one, else of the frame.
+    #[inline]
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+}
+
+/// An ethernet header and its VLAN tags.
+#[derive(Debug, Clone, Copy)]
+pub struct EthernetView<'a>(&'a [u8]);
+
+impl<'a> EthernetView<'a> {
+    /// The header's bytes.
+    #[inline]
+    pub fn header(&self) -> &'a [u8] {
+        self.0
+    }
+
+    /// The destination MAC address.
+    #[inline]
+    pub fn dst(&self) -> MacAddr {
+        addr::read_mac(self.0, 0).unwrap()
+    }
+
+    /// The source MAC address.
+    #[inline]
+    pub fn src(&self) -> MacAddr {
+        addr::read_mac(self.0, 6).unwrap()
+    }
+
+    /// The ethertype after any VLAN tags.
+    #[inline]
+    pub fn ethertype(&self) -> u16 {
+        be16(self.0, self.0.len() - 2)
+    }
+
+    /// The VLAN ID of the inner, or only, tag.
+    #[inline]
+    pub fn vlan(&self) -> Option<u16> {
+        Some(self.tag(self.tags().checked_sub(1)?))
+    }
+
+    /// The VLAN ID of the outer tag, if double tagged.
+    #[inline]
+    pub fn outer_vlan(&self) -> Option<u16> {
+        if self.tags() == 2 {
+            Some(self.tag(0))
+        } else {
+            None
+        }
+    }
+
+    fn tags(&self) -> usize {
+        (self.0.len() - ETH_HLEN) / VLAN_HLEN
+    }
+
+    fn tag(&self, idx: usize) -> u16 {
+        be16(self.0, ETH_HLEN + idx * VLAN_HLEN) & 0x0fff
+    }
+}
+
+/// An IPv4 or IPv6 header.
+#[derive(Debug, Clone, Copy)]
+pub enum NetView<'a> {
+    /// An IPv4 header.
+    Ipv4(Ipv4View<'a>),
+    /// An IPv6 header.
+    Ipv6(Ipv6View<'a>),
+}
+
+impl<'a> NetView<'a> {
+    /// The header's bytes.
+    #[inline]
+    pub fn header(&self) -> &'a [u8] {
+        match self {
+            Self::Ipv4(ip) => ip.header(),
+            Self::Ipv6(ip) => ip.header(),
+        }
+    }
+
+    /// The source address.
+    #[inline]
+    pub fn src(&self) -> IpAddr {
+        match self {
+            Self::Ipv4(ip) => ip.src().into(),
+            Self::Ipv6(ip) => ip.src().into(),
+        }
+    }
+
+    /// The destination address.
+    #[inline]
+    pub fn dst(&self) -> IpAddr {
+        match self {
+            Self::Ipv4(ip) => ip.dst().into(),
+            Self::Ipv6(ip) => ip.dst().into(),
+        }
+    }
+
+    /// The IPv4 protocol or IPv6 next header number.
+    #[inline]
+    pub fn protocol(&self) -> u8 {
+        match self {
+            Self::Ipv4(ip) => ip.protocol(),
+            Self::Ipv6(ip) => ip.next_header(),
+        }
+    }
+
+    /// The IPv4 TTL or IPv6 hop limit.
+    #[inline]
+    pub fn ttl(&self) -> u8 {
+        match self {
+            Self::Ipv4(ip) => ip.ttl(),
+            Self::Ipv6(ip) => ip.hop_limit(),
+        }
+    }
+
+    fn datagram_len(&self) -> usize {
+        match self {
+            Self::Ipv4(ip) => ip.total_len() as usize,
+            Self::Ipv6(ip) => IPV6_HLEN + ip.payload_len() as usize,
+        }
+    }
+
+    fn is_fragment(&self) -> bool {
+        match self {
+            Self::Ipv4(ip) => ip.fragment_offset() != 0,
+            Self::Ipv6(_) => false,
+        }
+    }
+}
+
+/// An IPv4 header, options included.
+#[derive(Debug, Clone, Copy)]
+pub struct Ipv4View<'a>(&'a [u8]);
+
+impl<'a> Ipv4View<'a> {
+    fn parse(buf: &'a [u8]) -> Option<Self> {
+        let ihl = (*buf.first()? & 0x0f) as usize * 4;
+
+        if ihl < IPV4_MIN_HLEN || buf.len() < ihl {
+            return None;
+        }
+
+        Some(Self(&buf[..ihl]))
+    }
+
+    /// The header's bytes.
+    #[inline]
+    pub fn header(&self) -> &'a [u8] {
+        self.0
+    }
+
+    /// The type of service byte, i.e. the DSCP and ECN bits.
+    #[inline]
+    pub fn tos(&self) -> u8 {
+        self.0[1]
+    }
+
+    /// The length of the datagram, header included.
+    #[inline]
+    pub fn total_len(&self) -> u16 {
+        be16(self.0, 2)
+    }
+
+    /// The identification field.
+    #[inline]
+    pub fn id(&self) -> u16 {
+        be16(self.0, 4)
+    }
+
+    /// Whether the don't fragment flag is set.
+    #[inline]
+    pub fn dont_fragment(&self) -> bool {
+        self.0[6] & 0x40 != 0
+    }
+
+    /// Whether the more fragments flag is set.
+    #[inline]
+    pub fn more_fragments(&self) -> bool {
+        self.0[6] & 0x20 != 0
+    }
+
+    /// The fragment's offset in the original datagram, in bytes.
+    #[inline]
+    pub fn fragment_offset(&self) -> u16 {
+        (be16(self.0, 6) & 0x1fff) * 8
+    }
+
+    /// The time to live.
+    #[inline]
+    pub fn ttl(&self) -> u8 {
+        self.0[8]
+    }
+
+    /// The protocol number of the payload.
+    #[inline]
+    pub fn protocol(&self) -> u8 {
+        self.0[9]
+    }
+
+    /// The header checksum.
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        be16(self.0, 10)
+    }
+
+    /// The source address.
+    #[inline]
+    pub fn src(&self) -> Ipv4Addr {
+        addr::read_ipv4(self.0, 12).unwrap()
+    }
+
+    /// The destination address.
+    #[inline]
+    pub fn dst(&self) -> Ipv4Addr {
+        addr::read_ipv4(self.0, 16).unwrap()
+    }
+
+    /// Any options after the fixed part of the header.
+    #[inline]
+    pub fn options(&self) -> &'a [u8] {
+        &self.0[IPV4_MIN_HLEN..]
+    }
+}
+
+/// An IPv6 header, without any extension headers.
+#[derive(Debug, Clone, Copy)]
+pub struct Ipv6View<'a>(&'a [u8]);
+
+impl<'a> Ipv6View<'a> {
+    fn parse(buf: &'a [u8]) -> Option<Self> {
+        buf.get(..IPV6_HLEN).map(Self)
+    }
+
+    /// The header's bytes.
+    #[inline]
+    pub fn header(&self) -> &'a [u8] {
+        self.0
+    }
+
+    /// The traffic class, i.e. the DSCP and ECN bits.
+    #[inline]
+    pub fn traffic_class(&self) -> u8 {
+        (be16(self.0, 0) >> 4) as u8
+    }
+
+    /// The 20 bit flow label.
+    #[inline]
+    pub fn flow_label(&self) -> u32 {
+        be32(self.0, 0) & 0x000f_ffff
+    }
+
+    /// The length of the payload, extension headers included.
+    #[inline]
+    pub fn payload_len(&self) -> u16 {
+        be16(self.0, 4)
+    }
+
+    /// The protocol number of the next header.
+    #[inline]
+    pub fn next_header(&self) -> u8 {
+        self.0[6]
+    }
+
+    /// The hop limit.
+    #[inline]
+    pub fn hop_limit(&self) -> u8 {
+        self.0[7]
+    }
+
+    /// The source address.
+    #[inline]
+    pub fn src(&self) -> Ipv6Addr {
+        addr::read_ipv6(self.0, 8).unwrap()
+    }
+
+    /// The destination address.
+    #[inline]
+    pub fn dst(&self) -> Ipv6Addr {
+        addr::read_ipv6(self.0, 24).unwrap()
+    }
+}
+
+/// A UDP or TCP header.
+#[derive(Debug, Clone, Copy)]
+pub enum TransportView<'a> {
+    /// A UDP header.
+    Udp(UdpView<'a>),
+    /// A TCP header.
+    Tcp(TcpView<'a>),
+}
+
+impl<'a> TransportView<'a> {
+    /// The header's bytes.
+    #[inline]
+    pub fn header(&self) -> &'a [u8] {
+        match self {
+            Self::Udp(udp) => udp.header(),
+            Self::Tcp(tcp) => tcp.header(),
+        }
+    }
+
+    /// The source port.
+    #[inline]
+    pub fn src_port(&self) -> u16 {
+        be16(self.header(), 0)
+    }
+
+    /// The destination port.
+    #[inline]
+    pub fn dst_port(&self) -> u16 {
+        be16(self.header(), 2)
+    }
+}
+
+/// A UDP header.
+#[derive(Debug, Clone, Copy)]
+pub struct UdpView<'a>(&'a [u8]);
+
+impl<'a> UdpView<'a> {
+    fn parse(buf: &'a [u8]) -> Option<Self> {
+        buf.get(..UDP_HLEN).map(Self)
+    }
+
+    /// The header's bytes.
+    #[inline]
+    pub fn header(&self) -> &'a [u8] {
+        self.0
+    }
+
+    /// The source port.
+    #[inline]
+    pub fn src_port(&self) -> u16 {
+        be16(self.0, 0)
+    }
+
+    /// The destination port.
+    #[inline]
+    pub fn dst_port(&self) -> u16 {
+        be16(self.0, 2)
+    }
+
+    /// The length of the datagram, header included.
+    #[inline]
+    pub fn len(&self) -> u16 {
+        be16(self.0, 4)
+    }
+
+    /// Whether the length field says there's no room for a header,
+    /// which only happens in malformed datagrams.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        (self.len() as usize) < UDP_HLEN
+    }
+
+    /// The checksum, zero if unused.
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        be16(self.0, 6)
+    }
+}
+
+/// A TCP header, options included.
+#[derive(Debug, Clone, Copy)]
+pub struct TcpView<'a>(&'a [u8]);
+
+impl<'a> TcpView<'a> {
+    /// The FIN flag.
+    pub const FIN: u8 = 1 << 0;
+    /// The SYN flag.
+    pub const SYN: u8 = 1 << 1;
+    /// The RST flag.
+    pub const RST: u8 = 1 << 2;
+    /// The PSH flag.
+    pub const PSH: u8 = 1 << 3;
+    /// The ACK flag.
+    pub const ACK: u8 = 1 << 4;
+
+    fn parse(buf: &'a [u8]) -> Option<Self> {
+        let hlen = (*buf.get(12)? >> 4) as usize * 4;
+
+        if hlen < TCP_MIN_HLEN || buf.len() < hlen {
+            return None;
+        }
+
+        Some(Self(&buf[..hlen]))
+    }
+
+    /// The header's bytes.
+    #[inline]
+    pub fn header(&self) -> &'a [u8] {
+        self.0
+    }
+
+    /// The source port.
+    #[inline]
+    pub fn src_port(&self) -> u16 {
+        be16(self.0, 0)
+    }
+
+    /// The destination port.
+    #[inline]
+    pub fn dst_port(&self) -> u16 {
+        be16(self.0, 2)
+    }
+
+    /// The sequence number.
+    #[inline]
+    pub fn seq(&self) -> u32 {
+        be32(self.0, 4)
+    }
+
+    /// The acknowledgment number.
+    #[inline]
+    pub fn ack(&self) -> u32 {
+        be32(self.0, 8)
+    }
+
+    /// The flags byte, see [`SYN`](Self::SYN) and the like.
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        self.0[13]
+    }
+
+    /// Whether all of `flags` are set.
+    #[inline]
+    pub fn has_flags(&self, flags: u8) -> bool {
+        self.flags() & flags == flags
+    }
+
+    /// The receive window, unscaled.
+    #[inline]
+    pub fn window(&self) -> u16 {
+        be16(self.0, 14)
+    }
+
+    /// The checksum.
+    #[inline]
+    pub fn checksum(&self) -> u16 {
+        be16(self.0, 16)
+    }
+
+    /// Any options after the fixed part of the header.
+    #[inline]
+    pub fn options(&self) -> &'a [u8] {
+        &self.0[TCP_MIN_HLEN..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Template;
+
+    #[test]
+    fn udp_over_tagged_ipv4_is_viewed_layer_by_layer() {
+        let mut frame = packet::tests::udp4_frame(2, 7, 9);
+        let len = frame.len();
+
+        // Ethernet padding past the datagram isn't payload.
+        frame.extend_from_slice(&[0; 6]);
+
+        let pkt = PacketView::parse(&frame).unwrap();
+
+        let eth = pkt.ethernet();
+        assert_eq!(eth.dst(), MacAddr::from([0xaa; 6]));
+        assert_eq!(eth.ethertype(), ETH_P_IPV4);
+        assert_eq!((eth.outer_vlan(), eth.vlan()), (Some(5), Some(5)));
+
+        let ip = match pkt.net() {
+            Some(NetView::Ipv4(ip)) => ip,
+            net => panic!("expected IPv4, got {:?}", net),
+        };
+        assert_eq!(ip.protocol(), IPPROTO_UDP);
+        assert!(ip.dont_fragment());
+        assert_eq!(ip.total_len() as usize, len - ETH_HLEN - 2 * VLAN_HLEN);
+
+        let udp = match pkt.transport() {
+            Some(TransportView::Udp(udp)) => udp,
+            transport => panic!("expected UDP, got {:?}", transport),
+        };
+        assert_eq!((udp.src_port(), udp.dst_port()), (7, 9));
+        assert_eq!(pkt.payload().len(), len - (ETH_HLEN + 8 + 20 + 8));
+    }
+
+    #[test]
+    fn tcp_over_ipv6_and_short_frames() {
+        let template = Template::builder()
+            .ether([1; 6], [2; 6])
+            .ipv6("fe80::1".parse().unwrap(), "fe80::2".parse().unwrap())
+            .tcp(80, 8080)
+            .payload(b"hi")
+            .finish();
+        let frame = template.bytes();
+
+        let pkt = PacketView::parse(frame).unwrap();
+
+        assert_eq!(pkt.ethernet().vlan(), None);
+        assert_eq!(
+            pkt.net().unwrap().src(),
+            "fe80::1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(pkt.net().unwrap().ttl(), 64);
+
+        let tcp = match pkt.transport() {
+            Some(TransportView::Tcp(tcp)) => tcp,
+            transport => panic!("expected TCP, got {:?}", transport),
+        };
+        assert_eq!(tcp.dst_port(), 8080);
+        assert!(tcp.has_flags(TcpView::ACK) && !tcp.has_flags(TcpView::SYN));
+        assert!(tcp.options().is_empty());
+        assert_eq!(pkt.payload(), b"hi");
+
+        // Cut into the TCP header, then the IP header.
+        let pkt = PacketView::parse(&frame[..ETH_HLEN + IPV6_HLEN + 10]).unwrap();
+        assert!(pkt.net().is_some() && pkt.transport().is_none());
+        assert_eq!(pkt.payload().len(), 10);
+
+        let pkt = PacketView::parse(&frame[..ETH_HLEN + 10]).unwrap();
+        assert!(pkt.net().is_none());
+
+        assert!(PacketView::parse(&frame[..ETH_HLEN - 1]).is_none());
+    }
+}
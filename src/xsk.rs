@@ -0,0 +1,100 @@
+//! Creating a [`Umem`] and a socket bound to it in a single call.
+
+use std::{error::Error, fmt, num::NonZeroU32};
+
+use crate::{
+    config::{Interface, SocketConfig, UmemConfig},
+    socket::{Socket, SocketCreateError},
+    umem::{frame::FrameDesc, CompQueue, FillQueue, Umem, UmemCreateError},
+    RxQueue, TxQueue,
+};
+
+/// A [`Umem`] along with an AF_XDP socket bound to it, and all of
+/// their queues.
+///
+/// The [`Umem`] is owned by this socket alone, so all four queues are
+/// always present.
+#[derive(Debug)]
+pub struct Xsk {
+    /// The socket's [`Umem`].
+    pub umem: Umem,
+    /// Descriptors for all frames of [`umem`](Self::umem).
+    pub descs: Vec<FrameDesc>,
+    /// The socket's tx queue.
+    pub tx_q: TxQueue,
+    /// The socket's rx queue.
+    pub rx_q: RxQueue,
+    /// The [`umem`](Self::umem)'s fill queue.
+    pub fq: FillQueue,
+    /// The [`umem`](Self::umem)'s completion queue.
+    pub cq: CompQueue,
+}
+
+impl Xsk {
+    /// Create a [`Umem`] with `frame_count` frames and bind a socket
+    /// to it on `queue_id` of `if_name`.
+    ///
+    /// The [`Umem`] is not backed by huge pages. Use [`Umem::new`] and
+    /// [`Socket::new`] directly for that, or to share a [`Umem`]
+    /// between sockets.
+    ///
+    /// May require root permissions to create successfully.
+    pub fn build(
+        if_name: &Interface,
+        queue_id: u32,
+        umem_config: UmemConfig,
+        socket_config: SocketConfig,
+        frame_count: NonZeroU32,
+    ) -> Result<Self, XskBuildError> {
+        let (umem, descs) =
+            Umem::new(umem_config, frame_count, false).map_err(XskBuildError::Umem)?;
+
+        // SAFETY: the UMEM was just created and is not shared.
+        let (tx_q, rx_q, fq_and_cq) =
+            unsafe { Socket::new(socket_config, &umem, if_name, queue_id) }
+                .map_err(XskBuildError::Socket)?;
+
+        let (fq, cq) = fq_and_cq.ok_or(XskBuildError::MissingQueues)?;
+
+        Ok(Self {
+            umem,
+            descs,
+            tx_q,
+            rx_q,
+            fq,
+            cq,
+        })
+    }
+}
+
+/// Error detailing which step of [`Xsk::build`] failed.
+#[derive(Debug)]
+pub enum XskBuildError {
+    /// Creating the [`Umem`] failed.
+    Umem(UmemCreateError),
+    /// Creating the socket failed.
+    Socket(SocketCreateError),
+    /// Socket creation didn't return a fill queue and completion
+    /// queue, which implies the queue was already bound to.
+    MissingQueues,
+}
+
+impl fmt::Display for XskBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Umem(_) => write!(f, "failed to create UMEM"),
+            Self::Socket(_) => write!(f, "failed to create socket"),
+            Self::MissingQueues => write!(f, "no fill queue and completion queue returned"),
+        }
+    }
+}
+
+impl Error for XskBuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Umem(err) => Some(err),
+            Self::Socket(err) => Some(err),
+            Self::MissingQueues => None,
+        }
+    }
+}
@@ -0,0 +1,244 @@
+#![cfg(feature = "async-io")]
+
+#[allow(dead_code)]
+mod setup;
+use setup::{PacketGenerator, Xsk, XskConfig, ETHERNET_PACKET};
+
+use async_io::Timer;
+use serial_test::serial;
+use std::{
+    convert::TryInto,
+    io::Write,
+    time::{Duration, Instant},
+};
+use xsk_rs::{
+    async_io::{AsyncIoReadiness, AsyncRxQueue, AsyncTxQueue},
+    config::{BindFlags, QueueSize, SocketConfig, UmemConfig},
+};
+
+const Q_SIZE: u32 = 4;
+const FRAME_COUNT: u32 = 8;
+
+fn build_configs() -> (UmemConfig, SocketConfig) {
+    let umem_config = UmemConfig::builder()
+        .comp_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .fill_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .build()
+        .unwrap();
+
+    let socket_config = SocketConfig::builder()
+        .tx_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .rx_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .build();
+
+    (umem_config, socket_config)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn awaited_consume_receives_what_was_sent() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let Xsk {
+            umem: umem1,
+            tx_q,
+            descs: mut descs1,
+            ..
+        } = dev1.0;
+
+        let Xsk {
+            umem: umem2,
+            rx_q,
+            mut fq,
+            descs: mut descs2,
+            ..
+        } = dev2.0;
+
+        let mut tx_q: AsyncTxQueue<AsyncIoReadiness> = AsyncTxQueue::new(tx_q).unwrap();
+        let mut rx_q: AsyncRxQueue<AsyncIoReadiness> = AsyncRxQueue::new(rx_q).unwrap();
+
+        async_io::block_on(async {
+            unsafe {
+                assert_eq!(fq.produce(&descs2[0..1]), 1);
+
+                umem1
+                    .data_mut(&mut descs1[0])
+                    .cursor()
+                    .write_all(&ETHERNET_PACKET[..])
+                    .unwrap();
+
+                assert_eq!(tx_q.produce(&descs1[..1]).await.unwrap(), 1);
+
+                assert_eq!(rx_q.consume(&mut descs2).await.unwrap(), 1);
+
+                assert_eq!(umem2.data(&descs2[0]).contents(), ETHERNET_PACKET);
+            }
+        });
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn consume_timeout_gives_up_without_losing_frames() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let Xsk {
+            umem: umem1,
+            tx_q,
+            descs: mut descs1,
+            ..
+        } = dev1.0;
+
+        let Xsk {
+            umem: umem2,
+            rx_q,
+            mut fq,
+            descs: mut descs2,
+            ..
+        } = dev2.0;
+
+        let mut tx_q: AsyncTxQueue<AsyncIoReadiness> = AsyncTxQueue::new(tx_q).unwrap();
+        let mut rx_q: AsyncRxQueue<AsyncIoReadiness> = AsyncRxQueue::new(rx_q).unwrap();
+
+        async_io::block_on(async {
+            unsafe {
+                assert_eq!(fq.produce(&descs2[0..1]), 1);
+
+                // Nothing sent yet, so this should time out.
+                let start = Instant::now();
+                let timeout = Duration::from_millis(50);
+
+                assert_eq!(rx_q.consume_timeout(&mut descs2, timeout).await.unwrap(), 0);
+                assert!(start.elapsed() >= timeout);
+
+                umem1
+                    .data_mut(&mut descs1[0])
+                    .cursor()
+                    .write_all(&ETHERNET_PACKET[..])
+                    .unwrap();
+
+                assert_eq!(tx_q.produce(&descs1[..1]).await.unwrap(), 1);
+
+                // Give the frame time to arrive, then check a deadline
+                // that's already passed still picks it up.
+                Timer::after(Duration::from_millis(50)).await;
+
+                assert_eq!(
+                    rx_q.consume_deadline(&mut descs2, Instant::now())
+                        .await
+                        .unwrap(),
+                    1
+                );
+
+                assert_eq!(umem2.data(&descs2[0]).contents(), ETHERNET_PACKET);
+            }
+        });
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn producing_more_than_ring_size_fails() {
+    fn test(dev1: (Xsk, PacketGenerator), _dev2: (Xsk, PacketGenerator)) {
+        let Xsk { tx_q, descs, .. } = dev1.0;
+
+        let mut tx_q: AsyncTxQueue<AsyncIoReadiness> = AsyncTxQueue::new(tx_q).unwrap();
+
+        async_io::block_on(async {
+            let descs = &descs[..Q_SIZE as usize + 1];
+
+            assert!(unsafe { tx_q.produce(descs).await }.is_err());
+        });
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn need_wakeup_sockets_yield_rather_than_register_while_draining() {
+    fn test(dev1: (Xsk, PacketGenerator), _dev2: (Xsk, PacketGenerator)) {
+        let Xsk {
+            umem, tx_q, descs, ..
+        } = dev1.0;
+
+        assert!(tx_q.uses_need_wakeup());
+
+        let mut tx_q: AsyncTxQueue<AsyncIoReadiness> = AsyncTxQueue::new(tx_q).unwrap();
+
+        assert_eq!(tx_q.wakeup_stats().registrations(), 0);
+        assert_eq!(tx_q.wakeup_stats().yields(), 0);
+
+        async_io::block_on(async {
+            let mut descs = descs;
+
+            for desc in descs.iter_mut() {
+                unsafe { umem.data_mut(desc) }
+                    .cursor()
+                    .write_all(&ETHERNET_PACKET[..])
+                    .unwrap();
+            }
+
+            // Keep the ring full so that waits are needed.
+            for chunk in descs.chunks(Q_SIZE as usize) {
+                assert_eq!(unsafe { tx_q.produce(chunk).await }.unwrap(), chunk.len());
+            }
+        });
+
+        let stats = tx_q.wakeup_stats();
+
+        // Some waits outlast the yields and fall back to the reactor,
+        // and each of those is woken once the ring has room again.
+        assert!(stats.registrations() > 0);
+        assert_eq!(stats.wakes(), stats.registrations());
+    }
+
+    let (dev1_umem_config, _) = build_configs();
+    let (dev2_umem_config, dev2_socket_config) = build_configs();
+
+    let dev1_socket_config = SocketConfig::builder()
+        .tx_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .rx_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .bind_flags(BindFlags::XDP_USE_NEED_WAKEUP)
+        .build();
+
+    setup::run_test(
+        XskConfig {
+            frame_count: FRAME_COUNT.try_into().unwrap(),
+            umem_config: dev1_umem_config,
+            socket_config: dev1_socket_config,
+        },
+        XskConfig {
+            frame_count: FRAME_COUNT.try_into().unwrap(),
+            umem_config: dev2_umem_config,
+            socket_config: dev2_socket_config,
+        },
+        test,
+    )
+    .await;
+}
+
+async fn build_configs_and_run_test<F>(test: F)
+where
+    F: Fn((Xsk, PacketGenerator), (Xsk, PacketGenerator)) + Send + 'static,
+{
+    let (dev1_umem_config, dev1_socket_config) = build_configs();
+    let (dev2_umem_config, dev2_socket_config) = build_configs();
+
+    setup::run_test(
+        XskConfig {
+            frame_count: FRAME_COUNT.try_into().unwrap(),
+            umem_config: dev1_umem_config,
+            socket_config: dev1_socket_config,
+        },
+        XskConfig {
+            frame_count: FRAME_COUNT.try_into().unwrap(),
+            umem_config: dev2_umem_config,
+            socket_config: dev2_socket_config,
+        },
+        test,
+    )
+    .await;
+}
@@ -0,0 +1,141 @@
+#[allow(dead_code)]
+#[path = "../examples/dns_responder/fast_path.rs"]
+mod fast_path;
+
+use std::net::Ipv4Addr;
+
+use etherparse::{IpHeader, PacketBuilder, PacketHeaders, TransportHeader};
+
+use fast_path::Zone;
+
+const CLIENT_MAC: [u8; 6] = [0xf6, 0xe0, 0xf6, 0xc9, 0x60, 0x0a];
+const SERVER_MAC: [u8; 6] = [0x4a, 0xf1, 0x30, 0xeb, 0x0d, 0x31];
+const CLIENT_IP: [u8; 4] = [192, 168, 69, 1];
+const SERVER_IP: [u8; 4] = [192, 168, 69, 2];
+
+fn zone() -> Zone {
+    let mut zone = Zone::new(300);
+    zone.add_a("www.example.com", Ipv4Addr::new(192, 0, 2, 2));
+    zone
+}
+
+fn query(name: &str, qtype: u16, extra: &[u8]) -> Vec<u8> {
+    let mut dns = vec![0xbe, 0xef, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+
+    for label in name.split('.') {
+        dns.push(label.len() as u8);
+        dns.extend_from_slice(label.as_bytes());
+    }
+
+    dns.push(0);
+    dns.extend_from_slice(&qtype.to_be_bytes());
+    dns.extend_from_slice(&[0, 1]);
+    dns.extend_from_slice(extra);
+
+    let builder = PacketBuilder::ethernet2(CLIENT_MAC, SERVER_MAC)
+        .ipv4(CLIENT_IP, SERVER_IP, 20)
+        .udp(40000, 53);
+
+    let mut pkt = Vec::with_capacity(builder.size(dns.len()));
+    builder.write(&mut pkt, &dns).unwrap();
+
+    pkt
+}
+
+/// Run `pkt` through the fast path in a frame sized buffer, returning
+/// the response.
+fn respond(pkt: &[u8]) -> Option<Vec<u8>> {
+    let mut buf = vec![0; 2048];
+    buf[..pkt.len()].copy_from_slice(pkt);
+
+    fast_path::respond(&zone(), 53, &mut buf, pkt.len()).map(|len| buf[..len].to_vec())
+}
+
+/// Check the response's headers and checksums, returning its DNS
+/// message.
+fn check_response(resp: &[u8]) -> Vec<u8> {
+    let headers = PacketHeaders::from_ethernet_slice(resp).unwrap();
+
+    let eth = headers.link.unwrap();
+    assert_eq!(eth.source, SERVER_MAC);
+    assert_eq!(eth.destination, CLIENT_MAC);
+
+    let ip = match headers.ip.unwrap() {
+        IpHeader::Version4(ip, _) => ip,
+        IpHeader::Version6(..) => panic!("expected IPv4"),
+    };
+
+    assert_eq!(ip.source, SERVER_IP);
+    assert_eq!(ip.destination, CLIENT_IP);
+    assert_eq!(ip.time_to_live, 64);
+    assert_eq!(usize::from(ip.total_len()), resp.len() - 14);
+    assert_eq!(ip.header_checksum, ip.calc_header_checksum().unwrap());
+
+    let udp = match headers.transport.unwrap() {
+        TransportHeader::Udp(udp) => udp,
+        _ => panic!("expected UDP"),
+    };
+
+    assert_eq!(udp.source_port, 53);
+    assert_eq!(udp.destination_port, 40000);
+    assert_eq!(usize::from(udp.length), resp.len() - 34);
+    assert_eq!(
+        udp.checksum,
+        udp.calc_checksum_ipv4(&ip, headers.payload).unwrap()
+    );
+
+    headers.payload.to_vec()
+}
+
+#[test]
+fn a_query_for_a_known_name_is_answered_in_place() {
+    let pkt = query("WWW.Example.com", 1, &[]);
+    let dns = check_response(&respond(&pkt).unwrap());
+
+    // Id kept, QR, AA and RD set, NOERROR, one question and one answer.
+    assert_eq!(&dns[..12], [0xbe, 0xef, 0x85, 0x00, 0, 1, 0, 1, 0, 0, 0, 0]);
+
+    let question_len = pkt.len() - 42 - 12;
+    let answer = &dns[12 + question_len..];
+
+    assert_eq!(
+        answer,
+        [0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0x01, 0x2c, 0, 4, 192, 0, 2, 2]
+    );
+}
+
+#[test]
+fn unknown_names_get_nxdomain_and_other_types_no_answers() {
+    let dns = check_response(&respond(&query("nope.example.com", 1, &[])).unwrap());
+    assert_eq!(&dns[2..8], [0x85, 0x03, 0, 1, 0, 0]);
+
+    // AAAA for a name with only an A record, with an EDNS OPT record
+    // in the additional section, which is dropped.
+    let mut pkt = query(
+        "www.example.com",
+        28,
+        &[0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0],
+    );
+    pkt[42 + 11] = 1;
+
+    let dns = check_response(&respond(&pkt).unwrap());
+    assert_eq!(&dns[2..12], [0x85, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(dns.len(), pkt.len() - 42 - 11);
+}
+
+#[test]
+fn responses_and_other_traffic_are_ignored() {
+    // Already a response.
+    let mut pkt = query("www.example.com", 1, &[]);
+    pkt[42 + 2] |= 0x80;
+    assert!(respond(&pkt).is_none());
+
+    // Not to the DNS port.
+    let mut pkt = query("www.example.com", 1, &[]);
+    pkt[36..38].copy_from_slice(&5353u16.to_be_bytes());
+    assert!(respond(&pkt).is_none());
+
+    // Truncated mid-question.
+    let pkt = query("www.example.com", 1, &[]);
+    assert!(respond(&pkt[..pkt.len() - 3]).is_none());
+}
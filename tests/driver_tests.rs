@@ -0,0 +1,149 @@
+#[allow(dead_code)]
+mod setup;
+use setup::{PacketGenerator, Xsk, XskConfig, ETHERNET_PACKET};
+
+use serial_test::serial;
+use std::{
+    cell::RefCell,
+    convert::TryInto,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+use xsk_rs::{
+    config::{QueueSize, SocketConfig, UmemConfig},
+    driver::Driver,
+    CancelToken,
+};
+
+const Q_SIZE: u32 = 16;
+const FRAME_COUNT: u32 = 32;
+
+fn xsk_config() -> XskConfig {
+    let umem_config = UmemConfig::builder()
+        .comp_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .fill_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .build()
+        .unwrap();
+
+    let socket_config = SocketConfig::builder()
+        .tx_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .rx_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .build();
+
+    XskConfig {
+        frame_count: FRAME_COUNT.try_into().unwrap(),
+        umem_config,
+        socket_config,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn callbacks_see_what_was_sent_and_completed() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut sender = Driver::new(dev1.0).unwrap();
+        let mut receiver = Driver::new(dev2.0).unwrap();
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let completed = Rc::new(RefCell::new(0));
+
+        {
+            let received = Rc::clone(&received);
+
+            receiver.on_rx(move |batch| {
+                for i in 0..batch.len() {
+                    received
+                        .borrow_mut()
+                        .push(batch.data(i).contents().to_vec());
+                }
+            });
+        }
+
+        {
+            let completed = Rc::clone(&completed);
+
+            sender.on_tx_complete(move |batch| {
+                *completed.borrow_mut() += batch.descs().len();
+            });
+        }
+
+        assert!(sender.send(&ETHERNET_PACKET));
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+
+        while (received.borrow().is_empty() || *completed.borrow() == 0)
+            && Instant::now() < deadline
+        {
+            sender.turn(0).unwrap();
+            receiver.turn(10).unwrap();
+        }
+
+        assert_eq!(received.borrow().as_slice(), [ETHERNET_PACKET.to_vec()]);
+        assert_eq!(*completed.borrow(), 1);
+
+        assert_eq!(sender.stats().tx(), 1);
+        assert_eq!(sender.stats().completed(), 1);
+        assert_eq!(receiver.stats().rx(), 1);
+    }
+
+    setup::run_test(xsk_config(), xsk_config(), test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn run_returns_once_stopped() {
+    fn test(dev1: (Xsk, PacketGenerator), _dev2: (Xsk, PacketGenerator)) {
+        let mut driver = Driver::new(dev1.0).unwrap();
+
+        let stopper = driver.stopper();
+
+        let start = Instant::now();
+
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            stopper.stop();
+        });
+
+        driver.poll_timeout(10_000).run().unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        handle.join().unwrap();
+    }
+
+    setup::run_test(xsk_config(), xsk_config(), test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn run_returns_once_cancelled_and_stays_cancelled() {
+    fn test(dev1: (Xsk, PacketGenerator), _dev2: (Xsk, PacketGenerator)) {
+        let mut driver = Driver::new(dev1.0).unwrap();
+
+        let cancel = CancelToken::new().unwrap();
+        driver.cancel_on(&cancel).poll_timeout(10_000);
+
+        let start = Instant::now();
+
+        let handle = {
+            let cancel = cancel.clone();
+
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                cancel.cancel();
+            })
+        };
+
+        driver.run().unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let turns = driver.stats().turns();
+        driver.run().unwrap();
+        assert_eq!(driver.stats().turns(), turns);
+
+        handle.join().unwrap();
+    }
+
+    setup::run_test(xsk_config(), xsk_config(), test).await
+}
@@ -0,0 +1,40 @@
+#[allow(dead_code)]
+mod setup;
+use setup::{veth_setup, VethDevConfig, ETHERNET_PACKET};
+
+use serial_test::serial;
+use std::time::Duration;
+use xsk_rs::easy::XskSocket;
+
+#[tokio::test]
+#[serial]
+async fn packets_are_sent_and_received_without_unsafe() {
+    let inner = move |dev1_config: VethDevConfig, dev2_config: VethDevConfig| {
+        let mut sender = XskSocket::new(&dev1_config.if_name().parse().unwrap(), 0).unwrap();
+        let mut receiver = XskSocket::new(&dev2_config.if_name().parse().unwrap(), 0).unwrap();
+
+        let mut pkt = Vec::new();
+
+        assert!(!receiver.recv(&mut pkt).unwrap());
+
+        for _ in 0..4 {
+            assert!(sender.send(&ETHERNET_PACKET).unwrap());
+        }
+
+        for _ in 0..4 {
+            assert!(receiver
+                .recv_timeout(&mut pkt, Duration::from_secs(1))
+                .unwrap());
+            assert_eq!(pkt, &ETHERNET_PACKET[..]);
+        }
+
+        assert!(sender.send(&vec![0; 1 << 16]).is_err());
+        assert_eq!(sender.tx_pool().counts().in_app(), 0);
+    };
+
+    let (dev1_config, dev2_config) = setup::default_veth_dev_configs();
+
+    veth_setup::run_with_veth_pair(inner, dev1_config, dev2_config)
+        .await
+        .unwrap();
+}
@@ -72,6 +72,36 @@ async fn produce_one_is_ok() {
     build_configs_and_run_test(test).await
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn recycle_from_rx_resets_descs_and_is_all_or_nothing() {
+    fn test(dev1: (Xsk, PacketGenerator), _dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+
+        let mut descs = xsk1.descs[..5].to_vec();
+
+        for desc in descs.iter_mut() {
+            desc.set_meta_len(8);
+            desc.set_continued(true);
+        }
+
+        assert_eq!(unsafe { xsk1.fq.recycle_from_rx(&mut descs) }, 0);
+        assert!(descs.iter().all(|d| d.is_continued()));
+
+        assert_eq!(unsafe { xsk1.fq.recycle_from_rx(&mut descs[..4]) }, 4);
+
+        for (desc, orig) in descs[..4].iter().zip(&xsk1.descs) {
+            assert_eq!(desc.addr(), orig.addr());
+            assert_eq!(desc.options(), 0);
+            assert_eq!(desc.lengths().meta(), 0);
+        }
+
+        assert!(descs[4].is_continued());
+    }
+
+    build_configs_and_run_test(test).await
+}
+
 async fn build_configs_and_run_test<F>(test: F)
 where
     F: Fn((Xsk, PacketGenerator), (Xsk, PacketGenerator)) + Send + 'static,
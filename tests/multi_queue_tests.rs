@@ -0,0 +1,35 @@
+#[allow(dead_code)]
+mod setup;
+use setup::{veth_setup, VethDevConfig};
+
+use serial_test::serial;
+use xsk_rs::multi_queue::{self, MultiQueueConfig};
+
+#[tokio::test]
+#[serial]
+async fn a_socket_is_bound_to_every_rx_queue() {
+    let inner = move |dev1_config: VethDevConfig, _dev2_config: VethDevConfig| {
+        let if_name = dev1_config.if_name().parse().unwrap();
+        let queues = multi_queue::rx_queue_count(&if_name).unwrap();
+
+        assert!(queues > 0);
+
+        for shared in [false, true] {
+            let mut config = MultiQueueConfig::default();
+            config.shared_umem(shared);
+
+            let xsks = multi_queue::bind_all(&if_name, &config).unwrap();
+
+            assert_eq!(xsks.len(), queues as usize);
+            assert!(xsks
+                .iter()
+                .all(|xsk| xsk.descs.len() == multi_queue::DEFAULT_FRAMES_PER_QUEUE as usize));
+        }
+    };
+
+    let (dev1_config, dev2_config) = setup::default_veth_dev_configs();
+
+    veth_setup::run_with_veth_pair(inner, dev1_config, dev2_config)
+        .await
+        .unwrap();
+}
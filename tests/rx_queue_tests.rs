@@ -4,8 +4,21 @@ use setup::{PacketGenerator, Xsk, XskConfig, ETHERNET_PACKET};
 
 use libxdp_sys::XDP_PACKET_HEADROOM;
 use serial_test::serial;
-use std::{convert::TryInto, io::Write};
-use xsk_rs::config::{FrameSize, QueueSize, SocketConfig, UmemConfig, XDP_UMEM_MIN_CHUNK_SIZE};
+use std::{
+    convert::TryInto,
+    io::Write,
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+use xsk_rs::{
+    addr,
+    config::{FrameSize, QueueSize, SocketConfig, UmemConfig, XDP_UMEM_MIN_CHUNK_SIZE},
+    filter::XskMap,
+    fixed::{FixedCompQueue, FixedFillQueue, FixedRxQueue, FixedTxQueue},
+    socket::{BindMode, BusyPoll, FanIn, Interest, NapiIdError, PollSet},
+    steering::{ExpectedTraffic, Mismatch, SteeringCheck},
+    umem::{frame::DescBatch, Recycler},
+};
 
 const CQ_SIZE: u32 = 4;
 const FQ_SIZE: u32 = 4;
@@ -418,6 +431,657 @@ async fn xdp_statistics_report_dropped_packet() {
     build_configs_and_run_test(test).await
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn xdp_prog_watcher_reports_nothing_until_prog_changes() {
+    fn test(dev1: (Xsk, PacketGenerator), _dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+
+        let watcher = xsk1.rx_q.xdp_prog_watcher();
+
+        assert_ne!(watcher.expected_prog_id(), 0);
+        assert_eq!(watcher.poll().unwrap(), None);
+
+        // Rebinding with the original program still attached is a
+        // no-op as far as the watcher is concerned.
+        let prog_id = xsk1.rx_q.rebind().unwrap();
+
+        assert_eq!(prog_id, watcher.expected_prog_id());
+        assert_eq!(watcher.poll().unwrap(), None);
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn counters_track_packets_and_bytes_in_each_direction() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+        let mut xsk2 = dev2.0;
+
+        unsafe {
+            assert_eq!(xsk2.fq.produce(&xsk2.descs[0..1]), 1);
+
+            xsk1.umem
+                .data_mut(&mut xsk1.descs[0])
+                .cursor()
+                .write_all(&ETHERNET_PACKET[..])
+                .unwrap();
+
+            assert_eq!(xsk1.tx_q.produce_and_wakeup(&xsk1.descs[..1]).unwrap(), 1);
+            assert_eq!(xsk2.rx_q.poll_and_consume(&mut xsk2.descs, 100).unwrap(), 1);
+        }
+
+        for counters in [xsk1.tx_q.counters(), xsk2.rx_q.counters()] {
+            assert_eq!(counters.packets(), 1);
+            assert_eq!(counters.bytes(), ETHERNET_PACKET.len() as u64);
+        }
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn batch_consume_matches_what_was_sent_as_a_batch() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+        let mut xsk2 = dev2.0;
+
+        let mut tx_batch = DescBatch::new(2);
+
+        unsafe {
+            assert_eq!(xsk2.fq.produce(&xsk2.descs[..2]), 2);
+
+            for desc in xsk1.descs[..2].iter_mut() {
+                xsk1.umem
+                    .data_mut(desc)
+                    .cursor()
+                    .write_all(&ETHERNET_PACKET[..])
+                    .unwrap();
+
+                assert!(tx_batch.push(desc));
+            }
+
+            assert_eq!(xsk1.tx_q.produce_batch(&tx_batch), 2);
+            xsk1.tx_q.commit_wakeup().unwrap();
+
+            assert!(xsk2.rx_q.poll(100).unwrap());
+        }
+
+        let mut rx_batch = DescBatch::new(4);
+
+        // Both may not have landed at once.
+        let mut received = Vec::new();
+
+        for _ in 0..10 {
+            unsafe { xsk2.rx_q.consume_batch(&mut rx_batch) };
+            received.extend(rx_batch.iter());
+
+            if received.len() == 2 {
+                break;
+            }
+
+            xsk2.rx_q.poll(100).unwrap();
+        }
+
+        assert_eq!(received.len(), 2);
+
+        for desc in received {
+            assert_eq!(desc.lengths().data(), ETHERNET_PACKET.len());
+            assert_eq!(unsafe { xsk2.umem.data(&desc) }.contents(), ETHERNET_PACKET);
+        }
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn drained_frames_are_recycled_unless_kept() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+        let mut xsk2 = dev2.0;
+
+        unsafe {
+            assert_eq!(xsk2.fq.produce(&xsk2.descs[..2]), 2);
+
+            for desc in xsk1.descs[..2].iter_mut() {
+                xsk1.umem
+                    .data_mut(desc)
+                    .cursor()
+                    .write_all(&ETHERNET_PACKET[..])
+                    .unwrap();
+            }
+
+            assert_eq!(xsk1.tx_q.extend(&xsk1.descs[..2]), 2);
+            xsk1.tx_q.commit_wakeup().unwrap();
+        }
+
+        let recycler = Recycler::with_capacity(2);
+        let mut kept = Vec::new();
+        let mut seen = 0;
+
+        for _ in 0..10 {
+            xsk2.rx_q.poll(100).unwrap();
+
+            // Take just the one at a time, anything else should stay
+            // on the ring for the next drain.
+            for frame in unsafe { xsk2.rx_q.drain(&recycler, 1) } {
+                assert_eq!(
+                    unsafe { xsk2.umem.data(frame.desc()) }.contents(),
+                    ETHERNET_PACKET
+                );
+
+                if seen == 0 {
+                    kept.push(frame.keep());
+                }
+
+                seen += 1;
+            }
+
+            if seen == 2 {
+                break;
+            }
+        }
+
+        assert_eq!(seen, 2);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(recycler.len(), 1);
+
+        assert_eq!(xsk2.rx_q.counters().packets(), 2);
+
+        assert_eq!(unsafe { recycler.flush(&mut xsk2.fq) }, 1);
+        assert!(recycler.is_empty());
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn frames_can_be_kept_or_recycled_after_checking_headers() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+        let mut xsk2 = dev2.0;
+
+        unsafe {
+            assert_eq!(xsk2.fq.produce(&xsk2.descs[..2]), 2);
+
+            for (i, desc) in xsk1.descs[..2].iter_mut().enumerate() {
+                let mut pkt = ETHERNET_PACKET;
+                pkt[0] = i as u8;
+
+                xsk1.umem.data_mut(desc).cursor().write_all(&pkt).unwrap();
+            }
+
+            assert_eq!(xsk1.tx_q.produce(&xsk1.descs[..2]), 2);
+            xsk1.tx_q.commit_wakeup().unwrap();
+        }
+
+        let recycler = Recycler::new();
+        let mut kept = Vec::new();
+        let mut seen = 0;
+
+        for _ in 0..10 {
+            xsk2.rx_q.poll(100).unwrap();
+
+            let frames = unsafe { xsk2.rx_q.drain(&recycler, 2) }.prefetch(&xsk2.umem);
+
+            for frame in frames {
+                let header = unsafe { frame.header(&xsk2.umem, 6) };
+
+                assert_eq!(header[1..], ETHERNET_PACKET[1..6]);
+
+                if header[0] == 0 {
+                    kept.push(frame.keep());
+                }
+
+                seen += 1;
+            }
+
+            if seen == 2 {
+                break;
+            }
+        }
+
+        assert_eq!(seen, 2);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(recycler.len(), 1);
+
+        assert_eq!(
+            unsafe { xsk2.umem.data(&kept[0]) }.contents().len(),
+            ETHERNET_PACKET.len()
+        );
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn drain_for_consumes_batches_until_ring_is_empty() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+        let mut xsk2 = dev2.0;
+
+        unsafe {
+            assert_eq!(xsk2.fq.produce(&xsk2.descs[..4]), 4);
+
+            for desc in xsk1.descs[..3].iter_mut() {
+                xsk1.umem
+                    .data_mut(desc)
+                    .cursor()
+                    .write_all(&ETHERNET_PACKET[..])
+                    .unwrap();
+            }
+
+            assert_eq!(xsk1.tx_q.produce_and_wakeup(&xsk1.descs[..3]).unwrap(), 3);
+        }
+
+        let umem = &xsk2.umem;
+        let mut batch = xsk2.descs[4..6].to_vec();
+        let mut batch_lens = Vec::new();
+        let mut frames = 0;
+
+        for _ in 0..10 {
+            xsk2.rx_q.poll(100).unwrap();
+
+            let stats = unsafe {
+                xsk2.rx_q
+                    .drain_for(&mut batch, Duration::from_secs(1), |descs| {
+                        batch_lens.push(descs.len());
+
+                        for desc in descs.iter() {
+                            assert_eq!(umem.data(desc).contents(), ETHERNET_PACKET);
+                        }
+                    })
+            };
+
+            assert!(stats.emptied());
+            assert_eq!(stats.batches(), batch_lens.len());
+
+            frames += stats.frames();
+
+            if frames == 3 {
+                break;
+            }
+
+            batch_lens.clear();
+        }
+
+        assert_eq!(frames, 3);
+        assert!(batch_lens.iter().all(|&len| len <= 2));
+
+        // Nothing left, so the next call returns straight away.
+        let stats = unsafe { xsk2.rx_q.drain_for(&mut batch, Duration::ZERO, |_| {}) };
+
+        assert_eq!(stats.frames(), 0);
+        assert!(stats.emptied());
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn batching_helpers_are_clamped_to_max_burst() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+        let mut xsk2 = dev2.0;
+
+        let max_burst = NonZeroUsize::new(2);
+
+        xsk1.tx_q.set_max_burst(max_burst);
+        xsk2.rx_q.set_max_burst(max_burst);
+
+        assert_eq!(xsk1.tx_q.max_burst(), max_burst);
+        assert_eq!(xsk2.rx_q.max_burst(), max_burst);
+
+        unsafe {
+            assert_eq!(xsk2.fq.produce(&xsk2.descs[..4]), 4);
+
+            for desc in xsk1.descs[..3].iter_mut() {
+                xsk1.umem
+                    .data_mut(desc)
+                    .cursor()
+                    .write_all(&ETHERNET_PACKET[..])
+                    .unwrap();
+            }
+
+            // Only the first two are considered, the third is sent
+            // on the next call.
+            assert_eq!(xsk1.tx_q.produce_and_wakeup(&xsk1.descs[..3]).unwrap(), 2);
+            assert_eq!(xsk1.tx_q.produce_and_wakeup(&xsk1.descs[2..3]).unwrap(), 1);
+        }
+
+        let mut frames = 0;
+
+        for _ in 0..10 {
+            let cnt = unsafe {
+                xsk2.rx_q
+                    .poll_and_consume(&mut xsk2.descs[4..8], 100)
+                    .unwrap()
+            };
+
+            assert!(cnt <= 2);
+
+            frames += cnt;
+
+            if frames == 3 {
+                break;
+            }
+        }
+
+        assert_eq!(frames, 3);
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn fan_in_merges_frames_from_every_queue_tagged_by_source() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+        let mut xsk2 = dev2.0;
+
+        unsafe {
+            assert_eq!(xsk1.fq.produce(&xsk1.descs[..4]), 4);
+            assert_eq!(xsk2.fq.produce(&xsk2.descs[..4]), 4);
+
+            for xsk in [&mut xsk1, &mut xsk2] {
+                for desc in xsk.descs[4..6].iter_mut() {
+                    xsk.umem
+                        .data_mut(desc)
+                        .cursor()
+                        .write_all(&ETHERNET_PACKET[..])
+                        .unwrap();
+                }
+
+                assert_eq!(xsk.tx_q.produce_and_wakeup(&xsk.descs[4..6]).unwrap(), 2);
+            }
+        }
+
+        let mut fan_in = FanIn::new(NonZeroUsize::new(4).unwrap());
+
+        let src1 = fan_in.add(xsk1.rx_q, NonZeroUsize::new(1).unwrap());
+        let src2 = fan_in.add(xsk2.rx_q, NonZeroUsize::new(1).unwrap());
+
+        let umems = [&xsk1.umem, &xsk2.umem];
+        let mut received = [0; 2];
+
+        for _ in 0..10 {
+            let batch = unsafe { fan_in.poll_and_consume(100) }.unwrap();
+
+            assert!(batch.len() <= 4);
+
+            for frame in batch {
+                let data = unsafe { umems[frame.source()].data(frame.desc()) };
+
+                assert_eq!(data.contents(), &ETHERNET_PACKET[..]);
+
+                received[frame.source()] += 1;
+            }
+
+            if received == [2, 2] {
+                break;
+            }
+        }
+
+        assert_eq!(received, [2, 2]);
+        assert_eq!(fan_in.received(src1), 2);
+        assert_eq!(fan_in.received(src2), 2);
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn napi_id_is_unknown_until_traffic_arrives() {
+    fn test(_dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let xsk2 = dev2.0;
+
+        xsk2.rx_q
+            .fd()
+            .set_busy_poll(BusyPoll::new(20).budget(8))
+            .unwrap();
+
+        assert_eq!(xsk2.rx_q.fd().napi_id().unwrap(), None);
+
+        assert!(matches!(
+            xsk2.rx_q.fd().check_napi_id(NonZeroU32::new(1).unwrap()),
+            Err(NapiIdError::NoTraffic)
+        ));
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn busy_poll_sockets_send_and_receive() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+        let mut xsk2 = dev2.0;
+
+        unsafe {
+            assert_eq!(xsk2.fq.produce(&xsk2.descs[0..1]), 1);
+
+            xsk1.umem
+                .data_mut(&mut xsk1.descs[0])
+                .cursor()
+                .write_all(&ETHERNET_PACKET[..])
+                .unwrap();
+
+            assert_eq!(xsk1.tx_q.busy_poll_send(&xsk1.descs[..1]).unwrap(), 1);
+
+            let mut received = 0;
+
+            for _ in 0..100 {
+                received = xsk2.rx_q.busy_poll_recv(&mut xsk2.descs).unwrap();
+
+                if received > 0 {
+                    break;
+                }
+
+                std::thread::sleep(Duration::from_millis(1));
+            }
+
+            assert_eq!(received, 1);
+            assert_eq!(xsk2.umem.data(&xsk2.descs[0]).contents(), ETHERNET_PACKET);
+        }
+    }
+
+    let (umem_config, _) = build_configs();
+
+    let socket_config = SocketConfig::builder()
+        .tx_queue_size(QueueSize::new(TX_Q_SIZE).unwrap())
+        .rx_queue_size(QueueSize::new(RX_Q_SIZE).unwrap())
+        .busy_poll(Some(*BusyPoll::new(20).budget(8).prefer(true)))
+        .build();
+
+    setup::run_test(
+        XskConfig {
+            frame_count: FRAME_COUNT.try_into().unwrap(),
+            umem_config,
+            socket_config,
+        },
+        XskConfig {
+            frame_count: FRAME_COUNT.try_into().unwrap(),
+            umem_config,
+            socket_config,
+        },
+        test,
+    )
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn veth_sockets_report_a_copy_bind_mode() {
+    fn test(dev1: (Xsk, PacketGenerator), _dev2: (Xsk, PacketGenerator)) {
+        let xsk1 = dev1.0;
+
+        // veth has no zero-copy support, so binding without asking
+        // for a mode falls back to copying.
+        assert!(!xsk1.rx_q.fd().is_zero_copy().unwrap());
+
+        let mode = xsk1.rx_q.bind_mode().unwrap();
+        assert_ne!(mode, BindMode::ZeroCopy);
+        assert_eq!(xsk1.tx_q.bind_mode().unwrap(), mode);
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn fixed_size_queues_send_and_receive() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let Xsk {
+            umem: umem1,
+            descs: mut descs1,
+            tx_q,
+            cq,
+            ..
+        } = dev1.0;
+
+        let Xsk {
+            umem: umem2,
+            descs: mut descs2,
+            rx_q,
+            fq,
+            ..
+        } = dev2.0;
+
+        let err = FixedCompQueue::<8>::new(cq).unwrap_err();
+        assert_eq!((err.expected(), err.actual()), (8, CQ_SIZE as usize));
+        assert!(FixedCompQueue::<4>::new(err.into_queue()).is_ok());
+
+        let mut tx_q = FixedTxQueue::<4>::new(tx_q).unwrap();
+        let mut rx_q = FixedRxQueue::<4>::new(rx_q).unwrap();
+        let mut fq = FixedFillQueue::<4>::new(fq).unwrap();
+
+        unsafe {
+            assert_eq!(fq.produce(&descs2[..FQ_SIZE as usize]), FQ_SIZE as usize);
+
+            umem1
+                .data_mut(&mut descs1[0])
+                .cursor()
+                .write_all(&ETHERNET_PACKET[..])
+                .unwrap();
+
+            assert_eq!(tx_q.produce(&descs1[..1]), 1);
+            tx_q.get_mut().wakeup().unwrap();
+
+            assert!(rx_q.get_mut().poll(100).unwrap());
+            assert_eq!(rx_q.consume(&mut descs2), 1);
+            assert_eq!(umem2.data(&descs2[0]).contents(), ETHERNET_PACKET);
+
+            assert_eq!(fq.recycle_from_rx(&mut descs2[..1]), 1);
+            assert_eq!(descs2[0].lengths().data(), 0);
+        }
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn poll_set_reports_which_sockets_are_ready() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+        let mut xsk2 = dev2.0;
+
+        let mut set = PollSet::new().unwrap();
+
+        let rx1 = set.add(xsk1.rx_q.fd(), Interest::Readable).unwrap();
+        let rx2 = set.add(xsk2.rx_q.fd(), Interest::Readable).unwrap();
+
+        assert_eq!(set.poll_all(10).unwrap().count(), 0);
+
+        unsafe {
+            assert_eq!(xsk2.fq.produce(&xsk2.descs[..1]), 1);
+
+            xsk1.umem
+                .data_mut(&mut xsk1.descs[0])
+                .cursor()
+                .write_all(&ETHERNET_PACKET[..])
+                .unwrap();
+
+            assert_eq!(xsk1.tx_q.produce_and_wakeup(&xsk1.descs[..1]).unwrap(), 1);
+        }
+
+        let ready: Vec<_> = set.poll_all(100).unwrap().collect();
+
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].0, rx2);
+        assert_ne!(ready[0].0, rx1);
+        assert!(ready[0].1.is_readable());
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn steering_check_counts_frames_not_matching_expected_traffic() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+        let mut xsk2 = dev2.0;
+
+        let udp = dev1.1.generate_packet(1234, 4789, 32).unwrap();
+        let (dst, _) = addr::eth_addrs(&udp).unwrap();
+
+        unsafe {
+            assert_eq!(xsk2.fq.produce(&xsk2.descs[..2]), 2);
+
+            for (desc, pkt) in xsk1.descs[..2]
+                .iter_mut()
+                .zip([&udp[..], &ETHERNET_PACKET[..]])
+            {
+                xsk1.umem.data_mut(desc).cursor().write_all(pkt).unwrap();
+            }
+
+            assert_eq!(xsk1.tx_q.produce_and_wakeup(&xsk1.descs[..2]).unwrap(), 2);
+        }
+
+        let mut received = Vec::new();
+
+        for _ in 0..10 {
+            xsk2.rx_q.poll(100).unwrap();
+
+            let mut descs = xsk2.descs.clone();
+            let cnt = unsafe { xsk2.rx_q.consume(&mut descs) };
+            received.extend_from_slice(&descs[..cnt]);
+
+            if received.len() == 2 {
+                break;
+            }
+        }
+
+        assert_eq!(received.len(), 2);
+
+        let mut expected = ExpectedTraffic::new();
+        expected.dst_mac(dst).dst_port(4789);
+
+        let mut check = SteeringCheck::new(expected, NonZeroU32::new(1).unwrap());
+        unsafe { check.observe_batch(&xsk2.umem, &received) };
+
+        let report = check.report();
+
+        // The ARP request is broadcast.
+        assert_eq!(report.sampled(), 2);
+        assert_eq!(report.unexpected(), 1);
+        assert_eq!(report.mismatches(Mismatch::DstMac), 1);
+    }
+
+    build_configs_and_run_test(test).await
+}
+
 async fn build_configs_and_run_test<F>(test: F)
 where
     F: Fn((Xsk, PacketGenerator), (Xsk, PacketGenerator)) + Send + 'static,
@@ -440,3 +1104,52 @@ where
     )
     .await;
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn frames_are_received_after_kicking_the_rx_queue_separately() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+        let mut xsk2 = dev2.0;
+
+        unsafe {
+            assert_eq!(xsk2.fq.produce(&xsk2.descs[0..1]), 1);
+
+            if xsk2.fq.needs_wakeup() {
+                xsk2.rx_q.wakeup().unwrap();
+            }
+
+            xsk1.umem
+                .data_mut(&mut xsk1.descs[0])
+                .cursor()
+                .write_all(&ETHERNET_PACKET[..])
+                .unwrap();
+
+            assert_eq!(xsk1.tx_q.produce(&xsk1.descs[..1]), 1);
+
+            if xsk1.tx_q.needs_wakeup() {
+                xsk1.tx_q.wakeup().unwrap();
+            }
+
+            assert_eq!(xsk2.rx_q.poll_and_consume(&mut xsk2.descs, 100).unwrap(), 1);
+            assert_eq!(xsk2.umem.data(&xsk2.descs[0]).contents(), ETHERNET_PACKET);
+        }
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn sockets_can_be_inserted_into_a_map_managed_elsewhere() {
+    fn test(dev1: (Xsk, PacketGenerator), _dev2: (Xsk, PacketGenerator)) {
+        let xsk1 = dev1.0;
+
+        let map = XskMap::new(4).unwrap();
+        xsk1.rx_q.insert_into_map(&map).unwrap();
+
+        assert!(XskMap::from_pinned("/sys/fs/bpf/no-such-map").is_err());
+    }
+
+    build_configs_and_run_test(test).await
+}
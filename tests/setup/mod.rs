@@ -5,11 +5,9 @@ pub mod veth_setup;
 pub use veth_setup::{LinkIpAddr, VethDevConfig};
 
 use std::{net::Ipv4Addr, num::NonZeroU32};
-use xsk_rs::{
-    config::{Interface, SocketConfig, UmemConfig},
-    socket::{RxQueue, Socket, TxQueue},
-    umem::{frame::FrameDesc, CompQueue, FillQueue, Umem},
-};
+use xsk_rs::config::{Interface, SocketConfig, UmemConfig};
+
+pub use xsk_rs::Xsk;
 
 pub const ETHERNET_PACKET: [u8; 42] = [
     0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xf6, 0xe0, 0xf6, 0xc9, 0x60, 0x0a, 0x08, 0x06, 0x00, 0x01,
@@ -17,15 +15,6 @@ pub const ETHERNET_PACKET: [u8; 42] = [
     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, 0xa8, 0x45, 0xfe,
 ];
 
-pub struct Xsk {
-    pub umem: Umem,
-    pub fq: FillQueue,
-    pub cq: CompQueue,
-    pub tx_q: TxQueue,
-    pub rx_q: RxQueue,
-    pub descs: Vec<FrameDesc>,
-}
-
 #[derive(Debug, Clone)]
 pub struct XskConfig {
     pub frame_count: NonZeroU32,
@@ -56,25 +45,8 @@ pub fn build_socket_and_umem(
     if_name: &Interface,
     queue_id: u32,
 ) -> Xsk {
-    let (umem, descs) = Umem::new(umem_config, frame_count, false).expect("failed to build umem");
-
-    let (tx_q, rx_q, fq_and_cq) = unsafe {
-        Socket::new(socket_config, &umem, if_name, queue_id).expect("failed to build socket")
-    };
-
-    let (fq, cq) = fq_and_cq.expect(&format!(
-        "missing fill and comp queue - interface {:?} may already be bound to",
-        if_name
-    ));
-
-    Xsk {
-        umem,
-        fq,
-        cq,
-        tx_q,
-        rx_q,
-        descs,
-    }
+    Xsk::build(if_name, queue_id, umem_config, socket_config, frame_count)
+        .expect("failed to build socket and umem")
 }
 
 pub async fn run_test<F>(xsk1_config: XskConfig, xsk2_config: XskConfig, test: F)
@@ -0,0 +1,186 @@
+#[allow(dead_code)]
+mod setup;
+use std::{
+    convert::TryInto,
+    env,
+    io::Write,
+    time::{Duration, Instant},
+};
+
+use serial_test::serial;
+use xsk_rs::{
+    config::{SocketConfig, UmemConfig},
+    testutil::{FrameLedger, FrameOwner, SequenceStamper, SequenceVerifier, Soak, SoakConfig},
+    FrameDesc,
+};
+
+use crate::setup::{PacketGenerator, Xsk, XskConfig};
+
+const PAYLOAD_LEN: usize = 64;
+
+/// Five seconds unless `XSK_SOAK_SECS` says otherwise, replaying
+/// `XSK_SOAK_SEED` if set.
+fn soak_config() -> SoakConfig {
+    let secs = env::var("XSK_SOAK_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(5);
+
+    let mut config = SoakConfig::new(Duration::from_secs(secs));
+
+    if let Some(seed) = env::var("XSK_SOAK_SEED").ok().and_then(|s| s.parse().ok()) {
+        config.seed(seed);
+    }
+
+    config
+}
+
+fn xsk_config(soak: &mut Soak) -> XskConfig {
+    // One frame per fill ring slot, so returning every received frame
+    // to the fill queue always fits.
+    let fill_q_size = soak.ring_size();
+
+    let umem_config = UmemConfig::builder()
+        .fill_queue_size(fill_q_size)
+        .comp_queue_size(soak.ring_size())
+        .build()
+        .unwrap();
+
+    let socket_config = SocketConfig::builder()
+        .rx_queue_size(soak.ring_size())
+        .tx_queue_size(soak.ring_size())
+        .build();
+
+    XskConfig {
+        frame_count: fill_q_size.get().try_into().unwrap(),
+        umem_config,
+        socket_config,
+    }
+}
+
+fn forward(mut soak: Soak, dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+    let seed = soak.seed();
+
+    let (mut tx, pkt_gen) = dev1;
+    let mut rx = dev2.0;
+
+    let pkt = pkt_gen.generate_packet(1234, 1234, PAYLOAD_LEN).unwrap();
+    let payload_offset = pkt.len() - PAYLOAD_LEN;
+
+    let mut tx_ledger = FrameLedger::new(&tx.umem);
+    let mut rx_ledger = FrameLedger::new(&rx.umem);
+
+    let mut stamper = SequenceStamper::new();
+    let mut verifier = SequenceVerifier::new();
+
+    rx_ledger.to_fill(&rx.descs).unwrap();
+    assert_eq!(unsafe { rx.fq.produce(&rx.descs) }, rx.descs.len());
+
+    let mut free: Vec<FrameDesc> = tx.descs.clone();
+    let mut completed = tx.descs.clone();
+    let mut received = rx.descs.clone();
+
+    let mut reap = |tx: &mut Xsk, ledger: &mut FrameLedger, free: &mut Vec<FrameDesc>| {
+        let cnt = unsafe { tx.cq.consume(&mut completed) };
+
+        ledger
+            .from_comp(&completed[..cnt])
+            .unwrap_or_else(|e| panic!("{} (seed {})", e, seed));
+
+        free.extend_from_slice(&completed[..cnt]);
+    };
+
+    while soak.next_round() {
+        reap(&mut tx, &mut tx_ledger, &mut free);
+
+        let batch = soak.batch_size().min(free.len());
+        let start = free.len() - batch;
+
+        for desc in free[start..].iter_mut() {
+            let mut data = unsafe { tx.umem.data_mut(desc) };
+
+            let mut cursor = data.cursor();
+            cursor.set_pos(0);
+            cursor.write_all(&pkt).unwrap();
+
+            stamper.stamp(&mut data.contents_mut()[payload_offset..]);
+        }
+
+        let sent = unsafe { tx.tx_q.extend(&free[start..]) };
+
+        tx_ledger
+            .to_tx(&free[start..start + sent])
+            .unwrap_or_else(|e| panic!("{} (seed {})", e, seed));
+
+        free.drain(start..start + sent);
+
+        tx.tx_q.commit_wakeup().unwrap();
+
+        let batch = soak.batch_size().min(received.len());
+        let cnt = unsafe { rx.rx_q.consume(&mut received[..batch]) };
+
+        rx_ledger
+            .from_rx(&received[..cnt])
+            .unwrap_or_else(|e| panic!("{} (seed {})", e, seed));
+
+        for desc in &received[..cnt] {
+            let data = unsafe { rx.umem.data(desc) };
+
+            if let Some(payload) = data.contents().get(payload_offset..) {
+                verifier.check(payload);
+            }
+        }
+
+        rx_ledger.to_fill(&received[..cnt]).unwrap();
+        assert_eq!(unsafe { rx.fq.produce(&received[..cnt]) }, cnt);
+
+        if rx.fq.needs_wakeup() {
+            rx.fq.wakeup(rx.rx_q.fd_mut(), 0).unwrap();
+        }
+
+        soak.pause();
+    }
+
+    // Give anything still in flight a chance to complete.
+    let deadline = Instant::now() + Duration::from_secs(1);
+
+    while tx_ledger.owned_by(FrameOwner::Tx) > 0 && Instant::now() < deadline {
+        tx.tx_q.wakeup().unwrap();
+        reap(&mut tx, &mut tx_ledger, &mut free);
+    }
+
+    assert_eq!(
+        tx_ledger.owned_by(FrameOwner::Tx),
+        0,
+        "tx frames never completed after {} rounds (seed {})",
+        soak.rounds(),
+        seed
+    );
+    assert_eq!(free.len(), tx.descs.len());
+
+    let report = verifier.report();
+
+    assert!(report.received() > 0, "nothing received (seed {})", seed);
+    assert_eq!(report.duplicated(), 0, "seed {}", seed);
+    assert_eq!(report.corrupted(), 0, "seed {}", seed);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+#[ignore = "long running, run with `--features testutil -- --ignored`"]
+async fn forwarding_with_random_batches_and_ring_sizes_accounts_for_every_frame() {
+    let mut config = soak_config();
+
+    let mut soak = Soak::new(&config);
+    println!("soak seed: {}", soak.seed());
+
+    let xsk1_config = xsk_config(&mut soak);
+    let xsk2_config = xsk_config(&mut soak);
+
+    config.seed(soak.seed());
+
+    setup::run_test(xsk1_config, xsk2_config, move |dev1, dev2| {
+        forward(Soak::new(&config), dev1, dev2)
+    })
+    .await
+}
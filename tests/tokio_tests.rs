@@ -0,0 +1,91 @@
+#![cfg(feature = "tokio")]
+
+#[allow(dead_code)]
+mod setup;
+use setup::{PacketGenerator, Xsk, XskConfig, ETHERNET_PACKET};
+
+use serial_test::serial;
+use std::{convert::TryInto, io::Write, time::Duration};
+use xsk_rs::{
+    async_io::{TokioRxQueue, TokioTxQueue},
+    config::{BindFlags, QueueSize, SocketConfig, UmemConfig},
+};
+
+const Q_SIZE: u32 = 16;
+const FRAME_COUNT: u32 = 32;
+
+fn xsk_config(bind_flags: BindFlags) -> XskConfig {
+    let umem_config = UmemConfig::builder()
+        .comp_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .fill_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .build()
+        .unwrap();
+
+    let socket_config = SocketConfig::builder()
+        .tx_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .rx_queue_size(QueueSize::new(Q_SIZE).unwrap())
+        .bind_flags(bind_flags)
+        .build();
+
+    XskConfig {
+        frame_count: FRAME_COUNT.try_into().unwrap(),
+        umem_config,
+        socket_config,
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn recv_wakes_an_empty_fill_ring_after_refilling() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let Xsk {
+            umem: umem1,
+            tx_q,
+            descs: mut descs1,
+            ..
+        } = dev1.0;
+
+        let Xsk {
+            umem: umem2,
+            rx_q,
+            mut fq,
+            descs: mut descs2,
+            ..
+        } = dev2.0;
+
+        tokio::runtime::Handle::current().block_on(async {
+            let mut tx_q = TokioTxQueue::new(tx_q).unwrap();
+            let mut rx_q = TokioRxQueue::new(rx_q).unwrap();
+
+            unsafe {
+                // Only fill after registering, so the kernel has had
+                // an empty fill ring and needs waking.
+                assert_eq!(fq.produce(&descs2[..1]), 1);
+
+                umem1
+                    .data_mut(&mut descs1[0])
+                    .cursor()
+                    .write_all(&ETHERNET_PACKET[..])
+                    .unwrap();
+
+                assert_eq!(tx_q.produce(&descs1[..1]).await.unwrap(), 1);
+
+                let cnt =
+                    tokio::time::timeout(Duration::from_secs(1), rx_q.recv(&mut fq, &mut descs2))
+                        .await
+                        .expect("frame never arrived")
+                        .unwrap();
+
+                assert_eq!(cnt, 1);
+                assert_eq!(umem2.data(&descs2[0]).contents(), ETHERNET_PACKET);
+            }
+        });
+    }
+
+    setup::run_test(
+        xsk_config(BindFlags::XDP_USE_NEED_WAKEUP),
+        xsk_config(BindFlags::XDP_USE_NEED_WAKEUP),
+        test,
+    )
+    .await
+}
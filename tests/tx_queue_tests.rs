@@ -1,11 +1,15 @@
 #[allow(dead_code)]
 mod setup;
-use std::convert::TryInto;
+use std::{convert::TryInto, io::Write, num::NonZeroUsize, thread};
 
-use setup::Xsk;
+use setup::{Xsk, ETHERNET_PACKET};
 
 use serial_test::serial;
-use xsk_rs::config::{QueueSize, SocketConfig, UmemConfig};
+use xsk_rs::{
+    config::{QueueSize, SocketConfig, UmemConfig},
+    socket::SharedTxQueue,
+    umem::{frame::FrameDesc, FramePool},
+};
 
 use crate::setup::{PacketGenerator, XskConfig};
 
@@ -63,6 +67,26 @@ async fn produce_frames_until_full() {
     build_configs_and_run_test(test).await
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn extend_submits_what_fits_and_leaves_the_rest() {
+    fn test(dev1: (Xsk, PacketGenerator), _dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+
+        let mut descs = xsk1.descs.iter().copied();
+
+        unsafe {
+            assert_eq!(xsk1.tx_q.extend(descs.by_ref().take(1)), 1);
+            assert_eq!(xsk1.tx_q.extend(descs.by_ref()), TX_Q_SIZE as usize - 1);
+        }
+
+        assert_eq!(descs.len(), FRAME_COUNT as usize - TX_Q_SIZE as usize);
+        assert_eq!(xsk1.tx_q.uncommitted(), TX_Q_SIZE as usize);
+    }
+
+    build_configs_and_run_test(test).await
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 #[serial]
 async fn produce_one_is_ok() {
@@ -75,6 +99,113 @@ async fn produce_one_is_ok() {
     build_configs_and_run_test(test).await
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn commit_wakeup_covers_all_frames_produced_since_last_commit() {
+    fn test(dev1: (Xsk, PacketGenerator), _dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+
+        unsafe {
+            assert_eq!(xsk1.tx_q.produce(&xsk1.descs[..2]), 2);
+            assert_eq!(xsk1.tx_q.produce_one(&xsk1.descs[2]), 1);
+        }
+
+        assert_eq!(xsk1.tx_q.uncommitted(), 3);
+        assert_eq!(xsk1.tx_q.commit_wakeup().unwrap(), 3);
+        assert_eq!(xsk1.tx_q.uncommitted(), 0);
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn shared_tx_queue_holds_back_frames_that_do_not_fit() {
+    fn test(dev1: (Xsk, PacketGenerator), _dev2: (Xsk, PacketGenerator)) {
+        let Xsk { tx_q, descs, .. } = dev1.0;
+
+        let (mut committer, handle1) =
+            SharedTxQueue::new(tx_q, NonZeroUsize::new(2).unwrap(), FRAME_COUNT as usize);
+
+        let handle2 = handle1.clone();
+        assert_ne!(handle1.shard(), handle2.shard());
+
+        let (first, second) = descs.split_at(3);
+        let second = second[..3].to_vec();
+
+        let t = thread::spawn(move || unsafe { handle2.produce(&second) });
+
+        assert_eq!(unsafe { handle1.produce(first) }, 3);
+        assert_eq!(t.join().unwrap(), 3);
+
+        assert_eq!(committer.flush().unwrap(), TX_Q_SIZE as usize);
+        assert_eq!(committer.backlog(), 6 - TX_Q_SIZE as usize);
+    }
+
+    build_configs_and_run_test(test).await
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn aliased_frame_is_sent_twice_and_recycled_once() {
+    fn test(dev1: (Xsk, PacketGenerator), dev2: (Xsk, PacketGenerator)) {
+        let mut xsk1 = dev1.0;
+        let mut xsk2 = dev2.0;
+
+        let pool = FramePool::new(&xsk1.descs);
+        let mut desc = pool.try_alloc().unwrap();
+
+        unsafe {
+            xsk1.umem
+                .data_mut(&mut desc)
+                .cursor()
+                .write_all(&ETHERNET_PACKET[..])
+                .unwrap();
+        }
+
+        let alias = pool.alias(&desc, 0, ETHERNET_PACKET.len()).unwrap();
+
+        unsafe {
+            assert_eq!(xsk2.fq.produce(&xsk2.descs[..2]), 2);
+
+            // Separate batches, since they share an address.
+            assert_eq!(pool.send(&mut xsk1.tx_q, &[desc]), 1);
+            assert_eq!(pool.send(&mut xsk1.tx_q, &[alias]), 1);
+            xsk1.tx_q.wakeup().unwrap();
+        }
+
+        let mut received = Vec::new();
+        let mut scratch = vec![FrameDesc::default(); 2];
+        let mut reaped = 0;
+
+        for _ in 0..10 {
+            xsk2.rx_q.poll(100).unwrap();
+
+            let mut descs = xsk2.descs.clone();
+            let cnt = unsafe { xsk2.rx_q.consume(&mut descs) };
+            received.extend_from_slice(&descs[..cnt]);
+
+            reaped += unsafe { pool.reap(&mut xsk1.cq, &mut scratch) };
+
+            if received.len() == 2 && reaped == 2 {
+                break;
+            }
+        }
+
+        assert_eq!(received.len(), 2);
+        assert_eq!(reaped, 2);
+
+        for desc in &received {
+            assert_eq!(unsafe { xsk2.umem.data(desc) }.contents(), ETHERNET_PACKET);
+        }
+
+        assert_eq!(pool.available(), FRAME_COUNT as usize);
+        assert_eq!(pool.stats().alias_allocs(), 1);
+    }
+
+    build_configs_and_run_test(test).await
+}
+
 async fn build_configs_and_run_test<F>(test: F)
 where
     F: Fn((Xsk, PacketGenerator), (Xsk, PacketGenerator)) + Send + 'static,
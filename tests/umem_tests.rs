@@ -3,9 +3,15 @@ mod setup;
 use setup::{veth_setup, VethDevConfig, Xsk, ETHERNET_PACKET};
 
 use serial_test::serial;
-use std::{convert::TryInto, io::Write};
+use std::{
+    convert::TryInto,
+    error::Error,
+    io::{self, Write},
+    time::Duration,
+};
 use xsk_rs::{
     config::{LibxdpFlags, SocketConfig, UmemConfig},
+    umem::{FramePool, SplitFramesError, UmemDrainError, UmemMismatchError},
     Socket, Umem,
 };
 
@@ -120,6 +126,294 @@ async fn shared_umem_does_not_return_new_fq_and_cq_when_sockets_are_bound_to_sam
         .unwrap();
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn shared_sockets_refuse_an_already_bound_queue() {
+    let inner = move |dev1_config: VethDevConfig, dev2_config: VethDevConfig| {
+        let (umem, _frames) =
+            Umem::new(UmemConfig::default(), 64.try_into().unwrap(), false).unwrap();
+
+        let dev1 = dev1_config.if_name().parse().unwrap();
+
+        let first = Socket::new_shared(SocketConfig::default(), &umem, &dev1, 0).unwrap();
+
+        let err = Socket::new_shared(SocketConfig::default(), &umem, &dev1, 0).unwrap_err();
+        let err = err.source().unwrap().downcast_ref::<io::Error>().unwrap();
+        assert_eq!(err.raw_os_error(), Some(libc::EBUSY));
+
+        assert!(Socket::new_shared(
+            SocketConfig::default(),
+            &umem,
+            &dev2_config.if_name().parse().unwrap(),
+            0
+        )
+        .is_ok());
+
+        // Once all its sockets are gone the queue is free again.
+        drop(first);
+        assert!(Socket::new_shared(SocketConfig::default(), &umem, &dev1, 0).is_ok());
+    };
+
+    let (dev1_config, dev2_config) = setup::default_veth_dev_configs();
+
+    veth_setup::run_with_veth_pair(inner, dev1_config, dev2_config)
+        .await
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn completed_frames_can_be_recycled_to_another_sockets_fill_queue() {
+    let inner = move |dev1_config: VethDevConfig, dev2_config: VethDevConfig| {
+        let frame_count = 64;
+
+        let (umem, descs) = Umem::new(
+            UmemConfig::default(),
+            frame_count.try_into().unwrap(),
+            false,
+        )
+        .unwrap();
+
+        let mut sender_descs = descs;
+        let receiver_descs = sender_descs.drain((frame_count / 2) as usize..).collect();
+
+        let (sender_tx_q, sender_rx_q, sender_fq_and_cq) = unsafe {
+            Socket::new(
+                SocketConfig::default(),
+                &umem,
+                &dev1_config.if_name().parse().unwrap(),
+                0,
+            )
+        }
+        .unwrap();
+
+        let (sender_fq, sender_cq) = sender_fq_and_cq.unwrap();
+
+        let (receiver_tx_q, receiver_rx_q, receiver_fq_and_cq) = unsafe {
+            Socket::new(
+                SocketConfig::default(),
+                &umem,
+                &dev2_config.if_name().parse().unwrap(),
+                0,
+            )
+        }
+        .unwrap();
+
+        let (receiver_fq, receiver_cq) = receiver_fq_and_cq.unwrap();
+
+        assert!(sender_cq.umem().is_shared_with(receiver_fq.umem()));
+
+        let mut sender = Xsk {
+            umem: umem.clone(),
+            fq: sender_fq,
+            cq: sender_cq,
+            tx_q: sender_tx_q,
+            rx_q: sender_rx_q,
+            descs: sender_descs,
+        };
+
+        let mut receiver = Xsk {
+            umem,
+            fq: receiver_fq,
+            cq: receiver_cq,
+            tx_q: receiver_tx_q,
+            rx_q: receiver_rx_q,
+            descs: receiver_descs,
+        };
+
+        let sent_addr = sender.descs[0].addr();
+
+        unsafe {
+            sender
+                .umem
+                .data_mut(&mut sender.descs[0])
+                .cursor()
+                .write_all(&ETHERNET_PACKET[..])
+                .unwrap();
+
+            while sender.tx_q.produce_and_wakeup(&sender.descs[..1]).unwrap() != 1 {}
+        }
+
+        // The sent frame completes on the sender's comp queue and is
+        // moved from there to the receiver's fill queue, where it's
+        // used to receive the packet.
+        let mut scratch = [sender.descs[1]; 4];
+
+        loop {
+            match receiver.fq.recycle_from(&mut sender.cq, &mut scratch) {
+                Ok(0) => continue,
+                Ok(n) => {
+                    assert_eq!(n, 1);
+                    break;
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+
+        assert_eq!(scratch[0].addr(), sent_addr);
+
+        let mut received = [receiver.descs[0]];
+
+        // Send again, this time from a fresh frame, for the receiver
+        // to pick up with the recycled one.
+        unsafe {
+            sender
+                .umem
+                .data_mut(&mut sender.descs[2])
+                .cursor()
+                .write_all(&ETHERNET_PACKET[..])
+                .unwrap();
+
+            while sender.tx_q.produce_and_wakeup(&sender.descs[2..3]).unwrap() != 1 {}
+
+            while receiver.rx_q.poll_and_consume(&mut received, 100).unwrap() != 1 {}
+
+            assert_eq!(received[0].addr(), sent_addr);
+            assert_eq!(
+                receiver.umem.data(&received[0]).contents(),
+                &ETHERNET_PACKET[..]
+            );
+        }
+    };
+
+    let (dev1_config, dev2_config) = setup::default_veth_dev_configs();
+
+    veth_setup::run_with_veth_pair(inner, dev1_config, dev2_config)
+        .await
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn recycling_between_different_umems_is_rejected() {
+    let inner = move |dev1_config: VethDevConfig, dev2_config: VethDevConfig| {
+        let mut xsk1 = setup::build_socket_and_umem(
+            UmemConfig::default(),
+            SocketConfig::default(),
+            64.try_into().unwrap(),
+            &dev1_config.if_name().parse().unwrap(),
+            0,
+        );
+
+        let mut xsk2 = setup::build_socket_and_umem(
+            UmemConfig::default(),
+            SocketConfig::default(),
+            64.try_into().unwrap(),
+            &dev2_config.if_name().parse().unwrap(),
+            0,
+        );
+
+        assert!(!xsk1.umem.is_shared_with(&xsk2.umem));
+
+        assert_eq!(
+            xsk1.fq.recycle_from(&mut xsk2.cq, &mut xsk1.descs[..4]),
+            Err(UmemMismatchError)
+        );
+
+        assert_eq!(
+            xsk1.fq.recycle_from(&mut xsk1.cq, &mut xsk1.descs[..4]),
+            Ok(0)
+        );
+    };
+
+    let (dev1_config, dev2_config) = setup::default_veth_dev_configs();
+
+    veth_setup::run_with_veth_pair(inner, dev1_config, dev2_config)
+        .await
+        .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn owned_frames_round_trip_without_unsafe_access() {
+    let inner = move |dev1_config: VethDevConfig, dev2_config: VethDevConfig| {
+        let mut sender = setup::build_socket_and_umem(
+            UmemConfig::default(),
+            SocketConfig::default(),
+            64.try_into().unwrap(),
+            &dev1_config.if_name().parse().unwrap(),
+            0,
+        );
+
+        let mut receiver = setup::build_socket_and_umem(
+            UmemConfig::default(),
+            SocketConfig::default(),
+            64.try_into().unwrap(),
+            &dev2_config.if_name().parse().unwrap(),
+            0,
+        );
+
+        let mut tx_frames = unsafe { sender.umem.own(sender.descs.drain(..4)) };
+        let mut rx_frames = unsafe { receiver.umem.own(receiver.descs.drain(..4)) };
+
+        // Frames can't be handed to a queue of another UMEM.
+        assert_eq!(
+            receiver.fq.produce_owned(&mut tx_frames),
+            Err(UmemMismatchError)
+        );
+        assert_eq!(tx_frames.len(), 4);
+
+        assert_eq!(receiver.fq.produce_owned(&mut rx_frames), Ok(4));
+        assert!(rx_frames.is_empty());
+
+        for frame in tx_frames.iter_mut() {
+            frame
+                .data_mut()
+                .cursor()
+                .write_all(&ETHERNET_PACKET)
+                .unwrap();
+        }
+
+        assert_eq!(sender.tx_q.produce_owned(&mut tx_frames), Ok(4));
+        sender.tx_q.commit_wakeup().unwrap();
+
+        for _ in 0..10 {
+            receiver.rx_q.poll(100).unwrap();
+
+            receiver
+                .rx_q
+                .consume_owned(&receiver.umem, 4, &mut rx_frames)
+                .unwrap();
+
+            if rx_frames.len() == 4 {
+                break;
+            }
+        }
+
+        assert_eq!(rx_frames.len(), 4);
+
+        for frame in rx_frames.iter() {
+            assert_eq!(frame.data().contents(), &ETHERNET_PACKET[..]);
+        }
+
+        assert_eq!(
+            sender.cq.consume_owned(&receiver.umem, 4, &mut tx_frames),
+            Err(UmemMismatchError)
+        );
+
+        for _ in 0..10 {
+            sender.tx_q.wakeup().unwrap();
+
+            sender
+                .cq
+                .consume_owned(&sender.umem, 4, &mut tx_frames)
+                .unwrap();
+
+            if tx_frames.len() == 4 {
+                break;
+            }
+        }
+
+        assert_eq!(tx_frames.len(), 4);
+    };
+
+    let (dev1_config, dev2_config) = setup::default_veth_dev_configs();
+
+    veth_setup::run_with_veth_pair(inner, dev1_config, dev2_config)
+        .await
+        .unwrap();
+}
+
 #[tokio::test]
 #[serial]
 async fn writing_to_frame_and_reading_works_as_expected() {
@@ -144,6 +438,245 @@ async fn writing_to_frame_and_reading_works_as_expected() {
     }
 }
 
+#[tokio::test]
+#[serial]
+async fn split_frame_slots_are_written_independently() {
+    let (umem, descs) = Umem::new(
+        UmemConfig::builder()
+            .unaligned_chunks(true)
+            .build()
+            .unwrap(),
+        64.try_into().unwrap(),
+        false,
+    )
+    .unwrap();
+
+    assert!(umem.unaligned_chunks());
+
+    let mut pool = FramePool::new(&descs);
+    pool.split_frames(&umem, 128).unwrap();
+
+    let slot_size = pool.slot_size().unwrap();
+
+    let mut a = pool.try_alloc_slot().unwrap();
+    let mut b = pool.try_alloc_slot().unwrap();
+
+    assert_eq!(b.addr() - a.addr(), 128);
+
+    unsafe {
+        umem.slot_data_mut(&mut a, slot_size)
+            .cursor()
+            .write_all(&[1; 128])
+            .unwrap();
+
+        assert!(umem
+            .slot_data_mut(&mut b, slot_size)
+            .cursor()
+            .write_all(&[2; 129])
+            .is_err());
+
+        assert_eq!(umem.data(&a).contents(), &[1; 128][..]);
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn frames_of_aligned_umems_cannot_be_split() {
+    let (umem, descs) = Umem::new(UmemConfig::default(), 64.try_into().unwrap(), false).unwrap();
+
+    let mut pool = FramePool::new(&descs);
+
+    assert!(matches!(
+        pool.split_frames(&umem, 128),
+        Err(SplitFramesError::AlignedChunks)
+    ));
+}
+
+#[tokio::test]
+#[serial]
+async fn meta_is_read_from_just_before_the_packet_data() {
+    let config = UmemConfig::builder().frame_headroom(32).build().unwrap();
+
+    let (umem, mut descs) = Umem::new(config, 16.try_into().unwrap(), false).unwrap();
+    let desc = &mut descs[0];
+
+    let hint: Vec<u8> = (0..32).collect();
+
+    unsafe { umem.headroom_mut(desc) }
+        .cursor()
+        .write_all(&hint)
+        .unwrap();
+
+    assert!(unsafe { umem.meta(desc) }.is_empty());
+
+    desc.set_meta_len(8);
+    assert_eq!(unsafe { umem.meta(desc) }.contents(), &hint[24..]);
+
+    desc.set_meta_len(usize::MAX);
+    assert_eq!(
+        unsafe { umem.meta(desc) }.len(),
+        desc.addr() % config.frame_size().get() as usize
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn frame_indices_and_addresses_match_the_initial_descs() {
+    let (umem, descs) = Umem::new(UmemConfig::default(), 16.try_into().unwrap(), false).unwrap();
+
+    for (i, desc) in descs.iter().enumerate() {
+        assert_eq!(umem.frame_addr(i), Some(desc.addr()));
+        assert_eq!(umem.frame_index(desc.addr()), Some(i));
+        assert_eq!(umem.frame_index(desc.addr() + 100), Some(i));
+    }
+
+    assert_eq!(umem.frame_addr(16), None);
+    assert_eq!(umem.frame_index(descs[15].addr() + 4096), None);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+#[serial]
+async fn tx_only_socket_sends_to_rx_only_socket() {
+    let inner = move |dev1_config: VethDevConfig, dev2_config: VethDevConfig| {
+        let (sender_umem, mut sender_descs) =
+            Umem::new(UmemConfig::default(), 16.try_into().unwrap(), false).unwrap();
+
+        let (mut tx_q, sender_fq_and_cq) = unsafe {
+            Socket::new_tx_only(
+                SocketConfig::default(),
+                &sender_umem,
+                &dev1_config.if_name().parse().unwrap(),
+                0,
+            )
+        }
+        .unwrap();
+
+        let (_, mut cq) = sender_fq_and_cq.unwrap();
+
+        let (receiver_umem, mut receiver_descs) =
+            Umem::new(UmemConfig::default(), 16.try_into().unwrap(), false).unwrap();
+
+        let (mut rx_q, receiver_fq_and_cq) = unsafe {
+            Socket::new_rx_only(
+                SocketConfig::default(),
+                &receiver_umem,
+                &dev2_config.if_name().parse().unwrap(),
+                0,
+            )
+        }
+        .unwrap();
+
+        let (mut fq, _) = receiver_fq_and_cq.unwrap();
+
+        unsafe {
+            assert_eq!(
+                fq.produce_and_wakeup(&receiver_descs[0..1], rx_q.fd_mut(), 100)
+                    .unwrap(),
+                1
+            );
+
+            sender_umem
+                .data_mut(&mut sender_descs[0])
+                .cursor()
+                .write_all(&ETHERNET_PACKET[..])
+                .unwrap();
+
+            while tx_q.produce_and_wakeup(&sender_descs[..1]).unwrap() != 1 {}
+
+            while rx_q
+                .poll_and_consume(&mut receiver_descs[1..2], 100)
+                .unwrap()
+                != 1
+            {}
+
+            while cq.consume(&mut sender_descs[1..2]) != 1 {}
+
+            assert_eq!(
+                receiver_umem.data(&receiver_descs[1]).contents(),
+                &ETHERNET_PACKET[..]
+            );
+        }
+    };
+
+    let (dev1_config, dev2_config) = setup::default_veth_dev_configs();
+
+    veth_setup::run_with_veth_pair(inner, dev1_config, dev2_config)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[serial]
+async fn socket_shutdown_and_drain_reclaim_every_frame() {
+    let inner = move |dev1_config: VethDevConfig, dev2_config: VethDevConfig| {
+        let bound = setup::build_socket_and_umem(
+            UmemConfig::default(),
+            SocketConfig::default(),
+            16.try_into().unwrap(),
+            &dev2_config.if_name().parse().unwrap(),
+            0,
+        );
+
+        assert!(matches!(
+            unsafe { bound.umem.drain(bound.fq, bound.cq) },
+            Err(UmemDrainError::StillBound { sockets: 1 })
+        ));
+
+        let Xsk {
+            umem,
+            mut descs,
+            mut tx_q,
+            rx_q,
+            mut fq,
+            mut cq,
+            ..
+        } = setup::build_socket_and_umem(
+            UmemConfig::default(),
+            SocketConfig::default(),
+            64.try_into().unwrap(),
+            &dev1_config.if_name().parse().unwrap(),
+            0,
+        );
+
+        let to_fill: Vec<_> = descs.drain(..8).collect();
+        assert_eq!(unsafe { fq.produce(&to_fill) }, 8);
+
+        let mut to_send: Vec<_> = descs.drain(..4).collect();
+
+        for desc in to_send.iter_mut() {
+            unsafe { umem.data_mut(desc) }
+                .cursor()
+                .write_all(&ETHERNET_PACKET)
+                .unwrap();
+        }
+
+        assert_eq!(unsafe { tx_q.produce(&to_send) }, 4);
+
+        let shutdown = Socket::shutdown(tx_q, rx_q, &mut cq, Duration::from_secs(1)).unwrap();
+
+        assert!(!shutdown.timed_out());
+        assert_eq!(shutdown.unsent(), 0);
+        assert_eq!(shutdown.completed().len(), 4);
+
+        descs.extend(shutdown.into_descs());
+        // Nothing is sent to the socket's queue, so the kernel takes no
+        // more frames off the fill queue.
+        descs.extend(unsafe { umem.drain(fq, cq) }.unwrap());
+
+        let mut addrs: Vec<_> = descs.iter().map(|d| d.addr()).collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+
+        assert_eq!(addrs.len(), 64);
+    };
+
+    let (dev1_config, dev2_config) = setup::default_veth_dev_configs();
+
+    veth_setup::run_with_veth_pair(inner, dev1_config, dev2_config)
+        .await
+        .unwrap();
+}
+
 fn send_and_receive_pkt(sender: &mut Xsk, receiver: &mut Xsk, pkt: &[u8]) {
     unsafe {
         assert_eq!(